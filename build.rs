@@ -0,0 +1,55 @@
+//! With the `embed` feature, lists the files below the public path for
+//! `embedded_assets::PUBLIC`: `EMBED_PATH`, relative to the crate's directory, or `public`
+
+use std::{
+  env, fs, io,
+  path::{Path, PathBuf},
+};
+
+fn main() -> io::Result<()> {
+  println!("cargo:rerun-if-env-changed=EMBED_PATH");
+  if env::var_os("CARGO_FEATURE_EMBED").is_none() {
+    return Ok(());
+  }
+  let manifest_dir = PathBuf::from(env::var_os("CARGO_MANIFEST_DIR").unwrap_or_default());
+  let public_path = manifest_dir.join(env::var_os("EMBED_PATH").unwrap_or("public".into()));
+  // directories are scanned for changes to any file below them
+  println!("cargo:rerun-if-changed={}", public_path.display());
+
+  let mut files = Vec::new();
+  collect_files(&public_path, "", &mut files)?;
+  files.sort();
+  let mut source = String::from(
+    "/// The files below the public path the crate was built with\n\
+     pub static PUBLIC: &[EmbeddedAsset] = &[\n",
+  );
+  for (path, full_path) in files {
+    source.push_str(&format!(
+      "  EmbeddedAsset {{ path: {:?}, contents: include_bytes!({:?}) }},\n",
+      path, full_path
+    ));
+  }
+  source.push_str("];\n");
+  let out_dir = PathBuf::from(env::var_os("OUT_DIR").unwrap_or_default());
+  fs::write(out_dir.join("embedded_public.rs"), source)
+}
+
+/// `(path relative to the public path, full path)` of the files below `dir`, following
+/// symlinks as serving them from disk does; names that aren't UTF-8 couldn't be requested
+fn collect_files(dir: &Path, prefix: &str, files: &mut Vec<(String, String)>) -> io::Result<()> {
+  for entry in fs::read_dir(dir)? {
+    let entry = entry?;
+    let (Ok(name), Some(full_path)) = (
+      entry.file_name().into_string(),
+      entry.path().to_str().map(String::from),
+    ) else {
+      continue;
+    };
+    let path = format!("{}{}", prefix, name);
+    match fs::metadata(entry.path())?.is_dir() {
+      true => collect_files(&entry.path(), &format!("{}/", path), files)?,
+      false => files.push((path, full_path)),
+    }
+  }
+  Ok(())
+}
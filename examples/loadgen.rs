@@ -0,0 +1,141 @@
+//! Hammers a running server and reports throughput and latency percentiles,
+//! e.g. while comparing parser/writer changes:
+//!
+//! ```shell
+//! cargo run --release --example loadgen -- --url http://127.0.0.1:8080/ --concurrency 64 --requests 20000
+//! ```
+//!
+//! Pass `--no-keep-alive` to open a fresh connection for every request.
+
+use std::{
+  env,
+  error::Error,
+  sync::Arc,
+  time::{Duration, Instant},
+};
+
+use reqwest::{header::CONNECTION, Client};
+
+struct Options {
+  url: String,
+  concurrency: usize,
+  requests: usize,
+  keep_alive: bool,
+}
+
+impl Options {
+  fn from_args() -> Result<Self, String> {
+    let mut options = Options {
+      url: "http://127.0.0.1:8080/".to_string(),
+      concurrency: 16,
+      requests: 10_000,
+      keep_alive: true,
+    };
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+      let mut value = |name: &str| args.next().ok_or(format!("{} requires a value", name));
+      match arg.as_str() {
+        "--url" => options.url = value("--url")?,
+        "--concurrency" => {
+          options.concurrency = value("--concurrency")?
+            .parse()
+            .map_err(|e| format!("{}", e))?
+        }
+        "--requests" => {
+          options.requests = value("--requests")?.parse().map_err(|e| format!("{}", e))?
+        }
+        "--no-keep-alive" => options.keep_alive = false,
+        other => return Err(format!("Unknown argument: {}", other)),
+      }
+    }
+
+    if options.concurrency == 0 {
+      return Err("--concurrency must be at least 1".to_string());
+    }
+    Ok(options)
+  }
+}
+
+/// Latency at `percentile` (0-100) of an ascending sorted sample
+fn percentile(sorted: &[Duration], percentile: f64) -> Duration {
+  if sorted.is_empty() {
+    return Duration::ZERO;
+  }
+  let rank = (percentile / 100.0 * (sorted.len() - 1) as f64).round() as usize;
+  sorted[rank]
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn Error>> {
+  let options = Options::from_args()?;
+  let client = Arc::new(if options.keep_alive {
+    Client::new()
+  } else {
+    Client::builder().pool_max_idle_per_host(0).build()?
+  });
+
+  println!(
+    "Sending {} requests to {} with concurrency {} (keep-alive: {})",
+    options.requests, options.url, options.concurrency, options.keep_alive
+  );
+
+  let started = Instant::now();
+
+  // every worker takes an equal share, the first ones pick up the remainder
+  let workers = (0..options.concurrency).map(|worker| {
+    let share = options.requests / options.concurrency
+      + usize::from(worker < options.requests % options.concurrency);
+    let client = Arc::clone(&client);
+    let url = options.url.clone();
+    let keep_alive = options.keep_alive;
+
+    tokio::spawn(async move {
+      let mut latencies = Vec::with_capacity(share);
+      let mut failures = 0usize;
+      for _ in 0..share {
+        let request_started = Instant::now();
+        let mut request = client.get(&url);
+        if !keep_alive {
+          request = request.header(CONNECTION, "close");
+        }
+        let completed = match request.send().await {
+          Ok(response) => response.bytes().await.is_ok(),
+          Err(_) => false,
+        };
+        if completed {
+          latencies.push(request_started.elapsed());
+        } else {
+          failures += 1;
+        }
+      }
+      (latencies, failures)
+    })
+  });
+
+  let mut latencies = Vec::with_capacity(options.requests);
+  let mut failures = 0;
+  for result in futures::future::join_all(workers).await {
+    let (worker_latencies, worker_failures) = result?;
+    latencies.extend(worker_latencies);
+    failures += worker_failures;
+  }
+  let elapsed = started.elapsed();
+  latencies.sort();
+
+  println!(
+    "Completed {} requests ({} failed) in {:?}",
+    latencies.len(),
+    failures,
+    elapsed
+  );
+  println!(
+    "Throughput: {:.1} req/s",
+    latencies.len() as f64 / elapsed.as_secs_f64()
+  );
+  for p in [50.0, 90.0, 99.0, 99.9] {
+    println!("p{:<5} {:?}", p, percentile(&latencies, p));
+  }
+  println!("max    {:?}", latencies.last().copied().unwrap_or_default());
+  Ok(())
+}
@@ -16,7 +16,7 @@ pub fn header_key_derive(input: TokenStream) -> TokenStream {
 
   let output = generate_match_arms(name, variants);
   println!("Generated impl: {}", output); // Debug output
-  output.into()
+  output
 }
 
 fn generate_match_arms(
@@ -0,0 +1,214 @@
+use serde::{Deserialize, Serialize};
+use std::{net::IpAddr, time::Duration};
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+
+use crate::http::{HttpRequest, StatusCode};
+
+/// How [`crate::server::Server`] writes one line per served request to stdout
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AccessLogFormat {
+  /// `127.0.0.1 "GET /hello" 200 512B 1.2ms trace_id=... request_id=...`
+  #[default]
+  Text,
+  /// One JSON object per line, ingestible by Loki or Elasticsearch without regex parsing
+  Json,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct AccessLogEntry {
+  /// RFC 3339, in UTC
+  timestamp: String,
+  client_ip: IpAddr,
+  method: String,
+  path: String,
+  status: u16,
+  /// written to the connection, headers included
+  bytes: u64,
+  duration_ms: f64,
+  request_id: String,
+  trace_id: String,
+}
+
+impl AccessLogEntry {
+  pub fn new(
+    client_ip: IpAddr,
+    request: &HttpRequest<'_>,
+    status_code: StatusCode,
+    bytes: u64,
+    duration: Duration,
+  ) -> Self {
+    let trace_id = request.trace_context().trace_id();
+    Self {
+      timestamp: OffsetDateTime::now_utc()
+        .format(&Rfc3339)
+        .unwrap_or_default(),
+      client_ip,
+      method: request.method().to_string(),
+      // as sent, a decoded path could break up the log line
+      path: request.raw_path().to_string(),
+      status: status_code.as_u16(),
+      bytes,
+      duration_ms: duration.as_secs_f64() * 1000.0,
+      request_id: request.request_id().to_string(),
+      trace_id,
+    }
+  }
+
+  /// Whether to write this entry when only `sample_rate` (0 to 1) of the requests are logged.
+  /// Server errors and requests the caller's trace marks as sampled are always written, the
+  /// rest is picked by trace ID so every service logs the same requests of a trace.
+  pub fn sampled(&self, request: &HttpRequest<'_>, sample_rate: f64) -> bool {
+    if sample_rate >= 1.0 || self.status >= 500 || request.trace_context().sampled() {
+      return true;
+    }
+    // trace IDs are random, so their low 64 bits are uniformly distributed
+    let low_bits = u64::from_str_radix(&self.trace_id[16..], 16).unwrap_or_default();
+    (low_bits as f64 / u64::MAX as f64) < sample_rate
+  }
+
+  pub fn format(&self, format: AccessLogFormat) -> String {
+    match format {
+      AccessLogFormat::Text => format!(
+        "{} \"{} {}\" {} {}B {:.1}ms trace_id={} request_id={}",
+        self.client_ip,
+        self.method,
+        self.path,
+        self.status,
+        self.bytes,
+        self.duration_ms,
+        self.trace_id,
+        self.request_id
+      ),
+      // serializing plain strings and numbers can't fail
+      AccessLogFormat::Json => serde_json::to_string(self).unwrap_or_default(),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use expectest::prelude::*;
+  use rstest::*;
+  use serde_json::Value;
+  use std::net::Ipv4Addr;
+
+  const TRACEPARENT: &str = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+
+  #[rstest]
+  #[case::generated_request_id(&[], None)]
+  #[case::client_request_id(&["X-Request-Id: checkout-42"], Some("checkout-42"))]
+  fn test_json_access_log(
+    #[case] extra_headers: &[&str],
+    #[case] expected_request_id: Option<&str>,
+  ) -> Result<(), crate::http::ParseError> {
+    let extra_headers = extra_headers
+      .iter()
+      .map(|header| format!("{}\r\n", header))
+      .collect::<String>();
+    let raw_request = format!(
+      "GET /hello?name=x HTTP/1.1\r\nHost: localhost\r\ntraceparent: {}\r\n{}\r\n",
+      TRACEPARENT, extra_headers
+    );
+    let request = HttpRequest::try_from(raw_request.as_bytes())?;
+    let entry = AccessLogEntry::new(
+      IpAddr::V4(Ipv4Addr::LOCALHOST),
+      &request,
+      StatusCode::Ok,
+      512,
+      Duration::from_micros(1500),
+    );
+
+    let line: Value = serde_json::from_str(&entry.format(AccessLogFormat::Json)).unwrap();
+    expect!(line["client_ip"].as_str()).to(be_some().value("127.0.0.1"));
+    expect!(line["method"].as_str()).to(be_some().value("GET"));
+    expect!(line["path"].as_str()).to(be_some().value("/hello"));
+    expect!(line["status"].as_u64()).to(be_some().value(200));
+    expect!(line["bytes"].as_u64()).to(be_some().value(512));
+    expect!(line["duration_ms"].as_f64()).to(be_some().value(1.5));
+    let expected_request_id = expected_request_id.unwrap_or(request.request_id());
+    expect!(line["request_id"].as_str()).to(be_some().value(expected_request_id));
+    expect!(line["trace_id"].as_str()).to(be_some().value("4bf92f3577b34da6a3ce929d0e0e4736"));
+    expect!(OffsetDateTime::parse(line["timestamp"].as_str().unwrap(), &Rfc3339).is_ok())
+      .to(be_true());
+    Ok(())
+  }
+
+  #[rstest]
+  #[case::everything(
+    1.0,
+    "00-4bf92f3577b34da6ffffffffffffffff-00f067aa0ba902b7-00",
+    StatusCode::Ok,
+    true
+  )]
+  #[case::nothing(
+    0.0,
+    "00-4bf92f3577b34da60000000000000001-00f067aa0ba902b7-00",
+    StatusCode::Ok,
+    false
+  )]
+  #[case::low_trace_id(
+    0.1,
+    "00-4bf92f3577b34da60000000000000001-00f067aa0ba902b7-00",
+    StatusCode::Ok,
+    true
+  )]
+  #[case::high_trace_id(
+    0.1,
+    "00-4bf92f3577b34da6ffffffffffffffff-00f067aa0ba902b7-00",
+    StatusCode::Ok,
+    false
+  )]
+  #[case::sampled_trace(
+    0.0,
+    "00-4bf92f3577b34da6ffffffffffffffff-00f067aa0ba902b7-01",
+    StatusCode::Ok,
+    true
+  )]
+  #[case::server_error(
+    0.0,
+    "00-4bf92f3577b34da6ffffffffffffffff-00f067aa0ba902b7-00",
+    StatusCode::InternalError,
+    true
+  )]
+  fn test_sample_access_log(
+    #[case] sample_rate: f64,
+    #[case] traceparent: &str,
+    #[case] status_code: StatusCode,
+    #[case] expected: bool,
+  ) -> Result<(), crate::http::ParseError> {
+    let raw_request = format!(
+      "GET / HTTP/1.1\r\nHost: localhost\r\ntraceparent: {}\r\n\r\n",
+      traceparent
+    );
+    let request = HttpRequest::try_from(raw_request.as_bytes())?;
+    let entry = AccessLogEntry::new(
+      IpAddr::V4(Ipv4Addr::LOCALHOST),
+      &request,
+      status_code,
+      0,
+      Duration::ZERO,
+    );
+    expect!(entry.sampled(&request, sample_rate)).to(be_equal_to(expected));
+    Ok(())
+  }
+
+  #[rstest]
+  fn test_text_access_log() -> Result<(), crate::http::ParseError> {
+    let request = HttpRequest::try_from(&b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n"[..])?;
+    let entry = AccessLogEntry::new(
+      IpAddr::V4(Ipv4Addr::LOCALHOST),
+      &request,
+      StatusCode::NotFound,
+      38,
+      Duration::from_micros(300),
+    );
+    expect!(entry.format(AccessLogFormat::Text)).to(be_equal_to(format!(
+      "127.0.0.1 \"GET /\" 404 38B 0.3ms trace_id={} request_id={}",
+      request.trace_context().trace_id(),
+      request.request_id()
+    )));
+    Ok(())
+  }
+}
@@ -0,0 +1,490 @@
+use std::{
+  collections::HashMap,
+  fs, io,
+  path::PathBuf,
+  sync::{Arc, RwLock},
+  time::Duration,
+};
+
+use derive_new::new;
+use instant_acme::{
+  Account, AccountCredentials, AuthorizationStatus, ChallengeType, Identifier, LetsEncrypt,
+  NewAccount, NewOrder, OrderStatus, RetryPolicy,
+};
+use rcgen::{CertificateParams, CustomExtension, KeyPair};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use thiserror::Error;
+use time::OffsetDateTime;
+use tokio_rustls::rustls::{
+  crypto::ring,
+  pki_types::{PrivateKeyDer, PrivatePkcs8KeyDer},
+  sign::CertifiedKey,
+};
+
+use crate::http::{
+  header::HttpResponseHeaderBuilder, HttpRequest, HttpResponse, Method, RequestBody, StatusCode,
+};
+use crate::server::{Handler, HandlerFuture, HandlerResult};
+use crate::tls::{AlpnChallenges, ReloadableTlsConfig, TlsError, TlsSettings};
+
+/// Path prefix of HTTP-01 challenge requests (RFC 8555, section 8.3)
+pub const ACME_CHALLENGE_PATH: &str = "/.well-known/acme-challenge/";
+
+/// How often certificates are checked for upcoming expiry
+const RENEWAL_CHECK_INTERVAL: Duration = Duration::from_secs(12 * 60 * 60);
+/// Wait before retrying a failed order, well within Let's Encrypt's rate limits
+const RETRY_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+const ACCOUNT_FILE: &str = "account.json";
+const CERT_FILE: &str = "cert.pem";
+const KEY_FILE: &str = "key.pem";
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub enum AcmeChallenge {
+  /// answered on [`AcmeSettings::http_address`], which has to be port 80
+  #[default]
+  #[serde(rename = "http-01")]
+  Http01,
+  /// answered on the TLS listener itself, which has to be port 443
+  #[serde(rename = "tls-alpn-01")]
+  TlsAlpn01,
+}
+
+/// Domains to obtain certificates for, e.g.
+///
+/// ```toml
+/// [acme]
+/// domains = ["example.com", "www.example.com"]
+/// contact = ["mailto:admin@example.com"]
+/// cache_dir = "/var/lib/udemy_server/acme"
+/// challenge = "tls-alpn-01"
+/// ```
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct AcmeSettings {
+  pub domains: Vec<String>,
+  #[serde(default)]
+  pub contact: Vec<String>,
+  /// Let's Encrypt production unless set, point it at staging while testing
+  #[serde(default = "default_directory_url")]
+  pub directory_url: String,
+  /// holds the account credentials along with the issued certificate and key
+  pub cache_dir: PathBuf,
+  #[serde(default)]
+  pub challenge: AcmeChallenge,
+  #[serde(default = "default_http_address")]
+  pub http_address: String,
+  #[serde(default = "default_renew_before_days")]
+  pub renew_before_days: u64,
+}
+
+fn default_directory_url() -> String {
+  LetsEncrypt::Production.url().to_string()
+}
+
+fn default_http_address() -> String {
+  "0.0.0.0:80".to_string()
+}
+
+fn default_renew_before_days() -> u64 {
+  30
+}
+
+#[derive(Error, Debug)]
+pub enum AcmeError {
+  #[error("ACME cache error: {0}")]
+  Io(#[from] io::Error),
+  #[error("ACME protocol error: {0}")]
+  Protocol(#[from] instant_acme::Error),
+  #[error("Failed to generate certificate: {0}")]
+  Certificate(#[from] rcgen::Error),
+  #[error("Invalid account credentials: {0}")]
+  Credentials(#[from] serde_json::Error),
+  #[error(transparent)]
+  Tls(#[from] TlsError),
+  #[error("Order ended up {0:?}")]
+  Order(OrderStatus),
+  #[error("No supported challenge offered for {0}")]
+  NoChallenge(String),
+  #[error("Authorization for {0} failed")]
+  Authorization(String),
+}
+
+/// Responses to pending challenges, shared between the order in progress and the listeners
+/// the validator connects to
+#[derive(Default)]
+pub struct AcmeChallenges {
+  /// HTTP-01 token -> key authorization
+  http: RwLock<HashMap<String, String>>,
+  alpn: AlpnChallenges,
+}
+
+impl AcmeChallenges {
+  pub fn key_authorization(&self, token: &str) -> Option<String> {
+    let http = self.http.read().unwrap_or_else(|e| e.into_inner());
+    http.get(token).cloned()
+  }
+
+  fn clear(&self) {
+    self.http.write().unwrap_or_else(|e| e.into_inner()).clear();
+    self.alpn.write().unwrap_or_else(|e| e.into_inner()).clear();
+  }
+}
+
+/// Answers HTTP-01 challenges and passes every other request on to `inner`
+#[derive(new)]
+pub struct AcmeChallengeHandler {
+  challenges: Arc<AcmeChallenges>,
+  inner: Arc<dyn Handler>,
+}
+
+impl Handler for AcmeChallengeHandler {
+  fn handle_request(&self, request: &HttpRequest<'_>) -> HandlerResult {
+    let token = request.path().strip_prefix(ACME_CHALLENGE_PATH);
+    match (request.method(), token) {
+      (Method::GET, Some(token)) => match self.challenges.key_authorization(token) {
+        Some(body) => {
+          let mut builder = HttpResponseHeaderBuilder::new();
+          builder.content_type("application/octet-stream");
+          builder.content_length(&body.len().to_string());
+          Ok(HttpResponse::new(
+            StatusCode::Ok,
+            Some(body.into_bytes()),
+            Some(Arc::new(builder.build())),
+          ))
+        }
+        None => Ok(HttpResponse::empty_body(StatusCode::NotFound)),
+      },
+      _ => self.inner.handle_request(request),
+    }
+  }
+
+  fn handle_async<'a>(&'a self, request: &'a HttpRequest<'a>) -> Option<HandlerFuture<'a>> {
+    match request.path().strip_prefix(ACME_CHALLENGE_PATH) {
+      Some(_) => None,
+      None => self.inner.handle_async(request),
+    }
+  }
+
+  fn handle_with_body(&self, request: &HttpRequest<'_>, body: RequestBody) -> HandlerResult {
+    match request.path().strip_prefix(ACME_CHALLENGE_PATH) {
+      Some(_) if *request.method() == Method::GET => self.handle_request(request),
+      _ => self.inner.handle_with_body(request, body),
+    }
+  }
+
+  fn allowed_methods(&self) -> Vec<Method> {
+    self.inner.allowed_methods()
+  }
+
+  fn describe(&self) -> Value {
+    json!({
+      "handler": "AcmeChallengeHandler",
+      "challenge_path": ACME_CHALLENGE_PATH,
+      "inner": self.inner.describe(),
+    })
+  }
+}
+
+/// Keeps the certificate in [`AcmeSettings::cache_dir`] valid, ordering a new one whenever it
+/// gets close to expiry. The TLS listener serves whatever is in the cache, so until the first
+/// order completes it presents a self-signed placeholder.
+pub struct AcmeManager {
+  settings: AcmeSettings,
+  challenges: Arc<AcmeChallenges>,
+  tls_config: Arc<ReloadableTlsConfig>,
+}
+
+impl AcmeManager {
+  pub fn new(settings: AcmeSettings) -> Result<Self, AcmeError> {
+    fs::create_dir_all(&settings.cache_dir)?;
+    let tls_settings = TlsSettings::new(
+      settings.cache_dir.join(CERT_FILE),
+      settings.cache_dir.join(KEY_FILE),
+    );
+    if !tls_settings.cert_path.exists() || !tls_settings.key_path.exists() {
+      write_placeholder(&settings.domains, &tls_settings)?;
+    }
+
+    let challenges = Arc::new(AcmeChallenges::default());
+    let alpn_challenges =
+      Some(Arc::clone(&challenges.alpn)).filter(|_| settings.challenge == AcmeChallenge::TlsAlpn01);
+    let tls_config = ReloadableTlsConfig::load_with_alpn_challenges(tls_settings, alpn_challenges)?;
+    Ok(Self { settings, challenges, tls_config: Arc::new(tls_config) })
+  }
+
+  pub fn settings(&self) -> &AcmeSettings {
+    &self.settings
+  }
+
+  pub fn challenges(&self) -> Arc<AcmeChallenges> {
+    Arc::clone(&self.challenges)
+  }
+
+  pub fn tls_config(&self) -> Arc<ReloadableTlsConfig> {
+    Arc::clone(&self.tls_config)
+  }
+
+  /// Check the cached certificate periodically and renew it in the background
+  pub fn watch(self: Arc<Self>) {
+    tokio::spawn(async move {
+      loop {
+        let wait = if !self.needs_renewal() {
+          RENEWAL_CHECK_INTERVAL
+        } else {
+          match self.provision().await {
+            Ok(()) => {
+              tracing::info!(domains = %self.settings.domains.join(", "), "Obtained certificate");
+              RENEWAL_CHECK_INTERVAL
+            }
+            Err(error) => {
+              tracing::error!(%error, "Failed to obtain certificate, retrying in an hour");
+              RETRY_INTERVAL
+            }
+          }
+        };
+        tokio::time::sleep(wait).await;
+      }
+    });
+  }
+
+  /// Whether the cached certificate is missing, unreadable or expires within
+  /// `renew_before_days`
+  pub fn needs_renewal(&self) -> bool {
+    let renew_before = self.settings.renew_before_days.saturating_mul(24 * 60 * 60);
+    let renew_before = time::Duration::seconds(renew_before.try_into().unwrap_or(i64::MAX));
+    match (
+      not_after(&self.settings.cache_dir.join(CERT_FILE)),
+      OffsetDateTime::now_utc().checked_add(renew_before),
+    ) {
+      (Some(not_after), Some(renew_at)) => not_after <= renew_at,
+      _ => true,
+    }
+  }
+
+  async fn provision(&self) -> Result<(), AcmeError> {
+    let account = self.account().await?;
+    let identifiers = self
+      .settings
+      .domains
+      .iter()
+      .map(|domain| Identifier::Dns(domain.clone()))
+      .collect::<Vec<_>>();
+    let mut order = account.new_order(&NewOrder::new(&identifiers)).await?;
+
+    let result = self.authorize(&mut order).await;
+    if result.is_err() {
+      self.challenges.clear();
+      return result;
+    }
+    let status = order.poll_ready(&RetryPolicy::default()).await;
+    self.challenges.clear();
+    match status? {
+      OrderStatus::Ready => {}
+      status => return Err(AcmeError::Order(status)),
+    }
+
+    let key_pem = order.finalize().await?;
+    let cert_pem = order.poll_certificate(&RetryPolicy::default()).await?;
+    fs::write(self.settings.cache_dir.join(KEY_FILE), key_pem)?;
+    fs::write(self.settings.cache_dir.join(CERT_FILE), cert_pem)?;
+    Ok(self.tls_config.reload()?)
+  }
+
+  /// Publish a response for every pending authorization and tell the CA to validate it
+  async fn authorize(&self, order: &mut instant_acme::Order) -> Result<(), AcmeError> {
+    let challenge_type = match self.settings.challenge {
+      AcmeChallenge::Http01 => ChallengeType::Http01,
+      AcmeChallenge::TlsAlpn01 => ChallengeType::TlsAlpn01,
+    };
+
+    let mut authorizations = order.authorizations();
+    while let Some(authorization) = authorizations.next().await {
+      let mut authorization = authorization?;
+      let domain = authorization.identifier().to_string();
+      match authorization.status {
+        AuthorizationStatus::Valid => continue,
+        AuthorizationStatus::Pending => {}
+        _ => return Err(AcmeError::Authorization(domain)),
+      }
+
+      let mut challenge = authorization
+        .challenge(challenge_type.clone())
+        .ok_or_else(|| AcmeError::NoChallenge(domain.clone()))?;
+      let key_authorization = challenge.key_authorization();
+      match self.settings.challenge {
+        AcmeChallenge::Http01 => {
+          let mut http = self
+            .challenges
+            .http
+            .write()
+            .unwrap_or_else(|e| e.into_inner());
+          http.insert(
+            challenge.token.clone(),
+            key_authorization.as_str().to_string(),
+          );
+        }
+        AcmeChallenge::TlsAlpn01 => {
+          let certified_key = alpn_certificate(&domain, key_authorization.digest().as_ref())?;
+          let mut alpn = self
+            .challenges
+            .alpn
+            .write()
+            .unwrap_or_else(|e| e.into_inner());
+          alpn.insert(domain, Arc::new(certified_key));
+        }
+      }
+      challenge.set_ready().await?;
+    }
+    Ok(())
+  }
+
+  /// The account stored in the cache, registered on first use
+  async fn account(&self) -> Result<Account, AcmeError> {
+    let path = self.settings.cache_dir.join(ACCOUNT_FILE);
+    if let Ok(credentials) = fs::read(&path) {
+      let credentials: AccountCredentials = serde_json::from_slice(&credentials)?;
+      return Ok(Account::builder()?.from_credentials(credentials).await?);
+    }
+
+    let contact = self
+      .settings
+      .contact
+      .iter()
+      .map(String::as_str)
+      .collect::<Vec<_>>();
+    let new_account = NewAccount {
+      contact: &contact,
+      terms_of_service_agreed: true,
+      only_return_existing: false,
+    };
+    let (account, credentials) = Account::builder()?
+      .create(&new_account, self.settings.directory_url.clone(), None)
+      .await?;
+    fs::write(&path, serde_json::to_vec(&credentials)?)?;
+    Ok(account)
+  }
+}
+
+/// Self-signed certificate carrying the `acmeIdentifier` extension (RFC 8737, section 3)
+fn alpn_certificate(domain: &str, digest: &[u8]) -> Result<CertifiedKey, AcmeError> {
+  let mut params = CertificateParams::new(vec![domain.to_string()])?;
+  params.custom_extensions = vec![CustomExtension::new_acme_identifier(digest)];
+  let key_pair = KeyPair::generate()?;
+  let cert = params.self_signed(&key_pair)?;
+
+  let key = PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(key_pair.serialize_der()));
+  // not `CertifiedKey::from_der`, its consistency check rejects the critical acmeIdentifier
+  let signing_key = ring::sign::any_supported_type(&key).map_err(TlsError::from)?;
+  Ok(CertifiedKey::new(vec![cert.der().clone()], signing_key))
+}
+
+/// Already expired, so the first check orders a real certificate right away
+fn write_placeholder(domains: &[String], tls_settings: &TlsSettings) -> Result<(), AcmeError> {
+  let mut params = CertificateParams::new(domains.to_vec())?;
+  params.not_before = OffsetDateTime::now_utc() - time::Duration::days(1);
+  params.not_after = OffsetDateTime::now_utc();
+  let key_pair = KeyPair::generate()?;
+  let cert = params.self_signed(&key_pair)?;
+
+  fs::write(&tls_settings.key_path, key_pair.serialize_pem())?;
+  fs::write(&tls_settings.cert_path, cert.pem())?;
+  Ok(())
+}
+
+/// Expiry of the first (leaf) certificate in a PEM file
+fn not_after(path: &PathBuf) -> Option<OffsetDateTime> {
+  let pem = fs::read(path).ok()?;
+  let (_, pem) = x509_parser::pem::parse_x509_pem(&pem).ok()?;
+  let cert = pem.parse_x509().ok()?;
+  Some(cert.validity().not_after.to_datetime())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::http::HandlerError;
+  use expectest::prelude::*;
+  use rstest::*;
+  use tempfile::TempDir;
+
+  struct NotFoundHandler;
+
+  impl Handler for NotFoundHandler {
+    fn handle_request(&self, _request: &HttpRequest) -> HandlerResult {
+      Ok(HttpResponse::empty_body(StatusCode::NotFound))
+    }
+  }
+
+  fn settings(dir: &TempDir) -> AcmeSettings {
+    toml::from_str(&format!(
+      "domains = [\"example.com\"]\ncache_dir = {:?}",
+      dir.path().display().to_string()
+    ))
+    .unwrap()
+  }
+
+  #[rstest]
+  fn test_serve_http01_key_authorization() -> Result<(), HandlerError> {
+    let challenges = Arc::new(AcmeChallenges::default());
+    challenges
+      .http
+      .write()
+      .unwrap()
+      .insert("token".to_string(), "token.thumb".to_string());
+    let handler = AcmeChallengeHandler::new(challenges, Arc::new(NotFoundHandler));
+
+    let request = HttpRequest::try_from(
+      &b"GET /.well-known/acme-challenge/token HTTP/1.1\r\nHost: example.com\r\n\r\n"[..],
+    )?;
+    let response = handler.handle_request(&request)?;
+    expect!(*response.status_code()).to(be_equal_to(StatusCode::Ok));
+    expect!(response.body().as_deref()).to(be_some().value(&b"token.thumb"[..]));
+
+    let request = HttpRequest::try_from(
+      &b"GET /.well-known/acme-challenge/other HTTP/1.1\r\nHost: example.com\r\n\r\n"[..],
+    )?;
+    expect!(*handler.handle_request(&request)?.status_code()).to(be_equal_to(StatusCode::NotFound));
+    Ok(())
+  }
+
+  #[rstest]
+  fn test_placeholder_certificate_needs_renewal() -> Result<(), AcmeError> {
+    let dir = TempDir::new().unwrap();
+    let manager = AcmeManager::new(settings(&dir))?;
+
+    expect!(dir.path().join(CERT_FILE).exists()).to(be_true());
+    expect!(manager.needs_renewal()).to(be_true());
+    Ok(())
+  }
+
+  #[rstest]
+  #[case::expires_soon(10, true)]
+  #[case::expires_later(60, false)]
+  fn test_renew_before_expiry(
+    #[case] valid_days: i64,
+    #[case] renew: bool,
+  ) -> Result<(), AcmeError> {
+    let dir = TempDir::new().unwrap();
+    let mut params = CertificateParams::new(vec!["example.com".to_string()])?;
+    params.not_after = OffsetDateTime::now_utc() + time::Duration::days(valid_days);
+    let key_pair = KeyPair::generate()?;
+    fs::write(dir.path().join(KEY_FILE), key_pair.serialize_pem())?;
+    fs::write(
+      dir.path().join(CERT_FILE),
+      params.self_signed(&key_pair)?.pem(),
+    )?;
+
+    let manager = AcmeManager::new(settings(&dir))?;
+    expect!(manager.needs_renewal()).to(be_equal_to(renew));
+    Ok(())
+  }
+
+  #[rstest]
+  fn test_alpn_certificate_for_domain() -> Result<(), AcmeError> {
+    let certified_key = alpn_certificate("example.com", &[0; 32])?;
+    expect!(certified_key.cert.len()).to(be_equal_to(1));
+    Ok(())
+  }
+}
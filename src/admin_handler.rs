@@ -0,0 +1,128 @@
+use std::sync::Arc;
+
+use derive_new::new;
+use serde_json::Value;
+
+use crate::http::{
+  header::HttpResponseHeaderBuilder, HttpRequest, HttpResponse, Method, StatusCode,
+};
+use crate::metrics::ServerMetrics;
+use crate::router;
+use crate::server::{Handler, HandlerResult, ReadinessCheck};
+
+const PROMETHEUS_CONTENT_TYPE: &str = "text/plain; version=0.0.4";
+
+/// Operational endpoints, served on the separate admin listener so they are
+/// never reachable through the public address
+#[derive(new)]
+pub struct AdminHandler {
+  metrics: Arc<ServerMetrics>,
+  readiness_checks: Vec<ReadinessCheck>,
+  /// [`crate::server::Server::describe`] of the server being administered
+  config: Value,
+}
+
+impl AdminHandler {
+  /// `200` once every readiness check passes, `503` listing the failures otherwise
+  fn readiness(&self) -> HttpResponse {
+    let failures = self
+      .readiness_checks
+      .iter()
+      .filter_map(|check| check().err())
+      .collect::<Vec<_>>();
+    let (status_code, body) = if failures.is_empty() {
+      (StatusCode::Ok, "ready\n".to_string())
+    } else {
+      (
+        StatusCode::ServiceUnavailable,
+        format!("{}\n", failures.join("\n")),
+      )
+    };
+
+    let mut builder = HttpResponseHeaderBuilder::new();
+    builder.content_type("text/plain; charset=utf-8");
+    builder.content_length(&body.len().to_string());
+    HttpResponse::new(
+      status_code,
+      Some(body.into_bytes()),
+      Some(Arc::new(builder.build())),
+    )
+  }
+}
+
+impl Handler for AdminHandler {
+  fn handle_request(&self, request: &HttpRequest<'_>) -> HandlerResult {
+    Ok(match (request.method(), request.path()) {
+      (Method::GET, "/metrics") => {
+        let body = self.metrics.render();
+        let mut builder = HttpResponseHeaderBuilder::new();
+        builder.content_type(PROMETHEUS_CONTENT_TYPE);
+        builder.content_length(&body.len().to_string());
+        HttpResponse::new(
+          StatusCode::Ok,
+          Some(body.into_bytes()),
+          Some(Arc::new(builder.build())),
+        )
+      }
+      (Method::GET, "/readyz") => self.readiness(),
+      (Method::GET, "/config") => {
+        let body = format!("{:#}\n", self.config);
+        let mut builder = HttpResponseHeaderBuilder::new();
+        builder.content_type("application/json");
+        builder.content_length(&body.len().to_string());
+        HttpResponse::new(
+          StatusCode::Ok,
+          Some(body.into_bytes()),
+          Some(Arc::new(builder.build())),
+        )
+      }
+      (_, "/metrics" | "/readyz" | "/config") => router::method_not_allowed(&[Method::GET]),
+      _ => HttpResponse::empty_body(StatusCode::NotFound),
+    })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::http::HandlerError;
+  use expectest::prelude::*;
+  use rstest::*;
+
+  #[rstest]
+  #[case::ready(Ok(()), StatusCode::Ok, "ready\n")]
+  #[case::not_ready(
+    Err("Public path /srv/www does not exist".to_string()),
+    StatusCode::ServiceUnavailable,
+    "Public path /srv/www does not exist\n"
+  )]
+  fn test_readyz(
+    #[case] check_result: Result<(), String>,
+    #[case] expected_status: StatusCode,
+    #[case] expected_body: &str,
+  ) -> Result<(), HandlerError> {
+    let check: ReadinessCheck = Arc::new(move || check_result.clone());
+    let handler = AdminHandler::new(Arc::new(ServerMetrics::default()), vec![check], Value::Null);
+
+    let request = HttpRequest::try_from(&b"GET /readyz HTTP/1.1\r\nHost: localhost\r\n\r\n"[..])?;
+    let response = handler.handle_request(&request)?;
+    expect!(*response.status_code()).to(be_equal_to(expected_status));
+    expect!(response.body().as_deref()).to(be_some().value(expected_body.as_bytes()));
+    Ok(())
+  }
+
+  #[rstest]
+  #[case::method_not_allowed("POST /metrics", StatusCode::MethodNotAllowed)]
+  #[case::not_found("GET /admin", StatusCode::NotFound)]
+  fn test_unknown_endpoint(
+    #[case] request_line: &str,
+    #[case] expected_status: StatusCode,
+  ) -> Result<(), HandlerError> {
+    let handler = AdminHandler::new(Arc::new(ServerMetrics::default()), vec![], Value::Null);
+
+    let raw_request = format!("{} HTTP/1.1\r\nHost: localhost\r\n\r\n", request_line);
+    let response = handler.handle_request(&HttpRequest::try_from(raw_request.as_bytes())?)?;
+    expect!(*response.status_code()).to(be_equal_to(expected_status));
+    Ok(())
+  }
+}
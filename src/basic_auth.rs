@@ -0,0 +1,307 @@
+use base64::{engine::general_purpose::STANDARD, Engine};
+use ring::{hmac, rand::SystemRandom};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::{
+  collections::HashMap,
+  io,
+  path::PathBuf,
+  sync::{Arc, Mutex},
+};
+use tokio::runtime::{Handle, RuntimeFlavor};
+
+use crate::htpasswd::Htpasswd;
+use crate::http::{
+  header::{HttpRequestHeaderKey, HttpResponseHeaderBuilder},
+  HttpRequest, HttpResponse, StatusCode,
+};
+use crate::middleware::{covered, Middleware, Next, RemoteUser};
+use crate::server::{HandlerFuture, HandlerResult};
+
+/// Paths only served to users of an htpasswd file, e.g.
+///
+/// ```toml
+/// [basic_auth]
+/// paths = ["/admin", "/reports"]
+/// htpasswd_file = "/etc/udemy_server/htpasswd"
+/// realm = "Staff"
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BasicAuthSettings {
+  /// each covers the path itself and everything below it, `/admin` covers `/admin/users` but
+  /// not `/administrators`
+  pub paths: Vec<String>,
+  /// see [`Htpasswd`] for the hashes understood
+  pub htpasswd_file: PathBuf,
+  /// shown by browsers when they ask for credentials
+  #[serde(default = "default_realm")]
+  pub realm: String,
+}
+
+fn default_realm() -> String {
+  "Restricted".to_string()
+}
+
+/// HTTP Basic authentication (RFC 7617) of requests below the protected paths: requests
+/// without valid credentials get `401 Unauthorized` with a `WWW-Authenticate` challenge, the
+/// others are passed on with the user as a [`RemoteUser`] extension. Paths are compared once
+/// `.` and `..` segments are applied, as files are looked up.
+///
+/// Credentials travel in the clear, so serve protected paths over TLS only.
+pub struct BasicAuth {
+  htpasswd: Htpasswd,
+  realm: String,
+  paths: Vec<String>,
+  /// per user, an HMAC of the password last verified for them under a key of this process, so
+  /// repeated requests skip the bcrypt work
+  verified: Mutex<HashMap<String, hmac::Tag>>,
+  key: hmac::Key,
+}
+
+impl BasicAuth {
+  pub fn new(htpasswd: Htpasswd, realm: &str) -> Self {
+    let key = hmac::Key::generate(hmac::HMAC_SHA256, &SystemRandom::new())
+      .expect("The system's random number generator failed");
+    Self {
+      htpasswd,
+      realm: realm.to_string(),
+      paths: Vec::new(),
+      verified: Mutex::new(HashMap::new()),
+      key,
+    }
+  }
+
+  /// Load the users of `settings.htpasswd_file` to protect `settings.paths`
+  pub fn with_settings(settings: &BasicAuthSettings) -> io::Result<Self> {
+    let htpasswd = Htpasswd::load(&settings.htpasswd_file)?;
+    if htpasswd.is_empty() {
+      tracing::warn!(
+        path = %settings.htpasswd_file.display(),
+        "No users in htpasswd file, protected paths can't be accessed"
+      );
+    }
+    let basic_auth = Self::new(htpasswd, &settings.realm);
+    Ok(
+      settings
+        .paths
+        .iter()
+        .fold(basic_auth, |basic_auth, path| basic_auth.protect(path)),
+    )
+  }
+
+  /// Require credentials for `path` and everything below it
+  pub fn protect(mut self, path: &str) -> Self {
+    self.paths.push(path.trim_end_matches('/').to_string());
+    self
+  }
+
+  /// The user named in the `Authorization` header of `request`, if the password matches
+  fn authenticated(&self, request: &HttpRequest<'_>) -> Option<String> {
+    let authorization = request.header().get(HttpRequestHeaderKey::Authorization)?;
+    let (scheme, credentials) = authorization.trim().split_once(' ')?;
+    if !scheme.eq_ignore_ascii_case("Basic") {
+      return None;
+    }
+    let credentials = String::from_utf8(STANDARD.decode(credentials.trim()).ok()?).ok()?;
+    let (user, password) = credentials.split_once(':')?;
+    match self.verify(user, password) {
+      true => Some(user.to_string()),
+      false => {
+        tracing::info!(user, path = request.path(), "Basic authentication failed");
+        None
+      }
+    }
+  }
+
+  /// `request` as the handler gets it, with the user it's from, or the challenge to answer it
+  /// with instead
+  fn admit<'r>(&self, request: &HttpRequest<'r>) -> Result<HttpRequest<'r>, HttpResponse> {
+    match self.authenticated(request) {
+      Some(user) => Ok(request.clone().with_extension(RemoteUser(user))),
+      None => Err(self.challenge()),
+    }
+  }
+
+  /// Checks the password against the htpasswd file unless it was verified for `user` before.
+  /// Middleware runs on the async workers, which a bcrypt check would hold up for the other
+  /// connections, so it's moved off them where the runtime allows.
+  fn verify(&self, user: &str, password: &str) -> bool {
+    let verified = self.verified.lock().unwrap().get(user).cloned();
+    if verified
+      .is_some_and(|tag| hmac::verify(&self.key, password.as_bytes(), tag.as_ref()).is_ok())
+    {
+      return true;
+    }
+    let valid = match Handle::try_current().map(|handle| handle.runtime_flavor()) {
+      Ok(RuntimeFlavor::MultiThread) => {
+        tokio::task::block_in_place(|| self.htpasswd.verify(user, password))
+      }
+      _ => self.htpasswd.verify(user, password),
+    };
+    if valid {
+      let tag = hmac::sign(&self.key, password.as_bytes());
+      self.verified.lock().unwrap().insert(user.to_string(), tag);
+    }
+    valid
+  }
+
+  fn challenge(&self) -> HttpResponse {
+    let mut builder = HttpResponseHeaderBuilder::new();
+    // quotes and backslashes would end the quoted string early
+    let realm = self.realm.replace(['"', '\\'], "");
+    builder.www_authenticate(&format!("Basic realm=\"{}\", charset=\"UTF-8\"", realm));
+    let body = b"<h1>401 Unauthorized</h1>".to_vec();
+    builder.content_type("text/html");
+    builder.content_length(&body.len().to_string());
+    HttpResponse::new(
+      StatusCode::Unauthorized,
+      Some(body),
+      Some(Arc::new(builder.build())),
+    )
+  }
+}
+
+impl Middleware for BasicAuth {
+  fn handle(&self, request: &HttpRequest, next: Next<'_>) -> HandlerResult {
+    if !covered(&self.paths, request.path()) {
+      return next.run(request);
+    }
+    match self.admit(request) {
+      Ok(request) => next.run(&request),
+      Err(challenge) => Ok(challenge),
+    }
+  }
+
+  fn handle_async<'a>(&'a self, request: &'a HttpRequest<'a>, next: Next<'a>) -> HandlerFuture<'a> {
+    if !covered(&self.paths, request.path()) {
+      return next.run_async(request);
+    }
+    Box::pin(async move {
+      match self.admit(request) {
+        Ok(request) => next.run_async(&request).await,
+        Err(challenge) => Ok(challenge),
+      }
+    })
+  }
+
+  fn describe(&self) -> Value {
+    json!({
+      "middleware": "BasicAuth",
+      "realm": self.realm,
+      "paths": self.paths,
+      "users": self.htpasswd.len(),
+    })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::http::header::HttpResponseHeaderKey;
+  use crate::middleware::Layered;
+  use crate::server::Handler;
+  use expectest::prelude::*;
+  use rstest::*;
+
+  /// Answers with the authenticated user
+  struct UserHandler;
+
+  impl Handler for UserHandler {
+    fn handle_request(&self, request: &HttpRequest<'_>) -> HandlerResult {
+      let user = request
+        .extensions()
+        .get::<RemoteUser>()
+        .map_or(String::new(), |RemoteUser(user)| user.clone());
+      Ok(HttpResponse::new(
+        StatusCode::Ok,
+        Some(user.into_bytes()),
+        None,
+      ))
+    }
+  }
+
+  fn basic(credentials: &str) -> Option<String> {
+    Some(format!("Basic {}", STANDARD.encode(credentials)))
+  }
+
+  #[rstest]
+  #[case::public("/index.html", None, StatusCode::Ok, "")]
+  #[case::lookalike("/administrators", None, StatusCode::Ok, "")]
+  #[case::missing("/admin", None, StatusCode::Unauthorized, "<h1>401 Unauthorized</h1>")]
+  #[case::below("/admin/users", None, StatusCode::Unauthorized, "<h1>401 Unauthorized</h1>")]
+  #[case::dot_segments(
+    "/public/../admin//users",
+    None,
+    StatusCode::Unauthorized,
+    "<h1>401 Unauthorized</h1>"
+  )]
+  #[case::authenticated("/admin/users", basic("bob:secret"), StatusCode::Ok, "bob")]
+  #[case::password_with_colon(
+    "/admin",
+    basic("bob:secret:"),
+    StatusCode::Unauthorized,
+    "<h1>401 Unauthorized</h1>"
+  )]
+  #[case::wrong_password(
+    "/admin",
+    basic("bob:Secret"),
+    StatusCode::Unauthorized,
+    "<h1>401 Unauthorized</h1>"
+  )]
+  #[case::other_scheme(
+    "/admin",
+    Some("Bearer abc".to_string()),
+    StatusCode::Unauthorized,
+    "<h1>401 Unauthorized</h1>"
+  )]
+  fn test_basic_auth(
+    #[case] path: &str,
+    #[case] authorization: Option<String>,
+    #[case] expected_status: StatusCode,
+    #[case] expected_body: &str,
+  ) {
+    let htpasswd = Htpasswd::parse("bob:{SHA}5en6G6MezRroT3XKqkdPOmY/BfQ=");
+    let handler = Layered::new(Arc::new(UserHandler)).layer(Arc::new(
+      BasicAuth::new(htpasswd, "Staff \"only\"").protect("/admin/"),
+    ));
+    let authorization = authorization
+      .map(|authorization| format!("Authorization: {}\r\n", authorization))
+      .unwrap_or_default();
+    let raw_request = format!(
+      "GET {} HTTP/1.1\r\nHost: localhost\r\n{}\r\n",
+      path, authorization
+    );
+    let request = HttpRequest::try_from(raw_request.as_bytes()).unwrap();
+
+    let response = handler.handle_request(&request).unwrap();
+    expect!(*response.status_code()).to(be_equal_to(expected_status));
+    expect!(response.body().as_deref().unwrap_or_default())
+      .to(be_equal_to(expected_body.as_bytes()));
+    let challenge = response
+      .http_header()
+      .as_ref()
+      .and_then(|header| header.get(HttpResponseHeaderKey::WwwAuthenticate).cloned());
+    match expected_status {
+      StatusCode::Unauthorized => expect!(challenge)
+        .to(be_some().value("Basic realm=\"Staff only\", charset=\"UTF-8\"".to_string())),
+      _ => expect!(challenge).to(be_none()),
+    };
+  }
+
+  #[rstest]
+  #[tokio::test(flavor = "multi_thread")]
+  async fn test_verify_remembers_passwords() {
+    let htpasswd =
+      Htpasswd::parse("alice:$2y$05$CCCCCCCCCCCCCCCCCCCCC.aDV7CQarKHMuNfh2oJkFzsHZya4whFe");
+    let basic_auth = BasicAuth::new(htpasswd, "Staff");
+
+    expect!(basic_auth.verify("alice", "Password")).to(be_false());
+    expect!(basic_auth.verified.lock().unwrap().is_empty()).to(be_true());
+    expect!(basic_auth.verify("alice", "password")).to(be_true());
+    expect!(basic_auth.verified.lock().unwrap().contains_key("alice")).to(be_true());
+    // answered from what was remembered, which still tells passwords apart
+    expect!(basic_auth.verify("alice", "password")).to(be_true());
+    expect!(basic_auth.verify("alice", "Password")).to(be_false());
+  }
+}
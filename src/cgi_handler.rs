@@ -0,0 +1,506 @@
+use std::{
+  env, fs, io,
+  io::Cursor,
+  path::{Path, PathBuf},
+  process::Stdio,
+  sync::Arc,
+};
+use tokio::{
+  io::AsyncReadExt,
+  process::{Child, Command},
+};
+
+use crate::http::{
+  codec,
+  header::{HttpHeader, HttpRequestHeaderKey, HttpResponseHeaderBuilder, HttpResponseHeaderKey},
+  trace_context::{TRACEPARENT, TRACESTATE},
+  HandlerError, HttpRequest, HttpResponse, Method, RequestBody, StatusCode,
+};
+use crate::middleware::RemoteUser;
+use crate::server::{self, Handler, HandlerFuture, HandlerResult};
+use serde_json::{json, Value};
+
+const GATEWAY_INTERFACE: &str = "CGI/1.1";
+const SERVER_SOFTWARE: &str = concat!("udemy_server/", env!("CARGO_PKG_VERSION"));
+/// Script output headers about the connection or the body's framing, which are this server's
+/// to write: scripts mustn't send them (RFC 3875, section 6.3.4), those that do are ignored
+const HOP_BY_HOP_HEADERS: [HttpResponseHeaderKey; 5] = [
+  HttpResponseHeaderKey::Connection,
+  HttpResponseHeaderKey::ContentLength,
+  HttpResponseHeaderKey::KeepAlive,
+  HttpResponseHeaderKey::TransferEncoding,
+  HttpResponseHeaderKey::Upgrade,
+];
+
+/// Executes scripts below `script_root` following the CGI/1.1 contract (RFC 3875):
+/// request metadata goes into the environment, the script prints header lines,
+/// a blank line and then the body on stdout
+pub struct CgiHandler {
+  script_root: PathBuf,
+}
+
+impl CgiHandler {
+  pub fn new(script_root: String) -> Self {
+    // canonical root, so the traversal check below compares like with like
+    let script_root = fs::canonicalize(&script_root).unwrap_or(PathBuf::from(script_root));
+    Self { script_root }
+  }
+
+  fn script_path(&self, request_path: &str) -> Option<PathBuf> {
+    let full_path = self.script_root.join(request_path.trim_start_matches('/'));
+    match fs::canonicalize(full_path) {
+      Ok(canonical_path) if canonical_path.starts_with(&self.script_root) => Some(canonical_path),
+      _ => {
+        tracing::warn!(
+          path = request_path,
+          "CGI script not found or outside of script root"
+        );
+        None
+      }
+    }
+  }
+
+  fn environment(&self, request: &HttpRequest<'_>, script_path: &Path) -> Vec<(String, String)> {
+    let host = request
+      .header()
+      .get("Host")
+      .map(String::as_str)
+      .unwrap_or_default();
+    let (server_name, server_port) = host.split_once(':').unwrap_or((host, "80"));
+
+    let mut environment = vec![
+      (
+        "GATEWAY_INTERFACE".to_string(),
+        GATEWAY_INTERFACE.to_string(),
+      ),
+      ("SERVER_SOFTWARE".to_string(), SERVER_SOFTWARE.to_string()),
+      ("SERVER_PROTOCOL".to_string(), request.version().to_string()),
+      ("SERVER_NAME".to_string(), server_name.to_string()),
+      ("SERVER_PORT".to_string(), server_port.to_string()),
+      (
+        "REQUEST_METHOD".to_string(),
+        format!("{:?}", request.method()),
+      ),
+      ("SCRIPT_NAME".to_string(), request.path().to_string()),
+      (
+        "SCRIPT_FILENAME".to_string(),
+        script_path.to_string_lossy().to_string(),
+      ),
+      (
+        "DOCUMENT_ROOT".to_string(),
+        self.script_root.to_string_lossy().to_string(),
+      ),
+      (
+        "QUERY_STRING".to_string(),
+        request
+          .query_string()
+          .as_ref()
+          .map(|query| query.raw())
+          .unwrap_or_default()
+          .to_string(),
+      ),
+    ];
+    if let Some(connection) = request.connection() {
+      let peer_addr = connection.peer_addr;
+      environment.push(("REMOTE_ADDR".to_string(), peer_addr.ip().to_string()));
+      environment.push(("REMOTE_PORT".to_string(), peer_addr.port().to_string()));
+      if connection.secure {
+        environment.push(("HTTPS".to_string(), "on".to_string()));
+      }
+    }
+    if let Some(RemoteUser(remote_user)) = request.extensions().get() {
+      environment.push(("REMOTE_USER".to_string(), remote_user.clone()));
+    }
+    if let Ok(path) = env::var("PATH") {
+      environment.push(("PATH".to_string(), path));
+    }
+
+    // Content-Type and Content-Length are meta-variables, every other header becomes HTTP_*
+    let header_variable = |key: &str, value: &str| {
+      let name = key.to_uppercase().replace('-', "_");
+      match name.as_str() {
+        "CONTENT_TYPE" | "CONTENT_LENGTH" => (name, value.to_string()),
+        _ => (format!("HTTP_{}", name), value.to_string()),
+      }
+    };
+    environment.extend(
+      request
+        .header()
+        .iter()
+        // a client's `Proxy` would become `HTTP_PROXY`, which scripts' HTTP libraries take
+        // for the proxy to send their own requests through (httpoxy, CVE-2016-5385)
+        .filter(|(key, _)| !is_trace_header(key) && !key.eq_ignore_ascii_case("Proxy"))
+        .map(|(key, value)| header_variable(key, value)),
+    );
+    // the script continues the trace as a child of this server
+    environment.extend(
+      request
+        .trace_context()
+        .child()
+        .headers()
+        .into_iter()
+        .map(|(key, value)| header_variable(key, &value)),
+    );
+    // also for requests without one, so the script logs under the same ID
+    environment.push(header_variable(
+      HttpRequestHeaderKey::XRequestId.as_ref(),
+      request.request_id(),
+    ));
+    environment
+  }
+
+  /// The status and header of the script's header lines, `None` if they're malformed
+  fn parse_head(head: &[u8]) -> Option<(StatusCode, HttpHeader)> {
+    // script headers are text, the body may be anything
+    let head = String::from_utf8_lossy(head);
+
+    let mut status_code = StatusCode::Ok;
+    let mut builder = HttpResponseHeaderBuilder::new();
+    for line in head.trim_end().lines() {
+      let (key, value) = line.split_once(':')?;
+      let value = value.trim();
+      match key.trim().to_lowercase().as_str() {
+        "status" => {
+          let code = value.split_whitespace().next()?.parse::<u16>().ok()?;
+          status_code = StatusCode::from_u16(code)?;
+        }
+        "content-type" => {
+          builder.content_type(value);
+        }
+        name
+          if HOP_BY_HOP_HEADERS
+            .iter()
+            .any(|hop_by_hop| name.eq_ignore_ascii_case(hop_by_hop.as_ref())) =>
+        {
+          tracing::warn!(
+            header = key.trim(),
+            "Ignored hop-by-hop header of CGI script"
+          );
+        }
+        _ => {
+          builder.custom(key.trim().to_string(), value);
+        }
+      }
+    }
+    Some((status_code, builder.build()))
+  }
+
+  fn spawn(
+    &self,
+    request: &HttpRequest<'_>,
+    script_path: &Path,
+    body: Option<RequestBody>,
+  ) -> io::Result<Child> {
+    let mut child = Command::new(script_path)
+      .current_dir(script_path.parent().unwrap_or(&self.script_root))
+      .env_clear()
+      .envs(self.environment(request, script_path))
+      .stdin(if body.is_some() { Stdio::piped() } else { Stdio::null() })
+      .stdout(Stdio::piped())
+      .stderr(Stdio::piped())
+      // e.g. when the handler times out before the script answered
+      .kill_on_drop(true)
+      .spawn()?;
+    // fed from a task of its own, scripts writing output before they read all of their input
+    // would deadlock otherwise
+    if let (Some(mut body), Some(mut stdin)) = (body, child.stdin.take()) {
+      tokio::spawn(async move {
+        // the script may exit without reading everything, which is fine
+        let _ = tokio::io::copy(&mut body, &mut stdin).await;
+      });
+    }
+    Ok(child)
+  }
+
+  /// Runs the script until it has written its header lines, the rest of its output is
+  /// streamed to the client as the script writes it
+  async fn run(&self, request: &HttpRequest<'_>, body: Option<RequestBody>) -> HandlerResult {
+    let Some(script_path) = self.script_path(request.path()) else {
+      return Ok(HttpResponse::empty_body(StatusCode::NotFound));
+    };
+    let failed = |error: io::Error| {
+      HandlerError::internal(format!(
+        "Failed to execute CGI script {}",
+        script_path.display()
+      ))
+      .with_source(error)
+    };
+
+    let mut child = self.spawn(request, &script_path, body).map_err(failed)?;
+    let (Some(mut stdout), Some(mut stderr)) = (child.stdout.take(), child.stderr.take()) else {
+      unreachable!("CGI scripts' output is piped");
+    };
+    // read alongside stdout, scripts filling the pipe would be stuck otherwise
+    let errors = tokio::spawn(async move {
+      let mut errors = Vec::new();
+      let _ = stderr.read_to_end(&mut errors).await;
+      String::from_utf8_lossy(&errors).trim_end().to_string()
+    });
+
+    let mut output = Vec::new();
+    let head_length = loop {
+      if let Some(head_length) = codec::head_length(&output) {
+        break Some(head_length);
+      }
+      if output.len() > codec::DEFAULT_MAX_HEAD_SIZE
+        || stdout.read_buf(&mut output).await.map_err(failed)? == 0
+      {
+        break None;
+      }
+    };
+    let parsed = head_length.and_then(|head_length| Self::parse_head(&output[..head_length]));
+    let Some((status_code, header)) = parsed else {
+      let status = child.wait().await.map_err(failed)?;
+      let errors = errors.await.unwrap_or_default();
+      return Err(match status.success() {
+        true => HandlerError::internal(format!(
+          "Malformed CGI response from {}",
+          script_path.display()
+        )),
+        false => HandlerError::internal(format!(
+          "CGI script {} failed with {}: {}",
+          script_path.display(),
+          status,
+          errors
+        )),
+      });
+    };
+
+    // the response is sent by now, failures past the header lines can only be logged
+    tokio::spawn(async move {
+      match child.wait().await {
+        Ok(status) if !status.success() => {
+          let errors = errors.await.unwrap_or_default();
+          tracing::warn!(
+            script = %script_path.display(),
+            %status,
+            errors,
+            "CGI script failed after its header lines"
+          );
+        }
+        Ok(_) => {}
+        Err(error) => tracing::warn!(%error, "Failed to wait for CGI script"),
+      }
+    });
+    let body = Cursor::new(output.split_off(head_length.unwrap_or_default())).chain(stdout);
+    Ok(HttpResponse::streamed(
+      status_code,
+      body,
+      Some(Arc::new(header)),
+    ))
+  }
+}
+
+/// Headers identifying the request, which the script gets as this server passes them on
+fn is_trace_header(key: &str) -> bool {
+  key.eq_ignore_ascii_case(TRACEPARENT)
+    || key.eq_ignore_ascii_case(TRACESTATE)
+    || key.eq_ignore_ascii_case(HttpRequestHeaderKey::XRequestId.as_ref())
+}
+
+impl Handler for CgiHandler {
+  fn describe(&self) -> Value {
+    json!({ "handler": "CgiHandler", "script_root": self.script_root })
+  }
+
+  fn handle_request(&self, request: &HttpRequest<'_>) -> HandlerResult {
+    server::block_on(self.run(request, None))?
+  }
+
+  /// The body is piped into the script's stdin as it arrives
+  fn handle_with_body(&self, request: &HttpRequest<'_>, body: RequestBody) -> HandlerResult {
+    server::block_on(self.run(request, Some(body)))?
+  }
+
+  fn handle_async<'a>(&'a self, request: &'a HttpRequest<'a>) -> Option<HandlerFuture<'a>> {
+    Some(Box::pin(self.run(request, None)))
+  }
+
+  fn allowed_methods(&self) -> Vec<Method> {
+    vec![Method::GET, Method::POST]
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use expectest::prelude::*;
+  use rstest::*;
+  use std::{io::Write, os::unix::fs::PermissionsExt};
+  use tempfile::TempDir;
+
+  fn write_script(dir: &TempDir, name: &str, contents: &str) -> std::io::Result<()> {
+    let path = dir.path().join(name);
+    fs::File::create(&path)?.write_all(contents.as_bytes())?;
+    fs::set_permissions(&path, fs::Permissions::from_mode(0o755))
+  }
+
+  /// Answers as the server does for bodyless requests, with the streamed output collected
+  async fn respond(handler: &CgiHandler, request: &HttpRequest<'_>) -> HandlerResult {
+    let mut response = handler.handle_async(request).unwrap().await?;
+    response.collect_body().await.unwrap();
+    Ok(response)
+  }
+
+  #[rstest]
+  #[tokio::test]
+  async fn test_cgi_environment_and_output() -> Result<(), Box<dyn std::error::Error>> {
+    let script_root = TempDir::new()?;
+    write_script(
+      &script_root,
+      "echo.cgi",
+      "#!/bin/sh\nprintf 'Content-Type: text/plain\\r\\nX-Method: %s\\r\\n\\r\\n%s|%s' \"$REQUEST_METHOD\" \"$QUERY_STRING\" \"$HTTP_USER_AGENT\"\n",
+    )?;
+    let handler = CgiHandler::new(script_root.path().to_string_lossy().to_string());
+
+    let raw_request = "GET /echo.cgi?name=none HTTP/1.1\r\nUser-Agent: test\r\n\r\n";
+    let request = HttpRequest::try_from(raw_request.as_bytes())?;
+    let response = respond(&handler, &request).await?;
+
+    expect!(*response.status_code()).to(be_equal_to(StatusCode::Ok));
+    expect!(response.body().as_deref()).to(be_some().value(&b"name=none|test"[..]));
+    let header = response.http_header().as_ref().unwrap();
+    expect!(header.get(HttpResponseHeaderKey::ContentType)).to(be_some().value("text/plain"));
+    expect!(header.get("X-Method")).to(be_some().value("GET"));
+    Ok(())
+  }
+
+  #[rstest]
+  #[tokio::test(flavor = "multi_thread")]
+  async fn test_cgi_reads_body_from_stdin() -> Result<(), Box<dyn std::error::Error>> {
+    let script_root = TempDir::new()?;
+    write_script(
+      &script_root,
+      "upload.cgi",
+      "#!/bin/sh\nprintf 'Content-Type: text/plain\\n\\n%s:' \"$CONTENT_LENGTH\"\ncat\n",
+    )?;
+    let handler = CgiHandler::new(script_root.path().to_string_lossy().to_string());
+
+    let raw_request = "POST /upload.cgi HTTP/1.1\r\nContent-Length: 11\r\n\r\n";
+    let request = HttpRequest::try_from(raw_request.as_bytes())?;
+    let (body, _) = RequestBody::channel(b"hello world", 11);
+    let mut response = handler.handle_with_body(&request, body)?;
+    response.collect_body().await?;
+
+    expect!(response.body().as_deref()).to(be_some().value(&b"11:hello world"[..]));
+    Ok(())
+  }
+
+  #[rstest]
+  #[tokio::test]
+  async fn test_cgi_continues_trace() -> Result<(), Box<dyn std::error::Error>> {
+    let script_root = TempDir::new()?;
+    write_script(
+      &script_root,
+      "trace.cgi",
+      "#!/bin/sh\nprintf 'Content-Type: text/plain\\n\\n%s|%s|%s' \"$HTTP_TRACEPARENT\" \"$HTTP_TRACESTATE\" \"$HTTP_X_REQUEST_ID\"\n",
+    )?;
+    let handler = CgiHandler::new(script_root.path().to_string_lossy().to_string());
+
+    let raw_request = "GET /trace.cgi HTTP/1.1\r\nTraceparent: 00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01\r\nTracestate: congo=t61rcWkgMzE\r\n\r\n";
+    let request = HttpRequest::try_from(raw_request.as_bytes())?;
+    let response = respond(&handler, &request).await?;
+
+    let body = String::from_utf8(response.body().clone().unwrap_or_default())?;
+    let [traceparent, tracestate, request_id] = body.splitn(3, '|').collect::<Vec<_>>()[..] else {
+      panic!("unexpected output {}", body);
+    };
+    expect!(traceparent.starts_with("00-4bf92f3577b34da6a3ce929d0e0e4736-")).to(be_true());
+    expect!(traceparent.ends_with("-01")).to(be_true());
+    expect!(traceparent.contains("00f067aa0ba902b7")).to(be_false());
+    expect!(tracestate).to(be_equal_to("congo=t61rcWkgMzE"));
+    expect!(request_id).to(be_equal_to(request.request_id()));
+    Ok(())
+  }
+
+  #[rstest]
+  #[tokio::test]
+  async fn test_cgi_ignores_proxy_header() -> Result<(), Box<dyn std::error::Error>> {
+    let script_root = TempDir::new()?;
+    write_script(
+      &script_root,
+      "proxy.cgi",
+      "#!/bin/sh\nprintf 'Content-Type: text/plain\\n\\n%s' \"${HTTP_PROXY-unset}\"\n",
+    )?;
+    let handler = CgiHandler::new(script_root.path().to_string_lossy().to_string());
+
+    let raw_request = "GET /proxy.cgi HTTP/1.1\r\nProxy: http://attacker.example:8080\r\n\r\n";
+    let request = HttpRequest::try_from(raw_request.as_bytes())?;
+    let response = respond(&handler, &request).await?;
+
+    expect!(response.body().as_deref()).to(be_some().value(&b"unset"[..]));
+    Ok(())
+  }
+
+  #[rstest]
+  #[tokio::test]
+  async fn test_cgi_drops_hop_by_hop_headers() -> Result<(), Box<dyn std::error::Error>> {
+    let script_root = TempDir::new()?;
+    write_script(
+      &script_root,
+      "framing.cgi",
+      "#!/bin/sh\nprintf 'Content-Type: text/plain\\nTransfer-Encoding: chunked\\nContent-Length: 1\\nConnection: keep-alive\\nUpgrade: websocket\\nX-Kept: yes\\n\\nhello'\n",
+    )?;
+    let handler = CgiHandler::new(script_root.path().to_string_lossy().to_string());
+
+    let request =
+      HttpRequest::try_from(&b"GET /framing.cgi HTTP/1.1\r\nHost: localhost\r\n\r\n"[..])?;
+    let mut response = handler.handle_async(&request).unwrap().await?;
+
+    // the output is streamed, in this server's framing
+    expect!(response.is_streamed()).to(be_true());
+    expect!(response.head().contains("Transfer-Encoding: chunked\r\n")).to(be_true());
+    response.collect_body().await?;
+    let header = response.http_header().as_ref().unwrap();
+    expect!(header.get(HttpResponseHeaderKey::TransferEncoding)).to(be_none());
+    expect!(header.get(HttpResponseHeaderKey::Connection)).to(be_none());
+    expect!(header.get(HttpResponseHeaderKey::Upgrade)).to(be_none());
+    expect!(header.get(HttpResponseHeaderKey::ContentLength)).to(be_none());
+    expect!(header.get("X-Kept")).to(be_some().value("yes"));
+    expect!(response.body().as_deref()).to(be_some().value(&b"hello"[..]));
+    Ok(())
+  }
+
+  #[rstest]
+  #[case::status_header("#!/bin/sh\nprintf 'Status: 404 Not Found\\n\\n'\n", StatusCode::NotFound)]
+  #[case::missing_separator("#!/bin/sh\necho 'no headers'\n", StatusCode::InternalError)]
+  #[case::failed_script("#!/bin/sh\nexit 3\n", StatusCode::InternalError)]
+  #[tokio::test]
+  async fn test_cgi_status(
+    #[case] script: &str,
+    #[case] expected_status: StatusCode,
+  ) -> Result<(), Box<dyn std::error::Error>> {
+    let script_root = TempDir::new()?;
+    write_script(&script_root, "script.cgi", script)?;
+    let handler = CgiHandler::new(script_root.path().to_string_lossy().to_string());
+
+    let request =
+      HttpRequest::try_from(&b"GET /script.cgi HTTP/1.1\r\nHost: localhost\r\n\r\n"[..])?;
+    let response = respond(&handler, &request)
+      .await
+      .unwrap_or_else(HttpResponse::from);
+    expect!(*response.status_code()).to(be_equal_to(expected_status));
+    Ok(())
+  }
+
+  #[rstest]
+  #[tokio::test]
+  async fn test_cgi_traversal_is_rejected() -> Result<(), Box<dyn std::error::Error>> {
+    let script_root = TempDir::new()?;
+    let handler = CgiHandler::new(
+      script_root
+        .path()
+        .join("cgi-bin")
+        .to_string_lossy()
+        .to_string(),
+    );
+
+    let request =
+      HttpRequest::try_from(&b"GET /../../bin/sh HTTP/1.1\r\nHost: localhost\r\n\r\n"[..])?;
+    let response = respond(&handler, &request)
+      .await
+      .unwrap_or_else(HttpResponse::from);
+    expect!(*response.status_code()).to(be_equal_to(StatusCode::NotFound));
+    Ok(())
+  }
+}
@@ -0,0 +1,378 @@
+use brotli::CompressorWriter;
+use flate2::{
+  write::{GzEncoder, ZlibEncoder},
+  Compression,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::{
+  io::{self, Write},
+  sync::Arc,
+};
+
+use crate::http::{
+  codec,
+  header::{HttpRequestHeaderKey, HttpResponseHeaderKey},
+  HttpRequest, HttpResponse, Method, RequestBody,
+};
+use crate::server::{Handler, HandlerFuture, HandlerResult};
+
+pub use crate::http::content_coding::ContentCoding;
+
+/// Which responses get compressed, e.g. from the config file:
+///
+/// ```toml
+/// [compression]
+/// min_size = 1024
+/// content_types = ["text/*", "application/json", "image/svg+xml"]
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CompressionSettings {
+  /// bodies smaller than this are sent as they are, compressing them gains next to nothing
+  pub min_size: usize,
+  /// MIME types worth compressing, `type/*` matches all subtypes. Images, video and archives
+  /// are compressed already.
+  pub content_types: Vec<String>,
+}
+
+impl Default for CompressionSettings {
+  fn default() -> Self {
+    Self {
+      min_size: 1024,
+      content_types: [
+        "text/*",
+        "application/javascript",
+        "application/json",
+        "application/wasm",
+        "application/xml",
+        "image/svg+xml",
+      ]
+      .map(String::from)
+      .to_vec(),
+    }
+  }
+}
+
+/// Compressing is the part that needs the codecs of the `compression` feature
+impl ContentCoding {
+  pub fn compress(&self, body: &[u8]) -> io::Result<Vec<u8>> {
+    match self {
+      Self::Brotli => {
+        // quality 5 of 11 keeps on-the-fly compression cheap
+        let mut encoder = CompressorWriter::new(Vec::new(), 4096, 5, 22);
+        encoder.write_all(body)?;
+        Ok(encoder.into_inner())
+      }
+      Self::Zstd => zstd::encode_all(body, 3),
+      Self::Gzip => {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(body)?;
+        encoder.finish()
+      }
+      // HTTP's "deflate" is the zlib format, not raw deflate
+      Self::Deflate => {
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(body)?;
+        encoder.finish()
+      }
+    }
+  }
+}
+
+/// Compresses the responses of `inner` with the best [`ContentCoding`] the client accepts,
+/// setting `Content-Encoding` and `Vary: Accept-Encoding`. Only bodies of a compressible
+/// content type and at least `min_size` bytes are compressed; those encoded already are left
+/// alone, as are streamed ones unless their length is known, e.g. files.
+pub struct CompressionHandler {
+  settings: CompressionSettings,
+  inner: Arc<dyn Handler>,
+}
+
+impl CompressionHandler {
+  pub fn new(settings: CompressionSettings, inner: Arc<dyn Handler>) -> Self {
+    Self { settings, inner }
+  }
+
+  fn compressible(&self, content_type: &str) -> bool {
+    let essence = content_type
+      .split(';')
+      .next()
+      .unwrap_or_default()
+      .trim()
+      .to_lowercase();
+    self.settings.content_types.iter().any(|compressible| {
+      let compressible = compressible.to_lowercase();
+      match compressible.strip_suffix('*') {
+        Some(prefix) => essence.starts_with(prefix),
+        None => essence == compressible,
+      }
+    })
+  }
+
+  /// Whether `response` streams a body [`Self::compress`] would compress, which has to be read
+  /// into memory first. Only bodies of a known length are, event streams never end.
+  fn worth_collecting(&self, request: &HttpRequest<'_>, response: &HttpResponse) -> bool {
+    let Some(header) = response.http_header().as_deref() else {
+      return false;
+    };
+    let content_length = header
+      .get(HttpResponseHeaderKey::ContentLength)
+      .and_then(|content_length| codec::parse_content_length(content_length));
+    response.is_streamed()
+      && response.status_code().allows_body()
+      && header.get(HttpResponseHeaderKey::ContentEncoding).is_none()
+      && header
+        .get(HttpResponseHeaderKey::ContentType)
+        .is_some_and(|content_type| self.compressible(content_type))
+      && content_length.is_some_and(|length| length >= self.settings.min_size as u64)
+      && request
+        .header()
+        .get(HttpRequestHeaderKey::AcceptEncoding)
+        .and_then(|accept_encoding| ContentCoding::negotiate(accept_encoding))
+        .is_some()
+  }
+
+  fn compress(&self, request: &HttpRequest<'_>, mut response: HttpResponse) -> HttpResponse {
+    let header = response.http_header().as_deref();
+    let content_type = header.and_then(|header| header.get(HttpResponseHeaderKey::ContentType));
+    let encoded =
+      header.is_some_and(|header| header.get(HttpResponseHeaderKey::ContentEncoding).is_some());
+    if response.is_streamed()
+      || !response.status_code().allows_body()
+      || encoded
+      || !content_type.is_some_and(|content_type| self.compressible(content_type))
+    {
+      return response;
+    }
+
+    // caches must not hand the compressed body to clients that can't take it, or vice versa
+    response.add_vary("Accept-Encoding");
+
+    let Some(body) = response
+      .body()
+      .as_deref()
+      .filter(|body| body.len() >= self.settings.min_size)
+    else {
+      return response;
+    };
+    let Some(coding) = request
+      .header()
+      .get(HttpRequestHeaderKey::AcceptEncoding)
+      .and_then(|accept_encoding| ContentCoding::negotiate(accept_encoding))
+    else {
+      return response;
+    };
+    let compressed = match coding.compress(body) {
+      Ok(compressed) if compressed.len() < body.len() => compressed,
+      _ => return response,
+    };
+
+    let status_code = *response.status_code();
+    response.insert_header(HttpResponseHeaderKey::ContentEncoding, coding.as_str());
+    response.insert_header(
+      HttpResponseHeaderKey::ContentLength,
+      &compressed.len().to_string(),
+    );
+    HttpResponse::new(
+      status_code,
+      Some(compressed),
+      response.http_header().clone(),
+    )
+  }
+}
+
+impl Handler for CompressionHandler {
+  fn handle_request(&self, request: &HttpRequest<'_>) -> HandlerResult {
+    Ok(self.compress(request, self.inner.handle_request(request)?))
+  }
+
+  /// Streamed files are read into memory to be compressed, but still through tokio
+  fn handle_async<'a>(&'a self, request: &'a HttpRequest<'a>) -> Option<HandlerFuture<'a>> {
+    let handled = self.inner.handle_async(request)?;
+    Some(Box::pin(async move {
+      let mut response = handled.await?;
+      if self.worth_collecting(request, &response) {
+        response.collect_body().await?;
+      }
+      Ok(self.compress(request, response))
+    }))
+  }
+
+  fn handle_with_body(&self, request: &HttpRequest<'_>, body: RequestBody) -> HandlerResult {
+    Ok(self.compress(request, self.inner.handle_with_body(request, body)?))
+  }
+
+  fn allowed_methods(&self) -> Vec<Method> {
+    self.inner.allowed_methods()
+  }
+
+  fn describe(&self) -> Value {
+    json!({
+      "handler": "CompressionHandler",
+      "min_size": self.settings.min_size,
+      "content_types": self.settings.content_types,
+      "inner": self.inner.describe(),
+    })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::http::{header::HttpResponseHeaderBuilder, StatusCode};
+  use expectest::prelude::*;
+  use flate2::read::{GzDecoder, ZlibDecoder};
+  use rstest::*;
+  use std::io::Read;
+
+  #[rstest]
+  #[case(ContentCoding::Brotli)]
+  #[case(ContentCoding::Zstd)]
+  #[case(ContentCoding::Gzip)]
+  #[case(ContentCoding::Deflate)]
+  fn test_compress_round_trip(#[case] coding: ContentCoding) -> io::Result<()> {
+    let body = "compress me ".repeat(100);
+    let compressed = coding.compress(body.as_bytes())?;
+    let mut decompressed = Vec::new();
+    match coding {
+      ContentCoding::Brotli => {
+        brotli::Decompressor::new(&compressed[..], 4096).read_to_end(&mut decompressed)?;
+      }
+      ContentCoding::Zstd => decompressed = zstd::decode_all(&compressed[..])?,
+      ContentCoding::Gzip => {
+        GzDecoder::new(&compressed[..]).read_to_end(&mut decompressed)?;
+      }
+      ContentCoding::Deflate => {
+        ZlibDecoder::new(&compressed[..]).read_to_end(&mut decompressed)?;
+      }
+    }
+    expect!(compressed.len() < body.len()).to(be_true());
+    expect!(decompressed).to(be_equal_to(body.into_bytes()));
+    Ok(())
+  }
+
+  struct TextHandler {
+    content_type: &'static str,
+    body: String,
+  }
+
+  impl Handler for TextHandler {
+    fn handle_request(&self, _request: &HttpRequest<'_>) -> HandlerResult {
+      let mut builder = HttpResponseHeaderBuilder::new();
+      builder.content_type(self.content_type);
+      builder.vary("Origin");
+      Ok(HttpResponse::new(
+        StatusCode::Ok,
+        Some(self.body.clone().into_bytes()),
+        Some(Arc::new(builder.build())),
+      ))
+    }
+  }
+
+  #[rstest]
+  #[case::compressed("text/html; charset=utf-8", 2048, "gzip", Some("gzip"), true)]
+  #[case::too_small("text/html", 100, "gzip", None, true)]
+  #[case::not_accepted("application/json", 2048, "identity", None, true)]
+  #[case::not_compressible("image/png", 2048, "gzip", None, false)]
+  #[tokio::test]
+  async fn test_compress_response(
+    #[case] content_type: &'static str,
+    #[case] body_size: usize,
+    #[case] accept_encoding: &str,
+    #[case] expected_encoding: Option<&str>,
+    #[case] varies: bool,
+  ) -> io::Result<()> {
+    let body = "a".repeat(body_size);
+    let inner = TextHandler { content_type, body: body.clone() };
+    let handler = CompressionHandler::new(CompressionSettings::default(), Arc::new(inner));
+    let raw_request = format!(
+      "GET / HTTP/1.1\r\nHost: localhost\r\nAccept-Encoding: {}\r\n\r\n",
+      accept_encoding
+    );
+    let request = HttpRequest::try_from(raw_request.as_bytes()).unwrap();
+
+    let mut response = handler.handle_request(&request).unwrap();
+    let header = response.http_header().clone().unwrap();
+    let encoding = header.get(HttpResponseHeaderKey::ContentEncoding);
+    expect!(encoding.map(String::as_str)).to(be_equal_to(expected_encoding));
+    let expected_vary = if varies { "Origin, Accept-Encoding" } else { "Origin" };
+    let vary = header.get(HttpResponseHeaderKey::Vary);
+    expect!(vary.map(String::as_str)).to(be_some().value(expected_vary));
+
+    let mut sent = Vec::new();
+    response.send(&mut sent).await?;
+    let head_length = crate::http::codec::head_length(&sent).unwrap();
+    let mut received = Vec::new();
+    match expected_encoding {
+      Some(_) => {
+        GzDecoder::new(&sent[head_length..]).read_to_end(&mut received)?;
+      }
+      None => received = sent[head_length..].to_vec(),
+    }
+    expect!(received).to(be_equal_to(body.into_bytes()));
+    expect!(response.closes_connection()).to(be_false());
+    Ok(())
+  }
+
+  /// Streams text through its async path, of a known length like files or not like event
+  /// streams
+  struct StreamedHandler {
+    known_length: bool,
+  }
+
+  impl Handler for StreamedHandler {
+    fn handle_request(&self, _request: &HttpRequest<'_>) -> HandlerResult {
+      unreachable!("asked asynchronously")
+    }
+
+    fn handle_async<'a>(&'a self, _request: &'a HttpRequest<'a>) -> Option<HandlerFuture<'a>> {
+      Some(Box::pin(async move {
+        let body = "a".repeat(2048).into_bytes();
+        let mut builder = HttpResponseHeaderBuilder::new();
+        builder.content_type("text/plain");
+        if self.known_length {
+          builder.content_length(&body.len().to_string());
+        }
+        let header = Some(Arc::new(builder.build()));
+        Ok(HttpResponse::streamed(
+          StatusCode::Ok,
+          io::Cursor::new(body),
+          header,
+        ))
+      }))
+    }
+  }
+
+  #[rstest]
+  #[case::file(true, Some("gzip"))]
+  #[case::unknown_length(false, None)]
+  #[tokio::test]
+  async fn test_compress_async_response(
+    #[case] known_length: bool,
+    #[case] expected_encoding: Option<&str>,
+  ) -> io::Result<()> {
+    let inner = StreamedHandler { known_length };
+    let handler = CompressionHandler::new(CompressionSettings::default(), Arc::new(inner));
+    let request = HttpRequest::try_from(
+      &b"GET / HTTP/1.1\r\nHost: localhost\r\nAccept-Encoding: gzip\r\n\r\n"[..],
+    )
+    .unwrap();
+
+    let mut response = handler.handle_async(&request).unwrap().await.unwrap();
+    let header = response.http_header().clone().unwrap();
+    let encoding = header.get(HttpResponseHeaderKey::ContentEncoding);
+    expect!(encoding.map(String::as_str)).to(be_equal_to(expected_encoding));
+    expect!(response.is_streamed()).to(be_equal_to(!known_length));
+
+    let mut sent = Vec::new();
+    response.send(&mut sent).await?;
+    if known_length {
+      let head_length = crate::http::codec::head_length(&sent).unwrap();
+      let mut received = Vec::new();
+      GzDecoder::new(&sent[head_length..]).read_to_end(&mut received)?;
+      expect!(received).to(be_equal_to("a".repeat(2048).into_bytes()));
+    }
+    Ok(())
+  }
+}
@@ -0,0 +1,456 @@
+use serde::Deserialize;
+use std::{collections::HashMap, env, fs, io, path::Path};
+use thiserror::Error;
+
+use crate::access_log::AccessLogFormat;
+use crate::basic_auth::BasicAuthSettings;
+use crate::cors::CorsPolicy;
+use crate::file_cache::FileCacheSettings;
+use crate::http::{
+  forwarded::IpRange, hsts::Hsts, mime::DEFAULT_CHARSET, MimeTypes, ParserLimits, QueryLimits,
+};
+use crate::limits::{Limits, RateLimit, RouteLimits};
+use crate::server::{ConnectionOverflow, ListenerSettings};
+use crate::session::SessionSettings;
+use crate::tenant_handler::TenantSettings;
+use crate::upload_handler::UploadSettings;
+use crate::virtual_host_handler::VirtualHostSettings;
+use crate::website_handler::WebsiteSettings;
+
+/// Settings read from the TOML file pointed to by `CONFIG_PATH`, e.g.
+///
+/// ```toml
+/// # where to listen, overridden by `BIND_ADDR` and `PORT`
+/// host = "0.0.0.0"
+/// port = 8080
+/// # served files, overridden by `PUBLIC_PATH`
+/// public_path = "/srv/www"
+/// # with the `embed` feature, serve the public directory compiled in instead
+/// embedded_assets = true
+///
+/// default_charset = "utf-8"
+///
+/// [mime_types]
+/// wasm = "application/wasm"
+/// m3u8 = "application/vnd.apple.mpegurl"
+///
+/// # executed as CGI scripts instead of being served raw
+/// cgi_extensions = ["cgi"]
+///
+/// # serves /metrics, keep it off public interfaces
+/// admin_address = "127.0.0.1:9090"
+///
+/// # "text" (the default) or "json", one object per request
+/// access_log = "json"
+/// # request line and headers, larger heads get 431 Request Header Fields Too Large
+/// max_head_size = 16384
+/// # bodies read before the handler is called, larger ones are streamed to it
+/// max_buffered_body_size = 65536
+/// # idle connections are closed after this long
+/// keep_alive_timeout_secs = 5
+/// # clients slower to send the head, the body or the whole request get 408 Request Timeout
+/// header_read_timeout_secs = 10
+/// body_read_timeout_secs = 30
+/// request_timeout_secs = 60
+/// # bytes per write of response bodies, trading throughput for memory
+/// response_chunk_size = 65536
+///
+/// # response bytes per second and connection
+/// bandwidth_limit = 1048576
+/// # connections served at once, the next ones "wait" in the backlog (the default) or
+/// # "reject" gets them 503 Service Unavailable
+/// max_connections = 10000
+/// connection_overflow = "reject"
+///
+/// # log a tenth of the requests, server errors are always logged
+/// access_log_sample_rate = 0.1
+/// # identical error messages written per period, the rest is counted
+/// error_log_rate_limit = { requests = 10, per_secs = 60 }
+///
+/// # answer /debug/echo with the parsed request, for checking proxies and clients
+/// debug_echo = true
+///
+/// # believe the client address and scheme these proxies forward
+/// trusted_proxies = ["10.0.0.0/8", "127.0.0.1"]
+/// # behind a TCP load balancer that sends a PROXY protocol header on every connection
+/// proxy_protocol = true
+/// # accept loops per listener, bound with SO_REUSEPORT when more than 1
+/// acceptors = 4
+///
+/// # more addresses for the same site, see `ListenerSettings`
+/// [[listeners]]
+/// address = "[::]:8080"
+///
+/// # see [`ParserLimits`]
+/// [parser_limits]
+/// max_request_line_length = 8192
+/// max_header_value_length = 8192
+/// max_headers_count = 100
+/// max_body_size = 1073741824
+///
+/// [query_limits]
+/// max_length = 4096
+/// max_keys = 64
+/// max_values_per_key = 32
+///
+/// # see [`Limits`], `route_limits` override them below a path prefix
+/// [limits]
+/// max_body_size = 65536
+///
+/// [[route_limits]]
+/// prefix = "/upload"
+/// limits = { max_body_size = 104857600 }
+///
+/// # see `WebsiteSettings`
+/// [website]
+/// index_documents = ["index.html", "index.htm"]
+/// spa_fallback = true
+///
+/// # pages for error responses without a body, relative to the public path
+/// [website.error_pages]
+/// 404 = "errors/404.html"
+///
+/// # keep up to 64 MiB of files of up to 1 MiB each in memory, see `FileCacheSettings`
+/// [file_cache]
+/// max_memory = 67108864
+/// max_file_size = 1048576
+///
+/// # PUT and multipart POST requests below /uploads store files, see `UploadSettings`
+/// [uploads]
+/// path = "/uploads"
+/// directory = "/srv/www/uploads"
+/// max_file_size = 104857600
+///
+/// # scripts on app.example.com may call the server, see `CorsSettings`
+/// [cors]
+/// allowed_origins = ["https://app.example.com"]
+/// allowed_methods = ["GET", "POST", "DELETE"]
+/// allowed_headers = ["Content-Type"]
+///
+/// # users of the htpasswd file only, see `BasicAuthSettings`
+/// [basic_auth]
+/// paths = ["/admin"]
+/// htpasswd_file = "/etc/udemy_server/htpasswd"
+///
+/// # a session per visitor, kept in memory behind a signed cookie, see `SessionSettings`
+/// [sessions]
+/// secret = "at least 32 bytes of random characters"
+/// secure = true
+///
+/// # with the `jwt` feature, bearer tokens below /api, see `JwtSettings`
+/// [jwt]
+/// paths = ["/api"]
+/// algorithm = "RS256"
+/// public_key_file = "/etc/udemy_server/jwt.pem"
+///
+/// # sites of their own, picked by the Host of each request, see `VirtualHostSettings`;
+/// # other hosts are served from public_path
+/// [[virtual_hosts]]
+/// hosts = ["example.com", "*.example.com"]
+/// public_path = "/srv/example"
+///
+/// # blog.sites.example.com is served from /srv/tenants/blog/public
+/// [tenants]
+/// domain = "*.sites.example.com"
+/// base_path = "/srv/tenants"
+///
+/// # with the `compression` feature, see `CompressionSettings` for the defaults
+/// [compression]
+/// min_size = 1024
+///
+/// # only sent over TLS, see [`Hsts`] for the validation rules
+/// [hsts]
+/// max_age = 31536000
+/// include_subdomains = true
+/// ```
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+  pub host: String,
+  pub port: u16,
+  /// defaults to `public` in the crate's directory, which only exists for cargo commands
+  pub public_path: String,
+  pub default_charset: String,
+  /// file extension -> MIME type, applied on top of the built-in table
+  pub mime_types: HashMap<String, String>,
+  /// extensions of files below the public path that are executed through [`crate::cgi_handler::CgiHandler`]
+  pub cgi_extensions: Vec<String>,
+  pub admin_address: Option<String>,
+  /// defaults to [`crate::server::DEFAULT_MAX_HEAD_SIZE`]
+  pub max_head_size: Option<usize>,
+  /// defaults to [`crate::server::DEFAULT_MAX_BUFFERED_BODY_SIZE`]
+  pub max_buffered_body_size: Option<usize>,
+  /// defaults to [`crate::server::DEFAULT_KEEP_ALIVE_TIMEOUT`]
+  pub keep_alive_timeout_secs: Option<u64>,
+  /// defaults to [`crate::server::DEFAULT_HEADER_READ_TIMEOUT`]
+  pub header_read_timeout_secs: Option<u64>,
+  /// defaults to [`crate::server::DEFAULT_BODY_READ_TIMEOUT`]
+  pub body_read_timeout_secs: Option<u64>,
+  /// no limit beyond the header and body read timeouts by default
+  pub request_timeout_secs: Option<u64>,
+  /// defaults to [`crate::http::response::DEFAULT_CHUNK_SIZE`]
+  pub response_chunk_size: Option<usize>,
+  /// response bytes per second, per connection
+  pub bandwidth_limit: Option<u64>,
+  /// no limit by default
+  pub max_connections: Option<usize>,
+  pub connection_overflow: ConnectionOverflow,
+  pub access_log: AccessLogFormat,
+  /// share of requests written to the access log, from 0 to 1
+  pub access_log_sample_rate: f64,
+  /// defaults to 10 identical messages per minute
+  pub error_log_rate_limit: Option<RateLimit>,
+  pub parser_limits: ParserLimits,
+  pub query_limits: QueryLimits,
+  pub limits: Limits,
+  pub route_limits: Vec<RouteLimits>,
+  pub hsts: Option<Hsts>,
+  /// addresses or CIDR blocks of the proxies whose `Forwarded` and `X-Forwarded-*` headers are
+  /// believed, none by default
+  pub trusted_proxies: Vec<IpRange>,
+  /// connections start with a PROXY protocol header naming the client, off by default
+  pub proxy_protocol: bool,
+  /// accepted on besides `host` and `port`
+  pub listeners: Vec<ListenerSettings>,
+  /// sockets bound with `SO_REUSEPORT` per listener, 1 (a single plain socket) by default
+  pub acceptors: usize,
+  pub website: WebsiteSettings,
+  /// files are read from disk for every request by default
+  pub file_cache: Option<FileCacheSettings>,
+  /// other sites' scripts can't read responses by default
+  pub cors: Option<CorsPolicy>,
+  /// nothing needs credentials by default
+  pub basic_auth: Option<BasicAuthSettings>,
+  /// handlers get no [`crate::session::Session`] by default
+  pub sessions: Option<SessionSettings>,
+  pub virtual_hosts: Vec<VirtualHostSettings>,
+  pub tenants: Option<TenantSettings>,
+  /// uploads are refused by default, see [`crate::upload_handler::UploadHandler`]
+  pub uploads: Option<UploadSettings>,
+  /// serve [`crate::echo_handler::EchoHandler`] on `/debug/echo`
+  pub debug_echo: bool,
+  /// only understood when built with the `tls` feature
+  #[cfg(feature = "tls")]
+  pub tls: Option<crate::tls::TlsSettings>,
+  /// only understood when built with the `compression` feature
+  #[cfg(feature = "compression")]
+  pub compression: Option<crate::compression::CompressionSettings>,
+  /// only understood when built with the `jwt` feature
+  #[cfg(feature = "jwt")]
+  pub jwt: Option<crate::jwt::JwtSettings>,
+  /// only understood when built with the `acme` feature, takes the place of `tls`
+  #[cfg(feature = "acme")]
+  pub acme: Option<crate::acme::AcmeSettings>,
+  /// serve the files compiled in with the `embed` feature rather than `public_path`, see
+  /// [`crate::embedded_assets::PUBLIC`]
+  #[cfg(feature = "embed")]
+  pub embedded_assets: bool,
+}
+
+#[derive(Error, Debug)]
+pub enum ConfigError {
+  #[error("Failed to read config file: {0}")]
+  Io(#[from] io::Error),
+  #[error("Invalid config file: {0}")]
+  Toml(#[from] toml::de::Error),
+  #[error("Invalid value for {0}: {1}")]
+  InvalidEnv(&'static str, String),
+}
+
+impl Config {
+  /// Load the config file named by `CONFIG_PATH` (if any) and apply environment overrides
+  pub fn load() -> Result<Self, ConfigError> {
+    let mut config = match env::var("CONFIG_PATH") {
+      Ok(path) => Self::from_file(path)?,
+      Err(_) => Self::default(),
+    };
+
+    if let Ok(host) = env::var("BIND_ADDR") {
+      config.host = host;
+    }
+    if let Ok(port) = env::var("PORT") {
+      config.port = port
+        .parse()
+        .map_err(|_| ConfigError::InvalidEnv("PORT", port))?;
+    }
+    if let Ok(public_path) = env::var("PUBLIC_PATH") {
+      config.public_path = public_path;
+    }
+    if let Ok(default_charset) = env::var("DEFAULT_CHARSET") {
+      config.default_charset = default_charset;
+    }
+    Ok(config)
+  }
+
+  /// `host:port` to listen on, bracketing IPv6 hosts
+  pub fn address(&self) -> String {
+    match self.host.contains(':') && !self.host.starts_with('[') {
+      true => format!("[{}]:{}", self.host, self.port),
+      false => format!("{}:{}", self.host, self.port),
+    }
+  }
+
+  pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, ConfigError> {
+    Ok(toml::from_str(&fs::read_to_string(path)?)?)
+  }
+
+  pub fn mime_types(&self) -> MimeTypes {
+    // an empty default_charset leaves textual content types without a charset parameter
+    let default_charset = Some(self.default_charset.clone()).filter(|charset| !charset.is_empty());
+    MimeTypes::new(default_charset).with_overrides(self.mime_types.clone())
+  }
+}
+
+impl Default for Config {
+  fn default() -> Self {
+    Self {
+      host: "127.0.0.1".to_string(),
+      port: 8080,
+      public_path: format!("{}/public", env!("CARGO_MANIFEST_DIR")),
+      default_charset: DEFAULT_CHARSET.to_string(),
+      mime_types: HashMap::new(),
+      cgi_extensions: Vec::new(),
+      admin_address: None,
+      max_head_size: None,
+      max_buffered_body_size: None,
+      keep_alive_timeout_secs: None,
+      header_read_timeout_secs: None,
+      body_read_timeout_secs: None,
+      request_timeout_secs: None,
+      response_chunk_size: None,
+      bandwidth_limit: None,
+      max_connections: None,
+      connection_overflow: ConnectionOverflow::default(),
+      access_log: AccessLogFormat::default(),
+      access_log_sample_rate: 1.0,
+      error_log_rate_limit: None,
+      parser_limits: ParserLimits::default(),
+      query_limits: QueryLimits::default(),
+      limits: Limits::default(),
+      route_limits: Vec::new(),
+      hsts: None,
+      trusted_proxies: Vec::new(),
+      proxy_protocol: false,
+      listeners: Vec::new(),
+      acceptors: 1,
+      website: WebsiteSettings::default(),
+      file_cache: None,
+      cors: None,
+      basic_auth: None,
+      sessions: None,
+      virtual_hosts: Vec::new(),
+      tenants: None,
+      uploads: None,
+      debug_echo: false,
+      #[cfg(feature = "tls")]
+      tls: None,
+      #[cfg(feature = "compression")]
+      compression: None,
+      #[cfg(feature = "jwt")]
+      jwt: None,
+      #[cfg(feature = "acme")]
+      acme: None,
+      #[cfg(feature = "embed")]
+      embedded_assets: false,
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use expectest::prelude::*;
+  use rstest::*;
+
+  #[rstest]
+  fn test_parse_mime_types() -> Result<(), ConfigError> {
+    let config: Config = toml::from_str(
+      r#"
+      [mime_types]
+      wasm = "application/wasm"
+      ".m3u8" = "application/vnd.apple.mpegurl"
+      "#,
+    )?;
+    let mime_types = config.mime_types();
+
+    expect!(config.default_charset.as_str()).to(be_equal_to(DEFAULT_CHARSET));
+    expect!(mime_types.content_type("app.wasm", b"")).to(be_equal_to("application/wasm"));
+    expect!(mime_types.content_type("live/stream.m3u8", b""))
+      .to(be_equal_to("application/vnd.apple.mpegurl"));
+    Ok(())
+  }
+
+  #[rstest]
+  #[case::ipv4("0.0.0.0", "0.0.0.0:3000")]
+  #[case::ipv6("::1", "[::1]:3000")]
+  #[case::name("localhost", "localhost:3000")]
+  fn test_address(#[case] host: &str, #[case] expected: &str) -> Result<(), ConfigError> {
+    let config: Config = toml::from_str(&format!("host = \"{}\"\nport = 3000", host))?;
+    expect!(config.address()).to(be_equal_to(expected));
+    Ok(())
+  }
+
+  #[rstest]
+  fn test_parse_error_pages() -> Result<(), ConfigError> {
+    let config: Config = toml::from_str(
+      r#"
+      [website.error_pages]
+      404 = "errors/404.html"
+      "503" = "errors/maintenance.html"
+      "#,
+    )?;
+    let error_pages = &config.website.error_pages;
+    expect!(error_pages.get(&404).map(String::as_str)).to(be_some().value("errors/404.html"));
+    expect!(error_pages.get(&503).map(String::as_str))
+      .to(be_some().value("errors/maintenance.html"));
+    Ok(())
+  }
+
+  #[rstest]
+  fn test_parse_virtual_hosts() -> Result<(), ConfigError> {
+    let config: Config = toml::from_str(
+      r#"
+      [[virtual_hosts]]
+      hosts = ["Example.com", "*.example.com"]
+      public_path = "/srv/example"
+      "#,
+    )?;
+    let hosts = &config.virtual_hosts[0].hosts;
+    expect!(hosts.iter().map(ToString::to_string).collect::<Vec<_>>())
+      .to(be_equal_to(vec!["example.com", "*.example.com"]));
+
+    let invalid = toml::from_str::<Config>(
+      "[[virtual_hosts]]\nhosts = [\"www.*.com\"]\npublic_path = \"/srv\"",
+    );
+    expect!(invalid.map_err(|e| e.to_string().contains("Invalid host pattern")))
+      .to(be_err().value(true));
+    Ok(())
+  }
+
+  #[rstest]
+  fn test_parse_uploads() -> Result<(), ConfigError> {
+    let config: Config = toml::from_str("[uploads]\ndirectory = \"/srv/uploads\"")?;
+    let uploads = config.uploads.unwrap();
+    expect!(uploads.path.as_str()).to(be_equal_to("/uploads"));
+    expect!(uploads.max_file_size).to(be_equal_to(10 * 1024 * 1024));
+    expect!(uploads.overwrite).to(be_false());
+    expect!(toml::from_str::<Config>("[uploads]\npath = \"/drop\"")).to(be_err());
+    Ok(())
+  }
+
+  #[rstest]
+  fn test_parse_parser_limits() -> Result<(), ConfigError> {
+    let config: Config =
+      toml::from_str("[parser_limits]\nmax_header_value_length = 16384\nmax_body_size = 1024")?;
+    expect!(config.parser_limits.max_header_value_length).to(be_equal_to(16384));
+    expect!(config.parser_limits.max_body_size).to(be_some().value(1024));
+    expect!(config.parser_limits.max_headers_count)
+      .to(be_equal_to(ParserLimits::default().max_headers_count));
+    Ok(())
+  }
+
+  #[rstest]
+  fn test_reject_unknown_keys() {
+    expect!(toml::from_str::<Config>("mime = {}")).to(be_err());
+  }
+}
@@ -0,0 +1,461 @@
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::convert::TryFrom;
+use thiserror::Error;
+
+use crate::http::{
+  header::{HttpRequestHeaderKey, HttpResponseHeaderKey},
+  HttpRequest, HttpResponse, Method, StatusCode,
+};
+use crate::middleware::{Middleware, Next};
+use crate::server::{HandlerFuture, HandlerResult};
+
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum CorsError {
+  #[error("Invalid CORS origin {0:?}, expected one like https://example.com:8443 or *")]
+  InvalidOrigin(String),
+  #[error("Invalid CORS method {0:?}")]
+  InvalidMethod(String),
+  #[error("CORS credentials can't be allowed for any origin, list the origins instead")]
+  CredentialsWithAnyOrigin,
+}
+
+/// What a [`CorsPolicy`] allows, e.g. from the config file:
+///
+/// ```toml
+/// [cors]
+/// allowed_origins = ["https://app.example.com"]
+/// allowed_methods = ["GET", "POST", "DELETE"]
+/// allowed_headers = ["Authorization", "Content-Type"]
+/// allow_credentials = true
+/// max_age_secs = 600
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CorsSettings {
+  /// origins as browsers send them, scheme, host and port if it isn't the default; `*` for any
+  pub allowed_origins: Vec<String>,
+  /// for requests preflighted, browsers always allow `GET`, `HEAD` and `POST` otherwise
+  pub allowed_methods: Vec<String>,
+  /// request headers beyond the few browsers always allow; `*` for any
+  pub allowed_headers: Vec<String>,
+  /// whether browsers may send cookies and credentials along, and let scripts read the response
+  pub allow_credentials: bool,
+  /// how long browsers may cache a preflight answer, a few seconds by default
+  pub max_age_secs: Option<u64>,
+}
+
+impl Default for CorsSettings {
+  fn default() -> Self {
+    Self {
+      allowed_origins: vec!["*".to_string()],
+      allowed_methods: vec!["GET".to_string(), "HEAD".to_string(), "POST".to_string()],
+      allowed_headers: Vec::new(),
+      allow_credentials: false,
+      max_age_secs: None,
+    }
+  }
+}
+
+/// Origins or request headers, listed lowercase, or `*`
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Allowed {
+  Any,
+  Only(Vec<String>),
+}
+
+impl Allowed {
+  fn new(values: &[String]) -> Self {
+    match values.iter().any(|value| value == "*") {
+      true => Self::Any,
+      false => Self::Only(values.iter().map(|value| value.to_lowercase()).collect()),
+    }
+  }
+
+  fn allows(&self, value: &str) -> bool {
+    match self {
+      Self::Any => true,
+      Self::Only(values) => values
+        .iter()
+        .any(|allowed| allowed.eq_ignore_ascii_case(value)),
+    }
+  }
+}
+
+/// A validated cross-origin resource sharing policy: which other sites' scripts may call the
+/// server, with which methods and headers. Applied as middleware, it answers the `OPTIONS`
+/// preflight requests browsers send ahead of requests that aren't simple, `204 No Content` if
+/// the policy allows the request and `403 Forbidden` if not, and adds the `Access-Control-*`
+/// headers to the responses for allowed origins. Requests without an `Origin` header, from the
+/// same site or not from a browser, pass through untouched.
+///
+/// Layer it before authentication: preflight requests come without credentials.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "CorsSettings")]
+pub struct CorsPolicy {
+  origins: Allowed,
+  methods: Vec<Method>,
+  headers: Allowed,
+  allow_credentials: bool,
+  max_age_secs: Option<u64>,
+}
+
+impl CorsPolicy {
+  /// The value of `Access-Control-Allow-Origin` for requests from `origin`, if allowed
+  fn allow_origin<'o>(&self, origin: &'o str) -> Option<&'o str> {
+    match (&self.origins, self.allow_credentials) {
+      (Allowed::Any, false) => Some("*"),
+      (origins, _) => origins.allows(origin).then_some(origin),
+    }
+  }
+
+  /// The answer to a preflight request from `origin`, asking for `method`
+  fn preflight(&self, request: &HttpRequest<'_>, origin: &str, method: &str) -> HttpResponse {
+    let requested_headers: Vec<&str> = request
+      .header()
+      .get(HttpRequestHeaderKey::AccessControlRequestHeaders)
+      .map(|headers| {
+        headers
+          .split(',')
+          .map(str::trim)
+          .filter(|header| !header.is_empty())
+          .collect()
+      })
+      .unwrap_or_default();
+    let allowed_method = method
+      .parse::<Method>()
+      .is_ok_and(|method| self.methods.contains(&method));
+    let allowed_headers = requested_headers
+      .iter()
+      .all(|header| self.headers.allows(header));
+    let allow_origin = match (allowed_method && allowed_headers, self.allow_origin(origin)) {
+      (true, Some(allow_origin)) => allow_origin,
+      _ => {
+        tracing::info!(
+          origin,
+          method,
+          path = request.path(),
+          "CORS preflight refused"
+        );
+        let mut response = HttpResponse::empty_body(StatusCode::Forbidden);
+        self.vary(&mut response);
+        return response;
+      }
+    };
+
+    let mut response = HttpResponse::empty_body(StatusCode::NoContent);
+    self.allow(&mut response, allow_origin);
+    let methods: Vec<String> = self.methods.iter().map(Method::to_string).collect();
+    response.insert_header(
+      HttpResponseHeaderKey::AccessControlAllowMethods,
+      &methods.join(", "),
+    );
+    let headers = match &self.headers {
+      // `*` only means any header without credentials, naming them works either way
+      Allowed::Any => requested_headers.join(", "),
+      Allowed::Only(headers) => headers.join(", "),
+    };
+    if !headers.is_empty() {
+      response.insert_header(HttpResponseHeaderKey::AccessControlAllowHeaders, &headers);
+    }
+    if let Some(max_age) = self.max_age_secs {
+      response.insert_header(
+        HttpResponseHeaderKey::AccessControlMaxAge,
+        &max_age.to_string(),
+      );
+    }
+    response
+  }
+
+  /// The answer to `request` if it's a preflight, which the handler never sees
+  fn answer_preflight(&self, request: &HttpRequest<'_>, origin: &str) -> Option<HttpResponse> {
+    let requested_method = request
+      .header()
+      .get(HttpRequestHeaderKey::AccessControlRequestMethod);
+    match (request.method(), requested_method) {
+      (Method::OPTIONS, Some(method)) => Some(self.preflight(request, origin, method)),
+      _ => None,
+    }
+  }
+
+  /// `response` for a request from `origin`
+  fn expose(&self, mut response: HttpResponse, origin: &str) -> HttpResponse {
+    match self.allow_origin(origin) {
+      Some(allow_origin) => self.allow(&mut response, allow_origin),
+      None => self.vary(&mut response),
+    }
+    response
+  }
+
+  fn allow(&self, response: &mut HttpResponse, allow_origin: &str) {
+    response.insert_header(
+      HttpResponseHeaderKey::AccessControlAllowOrigin,
+      allow_origin,
+    );
+    if self.allow_credentials {
+      response.insert_header(HttpResponseHeaderKey::AccessControlAllowCredentials, "true");
+    }
+    self.vary(response);
+  }
+
+  /// Responses echoing the origin differ by it, and caches have to keep them apart
+  fn vary(&self, response: &mut HttpResponse) {
+    if let Allowed::Only(_) = self.origins {
+      response.add_vary("Origin");
+    }
+  }
+}
+
+impl TryFrom<CorsSettings> for CorsPolicy {
+  type Error = CorsError;
+
+  fn try_from(settings: CorsSettings) -> Result<Self, Self::Error> {
+    for origin in settings
+      .allowed_origins
+      .iter()
+      .filter(|origin| *origin != "*")
+    {
+      let host = origin
+        .strip_prefix("https://")
+        .or_else(|| origin.strip_prefix("http://"));
+      if !host.is_some_and(|host| !host.is_empty() && !host.contains(['/', '?', '#'])) {
+        return Err(CorsError::InvalidOrigin(origin.clone()));
+      }
+    }
+    let methods = settings
+      .allowed_methods
+      .iter()
+      .map(|method| {
+        method
+          .parse()
+          .map_err(|_| CorsError::InvalidMethod(method.clone()))
+      })
+      .collect::<Result<_, _>>()?;
+    let origins = Allowed::new(&settings.allowed_origins);
+    if settings.allow_credentials && origins == Allowed::Any {
+      return Err(CorsError::CredentialsWithAnyOrigin);
+    }
+    Ok(Self {
+      origins,
+      methods,
+      headers: Allowed::new(&settings.allowed_headers),
+      allow_credentials: settings.allow_credentials,
+      max_age_secs: settings.max_age_secs,
+    })
+  }
+}
+
+impl Middleware for CorsPolicy {
+  fn handle(&self, request: &HttpRequest, next: Next<'_>) -> HandlerResult {
+    let Some(origin) = request.header().get(HttpRequestHeaderKey::Origin) else {
+      return next.run(request);
+    };
+    if let Some(preflight) = self.answer_preflight(request, origin) {
+      return Ok(preflight);
+    }
+    Ok(self.expose(next.run(request)?, origin))
+  }
+
+  fn handle_async<'a>(&'a self, request: &'a HttpRequest<'a>, next: Next<'a>) -> HandlerFuture<'a> {
+    let Some(origin) = request.header().get(HttpRequestHeaderKey::Origin) else {
+      return next.run_async(request);
+    };
+    Box::pin(async move {
+      if let Some(preflight) = self.answer_preflight(request, origin) {
+        return Ok(preflight);
+      }
+      Ok(self.expose(next.run_async(request).await?, origin))
+    })
+  }
+
+  fn describe(&self) -> Value {
+    let listed = |allowed: &Allowed| match allowed {
+      Allowed::Any => json!("*"),
+      Allowed::Only(values) => json!(values),
+    };
+    json!({
+      "middleware": "CorsPolicy",
+      "allowed_origins": listed(&self.origins),
+      "allowed_methods": self.methods.iter().map(Method::to_string).collect::<Vec<_>>(),
+      "allowed_headers": listed(&self.headers),
+      "allow_credentials": self.allow_credentials,
+      "max_age_secs": self.max_age_secs,
+    })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::middleware::Layered;
+  use crate::server::Handler;
+  use expectest::prelude::*;
+  use rstest::*;
+  use std::sync::Arc;
+
+  struct OkHandler;
+
+  impl Handler for OkHandler {
+    fn handle_request(&self, _request: &HttpRequest<'_>) -> HandlerResult {
+      Ok(HttpResponse::new(
+        StatusCode::Ok,
+        Some(b"ok".to_vec()),
+        None,
+      ))
+    }
+  }
+
+  fn settings(allowed_origins: &[&str], allow_credentials: bool) -> CorsSettings {
+    CorsSettings {
+      allowed_origins: allowed_origins
+        .iter()
+        .map(|origin| origin.to_string())
+        .collect(),
+      allowed_methods: vec!["GET".to_string(), "PUT".to_string()],
+      allowed_headers: vec!["Content-Type".to_string()],
+      allow_credentials,
+      max_age_secs: Some(600),
+    }
+  }
+
+  fn respond(policy: CorsPolicy, raw_request: &str) -> HttpResponse {
+    let handler = Layered::new(Arc::new(OkHandler)).layer(Arc::new(policy));
+    let request = HttpRequest::try_from(raw_request.as_bytes()).unwrap();
+    handler.handle_request(&request).unwrap()
+  }
+
+  fn header(response: &HttpResponse, key: HttpResponseHeaderKey) -> Option<String> {
+    response
+      .http_header()
+      .as_ref()
+      .and_then(|header| header.get(key).cloned())
+  }
+
+  #[rstest]
+  #[case::same_origin(&["*"], false, None, None, None)]
+  #[case::any_origin(&["*"], false, Some("https://a.example"), Some("*"), None)]
+  #[case::listed_origin(
+    &["https://a.example"],
+    true,
+    Some("https://a.example"),
+    Some("https://a.example"),
+    Some("Origin")
+  )]
+  #[case::other_origin(&["https://a.example"], false, Some("https://b.example"), None, Some("Origin"))]
+  fn test_simple_request(
+    #[case] allowed_origins: &[&str],
+    #[case] allow_credentials: bool,
+    #[case] origin: Option<&str>,
+    #[case] expected_allow_origin: Option<&str>,
+    #[case] expected_vary: Option<&str>,
+  ) {
+    let policy = CorsPolicy::try_from(settings(allowed_origins, allow_credentials)).unwrap();
+    let origin = origin
+      .map(|origin| format!("Origin: {}\r\n", origin))
+      .unwrap_or_default();
+    let raw_request = format!("GET / HTTP/1.1\r\nHost: localhost\r\n{}\r\n", origin);
+
+    let response = respond(policy, &raw_request);
+    expect!(*response.status_code()).to(be_equal_to(StatusCode::Ok));
+    expect!(header(
+      &response,
+      HttpResponseHeaderKey::AccessControlAllowOrigin
+    ))
+    .to(be_equal_to(expected_allow_origin.map(str::to_string)));
+    expect!(header(&response, HttpResponseHeaderKey::Vary))
+      .to(be_equal_to(expected_vary.map(str::to_string)));
+    let expected_credentials =
+      (allow_credentials && expected_allow_origin.is_some()).then(|| "true".to_string());
+    expect!(header(
+      &response,
+      HttpResponseHeaderKey::AccessControlAllowCredentials
+    ))
+    .to(be_equal_to(expected_credentials));
+  }
+
+  #[rstest]
+  #[case::allowed("https://a.example", "PUT", "content-type", StatusCode::NoContent)]
+  #[case::other_origin("https://b.example", "PUT", "content-type", StatusCode::Forbidden)]
+  #[case::other_method("https://a.example", "DELETE", "content-type", StatusCode::Forbidden)]
+  #[case::other_header("https://a.example", "PUT", "content-type, x-token", StatusCode::Forbidden)]
+  fn test_preflight(
+    #[case] origin: &str,
+    #[case] method: &str,
+    #[case] headers: &str,
+    #[case] expected_status: StatusCode,
+  ) {
+    let policy = CorsPolicy::try_from(settings(&["https://a.example"], false)).unwrap();
+    let raw_request = format!(
+      "OPTIONS /users HTTP/1.1\r\nHost: localhost\r\nOrigin: {}\r\n\
+       Access-Control-Request-Method: {}\r\nAccess-Control-Request-Headers: {}\r\n\r\n",
+      origin, method, headers
+    );
+
+    let response = respond(policy, &raw_request);
+    expect!(*response.status_code()).to(be_equal_to(expected_status));
+    expect!(response.body().as_deref().unwrap_or_default()).to(be_equal_to(&b""[..]));
+    let allowed = expected_status == StatusCode::NoContent;
+    expect!(header(
+      &response,
+      HttpResponseHeaderKey::AccessControlAllowOrigin
+    ))
+    .to(be_equal_to(allowed.then(|| origin.to_string())));
+    expect!(header(
+      &response,
+      HttpResponseHeaderKey::AccessControlAllowMethods
+    ))
+    .to(be_equal_to(allowed.then(|| "GET, PUT".to_string())));
+    expect!(header(
+      &response,
+      HttpResponseHeaderKey::AccessControlAllowHeaders
+    ))
+    .to(be_equal_to(allowed.then(|| "content-type".to_string())));
+    expect!(header(
+      &response,
+      HttpResponseHeaderKey::AccessControlMaxAge
+    ))
+    .to(be_equal_to(allowed.then(|| "600".to_string())));
+  }
+
+  #[rstest]
+  fn test_options_without_preflight_reaches_handler() {
+    let policy = CorsPolicy::try_from(CorsSettings::default()).unwrap();
+    let response = respond(
+      policy,
+      "OPTIONS / HTTP/1.1\r\nHost: localhost\r\nOrigin: https://a.example\r\n\r\n",
+    );
+    expect!(*response.status_code()).to(be_equal_to(StatusCode::Ok));
+    expect!(header(
+      &response,
+      HttpResponseHeaderKey::AccessControlAllowOrigin
+    ))
+    .to(be_some().value("*".to_string()));
+  }
+
+  #[rstest]
+  #[case::credentials_with_any_origin(settings(&["*"], true), CorsError::CredentialsWithAnyOrigin)]
+  #[case::origin_with_path(
+    settings(&["https://a.example/app"], false),
+    CorsError::InvalidOrigin("https://a.example/app".to_string())
+  )]
+  #[case::origin_without_scheme(
+    settings(&["a.example"], false),
+    CorsError::InvalidOrigin("a.example".to_string())
+  )]
+  #[case::method(
+    CorsSettings { allowed_methods: vec!["get".to_string()], ..CorsSettings::default() },
+    CorsError::InvalidMethod("get".to_string())
+  )]
+  fn test_invalid_settings(#[case] settings: CorsSettings, #[case] expected: CorsError) {
+    expect!(CorsPolicy::try_from(settings)).to(be_err().value(expected));
+  }
+
+  #[rstest]
+  fn test_deserialize_validates() {
+    expect!(toml::from_str::<CorsPolicy>("allow_credentials = true")).to(be_err());
+    expect!(toml::from_str::<CorsPolicy>(
+      "allowed_origins = [\"https://a.example\"]"
+    ))
+    .to(be_ok());
+  }
+}
@@ -0,0 +1,82 @@
+use std::{
+  future::Future,
+  io,
+  pin::Pin,
+  task::{Context, Poll},
+};
+use tokio::{
+  io::{AsyncRead, ReadBuf},
+  time::{sleep_until, Instant, Sleep},
+};
+
+/// Fails reads with [`io::ErrorKind::TimedOut`] once `deadline` has passed, so a client
+/// trickling in a request body can't hold on to the connection and its handler forever
+pub struct DeadlineReader<R> {
+  inner: R,
+  deadline: Pin<Box<Sleep>>,
+  timed_out: bool,
+}
+
+impl<R> DeadlineReader<R> {
+  pub fn new(inner: R, deadline: Instant) -> Self {
+    Self {
+      inner,
+      deadline: Box::pin(sleep_until(deadline)),
+      timed_out: false,
+    }
+  }
+
+  /// Whether a read failed because the deadline had passed
+  pub fn timed_out(&self) -> bool {
+    self.timed_out
+  }
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for DeadlineReader<R> {
+  fn poll_read(
+    mut self: Pin<&mut Self>,
+    cx: &mut Context<'_>,
+    buf: &mut ReadBuf<'_>,
+  ) -> Poll<io::Result<()>> {
+    if let Poll::Ready(read) = Pin::new(&mut self.inner).poll_read(cx, buf) {
+      return Poll::Ready(read);
+    }
+    match self.deadline.as_mut().poll(cx) {
+      Poll::Ready(()) => {
+        self.timed_out = true;
+        Poll::Ready(Err(io::Error::new(
+          io::ErrorKind::TimedOut,
+          "request body not received in time",
+        )))
+      }
+      Poll::Pending => Poll::Pending,
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use expectest::prelude::*;
+  use rstest::*;
+  use std::time::Duration;
+  use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+  #[rstest]
+  #[tokio::test(start_paused = true)]
+  async fn test_read_until_deadline() -> io::Result<()> {
+    let (mut client, connection) = tokio::io::duplex(64);
+    let mut reader = DeadlineReader::new(connection, Instant::now() + Duration::from_secs(1));
+    client.write_all(b"in time").await?;
+
+    let mut received = [0; 7];
+    reader.read_exact(&mut received).await?;
+    expect!(&received).to(be_equal_to(b"in time"));
+    expect!(reader.timed_out()).to(be_false());
+
+    let error = reader.read(&mut received).await.unwrap_err();
+    expect!(error.kind()).to(be_equal_to(io::ErrorKind::TimedOut));
+    expect!(reader.timed_out()).to(be_true());
+    Ok(())
+  }
+}
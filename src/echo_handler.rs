@@ -0,0 +1,154 @@
+use serde_json::{json, Map, Value};
+use std::{io, sync::Arc};
+
+use crate::http::{
+  header::HttpResponseHeaderBuilder, HandlerError, HttpRequest, HttpResponse, Method, RequestBody,
+  StatusCode,
+};
+use crate::server::{Handler, HandlerFuture, HandlerResult};
+
+pub const ECHO_PATH: &str = "/debug/echo";
+
+/// Answers [`ECHO_PATH`] with the request as this server parsed it, as JSON: method, path,
+/// query parameters, headers, connection addresses and body size. Meant for checking what proxies,
+/// header middleware and clients actually send; everything else goes to `fallback`.
+pub struct EchoHandler {
+  fallback: Arc<dyn Handler>,
+}
+
+impl EchoHandler {
+  pub fn new(fallback: Arc<dyn Handler>) -> Self {
+    Self { fallback }
+  }
+
+  fn echo(request: &HttpRequest<'_>, body_size: u64) -> HttpResponse {
+    let query = request
+      .query_string()
+      .iter()
+      .flat_map(|query_string| query_string.iter())
+      .map(|(key, value)| (key.to_string(), json!(value.iter().collect::<Vec<_>>())))
+      .collect::<Map<_, _>>();
+    let headers = request
+      .header()
+      .iter()
+      .map(|(key, value)| (key.clone(), json!(value)))
+      .collect::<Map<_, _>>();
+    let echo = json!({
+      "method": request.method().to_string(),
+      "path": request.path(),
+      "query": query,
+      "headers": headers,
+      "peer_addr": request.peer_addr().map(|peer_addr| peer_addr.to_string()),
+      "local_addr": request
+        .connection()
+        .and_then(|connection| connection.local_addr)
+        .map(|local_addr| local_addr.to_string()),
+      "secure": request.connection().is_some_and(|connection| connection.secure),
+      "client_ip": request.client_ip().map(|client_ip| client_ip.to_string()),
+      "scheme": request.scheme(),
+      "body_size": body_size,
+    });
+
+    let body = format!("{:#}\n", echo);
+    let mut builder = HttpResponseHeaderBuilder::new();
+    builder.content_type("application/json");
+    builder.content_length(&body.len().to_string());
+    builder.cache_control("no-store");
+    HttpResponse::new(
+      StatusCode::Ok,
+      Some(body.into_bytes()),
+      Some(Arc::new(builder.build())),
+    )
+  }
+}
+
+impl Handler for EchoHandler {
+  fn handle_request(&self, request: &HttpRequest<'_>) -> HandlerResult {
+    match request.path() {
+      ECHO_PATH => Ok(Self::echo(request, 0)),
+      _ => self.fallback.handle_request(request),
+    }
+  }
+
+  fn handle_async<'a>(&'a self, request: &'a HttpRequest<'a>) -> Option<HandlerFuture<'a>> {
+    match request.path() {
+      ECHO_PATH => None,
+      _ => self.fallback.handle_async(request),
+    }
+  }
+
+  /// The body is read and counted, not kept
+  fn handle_with_body(&self, request: &HttpRequest<'_>, mut body: RequestBody) -> HandlerResult {
+    if request.path() != ECHO_PATH {
+      return self.fallback.handle_with_body(request, body);
+    }
+    let body_size = io::copy(&mut body, &mut io::sink()).map_err(|error| {
+      HandlerError::bad_request("Failed to read request body").with_source(error)
+    })?;
+    Ok(Self::echo(request, body_size))
+  }
+
+  fn allowed_methods(&self) -> Vec<Method> {
+    self.fallback.allowed_methods()
+  }
+
+  fn describe(&self) -> Value {
+    json!({
+      "handler": "EchoHandler",
+      "path": ECHO_PATH,
+      "fallback": self.fallback.describe(),
+    })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::http::ConnectionInfo;
+  use expectest::prelude::*;
+  use rstest::*;
+
+  struct FallbackHandler;
+
+  impl Handler for FallbackHandler {
+    fn handle_request(&self, _request: &HttpRequest<'_>) -> HandlerResult {
+      Ok(HttpResponse::empty_body(StatusCode::NoContent))
+    }
+  }
+
+  #[rstest]
+  fn test_echo_request() {
+    let handler = EchoHandler::new(Arc::new(FallbackHandler));
+    let raw_request =
+      b"POST /debug/echo?tag=a&tag=b HTTP/1.1\r\nHost: localhost\r\nX-Forwarded-For: 10.0.0.1\r\nContent-Length: 5\r\n\r\n";
+    let request = HttpRequest::try_from(&raw_request[..])
+      .unwrap()
+      .with_connection(ConnectionInfo {
+        local_addr: Some("127.0.0.1:8443".parse().unwrap()),
+        secure: true,
+        ..ConnectionInfo::new("127.0.0.1:5000".parse().unwrap())
+      });
+    let (body, _sender) = RequestBody::channel(b"hello", 5);
+
+    let response = handler.handle_with_body(&request, body).unwrap();
+    expect!(*response.status_code()).to(be_equal_to(StatusCode::Ok));
+    let echo: Value = serde_json::from_slice(response.body().as_deref().unwrap()).unwrap();
+    expect!(echo["method"].as_str()).to(be_some().value("POST"));
+    expect!(echo["path"].as_str()).to(be_some().value("/debug/echo"));
+    expect!(echo["query"]["tag"].clone()).to(be_equal_to(json!(["a", "b"])));
+    expect!(echo["headers"]["X-Forwarded-For"].as_str()).to(be_some().value("10.0.0.1"));
+    expect!(echo["peer_addr"].as_str()).to(be_some().value("127.0.0.1:5000"));
+    expect!(echo["local_addr"].as_str()).to(be_some().value("127.0.0.1:8443"));
+    expect!(echo["secure"].as_bool()).to(be_some().value(true));
+    expect!(echo["body_size"].as_u64()).to(be_some().value(5));
+  }
+
+  #[rstest]
+  fn test_pass_other_paths_on() {
+    let handler = EchoHandler::new(Arc::new(FallbackHandler));
+    let request =
+      HttpRequest::try_from(&b"GET /debug HTTP/1.1\r\nHost: localhost\r\n\r\n"[..]).unwrap();
+    expect!(*handler.handle_request(&request).unwrap().status_code())
+      .to(be_equal_to(StatusCode::NoContent));
+  }
+}
@@ -0,0 +1,263 @@
+//! Files compiled into the executable, so the server ships as a single binary without a public
+//! directory next to it. Assets are listed with [`embed_assets!`](crate::embed_assets), or, with
+//! the `embed` feature, [`PUBLIC`] holds the whole public directory the crate was built with.
+
+use std::{
+  collections::{BTreeMap, HashMap},
+  env, fs,
+  path::PathBuf,
+  sync::Arc,
+  time::SystemTime,
+};
+
+use crate::file_cache::CachedFile;
+#[cfg(feature = "server")]
+use crate::filesystem::{AsyncFileSystem, OpenFile};
+use crate::filesystem::{DirEntry, FileSystem};
+
+/// A file embedded with `include_bytes!`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EmbeddedAsset {
+  /// relative to the embedded directory, with `/` separators, e.g. `css/site.css`
+  pub path: &'static str,
+  pub contents: &'static [u8],
+}
+
+/// Embed files below `$dir`, which is relative to the crate's manifest directory:
+///
+/// ```
+/// use udemy_server::{embed_assets, embedded_assets::EmbeddedAsset};
+///
+/// static ASSETS: &[EmbeddedAsset] = embed_assets!("public", ["index.html", "style.css"]);
+/// assert_eq!(ASSETS[1].path, "style.css");
+/// ```
+#[macro_export]
+macro_rules! embed_assets {
+  ($dir:literal, [$($path:literal),* $(,)?]) => {
+    &[$($crate::embedded_assets::EmbeddedAsset {
+      path: $path,
+      contents: include_bytes!(concat!(env!("CARGO_MANIFEST_DIR"), "/", $dir, "/", $path)),
+    }),*]
+  };
+}
+
+// every file below the public path the crate was built with, listed by build.rs: `EMBED_PATH`
+// at build time, `public` in the crate's directory by default
+#[cfg(feature = "embed")]
+include!(concat!(env!("OUT_DIR"), "/embedded_public.rs"));
+
+/// Serves [`EmbeddedAsset`]s from memory. Embedded files have no modification time of their
+/// own, so `ETag` and `Last-Modified` are derived from the executable's, which changes with
+/// every build that's deployed.
+pub struct EmbeddedFileSystem {
+  files: HashMap<&'static str, Arc<CachedFile>>,
+}
+
+impl EmbeddedFileSystem {
+  pub fn new(assets: &[EmbeddedAsset]) -> Self {
+    let modified = env::current_exe()
+      .and_then(fs::metadata)
+      .and_then(|metadata| metadata.modified())
+      .unwrap_or_else(|_| SystemTime::now());
+    Self::with_modified(assets, modified)
+  }
+
+  /// With `modified` as the assets' modification time
+  pub fn with_modified(assets: &[EmbeddedAsset], modified: SystemTime) -> Self {
+    let files = assets
+      .iter()
+      .filter_map(|asset| {
+        // times before 1970 can't be formatted, as they can't be for files on disk
+        let file = CachedFile::new(asset.contents, modified).ok()?;
+        Some((asset.path, Arc::new(file)))
+      })
+      .collect();
+    Self { files }
+  }
+
+  /// `dir_path` with a trailing slash, empty for the root
+  fn dir_prefix(dir_path: &str) -> String {
+    match dir_path.trim_matches('/') {
+      "" => String::new(),
+      dir_path => format!("{}/", dir_path),
+    }
+  }
+}
+
+impl FileSystem for EmbeddedFileSystem {
+  fn get_full_path(&self, file_path: &str) -> PathBuf {
+    PathBuf::from(file_path.trim_start_matches('/'))
+  }
+
+  /// Paths are looked up as they are, so `..` never leads anywhere
+  fn read_file(&self, file_path: &str) -> Option<Vec<u8>> {
+    let file = self.in_memory_file(file_path)?;
+    Some(file.contents.to_vec())
+  }
+
+  fn is_dir(&self, dir_path: &str) -> bool {
+    let prefix = Self::dir_prefix(dir_path);
+    self.files.keys().any(|path| path.starts_with(&prefix))
+  }
+
+  /// Directories exist as long as embedded files are below them
+  fn list_dir(&self, dir_path: &str) -> Option<Vec<DirEntry>> {
+    let prefix = Self::dir_prefix(dir_path);
+    let mut entries = BTreeMap::new();
+    for (path, file) in &self.files {
+      let Some(relative_path) = path.strip_prefix(&prefix) else {
+        continue;
+      };
+      let entry = match relative_path.split_once('/') {
+        Some((dir_name, _)) => DirEntry {
+          name: dir_name.to_string(),
+          is_dir: true,
+          size: 0,
+          modified: None,
+        },
+        None => DirEntry {
+          name: relative_path.to_string(),
+          is_dir: false,
+          size: file.contents.len() as u64,
+          modified: Some(file.modified),
+        },
+      };
+      entries.insert(entry.name.clone(), entry);
+    }
+    (!entries.is_empty()).then(|| entries.into_values().collect())
+  }
+
+  fn in_memory_file(&self, file_path: &str) -> Option<Arc<CachedFile>> {
+    self.files.get(file_path.trim_start_matches('/')).cloned()
+  }
+}
+
+#[cfg(feature = "server")]
+impl AsyncFileSystem for EmbeddedFileSystem {
+  /// Nothing to open, every file is served by [`FileSystem::in_memory_file`]
+  async fn open_file(&self, _file_path: &str, _prefetch: u64) -> Option<OpenFile> {
+    None
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::http::StatusCode;
+  use expectest::prelude::*;
+  use rstest::*;
+
+  static ASSETS: &[EmbeddedAsset] = &[
+    EmbeddedAsset { path: "index.html", contents: b"<h1>Home</h1>" },
+    EmbeddedAsset { path: "docs/index.html", contents: b"<h1>Docs</h1>" },
+    EmbeddedAsset { path: "docs/api/v1.html", contents: b"<h1>v1</h1>" },
+  ];
+
+  #[fixture]
+  fn file_system() -> EmbeddedFileSystem {
+    EmbeddedFileSystem::new(ASSETS)
+  }
+
+  #[rstest]
+  #[case::root("index.html", Some("<h1>Home</h1>"))]
+  #[case::nested("docs/index.html", Some("<h1>Docs</h1>"))]
+  #[case::directory("docs", None)]
+  #[case::traversal("docs/../index.html", None)]
+  #[case::missing("missing.html", None)]
+  fn test_read_file(
+    file_system: EmbeddedFileSystem,
+    #[case] file_path: &str,
+    #[case] expected: Option<&str>,
+  ) {
+    let contents = file_system.read_file(file_path);
+    expect!(contents).to(be_equal_to(
+      expected.map(|expected| expected.as_bytes().to_vec()),
+    ));
+  }
+
+  #[rstest]
+  #[case::root("", Some(vec![("docs", true), ("index.html", false)]))]
+  #[case::nested("docs/", Some(vec![("api", true), ("index.html", false)]))]
+  #[case::without_slash("docs/api", Some(vec![("v1.html", false)]))]
+  #[case::file("index.html", None)]
+  #[case::partial_name("do", None)]
+  fn test_list_dir(
+    file_system: EmbeddedFileSystem,
+    #[case] dir_path: &str,
+    #[case] expected: Option<Vec<(&str, bool)>>,
+  ) {
+    let entries = file_system.list_dir(dir_path).map(|entries| {
+      entries
+        .into_iter()
+        .map(|entry| (entry.name, entry.is_dir))
+        .collect::<Vec<_>>()
+    });
+    let expected = expected.map(|expected| {
+      expected
+        .into_iter()
+        .map(|(name, is_dir)| (name.to_string(), is_dir))
+        .collect::<Vec<_>>()
+    });
+    expect!(file_system.is_dir(dir_path)).to(be_equal_to(expected.is_some()));
+    expect!(entries).to(be_equal_to(expected));
+  }
+
+  #[rstest]
+  fn test_serve_without_disk(
+    file_system: EmbeddedFileSystem,
+  ) -> Result<(), crate::http::ParseError> {
+    use crate::http::{header::HttpResponseHeaderKey, HttpRequest, HttpResponse, MimeTypes};
+
+    let response = HttpResponse::with_body("/docs/index.html", &file_system, &MimeTypes::default());
+    expect!(*response.status_code()).to(be_equal_to(StatusCode::Ok));
+    expect!(response.body().as_deref()).to(be_some().value(&b"<h1>Docs</h1>"[..]));
+    let header = response.http_header().as_ref().unwrap();
+    let content_type = header.get(HttpResponseHeaderKey::ContentType);
+    expect!(content_type.map(String::as_str)).to(be_some().value("text/html; charset=utf-8"));
+
+    let etag = header.get(HttpResponseHeaderKey::Etag).unwrap();
+    let raw_request = format!(
+      "GET / HTTP/1.1\r\nHost: localhost\r\nIf-None-Match: {}\r\n\r\n",
+      etag
+    );
+    let request = HttpRequest::try_from(raw_request.as_bytes())?;
+    let response = response.conditional(&request);
+    expect!(*response.status_code()).to(be_equal_to(StatusCode::NotModified));
+    Ok(())
+  }
+
+  #[rstest]
+  #[case::file("/docs/api/v1.html", StatusCode::Ok, "<h1>v1</h1>")]
+  #[case::index_document("/docs/", StatusCode::Ok, "<h1>Docs</h1>")]
+  #[case::directory_without_slash("/docs", StatusCode::PermanentRedirect, "")]
+  #[case::missing("/missing.html", StatusCode::NotFound, "<h1>404 Not Found</h1>")]
+  #[tokio::test]
+  #[cfg(feature = "server")]
+  async fn test_website_from_embedded_assets(
+    file_system: EmbeddedFileSystem,
+    #[case] path: &str,
+    #[case] expected_status: StatusCode,
+    #[case] expected_body: &str,
+  ) -> Result<(), crate::http::ParseError> {
+    use crate::http::{HttpRequest, MimeTypes};
+    use crate::server::Handler;
+    use crate::website_handler::WebsiteHandler;
+
+    let website_handler = WebsiteHandler::new(Arc::new(file_system), MimeTypes::default());
+    let raw_request = format!("GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path);
+    let request = HttpRequest::try_from(raw_request.as_bytes())?;
+    for response in [
+      website_handler.handle_request(&request).unwrap(),
+      website_handler
+        .handle_async(&request)
+        .unwrap()
+        .await
+        .unwrap(),
+    ] {
+      expect!(*response.status_code()).to(be_equal_to(expected_status));
+      let body = String::from_utf8_lossy(response.body().as_deref().unwrap_or_default());
+      expect!(body.contains(expected_body)).to(be_true());
+    }
+    Ok(())
+  }
+}
@@ -0,0 +1,107 @@
+//! The errors the crate surfaces to embedders, gathered in one type so they can be matched on
+//! rather than only printed. Each keeps the error it wraps as its source, and maps to the
+//! status a client would get for it.
+
+use std::io;
+use thiserror::Error;
+
+#[cfg(feature = "acme")]
+use crate::acme::AcmeError;
+#[cfg(feature = "server")]
+use crate::config::ConfigError;
+use crate::filesystem::FileSystemError;
+use crate::http::{request::FileError, HandlerError, ParseError, StatusCode};
+#[cfg(feature = "tls")]
+use crate::tls::TlsError;
+
+#[derive(Error, Debug)]
+#[non_exhaustive]
+pub enum ServerError {
+  #[error("Invalid request: {0}")]
+  Parse(#[from] ParseError),
+  #[error("Handler failed: {0}")]
+  Handler(#[from] HandlerError),
+  #[error("File error: {0}")]
+  File(#[from] FileError),
+  /// the public path can't be served, see [`crate::filesystem::FileSystem::check_ready`]
+  #[error("File system not ready: {0}")]
+  FileSystem(#[from] FileSystemError),
+  #[cfg(feature = "server")]
+  #[error("Configuration error: {0}")]
+  Config(#[from] ConfigError),
+  #[cfg(feature = "tls")]
+  #[error("TLS error: {0}")]
+  Tls(#[from] TlsError),
+  #[cfg(feature = "acme")]
+  #[error("ACME error: {0}")]
+  Acme(#[from] AcmeError),
+  /// a listener set up to accept TLS on a server without a certificate
+  #[error("Listener on {0} needs TLS, but the server has no certificate")]
+  MissingCertificate(String),
+  /// binding, accepting on or reading a file for a listener
+  #[error("I/O error: {0}")]
+  Io(#[from] io::Error),
+  /// a listener's accept loop panicked
+  #[cfg(feature = "server")]
+  #[error("Listener task failed: {0}")]
+  Task(#[from] tokio::task::JoinError),
+}
+
+impl ServerError {
+  /// The status a client gets when this error ends a request: the one the request or handler
+  /// error carries, `404` or `403` for files that are missing or off limits, otherwise `500`
+  pub fn status_code(&self) -> StatusCode {
+    match self {
+      Self::Parse(error) => error.status_code(),
+      Self::Handler(error) => error.status_code(),
+      Self::File(FileError::Io(error)) | Self::Io(error) => match error.kind() {
+        io::ErrorKind::NotFound => StatusCode::NotFound,
+        io::ErrorKind::PermissionDenied => StatusCode::Forbidden,
+        _ => StatusCode::InternalError,
+      },
+      _ => StatusCode::InternalError,
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use expectest::prelude::*;
+  use rstest::*;
+  use std::error::Error;
+
+  #[rstest]
+  #[case::parse(ParseError::UriTooLong.into(), StatusCode::UriTooLong)]
+  #[case::handler(HandlerError::not_found("No such page").into(), StatusCode::NotFound)]
+  #[case::missing_file(
+    FileError::Io(io::Error::from(io::ErrorKind::NotFound)).into(),
+    StatusCode::NotFound
+  )]
+  #[case::forbidden(
+    io::Error::from(io::ErrorKind::PermissionDenied).into(),
+    StatusCode::Forbidden
+  )]
+  #[case::certificate(
+    ServerError::MissingCertificate("[::]:443".to_string()),
+    StatusCode::InternalError
+  )]
+  fn test_status_code(#[case] error: ServerError, #[case] expected: StatusCode) {
+    expect!(error.status_code()).to(be_equal_to(expected));
+  }
+
+  #[rstest]
+  fn test_source_chain() {
+    let error = ServerError::from(ParseError::from(
+      crate::http::chunked::ChunkedError::Truncated,
+    ));
+    let parse_error = error.source().unwrap();
+    expect!(parse_error.is::<ParseError>()).to(be_true());
+    expect!(parse_error.source().is_some()).to(be_true());
+    expect!(matches!(
+      error,
+      ServerError::Parse(ParseError::InvalidChunkedBody(_))
+    ))
+    .to(be_true());
+  }
+}
@@ -0,0 +1,113 @@
+use std::{
+  collections::HashMap,
+  fmt::Display,
+  sync::Mutex,
+  time::{Duration, Instant},
+};
+
+use crate::limits::RateLimit;
+
+/// Distinct messages tracked before ones whose window has passed are dropped
+const MAX_TRACKED_MESSAGES: usize = 10_000;
+
+struct Window {
+  started_at: Instant,
+  logged: u32,
+  suppressed: u64,
+}
+
+/// Whether a message gets written, see [`ErrorLog::check`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogDecision {
+  /// write it, mentioning how many identical ones were dropped since it was last written
+  Log {
+    suppressed: u64,
+  },
+  Suppress,
+}
+
+/// Error logging that writes each distinct message at most `requests` times per `per_secs`,
+/// so a flood of identical failures (e.g. a scanner sending garbage) can't saturate the log
+/// pipeline. Dropped messages are counted and reported with the next one that gets through.
+pub struct ErrorLog {
+  limit: RateLimit,
+  windows: Mutex<HashMap<String, Window>>,
+}
+
+impl Default for ErrorLog {
+  /// 10 identical messages per minute
+  fn default() -> Self {
+    Self::new(RateLimit::new(10, 60).expect("non-zero rate limit"))
+  }
+}
+
+impl ErrorLog {
+  pub fn new(limit: RateLimit) -> Self {
+    Self { limit, windows: Mutex::new(HashMap::new()) }
+  }
+
+  /// Emit `message` as an error event unless messages with the same `key` have exhausted the
+  /// limit. The key usually is the message without per-request details such as the peer address.
+  pub fn log(&self, key: &str, message: impl Display) {
+    match self.check(key) {
+      LogDecision::Log { suppressed: 0 } => tracing::error!("{}", message),
+      LogDecision::Log { suppressed } => tracing::error!(suppressed, "{}", message),
+      LogDecision::Suppress => {}
+    }
+  }
+
+  pub fn check(&self, key: &str) -> LogDecision {
+    self.check_at(key, Instant::now())
+  }
+
+  fn check_at(&self, key: &str, now: Instant) -> LogDecision {
+    let period = Duration::from_secs(self.limit.per_secs());
+    let mut windows = self.windows.lock().unwrap_or_else(|e| e.into_inner());
+    if windows.len() >= MAX_TRACKED_MESSAGES {
+      windows.retain(|_, window| now.duration_since(window.started_at) < period);
+    }
+
+    let window = windows.entry(key.to_string()).or_insert(Window {
+      started_at: now,
+      logged: 0,
+      suppressed: 0,
+    });
+    if now.duration_since(window.started_at) >= period {
+      window.started_at = now;
+      window.logged = 0;
+    }
+
+    if window.logged < self.limit.requests() {
+      window.logged += 1;
+      LogDecision::Log { suppressed: std::mem::take(&mut window.suppressed) }
+    } else {
+      window.suppressed += 1;
+      LogDecision::Suppress
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use expectest::prelude::*;
+  use rstest::*;
+
+  #[rstest]
+  fn test_suppress_repeated_messages() {
+    let error_log = ErrorLog::new(RateLimit::new(2, 10).unwrap());
+    let start = Instant::now();
+    let key = "Failed to parse request: Invalid Method";
+
+    expect!(error_log.check_at(key, start)).to(be_equal_to(LogDecision::Log { suppressed: 0 }));
+    expect!(error_log.check_at(key, start)).to(be_equal_to(LogDecision::Log { suppressed: 0 }));
+    expect!(error_log.check_at(key, start)).to(be_equal_to(LogDecision::Suppress));
+    expect!(error_log.check_at(key, start)).to(be_equal_to(LogDecision::Suppress));
+    expect!(error_log.check_at("Failed to send response", start))
+      .to(be_equal_to(LogDecision::Log { suppressed: 0 }));
+    expect!(error_log.check_at(key, start + Duration::from_secs(10)))
+      .to(be_equal_to(LogDecision::Log { suppressed: 2 }));
+    expect!(error_log.check_at(key, start + Duration::from_secs(10)))
+      .to(be_equal_to(LogDecision::Log { suppressed: 0 }));
+  }
+}
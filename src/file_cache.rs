@@ -0,0 +1,259 @@
+//! Files kept in memory by [`crate::filesystem::LocalFileSystem::with_cache`], so popular
+//! ones aren't read from disk for every request
+
+use serde::{Deserialize, Serialize};
+use std::{
+  borrow::Cow,
+  collections::{BTreeMap, HashMap},
+  fs::{File, Metadata},
+  io::Read,
+  path::{Path, PathBuf},
+  sync::{Arc, Mutex},
+  time::SystemTime,
+};
+
+use crate::http::{header::HttpHeader, request::FileError};
+
+/// The `[file_cache]` table, e.g.
+///
+/// ```toml
+/// [file_cache]
+/// max_memory = 268435456
+/// max_file_size = 4194304
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FileCacheSettings {
+  /// bytes of file contents kept at most, 64 MiB by default
+  pub max_memory: u64,
+  /// larger files are read from disk every time, 1 MiB by default
+  pub max_file_size: u64,
+}
+
+impl Default for FileCacheSettings {
+  fn default() -> Self {
+    Self { max_memory: 64 * 1024 * 1024, max_file_size: 1024 * 1024 }
+  }
+}
+
+/// A file's contents along with the validators of its responses, formatted once
+#[derive(Debug, PartialEq, Eq)]
+pub struct CachedFile {
+  /// borrowed for files embedded into the executable, see
+  /// [`crate::embedded_assets::EmbeddedFileSystem`]
+  pub contents: Cow<'static, [u8]>,
+  pub modified: SystemTime,
+  /// see [`HttpHeader::etag`]
+  pub etag: String,
+  /// see [`HttpHeader::last_modified`]
+  pub last_modified: String,
+}
+
+impl CachedFile {
+  pub fn new(
+    contents: impl Into<Cow<'static, [u8]>>,
+    modified: SystemTime,
+  ) -> Result<Self, FileError> {
+    let contents = contents.into();
+    Ok(Self {
+      etag: HttpHeader::etag(contents.len() as u64, modified),
+      last_modified: HttpHeader::last_modified(modified)?,
+      contents,
+      modified,
+    })
+  }
+}
+
+/// Least recently used files are dropped once their contents take up more than
+/// [`FileCacheSettings::max_memory`]. Entries are keyed by canonical path and dropped as well
+/// when the file's modification time or size no longer match, so edits show up with the next
+/// request.
+#[derive(Debug)]
+pub struct FileCache {
+  settings: FileCacheSettings,
+  entries: Mutex<Entries>,
+}
+
+#[derive(Debug, Default)]
+struct Entries {
+  files: HashMap<PathBuf, (Arc<CachedFile>, u64)>,
+  /// paths by when they were last used, least recently first
+  recency: BTreeMap<u64, PathBuf>,
+  uses: u64,
+  size: u64,
+}
+
+impl Entries {
+  fn remove(&mut self, path: &Path) {
+    if let Some((file, used)) = self.files.remove(path) {
+      self.recency.remove(&used);
+      self.size -= file.contents.len() as u64;
+    }
+  }
+
+  fn touch(&mut self, path: &Path) {
+    self.uses += 1;
+    let uses = self.uses;
+    if let Some((_, used)) = self.files.get_mut(path) {
+      let path = self
+        .recency
+        .remove(used)
+        .unwrap_or_else(|| path.to_path_buf());
+      *used = uses;
+      self.recency.insert(uses, path);
+    }
+  }
+}
+
+impl FileCache {
+  pub fn new(settings: FileCacheSettings) -> Self {
+    Self { settings, entries: Mutex::default() }
+  }
+
+  /// The file at the canonical `path`, unless it was modified or resized since it was cached
+  pub fn get(&self, path: &Path, metadata: &Metadata) -> Option<Arc<CachedFile>> {
+    let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+    let (file, _) = entries.files.get(path)?;
+    let file = Arc::clone(file);
+    if metadata.modified().ok() != Some(file.modified)
+      || metadata.len() != file.contents.len() as u64
+    {
+      entries.remove(path);
+      return None;
+    }
+    entries.touch(path);
+    Some(file)
+  }
+
+  /// Cache `file`, making room for it by dropping the least recently used files; files larger
+  /// than [`FileCacheSettings::max_file_size`] are returned without being kept
+  pub fn insert(&self, path: PathBuf, file: CachedFile) -> Arc<CachedFile> {
+    let file = Arc::new(file);
+    let size = file.contents.len() as u64;
+    if !self.caches(size) {
+      return file;
+    }
+    let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+    entries.remove(&path);
+    while entries.size + size > self.settings.max_memory {
+      let Some((_, least_recent)) = entries.recency.pop_first() else {
+        break;
+      };
+      entries.remove(&least_recent);
+    }
+    entries.size += size;
+    entries.files.insert(path.clone(), (Arc::clone(&file), 0));
+    entries.touch(&path);
+    file
+  }
+
+  /// `file`, opened from the canonical `path`, from the cache or read into it; `None` if it's
+  /// too large to cache or can't be read
+  pub fn load(&self, path: &Path, file: &mut File, metadata: &Metadata) -> Option<Arc<CachedFile>> {
+    if !metadata.is_file() || !self.caches(metadata.len()) {
+      return None;
+    }
+    if let Some(cached) = self.get(path, metadata) {
+      return Some(cached);
+    }
+    let mut contents = Vec::with_capacity(metadata.len() as usize);
+    file.read_to_end(&mut contents).ok()?;
+    let cached = CachedFile::new(contents, metadata.modified().ok()?).ok()?;
+    Some(self.insert(path.to_path_buf(), cached))
+  }
+
+  fn caches(&self, size: u64) -> bool {
+    size <= self.settings.max_file_size && size <= self.settings.max_memory
+  }
+
+  /// The number of cached files
+  pub fn len(&self) -> usize {
+    let entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+    entries.files.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.len() == 0
+  }
+
+  /// Bytes of file contents cached
+  pub fn size(&self) -> u64 {
+    let entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+    entries.size
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use expectest::prelude::*;
+  use rstest::*;
+  use std::{fs, io, time::Duration};
+  use tempfile::TempDir;
+
+  fn load(cache: &FileCache, path: &Path) -> io::Result<Option<Arc<CachedFile>>> {
+    let mut file = File::open(path)?;
+    let metadata = file.metadata()?;
+    Ok(cache.load(path, &mut file, &metadata))
+  }
+
+  #[rstest]
+  fn test_evict_least_recently_used() -> io::Result<()> {
+    let dir = TempDir::new()?;
+    let paths = ["a", "b", "c"].map(|name| dir.path().join(name));
+    for path in &paths {
+      fs::write(path, "1234")?;
+    }
+    let cache = FileCache::new(FileCacheSettings { max_memory: 8, max_file_size: 8 });
+
+    load(&cache, &paths[0])?;
+    load(&cache, &paths[1])?;
+    // `a` is used again, so `b` makes room for `c`
+    load(&cache, &paths[0])?;
+    load(&cache, &paths[2])?;
+    let cached = paths
+      .iter()
+      .map(|path| cache.entries.lock().unwrap().files.contains_key(path))
+      .collect::<Vec<_>>();
+    expect!(cached).to(be_equal_to(vec![true, false, true]));
+    expect!(cache.size()).to(be_equal_to(8));
+    Ok(())
+  }
+
+  #[rstest]
+  fn test_invalidate_modified_file() -> io::Result<()> {
+    let dir = TempDir::new()?;
+    let path = dir.path().join("index.html");
+    fs::write(&path, "old")?;
+    let cache = FileCache::new(FileCacheSettings::default());
+
+    let cached = load(&cache, &path)?.unwrap();
+    expect!(&*cached.contents).to(be_equal_to(&b"old"[..]));
+    expect!(Arc::ptr_eq(&cached, &load(&cache, &path)?.unwrap())).to(be_true());
+
+    // same size, only the modification time tells them apart
+    fs::write(&path, "new")?;
+    let modified = cached.modified + Duration::from_secs(1);
+    File::options()
+      .write(true)
+      .open(&path)?
+      .set_modified(modified)?;
+    let reloaded = load(&cache, &path)?.unwrap();
+    expect!(&*reloaded.contents).to(be_equal_to(&b"new"[..]));
+    expect!(reloaded.etag == cached.etag).to(be_false());
+    expect!(cache.len()).to(be_equal_to(1));
+    Ok(())
+  }
+
+  #[rstest]
+  fn test_skip_large_files() -> io::Result<()> {
+    let dir = TempDir::new()?;
+    let path = dir.path().join("video.mp4");
+    fs::write(&path, "0123456789")?;
+    let cache = FileCache::new(FileCacheSettings { max_memory: 1024, max_file_size: 4 });
+
+    expect!(load(&cache, &path)?).to(be_none());
+    expect!(cache.is_empty()).to(be_true());
+    Ok(())
+  }
+}
@@ -1,36 +1,580 @@
-use std::{fs, path::PathBuf};
+use std::{
+  fs,
+  io::{self, Read},
+  path::{Component, Path, PathBuf},
+  process,
+  sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc,
+  },
+  time::SystemTime,
+};
+#[cfg(feature = "server")]
+use std::{fs::Metadata, future::Future};
+use thiserror::Error;
+
+use crate::file_cache::{CachedFile, FileCache};
 
 pub trait FileSystem {
   fn get_full_path(&self, file_path: &str) -> PathBuf;
-  fn read_file(&self, file_path: &str) -> Option<String>;
+  /// The file's bytes, whatever their encoding, so images and fonts are served as well
+  fn read_file(&self, file_path: &str) -> Option<Vec<u8>>;
+
+  /// Whether files can be served at all, checked at startup and by `/readyz`
+  fn check_ready(&self) -> Result<(), FileSystemError> {
+    Ok(())
+  }
+
+  /// Whether `dir_path` is a directory, whose index documents are served for it
+  fn is_dir(&self, _dir_path: &str) -> bool {
+    false
+  }
+
+  /// The entries of the directory at `dir_path`, `None` if there's no such directory or
+  /// the file system can't list them
+  fn list_dir(&self, _dir_path: &str) -> Option<Vec<DirEntry>> {
+    None
+  }
+
+  /// The file at `file_path` if the file system holds it in memory anyway, so it's served
+  /// without being read or its validators formatted again
+  fn in_memory_file(&self, _file_path: &str) -> Option<Arc<CachedFile>> {
+    None
+  }
+
+  /// Store what `contents` yields at `file_path`, replacing a file that's there only if
+  /// `overwrite` is set. Read-only file systems, the default, refuse with
+  /// [`io::ErrorKind::Unsupported`].
+  fn write_file(
+    &self,
+    _file_path: &str,
+    _contents: &mut dyn Read,
+    _overwrite: bool,
+  ) -> io::Result<Written> {
+    Err(io::ErrorKind::Unsupported.into())
+  }
+}
+
+/// What [`FileSystem::write_file`] did
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Written {
+  Created,
+  Replaced,
+}
+
+/// A file or directory listed by [`FileSystem::list_dir`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DirEntry {
+  pub name: String,
+  pub is_dir: bool,
+  /// in bytes, 0 for directories
+  pub size: u64,
+  pub modified: Option<SystemTime>,
+}
+
+/// [`FileSystem`] for async code, whose file access waits on tokio rather than blocking the
+/// runtime's threads
+#[cfg(feature = "server")]
+pub trait AsyncFileSystem: FileSystem + Send + Sync {
+  /// The file at `file_path` with up to `prefetch` bytes of it read already, `None` if there's
+  /// no such file
+  fn open_file(
+    &self,
+    file_path: &str,
+    prefetch: u64,
+  ) -> impl Future<Output = Option<OpenFile>> + Send;
+}
+
+/// A file opened by [`AsyncFileSystem::open_file`]
+#[cfg(feature = "server")]
+pub struct OpenFile {
+  /// positioned after `start`
+  pub file: tokio::fs::File,
+  pub metadata: Metadata,
+  /// the start of the file, as much of it as was prefetched; empty if it's `cached`
+  pub start: Vec<u8>,
+  /// the whole file, if the file system keeps it in memory
+  pub cached: Option<Arc<CachedFile>>,
+}
+
+#[derive(Error, Debug)]
+pub enum FileSystemError {
+  #[error("Public path {0} does not exist: {1}")]
+  Missing(PathBuf, io::Error),
+  #[error("Public path {0} is not a directory")]
+  NotADirectory(PathBuf),
+  #[error("Public path {0} is not readable: {1}")]
+  Unreadable(PathBuf, io::Error),
 }
 
 pub struct LocalFileSystem {
   public_path: PathBuf,
+  /// `public_path` canonicalized, or made absolute if it doesn't exist (yet), which the
+  /// canonical paths of served files have to start with
+  root: PathBuf,
+  cache: Option<Arc<FileCache>>,
+}
+
+/// Why a requested path wasn't resolved to a file below the public path
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Unresolved {
+  /// nothing there, worth a 404 but not a warning
+  Missing,
+  /// `..` segments or a symlink leading out of the public path
+  Traversal,
 }
 
 impl LocalFileSystem {
   pub fn new(public_path: String) -> Self {
-    Self { public_path: PathBuf::from(public_path) }
+    let public_path = PathBuf::from(public_path);
+    let root = fs::canonicalize(&public_path)
+      .or_else(|_| std::path::absolute(&public_path))
+      .unwrap_or_else(|_| public_path.clone());
+    Self { public_path, root, cache: None }
+  }
+
+  /// Keep the files read in `cache`, see [`FileCache`]
+  pub fn with_cache(mut self, cache: FileCache) -> Self {
+    self.cache = Some(Arc::new(cache));
+    self
+  }
+
+  pub fn cache(&self) -> Option<&FileCache> {
+    self.cache.as_deref()
+  }
+
+  /// The canonical path of `file_path`, if it exists within the public path
+  fn contained_path(&self, file_path: &str) -> Option<PathBuf> {
+    resolve(&self.root, file_path).ok()
   }
 }
 
+/// `file_path` without `.` segments and with `..` applied, relative to the public path; `None`
+/// if it climbs above it. Nothing is looked up, so this holds for files that don't exist.
+pub(crate) fn normalize(file_path: &str) -> Option<PathBuf> {
+  let mut segments = Vec::new();
+  for segment in file_path.split('/') {
+    match segment {
+      "" | "." => {}
+      ".." => {
+        segments.pop()?;
+      }
+      // a single name, not e.g. `C:` or `a\..\..` on Windows
+      segment => match Path::new(segment).components().collect::<Vec<_>>()[..] {
+        [Component::Normal(_)] => segments.push(segment),
+        _ => return None,
+      },
+    }
+  }
+  Some(segments.iter().collect())
+}
+
+/// The canonical path of `file_path` below `root`, checked twice: lexically first, then once
+/// symlinks are resolved, since those may still point out of `root`
+fn resolve(root: &Path, file_path: &str) -> Result<PathBuf, Unresolved> {
+  let relative_path = normalize(file_path).ok_or(Unresolved::Traversal)?;
+  let cannonical_path =
+    fs::canonicalize(root.join(relative_path)).map_err(|_| Unresolved::Missing)?;
+  match cannonical_path.starts_with(root) {
+    true => Ok(cannonical_path),
+    false => Err(Unresolved::Traversal),
+  }
+}
+
+/// Names the temporary files of [`LocalFileSystem::write_file`] apart
+static UPLOADS: AtomicU64 = AtomicU64::new(0);
+
+/// Only attempts to leave the public path are worth a warning, missing files are routine
+fn warn_traversal<T>(resolved: Result<T, Unresolved>, file_path: &str) -> Option<T> {
+  if let Err(Unresolved::Traversal) = resolved {
+    tracing::warn!(path = file_path, "Directory traversal attack attempted");
+  }
+  resolved.ok()
+}
+
 impl FileSystem for LocalFileSystem {
   fn get_full_path(&self, file_path: &str) -> PathBuf {
-    // equivalent of  let path = format!("{}/{}", self.public_path, file_path);
-    self.public_path.join(file_path)
+    // equivalent of  let path = format!("{}/{}", self.root, file_path);
+    self.root.join(file_path)
   }
 
-  fn read_file(&self, file_path: &str) -> Option<String> {
-    let full_path = self.get_full_path(file_path);
-    match fs::canonicalize(full_path) {
-      Ok(cannonical_path) if cannonical_path.starts_with(&self.public_path) => {
-        fs::read_to_string(cannonical_path).ok()
-      }
-      _ => {
-        eprintln!("Directory Traversal Attack Attempted: {}", file_path);
-        None
+  fn read_file(&self, file_path: &str) -> Option<Vec<u8>> {
+    let cannonical_path = warn_traversal(resolve(&self.root, file_path), file_path)?;
+    match &self.cache {
+      Some(cache) => read_through(cache, &cannonical_path),
+      None => fs::read(cannonical_path).ok(),
+    }
+  }
+
+  fn is_dir(&self, dir_path: &str) -> bool {
+    self
+      .contained_path(dir_path)
+      .is_some_and(|path| path.is_dir())
+  }
+
+  /// Entries whose names aren't UTF-8 are left out, they couldn't be requested anyway
+  fn list_dir(&self, dir_path: &str) -> Option<Vec<DirEntry>> {
+    let entries = fs::read_dir(self.contained_path(dir_path)?).ok()?;
+    let entries = entries
+      .filter_map(|entry| {
+        let entry = entry.ok()?;
+        let name = entry.file_name().into_string().ok()?;
+        // following symlinks, as serving them does
+        let metadata = fs::metadata(entry.path()).ok()?;
+        Some(DirEntry {
+          name,
+          is_dir: metadata.is_dir(),
+          size: if metadata.is_dir() { 0 } else { metadata.len() },
+          modified: metadata.modified().ok(),
+        })
+      })
+      .collect();
+    Some(entries)
+  }
+
+  fn check_ready(&self) -> Result<(), FileSystemError> {
+    let path = &self.public_path;
+    let metadata = fs::metadata(path).map_err(|e| FileSystemError::Missing(path.clone(), e))?;
+    if !metadata.is_dir() {
+      return Err(FileSystemError::NotADirectory(path.clone()));
+    }
+    fs::read_dir(path).map_err(|e| FileSystemError::Unreadable(path.clone(), e))?;
+    Ok(())
+  }
+
+  /// Missing directories are created. The contents go to a hidden temporary file next to the
+  /// target first, so readers never see a partial file and a failed write leaves nothing
+  /// behind; without `overwrite`, the temporary file is linked into place, which fails
+  /// atomically if the file exists meanwhile.
+  fn write_file(
+    &self,
+    file_path: &str,
+    contents: &mut dyn Read,
+    overwrite: bool,
+  ) -> io::Result<Written> {
+    let denied = || {
+      tracing::warn!(path = file_path, "Directory traversal attack attempted");
+      io::Error::from(io::ErrorKind::PermissionDenied)
+    };
+    let relative_path = normalize(file_path).ok_or_else(denied)?;
+    let (Some(parent), Some(file_name)) = (relative_path.parent(), relative_path.file_name())
+    else {
+      return Err(io::ErrorKind::IsADirectory.into());
+    };
+    // checked before anything is created, as a symlinked directory on the way could otherwise
+    // have directories created outside the root
+    let existing = self
+      .root
+      .join(parent)
+      .ancestors()
+      .find(|ancestor| ancestor.exists())
+      .map(fs::canonicalize)
+      .transpose()?;
+    if !existing.is_some_and(|existing| existing.starts_with(&self.root)) {
+      return Err(denied());
+    }
+    fs::create_dir_all(self.root.join(parent))?;
+    let parent = fs::canonicalize(self.root.join(parent))?;
+    if !parent.starts_with(&self.root) {
+      return Err(denied());
+    }
+    let path = parent.join(file_name);
+    if path.is_dir() {
+      return Err(io::ErrorKind::IsADirectory.into());
+    }
+
+    let upload = UPLOADS.fetch_add(1, Ordering::Relaxed);
+    let temporary_path = parent.join(format!(
+      ".{}.{}-{}.upload",
+      file_name.to_string_lossy(),
+      process::id(),
+      upload
+    ));
+    let written = fs::File::create_new(&temporary_path)
+      .and_then(|mut file| io::copy(contents, &mut file).and_then(|_| file.sync_all()))
+      .and_then(|_| match overwrite {
+        true => {
+          let existed = path.exists();
+          fs::rename(&temporary_path, &path)?;
+          Ok(if existed { Written::Replaced } else { Written::Created })
+        }
+        false => fs::hard_link(&temporary_path, &path).map(|_| Written::Created),
+      });
+    // gone already if it was renamed
+    let _ = fs::remove_file(&temporary_path);
+    written
+  }
+}
+
+#[cfg(feature = "server")]
+impl AsyncFileSystem for LocalFileSystem {
+  /// Resolved, opened and prefetched on one blocking thread rather than a round trip each
+  async fn open_file(&self, file_path: &str, prefetch: u64) -> Option<OpenFile> {
+    let (root, cache) = (self.root.clone(), self.cache.clone());
+    let file_path = file_path.to_string();
+    let opened = tokio::task::spawn_blocking(move || {
+      let cannonical_path = warn_traversal(resolve(&root, &file_path), &file_path)?;
+      let mut file = fs::File::open(&cannonical_path).ok()?;
+      let metadata = file.metadata().ok().filter(Metadata::is_file)?;
+      let cached = cache.and_then(|cache| cache.load(&cannonical_path, &mut file, &metadata));
+      let mut start = Vec::new();
+      if cached.is_none() {
+        start.reserve(metadata.len().min(prefetch) as usize);
+        (&mut file).take(prefetch).read_to_end(&mut start).ok()?;
       }
+      Some(OpenFile {
+        file: tokio::fs::File::from_std(file),
+        metadata,
+        start,
+        cached,
+      })
+    });
+    opened.await.ok()?
+  }
+}
+
+/// The file at the canonical `path` from `cache`, or from disk if it's too large to cache
+fn read_through(cache: &FileCache, path: &Path) -> Option<Vec<u8>> {
+  let mut file = fs::File::open(path).ok()?;
+  let metadata = file.metadata().ok()?;
+  match cache.load(path, &mut file, &metadata) {
+    Some(cached) => Some(cached.contents.to_vec()),
+    None => {
+      let mut contents = Vec::new();
+      file.read_to_end(&mut contents).ok()?;
+      Some(contents)
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use expectest::prelude::*;
+  use rstest::*;
+  use tempfile::TempDir;
+
+  #[rstest]
+  #[case::directory("", "ok")]
+  #[case::missing("missing", "missing")]
+  #[case::file("index.html", "not a directory")]
+  fn test_check_ready(#[case] public_path: &str, #[case] expected: &str) -> io::Result<()> {
+    let dir = TempDir::new()?;
+    fs::write(dir.path().join("index.html"), "<html></html>")?;
+    let file_system =
+      LocalFileSystem::new(dir.path().join(public_path).to_string_lossy().to_string());
+
+    let result = match file_system.check_ready() {
+      Ok(()) => "ok",
+      Err(FileSystemError::Missing(..)) => "missing",
+      Err(FileSystemError::NotADirectory(_)) => "not a directory",
+      Err(FileSystemError::Unreadable(..)) => "unreadable",
+    };
+    expect!(result).to(be_equal_to(expected));
+    Ok(())
+  }
+
+  #[rstest]
+  #[case::root("", Some(vec![("docs", true, 0), ("index.html", false, 13)]))]
+  #[case::nested("docs/", Some(vec![]))]
+  #[case::file("index.html", None)]
+  #[case::missing("missing", None)]
+  #[case::traversal("..", None)]
+  fn test_list_dir(
+    #[case] dir_path: &str,
+    #[case] expected: Option<Vec<(&str, bool, u64)>>,
+  ) -> io::Result<()> {
+    let dir = TempDir::new()?;
+    let public_path = dir.path().canonicalize()?.join("public");
+    fs::create_dir_all(public_path.join("docs"))?;
+    fs::write(public_path.join("index.html"), "<html></html>")?;
+    let file_system = LocalFileSystem::new(public_path.to_string_lossy().to_string());
+
+    let entries = file_system.list_dir(dir_path).map(|entries| {
+      let mut entries = entries
+        .iter()
+        .map(|entry| (entry.name.clone(), entry.is_dir, entry.size))
+        .collect::<Vec<_>>();
+      entries.sort();
+      entries
+    });
+    let expected = expected.map(|expected| {
+      expected
+        .into_iter()
+        .map(|(name, is_dir, size)| (name.to_string(), is_dir, size))
+        .collect::<Vec<_>>()
+    });
+    expect!(entries).to(be_equal_to(expected));
+    Ok(())
+  }
+
+  #[rstest]
+  #[case::plain("docs/index.html", Some("docs/index.html"))]
+  #[case::leading_slash("/index.html", Some("index.html"))]
+  #[case::dots("./docs/./drafts/../index.html", Some("docs/index.html"))]
+  #[case::root("", Some(""))]
+  #[case::above_root("docs/../../secret.txt", None)]
+  #[case::parent("..", None)]
+  fn test_normalize(#[case] file_path: &str, #[case] expected: Option<&str>) {
+    expect!(normalize(file_path)).to(be_equal_to(expected.map(PathBuf::from)));
+  }
+
+  #[rstest]
+  #[case::file("index.html", Ok(()))]
+  #[case::missing("missing.html", Err(Unresolved::Missing))]
+  #[case::missing_above_root("../missing.html", Err(Unresolved::Traversal))]
+  #[case::existing_above_root("../secret.txt", Err(Unresolved::Traversal))]
+  #[case::escaping_symlink("link/secret.txt", Err(Unresolved::Traversal))]
+  fn test_resolve(
+    #[case] file_path: &str,
+    #[case] expected: Result<(), Unresolved>,
+  ) -> io::Result<()> {
+    let dir = TempDir::new()?;
+    let public_path = dir.path().join("public");
+    fs::create_dir(&public_path)?;
+    fs::write(public_path.join("index.html"), "<html></html>")?;
+    fs::write(dir.path().join("secret.txt"), "secret")?;
+    std::os::unix::fs::symlink(dir.path(), public_path.join("link"))?;
+    // not canonicalized on purpose, the file system does that itself
+    let file_system = LocalFileSystem::new(public_path.to_string_lossy().to_string());
+
+    expect!(resolve(&file_system.root, file_path).map(|_| ())).to(be_equal_to(expected));
+    Ok(())
+  }
+
+  #[rstest]
+  fn test_relative_public_path() -> io::Result<()> {
+    let dir = tempfile::Builder::new().tempdir_in(env!("CARGO_MANIFEST_DIR"))?;
+    fs::write(dir.path().join("index.html"), "<html></html>")?;
+    let relative_path = dir.path().strip_prefix(std::env::current_dir()?).unwrap();
+    let file_system = LocalFileSystem::new(relative_path.to_string_lossy().to_string());
+
+    expect!(file_system.read_file("index.html")).to(be_some().value(b"<html></html>".to_vec()));
+    expect!(file_system.read_file("../Cargo.toml")).to(be_none());
+    Ok(())
+  }
+
+  #[rstest]
+  #[case::new_file("docs/notes.txt", true, Ok(Written::Created), "new")]
+  #[case::replaced("index.html", true, Ok(Written::Replaced), "new")]
+  #[case::kept("index.html", false, Err(io::ErrorKind::AlreadyExists), "old")]
+  #[case::directory("docs", true, Err(io::ErrorKind::IsADirectory), "")]
+  #[case::above_root("../index.html", true, Err(io::ErrorKind::PermissionDenied), "")]
+  #[case::escaping_symlink("link/index.html", true, Err(io::ErrorKind::PermissionDenied), "")]
+  #[case::below_escaping_symlink(
+    "link/new/index.html",
+    true,
+    Err(io::ErrorKind::PermissionDenied),
+    ""
+  )]
+  fn test_write_file(
+    #[case] file_path: &str,
+    #[case] overwrite: bool,
+    #[case] expected: Result<Written, io::ErrorKind>,
+    #[case] expected_contents: &str,
+  ) -> io::Result<()> {
+    let dir = TempDir::new()?;
+    let public_path = dir.path().join("public");
+    fs::create_dir_all(public_path.join("docs"))?;
+    fs::write(public_path.join("index.html"), "old")?;
+    std::os::unix::fs::symlink(dir.path(), public_path.join("link"))?;
+    let file_system = LocalFileSystem::new(public_path.to_string_lossy().to_string());
+
+    let written = file_system.write_file(file_path, &mut &b"new"[..], overwrite);
+    expect!(written.map_err(|e| e.kind())).to(be_equal_to(expected));
+    let contents = file_system.read_file(file_path).unwrap_or_default();
+    expect!(String::from_utf8_lossy(&contents).as_ref()).to(be_equal_to(expected_contents));
+    // no temporary files left behind, and nothing written outside the public path
+    let hidden = fs::read_dir(&public_path)?
+      .chain(fs::read_dir(public_path.join("docs"))?)
+      .filter_map(Result::ok)
+      .filter(|entry| entry.file_name().to_string_lossy().starts_with('.'))
+      .count();
+    expect!(hidden).to(be_equal_to(0));
+    expect!(dir.path().join("index.html").exists()).to(be_false());
+    expect!(dir.path().join("new").exists()).to(be_false());
+    Ok(())
+  }
+
+  #[rstest]
+  fn test_write_file_failing_midway() -> io::Result<()> {
+    let dir = TempDir::new()?;
+    let file_system = LocalFileSystem::new(dir.path().to_string_lossy().to_string());
+    let mut contents = (&b"partial"[..]).chain(FailingReader);
+
+    let written = file_system.write_file("upload.bin", &mut contents, true);
+    expect!(written.map_err(|e| e.kind())).to(be_err().value(io::ErrorKind::FileTooLarge));
+    expect!(fs::read_dir(dir.path())?.count()).to(be_equal_to(0));
+    Ok(())
+  }
+
+  struct FailingReader;
+
+  impl Read for FailingReader {
+    fn read(&mut self, _buf: &mut [u8]) -> io::Result<usize> {
+      Err(io::ErrorKind::FileTooLarge.into())
+    }
+  }
+
+  #[rstest]
+  fn test_read_file_through_cache() -> io::Result<()> {
+    let dir = TempDir::new()?;
+    let public_path = dir.path().canonicalize()?;
+    fs::write(public_path.join("index.html"), "<html></html>")?;
+    let file_system = LocalFileSystem::new(public_path.to_string_lossy().to_string())
+      .with_cache(FileCache::new(Default::default()));
+
+    expect!(file_system.read_file("index.html")).to(be_some().value(b"<html></html>".to_vec()));
+    expect!(file_system.cache().map(FileCache::len)).to(be_some().value(1));
+    fs::write(public_path.join("index.html"), "<html>new</html>")?;
+    expect!(file_system.read_file("index.html")).to(be_some().value(b"<html>new</html>".to_vec()));
+    expect!(file_system.read_file("../secret.txt")).to(be_none());
+    Ok(())
+  }
+
+  #[rstest]
+  #[tokio::test]
+  #[cfg(feature = "server")]
+  async fn test_open_cached_file() -> io::Result<()> {
+    let dir = TempDir::new()?;
+    let public_path = dir.path().canonicalize()?;
+    fs::write(public_path.join("index.html"), "<html></html>")?;
+    let file_system = LocalFileSystem::new(public_path.to_string_lossy().to_string())
+      .with_cache(FileCache::new(Default::default()));
+
+    for _ in 0..2 {
+      let opened = file_system.open_file("index.html", 4).await.unwrap();
+      expect!(opened.start.is_empty()).to(be_true());
+      let cached = opened.cached.unwrap();
+      expect!(&*cached.contents).to(be_equal_to(&b"<html></html>"[..]));
+    }
+    expect!(file_system.cache().map(FileCache::size)).to(be_some().value(13));
+    Ok(())
+  }
+
+  #[rstest]
+  #[case::file("index.html", Some(13))]
+  #[case::missing("missing.html", None)]
+  #[case::directory("", None)]
+  #[case::traversal("../secret.txt", None)]
+  #[tokio::test]
+  #[cfg(feature = "server")]
+  async fn test_open_file(
+    #[case] file_path: &str,
+    #[case] expected_size: Option<u64>,
+  ) -> io::Result<()> {
+    let dir = TempDir::new()?;
+    let public_path = dir.path().canonicalize()?.join("public");
+    fs::create_dir(&public_path)?;
+    fs::write(public_path.join("index.html"), "<html></html>")?;
+    fs::write(dir.path().join("secret.txt"), "secret")?;
+    let file_system = LocalFileSystem::new(public_path.to_string_lossy().to_string());
+
+    let opened = file_system.open_file(file_path, 4).await;
+    expect!(opened.as_ref().map(|opened| opened.metadata.len())).to(be_equal_to(expected_size));
+    if let Some(opened) = opened {
+      expect!(opened.start).to(be_equal_to(b"<htm".to_vec()));
     }
+    Ok(())
   }
 }
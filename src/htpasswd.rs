@@ -0,0 +1,137 @@
+use base64::{engine::general_purpose::STANDARD, Engine};
+use sha1::{Digest, Sha1};
+use std::{collections::HashMap, fs, io, path::Path};
+
+/// Users and their password hashes as `htpasswd` writes them, one `user:hash` per line, e.g.
+/// `alice:$2y$05$...` from `htpasswd -B` or `bob:{SHA}...` from `htpasswd -s`. Other formats
+/// (`$apr1$` MD5, crypt(3) DES, plain text) are too weak to support: their users are skipped
+/// with a warning.
+#[derive(Debug, Clone, Default)]
+pub struct Htpasswd {
+  users: HashMap<String, String>,
+}
+
+impl Htpasswd {
+  pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+    Ok(Self::parse(&fs::read_to_string(path)?))
+  }
+
+  /// Blank lines and `#` comments are ignored, as are lines without a `:`
+  pub fn parse(contents: &str) -> Self {
+    let mut users = HashMap::new();
+    for line in contents.lines().map(str::trim) {
+      if line.is_empty() || line.starts_with('#') {
+        continue;
+      }
+      let Some((user, hash)) = line.split_once(':') else {
+        tracing::warn!("Skipped htpasswd line without a password hash");
+        continue;
+      };
+      if !is_supported(hash) {
+        tracing::warn!(
+          user,
+          "Skipped htpasswd user with an unsupported password hash"
+        );
+        continue;
+      }
+      users.insert(user.to_string(), hash.to_string());
+    }
+    Self { users }
+  }
+
+  /// Whether `user` is listed, with `password`. bcrypt takes its time on purpose, so this
+  /// blocks for as long as the cost the hash was written with demands: keep it off async
+  /// workers, as [`crate::basic_auth::BasicAuth`] does.
+  pub fn verify(&self, user: &str, password: &str) -> bool {
+    let Some(hash) = self.users.get(user) else {
+      return false;
+    };
+    match hash.strip_prefix("{SHA}") {
+      Some(digest) => {
+        let expected = STANDARD.encode(Sha1::digest(password));
+        constant_time_eq(expected.as_bytes(), digest.as_bytes())
+      }
+      None => bcrypt::verify(password, hash).unwrap_or(false),
+    }
+  }
+
+  pub fn len(&self) -> usize {
+    self.users.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.users.is_empty()
+  }
+}
+
+fn is_supported(hash: &str) -> bool {
+  ["$2a$", "$2b$", "$2y$", "{SHA}"]
+    .iter()
+    .any(|prefix| hash.starts_with(prefix))
+}
+
+/// Compares as long for every `a` of one length, however much of it matches `b`
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+  a.len() == b.len()
+    && a
+      .iter()
+      .zip(b)
+      .fold(0, |difference, (a, b)| difference | (a ^ b))
+      == 0
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use expectest::prelude::*;
+  use rstest::*;
+
+  const HTPASSWD: &str = "\
+    # written by htpasswd
+    alice:$2y$05$CCCCCCCCCCCCCCCCCCCCC.aDV7CQarKHMuNfh2oJkFzsHZya4whFe
+    bob:{SHA}5en6G6MezRroT3XKqkdPOmY/BfQ=
+
+    carol:$apr1$salt$hash
+    dave
+  ";
+
+  #[rstest]
+  #[case::bcrypt("alice", "password", true)]
+  #[case::sha("bob", "secret", true)]
+  #[case::wrong_password("bob", "Secret", false)]
+  #[case::other_users_password("alice", "secret", false)]
+  #[case::unsupported_hash("carol", "hash", false)]
+  #[case::unknown_user("eve", "password", false)]
+  fn test_verify(#[case] user: &str, #[case] password: &str, #[case] expected: bool) {
+    let htpasswd = Htpasswd::parse(HTPASSWD);
+    expect!(htpasswd.len()).to(be_equal_to(2));
+    expect!(htpasswd.verify(user, password)).to(be_equal_to(expected));
+  }
+
+  // written by crypt(3) of libxcrypt
+  #[rstest]
+  #[case::empty("", "$2y$04$DCq7YPn5Rq63x1Lad4cll.gPqnXZmDhhq3zJUTSJckzck53/zZY4W", true)]
+  #[case::openbsd("U*U", "$2a$05$CCCCCCCCCCCCCCCCCCCCC.E5YPO9kmyuRGyh0XouQYb4YMJKvyOeW", true)]
+  #[case::non_ascii("café", "$2b$04$abcdefghijklmnopqrstuuusvfeYzFuQdqd.KjimlRKkZ2QcoHWhO", true)]
+  #[case::truncated(
+    &format!("{}ignored", "x".repeat(72)),
+    "$2y$04$abcdefghijklmnopqrstuubzadhGtS2zEF.gu0yd0opP6cVzb.e0i",
+    true
+  )]
+  #[case::buggy_variant(
+    "U*U",
+    "$2x$05$CCCCCCCCCCCCCCCCCCCCC.E5YPO9kmyuRGyh0XouQYb4YMJKvyOeW",
+    false
+  )]
+  #[case::low_cost("U*U", "$2a$03$CCCCCCCCCCCCCCCCCCCCC.E5YPO9kmyuRGyh0XouQYb4YMJKvyOeW", false)]
+  #[case::short_hash("U*U", "$2a$05$CCCCCCCCCCCCCCCCCCCCC.E5YPO9kmyuRGyh0XouQYb4YMJKvyOe", false)]
+  #[case::multibyte_salt(
+    "U*U",
+    "$2a$05$CCCCCCCCCCCCCCCCCCCCé.E5YPO9kmyuRGyh0XouQYb4YMJKvyOeW",
+    false
+  )]
+  fn test_verify_bcrypt(#[case] password: &str, #[case] hash: &str, #[case] expected: bool) {
+    let htpasswd = Htpasswd::parse(&format!("user:{}", hash));
+    expect!(htpasswd.verify("user", password)).to(be_equal_to(expected));
+  }
+}
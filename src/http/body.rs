@@ -0,0 +1,302 @@
+use std::{
+  io::{self, Read},
+  pin::Pin,
+  task::{ready, Context, Poll},
+};
+use tokio::{
+  io::{AsyncRead, AsyncReadExt, ReadBuf},
+  sync::mpsc,
+};
+
+use super::chunked::{ChunkedDecoder, ChunkedError};
+
+/// Bytes read from the connection per chunk handed to [`RequestBody`]
+pub const BODY_CHUNK_SIZE: usize = 8 * 1024;
+/// Chunks read ahead of the handler, bounding the memory one upload takes up
+const BODY_CHUNKS_BUFFERED: usize = 4;
+
+type Chunk = io::Result<Vec<u8>>;
+
+/// A request body streamed from the connection while the handler runs, readable both as
+/// [`std::io::Read`] from blocking code (which handlers are) and as [`AsyncRead`] from async
+/// code. Only a few chunks are held at any time, so bodies of any size take constant memory;
+/// reading ahead stops once the handler stops reading.
+pub struct RequestBody {
+  chunk: Vec<u8>,
+  position: usize,
+  /// `None` once everything has been received
+  receiver: Option<mpsc::Receiver<Chunk>>,
+  /// `None` for chunked bodies
+  length: Option<u64>,
+}
+
+/// Feeds a [`RequestBody`] from the connection, see [`BodySender::pump`]
+pub struct BodySender {
+  /// dropped once the body is complete, ending the [`RequestBody`]
+  sender: Option<mpsc::Sender<Chunk>>,
+  framing: Framing,
+}
+
+enum Framing {
+  Length {
+    remaining: u64,
+  },
+  Chunked {
+    decoder: ChunkedDecoder,
+    /// read but not decoded yet, such as a chunk size line that's still incomplete
+    pending: Vec<u8>,
+    invalid: Option<ChunkedError>,
+  },
+}
+
+impl RequestBody {
+  pub fn empty() -> Self {
+    Self {
+      chunk: Vec::new(),
+      position: 0,
+      receiver: None,
+      length: Some(0),
+    }
+  }
+
+  /// A body of `length` bytes, starting with the `received` ones read along with the head
+  pub fn channel(received: &[u8], length: u64) -> (Self, BodySender) {
+    let received = &received[..received
+      .len()
+      .min(usize::try_from(length).unwrap_or(usize::MAX))];
+    let remaining = length - received.len() as u64;
+    let (sender, receiver) = mpsc::channel(BODY_CHUNKS_BUFFERED);
+    let body = Self {
+      chunk: received.to_vec(),
+      position: 0,
+      receiver: Some(receiver).filter(|_| remaining > 0),
+      length: Some(length),
+    };
+    let framing = Framing::Length { remaining };
+    (body, BodySender { sender: Some(sender), framing })
+  }
+
+  /// A `Transfer-Encoding: chunked` body of up to `max_size` bytes once decoded, starting
+  /// with the `received` ones read along with the head. Only as much of the connection is
+  /// read as the body takes up, so `received` may go on with the next request.
+  pub fn chunked(received: &[u8], max_size: usize) -> (Self, BodySender) {
+    let (sender, receiver) = mpsc::channel(BODY_CHUNKS_BUFFERED);
+    let body = Self {
+      chunk: Vec::new(),
+      position: 0,
+      receiver: Some(receiver),
+      length: None,
+    };
+    let framing = Framing::Chunked {
+      decoder: ChunkedDecoder::new(max_size),
+      pending: received.to_vec(),
+      invalid: None,
+    };
+    (body, BodySender { sender: Some(sender), framing })
+  }
+
+  /// From `Content-Length`, the total a handler reads unless the client disconnects; 0 for
+  /// chunked bodies, whose length isn't known before they've been read
+  pub fn len(&self) -> u64 {
+    self.length.unwrap_or(0)
+  }
+
+  /// Whether there's no body, which a chunked one never is until read
+  pub fn is_empty(&self) -> bool {
+    self.length == Some(0)
+  }
+
+  /// Copy out of the current chunk, `None` if it has been read entirely
+  fn read_buffered(&mut self, buf: &mut [u8]) -> Option<usize> {
+    let available = &self.chunk[self.position..];
+    if available.is_empty() {
+      return None;
+    }
+    let copied = available.len().min(buf.len());
+    buf[..copied].copy_from_slice(&available[..copied]);
+    self.position += copied;
+    Some(copied)
+  }
+
+  fn next_chunk(&mut self, chunk: Option<Chunk>) -> io::Result<()> {
+    match chunk {
+      Some(chunk) => {
+        self.chunk = chunk?;
+        self.position = 0;
+      }
+      None => self.receiver = None,
+    }
+    Ok(())
+  }
+}
+
+impl Read for RequestBody {
+  /// Blocks until the next chunk arrives, so must not be called from async code
+  fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+    loop {
+      if let Some(copied) = self.read_buffered(buf) {
+        return Ok(copied);
+      }
+      let Some(receiver) = self.receiver.as_mut() else {
+        return Ok(0);
+      };
+      let chunk = receiver.blocking_recv();
+      self.next_chunk(chunk)?;
+    }
+  }
+}
+
+impl AsyncRead for RequestBody {
+  fn poll_read(
+    mut self: Pin<&mut Self>,
+    cx: &mut Context<'_>,
+    buf: &mut ReadBuf<'_>,
+  ) -> Poll<io::Result<()>> {
+    loop {
+      if let Some(copied) = self.read_buffered(buf.initialize_unfilled()) {
+        buf.advance(copied);
+        return Poll::Ready(Ok(()));
+      }
+      let Some(receiver) = self.receiver.as_mut() else {
+        return Poll::Ready(Ok(()));
+      };
+      let chunk = ready!(receiver.poll_recv(cx));
+      self.next_chunk(chunk)?;
+    }
+  }
+}
+
+impl BodySender {
+  /// Read the rest of the body from `source` into the [`RequestBody`], until it's complete,
+  /// the connection fails or the body has been dropped. Cancelling it leaves `source` right
+  /// after the bytes counted as read, see [`Self::is_complete`].
+  pub async fn pump<R: AsyncRead + Unpin>(&mut self, source: &mut R) {
+    while !self.is_complete() {
+      let Some(sender) = &self.sender else {
+        return;
+      };
+      let chunk = match &mut self.framing {
+        Framing::Length { remaining } => Self::read_length(source, remaining).await,
+        Framing::Chunked { decoder, pending, invalid } => {
+          Self::read_chunked(source, decoder, pending, invalid).await
+        }
+      };
+      let failed = chunk.is_err();
+      if sender.send(chunk).await.is_err() || failed {
+        self.sender = None;
+        return;
+      }
+    }
+    self.sender = None;
+  }
+
+  /// The next chunk of a body of `remaining` bytes
+  async fn read_length<R: AsyncRead + Unpin>(source: &mut R, remaining: &mut u64) -> Chunk {
+    let chunk_size = BODY_CHUNK_SIZE.min(usize::try_from(*remaining).unwrap_or(usize::MAX));
+    let mut chunk = vec![0; chunk_size];
+    let bytes_read = Self::read(source, &mut chunk).await?;
+    chunk.truncate(bytes_read);
+    *remaining -= bytes_read as u64;
+    Ok(chunk)
+  }
+
+  /// The next piece of chunk data, reading no further than `decoder` wants so the next
+  /// request stays on the connection
+  async fn read_chunked<R: AsyncRead + Unpin>(
+    source: &mut R,
+    decoder: &mut ChunkedDecoder,
+    pending: &mut Vec<u8>,
+    invalid: &mut Option<ChunkedError>,
+  ) -> Chunk {
+    loop {
+      let mut data = Vec::new();
+      let consumed = decoder.decode(pending, &mut data).map_err(|error| {
+        *invalid = Some(error);
+        io::Error::new(io::ErrorKind::InvalidData, error)
+      })?;
+      pending.drain(..consumed);
+      if !data.is_empty() || decoder.is_done() {
+        return Ok(data);
+      }
+      let mut chunk = vec![0; BODY_CHUNK_SIZE.min(decoder.wanted())];
+      let bytes_read = Self::read(source, &mut chunk).await?;
+      pending.extend_from_slice(&chunk[..bytes_read]);
+    }
+  }
+
+  async fn read<R: AsyncRead + Unpin>(source: &mut R, chunk: &mut [u8]) -> io::Result<usize> {
+    match source.read(chunk).await? {
+      0 => Err(io::Error::new(
+        io::ErrorKind::UnexpectedEof,
+        "connection closed before the request body was complete",
+      )),
+      bytes_read => Ok(bytes_read),
+    }
+  }
+
+  /// Whether all of the body has been read from the connection, so the next request (if
+  /// any) starts right after it
+  pub fn is_complete(&self) -> bool {
+    match &self.framing {
+      Framing::Length { remaining } => *remaining == 0,
+      Framing::Chunked { decoder, .. } => decoder.is_done(),
+    }
+  }
+
+  /// Why a chunked body couldn't be decoded, which its handler only sees as a failed read
+  pub fn invalid_body(&self) -> Option<ChunkedError> {
+    match &self.framing {
+      Framing::Length { .. } => None,
+      Framing::Chunked { invalid, .. } => *invalid,
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use expectest::prelude::*;
+  use rstest::*;
+
+  #[rstest]
+  #[tokio::test]
+  async fn test_stream_body_beyond_buffered_chunks() -> io::Result<()> {
+    let length = BODY_CHUNK_SIZE * (BODY_CHUNKS_BUFFERED + 4) + 3;
+    let upload = (0..length).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+    let (mut body, mut sender) = RequestBody::channel(&upload[..10], length as u64);
+    let mut source = &upload[10..];
+
+    let reader = tokio::task::spawn_blocking(move || {
+      let mut received = Vec::new();
+      Read::read_to_end(&mut body, &mut received).map(|_| received)
+    });
+    sender.pump(&mut source).await;
+    expect!(reader.await??).to(be_equal_to(upload));
+    Ok(())
+  }
+
+  #[rstest]
+  #[tokio::test]
+  async fn test_truncated_body() {
+    let (mut body, mut sender) = RequestBody::channel(b"abc", 10);
+    let mut source = &b"de"[..];
+    sender.pump(&mut source).await;
+
+    let mut received = Vec::new();
+    let error = AsyncReadExt::read_to_end(&mut body, &mut received)
+      .await
+      .unwrap_err();
+    expect!(error.kind()).to(be_equal_to(io::ErrorKind::UnexpectedEof));
+    expect!(received).to(be_equal_to(b"abcde".to_vec()));
+  }
+
+  #[rstest]
+  #[tokio::test]
+  async fn test_ignore_bytes_past_content_length() -> io::Result<()> {
+    let (mut body, _sender) = RequestBody::channel(b"abcGET / HTTP/1.1", 3);
+    let mut received = Vec::new();
+    AsyncReadExt::read_to_end(&mut body, &mut received).await?;
+    expect!(received).to(be_equal_to(b"abc".to_vec()));
+    Ok(())
+  }
+}
@@ -0,0 +1,279 @@
+//! Decoding of `Transfer-Encoding: chunked` request bodies (RFC 9112, section 7.1). Like
+//! [`super::codec`], it only looks at bytes, reading them is up to the caller.
+
+use thiserror::Error;
+
+use super::StatusCode;
+
+/// Longest chunk size line or trailer field accepted, extensions included
+pub const MAX_CHUNK_LINE_LENGTH: usize = 1024;
+
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChunkedError {
+  #[error("Invalid chunk size")]
+  InvalidChunkSize,
+  #[error("Chunk data not followed by a line break")]
+  MissingChunkEnd,
+  #[error("Chunk size line or trailer longer than {MAX_CHUNK_LINE_LENGTH} bytes")]
+  LineTooLong,
+  #[error("Chunked body larger than {0} bytes")]
+  TooLarge(usize),
+  #[error("Connection closed before the chunked body was complete")]
+  Truncated,
+}
+
+impl ChunkedError {
+  /// The status a client receives when its body can't be decoded
+  pub fn status_code(&self) -> StatusCode {
+    match self {
+      Self::TooLarge(_) => StatusCode::PayloadTooLarge,
+      _ => StatusCode::BadRequest,
+    }
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+  Size,
+  Data { remaining: u64 },
+  DataEnd,
+  Trailers,
+  Done,
+}
+
+/// Reassembles a chunked body from input arriving in pieces of any size, refusing to decode
+/// more than `max_size` bytes. Chunk extensions and trailer fields are skipped.
+///
+/// ```
+/// use udemy_server::http::chunked::ChunkedDecoder;
+///
+/// let mut decoder = ChunkedDecoder::new(1024);
+/// let mut body = Vec::new();
+/// let consumed = decoder.decode(b"5\r\nhello\r\n0\r\n\r\nGET /", &mut body).unwrap();
+/// assert_eq!(body, b"hello");
+/// assert_eq!(consumed, 15);
+/// assert!(decoder.is_done());
+/// ```
+#[derive(Debug, Clone)]
+pub struct ChunkedDecoder {
+  state: State,
+  max_size: usize,
+  decoded: usize,
+}
+
+impl ChunkedDecoder {
+  pub fn new(max_size: usize) -> Self {
+    Self { state: State::Size, max_size, decoded: 0 }
+  }
+
+  /// Whether the last chunk and the trailers have been decoded
+  pub fn is_done(&self) -> bool {
+    self.state == State::Done
+  }
+
+  /// How many bytes to read next without reading past the end of the body: the rest of the
+  /// current chunk's data, the lines around it a byte at a time, none once done
+  pub fn wanted(&self) -> usize {
+    match self.state {
+      State::Data { remaining } => usize::try_from(remaining).unwrap_or(usize::MAX),
+      State::Done => 0,
+      State::Size | State::DataEnd | State::Trailers => 1,
+    }
+  }
+
+  /// Decode as much of `input` as is complete, appending the chunk data to `output`, and
+  /// return how many bytes of `input` that consumed. The rest has to be passed in again
+  /// along with more input; nothing past the end of the body is consumed.
+  pub fn decode(&mut self, input: &[u8], output: &mut Vec<u8>) -> Result<usize, ChunkedError> {
+    let mut consumed = 0;
+    loop {
+      let (data, length) = self.next_data(&input[consumed..])?;
+      output.extend_from_slice(data);
+      consumed += length;
+      if data.is_empty() {
+        return Ok(consumed);
+      }
+    }
+  }
+
+  /// Like [`Self::decode`] without copying: skip the framing at the start of `input` up to
+  /// the next chunk data, and return as much of that data as `input` holds, borrowed from it,
+  /// along with how many bytes of `input` that consumed. The data is empty once the body is
+  /// done or until more input arrives.
+  pub fn next_data<'input>(
+    &mut self,
+    input: &'input [u8],
+  ) -> Result<(&'input [u8], usize), ChunkedError> {
+    let mut consumed = 0;
+    loop {
+      let input = &input[consumed..];
+      match self.state {
+        State::Size => {
+          let Some((line, length)) = Self::line(input)? else {
+            return Ok((&[], consumed));
+          };
+          let size = Self::chunk_size(line)?;
+          if usize::try_from(size).map_or(true, |size| size > self.max_size - self.decoded) {
+            return Err(ChunkedError::TooLarge(self.max_size));
+          }
+          self.state = match size {
+            0 => State::Trailers,
+            remaining => State::Data { remaining },
+          };
+          consumed += length;
+        }
+        State::Data { remaining } => {
+          let length = input
+            .len()
+            .min(usize::try_from(remaining).unwrap_or(usize::MAX));
+          self.decoded += length;
+          self.state = match remaining - length as u64 {
+            0 => State::DataEnd,
+            remaining => State::Data { remaining },
+          };
+          return Ok((&input[..length], consumed + length));
+        }
+        State::DataEnd => {
+          let Some((line, length)) = Self::line(input)? else {
+            return Ok((&[], consumed));
+          };
+          if !line.is_empty() {
+            return Err(ChunkedError::MissingChunkEnd);
+          }
+          self.state = State::Size;
+          consumed += length;
+        }
+        State::Trailers => {
+          let Some((line, length)) = Self::line(input)? else {
+            return Ok((&[], consumed));
+          };
+          if line.is_empty() {
+            self.state = State::Done;
+          }
+          consumed += length;
+        }
+        State::Done => return Ok((&[], consumed)),
+      }
+    }
+  }
+
+  /// The line at the start of `input` without its line break, and its length with it
+  fn line(input: &[u8]) -> Result<Option<(&[u8], usize)>, ChunkedError> {
+    match input.iter().position(|&byte| byte == b'\n') {
+      Some(end) if end > MAX_CHUNK_LINE_LENGTH => Err(ChunkedError::LineTooLong),
+      Some(end) => Ok(Some((
+        input[..end].strip_suffix(b"\r").unwrap_or(&input[..end]),
+        end + 1,
+      ))),
+      None if input.len() > MAX_CHUNK_LINE_LENGTH => Err(ChunkedError::LineTooLong),
+      None => Ok(None),
+    }
+  }
+
+  /// The hexadecimal size starting `line`, ignoring any extensions after it
+  fn chunk_size(line: &[u8]) -> Result<u64, ChunkedError> {
+    let size = line
+      .split(|&byte| byte == b';')
+      .next()
+      .unwrap_or_default()
+      .trim_ascii_end();
+    if size.is_empty() || !size.iter().all(u8::is_ascii_hexdigit) {
+      return Err(ChunkedError::InvalidChunkSize);
+    }
+    std::str::from_utf8(size)
+      .ok()
+      .and_then(|size| u64::from_str_radix(size, 16).ok())
+      .ok_or(ChunkedError::InvalidChunkSize)
+  }
+}
+
+/// `head` of a request whose chunked body has been decoded into `content_length` bytes, with
+/// its `Transfer-Encoding` replaced by that `Content-Length`. Handlers, limits and the rest of
+/// the connection then treat it like any request with a `Content-Length`.
+pub fn unchunked_head(head: &[u8], content_length: usize) -> Vec<u8> {
+  let mut lines = head.split_inclusive(|&byte| byte == b'\n');
+  let mut unchunked = lines.next().unwrap_or_default().to_vec();
+  lines
+    .take_while(|line| !line.trim_ascii().is_empty())
+    .filter(|line| {
+      let name = line.split(|&byte| byte == b':').next().unwrap_or_default();
+      let name = name.trim_ascii();
+      !name.eq_ignore_ascii_case(b"transfer-encoding")
+        && !name.eq_ignore_ascii_case(b"content-length")
+    })
+    .for_each(|line| unchunked.extend_from_slice(line));
+  unchunked.extend_from_slice(format!("Content-Length: {}\r\n\r\n", content_length).as_bytes());
+  unchunked
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use expectest::prelude::*;
+  use rstest::*;
+
+  /// Decode `input` `piece_size` bytes at a time
+  fn decode(input: &[u8], piece_size: usize, max_size: usize) -> Result<Vec<u8>, ChunkedError> {
+    let mut decoder = ChunkedDecoder::new(max_size);
+    let mut output = Vec::new();
+    let (mut consumed, mut received) = (0, 0);
+    while !decoder.is_done() {
+      if received == input.len() {
+        return Err(ChunkedError::Truncated);
+      }
+      received = input.len().min(received + piece_size);
+      consumed += decoder.decode(&input[consumed..received], &mut output)?;
+    }
+    Ok(output)
+  }
+
+  #[rstest]
+  #[case::whole(usize::MAX)]
+  #[case::byte_by_byte(1)]
+  #[case::pieces(4)]
+  fn test_decode(#[case] piece_size: usize) -> Result<(), ChunkedError> {
+    let input = b"4\r\nWiki\r\n6;name=value\r\npedia \r\nE\r\nin \r\n\r\nchunks.\r\n0\r\nExpires: never\r\n\r\n";
+    let body = decode(input, piece_size, 1024)?;
+    expect!(body).to(be_equal_to(b"Wikipedia in \r\n\r\nchunks.".to_vec()));
+    Ok(())
+  }
+
+  #[rstest]
+  #[case::not_hex(&b"g\r\nhello\r\n0\r\n\r\n"[..], ChunkedError::InvalidChunkSize)]
+  #[case::empty(&b"\r\nhello\r\n0\r\n\r\n"[..], ChunkedError::InvalidChunkSize)]
+  #[case::signed(&b"+5\r\nhello\r\n0\r\n\r\n"[..], ChunkedError::InvalidChunkSize)]
+  #[case::overflowing(&b"10000000000000000\r\n"[..], ChunkedError::InvalidChunkSize)]
+  #[case::data_too_long(&b"3\r\nhello\r\n0\r\n\r\n"[..], ChunkedError::MissingChunkEnd)]
+  #[case::endless_line(&[b'1'; MAX_CHUNK_LINE_LENGTH + 1][..], ChunkedError::LineTooLong)]
+  #[case::too_large(&b"5\r\nhello\r\n6\r\n world\r\n0\r\n\r\n"[..], ChunkedError::TooLarge(10))]
+  #[case::truncated(&b"5\r\nhel"[..], ChunkedError::Truncated)]
+  fn test_reject_malformed(#[case] input: &[u8], #[case] expected: ChunkedError) {
+    expect!(decode(input, usize::MAX, 10)).to(be_err().value(expected));
+  }
+
+  #[rstest]
+  fn test_read_no_further_than_wanted() -> Result<(), ChunkedError> {
+    let input = b"5\r\nhello\r\n0\r\nX-Trailer: t\r\n\r\nGET / HTTP/1.1\r\n";
+    let mut decoder = ChunkedDecoder::new(1024);
+    let (mut output, mut pending, mut received) = (Vec::new(), Vec::new(), 0);
+    while !decoder.is_done() {
+      let wanted = decoder.wanted();
+      pending.extend_from_slice(&input[received..received + wanted]);
+      received += wanted;
+      let consumed = decoder.decode(&pending, &mut output)?;
+      pending.drain(..consumed);
+    }
+    expect!(output).to(be_equal_to(b"hello".to_vec()));
+    expect!(&input[received..]).to(be_equal_to(&b"GET / HTTP/1.1\r\n"[..]));
+    Ok(())
+  }
+
+  #[rstest]
+  fn test_unchunked_head() {
+    let head =
+      b"POST /upload HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked\r\nX-Tag: a\r\n\r\n";
+    let expected =
+      "POST /upload HTTP/1.1\r\nHost: localhost\r\nX-Tag: a\r\nContent-Length: 11\r\n\r\n";
+    expect!(String::from_utf8(unchunked_head(head, 11)).unwrap()).to(be_equal_to(expected));
+  }
+}
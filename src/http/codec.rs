@@ -0,0 +1,643 @@
+//! The transport-agnostic core of the HTTP layer: bytes in, events out. Nothing here reads
+//! from or writes to a connection, so it builds without the tokio server (`default-features =
+//! false`) and can be driven by clients, tests or fuzzers as well as by the server.
+
+use super::{
+  chunked::ChunkedDecoder, request::OwnedHttpRequest, HttpRequest, ParseError, ParserLimits,
+  QueryLimits,
+};
+use std::task::Poll;
+
+/// Upper bound of the head [`RequestParser::advance`] collects unless configured otherwise,
+/// see [`RequestParser::max_head_size`]
+pub const DEFAULT_MAX_HEAD_SIZE: usize = 16 * 1024;
+
+/// Length of the request head in `buffer`, up to and including the blank line ending it
+pub fn head_length(buffer: &[u8]) -> Option<usize> {
+  let crlf = buffer
+    .windows(4)
+    .position(|window| window == b"\r\n\r\n")
+    .map(|position| position + 4);
+  let lf = buffer
+    .windows(2)
+    .position(|window| window == b"\n\n")
+    .map(|position| position + 2);
+  crlf.into_iter().chain(lf).min()
+}
+
+/// The values of every `name` header in `head`, in the order sent, without parsing the rest
+/// of it. Values that aren't UTF-8 come out empty.
+fn header_values<'head>(head: &'head [u8], name: &'head str) -> impl Iterator<Item = &'head str> {
+  head
+    .split(|&byte| byte == b'\n')
+    .skip(1)
+    .take_while(|line| !line.trim_ascii().is_empty())
+    .filter_map(move |line| {
+      let colon = line.iter().position(|&byte| byte == b':')?;
+      let (key, value) = (&line[..colon], &line[colon + 1..]);
+      key
+        .trim_ascii()
+        .eq_ignore_ascii_case(name.as_bytes())
+        .then_some(value)
+    })
+    .map(|value| std::str::from_utf8(value).unwrap_or_default().trim())
+}
+
+/// The value of the first `name` header in `head`
+fn header_value<'head>(head: &'head [u8], name: &'head str) -> Option<&'head str> {
+  header_values(head, name).next()
+}
+
+/// The last coding of all `Transfer-Encoding` headers in `head` taken together, which is the
+/// one framing the body (RFC 9112, section 6.1)
+fn final_transfer_coding(head: &[u8]) -> Option<&str> {
+  header_values(head, "transfer-encoding")
+    .last()
+    .map(|transfer_encoding| {
+      transfer_encoding
+        .rsplit(',')
+        .next()
+        .unwrap_or_default()
+        .trim()
+    })
+}
+
+/// A `Content-Length` value as a number of bytes: digits only (`1*DIGIT` of RFC 9110,
+/// section 8.6), no sign, list or whitespace within, so every parser along the way reads the
+/// same length
+pub fn parse_content_length(value: &str) -> Option<u64> {
+  let digits = value.trim();
+  match !digits.is_empty() && digits.bytes().all(|byte| byte.is_ascii_digit()) {
+    true => digits.parse().ok(),
+    false => None,
+  }
+}
+
+/// Length of the request line in `buffer` without its line break, or as much of it as has
+/// arrived, e.g. to turn down overly long targets before the rest of the head is read
+pub fn request_line_length(buffer: &[u8]) -> usize {
+  let line_end = buffer
+    .iter()
+    .position(|&byte| byte == b'\n')
+    .unwrap_or(buffer.len());
+  buffer[..line_end].trim_ascii_end().len()
+}
+
+/// The `Content-Length` declared in `head`, e.g. to know how much more to read before the
+/// request can be handled
+pub fn content_length(head: &[u8]) -> Option<u64> {
+  parse_content_length(header_value(head, "content-length")?)
+}
+
+/// The path the request line of `head` targets, without its query, e.g. to look up the limits
+/// applying to the request before it has been parsed
+pub fn request_path(head: &[u8]) -> Option<&str> {
+  let request_line = head.split(|&byte| byte == b'\n').next()?;
+  let target = std::str::from_utf8(request_line).ok()?.split(' ').nth(1)?;
+  target.split('?').next()
+}
+
+/// Whether the body following `head` is sent with `Transfer-Encoding: chunked`, see
+/// [`super::chunked`]
+pub fn is_chunked(head: &[u8]) -> bool {
+  final_transfer_coding(head).is_some_and(|coding| coding.eq_ignore_ascii_case("chunked"))
+}
+
+/// Turn down heads whose body length parsers along the way could read differently, the
+/// opening for request smuggling (RFC 9112, sections 5.2 and 6.3): folded header lines,
+/// `Transfer-Encoding` along with `Content-Length` or not ending in `chunked`, and
+/// `Content-Length`s that aren't a number or disagree.
+pub fn check_framing(head: &[u8]) -> Result<(), ParseError> {
+  let lines = head
+    .split(|&byte| byte == b'\n')
+    .skip(1)
+    .take_while(|line| !line.trim_ascii().is_empty());
+  let (mut content_length, mut transfer_encoding) = (None, false);
+  for line in lines {
+    if line
+      .first()
+      .is_some_and(|&byte| byte == b' ' || byte == b'\t')
+    {
+      return Err(ParseError::AmbiguousFraming(
+        "obsolete line folding".to_string(),
+      ));
+    }
+    let Some(colon) = line.iter().position(|&byte| byte == b':') else {
+      continue;
+    };
+    let (key, value) = (line[..colon].trim_ascii(), line[colon + 1..].trim_ascii());
+    if key.eq_ignore_ascii_case(b"transfer-encoding") {
+      transfer_encoding = true;
+    } else if key.eq_ignore_ascii_case(b"content-length") {
+      let Some(value) = std::str::from_utf8(value)
+        .ok()
+        .and_then(parse_content_length)
+      else {
+        return Err(ParseError::AmbiguousFraming(
+          "invalid Content-Length".to_string(),
+        ));
+      };
+      if content_length
+        .replace(value)
+        .is_some_and(|previous| previous != value)
+      {
+        return Err(ParseError::AmbiguousFraming(
+          "conflicting Content-Length values".to_string(),
+        ));
+      }
+    }
+  }
+  if transfer_encoding && content_length.is_some() {
+    return Err(ParseError::AmbiguousFraming(
+      "both Transfer-Encoding and Content-Length".to_string(),
+    ));
+  }
+  // a body of unknown length would run to the end of the connection, or be taken for the next
+  // request by a parser that ignores the coding
+  match transfer_encoding && !is_chunked(head) {
+    true => Err(ParseError::AmbiguousFraming(
+      "Transfer-Encoding not ending in chunked".to_string(),
+    )),
+    false => Ok(()),
+  }
+}
+
+/// What the client waits for before sending the body, as its `Expect` header says
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Expectation {
+  /// `100-continue`: an interim `100 Continue` before the body, or a final status instead
+  Continue,
+  /// anything else, which gets `417 Expectation Failed`
+  Unsupported,
+}
+
+/// The expectation `head` declares, if any. HTTP/1.0 clients can't know about `100 Continue`,
+/// so theirs is ignored (RFC 9110, section 10.1.1).
+pub fn expectation(head: &[u8]) -> Option<Expectation> {
+  let expect = header_value(head, "expect")?;
+  let request_line = head.split(|&byte| byte == b'\n').next()?;
+  match expect.eq_ignore_ascii_case("100-continue") {
+    true if request_line.trim_ascii_end().ends_with(b"HTTP/1.0") => None,
+    true => Some(Expectation::Continue),
+    false => Some(Expectation::Unsupported),
+  }
+}
+
+/// What [`RequestParser::parse`] found at the start of its input
+#[derive(Debug)]
+pub enum Event<'buf> {
+  /// A complete request head, borrowing from the input
+  Head(Box<HttpRequest<'buf>>),
+  /// Part of the body, in the order received
+  Body(&'buf [u8]),
+  /// The request is complete, the next input starts another one
+  End,
+}
+
+#[derive(Debug, Clone)]
+enum State {
+  Head,
+  Body { remaining: u64 },
+  Chunked(ChunkedDecoder),
+  End,
+}
+
+/// Parses a stream of requests from input arriving in pieces of any size, bodies framed by
+/// `Content-Length` or `Transfer-Encoding: chunked`. With [`Self::parse`], nothing is buffered
+/// or copied: the caller keeps the bytes, the parser only tracks where it is. Callers that would rather hand over each piece as it arrives let
+/// [`Self::advance`] collect the head instead.
+///
+/// ```
+/// use udemy_server::http::codec::{Event, RequestParser};
+///
+/// let input = b"POST /upload HTTP/1.1\r\nContent-Length: 3\r\n\r\nabc";
+/// let mut parser = RequestParser::default();
+/// let (event, consumed) = parser.parse(input).unwrap().unwrap();
+/// assert!(matches!(event, Event::Head(request) if request.path() == "/upload"));
+/// let (event, _) = parser.parse(&input[consumed..]).unwrap().unwrap();
+/// assert!(matches!(event, Event::Body(b"abc")));
+/// ```
+#[derive(Debug, Clone)]
+pub struct RequestParser {
+  limits: ParserLimits,
+  query_limits: QueryLimits,
+  state: State,
+  max_head_size: usize,
+  /// what [`Self::advance`] has received of the head so far
+  head: Vec<u8>,
+}
+
+impl Default for RequestParser {
+  fn default() -> Self {
+    Self::new(ParserLimits::default(), QueryLimits::default())
+  }
+}
+
+impl RequestParser {
+  pub fn new(limits: ParserLimits, query_limits: QueryLimits) -> Self {
+    Self {
+      limits,
+      query_limits,
+      state: State::Head,
+      max_head_size: DEFAULT_MAX_HEAD_SIZE,
+      head: Vec::new(),
+    }
+  }
+
+  /// Heads [`Self::advance`] hasn't found the end of within `max_head_size` bytes are turned
+  /// down with `431 Request Header Fields Too Large`
+  pub fn max_head_size(mut self, max_head_size: usize) -> Self {
+    self.max_head_size = max_head_size.max(1);
+    self
+  }
+
+  /// Take the next piece of a request head, returning the request once the head is complete,
+  /// and how many bytes of `input` were consumed either way. Pieces are kept until then, and
+  /// only their new bytes are searched for the end of the head, so the head may arrive in as
+  /// many reads as it takes. What follows the head in `input`, its body or the next request,
+  /// is left for [`Self::parse`]; while a body is still being parsed, nothing is consumed.
+  ///
+  /// ```
+  /// use std::task::Poll;
+  /// use udemy_server::http::codec::RequestParser;
+  ///
+  /// let mut parser = RequestParser::default();
+  /// let (request, consumed) = parser.advance(b"GET /search?q=rust HT").unwrap();
+  /// assert!(request.is_pending() && consumed == 21);
+  /// let (request, consumed) = parser.advance(b"TP/1.1\r\nHost: a\r\n\r\n").unwrap();
+  /// assert!(matches!(request, Poll::Ready(request) if request.path() == "/search"));
+  /// assert_eq!(consumed, 19);
+  /// ```
+  pub fn advance(&mut self, input: &[u8]) -> Result<(Poll<OwnedHttpRequest>, usize), ParseError> {
+    match self.state {
+      State::Body { .. } | State::Chunked(_) => return Ok((Poll::Pending, 0)),
+      State::End => self.state = State::Head,
+      State::Head => {}
+    }
+    // the end of the head may straddle the previous piece and this one
+    let searched = self.head.len().saturating_sub(3);
+    let received = self.head.len();
+    self.head.extend_from_slice(input);
+    if request_line_length(&self.head) > self.limits.max_request_line_length {
+      self.head.clear();
+      return Err(ParseError::UriTooLong);
+    }
+    let Some(head_length) = head_length(&self.head[searched..]).map(|length| searched + length)
+    else {
+      if self.head.len() > self.max_head_size {
+        self.head.clear();
+        return Err(ParseError::HeaderFieldsTooLarge(format!(
+          "Request head exceeds {} bytes",
+          self.max_head_size
+        )));
+      }
+      return Ok((Poll::Pending, input.len()));
+    };
+    self.head.truncate(head_length);
+    let head = std::mem::take(&mut self.head);
+    let request = HttpRequest::parse(&head, &self.limits, &self.query_limits)?;
+    self.start_body(&head, &request)?;
+    Ok((Poll::Ready(request.into_owned()), head_length - received))
+  }
+
+  /// The next event at the start of `input` and how many of its bytes that consumed, or
+  /// `None` until enough input has arrived. Consumed bytes must not be passed in again.
+  /// Chunked bodies come out decoded, in as many [`Event::Body`]s as there are pieces of
+  /// chunk data; one is empty where `input` ended in the framing between them.
+  pub fn parse<'buf>(
+    &mut self,
+    input: &'buf [u8],
+  ) -> Result<Option<(Event<'buf>, usize)>, ParseError> {
+    match &mut self.state {
+      State::Head => {
+        let Some(head_length) = head_length(input) else {
+          return Ok(None);
+        };
+        let head = &input[..head_length];
+        let request = HttpRequest::parse(head, &self.limits, &self.query_limits)?;
+        self.start_body(head, &request)?;
+        Ok(Some((Event::Head(Box::new(request)), head_length)))
+      }
+      State::Body { remaining } => {
+        if input.is_empty() {
+          return Ok(None);
+        }
+        let length = input
+          .len()
+          .min(usize::try_from(*remaining).unwrap_or(usize::MAX));
+        self.state = match *remaining - length as u64 {
+          0 => State::End,
+          remaining => State::Body { remaining },
+        };
+        Ok(Some((Event::Body(&input[..length]), length)))
+      }
+      State::Chunked(decoder) => {
+        let (data, consumed) = decoder.next_data(input)?;
+        if decoder.is_done() {
+          // the last chunk and trailers, nothing of the next request
+          self.state = State::Head;
+          return Ok(Some((Event::End, consumed)));
+        }
+        Ok((consumed > 0).then_some((Event::Body(data), consumed)))
+      }
+      State::End => {
+        self.state = State::Head;
+        Ok(Some((Event::End, 0)))
+      }
+    }
+  }
+
+  /// Expect the body `request`, parsed from `head`, announces, if any
+  fn start_body(&mut self, head: &[u8], request: &HttpRequest<'_>) -> Result<(), ParseError> {
+    if is_chunked(head) {
+      let max_size = self
+        .limits
+        .max_body_size
+        .map_or(usize::MAX, |max| usize::try_from(max).unwrap_or(usize::MAX));
+      self.state = State::Chunked(ChunkedDecoder::new(max_size));
+      return Ok(());
+    }
+    let content_length = request.header().content_length()?.unwrap_or(0);
+    self.state = match content_length {
+      0 => State::End,
+      remaining => State::Body { remaining },
+    };
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::http::chunked::ChunkedError;
+  use expectest::prelude::*;
+  use rstest::*;
+
+  #[rstest]
+  #[case::crlf(b"POST / HTTP/1.1\r\nHost: a\r\n\r\nbody", Some(28))]
+  #[case::lf(b"POST / HTTP/1.1\nHost: a\n\nbody", Some(25))]
+  #[case::incomplete(b"POST / HTTP/1.1\r\nHost: a\r\n", None)]
+  fn test_head_length(#[case] buffer: &[u8], #[case] expected: Option<usize>) {
+    expect!(head_length(buffer)).to(be_equal_to(expected));
+  }
+
+  #[rstest]
+  #[case::complete(b"GET /a HTTP/1.1\r\nHost: a\r\n\r\n", 15)]
+  #[case::lf(b"GET /a HTTP/1.1\nHost: a\n\n", 15)]
+  #[case::partial(b"GET /aaaa", 9)]
+  fn test_request_line_length(#[case] buffer: &[u8], #[case] expected: usize) {
+    expect!(request_line_length(buffer)).to(be_equal_to(expected));
+  }
+
+  #[rstest]
+  #[case::present(b"POST / HTTP/1.1\r\ncontent-length: 42\r\n\r\n", Some(42))]
+  #[case::missing(b"GET / HTTP/1.1\r\nHost: a\r\n\r\n", None)]
+  #[case::body_lines_ignored(b"GET / HTTP/1.1\r\n\r\nContent-Length: 1\r\n", None)]
+  #[case::signed(b"POST / HTTP/1.1\r\nContent-Length: +5\r\n\r\n", None)]
+  #[case::list(b"POST / HTTP/1.1\r\nContent-Length: 5, 5\r\n\r\n", None)]
+  fn test_content_length(#[case] head: &[u8], #[case] expected: Option<u64>) {
+    expect!(content_length(head)).to(be_equal_to(expected));
+  }
+
+  #[rstest]
+  #[case::path(b"POST /upload HTTP/1.1\r\nHost: a\r\n\r\n", Some("/upload"))]
+  #[case::query(b"GET /search?q=x HTTP/1.1\r\n\r\n", Some("/search"))]
+  #[case::missing(b"GET\r\n\r\n", None)]
+  fn test_request_path(#[case] head: &[u8], #[case] expected: Option<&str>) {
+    expect!(request_path(head)).to(be_equal_to(expected));
+  }
+
+  #[rstest]
+  #[case::chunked(b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n", true)]
+  #[case::chunked_last(b"POST / HTTP/1.1\r\ntransfer-encoding: gzip, Chunked\r\n\r\n", true)]
+  #[case::other_coding(b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked, gzip\r\n\r\n", false)]
+  #[case::missing(b"POST / HTTP/1.1\r\nContent-Length: 1\r\n\r\n", false)]
+  #[case::last_line(
+    b"POST / HTTP/1.1\r\nTransfer-Encoding: gzip\r\nTransfer-Encoding: chunked\r\n\r\n",
+    true
+  )]
+  #[case::first_line_only(
+    b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\nTransfer-Encoding: gzip\r\n\r\n",
+    false
+  )]
+  fn test_is_chunked(#[case] head: &[u8], #[case] expected: bool) {
+    expect!(is_chunked(head)).to(be_equal_to(expected));
+  }
+
+  #[rstest]
+  #[case::continue_(b"PUT / HTTP/1.1\r\nExpect: 100-Continue\r\n\r\n", Some(Expectation::Continue))]
+  #[case::unsupported(b"PUT / HTTP/1.1\r\nExpect: 200-ok\r\n\r\n", Some(Expectation::Unsupported))]
+  #[case::http_1_0(b"PUT / HTTP/1.0\r\nExpect: 100-continue\r\n\r\n", None)]
+  #[case::missing(b"PUT / HTTP/1.1\r\nContent-Length: 1\r\n\r\n", None)]
+  fn test_expectation(#[case] head: &[u8], #[case] expected: Option<Expectation>) {
+    expect!(expectation(head)).to(be_equal_to(expected));
+  }
+
+  #[rstest]
+  #[case::content_length(b"POST / HTTP/1.1\r\nContent-Length: 5\r\n\r\n", true)]
+  #[case::repeated_content_length(
+    b"POST / HTTP/1.1\r\nContent-Length: 5\r\ncontent-length: 5\r\n\r\n",
+    true
+  )]
+  #[case::conflicting_content_length(
+    b"POST / HTTP/1.1\r\nContent-Length: 5\r\nContent-Length: 6\r\n\r\n",
+    false
+  )]
+  #[case::content_length_list(b"POST / HTTP/1.1\r\nContent-Length: 5, 6\r\n\r\n", false)]
+  #[case::content_length_repeated_in_list(
+    b"POST / HTTP/1.1\r\nContent-Length: 5, 5\r\n\r\n",
+    false
+  )]
+  #[case::content_length_not_a_number(b"POST / HTTP/1.1\r\nContent-Length: abc\r\n\r\n", false)]
+  #[case::content_length_signed(b"POST / HTTP/1.1\r\nContent-Length: +5\r\n\r\n", false)]
+  #[case::chunked(b"POST / HTTP/1.1\r\nTransfer-Encoding: gzip, chunked\r\n\r\n", true)]
+  #[case::not_ending_in_chunked(b"POST / HTTP/1.1\r\nTransfer-Encoding: xchunked\r\n\r\n", false)]
+  #[case::chunked_then_other(b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked, gzip\r\n\r\n", false)]
+  #[case::repeated_transfer_encoding(
+    b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\nTransfer-Encoding: identity\r\n\r\n",
+    false
+  )]
+  #[case::transfer_encoding_continued(
+    b"POST / HTTP/1.1\r\nTransfer-Encoding: gzip\r\nTransfer-Encoding: chunked\r\n\r\n",
+    true
+  )]
+  #[case::both(
+    b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\nContent-Length: 5\r\n\r\n",
+    false
+  )]
+  #[case::obs_fold(b"GET / HTTP/1.1\r\nX-Folded: a\r\n b\r\n\r\n", false)]
+  #[case::body_lines_ignored(
+    b"POST / HTTP/1.1\r\nContent-Length: 5\r\n\r\n Transfer-Encoding: x",
+    true
+  )]
+  fn test_check_framing(#[case] head: &[u8], #[case] valid: bool) {
+    expect!(check_framing(head).is_ok()).to(be_equal_to(valid));
+  }
+
+  /// Feed `input` `piece_size` bytes at a time, describing the events as they come
+  fn events(input: &[u8], piece_size: usize) -> Result<Vec<String>, ParseError> {
+    let mut parser = RequestParser::default();
+    let mut events = Vec::new();
+    let (mut consumed, mut received) = (0, 0);
+    loop {
+      match parser.parse(&input[consumed..received])? {
+        Some((event, length)) => {
+          consumed += length;
+          events.push(match event {
+            Event::Head(request) => format!("head {} {}", request.method(), request.path()),
+            Event::Body(body) => format!("body {}", String::from_utf8_lossy(body)),
+            Event::End => "end".to_string(),
+          });
+        }
+        None if received < input.len() => {
+          received = input.len().min(received.saturating_add(piece_size))
+        }
+        None => return Ok(events),
+      }
+    }
+  }
+
+  #[rstest]
+  #[case::whole(usize::MAX)]
+  #[case::byte_by_byte(1)]
+  #[case::pieces(7)]
+  fn test_parse_pipelined_requests(
+    #[case] piece_size: usize,
+    #[values(
+      &b"POST /a HTTP/1.1\r\nHost: a\r\nContent-Length: 5\r\n\r\nhelloGET /b HTTP/1.1\r\nHost: a\r\n\r\n"[..],
+      // the chunk data mustn't be taken for the next request
+      &b"POST /a HTTP/1.1\r\nHost: a\r\nTransfer-Encoding: chunked\r\n\r\n2\r\nhe\r\n3;ext=1\r\nllo\r\n0\r\nX-Trailer: t\r\n\r\nGET /b HTTP/1.1\r\nHost: a\r\n\r\n"[..]
+    )]
+    input: &[u8],
+  ) -> Result<(), ParseError> {
+    let events = events(input, piece_size)?;
+
+    let body = events
+      .iter()
+      .filter_map(|event| event.strip_prefix("body "))
+      .collect::<String>();
+    expect!(body).to(be_equal_to("hello"));
+    let events = events
+      .into_iter()
+      .filter(|event| !event.starts_with("body "))
+      .collect::<Vec<_>>();
+    expect!(events).to(be_equal_to(vec![
+      "head POST /a".to_string(),
+      "end".to_string(),
+      "head GET /b".to_string(),
+      "end".to_string(),
+    ]));
+    Ok(())
+  }
+
+  #[rstest]
+  #[case::whole(usize::MAX)]
+  #[case::byte_by_byte(1)]
+  #[case::pieces(3)]
+  fn test_advance_through_pieces(#[case] piece_size: usize) -> Result<(), ParseError> {
+    let input = b"POST /a HTTP/1.1\r\nHost: a\r\nContent-Length: 2\r\n\r\nhiGET /b HTTP/1.1\r\nHost: a\r\n\r\n";
+    let mut parser = RequestParser::default();
+    let mut requests = Vec::new();
+    let mut offset = 0;
+    while offset < input.len() {
+      let piece = &input[offset..input.len().min(offset.saturating_add(piece_size))];
+      let (request, consumed) = parser.advance(piece)?;
+      offset += consumed;
+      let Poll::Ready(request) = request else {
+        continue;
+      };
+      requests.push(format!("{} {}", request.method(), request.path()));
+      // the body goes through parse, as with heads it found itself
+      while let Some((event, consumed)) = parser.parse(&input[offset..])? {
+        offset += consumed;
+        if matches!(event, Event::End) {
+          break;
+        }
+      }
+    }
+    expect!(requests).to(be_equal_to(vec![
+      "POST /a".to_string(),
+      "GET /b".to_string(),
+    ]));
+    Ok(())
+  }
+
+  #[rstest]
+  #[case::whole(usize::MAX)]
+  #[case::byte_by_byte(1)]
+  #[case::pieces(3)]
+  fn test_advance_past_chunked_body(#[case] piece_size: usize) -> Result<(), ParseError> {
+    let input = b"POST /a HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhello\r\n6\r\n world\r\n0\r\n\r\nGET /b HTTP/1.1\r\nHost: a\r\n\r\n";
+    let mut parser = RequestParser::default();
+    let (mut requests, mut body) = (Vec::new(), Vec::new());
+    let (mut offset, mut received, mut in_body) = (0, 0, false);
+    while offset < input.len() {
+      if offset == received {
+        received = input.len().min(received.saturating_add(piece_size));
+      }
+      let pending = &input[offset..received];
+      if !in_body {
+        let (request, consumed) = parser.advance(pending)?;
+        offset += consumed;
+        if let Poll::Ready(request) = request {
+          requests.push(format!("{} {}", request.method(), request.path()));
+          in_body = true;
+        }
+        continue;
+      }
+      // heads wait while the body goes through parse, a piece at a time
+      expect!(parser.advance(pending)?.1).to(be_equal_to(0));
+      match parser.parse(pending)? {
+        Some((Event::Body(data), consumed)) => {
+          body.extend_from_slice(data);
+          offset += consumed;
+        }
+        Some((Event::End, consumed)) => {
+          offset += consumed;
+          in_body = false;
+        }
+        Some((Event::Head(_), _)) => unreachable!("heads are left to advance"),
+        None => received = input.len().min(received.saturating_add(piece_size)),
+      }
+    }
+    expect!(requests).to(be_equal_to(vec![
+      "POST /a".to_string(),
+      "GET /b".to_string(),
+    ]));
+    expect!(body).to(be_equal_to(b"hello world".to_vec()));
+    Ok(())
+  }
+
+  #[rstest]
+  #[case::request_line_too_long(b"GET /aaaaaaaaaaaaaaaaaaaa", ParseError::UriTooLong)]
+  #[case::head_too_large(
+    b"GET / HTTP/1.1\r\nX-Padding: aaaaaaaaaaaaaaaaaaaa",
+    ParseError::HeaderFieldsTooLarge("Request head exceeds 32 bytes".to_string())
+  )]
+  fn test_advance_limits(#[case] input: &[u8], #[case] expected: ParseError) {
+    let limits = ParserLimits { max_request_line_length: 16, ..ParserLimits::default() };
+    let mut parser = RequestParser::new(limits, QueryLimits::default()).max_head_size(32);
+    let errors = input
+      .chunks(8)
+      .find_map(|piece| parser.advance(piece).err());
+    expect!(errors).to(be_some().value(expected));
+  }
+
+  #[rstest]
+  fn test_reject_chunked_body_too_large() {
+    let limits = ParserLimits { max_body_size: Some(4), ..ParserLimits::default() };
+    let mut parser = RequestParser::new(limits, QueryLimits::default());
+    let input = b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhello\r\n0\r\n\r\n";
+    let (_, consumed) = parser.parse(input).unwrap().unwrap();
+    let result = parser.parse(&input[consumed..]);
+    expect!(result.err())
+      .to(be_some().value(ParseError::InvalidChunkedBody(ChunkedError::TooLarge(4))));
+  }
+
+  #[rstest]
+  fn test_reject_invalid_content_length() {
+    let mut parser = RequestParser::default();
+    let result = parser.parse(b"POST / HTTP/1.1\r\nHost: a\r\nContent-Length: -1\r\n\r\n");
+    expect!(result.is_err()).to(be_true());
+  }
+}
@@ -0,0 +1,21 @@
+use std::net::SocketAddr;
+
+/// What the server knows about the connection a request came on, for handlers that log, rate
+/// limit or allow clients by address, see [`crate::http::HttpRequest::connection`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConnectionInfo {
+  /// the client's address, unspecified for transports without addresses
+  pub peer_addr: SocketAddr,
+  /// the server's end of the connection, the address the client connected to; `None` for
+  /// transports without addresses
+  pub local_addr: Option<SocketAddr>,
+  /// whether the connection is encrypted with TLS
+  pub secure: bool,
+}
+
+impl ConnectionInfo {
+  /// A plain connection from `peer_addr`, to an unknown local address
+  pub fn new(peer_addr: SocketAddr) -> Self {
+    Self { peer_addr, local_addr: None, secure: false }
+  }
+}
@@ -0,0 +1,117 @@
+//! `Content-Encoding`s negotiated through `Accept-Encoding` (RFC 9110, section 12.5.3), for
+//! compressing responses on the fly with the `compression` feature and for serving files
+//! compressed ahead of time
+
+/// The codings responses can be compressed with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ContentCoding {
+  Brotli,
+  Zstd,
+  Gzip,
+  Deflate,
+}
+
+impl ContentCoding {
+  /// Best first, picked among the ones a client accepts equally
+  pub const PREFERRED: [Self; 4] = [Self::Brotli, Self::Zstd, Self::Gzip, Self::Deflate];
+
+  pub fn as_str(&self) -> &'static str {
+    match self {
+      Self::Brotli => "br",
+      Self::Zstd => "zstd",
+      Self::Gzip => "gzip",
+      Self::Deflate => "deflate",
+    }
+  }
+
+  /// The extension of files compressed with it ahead of time, e.g. `app.js.br`. Deflate has
+  /// no common one.
+  pub fn file_extension(&self) -> Option<&'static str> {
+    match self {
+      Self::Brotli => Some("br"),
+      Self::Zstd => Some("zst"),
+      Self::Gzip => Some("gz"),
+      Self::Deflate => None,
+    }
+  }
+
+  /// The codings a request's `Accept-Encoding` takes, highest quality first and by
+  /// preference among equals. Empty if the client takes none of them, e.g. only `identity`
+  /// or everything at `q=0`.
+  pub fn accepted(accept_encoding: &str) -> Vec<Self> {
+    let accepted = accept_encoding
+      .split(',')
+      .filter_map(|item| {
+        let mut parameters = item.split(';');
+        let coding = parameters.next()?.trim().to_lowercase();
+        let quality = parameters
+          .filter_map(|parameter| parameter.split_once('='))
+          .find(|(name, _)| name.trim().eq_ignore_ascii_case("q"))
+          .map_or(1.0, |(_, quality)| {
+            quality.trim().parse::<f32>().unwrap_or(0.0)
+          });
+        Some((coding, quality))
+      })
+      .collect::<Vec<_>>();
+    let quality = |coding: Self| {
+      let alias = match coding {
+        Self::Gzip => "x-gzip",
+        _ => coding.as_str(),
+      };
+      let quality_of = |name: &str| {
+        accepted
+          .iter()
+          .find(|(accepted, _)| accepted == name)
+          .map(|(_, quality)| *quality)
+      };
+      quality_of(coding.as_str())
+        .or_else(|| quality_of(alias))
+        .or_else(|| quality_of("*"))
+        .unwrap_or(0.0)
+    };
+
+    let mut codings = Self::PREFERRED
+      .into_iter()
+      .map(|coding| (coding, quality(coding)))
+      .filter(|(_, quality)| *quality > 0.0)
+      .collect::<Vec<_>>();
+    // stable, so equals stay in order of preference
+    codings.sort_by(|(_, a), (_, b)| b.total_cmp(a));
+    codings.into_iter().map(|(coding, _)| coding).collect()
+  }
+
+  /// The coding to use for a request's `Accept-Encoding`, the first of [`Self::accepted`]
+  pub fn negotiate(accept_encoding: &str) -> Option<Self> {
+    Self::accepted(accept_encoding).into_iter().next()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use expectest::prelude::*;
+  use rstest::*;
+
+  #[rstest]
+  #[case::single("gzip", Some(ContentCoding::Gzip))]
+  #[case::preferred("gzip, deflate, br, zstd", Some(ContentCoding::Brotli))]
+  #[case::by_quality("br;q=0.5, gzip;q=0.8, zstd;q=0.1", Some(ContentCoding::Gzip))]
+  #[case::refused("br;q=0, gzip", Some(ContentCoding::Gzip))]
+  #[case::alias("x-gzip", Some(ContentCoding::Gzip))]
+  #[case::wildcard("*;q=0.5, br;q=0", Some(ContentCoding::Zstd))]
+  #[case::identity_only("identity", None)]
+  #[case::nothing("", None)]
+  fn test_negotiate(#[case] accept_encoding: &str, #[case] expected: Option<ContentCoding>) {
+    expect!(ContentCoding::negotiate(accept_encoding)).to(be_equal_to(expected));
+  }
+
+  #[rstest]
+  fn test_accepted_in_order() {
+    let accepted = ContentCoding::accepted("gzip;q=0.8, deflate;q=0.8, br, zstd;q=0");
+    expect!(accepted).to(be_equal_to(vec![
+      ContentCoding::Brotli,
+      ContentCoding::Gzip,
+      ContentCoding::Deflate,
+    ]));
+  }
+}
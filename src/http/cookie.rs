@@ -0,0 +1,298 @@
+//! Cookies (RFC 6265): those a request carries in its `Cookie` header, see
+//! [`super::HttpRequest::cookies`], and `Set-Cookie` fields for responses, see
+//! [`super::HttpResponse::set_cookie`].
+
+use std::{fmt, time::Duration};
+use thiserror::Error;
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum CookieError {
+  #[error("Cookie name {0:?} isn't a token")]
+  InvalidName(String),
+  #[error("Value of cookie {0} has characters cookies can't carry")]
+  InvalidValue(String),
+  #[error("Attribute {0} of cookie {1} has control characters or a ;")]
+  InvalidAttribute(&'static str, String),
+}
+
+/// A cookie the client sent, borrowed from the request's `Cookie` header
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cookie<'a> {
+  name: &'a str,
+  value: &'a str,
+}
+
+impl<'a> Cookie<'a> {
+  pub fn name(&self) -> &'a str {
+    self.name
+  }
+
+  /// Without the double quotes the value may be wrapped in
+  pub fn value(&self) -> &'a str {
+    self.value
+  }
+}
+
+/// The cookies of a request in the order they were sent. Browsers don't send a cookie's
+/// path or domain, so the same name can occur more than once, most specific path first.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CookieJar<'a> {
+  cookies: Vec<Cookie<'a>>,
+}
+
+impl<'a> CookieJar<'a> {
+  /// Parse a `Cookie` header value, `name=value` pairs separated by `;`. Pairs without a
+  /// name are skipped rather than failing the whole header.
+  ///
+  /// ```
+  /// use udemy_server::http::cookie::CookieJar;
+  ///
+  /// let jar = CookieJar::parse("session=abc123; theme=\"dark\"");
+  /// assert_eq!(jar.get("theme").map(|cookie| cookie.value()), Some("dark"));
+  /// assert!(jar.get("missing").is_none());
+  /// ```
+  pub fn parse(header: &'a str) -> Self {
+    let cookies = header
+      .split(';')
+      .filter_map(|pair| {
+        let (name, value) = pair.split_once('=')?;
+        let name = name.trim();
+        let value = value.trim();
+        let value = value
+          .strip_prefix('"')
+          .and_then(|value| value.strip_suffix('"'))
+          .unwrap_or(value);
+        (!name.is_empty()).then_some(Cookie { name, value })
+      })
+      .collect();
+    Self { cookies }
+  }
+
+  /// The first cookie called `name`
+  pub fn get(&self, name: &str) -> Option<&Cookie<'a>> {
+    self.cookies.iter().find(|cookie| cookie.name == name)
+  }
+
+  pub fn iter(&self) -> impl Iterator<Item = &Cookie<'a>> {
+    self.cookies.iter()
+  }
+
+  pub fn len(&self) -> usize {
+    self.cookies.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.cookies.is_empty()
+  }
+}
+
+/// Whether browsers send a cookie along with requests started by other sites
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SameSite {
+  Strict,
+  Lax,
+  /// Browsers only accept this on [`SetCookie::secure`] cookies
+  None,
+}
+
+impl fmt::Display for SameSite {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let same_site = match self {
+      Self::Strict => "Strict",
+      Self::Lax => "Lax",
+      Self::None => "None",
+    };
+    write!(f, "{}", same_site)
+  }
+}
+
+/// A `Set-Cookie` field, e.g. for a session:
+///
+/// ```
+/// use std::time::Duration;
+/// use udemy_server::http::cookie::{SameSite, SetCookie};
+///
+/// let cookie = SetCookie::new("session", "abc123")
+///   .path("/")
+///   .max_age(Duration::from_secs(3600))
+///   .secure()
+///   .http_only()
+///   .same_site(SameSite::Lax);
+/// assert_eq!(
+///   cookie.header_value().unwrap(),
+///   "session=abc123; Path=/; Max-Age=3600; Secure; HttpOnly; SameSite=Lax"
+/// );
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SetCookie {
+  name: String,
+  value: String,
+  path: Option<String>,
+  domain: Option<String>,
+  max_age: Option<Duration>,
+  secure: bool,
+  http_only: bool,
+  same_site: Option<SameSite>,
+}
+
+impl SetCookie {
+  pub fn new(name: &str, value: &str) -> Self {
+    Self {
+      name: name.to_string(),
+      value: value.to_string(),
+      path: None,
+      domain: None,
+      max_age: None,
+      secure: false,
+      http_only: false,
+      same_site: None,
+    }
+  }
+
+  /// Tells the browser to drop the cookie called `name`; `path` and `domain` have to match
+  /// the ones it was set with
+  pub fn removal(name: &str) -> Self {
+    Self::new(name, "").max_age(Duration::ZERO)
+  }
+
+  /// Only sent with requests for `path` and the paths below it
+  pub fn path(mut self, path: &str) -> Self {
+    self.path = Some(path.to_string());
+    self
+  }
+
+  /// Also sent to the subdomains of `domain`, rather than only to the host that set it
+  pub fn domain(mut self, domain: &str) -> Self {
+    self.domain = Some(domain.to_string());
+    self
+  }
+
+  /// Kept for `max_age` rather than until the browser closes, in whole seconds
+  pub fn max_age(mut self, max_age: Duration) -> Self {
+    self.max_age = Some(max_age);
+    self
+  }
+
+  /// Only sent over HTTPS
+  pub fn secure(mut self) -> Self {
+    self.secure = true;
+    self
+  }
+
+  /// Hidden from the page's scripts
+  pub fn http_only(mut self) -> Self {
+    self.http_only = true;
+    self
+  }
+
+  pub fn same_site(mut self, same_site: SameSite) -> Self {
+    self.same_site = Some(same_site);
+    self
+  }
+
+  /// The field value, refusing names, values and attributes that would break out of it
+  pub fn header_value(&self) -> Result<String, CookieError> {
+    if self.name.is_empty() || !self.name.bytes().all(is_token_byte) {
+      return Err(CookieError::InvalidName(self.name.clone()));
+    }
+    if !self.value.bytes().all(is_cookie_octet) {
+      return Err(CookieError::InvalidValue(self.name.clone()));
+    }
+
+    let mut value = format!("{}={}", self.name, self.value);
+    for (attribute, attribute_value) in [("Path", &self.path), ("Domain", &self.domain)] {
+      let Some(attribute_value) = attribute_value else {
+        continue;
+      };
+      if attribute_value
+        .bytes()
+        .any(|byte| byte == b';' || byte.is_ascii_control())
+      {
+        return Err(CookieError::InvalidAttribute(attribute, self.name.clone()));
+      }
+      value.push_str(&format!("; {}={}", attribute, attribute_value));
+    }
+    if let Some(max_age) = self.max_age {
+      value.push_str(&format!("; Max-Age={}", max_age.as_secs()));
+    }
+    if self.secure {
+      value.push_str("; Secure");
+    }
+    if self.http_only {
+      value.push_str("; HttpOnly");
+    }
+    if let Some(same_site) = self.same_site {
+      value.push_str(&format!("; SameSite={}", same_site));
+    }
+    Ok(value)
+  }
+}
+
+/// `tchar` of RFC 9110, section 5.6.2
+fn is_token_byte(byte: u8) -> bool {
+  byte.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&byte)
+}
+
+/// `cookie-octet` of RFC 6265, section 4.1.1: printable ASCII but for `"`, `,`, `;` and `\`
+fn is_cookie_octet(byte: u8) -> bool {
+  matches!(byte, 0x21 | 0x23..=0x2B | 0x2D..=0x3A | 0x3C..=0x5B | 0x5D..=0x7E)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use expectest::prelude::*;
+  use rstest::*;
+
+  #[rstest]
+  #[case::single("session=abc", vec![("session", "abc")])]
+  #[case::several("a=1; b=2;c=3", vec![("a", "1"), ("b", "2"), ("c", "3")])]
+  #[case::quoted("theme=\"dark\"", vec![("theme", "dark")])]
+  #[case::empty_value("flag=", vec![("flag", "")])]
+  #[case::equals_in_value("token=a=b", vec![("token", "a=b")])]
+  #[case::malformed_pairs("junk; =x; a=1;", vec![("a", "1")])]
+  #[case::duplicate_names("id=1; id=2", vec![("id", "1"), ("id", "2")])]
+  fn test_parse(#[case] header: &str, #[case] expected: Vec<(&str, &str)>) {
+    let jar = CookieJar::parse(header);
+    let cookies = jar
+      .iter()
+      .map(|cookie| (cookie.name(), cookie.value()))
+      .collect::<Vec<_>>();
+    expect!(cookies).to(be_equal_to(expected));
+  }
+
+  #[rstest]
+  fn test_get_first_of_name() {
+    let jar = CookieJar::parse("id=1; theme=dark; id=2");
+    expect!(jar.get("id").map(Cookie::value)).to(be_some().value("1"));
+    expect!(jar.get("ID")).to(be_none());
+    expect!(jar.len()).to(be_equal_to(3));
+  }
+
+  #[rstest]
+  #[case::plain(SetCookie::new("a", "1"), "a=1")]
+  #[case::domain(SetCookie::new("a", "1").domain("example.com"), "a=1; Domain=example.com")]
+  #[case::removal(SetCookie::removal("session").path("/"), "session=; Path=/; Max-Age=0")]
+  #[case::same_site_none(
+    SetCookie::new("a", "1").secure().same_site(SameSite::None),
+    "a=1; Secure; SameSite=None"
+  )]
+  fn test_header_value(#[case] cookie: SetCookie, #[case] expected: &str) {
+    expect!(cookie.header_value()).to(be_ok().value(expected.to_string()));
+  }
+
+  #[rstest]
+  #[case::empty_name(SetCookie::new("", "1"), CookieError::InvalidName("".to_string()))]
+  #[case::name_with_space(SetCookie::new("a b", "1"), CookieError::InvalidName("a b".to_string()))]
+  #[case::value_with_semicolon(
+    SetCookie::new("a", "1; Domain=evil.example"),
+    CookieError::InvalidValue("a".to_string())
+  )]
+  #[case::path_with_semicolon(
+    SetCookie::new("a", "1").path("/; Secure"),
+    CookieError::InvalidAttribute("Path", "a".to_string())
+  )]
+  fn test_reject_invalid(#[case] cookie: SetCookie, #[case] expected: CookieError) {
+    expect!(cookie.header_value()).to(be_err().value(expected));
+  }
+}
@@ -0,0 +1,258 @@
+//! Server-Sent Events (`text/event-stream`), see [`super::HttpResponse::event_stream`]
+
+use std::{
+  io,
+  pin::Pin,
+  task::{Context, Poll},
+  time::Duration,
+};
+use thiserror::Error;
+use tokio::{
+  io::{AsyncRead, ReadBuf},
+  sync::mpsc,
+  time::{self, Instant, Interval, MissedTickBehavior},
+};
+
+/// How long a stream may go without events before a comment is sent, unless configured
+/// otherwise: proxies close idle connections, and disconnected clients are only noticed when
+/// writing to them
+pub const DEFAULT_KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(15);
+/// Events queued for a client before [`EventSender::send`] waits for it to catch up
+const EVENTS_BUFFERED: usize = 16;
+/// Keeps the connection busy without dispatching an event
+const KEEP_ALIVE_COMMENT: &str = ":\n\n";
+
+/// A single event, e.g. `Event::new("42").event("price").id("7")`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Event {
+  data: String,
+  event: Option<String>,
+  id: Option<String>,
+  retry: Option<Duration>,
+}
+
+impl Event {
+  pub fn new<D: Into<String>>(data: D) -> Self {
+    Self { data: data.into(), ..Self::default() }
+  }
+
+  /// The type clients listen for with `addEventListener`, `message` unless set
+  pub fn event<E: Into<String>>(mut self, event: E) -> Self {
+    self.event = Some(event.into());
+    self
+  }
+
+  /// Sent back by reconnecting clients in `Last-Event-ID`
+  pub fn id<I: Into<String>>(mut self, id: I) -> Self {
+    self.id = Some(id.into());
+    self
+  }
+
+  /// How long clients wait before reconnecting once the stream ends
+  pub fn retry(mut self, retry: Duration) -> Self {
+    self.retry = Some(retry);
+    self
+  }
+
+  /// The event as it's sent, ending in a blank line. Every line of `data` becomes a field of
+  /// its own, line breaks in the event type or ID are dropped, as they'd end the field early.
+  pub fn format(&self) -> String {
+    let single_line = |value: &str| value.replace(['\r', '\n'], "");
+    let mut formatted = String::new();
+    if let Some(event) = &self.event {
+      formatted.push_str(&format!("event: {}\n", single_line(event)));
+    }
+    if let Some(id) = &self.id {
+      // clients ignore IDs containing NUL
+      formatted.push_str(&format!("id: {}\n", single_line(id).replace('\0', "")));
+    }
+    if let Some(retry) = self.retry {
+      formatted.push_str(&format!("retry: {}\n", retry.as_millis()));
+    }
+    for line in self.data.split('\n') {
+      formatted.push_str(&format!(
+        "data: {}\n",
+        line.strip_suffix('\r').unwrap_or(line)
+      ));
+    }
+    formatted.push('\n');
+    formatted
+  }
+}
+
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
+#[error("The client is no longer connected to the event stream")]
+pub struct Disconnected;
+
+/// Pushes events to one client. The stream ends once every sender is dropped; sending fails
+/// with [`Disconnected`] once the client is gone.
+#[derive(Debug, Clone)]
+pub struct EventSender {
+  sender: mpsc::Sender<String>,
+}
+
+impl EventSender {
+  /// Waits while the client is behind on reading
+  pub async fn send(&self, event: Event) -> Result<(), Disconnected> {
+    self
+      .sender
+      .send(event.format())
+      .await
+      .map_err(|_| Disconnected)
+  }
+
+  /// Like [`Self::send`] from blocking code, e.g. [`crate::server::Handler::handle_with_body`]
+  pub fn blocking_send(&self, event: Event) -> Result<(), Disconnected> {
+    self
+      .sender
+      .blocking_send(event.format())
+      .map_err(|_| Disconnected)
+  }
+
+  pub fn is_closed(&self) -> bool {
+    self.sender.is_closed()
+  }
+
+  /// Completes once the client is gone, so producers can stop
+  pub async fn closed(&self) {
+    self.sender.closed().await
+  }
+}
+
+/// The body of an event stream, the formatted events as they're sent
+pub(crate) struct EventReader {
+  receiver: mpsc::Receiver<String>,
+  pending: Vec<u8>,
+  position: usize,
+  keep_alive: Option<Interval>,
+}
+
+impl EventReader {
+  pub(crate) fn channel(keep_alive: Option<Duration>) -> (Self, EventSender) {
+    let (sender, receiver) = mpsc::channel(EVENTS_BUFFERED);
+    let keep_alive = keep_alive.map(|period| {
+      let mut interval = time::interval_at(Instant::now() + period, period);
+      interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+      interval
+    });
+    let reader = Self { receiver, pending: Vec::new(), position: 0, keep_alive };
+    (reader, EventSender { sender })
+  }
+
+  fn next_pending(&mut self, cx: &mut Context<'_>) -> Poll<Option<String>> {
+    if let Poll::Ready(event) = self.receiver.poll_recv(cx) {
+      if let Some(keep_alive) = self.keep_alive.as_mut() {
+        keep_alive.reset();
+      }
+      return Poll::Ready(event);
+    }
+    match self
+      .keep_alive
+      .as_mut()
+      .map(|keep_alive| keep_alive.poll_tick(cx))
+    {
+      Some(Poll::Ready(_)) => Poll::Ready(Some(KEEP_ALIVE_COMMENT.to_string())),
+      _ => Poll::Pending,
+    }
+  }
+}
+
+impl AsyncRead for EventReader {
+  /// Ends once every [`EventSender`] is dropped
+  fn poll_read(
+    mut self: Pin<&mut Self>,
+    cx: &mut Context<'_>,
+    buf: &mut ReadBuf<'_>,
+  ) -> Poll<io::Result<()>> {
+    if self.position == self.pending.len() {
+      match self.next_pending(cx) {
+        Poll::Ready(Some(pending)) => {
+          self.pending = pending.into_bytes();
+          self.position = 0;
+        }
+        Poll::Ready(None) => return Poll::Ready(Ok(())),
+        Poll::Pending => return Poll::Pending,
+      }
+    }
+    let available = &self.pending[self.position..];
+    let copied = available.len().min(buf.remaining());
+    buf.put_slice(&available[..copied]);
+    self.position += copied;
+    Poll::Ready(Ok(()))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::http::{header::HttpResponseHeaderKey, HttpResponse, StatusCode};
+  use expectest::prelude::*;
+  use rstest::*;
+  use tokio::io::AsyncReadExt;
+
+  #[rstest]
+  #[case::data_only(Event::new("hello"), "data: hello\n\n")]
+  #[case::multi_line(Event::new("a\r\nb\nc"), "data: a\ndata: b\ndata: c\n\n")]
+  #[case::all_fields(
+    Event::new("{}").event("update").id("7").retry(Duration::from_secs(3)),
+    "event: update\nid: 7\nretry: 3000\ndata: {}\n\n"
+  )]
+  #[case::injected_field(Event::new("").event("a\ndata: b"), "event: adata: b\ndata: \n\n")]
+  fn test_format_event(#[case] event: Event, #[case] expected: &str) {
+    expect!(event.format().as_str()).to(be_equal_to(expected));
+  }
+
+  #[rstest]
+  #[tokio::test]
+  async fn test_stream_events() -> io::Result<()> {
+    let (mut response, sender) = HttpResponse::event_stream();
+    let header = response.http_header().as_ref().unwrap();
+    expect!(header
+      .get(HttpResponseHeaderKey::ContentType)
+      .map(String::as_str))
+    .to(be_some().value("text/event-stream"));
+    expect!(*response.status_code()).to(be_equal_to(StatusCode::Ok));
+
+    let (mut client, mut connection) = tokio::io::duplex(4096);
+    let sent = tokio::spawn(async move { response.send(&mut connection).await });
+    tokio::spawn(async move {
+      sender.send(Event::new("first")).await?;
+      sender.send(Event::new("second").id("2")).await
+    });
+
+    let mut received = String::new();
+    client.read_to_string(&mut received).await?;
+    sent.await??;
+    expect!(received.contains("Transfer-Encoding: chunked\r\n")).to(be_true());
+    let body = received.split_once("\r\n\r\n").unwrap().1;
+    expect!(body).to(be_equal_to(
+      "D\r\ndata: first\n\n\r\n14\r\nid: 2\ndata: second\n\n\r\n0\r\n\r\n",
+    ));
+    Ok(())
+  }
+
+  #[rstest]
+  #[tokio::test(start_paused = true)]
+  async fn test_keep_alive_notices_disconnects() -> io::Result<()> {
+    let (mut response, sender) = HttpResponse::event_stream_with(Some(Duration::from_secs(1)));
+    let (mut client, mut connection) = tokio::io::duplex(4096);
+    let sent = tokio::spawn(async move { response.send(&mut connection).await });
+
+    // the head right away, a comment once the stream was idle for a second
+    let mut received = Vec::new();
+    while !String::from_utf8_lossy(&received).contains(":\n\n") {
+      let mut chunk = [0; 1024];
+      let read = client.read(&mut chunk).await?;
+      received.extend_from_slice(&chunk[..read]);
+    }
+    expect!(String::from_utf8_lossy(&received).starts_with("HTTP/1.1 200 Ok\r\n")).to(be_true());
+
+    // noticed with the next comment, which can't be written anymore
+    drop(client);
+    sender.closed().await;
+    let sent = sent.await?;
+    expect!(sent.map_err(|e| e.kind())).to(be_err().value(io::ErrorKind::BrokenPipe));
+    expect!(sender.send(Event::new("late")).await).to(be_err().value(Disconnected));
+    Ok(())
+  }
+}
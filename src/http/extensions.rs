@@ -0,0 +1,93 @@
+use std::{
+  any::{Any, TypeId},
+  collections::HashMap,
+  fmt,
+  sync::Arc,
+};
+
+/// Values of any type attached to a request, one per type, so middleware can hand what it
+/// worked out (a tenant, a quota, a parsed token) to the handlers behind it without another
+/// field on [`crate::http::HttpRequest`]. A crate-private type as key keeps a middleware's
+/// values apart from everyone else's.
+///
+/// Values are shared rather than copied when the request is cloned, as middleware does to
+/// attach them.
+#[derive(Clone, Default)]
+pub struct Extensions {
+  values: HashMap<TypeId, Arc<dyn Any + Send + Sync>>,
+}
+
+impl Extensions {
+  /// Attach `value`, replacing the one of the same type
+  pub fn insert<T: Send + Sync + 'static>(&mut self, value: T) {
+    self.values.insert(TypeId::of::<T>(), Arc::new(value));
+  }
+
+  pub fn get<T: Send + Sync + 'static>(&self) -> Option<&T> {
+    self
+      .values
+      .get(&TypeId::of::<T>())
+      .and_then(|value| value.downcast_ref())
+  }
+
+  pub fn contains<T: Send + Sync + 'static>(&self) -> bool {
+    self.values.contains_key(&TypeId::of::<T>())
+  }
+
+  /// Detach the value of type `T`, shared with the clones of the request made before
+  pub fn remove<T: Send + Sync + 'static>(&mut self) -> Option<Arc<T>> {
+    self
+      .values
+      .remove(&TypeId::of::<T>())
+      .and_then(|value| value.downcast().ok())
+  }
+
+  pub fn len(&self) -> usize {
+    self.values.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.values.is_empty()
+  }
+}
+
+/// Values are opaque, so only their number is shown
+impl fmt::Debug for Extensions {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_struct("Extensions")
+      .field("len", &self.values.len())
+      .finish()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use expectest::prelude::*;
+  use rstest::*;
+
+  #[derive(Debug, PartialEq)]
+  struct Tenant(&'static str);
+
+  #[derive(Debug, PartialEq)]
+  struct Quota(u32);
+
+  #[rstest]
+  fn test_values_by_type() {
+    let mut extensions = Extensions::default();
+    extensions.insert(Tenant("acme"));
+    extensions.insert(Quota(10));
+    extensions.insert(Quota(9));
+
+    expect!(extensions.get::<Tenant>()).to(be_some().value(&Tenant("acme")));
+    expect!(extensions.get::<Quota>()).to(be_some().value(&Quota(9)));
+    expect!(extensions.get::<String>()).to(be_none());
+    expect!(extensions.len()).to(be_equal_to(2));
+
+    let clone = extensions.clone();
+    expect!(extensions.remove::<Tenant>().as_deref()).to(be_some().value(&Tenant("acme")));
+    expect!(extensions.contains::<Tenant>()).to(be_false());
+    // clones keep what was attached when they were made
+    expect!(clone.get::<Tenant>()).to(be_some().value(&Tenant("acme")));
+  }
+}
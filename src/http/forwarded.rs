@@ -0,0 +1,318 @@
+use serde::Deserialize;
+use std::{
+  fmt,
+  net::{IpAddr, Ipv4Addr, Ipv6Addr},
+  str::FromStr,
+};
+use thiserror::Error;
+
+use super::header::{HttpHeader, HttpRequestHeaderKey};
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+#[error("Invalid IP range {0:?}, expected an address like 10.0.0.1 or a block like 10.0.0.0/8")]
+pub struct InvalidIpRange(String);
+
+/// An address, or a CIDR block of them such as `10.0.0.0/8` or `fd00::/8`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct IpRange {
+  network: IpAddr,
+  prefix_len: u8,
+}
+
+impl IpRange {
+  pub fn contains(&self, ip: IpAddr) -> bool {
+    // IPv4 clients of dual-stack sockets show up as ::ffff:10.0.0.1
+    match (self.network, ip.to_canonical()) {
+      (IpAddr::V4(network), IpAddr::V4(ip)) => mask_v4(ip, self.prefix_len) == u32::from(network),
+      (IpAddr::V6(network), IpAddr::V6(ip)) => mask_v6(ip, self.prefix_len) == u128::from(network),
+      _ => false,
+    }
+  }
+}
+
+fn mask_v4(ip: Ipv4Addr, prefix_len: u8) -> u32 {
+  u32::from(ip)
+    .checked_shr(32 - u32::from(prefix_len))
+    .map_or(0, |network| network << (32 - u32::from(prefix_len)))
+}
+
+fn mask_v6(ip: Ipv6Addr, prefix_len: u8) -> u128 {
+  u128::from(ip)
+    .checked_shr(128 - u32::from(prefix_len))
+    .map_or(0, |network| network << (128 - u32::from(prefix_len)))
+}
+
+impl FromStr for IpRange {
+  type Err = InvalidIpRange;
+
+  fn from_str(range: &str) -> Result<Self, Self::Err> {
+    let invalid = || InvalidIpRange(range.to_string());
+    let (address, prefix_len) = match range.split_once('/') {
+      Some((address, prefix_len)) => (address, Some(prefix_len)),
+      None => (range, None),
+    };
+    let address: IpAddr = address.parse().map_err(|_| invalid())?;
+    let max_prefix_len = match address {
+      IpAddr::V4(_) => 32,
+      IpAddr::V6(_) => 128,
+    };
+    let prefix_len = match prefix_len {
+      Some(prefix_len) => prefix_len
+        .parse()
+        .ok()
+        .filter(|prefix_len| *prefix_len <= max_prefix_len)
+        .ok_or_else(invalid)?,
+      None => max_prefix_len,
+    };
+    // the bits past the prefix don't matter, 10.1.2.3/8 is 10.0.0.0/8
+    let network = match address {
+      IpAddr::V4(ip) => IpAddr::V4(Ipv4Addr::from(mask_v4(ip, prefix_len))),
+      IpAddr::V6(ip) => IpAddr::V6(Ipv6Addr::from(mask_v6(ip, prefix_len))),
+    };
+    Ok(Self { network, prefix_len })
+  }
+}
+
+impl TryFrom<String> for IpRange {
+  type Error = InvalidIpRange;
+
+  fn try_from(range: String) -> Result<Self, Self::Error> {
+    range.parse()
+  }
+}
+
+impl fmt::Display for IpRange {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{}/{}", self.network, self.prefix_len)
+  }
+}
+
+/// What trusted proxies reported about the client of a request, see [`TrustedProxies`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Forwarded {
+  /// the nearest address in the chain of proxies that isn't a trusted proxy itself
+  pub client_ip: IpAddr,
+  /// `http` or `https`, as the proxy that received the request from the client reported it,
+  /// or lacking that, the nearest proxy that did
+  pub proto: Option<String>,
+}
+
+/// One proxy's entry: the address it received the request from, and over which scheme
+struct Hop {
+  /// `None` for `unknown` and obfuscated identifiers, which Forwarded allows
+  node: Option<IpAddr>,
+  proto: Option<String>,
+}
+
+/// The proxies whose `Forwarded` (RFC 7239) or, without one, `X-Forwarded-For` and
+/// `X-Forwarded-Proto` headers are believed. Anyone can send those headers, so they're
+/// ignored unless the connection comes from one of these; the proxies' entries are then
+/// followed from the nearest on, the client being the first address that isn't trusted.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TrustedProxies {
+  ranges: Vec<IpRange>,
+}
+
+impl TrustedProxies {
+  pub fn new(ranges: Vec<IpRange>) -> Self {
+    Self { ranges }
+  }
+
+  pub fn ranges(&self) -> &[IpRange] {
+    &self.ranges
+  }
+
+  fn trusts(&self, ip: IpAddr) -> bool {
+    self.ranges.iter().any(|range| range.contains(ip))
+  }
+
+  /// What the proxies say about a request from `peer` with `header`, `None` if `peer` isn't
+  /// trusted or they don't say anything
+  pub fn resolve(&self, peer: IpAddr, header: &HttpHeader) -> Option<Forwarded> {
+    if !self.trusts(peer) {
+      return None;
+    }
+    let hops = match header.get(HttpRequestHeaderKey::Forwarded) {
+      Some(forwarded) => forwarded_hops(forwarded),
+      None => x_forwarded_hops(header),
+    };
+    if hops.is_empty() {
+      return None;
+    }
+    let (mut client_ip, mut proto) = (peer, None);
+    for hop in hops.into_iter().rev() {
+      match hop.node {
+        Some(node) => client_ip = node,
+        // whoever sent this one didn't want to be known, the proxy before is as far as it goes
+        None => break,
+      }
+      proto = hop.proto.or(proto);
+      if !self.trusts(client_ip) {
+        break;
+      }
+    }
+    Some(Forwarded { client_ip, proto })
+  }
+}
+
+/// The elements of a `Forwarded` header, e.g.
+/// `for=192.0.2.60;proto=http, for="[2001:db8:cafe::17]:4711"`
+fn forwarded_hops(forwarded: &str) -> Vec<Hop> {
+  split_unquoted(forwarded, ',')
+    .into_iter()
+    .map(|element| {
+      let mut hop = Hop { node: None, proto: None };
+      for pair in split_unquoted(element, ';') {
+        let Some((name, value)) = pair.split_once('=') else {
+          continue;
+        };
+        let value = value.trim().trim_matches('"');
+        match name.trim().to_ascii_lowercase().as_str() {
+          "for" => hop.node = node_ip(value),
+          "proto" => hop.proto = Some(value.to_ascii_lowercase()),
+          _ => {}
+        }
+      }
+      hop
+    })
+    .collect()
+}
+
+fn x_forwarded_hops(header: &HttpHeader) -> Vec<Hop> {
+  let Some(forwarded_for) = header.get(HttpRequestHeaderKey::XForwardedFor) else {
+    return Vec::new();
+  };
+  let mut hops: Vec<Hop> = forwarded_for
+    .split(',')
+    .map(|node| Hop { node: node_ip(node.trim()), proto: None })
+    .collect();
+  // many proxies replace the scheme rather than append to it, so it's matched from the nearest
+  let protos = header
+    .get(HttpRequestHeaderKey::XForwardedProto)
+    .map(|protos| protos.rsplit(',').map(str::trim).collect::<Vec<_>>())
+    .unwrap_or_default();
+  for (hop, proto) in hops.iter_mut().rev().zip(protos) {
+    hop.proto = Some(proto.to_ascii_lowercase()).filter(|proto| !proto.is_empty());
+  }
+  hops
+}
+
+/// `192.0.2.60`, `192.0.2.60:8080`, `[2001:db8::17]:4711` or `2001:db8::17`
+fn node_ip(node: &str) -> Option<IpAddr> {
+  if let Some(bracketed) = node.strip_prefix('[') {
+    return bracketed.split_once(']')?.0.parse().ok();
+  }
+  node.parse().ok().or_else(|| {
+    let (ip, _port) = node.rsplit_once(':')?;
+    ip.parse::<Ipv4Addr>().ok().map(IpAddr::V4)
+  })
+}
+
+/// `value` split at `separator`s outside of quoted strings
+fn split_unquoted(value: &str, separator: char) -> Vec<&str> {
+  let mut parts = Vec::new();
+  let (mut start, mut quoted, mut escaped) = (0, false, false);
+  for (index, c) in value.char_indices() {
+    match c {
+      _ if escaped => escaped = false,
+      '\\' if quoted => escaped = true,
+      '"' => quoted = !quoted,
+      c if c == separator && !quoted => {
+        parts.push(&value[start..index]);
+        start = index + 1;
+      }
+      _ => {}
+    }
+  }
+  parts.push(&value[start..]);
+  parts
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use expectest::prelude::*;
+  use rstest::*;
+
+  fn ip(ip: &str) -> IpAddr {
+    ip.parse().unwrap()
+  }
+
+  #[rstest]
+  #[case::address("10.0.0.1", "10.0.0.1/32")]
+  #[case::block("10.1.2.3/8", "10.0.0.0/8")]
+  #[case::any("0.0.0.0/0", "0.0.0.0/0")]
+  #[case::ipv6("fd00::1/8", "fd00::/8")]
+  fn test_parse_ip_range(#[case] range: &str, #[case] expected: &str) {
+    expect!(range.parse::<IpRange>().map(|range| range.to_string()))
+      .to(be_ok().value(expected.to_string()));
+  }
+
+  #[rstest]
+  #[case::prefix_too_long("10.0.0.0/33")]
+  #[case::hostname("proxy.local")]
+  #[case::empty_prefix("10.0.0.0/")]
+  fn test_invalid_ip_range(#[case] range: &str) {
+    expect!(range.parse::<IpRange>()).to(be_err().value(InvalidIpRange(range.to_string())));
+  }
+
+  #[rstest]
+  #[case::inside("10.0.0.0/8", "10.200.0.1", true)]
+  #[case::outside("10.0.0.0/8", "11.0.0.1", false)]
+  #[case::mapped("10.0.0.0/8", "::ffff:10.0.0.1", true)]
+  #[case::other_family("fd00::/8", "10.0.0.1", false)]
+  #[case::any("0.0.0.0/0", "203.0.113.9", true)]
+  fn test_contains(#[case] range: &str, #[case] address: &str, #[case] expected: bool) {
+    let range: IpRange = range.parse().unwrap();
+    expect!(range.contains(ip(address))).to(be_equal_to(expected));
+  }
+
+  #[rstest]
+  #[case::untrusted_peer("203.0.113.9", "X-Forwarded-For: 198.51.100.1", None)]
+  #[case::no_headers("10.0.0.1", "Accept: */*", None)]
+  #[case::x_forwarded_for(
+    "10.0.0.1",
+    "X-Forwarded-For: 198.51.100.1\r\nX-Forwarded-Proto: https",
+    Some(("198.51.100.1", Some("https")))
+  )]
+  #[case::spoofed_entries_before_client(
+    "10.0.0.1",
+    "X-Forwarded-For: 127.0.0.1, 198.51.100.1, 10.0.0.2",
+    Some(("198.51.100.1", None))
+  )]
+  #[case::proto_of_nearest(
+    "10.0.0.1",
+    "X-Forwarded-For: 198.51.100.1, 10.0.0.2\r\nX-Forwarded-Proto: https",
+    Some(("198.51.100.1", Some("https")))
+  )]
+  #[case::only_proxies(
+    "10.0.0.1",
+    "X-Forwarded-For: 10.0.0.3, 10.0.0.2",
+    Some(("10.0.0.3", None))
+  )]
+  #[case::forwarded(
+    "10.0.0.1",
+    "Forwarded: for=\"[2001:db8:cafe::17]:4711\";proto=HTTPS, for=10.0.0.2;proto=http\r\nX-Forwarded-For: 198.51.100.1",
+    Some(("2001:db8:cafe::17", Some("https")))
+  )]
+  #[case::forwarded_port("10.0.0.1", "Forwarded: For=198.51.100.1:8080", Some(("198.51.100.1", None)))]
+  #[case::unknown(
+    "10.0.0.1",
+    "Forwarded: for=198.51.100.1, for=unknown, for=10.0.0.2",
+    Some(("10.0.0.2", None))
+  )]
+  fn test_resolve(
+    #[case] peer: &str,
+    #[case] header: &str,
+    #[case] expected: Option<(&str, Option<&str>)>,
+  ) {
+    let trusted = TrustedProxies::new(vec!["10.0.0.0/8".parse().unwrap()]);
+    let header = HttpHeader::try_from(format!("{}\r\n\r\n", header).as_bytes()).unwrap();
+    let expected = expected.map(|(client_ip, proto)| Forwarded {
+      client_ip: ip(client_ip),
+      proto: proto.map(str::to_string),
+    });
+    expect!(trusted.resolve(ip(peer), &header)).to(be_equal_to(expected));
+  }
+}
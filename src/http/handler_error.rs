@@ -0,0 +1,122 @@
+use std::{error::Error, fmt, io};
+
+use crate::http::{HttpResponse, ParseError, StatusCode};
+
+/// Why a handler couldn't answer a request: the status the client gets, a message for the
+/// error log and, optionally, the error behind it. The server turns it into the response, so
+/// handlers can `?` their way through failures rather than pick a status for each one.
+#[derive(Debug)]
+pub struct HandlerError {
+  status_code: StatusCode,
+  message: String,
+  source: Option<Box<dyn Error + Send + Sync>>,
+}
+
+impl HandlerError {
+  pub fn new(status_code: StatusCode, message: impl Into<String>) -> Self {
+    Self { status_code, message: message.into(), source: None }
+  }
+
+  /// A `500`, for failures that are the server's fault rather than the client's
+  pub fn internal(message: impl Into<String>) -> Self {
+    Self::new(StatusCode::InternalError, message)
+  }
+
+  pub fn bad_request(message: impl Into<String>) -> Self {
+    Self::new(StatusCode::BadRequest, message)
+  }
+
+  pub fn not_found(message: impl Into<String>) -> Self {
+    Self::new(StatusCode::NotFound, message)
+  }
+
+  /// The error that caused this one, logged along with the message
+  pub fn with_source(mut self, source: impl Into<Box<dyn Error + Send + Sync>>) -> Self {
+    self.source = Some(source.into());
+    self
+  }
+
+  pub fn status_code(&self) -> StatusCode {
+    self.status_code
+  }
+
+  pub fn message(&self) -> &str {
+    &self.message
+  }
+}
+
+impl fmt::Display for HandlerError {
+  /// The message, followed by the chain of errors behind it
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{}", self.message)?;
+    let mut source = self.source();
+    while let Some(error) = source {
+      write!(f, ": {}", error)?;
+      source = error.source();
+    }
+    Ok(())
+  }
+}
+
+impl Error for HandlerError {
+  fn source(&self) -> Option<&(dyn Error + 'static)> {
+    self
+      .source
+      .as_deref()
+      .map(|source| source as &(dyn Error + 'static))
+  }
+}
+
+/// Missing files are the client asking for the wrong thing, anything else is on the server
+impl From<io::Error> for HandlerError {
+  fn from(error: io::Error) -> Self {
+    let status_code = match error.kind() {
+      io::ErrorKind::NotFound => StatusCode::NotFound,
+      io::ErrorKind::PermissionDenied => StatusCode::Forbidden,
+      _ => StatusCode::InternalError,
+    };
+    Self::new(status_code, "I/O error").with_source(error)
+  }
+}
+
+impl From<ParseError> for HandlerError {
+  fn from(error: ParseError) -> Self {
+    Self::new(error.status_code(), "Invalid request").with_source(error)
+  }
+}
+
+/// What the client is told: the status alone, as messages and sources are for the error log
+impl From<HandlerError> for HttpResponse {
+  fn from(error: HandlerError) -> Self {
+    HttpResponse::empty_body(error.status_code)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use expectest::prelude::*;
+  use rstest::*;
+
+  #[rstest]
+  #[case::not_found(io::ErrorKind::NotFound, StatusCode::NotFound)]
+  #[case::permission_denied(io::ErrorKind::PermissionDenied, StatusCode::Forbidden)]
+  #[case::other(io::ErrorKind::BrokenPipe, StatusCode::InternalError)]
+  fn test_from_io_error(#[case] kind: io::ErrorKind, #[case] expected: StatusCode) {
+    let error = HandlerError::from(io::Error::new(kind, "index.html"));
+    expect!(error.status_code()).to(be_equal_to(expected));
+    expect!(error.to_string()).to(be_equal_to("I/O error: index.html"));
+  }
+
+  #[rstest]
+  fn test_source_chain() {
+    let parse_error = "x".parse::<u8>().unwrap_err();
+    let error = HandlerError::bad_request("Invalid page number").with_source(parse_error);
+    expect!(error.status_code()).to(be_equal_to(StatusCode::BadRequest));
+    expect!(error.message()).to(be_equal_to("Invalid page number"));
+    expect!(error.to_string()).to(be_equal_to(
+      "Invalid page number: invalid digit found in string",
+    ));
+    expect!(error.source().is_some()).to(be_true());
+  }
+}
@@ -3,78 +3,266 @@ use header_key_derive::HeaderKey;
 use mockall::automock;
 use paste::paste;
 use std::{
-  collections::HashMap,
   ffi::OsStr,
   fs::{self, File},
   path::Path,
-  str::FromStr,
+  str::{from_utf8, FromStr},
+  time::SystemTime,
 };
 use time::{format_description::well_known::Rfc2822, OffsetDateTime};
 
-use super::{request::FileError, ParseError};
-
-pub const MAX_HEADER_LENGTH_VALUE: usize = 250;
-pub const MAX_HEADERS_COUNT: usize = 100;
+use super::{
+  request::{FileError, ParserLimits},
+  ParseError,
+};
+use crate::file_cache::CachedFile;
 
-#[derive(Debug, new)]
+/// Header fields in the order they were first set. Names are case-insensitive (RFC 9110,
+/// section 5.1), so setting `content-length` replaces `Content-Length` in place. Fields that
+/// can't be combined into one line, like `Set-Cookie`, are repeated with [`Self::append`].
+#[derive(Debug, Clone, Default)]
 pub struct HttpHeader {
-  pub headers: HashMap<String, String>,
+  headers: Vec<(String, String)>,
+}
+
+/// Names whose conventional spelling isn't Train-Case
+const IRREGULAR_NAMES: [&str; 11] = [
+  "Content-MD5",
+  "DNT",
+  "ETag",
+  "Sec-WebSocket-Accept",
+  "Sec-WebSocket-Protocol",
+  "Sec-WebSocket-Version",
+  "TE",
+  "WWW-Authenticate",
+  "X-DNS-Prefetch-Control",
+  "X-UA-Compatible",
+  "X-XSS-Protection",
+];
+
+/// `content-type` -> `Content-Type`, so the wire casing doesn't depend on who set a header
+pub fn canonical_name(name: &str) -> String {
+  if let Some(irregular) = IRREGULAR_NAMES
+    .iter()
+    .find(|irregular| irregular.eq_ignore_ascii_case(name))
+  {
+    return irregular.to_string();
+  }
+  name
+    .split('-')
+    .map(|word| {
+      let mut chars = word.chars();
+      chars
+        .next()
+        .map(|first| first.to_ascii_uppercase().to_string() + &chars.as_str().to_ascii_lowercase())
+        .unwrap_or_default()
+    })
+    .collect::<Vec<_>>()
+    .join("-")
 }
 
 impl HttpHeader {
+  pub fn new<I: IntoIterator<Item = (String, String)>>(headers: I) -> Self {
+    headers
+      .into_iter()
+      .fold(Self::default(), |mut header, (key, value)| {
+        header.insert(key, value);
+        header
+      })
+  }
+
+  /// Replace the value of `key` if set, keeping its position, or append it otherwise
   pub fn insert(&mut self, key: String, value: String) {
-    self.headers.insert(key, value);
+    match self.position(&key) {
+      Some(position) => {
+        self.headers[position].1 = value;
+        let mut index = 0;
+        self.headers.retain(|(name, _)| {
+          index += 1;
+          index - 1 <= position || !name.eq_ignore_ascii_case(&key)
+        });
+      }
+      None => self.headers.push((key, value)),
+    }
+  }
+
+  /// Add another `key` field after those already set, sent as a line of its own
+  pub fn append(&mut self, key: String, value: String) {
+    self.headers.push((key, value));
   }
 
   pub fn get<K: AsRef<str>>(&self, key: K) -> Option<&String> {
-    self.headers.get(key.as_ref())
+    self
+      .position(key.as_ref())
+      .map(|position| &self.headers[position].1)
+  }
+
+  /// The `Content-Type` without parameters, lowercased, e.g. `text/html` for
+  /// `Text/HTML; charset=utf-8`
+  pub fn media_type(&self) -> Option<String> {
+    let content_type = self.get("Content-Type")?;
+    let media_type = content_type.split(';').next().unwrap_or_default();
+    Some(media_type.trim().to_ascii_lowercase())
+  }
+
+  /// Every value of `key`, in the order they were set
+  pub fn get_all<'a>(&'a self, key: &'a str) -> impl Iterator<Item = &'a String> + 'a {
+    self
+      .headers
+      .iter()
+      .filter(move |(name, _)| name.eq_ignore_ascii_case(key))
+      .map(|(_, value)| value)
   }
 
   pub fn remove<K: AsRef<str>>(&mut self, key: K) {
-    self.headers.remove(key.as_ref());
+    let key = key.as_ref();
+    self
+      .headers
+      .retain(|(name, _)| !name.eq_ignore_ascii_case(key));
+  }
+
+  /// In insertion order, with names as they were set
+  pub fn iter(&self) -> impl Iterator<Item = (&String, &String)> {
+    self.headers.iter().map(|(key, value)| (key, value))
   }
 
-  pub fn iter(&self) -> std::collections::hash_map::Iter<String, String> {
-    self.headers.iter()
+  pub fn is_empty(&self) -> bool {
+    self.headers.is_empty()
   }
 
+  fn position(&self, key: &str) -> Option<usize> {
+    self
+      .headers
+      .iter()
+      .position(|(name, _)| name.eq_ignore_ascii_case(key))
+  }
+
+  /// The built-in MIME type for `path`'s extension, see [`super::MimeTypes`] for overrides
   pub fn get_mime_type(path: &str) -> &'static str {
-    match Path::new(path).extension().and_then(OsStr::to_str) {
-      Some("html") => "text/html",
+    let extension = Path::new(path)
+      .extension()
+      .and_then(OsStr::to_str)
+      .map(str::to_lowercase);
+    match extension.as_deref() {
+      Some("html") | Some("htm") => "text/html",
       Some("css") => "text/css",
-      Some("js") => "application/javascript",
+      Some("js") | Some("mjs") => "application/javascript",
+      Some("json") | Some("map") => "application/json",
+      Some("txt") => "text/plain",
+      Some("csv") => "text/csv",
+      Some("md") => "text/markdown",
+      Some("xml") => "application/xml",
+      Some("wasm") => "application/wasm",
+      Some("pdf") => "application/pdf",
+      Some("zip") => "application/zip",
       Some("png") => "image/png",
       Some("jpg") | Some("jpeg") => "image/jpeg",
+      Some("gif") => "image/gif",
+      Some("webp") => "image/webp",
+      Some("avif") => "image/avif",
+      Some("svg") => "image/svg+xml",
       Some("ico") => "image/x-icon",
+      Some("woff") => "font/woff",
+      Some("woff2") => "font/woff2",
+      Some("ttf") => "font/ttf",
+      Some("otf") => "font/otf",
+      Some("mp3") => "audio/mpeg",
+      Some("ogg") => "audio/ogg",
+      Some("mp4") => "video/mp4",
+      Some("webm") => "video/webm",
       _ => "application/octet-stream",
     }
   }
 
   pub fn html_response_header_for_file<P: AsRef<Path>>(
     file_path: P,
+    content_type: &str,
     file_ops: &dyn FileOps,
   ) -> Result<Self, FileError> {
     let path = file_path.as_ref();
-    let mut builder = HttpResponseHeaderBuilder::new();
-
     let size = file_ops.get_file_size(path)?;
-    let last_modified = file_ops.get_file_last_modified_time(path)?;
+    let modified = file_ops.get_file_modified_time(path)?;
+    Self::file_response_header(content_type, size, modified)
+  }
 
-    builder.content_type(Self::get_mime_type(&path.to_string_lossy()));
+  /// The header of a response serving a file of `size` bytes last modified at `modified`
+  pub fn file_response_header(
+    content_type: &str,
+    size: u64,
+    modified: SystemTime,
+  ) -> Result<Self, FileError> {
+    let last_modified = Self::last_modified(modified)?;
+    Ok(Self::file_header(
+      content_type,
+      size,
+      &last_modified,
+      &Self::etag(size, modified),
+    ))
+  }
+
+  /// [`Self::file_response_header`] with the validators the cache formatted already
+  pub fn cached_file_response_header(content_type: &str, file: &CachedFile) -> Self {
+    let size = file.contents.len() as u64;
+    Self::file_header(content_type, size, &file.last_modified, &file.etag)
+  }
+
+  fn file_header(content_type: &str, size: u64, last_modified: &str, etag: &str) -> Self {
+    let mut builder = HttpResponseHeaderBuilder::new();
+    builder.content_type(content_type);
     builder.connection("keep-alive");
     builder.keep_alive("timeout=5, max=1000");
-    builder.access_control_allow_origin("*");
     builder.content_length(&size.to_string());
-    builder.last_modified(&last_modified);
+    builder.last_modified(last_modified);
+    builder.etag(etag);
     builder.custom("X-Content-Type-Options".to_string(), "nosniff");
-    Ok(builder.build())
+    builder.build()
+  }
+
+  /// `modified` as a `Last-Modified` value
+  pub fn last_modified(modified: SystemTime) -> Result<String, FileError> {
+    Ok(OffsetDateTime::from(modified).format(&Rfc2822)?)
+  }
+
+  /// A strong `ETag` for a file from its size and modification time, to the second like
+  /// `Last-Modified`, e.g. `"6708f1c0-2a"`
+  pub fn etag(size: u64, modified: SystemTime) -> String {
+    let modified = modified
+      .duration_since(SystemTime::UNIX_EPOCH)
+      .unwrap_or_default();
+    format!("\"{:x}-{:x}\"", modified.as_secs(), size)
+  }
+
+  /// Whether a client sending this request header has the representation `response` describes
+  /// cached already (RFC 9110, section 13.1): `If-None-Match` lists its `ETag`, or without an
+  /// `If-None-Match`, it hasn't been modified since `If-Modified-Since`
+  pub fn is_not_modified(&self, response: &HttpHeader) -> bool {
+    if let Some(if_none_match) = self.get(HttpRequestHeaderKey::IfNoneMatch) {
+      let Some(etag) = response.get(HttpResponseHeaderKey::Etag) else {
+        return false;
+      };
+      // weak comparison, `W/"1"` matches `"1"`
+      let opaque = |tag: &str| tag.trim().trim_start_matches("W/").to_string();
+      return if_none_match
+        .split(',')
+        .any(|tag| tag.trim() == "*" || opaque(tag) == opaque(etag));
+    }
+    // an invalid date is ignored (RFC 9110, section 13.1.3)
+    let if_modified_since = self.if_modified_since().ok().flatten();
+    let last_modified = response
+      .date(HttpResponseHeaderKey::LastModified)
+      .ok()
+      .flatten();
+    match (last_modified, if_modified_since) {
+      (Some(last_modified), Some(if_modified_since)) => last_modified <= if_modified_since,
+      _ => false,
+    }
   }
 }
 
 #[automock]
 pub trait FileOps {
   fn get_file_size(&self, path: &Path) -> Result<u64, FileError>;
-  fn get_file_last_modified_time(&self, path: &Path) -> Result<String, FileError>;
+  fn get_file_modified_time(&self, path: &Path) -> Result<SystemTime, FileError>;
 }
 
 pub struct ReadFileOps;
@@ -85,29 +273,42 @@ impl FileOps for ReadFileOps {
     Ok(file.metadata()?.len())
   }
 
-  fn get_file_last_modified_time(&self, path: &Path) -> Result<String, FileError> {
-    let metadata = fs::metadata(path)?;
-    let metadata_modified = metadata.modified()?;
-    let last_modified = OffsetDateTime::from(metadata_modified).format(&Rfc2822)?;
-    Ok(last_modified)
+  fn get_file_modified_time(&self, path: &Path) -> Result<SystemTime, FileError> {
+    Ok(fs::metadata(path)?.modified()?)
   }
 }
 
 impl FromStr for HttpHeader {
   type Err = ParseError;
 
+  fn from_str(request: &str) -> Result<Self, Self::Err> {
+    Self::try_from(request.as_bytes())
+  }
+}
+
+/// Header names must be valid UTF-8, whereas values are opaque bytes: clients do send
+/// Latin-1 (e.g. in filenames), so those are accepted instead of failing the request
+impl TryFrom<&[u8]> for HttpHeader {
+  type Error = ParseError;
+
+  fn try_from(request: &[u8]) -> Result<Self, Self::Error> {
+    Self::parse(request, &ParserLimits::default())
+  }
+}
+
+impl HttpHeader {
   /// Parse header lines until first error and return the latter if occurred
   /// else build HttpHeader from key -> values and return it
-  fn from_str(request: &str) -> Result<Self, Self::Err> {
+  pub fn parse(request: &[u8], limits: &ParserLimits) -> Result<Self, ParseError> {
     request
-      .lines()
-      .take_while(|line| !line.trim().is_empty())
-      .map(parse_header)
+      .split(|&byte| byte == b'\n')
+      .take_while(|line| !line.trim_ascii().is_empty())
+      .map(|line| parse_header(line, limits.max_header_value_length))
       .enumerate()
-      .try_fold(HashMap::new(), |mut m, (i, res)| {
+      .try_fold(HttpHeader::default(), |mut m, (i, res)| {
         let (key, value) = res?;
-        if i >= MAX_HEADERS_COUNT {
-          Err(ParseError::InvalidRequest(
+        if i >= limits.max_headers_count {
+          Err(ParseError::HeaderFieldsTooLarge(
             "Too many HTTP headers".to_string(),
           ))
         } else {
@@ -115,59 +316,77 @@ impl FromStr for HttpHeader {
           Ok(m)
         }
       })
-      .and_then(|m| {
-        if m.is_empty() {
-          Err(ParseError::InvalidRequest(
-            "Http header missing!".to_string(),
-          ))
-        } else {
-          Ok(HttpHeader::new(m))
-        }
-      })
   }
 }
 
-fn parse_header(line: &str) -> Result<(HttpRequestHeaderKey, String), ParseError> {
-  let (key, value) = line
-    .trim()
-    .split_once(':')
+fn parse_header(
+  line: &[u8],
+  max_value_length: usize,
+) -> Result<(HttpRequestHeaderKey, String), ParseError> {
+  let colon = line
+    .iter()
+    .position(|&byte| byte == b':')
     .ok_or(ParseError::InvalidRequest(
       "Invalid header format!".to_string(),
     ))?;
+  let (key, value) = (&line[..colon], line[colon + 1..].trim_ascii());
 
-  let key = key.trim().to_lowercase();
-  let value = value.trim().to_string();
+  let key = from_utf8(key)
+    .map_err(|_| ParseError::InvalidRequest("Invalid header name encoding!".to_string()))?
+    .trim()
+    .to_lowercase();
 
-  if value.len() > MAX_HEADER_LENGTH_VALUE {
-    return Err(ParseError::InvalidRequest(format!(
+  if value.len() > max_value_length {
+    return Err(ParseError::HeaderFieldsTooLarge(format!(
       "Header value too long for {}",
       key
     )));
   }
+  let value = decode_header_value(value);
 
   let header_key = HEADER_KEY_MAP
     .get(&key)
     .cloned()
     .unwrap_or_else(|| HttpRequestHeaderKey::Custom(key.clone()));
+  tracing::trace!(name = key, "Parsed header");
 
   Ok((header_key, value))
 }
 
+/// UTF-8 when the value is valid UTF-8, otherwise ISO-8859-1, which maps every byte to
+/// the char of the same value and therefore preserves the original bytes
+fn decode_header_value(value: &[u8]) -> String {
+  match from_utf8(value) {
+    Ok(value) => value.to_string(),
+    Err(_) => value.iter().map(|&byte| byte as char).collect(),
+  }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Hash, HeaderKey)]
 pub enum HttpRequestHeaderKey {
   Accept,
   AcceptEncoding,
   AcceptLanguage,
+  AccessControlRequestHeaders,
+  AccessControlRequestMethod,
   Authorization,
   Host,
   CacheControl,
+  Connection,
   ContentType,
   ContentLength,
   Cookie,
   Custom(String),
+  Forwarded,
+  IfModifiedSince,
+  IfNoneMatch,
   Origin,
   Referer,
+  Upgrade,
   UserAgent,
+  XForwardedFor,
+  XForwardedProto,
+  XRequestId,
 }
 
 macro_rules! generate_header_key_map {
@@ -191,21 +410,31 @@ generate_header_key_map! {
   Accept,
   AcceptEncoding,
   AcceptLanguage,
+  AccessControlRequestHeaders,
+  AccessControlRequestMethod,
   Authorization,
   Host,
   CacheControl,
+  Connection,
   ContentType,
   ContentLength,
   Cookie,
+  Forwarded,
+  IfModifiedSince,
+  IfNoneMatch,
   Origin,
   Referer,
+  Upgrade,
   UserAgent,
+  XForwardedFor,
+  XForwardedProto,
+  XRequestId,
 }
 
 #[derive(new)]
 pub struct HttpRequestHeaderBuilder {
   #[new(default)]
-  headers: HashMap<String, String>,
+  headers: HttpHeader,
 }
 
 macro_rules! add_request_builder_headers {
@@ -237,36 +466,57 @@ impl HttpRequestHeaderBuilder {
     Accept,
     AcceptEncoding,
     AcceptLanguage,
+    AccessControlRequestHeaders,
+    AccessControlRequestMethod,
     Authorization,
     CacheControl,
+    Connection,
     ContentType,
     ContentLength,
     Host,
     Cookie,
     Origin,
+    Upgrade,
     UserAgent,
   );
 
-  fn build(self) -> HttpHeader {
-    HttpHeader::new(self.headers)
+  pub fn build(self) -> HttpHeader {
+    self.headers
   }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash, HeaderKey)]
 pub enum HttpResponseHeaderKey {
+  AccessControlAllowCredentials,
+  AccessControlAllowHeaders,
+  AccessControlAllowMethods,
   AccessControlAllowOrigin,
+  AccessControlMaxAge,
+  Allow,
+  CacheControl,
   Connection,
+  ContentEncoding,
   ContentLength,
   ContentType,
   Custom(String),
+  Etag,
   KeepAlive,
   LastModified,
+  Location,
+  RetryAfter,
+  SetCookie,
+  StrictTransportSecurity,
+  TransferEncoding,
+  Upgrade,
+  Vary,
+  WwwAuthenticate,
+  XRequestId,
 }
 
 #[derive(new)]
 pub struct HttpResponseHeaderBuilder {
   #[new(default)]
-  headers: HashMap<String, String>,
+  headers: HttpHeader,
 }
 
 macro_rules! add_response_builder_headers {
@@ -295,16 +545,33 @@ macro_rules! add_response_builder_headers {
 
 impl HttpResponseHeaderBuilder {
   add_response_builder_headers!(
+    AccessControlAllowCredentials,
+    AccessControlAllowHeaders,
+    AccessControlAllowMethods,
     AccessControlAllowOrigin,
+    AccessControlMaxAge,
+    Allow,
+    CacheControl,
     Connection,
+    ContentEncoding,
     ContentLength,
     ContentType,
+    Etag,
     KeepAlive,
-    LastModified
+    LastModified,
+    Location,
+    RetryAfter,
+    SetCookie,
+    StrictTransportSecurity,
+    TransferEncoding,
+    Upgrade,
+    Vary,
+    WwwAuthenticate,
+    XRequestId,
   );
 
-  fn build(self) -> HttpHeader {
-    HttpHeader::new(self.headers)
+  pub fn build(self) -> HttpHeader {
+    self.headers
   }
 }
 
@@ -388,30 +655,106 @@ mod tests {
     expect!(http_header.iter().count()).to(be_equal_to(7));
   }
 
+  #[rstest]
+  #[case::lowercase("content-type", "Content-Type")]
+  #[case::uppercase("X-REQUEST-ID", "X-Request-Id")]
+  #[case::irregular("etag", "ETag")]
+  #[case::single_word("host", "Host")]
+  fn test_canonical_name(#[case] name: &str, #[case] expected: &str) {
+    expect!(canonical_name(name)).to(be_equal_to(expected));
+  }
+
+  #[rstest]
+  fn test_keep_insertion_order_ignoring_case() {
+    let mut header = HttpHeader::default();
+    header.insert("Content-Type".to_string(), "text/html".to_string());
+    header.insert("x-trace".to_string(), "1".to_string());
+    header.insert("Content-Length".to_string(), "0".to_string());
+    header.insert("content-type".to_string(), "text/plain".to_string());
+
+    let names = header
+      .iter()
+      .map(|(key, _)| key.as_str())
+      .collect::<Vec<_>>();
+    expect!(names).to(be_equal_to(vec![
+      "Content-Type",
+      "x-trace",
+      "Content-Length",
+    ]));
+    expect!(header.get("CONTENT-TYPE")).to(be_some().value("text/plain"));
+  }
+
+  #[rstest]
+  #[case::plain("application/json", Some("application/json"))]
+  #[case::parameters("Text/HTML; charset=utf-8", Some("text/html"))]
+  fn test_media_type(#[case] content_type: &str, #[case] expected: Option<&str>) {
+    let header = HttpRequestHeaderBuilder::new()
+      .content_type(content_type)
+      .build();
+    expect!(header.media_type().as_deref()).to(be_equal_to(expected));
+    expect!(HttpHeader::default().media_type()).to(be_none());
+  }
+
+  #[rstest]
+  fn test_repeated_fields() {
+    let mut header = HttpHeader::default();
+    header.append("Set-Cookie".to_string(), "a=1".to_string());
+    header.append("set-cookie".to_string(), "b=2".to_string());
+    expect!(header.get_all("Set-Cookie").collect::<Vec<_>>()).to(be_equal_to(vec!["a=1", "b=2"]));
+    expect!(header.get("Set-Cookie")).to(be_some().value("a=1"));
+
+    header.insert("Set-Cookie".to_string(), "c=3".to_string());
+    expect!(header.get_all("Set-Cookie").collect::<Vec<_>>()).to(be_equal_to(vec!["c=3"]));
+    header.remove("SET-COOKIE");
+    expect!(header.get("Set-Cookie")).to(be_none());
+  }
+
   #[rstest]
   #[case::too_many_headers({
-    (1..(MAX_HEADERS_COUNT + 1)).map(|i| format!("X-Custom-Header-{}: Value\r\n", i)).collect()
+    (1..(ParserLimits::default().max_headers_count + 1)).map(|i| format!("X-Custom-Header-{}: Value\r\n", i)).collect()
   })]
   fn test_max_allowed_headers_count(#[case] input: String) {
     let result = HttpHeader::from_str(&input);
     expect!(result.is_err());
 
     if let Err(error) = result {
-      expect!(error).to(be_equal_to(ParseError::InvalidRequest(
+      expect!(error).to(be_equal_to(ParseError::HeaderFieldsTooLarge(
         "Too many HTTP headers".to_string(),
       )));
     }
   }
 
   #[rstest]
-  #[case::empty("")]
   #[case::no_colon("Missing column")]
-  #[case::header_value_too_long("X-Long: ".to_string() + &"a".repeat(MAX_HEADER_LENGTH_VALUE + 1))]
+  #[case::header_value_too_long(
+    "X-Long: ".to_string() + &"a".repeat(ParserLimits::default().max_header_value_length + 1)
+  )]
   fn test_failed_parse_header_cases(#[case] input: String) {
     let result = HttpHeader::from_str(&input);
     expect!(result).to(be_err());
   }
 
+  #[rstest]
+  fn test_non_utf8_header_value_is_accepted() {
+    let result = HttpHeader::try_from(&b"Host: localhost\r\nX-Filename: caf\xe9.txt\r\n\r\n"[..]);
+
+    expect!(result.is_ok()).to(be_true());
+    if let Ok(header) = result {
+      expect!(header.get(HttpRequestHeaderKey::Host)).to(be_some().value("localhost"));
+      let filename = header.get("x-filename").unwrap();
+      expect!(filename.as_str()).to(be_equal_to("café.txt"));
+      // each char maps back to the byte that was sent
+      let bytes: Vec<u8> = filename.chars().map(|ch| ch as u8).collect();
+      expect!(bytes).to(be_equal_to(b"caf\xe9.txt".to_vec()));
+    }
+  }
+
+  #[rstest]
+  fn test_non_utf8_header_name_is_rejected() {
+    let result = HttpHeader::try_from(&b"X-Caf\xe9: value\r\n\r\n"[..]);
+    expect!(result).to(be_err());
+  }
+
   #[rstest]
   fn test_valid_header(valid_header: String) {
     let result = HttpHeader::from_str(&valid_header);
@@ -434,7 +777,8 @@ mod tests {
     temp_file.write_all(content.as_bytes())?;
 
     // Get the header
-    let header = HttpHeader::html_response_header_for_file(&temp_file_path, &ReadFileOps)?;
+    let header =
+      HttpHeader::html_response_header_for_file(&temp_file_path, "text/html", &ReadFileOps)?;
 
     // Test content length
     expect!(header.get(HttpResponseHeaderKey::ContentLength))
@@ -464,25 +808,62 @@ mod tests {
         )))
       });
 
-    let result = HttpHeader::html_response_header_for_file("test.html", &mock_file_ops);
+    let result =
+      HttpHeader::html_response_header_for_file("test.html", "text/html", &mock_file_ops);
     expect!(result).to(be_err());
 
-    // Mock failure of last modified parsing
+    // Mock failure of reading the modification time
     mock_file_ops.expect_get_file_size().returning(|_| Ok(100)); // Assume size succeeds
     mock_file_ops
-      .expect_get_file_last_modified_time()
+      .expect_get_file_modified_time()
       .with(eq(Path::new("test.html")))
       .times(1)
       .returning(|_| {
-        Err(FileError::TimeParseError(
-          time::error::Parse::TryFromParsed(time::error::TryFromParsed::InsufficientInformation),
-        ))
+        Err(FileError::Io(std::io::Error::new(
+          std::io::ErrorKind::Unsupported,
+          "Modification time not available",
+        )))
       });
 
-    let result = HttpHeader::html_response_header_for_file("test.html", &mock_file_ops);
+    let result =
+      HttpHeader::html_response_header_for_file("test.html", "text/html", &mock_file_ops);
     expect!(result).to(be_err());
   }
 
+  #[rstest]
+  fn test_file_response_header_etag() -> Result<(), FileError> {
+    let modified = SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1_700_000_000);
+    let header = HttpHeader::file_response_header("text/html", 42, modified)?;
+    expect!(header.get(HttpResponseHeaderKey::Etag)).to(be_some().value("\"6553f100-2a\""));
+    expect!(header.get(HttpResponseHeaderKey::LastModified))
+      .to(be_some().value("Tue, 14 Nov 2023 22:13:20 +0000"));
+    Ok(())
+  }
+
+  #[rstest]
+  #[case::no_conditions("", false)]
+  #[case::etag_matches("If-None-Match: \"6553f100-2a\"\r\n", true)]
+  #[case::etag_listed("If-None-Match: \"1-1\", W/\"6553f100-2a\"\r\n", true)]
+  #[case::any_etag("If-None-Match: *\r\n", true)]
+  #[case::etag_differs("If-None-Match: \"6553f100-2b\"\r\n", false)]
+  #[case::etag_before_date(
+    "If-None-Match: \"1-1\"\r\nIf-Modified-Since: Tue, 14 Nov 2023 22:13:20 GMT\r\n",
+    false
+  )]
+  #[case::unmodified("If-Modified-Since: Tue, 14 Nov 2023 22:13:20 GMT\r\n", true)]
+  #[case::modified("If-Modified-Since: Tue, 14 Nov 2023 22:13:19 +0000\r\n", false)]
+  #[case::invalid_date("If-Modified-Since: yesterday\r\n", false)]
+  fn test_is_not_modified(
+    #[case] conditions: &str,
+    #[case] expected: bool,
+  ) -> Result<(), Box<dyn std::error::Error>> {
+    let modified = SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1_700_000_000);
+    let response = HttpHeader::file_response_header("text/html", 42, modified)?;
+    let request = HttpHeader::from_str(&format!("Host: localhost\r\n{}\r\n", conditions))?;
+    expect!(request.is_not_modified(&response)).to(be_equal_to(expected));
+    Ok(())
+  }
+
   #[rstest]
   #[case("test.html", "text/html")]
   #[case("styles.css", "text/css")]
@@ -491,6 +872,12 @@ mod tests {
   #[case("photo.jpg", "image/jpeg")]
   #[case("photo.jpeg", "image/jpeg")]
   #[case("favicon.ico", "image/x-icon")]
+  #[case("logo.svg", "image/svg+xml")]
+  #[case("data.json", "application/json")]
+  #[case("module.wasm", "application/wasm")]
+  #[case("font.woff2", "font/woff2")]
+  #[case("UPPER.PNG", "image/png")]
+  #[case("archive.tar.gz", "application/octet-stream")]
   #[case("unknown.file", "application/octet-stream")]
   #[case("noextension", "application/octet-stream")]
   fn test_get_mime_type(#[case] input: &str, #[case] expected: &str) {
@@ -0,0 +1,104 @@
+use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
+use thiserror::Error;
+
+/// One year, the minimum accepted by the browsers' HSTS preload lists
+pub const PRELOAD_MIN_MAX_AGE: u64 = 31_536_000;
+
+/// A validated `Strict-Transport-Security` policy (RFC 6797), e.g. from the config file:
+///
+/// ```toml
+/// [hsts]
+/// max_age = 31536000
+/// include_subdomains = true
+/// preload = true
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(try_from = "HstsSettings")]
+pub struct Hsts {
+  max_age: u64,
+  include_subdomains: bool,
+  preload: bool,
+}
+
+#[derive(Error, Debug, PartialEq)]
+pub enum HstsError {
+  #[error("HSTS preload requires max_age of at least {PRELOAD_MIN_MAX_AGE} seconds, got {0}")]
+  PreloadMaxAgeTooShort(u64),
+  #[error("HSTS preload requires include_subdomains")]
+  PreloadWithoutSubdomains,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct HstsSettings {
+  max_age: u64,
+  #[serde(default)]
+  include_subdomains: bool,
+  #[serde(default)]
+  preload: bool,
+}
+
+impl Hsts {
+  /// A `max_age` of 0 tells browsers to forget a previously sent policy
+  pub fn new(max_age: u64, include_subdomains: bool, preload: bool) -> Result<Self, HstsError> {
+    if preload && max_age < PRELOAD_MIN_MAX_AGE {
+      return Err(HstsError::PreloadMaxAgeTooShort(max_age));
+    }
+    if preload && !include_subdomains {
+      return Err(HstsError::PreloadWithoutSubdomains);
+    }
+    Ok(Self { max_age, include_subdomains, preload })
+  }
+
+  pub fn header_value(&self) -> String {
+    let mut value = format!("max-age={}", self.max_age);
+    if self.include_subdomains {
+      value.push_str("; includeSubDomains");
+    }
+    if self.preload {
+      value.push_str("; preload");
+    }
+    value
+  }
+}
+
+impl TryFrom<HstsSettings> for Hsts {
+  type Error = HstsError;
+
+  fn try_from(settings: HstsSettings) -> Result<Self, Self::Error> {
+    Self::new(
+      settings.max_age,
+      settings.include_subdomains,
+      settings.preload,
+    )
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use expectest::prelude::*;
+  use rstest::*;
+
+  #[rstest]
+  #[case::max_age_only(Hsts::new(300, false, false), "max-age=300")]
+  #[case::subdomains(Hsts::new(86400, true, false), "max-age=86400; includeSubDomains")]
+  #[case::preload(Hsts::new(63072000, true, true), "max-age=63072000; includeSubDomains; preload")]
+  fn test_header_value(#[case] hsts: Result<Hsts, HstsError>, #[case] expected: &str) {
+    expect!(hsts.map(|hsts| hsts.header_value())).to(be_ok().value(expected.to_string()));
+  }
+
+  #[rstest]
+  #[case::short_max_age(Hsts::new(86400, true, true), HstsError::PreloadMaxAgeTooShort(86400))]
+  #[case::no_subdomains(Hsts::new(63072000, false, true), HstsError::PreloadWithoutSubdomains)]
+  fn test_invalid_preload(#[case] hsts: Result<Hsts, HstsError>, #[case] expected: HstsError) {
+    expect!(hsts).to(be_err().value(expected));
+  }
+
+  #[rstest]
+  fn test_deserialize_validates() {
+    expect!(toml::from_str::<Hsts>("max_age = 600\npreload = true")).to(be_err());
+    expect!(toml::from_str::<Hsts>("max_age = 600")).to(be_ok());
+  }
+}
@@ -0,0 +1,173 @@
+//! JSON bodies for API-style handlers: [`HttpRequest::json`] reads them and
+//! [`HttpResponse::json`] writes them.
+
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use thiserror::Error;
+
+use super::{
+  header::{HttpRequestHeaderKey, HttpResponseHeaderBuilder},
+  HttpRequest, HttpResponse, StatusCode,
+};
+
+#[derive(Error, Debug)]
+pub enum JsonError {
+  #[error("Expected a JSON body, got Content-Type {0:?}")]
+  UnsupportedMediaType(Option<String>),
+  #[error("Invalid JSON body: {0}")]
+  Invalid(#[from] serde_json::Error),
+}
+
+impl JsonError {
+  /// `415 Unsupported Media Type` for other content types, `400 Bad Request` for bodies that
+  /// don't deserialize
+  pub fn status_code(&self) -> StatusCode {
+    match self {
+      Self::UnsupportedMediaType(_) => StatusCode::UnsupportedMediaType,
+      Self::Invalid(_) => StatusCode::BadRequest,
+    }
+  }
+}
+
+impl From<JsonError> for HttpResponse {
+  fn from(error: JsonError) -> Self {
+    HttpResponse::new(
+      error.status_code(),
+      Some(format!("{}\n", error).into_bytes()),
+      None,
+    )
+  }
+}
+
+/// `application/json` or a `+json` type such as `application/problem+json`
+fn is_json(media_type: &str) -> bool {
+  media_type == "application/json"
+    || media_type.starts_with("application/") && media_type.ends_with("+json")
+}
+
+impl HttpRequest<'_> {
+  /// The body deserialized as `T`, provided the `Content-Type` says it's JSON. Only the
+  /// buffered body is seen, see [`HttpRequest::body`]; larger ones can be read with
+  /// `serde_json::from_reader` in [`crate::server::Handler::handle_with_body`].
+  ///
+  /// ```
+  /// use serde::Deserialize;
+  /// use udemy_server::http::HttpRequest;
+  ///
+  /// #[derive(Deserialize)]
+  /// struct Todo {
+  ///   title: String,
+  /// }
+  ///
+  /// let buffer = b"POST /todos HTTP/1.1\r\nHost: localhost\r\nContent-Type: application/json\r\nContent-Length: 17\r\n\r\n{\"title\":\"Write\"}";
+  /// let request = HttpRequest::try_from(&buffer[..]).unwrap();
+  /// assert_eq!(request.json::<Todo>().unwrap().title, "Write");
+  /// ```
+  pub fn json<'a, T: Deserialize<'a>>(&'a self) -> Result<T, JsonError> {
+    if !self
+      .header()
+      .media_type()
+      .is_some_and(|media_type| is_json(&media_type))
+    {
+      let content_type = self.header().get(HttpRequestHeaderKey::ContentType);
+      return Err(JsonError::UnsupportedMediaType(content_type.cloned()));
+    }
+    Ok(serde_json::from_slice(self.body())?)
+  }
+}
+
+impl HttpResponse {
+  /// `value` serialized as the body, with `Content-Type` and `Content-Length` set for it.
+  /// Values serde can't represent as JSON, e.g. maps with non-string keys, get
+  /// `500 Internal Error` instead.
+  pub fn json<T: Serialize + ?Sized>(status_code: StatusCode, value: &T) -> Self {
+    match serde_json::to_vec(value) {
+      Ok(body) => {
+        let mut builder = HttpResponseHeaderBuilder::new();
+        builder.content_type("application/json");
+        builder.content_length(&body.len().to_string());
+        HttpResponse::new(status_code, Some(body), Some(Arc::new(builder.build())))
+      }
+      Err(error) => {
+        tracing::error!(%error, "Failed to serialize JSON response");
+        HttpResponse::empty_body(StatusCode::InternalError)
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use expectest::prelude::*;
+  use rstest::*;
+  use std::collections::HashMap;
+
+  #[derive(Debug, PartialEq, Deserialize, Serialize)]
+  struct Todo<'a> {
+    title: &'a str,
+    done: bool,
+  }
+
+  fn request(content_type: Option<&str>, body: &str) -> Vec<u8> {
+    let content_type = content_type
+      .map(|content_type| format!("Content-Type: {}\r\n", content_type))
+      .unwrap_or_default();
+    format!(
+      "POST /todos HTTP/1.1\r\nHost: localhost\r\n{}Content-Length: {}\r\n\r\n{}",
+      content_type,
+      body.len(),
+      body
+    )
+    .into_bytes()
+  }
+
+  #[rstest]
+  #[case::json(Some("application/json"), r#"{"title":"a","done":true}"#, Ok(()))]
+  #[case::charset(Some("Application/JSON; charset=utf-8"), r#"{"title":"a","done":true}"#, Ok(()))]
+  #[case::suffix(Some("application/merge-patch+json"), r#"{"title":"a","done":true}"#, Ok(()))]
+  #[case::missing_content_type(
+    None,
+    r#"{"title":"a","done":true}"#,
+    Err(StatusCode::UnsupportedMediaType)
+  )]
+  #[case::form(
+    Some("application/x-www-form-urlencoded"),
+    "title=a",
+    Err(StatusCode::UnsupportedMediaType)
+  )]
+  #[case::invalid(Some("application/json"), r#"{"title":"a""#, Err(StatusCode::BadRequest))]
+  #[case::wrong_shape(Some("application/json"), r#"{"title":1}"#, Err(StatusCode::BadRequest))]
+  fn test_request_json(
+    #[case] content_type: Option<&str>,
+    #[case] body: &str,
+    #[case] expected: Result<(), StatusCode>,
+  ) -> Result<(), crate::http::ParseError> {
+    let buffer = request(content_type, body);
+    let request = HttpRequest::try_from(&buffer[..])?;
+    match (request.json::<Todo>(), expected) {
+      (Ok(todo), Ok(())) => {
+        expect!(todo).to(be_equal_to(Todo { title: "a", done: true }));
+      }
+      (Err(error), Err(status_code)) => {
+        expect!(error.status_code()).to(be_equal_to(status_code));
+      }
+      (result, expected) => panic!("Got {:?}, expected {:?}", result, expected),
+    }
+    Ok(())
+  }
+
+  #[rstest]
+  fn test_response_json() {
+    let response = HttpResponse::json(StatusCode::Ok, &Todo { title: "a", done: false });
+    let body = br#"{"title":"a","done":false}"#;
+    expect!(response.body().as_deref()).to(be_some().value(&body[..]));
+    let header = response.http_header().as_ref().unwrap();
+    expect!(header.get("Content-Type")).to(be_some().value("application/json"));
+    expect!(header.get("Content-Length")).to(be_some().value(&body.len().to_string()));
+
+    let unrepresentable = HashMap::from([((1, 2), "tuple key")]);
+    let response = HttpResponse::json(StatusCode::Ok, &unrepresentable);
+    expect!(*response.status_code()).to(be_equal_to(StatusCode::InternalError));
+  }
+}
@@ -1,6 +1,9 @@
+use std::fmt::{Display, Formatter, Result as FmtResult};
 use std::str::FromStr;
+use thiserror::Error;
 
-#[derive(Debug, PartialEq)]
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum Method {
   GET,
   POST,
@@ -32,4 +35,13 @@ impl FromStr for Method {
   }
 }
 
+impl Display for Method {
+  fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+    // the variants are spelled exactly like the methods on the wire
+    write!(f, "{:?}", self)
+  }
+}
+
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
+#[error("Unknown method")]
 pub struct MethodError;
@@ -0,0 +1,122 @@
+use serde::Serialize;
+use std::{collections::HashMap, ffi::OsStr, path::Path};
+
+use super::header::HttpHeader;
+
+pub const UTF8_BOM: &[u8] = &[0xEF, 0xBB, 0xBF];
+pub const DEFAULT_CHARSET: &str = "utf-8";
+
+/// Resolves the `Content-Type` sent for a served file, including the
+/// `charset` parameter of textual types
+#[derive(Debug, Clone, Serialize)]
+pub struct MimeTypes {
+  default_charset: Option<String>,
+  /// lowercase extension (without the dot) -> MIME type, consulted before the built-in table
+  overrides: HashMap<String, String>,
+}
+
+impl MimeTypes {
+  /// `None` disables the charset parameter for files that carry no BOM
+  pub fn new(default_charset: Option<String>) -> Self {
+    Self { default_charset, overrides: HashMap::new() }
+  }
+
+  /// Extend or replace entries of the built-in extension table
+  pub fn with_overrides(mut self, overrides: HashMap<String, String>) -> Self {
+    self.overrides.extend(
+      overrides
+        .into_iter()
+        .map(|(extension, mime)| (extension.trim_start_matches('.').to_lowercase(), mime)),
+    );
+    self
+  }
+
+  pub fn mime_type(&self, path: &str) -> &str {
+    Path::new(path)
+      .extension()
+      .and_then(OsStr::to_str)
+      .and_then(|extension| self.overrides.get(&extension.to_lowercase()))
+      .map(String::as_str)
+      .unwrap_or_else(|| HttpHeader::get_mime_type(path))
+  }
+
+  pub fn content_type(&self, path: &str, body: &[u8]) -> String {
+    let mime = self.mime_type(path);
+    if !Self::is_text(mime) {
+      return mime.to_string();
+    }
+
+    // a UTF-8 BOM is an explicit declaration by the author and wins over the default
+    let charset = if body.starts_with(UTF8_BOM) {
+      Some(DEFAULT_CHARSET)
+    } else {
+      self.default_charset.as_deref()
+    };
+
+    match charset {
+      Some(charset) => format!("{}; charset={}", mime, charset),
+      None => mime.to_string(),
+    }
+  }
+
+  fn is_text(mime: &str) -> bool {
+    mime.starts_with("text/")
+      || ["application/javascript", "application/json", "application/xml", "image/svg+xml"]
+        .contains(&mime)
+  }
+}
+
+impl Default for MimeTypes {
+  fn default() -> Self {
+    Self::new(Some(DEFAULT_CHARSET.to_string()))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use expectest::prelude::*;
+  use rstest::*;
+
+  #[rstest]
+  #[case::html("index.html", b"<html>", "text/html; charset=utf-8")]
+  #[case::css("style.css", b"body {}", "text/css; charset=utf-8")]
+  #[case::js("app.js", b"let a;", "application/javascript; charset=utf-8")]
+  #[case::svg("logo.svg", b"<svg>", "image/svg+xml; charset=utf-8")]
+  #[case::binary("favicon.ico", b"\x00\x01", "image/x-icon")]
+  fn test_default_charset(#[case] path: &str, #[case] body: &[u8], #[case] expected: &str) {
+    expect!(MimeTypes::default().content_type(path, body)).to(be_equal_to(expected));
+  }
+
+  #[rstest]
+  fn test_configured_charset() {
+    let mime_types = MimeTypes::new(Some("iso-8859-1".to_string()));
+    expect!(mime_types.content_type("index.html", b"<html>"))
+      .to(be_equal_to("text/html; charset=iso-8859-1"));
+  }
+
+  #[rstest]
+  fn test_bom_overrides_configured_charset() {
+    let mime_types = MimeTypes::new(None);
+    let body = [UTF8_BOM, b"<html>"].concat();
+    expect!(mime_types.content_type("index.html", b"<html>")).to(be_equal_to("text/html"));
+    expect!(mime_types.content_type("index.html", &body))
+      .to(be_equal_to("text/html; charset=utf-8"));
+  }
+
+  #[rstest]
+  #[case::added("playlist.M3U8", "application/vnd.apple.mpegurl")]
+  #[case::replaced("data.bin", "application/x-custom")]
+  #[case::built_in("favicon.ico", "image/x-icon")]
+  fn test_overrides(#[case] path: &str, #[case] expected: &str) {
+    let overrides = HashMap::from([
+      (
+        ".m3u8".to_string(),
+        "application/vnd.apple.mpegurl".to_string(),
+      ),
+      ("bin".to_string(), "application/x-custom".to_string()),
+    ]);
+    let mime_types = MimeTypes::default().with_overrides(overrides);
+    expect!(mime_types.mime_type(path)).to(be_equal_to(expected));
+  }
+}
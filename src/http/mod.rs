@@ -1,16 +1,51 @@
 // required to expose to rustc the structure of the module represented by the directory
 
 // export sub-module structs directly from the parent module
+#[cfg(feature = "server")]
+pub use body::RequestBody;
+pub use connection_info::ConnectionInfo;
+pub use extensions::Extensions;
+pub use handler_error::HandlerError;
 pub use method::Method;
-pub use query_string::QueryString;
+pub use mime::MimeTypes;
+pub use query_string::{QueryLimits, QueryString};
 pub use request::HttpRequest;
+pub use request::OwnedHttpRequest;
 pub use request::ParseError;
+pub use request::ParserLimits;
+pub use request::TargetForm;
 pub use response::HttpResponse;
 pub use status_code::StatusCode;
+pub use trace_context::TraceContext;
+pub use version::Version;
 
+#[cfg(feature = "server")]
+pub mod body;
+pub mod chunked;
+pub mod codec;
+pub mod connection_info;
+pub mod content_coding;
+pub mod cookie;
+#[cfg(feature = "server")]
+pub mod event_stream;
+pub mod extensions;
+pub mod forwarded;
+pub mod handler_error;
 pub mod header;
+pub mod hsts;
+pub mod json;
 pub mod method;
+pub mod mime;
+pub mod multipart;
+pub mod path_pattern;
+pub mod percent_encoding;
 pub mod query_string;
 pub mod request;
 pub mod response;
 pub mod status_code;
+pub mod trace_context;
+pub mod typed_header;
+#[cfg(feature = "server")]
+pub mod upgrade;
+pub mod version;
+pub mod websocket;
@@ -0,0 +1,600 @@
+//! `multipart/form-data` bodies (RFC 7578), as browsers send file uploads. Like
+//! [`super::chunked`], [`MultipartDecoder`] only looks at bytes; [`MultipartReader`] reads
+//! them from a [`std::io::Read`] such as a [`super::RequestBody`].
+
+use std::io::{self, Read};
+use thiserror::Error;
+
+use super::{
+  header::{HttpHeader, HttpRequestHeaderKey},
+  StatusCode,
+};
+
+/// Bytes read from the underlying reader at a time by [`MultipartReader`]
+const READ_CHUNK_SIZE: usize = 8 * 1024;
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum MultipartError {
+  #[error("Expected a multipart/form-data body, got Content-Type {0:?}")]
+  NotMultipart(Option<String>),
+  #[error("Multipart Content-Type without a valid boundary")]
+  MissingBoundary,
+  #[error("Multipart boundary not followed by a line break or --")]
+  InvalidDelimiter,
+  #[error("Invalid part header: {0}")]
+  InvalidPartHeader(String),
+  #[error("Part headers longer than {0} bytes")]
+  HeadersTooLarge(usize),
+  #[error("Part larger than {0} bytes")]
+  PartTooLarge(u64),
+  #[error("More than {0} parts")]
+  TooManyParts(usize),
+  #[error("Body ended before the closing boundary")]
+  Truncated,
+}
+
+impl MultipartError {
+  /// The status a client receives when its body can't be decoded
+  pub fn status_code(&self) -> StatusCode {
+    match self {
+      Self::NotMultipart(_) => StatusCode::UnsupportedMediaType,
+      Self::PartTooLarge(_) | Self::TooManyParts(_) => StatusCode::PayloadTooLarge,
+      _ => StatusCode::BadRequest,
+    }
+  }
+}
+
+impl From<MultipartError> for io::Error {
+  fn from(error: MultipartError) -> Self {
+    io::Error::new(io::ErrorKind::InvalidData, error)
+  }
+}
+
+/// Bounds on what a single body may make the decoder accept
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MultipartLimits {
+  /// data bytes of each part, a part's headers don't count
+  pub max_part_size: u64,
+  pub max_parts: usize,
+  /// headers of each part, including their line breaks
+  pub max_header_size: usize,
+}
+
+impl Default for MultipartLimits {
+  fn default() -> Self {
+    Self {
+      max_part_size: 10 * 1024 * 1024,
+      max_parts: 100,
+      max_header_size: 8 * 1024,
+    }
+  }
+}
+
+/// The boundary a `multipart/form-data` `Content-Type` in `header` declares
+pub fn boundary(header: &HttpHeader) -> Result<String, MultipartError> {
+  if header.media_type().as_deref() != Some("multipart/form-data") {
+    let content_type = header.get(HttpRequestHeaderKey::ContentType);
+    return Err(MultipartError::NotMultipart(content_type.cloned()));
+  }
+  let content_type = header
+    .get(HttpRequestHeaderKey::ContentType)
+    .ok_or(MultipartError::MissingBoundary)?;
+  // RFC 2046, section 5.1.1: 1 to 70 characters, not ending in a space
+  parameter(content_type, "boundary")
+    .filter(|boundary| (1..=70).contains(&boundary.len()) && !boundary.ends_with(' '))
+    .ok_or(MultipartError::MissingBoundary)
+}
+
+/// The value of the `name` parameter of a header value such as
+/// `form-data; name="file"; filename="a;b.txt"`, unquoted
+fn parameter(value: &str, name: &str) -> Option<String> {
+  let mut rest = value.split_once(';')?.1;
+  loop {
+    let (key, after_key) = rest.split_once('=')?;
+    let after_key = after_key.trim_start();
+    let (parameter, after_value) = match after_key.strip_prefix('"') {
+      Some(quoted) => {
+        let mut unquoted = String::new();
+        let mut chars = quoted.char_indices();
+        let end = loop {
+          match chars.next()? {
+            (_, '\\') => unquoted.push(chars.next()?.1),
+            (end, '"') => break end,
+            (_, char) => unquoted.push(char),
+          }
+        };
+        let after_value = &quoted[end + 1..];
+        (
+          unquoted,
+          after_value.split_once(';').map_or("", |(_, next)| next),
+        )
+      }
+      None => match after_key.split_once(';') {
+        Some((parameter, next)) => (parameter.trim().to_string(), next),
+        None => (after_key.trim().to_string(), ""),
+      },
+    };
+    if key.trim().eq_ignore_ascii_case(name) {
+      return Some(parameter);
+    }
+    if after_value.is_empty() {
+      return None;
+    }
+    rest = after_value;
+  }
+}
+
+/// The headers of one part, with what its `Content-Disposition` names
+#[derive(Debug, Clone)]
+pub struct Part {
+  header: HttpHeader,
+  name: Option<String>,
+  filename: Option<String>,
+}
+
+impl Part {
+  fn new(header: HttpHeader) -> Self {
+    let disposition = header.get("Content-Disposition");
+    let name = disposition.and_then(|disposition| parameter(disposition, "name"));
+    let filename = disposition.and_then(|disposition| parameter(disposition, "filename"));
+    Self { header, name, filename }
+  }
+
+  pub fn header(&self) -> &HttpHeader {
+    &self.header
+  }
+
+  /// The form field the part belongs to
+  pub fn name(&self) -> Option<&str> {
+    self.name.as_deref()
+  }
+
+  /// The uploaded file's name as the client sent it. Never use it as a path unchecked, it can
+  /// contain `..` or separators.
+  pub fn filename(&self) -> Option<&str> {
+    self.filename.as_deref()
+  }
+
+  /// `text/plain` unless the part says otherwise (RFC 7578, section 4.4)
+  pub fn content_type(&self) -> &str {
+    self
+      .header
+      .get(HttpRequestHeaderKey::ContentType)
+      .map_or("text/plain", String::as_str)
+  }
+}
+
+/// What [`MultipartDecoder::decode`] found at the start of its input
+#[derive(Debug)]
+pub enum Event<'buf> {
+  /// A part starts, its data follows
+  Part(Box<Part>),
+  /// Data of the current part, in the order received
+  Data(&'buf [u8]),
+  /// The closing boundary, anything after it is ignored
+  End,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+  Preamble,
+  Delimiter,
+  Headers,
+  Data,
+  Done,
+}
+
+/// Splits a multipart body arriving in pieces of any size into parts and their data,
+/// without buffering either: data is handed out as soon as it can't be the start of a
+/// boundary, so parts of any size can be streamed to their destination.
+///
+/// ```
+/// use udemy_server::http::multipart::{Event, MultipartDecoder, MultipartLimits};
+///
+/// let body = b"--x\r\nContent-Disposition: form-data; name=\"a\"\r\n\r\nhello\r\n--x--\r\n";
+/// let mut decoder = MultipartDecoder::new("x", MultipartLimits::default());
+/// let (event, consumed) = decoder.decode(body).unwrap();
+/// assert!(matches!(event, Some(Event::Part(part)) if part.name() == Some("a")));
+/// let (event, _) = decoder.decode(&body[consumed..]).unwrap();
+/// assert!(matches!(event, Some(Event::Data(b"hello"))));
+/// ```
+#[derive(Debug, Clone)]
+pub struct MultipartDecoder {
+  /// `\r\n--boundary`, what ends the data of a part
+  delimiter: Vec<u8>,
+  limits: MultipartLimits,
+  state: State,
+  parts: usize,
+  part_size: u64,
+}
+
+impl MultipartDecoder {
+  pub fn new(boundary: &str, limits: MultipartLimits) -> Self {
+    Self {
+      delimiter: format!("\r\n--{}", boundary).into_bytes(),
+      limits,
+      state: State::Preamble,
+      parts: 0,
+      part_size: 0,
+    }
+  }
+
+  /// Whether the closing boundary has been decoded
+  pub fn is_done(&self) -> bool {
+    self.state == State::Done
+  }
+
+  /// The next event at the start of `input`, `None` until enough input has arrived, and how
+  /// many of its bytes were consumed, possibly some even without an event. Consumed bytes
+  /// must not be passed in again.
+  pub fn decode<'buf>(
+    &mut self,
+    input: &'buf [u8],
+  ) -> Result<(Option<Event<'buf>>, usize), MultipartError> {
+    let mut consumed = 0;
+    loop {
+      let input = &input[consumed..];
+      match self.state {
+        State::Preamble => {
+          // the first boundary may start the body, without a line break before it
+          let dash_boundary = &self.delimiter[2..];
+          match find(input, dash_boundary) {
+            Some(start) => {
+              consumed += start + dash_boundary.len();
+              self.state = State::Delimiter;
+            }
+            // bounded like headers, as it can't be consumed until the boundary shows up
+            None if input.len() > self.limits.max_header_size => {
+              return Err(MultipartError::InvalidDelimiter)
+            }
+            None => return Ok((None, consumed)),
+          }
+        }
+        State::Delimiter => {
+          if input.starts_with(b"--") {
+            self.state = State::Done;
+            return Ok((Some(Event::End), consumed + 2));
+          }
+          let Some(end) = input.iter().position(|&byte| byte == b'\n') else {
+            return match input.len() > self.limits.max_header_size {
+              true => Err(MultipartError::InvalidDelimiter),
+              false => Ok((None, consumed)),
+            };
+          };
+          // transport padding may follow the boundary (RFC 2046, section 5.1.1)
+          if !input[..end]
+            .iter()
+            .all(|&byte| matches!(byte, b' ' | b'\t' | b'\r'))
+          {
+            return Err(MultipartError::InvalidDelimiter);
+          }
+          consumed += end + 1;
+          self.state = State::Headers;
+        }
+        State::Headers => {
+          let header_length = match input.starts_with(b"\r\n") {
+            true => Some(2),
+            false => find(input, b"\r\n\r\n").map(|end| end + 4),
+          };
+          let Some(header_length) = header_length else {
+            return match input.len() > self.limits.max_header_size {
+              true => Err(MultipartError::HeadersTooLarge(self.limits.max_header_size)),
+              false => Ok((None, consumed)),
+            };
+          };
+          if header_length > self.limits.max_header_size {
+            return Err(MultipartError::HeadersTooLarge(self.limits.max_header_size));
+          }
+          if self.parts >= self.limits.max_parts {
+            return Err(MultipartError::TooManyParts(self.limits.max_parts));
+          }
+          let header = match header_length {
+            2 => HttpHeader::default(),
+            _ => HttpHeader::try_from(&input[..header_length])
+              .map_err(|error| MultipartError::InvalidPartHeader(error.to_string()))?,
+          };
+          self.parts += 1;
+          self.part_size = 0;
+          self.state = State::Data;
+          return Ok((
+            Some(Event::Part(Box::new(Part::new(header)))),
+            consumed + header_length,
+          ));
+        }
+        State::Data => {
+          let (length, delimited) = match find(input, &self.delimiter) {
+            Some(start) => (start, true),
+            None => (input.len() - partial_suffix(input, &self.delimiter), false),
+          };
+          if length > 0 {
+            self.part_size += length as u64;
+            if self.part_size > self.limits.max_part_size {
+              return Err(MultipartError::PartTooLarge(self.limits.max_part_size));
+            }
+            return Ok((Some(Event::Data(&input[..length])), consumed + length));
+          }
+          if !delimited {
+            return Ok((None, consumed));
+          }
+          consumed += self.delimiter.len();
+          self.state = State::Delimiter;
+        }
+        State::Done => return Ok((None, consumed)),
+      }
+    }
+  }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+  haystack
+    .windows(needle.len())
+    .position(|window| window == needle)
+}
+
+/// The length of the longest end of `input` that `delimiter` could continue
+fn partial_suffix(input: &[u8], delimiter: &[u8]) -> usize {
+  (1..delimiter.len().min(input.len() + 1))
+    .rev()
+    .find(|&length| input.ends_with(&delimiter[..length]))
+    .unwrap_or(0)
+}
+
+/// Reads a multipart body part by part from blocking code, as handlers are, e.g. in
+/// [`crate::server::Handler::handle_with_body`]. Reading from it yields the data of the
+/// current part, and ends with it.
+///
+/// ```
+/// use std::io::Read;
+/// use udemy_server::http::multipart::{MultipartLimits, MultipartReader};
+///
+/// let body = &b"--x\r\nContent-Disposition: form-data; name=\"file\"; filename=\"a.txt\"\r\n\r\nhello\r\n--x--"[..];
+/// let mut reader = MultipartReader::new(body, "x", MultipartLimits::default());
+/// let part = reader.next_part().unwrap().unwrap();
+/// assert_eq!(part.filename(), Some("a.txt"));
+/// let mut data = String::new();
+/// reader.read_to_string(&mut data).unwrap();
+/// assert_eq!(data, "hello");
+/// assert!(reader.next_part().unwrap().is_none());
+/// ```
+pub struct MultipartReader<R> {
+  reader: R,
+  decoder: MultipartDecoder,
+  /// read but not decoded yet
+  buffer: Vec<u8>,
+  /// decoded data the caller hasn't read yet
+  pending: usize,
+  /// a part decoded while reading the previous one's data
+  next: Option<Part>,
+  reader_done: bool,
+}
+
+impl<R: Read> MultipartReader<R> {
+  pub fn new(reader: R, boundary: &str, limits: MultipartLimits) -> Self {
+    Self {
+      reader,
+      decoder: MultipartDecoder::new(boundary, limits),
+      buffer: Vec::new(),
+      pending: 0,
+      next: None,
+      reader_done: false,
+    }
+  }
+
+  /// The next part, skipping whatever of the current one's data hasn't been read; `None`
+  /// after the last one
+  pub fn next_part(&mut self) -> Result<Option<Part>, MultipartError> {
+    if let Some(part) = self.next.take() {
+      return Ok(Some(part));
+    }
+    self.buffer.drain(..self.pending);
+    self.pending = 0;
+    loop {
+      match self.next_event()? {
+        Some(Step::Part(part)) => return Ok(Some(part)),
+        Some(Step::Data(length)) => {
+          self.buffer.drain(..length);
+        }
+        None => return Ok(None),
+      }
+    }
+  }
+
+  /// Decode the next event from the buffer, reading more as needed
+  fn next_event(&mut self) -> Result<Option<Step>, MultipartError> {
+    loop {
+      if self.decoder.is_done() {
+        return Ok(None);
+      }
+      let (event, consumed) = self.decoder.decode(&self.buffer)?;
+      match event {
+        Some(Event::Part(part)) => {
+          self.buffer.drain(..consumed);
+          return Ok(Some(Step::Part(*part)));
+        }
+        Some(Event::Data(data)) => {
+          let length = data.len();
+          self.buffer.drain(..consumed - length);
+          return Ok(Some(Step::Data(length)));
+        }
+        Some(Event::End) => {
+          self.buffer.clear();
+          return Ok(None);
+        }
+        None if self.reader_done => return Err(MultipartError::Truncated),
+        None => {
+          self.buffer.drain(..consumed);
+          self.fill_buffer().map_err(|_| MultipartError::Truncated)?;
+        }
+      }
+    }
+  }
+
+  fn fill_buffer(&mut self) -> io::Result<()> {
+    let length = self.buffer.len();
+    self.buffer.resize(length + READ_CHUNK_SIZE, 0);
+    let read = self.reader.read(&mut self.buffer[length..]);
+    self
+      .buffer
+      .truncate(length + read.as_ref().map_or(0, |read| *read));
+    self.reader_done = read? == 0;
+    Ok(())
+  }
+}
+
+enum Step {
+  Part(Part),
+  /// bytes of data at the start of the buffer
+  Data(usize),
+}
+
+impl<R: Read> Read for MultipartReader<R> {
+  fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+    if self.pending == 0 {
+      if self.next.is_some() {
+        return Ok(0);
+      }
+      match self.next_event()? {
+        Some(Step::Data(length)) => self.pending = length,
+        Some(Step::Part(part)) => {
+          self.next = Some(part);
+          return Ok(0);
+        }
+        None => return Ok(0),
+      }
+    }
+    let length = buf.len().min(self.pending);
+    buf[..length].copy_from_slice(&self.buffer[..length]);
+    self.buffer.drain(..length);
+    self.pending -= length;
+    Ok(length)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::http::header::HttpRequestHeaderBuilder;
+  use expectest::prelude::*;
+  use rstest::*;
+
+  const BODY: &[u8] = b"preamble\r\n--b0undary\r\n\
+Content-Disposition: form-data; name=\"title\"\r\n\r\n\
+Holiday\r\n--b0undary  \r\n\
+Content-Disposition: form-data; name=\"photo\"; filename=\"beach;1.jpg\"\r\n\
+Content-Type: image/jpeg\r\n\r\n\
+\xff\xd8\r\n--b0und\xff\xd9\r\n--b0undary--\r\nepilogue";
+
+  /// Name, filename and data of a decoded part
+  type Decoded = (Option<String>, Option<String>, Vec<u8>);
+
+  /// Decode `input` `piece_size` bytes at a time
+  fn decode(
+    input: &[u8],
+    piece_size: usize,
+    limits: MultipartLimits,
+  ) -> Result<Vec<Decoded>, MultipartError> {
+    let mut decoder = MultipartDecoder::new("b0undary", limits);
+    let mut parts = Vec::new();
+    let (mut consumed, mut received) = (0, 0);
+    while !decoder.is_done() {
+      received = input
+        .len()
+        .min(usize::max(received, consumed).saturating_add(piece_size));
+      let (event, length) = decoder.decode(&input[consumed..received])?;
+      consumed += length;
+      match event {
+        Some(Event::Part(part)) => {
+          parts.push((part.name.clone(), part.filename.clone(), Vec::new()));
+        }
+        Some(Event::Data(data)) => parts.last_mut().unwrap().2.extend_from_slice(data),
+        Some(Event::End) => {}
+        None if received == input.len() => return Err(MultipartError::Truncated),
+        None => {}
+      }
+    }
+    Ok(parts)
+  }
+
+  #[rstest]
+  #[case::whole(usize::MAX)]
+  #[case::byte_by_byte(1)]
+  #[case::pieces(7)]
+  fn test_decode(#[case] piece_size: usize) -> Result<(), MultipartError> {
+    let parts = decode(BODY, piece_size, MultipartLimits::default())?;
+    expect!(parts).to(be_equal_to(vec![
+      (Some("title".to_string()), None, b"Holiday".to_vec()),
+      (
+        Some("photo".to_string()),
+        Some("beach;1.jpg".to_string()),
+        b"\xff\xd8\r\n--b0und\xff\xd9".to_vec(),
+      ),
+    ]));
+    Ok(())
+  }
+
+  #[rstest]
+  #[case::part_too_large(
+    MultipartLimits { max_part_size: 4, ..MultipartLimits::default() },
+    MultipartError::PartTooLarge(4)
+  )]
+  #[case::too_many_parts(
+    MultipartLimits { max_parts: 1, ..MultipartLimits::default() },
+    MultipartError::TooManyParts(1)
+  )]
+  #[case::headers_too_large(
+    MultipartLimits { max_header_size: 16, ..MultipartLimits::default() },
+    MultipartError::HeadersTooLarge(16)
+  )]
+  fn test_limits(#[case] limits: MultipartLimits, #[case] expected: MultipartError) {
+    expect!(decode(BODY, usize::MAX, limits)).to(be_err().value(expected));
+  }
+
+  #[rstest]
+  #[case::truncated(&b"--b0undary\r\n\r\nabc"[..], MultipartError::Truncated)]
+  #[case::garbage_after_boundary(&b"--b0undaryX\r\n\r\nabc"[..], MultipartError::InvalidDelimiter)]
+  fn test_reject_malformed(#[case] input: &[u8], #[case] expected: MultipartError) {
+    expect!(decode(input, usize::MAX, MultipartLimits::default())).to(be_err().value(expected));
+  }
+
+  #[rstest]
+  fn test_read_parts() -> Result<(), MultipartError> {
+    // reads as small as the body arrives in
+    let body = BODY.chunks(3).map(<[u8]>::to_vec).collect::<Vec<_>>();
+    let reader = body
+      .iter()
+      .fold(Box::new(io::empty()) as Box<dyn Read>, |reader, chunk| {
+        Box::new(reader.chain(io::Cursor::new(chunk.clone())))
+      });
+    let mut reader = MultipartReader::new(reader, "b0undary", MultipartLimits::default());
+
+    // the title is skipped without reading it
+    let title = reader.next_part()?.unwrap();
+    expect!(title.content_type()).to(be_equal_to("text/plain"));
+    let photo = reader.next_part()?.unwrap();
+    expect!(photo.content_type()).to(be_equal_to("image/jpeg"));
+    let mut data = Vec::new();
+    reader.read_to_end(&mut data).unwrap();
+    expect!(data).to(be_equal_to(b"\xff\xd8\r\n--b0und\xff\xd9".to_vec()));
+    expect!(reader.next_part()?.is_none()).to(be_true());
+    Ok(())
+  }
+
+  #[rstest]
+  #[case::quoted("multipart/form-data; boundary=\"a b\"", Ok("a b"))]
+  #[case::token("Multipart/Form-Data; charset=utf-8; boundary=xyz", Ok("xyz"))]
+  #[case::missing("multipart/form-data", Err(MultipartError::MissingBoundary))]
+  #[case::too_long(
+    "multipart/form-data; boundary=aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa",
+    Err(MultipartError::MissingBoundary)
+  )]
+  #[case::not_multipart(
+    "text/plain",
+    Err(MultipartError::NotMultipart(Some("text/plain".to_string())))
+  )]
+  fn test_boundary(#[case] content_type: &str, #[case] expected: Result<&str, MultipartError>) {
+    let header = HttpRequestHeaderBuilder::new()
+      .content_type(content_type)
+      .build();
+    expect!(boundary(&header)).to(be_equal_to(expected.map(str::to_string)));
+  }
+}
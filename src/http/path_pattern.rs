@@ -0,0 +1,207 @@
+//! Path patterns such as `/users/{id}` or `/static/*`, matched against request paths by
+//! [`crate::router::Router`]. Like [`super::codec`], nothing here depends on the server.
+
+use std::{borrow::Cow, str::FromStr};
+use thiserror::Error;
+
+/// Name under which [`PathParams`] holds what a trailing `*` matched
+pub const WILDCARD: &str = "*";
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum PatternError {
+  #[error("Path pattern {0} doesn't start with a /")]
+  MissingLeadingSlash(String),
+  #[error("Path pattern {0} has a * that isn't its last segment")]
+  MisplacedWildcard(String),
+  #[error("Path pattern {0} has a parameter without a name")]
+  UnnamedParam(String),
+  #[error("Path pattern {0} names parameter {1} more than once")]
+  DuplicateParam(String, String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Segment {
+  Literal(String),
+  Param(String),
+  Wildcard,
+}
+
+/// A request path pattern made of `/`-separated segments: literals match themselves, a
+/// `{name}` segment matches any non-empty segment and captures it as `name`, and a trailing
+/// `*` matches the rest of the path, possibly nothing, captured as [`WILDCARD`].
+///
+/// ```
+/// use udemy_server::http::path_pattern::PathPattern;
+///
+/// let pattern = "/users/{id}/files/*".parse::<PathPattern>().unwrap();
+/// let params = pattern.matches("/users/42/files/docs/cv.pdf").unwrap();
+/// assert_eq!(params.get("id"), Some("42"));
+/// assert_eq!(params.wildcard(), Some("docs/cv.pdf"));
+/// assert!(pattern.matches("/users/42").is_none());
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PathPattern {
+  pattern: String,
+  segments: Vec<Segment>,
+}
+
+impl FromStr for PathPattern {
+  type Err = PatternError;
+
+  fn from_str(pattern: &str) -> Result<Self, Self::Err> {
+    let path = pattern
+      .strip_prefix('/')
+      .ok_or_else(|| PatternError::MissingLeadingSlash(pattern.to_string()))?;
+    let mut segments = Vec::new();
+    for segment in path.split('/') {
+      if segments.last() == Some(&Segment::Wildcard) {
+        return Err(PatternError::MisplacedWildcard(pattern.to_string()));
+      }
+      segments.push(match segment {
+        "*" => Segment::Wildcard,
+        segment => match segment
+          .strip_prefix('{')
+          .and_then(|param| param.strip_suffix('}'))
+        {
+          Some("") => return Err(PatternError::UnnamedParam(pattern.to_string())),
+          Some(name) if segments.contains(&Segment::Param(name.to_string())) => {
+            return Err(PatternError::DuplicateParam(
+              pattern.to_string(),
+              name.to_string(),
+            ))
+          }
+          Some(name) => Segment::Param(name.to_string()),
+          None => Segment::Literal(segment.to_string()),
+        },
+      });
+    }
+    Ok(Self { pattern: pattern.to_string(), segments })
+  }
+}
+
+impl PathPattern {
+  /// The pattern as it was written
+  pub fn as_str(&self) -> &str {
+    &self.pattern
+  }
+
+  /// The parameters captured from `path`, `None` if it doesn't match
+  pub fn matches<'buf>(&self, path: &'buf str) -> Option<PathParams<'buf>> {
+    let mut params = PathParams::default();
+    // `None` once all of the path's segments have been matched
+    let mut rest = Some(path.strip_prefix('/')?);
+    for segment in &self.segments {
+      if *segment == Segment::Wildcard {
+        params.params.push((
+          WILDCARD.to_string(),
+          Cow::Borrowed(rest.unwrap_or_default()),
+        ));
+        return Some(params);
+      }
+      let (current, next) = match rest?.split_once('/') {
+        Some((current, next)) => (current, Some(next)),
+        None => (rest?, None),
+      };
+      match segment {
+        Segment::Literal(literal) if literal == current => {}
+        Segment::Param(name) if !current.is_empty() => {
+          params.params.push((name.clone(), Cow::Borrowed(current)));
+        }
+        _ => return None,
+      }
+      rest = next;
+    }
+    rest.is_none().then_some(params)
+  }
+}
+
+/// What a [`PathPattern`] captured from a request path, by parameter name
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PathParams<'buf> {
+  params: Vec<(String, Cow<'buf, str>)>,
+}
+
+impl<'buf> PathParams<'buf> {
+  pub fn get(&self, name: &str) -> Option<&str> {
+    self
+      .params
+      .iter()
+      .find(|(param, _)| param == name)
+      .map(|(_, value)| value.as_ref())
+  }
+
+  /// The rest of the path matched by a trailing `*`
+  pub fn wildcard(&self) -> Option<&str> {
+    self.get(WILDCARD)
+  }
+
+  pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+    self
+      .params
+      .iter()
+      .map(|(name, value)| (name.as_str(), value.as_ref()))
+  }
+
+  /// The same parameters, no longer borrowing the matched path
+  pub fn into_owned(self) -> PathParams<'static> {
+    let params = self
+      .params
+      .into_iter()
+      .map(|(name, value)| (name, Cow::Owned(value.into_owned())))
+      .collect();
+    PathParams { params }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use expectest::prelude::*;
+  use rstest::*;
+
+  #[rstest]
+  #[case::root("/", "/", Some(vec![]))]
+  #[case::root_only("/", "/index.html", None)]
+  #[case::literal("/hello", "/hello", Some(vec![]))]
+  #[case::trailing_slash("/hello", "/hello/", None)]
+  #[case::param("/users/{id}", "/users/42", Some(vec![("id", "42")]))]
+  #[case::empty_param("/users/{id}", "/users/", None)]
+  #[case::params("/{a}/x/{b}", "/1/x/2", Some(vec![("a", "1"), ("b", "2")]))]
+  #[case::too_short("/users/{id}/posts", "/users/42", None)]
+  #[case::wildcard("/static/*", "/static/css/site.css", Some(vec![("*", "css/site.css")]))]
+  #[case::empty_wildcard("/static/*", "/static", Some(vec![("*", "")]))]
+  #[case::wildcard_prefix("/static/*", "/statics/site.css", None)]
+  #[case::everything("/*", "/", Some(vec![("*", "")]))]
+  fn test_matches(
+    #[case] pattern: &str,
+    #[case] path: &str,
+    #[case] expected: Option<Vec<(&str, &str)>>,
+  ) -> Result<(), PatternError> {
+    let params = pattern.parse::<PathPattern>()?.matches(path).map(|params| {
+      params
+        .iter()
+        .map(|(name, value)| (name.to_string(), value.to_string()))
+        .collect::<Vec<_>>()
+    });
+    let expected = expected.map(|expected| {
+      expected
+        .into_iter()
+        .map(|(name, value)| (name.to_string(), value.to_string()))
+        .collect::<Vec<_>>()
+    });
+    expect!(params).to(be_equal_to(expected));
+    Ok(())
+  }
+
+  #[rstest]
+  #[case::relative("users", PatternError::MissingLeadingSlash("users".to_string()))]
+  #[case::inner_wildcard("/*/x", PatternError::MisplacedWildcard("/*/x".to_string()))]
+  #[case::unnamed("/users/{}", PatternError::UnnamedParam("/users/{}".to_string()))]
+  #[case::duplicate(
+    "/{id}/{id}",
+    PatternError::DuplicateParam("/{id}/{id}".to_string(), "id".to_string())
+  )]
+  fn test_reject_invalid_pattern(#[case] pattern: &str, #[case] expected: PatternError) {
+    expect!(pattern.parse::<PathPattern>()).to(be_err().value(expected));
+  }
+}
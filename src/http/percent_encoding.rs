@@ -0,0 +1,141 @@
+use std::borrow::Cow;
+use thiserror::Error;
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum DecodeError {
+  #[error("Malformed percent escape at byte {0}")]
+  MalformedEscape(usize),
+  #[error("Percent escapes don't decode to UTF-8")]
+  InvalidUtf8,
+}
+
+/// Decode `%XX` escapes (RFC 3986) and, for `application/x-www-form-urlencoded` data,
+/// `+` as space. Input without anything to decode is returned borrowed, and malformed
+/// escapes are kept verbatim.
+pub fn decode(input: &str, plus_as_space: bool) -> Cow<'_, str> {
+  if !needs_decoding(input, plus_as_space) {
+    return Cow::Borrowed(input);
+  }
+  let decoded = unescape(input, plus_as_space, false).unwrap_or_default();
+  match String::from_utf8(decoded) {
+    Ok(decoded) => Cow::Owned(decoded),
+    Err(error) => Cow::Owned(String::from_utf8_lossy(error.as_bytes()).into_owned()),
+  }
+}
+
+/// Like [`decode`], but malformed escapes and escapes that don't decode to UTF-8 are errors,
+/// for request targets that are better refused than guessed at
+pub fn try_decode(input: &str, plus_as_space: bool) -> Result<Cow<'_, str>, DecodeError> {
+  if !needs_decoding(input, plus_as_space) {
+    return Ok(Cow::Borrowed(input));
+  }
+  let decoded = unescape(input, plus_as_space, true)?;
+  String::from_utf8(decoded)
+    .map(Cow::Owned)
+    .map_err(|_| DecodeError::InvalidUtf8)
+}
+
+/// Encode `input` to be a single path segment: everything but unreserved characters
+/// (RFC 3986, section 2.3) is escaped, `/` included
+pub fn encode_segment(input: &str) -> Cow<'_, str> {
+  let unreserved = |byte: u8| byte.is_ascii_alphanumeric() || b"-._~".contains(&byte);
+  if input.bytes().all(unreserved) {
+    return Cow::Borrowed(input);
+  }
+  let encoded = input.bytes().fold(String::new(), |mut encoded, byte| {
+    match unreserved(byte) {
+      true => encoded.push(byte as char),
+      false => encoded.push_str(&format!("%{:02X}", byte)),
+    }
+    encoded
+  });
+  Cow::Owned(encoded)
+}
+
+fn needs_decoding(input: &str, plus_as_space: bool) -> bool {
+  input
+    .bytes()
+    .any(|byte| byte == b'%' || (plus_as_space && byte == b'+'))
+}
+
+/// The bytes `input` encodes; malformed escapes are kept verbatim unless `strict`
+fn unescape(input: &str, plus_as_space: bool, strict: bool) -> Result<Vec<u8>, DecodeError> {
+  let bytes = input.as_bytes();
+  let mut decoded = Vec::with_capacity(bytes.len());
+  let mut i = 0;
+  while i < bytes.len() {
+    match bytes[i] {
+      b'%' => match (
+        bytes.get(i + 1).and_then(hex_value),
+        bytes.get(i + 2).and_then(hex_value),
+      ) {
+        (Some(high), Some(low)) => {
+          decoded.push(high << 4 | low);
+          i += 3;
+          continue;
+        }
+        _ if strict => return Err(DecodeError::MalformedEscape(i)),
+        _ => decoded.push(b'%'),
+      },
+      b'+' if plus_as_space => decoded.push(b' '),
+      byte => decoded.push(byte),
+    }
+    i += 1;
+  }
+  Ok(decoded)
+}
+
+fn hex_value(byte: &u8) -> Option<u8> {
+  (*byte as char).to_digit(16).map(|digit| digit as u8)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use expectest::prelude::*;
+  use rstest::*;
+
+  #[rstest]
+  #[case::plain("plain", true, "plain")]
+  #[case::plus_as_space("John+Doe%21", true, "John Doe!")]
+  #[case::plus_kept("John+Doe%21", false, "John+Doe!")]
+  #[case::multibyte("caf%C3%A9", true, "café")]
+  #[case::lowercase_hex("a%2fb", true, "a/b")]
+  #[case::malformed("100%zz%4", true, "100%zz%4")]
+  #[case::invalid_utf8("%FF", true, "\u{FFFD}")]
+  fn test_decode(#[case] input: &str, #[case] plus_as_space: bool, #[case] expected: &str) {
+    expect!(decode(input, plus_as_space).as_ref()).to(be_equal_to(expected));
+  }
+
+  #[rstest]
+  #[case::plain("/hello", false, Ok("/hello"))]
+  #[case::space("/hello%20world", false, Ok("/hello world"))]
+  #[case::plus_kept("/a+b", false, Ok("/a+b"))]
+  #[case::plus_as_space("John%26Jane+Doe", true, Ok("John&Jane Doe"))]
+  #[case::truncated("100%4", true, Err(DecodeError::MalformedEscape(3)))]
+  #[case::not_hex("%zz", true, Err(DecodeError::MalformedEscape(0)))]
+  #[case::invalid_utf8("%FF", false, Err(DecodeError::InvalidUtf8))]
+  fn test_try_decode(
+    #[case] input: &str,
+    #[case] plus_as_space: bool,
+    #[case] expected: Result<&str, DecodeError>,
+  ) {
+    let decoded = try_decode(input, plus_as_space);
+    expect!(decoded.as_deref().map_err(Clone::clone)).to(be_equal_to(expected));
+  }
+
+  #[rstest]
+  #[case::plain("site.css", "site.css")]
+  #[case::space_and_slash("my notes/a.txt", "my%20notes%2Fa.txt")]
+  #[case::multibyte("café", "caf%C3%A9")]
+  #[case::round_trip("100%", "100%25")]
+  fn test_encode_segment(#[case] input: &str, #[case] expected: &str) {
+    expect!(encode_segment(input).as_ref()).to(be_equal_to(expected));
+    expect!(try_decode(expected, false).as_deref()).to(be_ok().value(input));
+  }
+
+  #[rstest]
+  fn test_decode_borrows_when_nothing_to_decode() {
+    expect!(matches!(decode("name", true), Cow::Borrowed("name"))).to(be_true());
+  }
+}
@@ -1,47 +1,259 @@
-use std::collections::HashMap;
+use serde::{Deserialize, Serialize};
+use std::{borrow::Cow, collections::HashMap, convert::TryFrom};
 
-#[derive(Debug, PartialEq)]
+use super::{
+  percent_encoding::{decode, try_decode},
+  ParseError,
+};
+
+/// Caps applied while parsing, so abusive query strings are rejected before
+/// they allocate large maps
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct QueryLimits {
+  /// Raw length in bytes, answered with `414 URI Too Long` when exceeded
+  pub max_length: usize,
+  pub max_keys: usize,
+  pub max_values_per_key: usize,
+}
+
+impl Default for QueryLimits {
+  fn default() -> Self {
+    Self { max_length: 4096, max_keys: 64, max_values_per_key: 32 }
+  }
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct QueryString<'buf> {
-  data: HashMap<&'buf str, Value<'buf>>,
+  raw: Cow<'buf, str>,
+  data: HashMap<Cow<'buf, str>, Value<'buf>>,
 }
 
-#[derive(Debug, PartialEq)]
+/// Decoded query values; they only own their data when the raw value contained
+/// escapes, otherwise they borrow from the request buffer.
+/// [`Value::Multiple`] keeps the values in the order they occur in the query.
+#[derive(Debug, Clone, PartialEq)]
 pub enum Value<'buf> {
-  Single(&'buf str),
-  Multiple(Vec<&'buf str>),
+  Single(Cow<'buf, str>),
+  Multiple(Vec<Cow<'buf, str>>),
+}
+
+impl<'buf> Value<'buf> {
+  /// The first occurrence, for "first value wins" semantics
+  pub fn first(&self) -> &str {
+    match self {
+      Value::Single(single) => single,
+      Value::Multiple(vec) => vec.first().map(Cow::as_ref).unwrap_or_default(),
+    }
+  }
+
+  /// The last occurrence, for "last value wins" semantics
+  pub fn last(&self) -> &str {
+    match self {
+      Value::Single(single) => single,
+      Value::Multiple(vec) => vec.last().map(Cow::as_ref).unwrap_or_default(),
+    }
+  }
+
+  fn len(&self) -> usize {
+    match self {
+      Value::Single(_) => 1,
+      Value::Multiple(vec) => vec.len(),
+    }
+  }
+
+  fn push(&mut self, value: Cow<'buf, str>) {
+    match self {
+      Value::Single(single) => *self = Value::Multiple(vec![std::mem::take(single), value]),
+      Value::Multiple(vec) => vec.push(value),
+    }
+  }
+
+  pub fn into_owned(self) -> Value<'static> {
+    match self {
+      Value::Single(single) => Value::Single(Cow::Owned(single.into_owned())),
+      Value::Multiple(vec) => Value::Multiple(
+        vec
+          .into_iter()
+          .map(|value| Cow::Owned(value.into_owned()))
+          .collect(),
+      ),
+    }
+  }
+
+  /// All occurrences in query order
+  pub fn iter(&self) -> impl Iterator<Item = &str> {
+    let values = match self {
+      Value::Single(single) => std::slice::from_ref(single),
+      Value::Multiple(vec) => vec.as_slice(),
+    };
+    values.iter().map(Cow::as_ref)
+  }
 }
 
 impl<'buf> QueryString<'buf> {
-  pub fn get(&self, key: &str) -> Option<&Value> {
+  pub fn get(&self, key: &str) -> Option<&Value<'_>> {
     self.data.get(key)
   }
+
+  pub fn first(&self, key: &str) -> Option<&str> {
+    self.get(key).map(Value::first)
+  }
+
+  pub fn last(&self, key: &str) -> Option<&str> {
+    self.get(key).map(Value::last)
+  }
+
+  /// Decoded keys with their values, in no particular order
+  pub fn iter(&self) -> impl Iterator<Item = (&str, &Value<'buf>)> {
+    self.data.iter().map(|(key, value)| (key.as_ref(), value))
+  }
+
+  /// The query exactly as it appeared in the request target, without the leading `?`
+  pub fn raw(&self) -> &str {
+    &self.raw
+  }
+
+  /// Undecoded values of `key`, for callers that need the bytes exactly as sent
+  pub fn get_raw(&self, key: &str) -> Vec<&str> {
+    pairs(&self.raw)
+      .filter(|(raw_key, _)| decode(raw_key, true) == key)
+      .map(|(_, raw_value)| raw_value)
+      .collect()
+  }
 }
 
-/// We're using a [`From`] as opposed to [`TryFrom`] because
-/// any [`std::str`] can be converted to at least an empty
-/// [`HashMap`]
-impl<'buf> From<&'buf str> for QueryString<'buf> {
-  fn from(value: &'buf str) -> Self {
-    let map = value.split('&').fold(HashMap::new(), |mut acc, hit| {
-      if let Some(equals_index) = hit.find('=') {
-        let (key, val) = hit.split_at(equals_index);
-        let val = &val[1..]; // Skip the '='
-
-        acc
-          .entry(key)
-          .and_modify(|existing_value: &mut Value| match existing_value {
-            Value::Single(single) => {
-              *existing_value = Value::Multiple(vec![single, val]);
-            }
-            Value::Multiple(vec) => vec.push(val),
-          })
-          .or_insert(Value::Single(val));
-      } else {
-        acc.insert(hit, Value::Single(""));
+fn pairs(query: &str) -> impl Iterator<Item = (&str, &str)> {
+  query
+    .split('&')
+    .map(|hit| hit.split_once('=').unwrap_or((hit, "")))
+}
+
+impl<'buf> QueryString<'buf> {
+  pub fn parse(value: &'buf str, limits: &QueryLimits) -> Result<Self, ParseError> {
+    if value.len() > limits.max_length {
+      return Err(ParseError::UriTooLong);
+    }
+
+    let mut map: HashMap<Cow<'buf, str>, Value<'buf>> = HashMap::new();
+    for (key, val) in pairs(value) {
+      let key = try_decode(key, true)?;
+      let val = try_decode(val, true)?;
+      let key_count = map.len();
+
+      match map.get_mut(&key) {
+        Some(existing_value) if existing_value.len() >= limits.max_values_per_key => {
+          return Err(ParseError::InvalidRequest(format!(
+            "Too many values for query key {}",
+            key
+          )));
+        }
+        Some(existing_value) => existing_value.push(val),
+        None if key_count >= limits.max_keys => {
+          return Err(ParseError::InvalidRequest(
+            "Too many query parameters".to_string(),
+          ));
+        }
+        None => {
+          map.insert(key, Value::Single(val));
+        }
       }
-      acc
-    });
+    }
+
+    Ok(QueryString { raw: Cow::Borrowed(value), data: map })
+  }
+
+  /// The same query, no longer borrowing the request buffer
+  pub fn into_owned(self) -> QueryString<'static> {
+    let data = self
+      .data
+      .into_iter()
+      .map(|(key, value)| (Cow::Owned(key.into_owned()), value.into_owned()))
+      .collect();
+    QueryString { raw: Cow::Owned(self.raw.into_owned()), data }
+  }
+}
+
+/// Parsing with the default [`QueryLimits`]; this is a [`TryFrom`] rather than a
+/// [`From`] because query strings past those limits are rejected
+impl<'buf> TryFrom<&'buf str> for QueryString<'buf> {
+  type Error = ParseError;
+
+  fn try_from(value: &'buf str) -> Result<Self, Self::Error> {
+    Self::parse(value, &QueryLimits::default())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use expectest::prelude::*;
+  use rstest::*;
+
+  fn query(value: &str) -> QueryString<'_> {
+    QueryString::try_from(value).expect("query within the default limits")
+  }
+
+  #[rstest]
+  fn test_values_are_decoded() {
+    let query = query("name=John+Doe%21&city%20name=Montr%C3%A9al&flag");
+
+    expect!(query.get("name")).to(be_some().value(&Value::Single("John Doe!".into())));
+    expect!(query.get("city name")).to(be_some().value(&Value::Single("Montréal".into())));
+    expect!(query.get("flag")).to(be_some().value(&Value::Single("".into())));
+  }
+
+  #[rstest]
+  fn test_undecoded_values_borrow_from_the_request() {
+    let query = query("name=none");
+    expect!(matches!(
+      query.get("name"),
+      Some(Value::Single(Cow::Borrowed("none")))
+    ))
+    .to(be_true());
+  }
+
+  #[rstest]
+  fn test_duplicate_keys_keep_occurrence_order() {
+    let query = query("sort=name&page=1&sort=date&sort=size");
+
+    let sort = query.get("sort").unwrap();
+    expect!(sort.iter().collect::<Vec<_>>()).to(be_equal_to(vec!["name", "date", "size"]));
+    expect!(query.first("sort")).to(be_some().value("name"));
+    expect!(query.last("sort")).to(be_some().value("size"));
+    expect!(query.first("page")).to(be_some().value("1"));
+    expect!(query.last("page")).to(be_some().value("1"));
+    expect!(query.last("missing")).to(be_none());
+  }
+
+  #[rstest]
+  #[case::too_long("a=".to_string() + &"b".repeat(64), ParseError::UriTooLong)]
+  #[case::too_many_keys(
+    (0..5).map(|i| format!("k{}=v", i)).collect::<Vec<_>>().join("&"),
+    ParseError::InvalidRequest("Too many query parameters".to_string())
+  )]
+  #[case::too_many_values(
+    "k=1&k=2&k=3&k=4".to_string(),
+    ParseError::InvalidRequest("Too many values for query key k".to_string())
+  )]
+  #[case::malformed_escape("k=100%".to_string(), ParseError::InvalidEncoding)]
+  fn test_query_limits(#[case] input: String, #[case] expected: ParseError) {
+    let limits = QueryLimits { max_length: 32, max_keys: 4, max_values_per_key: 3 };
+    expect!(QueryString::parse(&input, &limits)).to(be_err().value(expected));
+  }
+
+  #[rstest]
+  fn test_query_within_limits() {
+    let limits = QueryLimits { max_length: 32, max_keys: 2, max_values_per_key: 3 };
+    expect!(QueryString::parse("k=1&k=2&k=3&j=1", &limits)).to(be_ok());
+  }
+
+  #[rstest]
+  fn test_raw_access() {
+    let query = query("q=a+b&q=c%26d");
 
-    QueryString { data: map }
+    expect!(query.raw()).to(be_equal_to("q=a+b&q=c%26d"));
+    expect!(query.get_raw("q")).to(be_equal_to(vec!["a+b", "c%26d"]));
+    expect!(query.get("q")).to(be_some().value(&Value::Multiple(vec!["a b".into(), "c&d".into()])));
   }
 }
@@ -1,61 +1,370 @@
-use super::header::HttpHeader;
+use super::chunked::ChunkedError;
+use super::codec;
+use super::connection_info::ConnectionInfo;
+use super::cookie::CookieJar;
+use super::extensions::Extensions;
+use super::forwarded::Forwarded;
+use super::header::{HttpHeader, HttpRequestHeaderKey};
 use super::method::{Method, MethodError};
-use super::QueryString;
+use super::path_pattern::PathParams;
+use super::percent_encoding::{try_decode, DecodeError};
+use super::trace_context::random_id;
+use super::version::{Version, VersionError};
+use super::{query_string::QueryLimits, QueryString};
+use super::{StatusCode, TraceContext};
 use derive_getters::Getters;
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
 use std::convert::TryFrom;
-use std::error::Error;
-use std::fmt::{Debug, Display, Formatter, Result as FmtResult};
+use std::fmt::{Debug, Formatter, Result as FmtResult};
 use std::io;
-use std::str::{self, FromStr, Utf8Error};
+use std::net::{IpAddr, SocketAddr};
+use std::str::{self, Utf8Error};
 use thiserror::Error;
 
 // lifetimes are designed to address the possibility of a danglinf reference
-#[derive(Debug, Getters)]
+#[derive(Debug, Clone, Getters)]
 pub struct HttpRequest<'buf> {
-  path: &'buf str,
+  /// percent-decoded, see [`Self::path`]
+  #[getter(skip)]
+  path: Cow<'buf, str>,
+  /// the path exactly as it appeared in the request target
+  #[getter(skip)]
+  raw_path: Cow<'buf, str>,
   query_string: Option<QueryString<'buf>>,
   method: Method,
+  version: Version,
   header: HttpHeader,
+  /// The part of the `Content-Length` body that came along with the head in the parsed
+  /// buffer. The server reads bodies up to [`crate::server::Server::max_buffered_body_size`]
+  /// before parsing, so those are complete here; larger ones stream through
+  /// [`crate::server::Handler::handle_with_body`] instead.
+  #[getter(skip)]
+  body: Cow<'buf, [u8]>,
+  target_form: TargetForm,
+  trace_context: TraceContext,
+  /// see [`Self::request_id`]
+  #[getter(skip)]
+  request_id: String,
+  /// where the request came from, once the server has attached it
+  connection: Option<ConnectionInfo>,
+  /// what trusted proxies said about the client, once the server believed them
+  forwarded: Option<Forwarded>,
+  /// what the matched route's pattern captured, once a [`crate::router::Router`] has routed it
+  path_params: PathParams<'buf>,
+  /// whatever else middleware attached, see [`Self::with_extension`]
+  extensions: Extensions,
 }
 
-pub const HTTP1: &str = "HTTP/1.1";
+/// Shape of the request target (RFC 7230, section 5.3)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TargetForm {
+  /// `/path?query`, the usual form
+  Origin,
+  /// `*`, addressing the server as a whole; only valid for `OPTIONS`
+  Asterisk,
+}
+
+/// A request that owns its path, query and body rather than borrowing the read buffer, so it
+/// can be moved into spawned tasks or queued, see [`HttpRequest::into_owned`]
+pub type OwnedHttpRequest = HttpRequest<'static>;
+
+const FORM_URLENCODED: &str = "application/x-www-form-urlencoded";
+/// Longest `X-Request-Id` taken from clients, longer ones get a new ID
+const MAX_REQUEST_ID_LENGTH: usize = 128;
+
+/// Caps applied while parsing a request's head, so oversized requests are turned down with
+/// a status telling the client which part was too large
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ParserLimits {
+  /// Method, target and version, answered with `414 URI Too Long` when exceeded
+  pub max_request_line_length: usize,
+  /// Any single header value, answered with `431 Request Header Fields Too Large`
+  pub max_header_value_length: usize,
+  /// Header fields per request, also answered with `431`
+  pub max_headers_count: usize,
+  /// Declared or decoded body length, answered with `413 Payload Too Large`. Unbounded by
+  /// default, routes can set their own [`crate::limits::Limits::max_body_size`] below it.
+  pub max_body_size: Option<u64>,
+}
+
+impl Default for ParserLimits {
+  fn default() -> Self {
+    Self {
+      max_request_line_length: 8 * 1024,
+      max_header_value_length: 8 * 1024,
+      max_headers_count: 100,
+      max_body_size: None,
+    }
+  }
+}
 
 /// rustc will try to auto-implement [`std::convert::TryInto`]
 impl<'buf> TryFrom<&'buf [u8]> for HttpRequest<'buf> {
   type Error = ParseError;
 
   fn try_from(buf: &'buf [u8]) -> Result<HttpRequest<'buf>, Self::Error> {
-    let request = str::from_utf8(buf)?;
-    let (method, request) = get_next_word(request).ok_or(ParseError::InvalidRequest(
+    Self::parse(buf, &ParserLimits::default(), &QueryLimits::default())
+  }
+}
+
+impl<'buf> HttpRequest<'buf> {
+  pub fn parse(
+    buf: &'buf [u8],
+    limits: &ParserLimits,
+    query_limits: &QueryLimits,
+  ) -> Result<Self, ParseError> {
+    // only the request line has to be UTF-8, header values may carry opaque bytes
+    let line_end = buf
+      .iter()
+      .position(|&byte| byte == b'\n')
+      .unwrap_or(buf.len());
+    let request_line = str::from_utf8(&buf[..line_end])?.trim_end_matches('\r');
+    if request_line.len() > limits.max_request_line_length {
+      return Err(ParseError::UriTooLong);
+    }
+
+    let (method, request_line) = get_next_word(request_line).ok_or(ParseError::InvalidRequest(
       "Method missing from HttpHeader missing!".to_string(),
     ))?;
-    let (mut path, request) = get_next_word(request).ok_or(ParseError::InvalidRequest(
+    let (mut path, protocol) = get_next_word(request_line).ok_or(ParseError::InvalidRequest(
       "Failed to etract path from HttpRequest!".to_string(),
     ))?;
-    let (protocol, request) = get_next_word(request).ok_or(ParseError::InvalidRequest(
-      "Protocol missing in HTTP request!".to_string(),
-    ))?;
-    let request = request.trim_start_matches(|ch| ch == '\n');
-    let header = HttpHeader::from_str(request)?;
+    if protocol.is_empty() {
+      return Err(ParseError::InvalidRequest(
+        "Protocol missing in HTTP request!".to_string(),
+      ));
+    }
+
+    codec::check_framing(&buf[..codec::head_length(buf).unwrap_or(buf.len())])?;
+    let headers = buf.get(line_end + 1..).unwrap_or_default();
+    let headers = &headers[headers.iter().take_while(|&&byte| byte == b'\n').count()..];
+    let header = HttpHeader::parse(headers, limits)?;
+    if limits
+      .max_body_size
+      .zip(header.content_length().ok().flatten())
+      .is_some_and(|(max_body_size, content_length)| content_length > max_body_size)
+    {
+      return Err(ParseError::PayloadTooLarge);
+    }
 
-    if protocol != HTTP1 {
-      return Err(ParseError::InvalidProtocol);
+    let version = protocol.parse::<Version>()?;
+    // HTTP/1.1 requires a Host header, HTTP/1.0 requests may come without any
+    if version == Version::Http11 && header.is_empty() {
+      return Err(ParseError::InvalidRequest(
+        "Http header missing!".to_string(),
+      ));
     }
     // use 'turbofish' instead of annotating 'method'
     let method = method.parse::<Method>()?;
 
-    let query_string = path.find('?').map(|i| {
-      let query = QueryString::from(&path[i + 1..]);
-      path = &path[..i];
-      query
-    });
-    Ok(Self { path, query_string, method, header })
+    let target_form = if path == "*" {
+      TargetForm::Asterisk
+    } else {
+      TargetForm::Origin
+    };
+    if target_form == TargetForm::Asterisk && method != Method::OPTIONS {
+      return Err(ParseError::InvalidRequest(
+        "Asterisk-form target is only allowed for OPTIONS".to_string(),
+      ));
+    }
+
+    let query_string = match path.split_once('?') {
+      Some((target_path, query)) => {
+        path = target_path;
+        Some(QueryString::parse(query, query_limits)?)
+      }
+      None => None,
+    };
+    let trace_context = TraceContext::from_header(&header);
+    let request_id = request_id(&header);
+    let body = match codec::head_length(buf) {
+      Some(head_length) => {
+        let content_length = codec::content_length(&buf[..head_length]).unwrap_or(0);
+        let rest = &buf[head_length..];
+        &rest[..rest
+          .len()
+          .min(usize::try_from(content_length).unwrap_or(usize::MAX))]
+      }
+      None => &[],
+    };
+    tracing::trace!(%method, path, body_length = body.len(), "Parsed request");
+    Ok(Self {
+      path: try_decode(path, false)?,
+      raw_path: Cow::Borrowed(path),
+      query_string,
+      method,
+      version,
+      header,
+      body: Cow::Borrowed(body),
+      target_form,
+      trace_context,
+      request_id,
+      connection: None,
+      forwarded: None,
+      path_params: PathParams::default(),
+      extensions: Extensions::default(),
+    })
+  }
+
+  /// The percent-decoded path, e.g. `/hello world` for `/hello%20world`; `+` stays as it is,
+  /// it only means a space in queries
+  pub fn path(&self) -> &str {
+    &self.path
+  }
+
+  /// The path exactly as it appeared in the request target, escapes and all
+  pub fn raw_path(&self) -> &str {
+    &self.raw_path
+  }
+
+  /// Names the request in logs and in the `X-Request-Id` response header: the client's
+  /// `X-Request-Id` when it sent a usable one, so the services handling it log the same ID, or
+  /// else a new random one
+  pub fn request_id(&self) -> &str {
+    &self.request_id
+  }
+
+  /// The part of the body that came along with the head, complete for bodies up to
+  /// [`crate::server::Server::max_buffered_body_size`]
+  pub fn body(&self) -> &[u8] {
+    &self.body
+  }
+
+  /// The same request, copying what it borrows from the read buffer
+  pub fn into_owned(self) -> OwnedHttpRequest {
+    HttpRequest {
+      path: Cow::Owned(self.path.into_owned()),
+      raw_path: Cow::Owned(self.raw_path.into_owned()),
+      query_string: self.query_string.map(QueryString::into_owned),
+      method: self.method,
+      version: self.version,
+      header: self.header,
+      body: Cow::Owned(self.body.into_owned()),
+      target_form: self.target_form,
+      trace_context: self.trace_context,
+      request_id: self.request_id,
+      connection: self.connection,
+      forwarded: self.forwarded,
+      path_params: self.path_params.into_owned(),
+      extensions: self.extensions,
+    }
+  }
+
+  /// The fields of an `application/x-www-form-urlencoded` body, as POSTed by HTML forms,
+  /// decoded like [`Self::query_string`] and within the default [`QueryLimits`] but for the
+  /// length, which [`crate::limits::Limits::max_body_size`] bounds. Only the buffered body is
+  /// seen, see [`Self::body`]; other content types get
+  /// [`ParseError::UnsupportedMediaType`].
+  pub fn form(&self) -> Result<QueryString<'_>, ParseError> {
+    if self.header.media_type().as_deref() != Some(FORM_URLENCODED) {
+      let content_type = self.header.get(HttpRequestHeaderKey::ContentType);
+      return Err(ParseError::UnsupportedMediaType(content_type.cloned()));
+    }
+    let limits = QueryLimits { max_length: usize::MAX, ..QueryLimits::default() };
+    QueryString::parse(str::from_utf8(&self.body)?, &limits)
+  }
+
+  /// The name or address the request is for, from its `Host` header without the port or a
+  /// trailing dot (`Example.com` for `Example.com.:8080`). `None` if the header is missing or
+  /// invalid; compare it case-insensitively.
+  pub fn hostname(&self) -> Option<&str> {
+    let hostname = self.header.host().ok()??.hostname();
+    Some(hostname.strip_suffix('.').unwrap_or(hostname))
+  }
+
+  /// The cookies sent in the `Cookie` header, none without one
+  pub fn cookies(&self) -> CookieJar<'_> {
+    self
+      .header
+      .get(HttpRequestHeaderKey::Cookie)
+      .map(|cookies| CookieJar::parse(cookies))
+      .unwrap_or_default()
+  }
+
+  /// The client's address, once the server has attached the connection
+  pub fn peer_addr(&self) -> Option<SocketAddr> {
+    self.connection.map(|connection| connection.peer_addr)
+  }
+
+  pub fn with_connection(mut self, connection: ConnectionInfo) -> Self {
+    self.connection = Some(connection);
+    self
+  }
+
+  /// The client's address, as trusted proxies forwarded it or else the peer's, see
+  /// [`crate::http::forwarded::TrustedProxies`]
+  pub fn client_ip(&self) -> Option<IpAddr> {
+    match &self.forwarded {
+      Some(forwarded) => Some(forwarded.client_ip),
+      None => self.peer_addr().map(|peer_addr| peer_addr.ip()),
+    }
+  }
+
+  /// `https` or `http`, as the client sent the request: what trusted proxies forwarded, or
+  /// else whether the connection is TLS
+  pub fn scheme(&self) -> &str {
+    let forwarded = self
+      .forwarded
+      .as_ref()
+      .and_then(|forwarded| forwarded.proto.as_deref());
+    match forwarded {
+      Some(proto) => proto,
+      None if self.connection.is_some_and(|connection| connection.secure) => "https",
+      None => "http",
+    }
+  }
+
+  pub fn with_forwarded(mut self, forwarded: Forwarded) -> Self {
+    self.forwarded = Some(forwarded);
+    self
+  }
+
+  /// The same request under the ID it was first given, e.g. once parsed again from the same
+  /// bytes, which would otherwise generate another one
+  pub fn with_request_id(mut self, request_id: String) -> Self {
+    self.request_id = request_id;
+    self
+  }
+
+  /// The same request with another method, e.g. a `HEAD` answered by the `GET` path
+  pub fn with_method(mut self, method: Method) -> Self {
+    self.method = method;
+    self
+  }
+
+  pub fn with_path_params(mut self, path_params: PathParams<'buf>) -> Self {
+    self.path_params = path_params;
+    self
   }
+
+  /// The same request carrying `value` for handlers further down to read with
+  /// `request.extensions().get::<T>()`, in place of an earlier value of its type
+  pub fn with_extension<T: Send + Sync + 'static>(mut self, value: T) -> Self {
+    self.extensions.insert(value);
+    self
+  }
+}
+
+/// The client's `X-Request-Id`, unless it's empty, longer than [`MAX_REQUEST_ID_LENGTH`] or has
+/// characters that could garble a log line
+fn request_id(header: &HttpHeader) -> String {
+  header
+    .get(HttpRequestHeaderKey::XRequestId)
+    .map(|request_id| request_id.trim())
+    .filter(|request_id| {
+      !request_id.is_empty()
+        && request_id.len() <= MAX_REQUEST_ID_LENGTH
+        && request_id.bytes().all(|byte| byte.is_ascii_graphic())
+    })
+    .map(str::to_string)
+    .unwrap_or_else(|| format!("{:016x}{:016x}", random_id(), random_id()))
 }
 
 fn get_next_word(request: &str) -> Option<(&str, &str)> {
   request
-    .find(|ch| ch == ' ' || ch == '\r' || ch == '\n')
+    .find([' ', '\r', '\n'])
     .map(|matched_index| (&request[..matched_index], &request[matched_index + 1..]))
 }
 
@@ -70,34 +379,60 @@ pub enum FileError {
   TimeParseError(#[from] time::error::Parse),
 }
 
-#[derive(PartialEq)]
+#[allow(clippy::enum_variant_names)]
+#[derive(Error, PartialEq)]
 pub enum ParseError {
+  #[error("Invalid Request: {0}")]
   InvalidRequest(String),
+  #[error("Invalid Encoding")]
   InvalidEncoding,
-  InvalidProtocol,
-  InvalidMethodError,
+  #[error("Invalid Protocol: {0}")]
+  InvalidProtocol(#[from] VersionError),
+  #[error("Invalid Method Error: {0}")]
+  InvalidMethodError(#[from] MethodError),
+  #[error("URI Too Long")]
+  UriTooLong,
+  /// too many header fields, or one too long, answered with `431 Request Header Fields Too Large`
+  #[error("Request Header Fields Too Large: {0}")]
+  HeaderFieldsTooLarge(String),
+  /// a `Content-Length` over [`ParserLimits::max_body_size`]
+  #[error("Payload Too Large")]
+  PayloadTooLarge,
+  /// a body in another format than the handler reads, with the `Content-Type` it had
+  #[error("Unsupported Media Type: {0:?}")]
+  UnsupportedMediaType(Option<String>),
+  #[error("Invalid Chunked Body: {0}")]
+  InvalidChunkedBody(#[from] ChunkedError),
+  /// the client didn't send the request in time, see [`crate::server::Server::header_read_timeout`]
+  #[error("Request Timeout")]
+  Timeout,
+  /// a head whose body length is open to more than one reading, see [`codec::check_framing`]
+  #[error("Ambiguous Framing: {0}")]
+  AmbiguousFraming(String),
+  /// an `Expect` the server won't meet, such as `100-continue` for a body it would turn down
+  #[error("Expectation Failed")]
+  ExpectationFailed,
 }
 
 impl ParseError {
-  fn message(&self) -> String {
-    match &self {
-      Self::InvalidRequest(issue) => format!("Invalid Request: {}", issue),
-      Self::InvalidEncoding => "Invalid Encoding".to_string(),
-      Self::InvalidProtocol => "Invalid Protocol".to_string(),
-      Self::InvalidMethodError => "Invalid Method Error".to_string(),
+  /// The status a client receives when its request can't be parsed
+  pub fn status_code(&self) -> StatusCode {
+    match self {
+      Self::UriTooLong => StatusCode::UriTooLong,
+      Self::HeaderFieldsTooLarge(_) => StatusCode::RequestHeaderFieldsTooLarge,
+      Self::PayloadTooLarge => StatusCode::PayloadTooLarge,
+      Self::UnsupportedMediaType(_) => StatusCode::UnsupportedMediaType,
+      Self::InvalidChunkedBody(error) => error.status_code(),
+      Self::Timeout => StatusCode::RequestTimeout,
+      Self::ExpectationFailed => StatusCode::ExpectationFailed,
+      _ => StatusCode::BadRequest,
     }
   }
 }
 
-impl Display for ParseError {
-  fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
-    write!(f, "{}", self.message())
-  }
-}
-
-impl From<MethodError> for ParseError {
-  fn from(_: MethodError) -> Self {
-    Self::InvalidMethodError
+impl From<DecodeError> for ParseError {
+  fn from(_: DecodeError) -> Self {
+    Self::InvalidEncoding
   }
 }
 
@@ -107,15 +442,13 @@ impl From<Utf8Error> for ParseError {
   }
 }
 
+/// The message, as in the error log, rather than the variant
 impl Debug for ParseError {
   fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
-    write!(f, "{}", self.message())
+    write!(f, "{}", self)
   }
 }
 
-/// to be more idiomatic, we need to implement a std library trait [`std::error::Error`]
-impl Error for ParseError {}
-
 #[cfg(test)]
 mod tests {
   use crate::http::method::Method::*;
@@ -143,10 +476,273 @@ mod tests {
     ))?;
     assert_eq!(method, "GET");
     assert_eq!(path, "/home?name=none");
-    assert_eq!(protocol, HTTP1);
+    assert_eq!(protocol, Version::Http11.as_str());
     Ok(())
   }
 
+  #[rstest]
+  fn try_from_u8_array_should_recognize_asterisk_form() {
+    let request =
+      HttpRequest::try_from(&b"OPTIONS * HTTP/1.1\r\nHost: localhost\r\n\r\n"[..]).unwrap();
+    assert_eq!(request.target_form, TargetForm::Asterisk);
+    assert_eq!(request.method, OPTIONS);
+
+    let request = HttpRequest::try_from(&b"GET * HTTP/1.1\r\nHost: localhost\r\n\r\n"[..]);
+    assert!(request.is_err());
+  }
+
+  #[rstest]
+  fn try_from_u8_array_should_accept_latin1_header_values() {
+    let request = HttpRequest::try_from(
+      &b"GET /download HTTP/1.1\r\nHost: localhost\r\nX-Name: Fran\xe7ois\r\n\r\n"[..],
+    );
+
+    assert!(request.is_ok());
+    assert_eq!(request.unwrap().header().get("x-name").unwrap(), "François");
+  }
+
+  #[rstest]
+  #[case::complete(&b"POST /form HTTP/1.1\r\nContent-Length: 3\r\n\r\nabc"[..], &b"abc"[..])]
+  #[case::followed_by_next_request(&b"POST /form HTTP/1.1\r\nContent-Length: 3\r\n\r\nabcGET / HTTP/1.1\r\n"[..], &b"abc"[..])]
+  #[case::partial(&b"POST /form HTTP/1.1\r\nContent-Length: 10\r\n\r\nabc"[..], &b"abc"[..])]
+  #[case::without_content_length(&b"POST /form HTTP/1.1\r\nHost: localhost\r\n\r\nabc"[..], &b""[..])]
+  fn try_from_u8_array_should_capture_body(#[case] buffer: &[u8], #[case] expected: &[u8]) {
+    let request = HttpRequest::try_from(buffer).unwrap();
+    assert_eq!(request.body(), expected);
+  }
+
+  #[rstest]
+  #[case::request_line(
+    ParserLimits { max_request_line_length: 16, ..ParserLimits::default() },
+    ParseError::UriTooLong
+  )]
+  #[case::header_value(
+    ParserLimits { max_header_value_length: 9, ..ParserLimits::default() },
+    ParseError::HeaderFieldsTooLarge("Header value too long for user-agent".to_string())
+  )]
+  #[case::headers_count(
+    ParserLimits { max_headers_count: 2, ..ParserLimits::default() },
+    ParseError::HeaderFieldsTooLarge("Too many HTTP headers".to_string())
+  )]
+  #[case::body_size(
+    ParserLimits { max_body_size: Some(2), ..ParserLimits::default() },
+    ParseError::PayloadTooLarge
+  )]
+  fn parse_should_enforce_parser_limits(
+    #[case] limits: ParserLimits,
+    #[case] expected: ParseError,
+  ) {
+    let buffer = b"POST /upload/report HTTP/1.1\r\nHost: localhost\r\nUser-Agent: curl/8.5.0\r\nContent-Length: 3\r\n\r\nabc";
+    assert!(HttpRequest::parse(buffer, &ParserLimits::default(), &QueryLimits::default()).is_ok());
+    let request = HttpRequest::parse(buffer, &limits, &QueryLimits::default());
+    assert_eq!(request.unwrap_err(), expected);
+  }
+
+  #[rstest]
+  fn try_from_u8_array_should_reject_non_utf8_request_line() {
+    let request = HttpRequest::try_from(&b"GET /caf\xe9 HTTP/1.1\r\nHost: localhost\r\n\r\n"[..]);
+    assert_eq!(request.unwrap_err(), ParseError::InvalidEncoding);
+  }
+
+  #[rstest]
+  #[case::space(&b"GET /hello%20world?name=John%26Jane HTTP/1.1\r\nHost: localhost\r\n\r\n"[..], "/hello world", "/hello%20world")]
+  #[case::plus_kept(&b"GET /a+b HTTP/1.1\r\nHost: localhost\r\n\r\n"[..], "/a+b", "/a+b")]
+  fn try_from_u8_array_should_decode_path(
+    #[case] buffer: &[u8],
+    #[case] path: &str,
+    #[case] raw_path: &str,
+  ) {
+    let request = HttpRequest::try_from(buffer).unwrap();
+    assert_eq!(request.path(), path);
+    assert_eq!(request.raw_path(), raw_path);
+  }
+
+  #[rstest]
+  fn into_owned_should_outlive_the_buffer() {
+    let buffer =
+      b"POST /hello%20world?q=a+b HTTP/1.1\r\nHost: localhost\r\nContent-Length: 3\r\n\r\nabc"
+        .to_vec();
+    let request = HttpRequest::try_from(&buffer[..]).unwrap().into_owned();
+    drop(buffer);
+
+    let handle = std::thread::spawn(move || {
+      (
+        request.path().to_string(),
+        request.raw_path().to_string(),
+        request
+          .query_string()
+          .as_ref()
+          .and_then(|query| query.first("q"))
+          .map(str::to_string),
+        request.body().to_vec(),
+        request.header().get("Host").cloned(),
+      )
+    });
+    let (path, raw_path, query, body, host) = handle.join().unwrap();
+    assert_eq!(path, "/hello world");
+    assert_eq!(raw_path, "/hello%20world");
+    assert_eq!(query.as_deref(), Some("a b"));
+    assert_eq!(body, b"abc");
+    assert_eq!(host.as_deref(), Some("localhost"));
+  }
+
+  #[rstest]
+  #[case::fields(
+    "application/x-www-form-urlencoded",
+    "name=John+Doe&tag=a&tag=b%26c",
+    Ok(vec![("name", vec!["John Doe"]), ("tag", vec!["a", "b&c"])])
+  )]
+  #[case::charset("application/x-www-form-urlencoded; charset=UTF-8", "a=1", Ok(vec![("a", vec!["1"])]))]
+  #[case::empty("application/x-www-form-urlencoded", "", Ok(vec![("", vec![""])]))]
+  #[case::json(
+    "application/json",
+    "{}",
+    Err(ParseError::UnsupportedMediaType(Some("application/json".to_string())))
+  )]
+  #[case::malformed_escape(
+    "application/x-www-form-urlencoded",
+    "a=%zz",
+    Err(ParseError::InvalidEncoding)
+  )]
+  fn form_should_decode_urlencoded_body(
+    #[case] content_type: &str,
+    #[case] body: &str,
+    #[case] expected: Result<Vec<(&str, Vec<&str>)>, ParseError>,
+  ) {
+    let buffer = format!(
+      "POST /form HTTP/1.1\r\nHost: localhost\r\nContent-Type: {}\r\nContent-Length: {}\r\n\r\n{}",
+      content_type,
+      body.len(),
+      body
+    );
+    let request = HttpRequest::try_from(buffer.as_bytes()).unwrap();
+    let fields = request.form().map(|form| {
+      let mut fields = form
+        .iter()
+        .map(|(key, value)| {
+          (
+            key.to_string(),
+            value.iter().map(str::to_string).collect::<Vec<_>>(),
+          )
+        })
+        .collect::<Vec<_>>();
+      fields.sort();
+      fields
+    });
+    let expected = expected.map(|expected| {
+      expected
+        .into_iter()
+        .map(|(key, values)| {
+          (
+            key.to_string(),
+            values.into_iter().map(str::to_string).collect(),
+          )
+        })
+        .collect::<Vec<_>>()
+    });
+    assert_eq!(fields, expected);
+  }
+
+  #[rstest]
+  #[case::name("Host: Example.com\r\n", Some("Example.com"))]
+  #[case::port_and_trailing_dot("Host: example.com.:8080\r\n", Some("example.com"))]
+  #[case::ipv6("Host: [::1]:8080\r\n", Some("::1"))]
+  #[case::invalid("Host: exa mple.com\r\n", None)]
+  fn hostname_should_come_from_host_header(#[case] host: &str, #[case] expected: Option<&str>) {
+    let raw_request = format!("GET / HTTP/1.1\r\n{}\r\n", host);
+    let request = HttpRequest::try_from(raw_request.as_bytes()).unwrap();
+    assert_eq!(request.hostname(), expected);
+  }
+
+  #[rstest]
+  #[case::client("X-Request-Id: checkout-42\r\n", Some("checkout-42"))]
+  #[case::missing("", None)]
+  #[case::too_long(&format!("X-Request-Id: {}\r\n", "x".repeat(129)), None)]
+  #[case::spaces("X-Request-Id: checkout 42\r\n", None)]
+  fn request_id_should_come_from_client_or_be_generated(
+    #[case] request_id: &str,
+    #[case] expected: Option<&str>,
+  ) {
+    let raw_request = format!("GET / HTTP/1.1\r\nHost: localhost\r\n{}\r\n", request_id);
+    let request = HttpRequest::try_from(raw_request.as_bytes()).unwrap();
+    match expected {
+      Some(expected) => assert_eq!(request.request_id(), expected),
+      None => {
+        assert_eq!(request.request_id().len(), 32);
+        // every request gets its own
+        let other = HttpRequest::try_from(raw_request.as_bytes()).unwrap();
+        assert_ne!(request.request_id(), other.request_id());
+      }
+    }
+  }
+
+  #[rstest]
+  #[case::http_1_0(&b"GET / HTTP/1.0\r\n\r\n"[..], Ok(Version::Http10))]
+  #[case::http_1_1(&b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n"[..], Ok(Version::Http11))]
+  #[case::http_1_1_without_host(&b"GET / HTTP/1.1\r\n\r\n"[..], Err(ParseError::InvalidRequest("Http header missing!".to_string())))]
+  #[case::http_2(&b"GET / HTTP/2.0\r\nHost: localhost\r\n\r\n"[..], Err(ParseError::InvalidProtocol(VersionError)))]
+  fn version_should_come_from_request_line(
+    #[case] raw_request: &[u8],
+    #[case] expected: Result<Version, ParseError>,
+  ) {
+    let version = HttpRequest::try_from(raw_request).map(|request| request.version);
+    assert!(version == expected);
+  }
+
+  #[rstest]
+  #[case::peer(false, None, "10.0.0.1", "http")]
+  #[case::tls(true, None, "10.0.0.1", "https")]
+  #[case::forwarded(false, Some("https"), "198.51.100.1", "https")]
+  fn client_should_be_forwarded_or_peer(
+    #[case] secure: bool,
+    #[case] proto: Option<&str>,
+    #[case] expected_ip: &str,
+    #[case] expected_scheme: &str,
+  ) {
+    let request = HttpRequest::try_from(&b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n"[..])
+      .unwrap()
+      .with_connection(ConnectionInfo {
+        secure,
+        ..ConnectionInfo::new("10.0.0.1:5000".parse().unwrap())
+      });
+    let request = match proto {
+      Some(proto) => request.with_forwarded(Forwarded {
+        client_ip: "198.51.100.1".parse().unwrap(),
+        proto: Some(proto.to_string()),
+      }),
+      None => request,
+    };
+    assert_eq!(request.client_ip(), expected_ip.parse().ok());
+    assert_eq!(request.scheme(), expected_scheme);
+  }
+
+  #[rstest]
+  fn cookies_should_come_from_cookie_header() {
+    let request = HttpRequest::try_from(
+      &b"GET / HTTP/1.1\r\nHost: localhost\r\nCookie: session=abc; theme=dark\r\n\r\n"[..],
+    )
+    .unwrap();
+    let cookies = request.cookies();
+    assert_eq!(
+      cookies.get("session").map(|cookie| cookie.value()),
+      Some("abc")
+    );
+    assert_eq!(cookies.len(), 2);
+
+    let request = HttpRequest::try_from(&b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n"[..]).unwrap();
+    assert!(request.cookies().is_empty());
+  }
+
+  #[rstest]
+  #[case::malformed_path(&b"GET /100%zz HTTP/1.1\r\nHost: localhost\r\n\r\n"[..])]
+  #[case::non_utf8_path(&b"GET /caf%E9 HTTP/1.1\r\nHost: localhost\r\n\r\n"[..])]
+  #[case::malformed_query(&b"GET /?q=%4 HTTP/1.1\r\nHost: localhost\r\n\r\n"[..])]
+  fn try_from_u8_array_should_reject_invalid_escapes(#[case] buffer: &[u8]) {
+    let error = HttpRequest::try_from(buffer).unwrap_err();
+    assert_eq!(error, ParseError::InvalidEncoding);
+    assert_eq!(error.status_code(), StatusCode::BadRequest);
+  }
+
   #[rstest]
   fn try_from_u8_array_should_return_http_request_for_valid_header(valid_request_header: String) {
     let header = valid_request_header.as_bytes();
@@ -159,10 +755,11 @@ mod tests {
       // Assertions on the HttpRequest
       assert_eq!(request.method, GET);
       assert_eq!(request.path, "/home");
+      assert_eq!(request.target_form, TargetForm::Origin);
       if let Some(i) = request.path.find('?') {
         assert_eq!(
           request.query_string,
-          Some(QueryString::from(&request.path[i + 1..]))
+          QueryString::try_from(&request.path[i + 1..]).ok()
         );
       }
     } else {
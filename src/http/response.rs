@@ -1,84 +1,1016 @@
-use std::sync::Arc;
-use tokio::io::{AsyncWriteExt, Result as TokioResult};
-use tokio::net::TcpStream;
+use derive_getters::Getters;
+use derive_new::new;
+#[cfg(feature = "server")]
+use std::io::{Error as IoError, ErrorKind, IoSlice};
+use std::{
+  fmt::{Debug, Formatter, Result as FmtResult},
+  sync::Arc,
+};
+use thiserror::Error;
+#[cfg(feature = "server")]
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, Result as TokioResult};
 
-use crate::{filesystem::FileSystem, http::request::HTTP1};
+#[cfg(feature = "server")]
+use crate::filesystem::{AsyncFileSystem, OpenFile};
+use crate::{file_cache::CachedFile, filesystem::FileSystem, http::Version};
 
+#[cfg(feature = "server")]
+use super::request::FileError;
+use super::{
+  cookie::{CookieError, SetCookie},
+  header::{canonical_name, HttpHeader, HttpResponseHeaderKey, ReadFileOps},
+  typed_header::is_token,
+  HttpRequest, Method, MimeTypes, StatusCode,
+};
+#[cfg(feature = "server")]
 use super::{
-  header::{HttpHeader, ReadFileOps},
-  StatusCode,
+  event_stream::{EventReader, EventSender, DEFAULT_KEEP_ALIVE_INTERVAL},
+  upgrade::{OnUpgrade, Upgraded},
 };
 
-#[derive(Debug)]
+/// Body bytes handed to the connection per write unless configured otherwise, see
+/// [`HttpResponse::send_in_chunks`]
+pub const DEFAULT_CHUNK_SIZE: usize = 16 * 1024;
+
+#[derive(Debug, Getters, new)]
 pub struct HttpResponse {
   status_code: StatusCode,
-  body: Option<String>,
+  body: Option<Vec<u8>>,
   http_header: Option<Arc<HttpHeader>>,
+  /// sent instead of `body`, see [`HttpResponse::streamed`]
+  #[new(default)]
+  #[getter(skip)]
+  stream: Option<BodyStream>,
+  /// only the head is sent, see [`HttpResponse::omit_body`]
+  #[new(default)]
+  #[getter(skip)]
+  head_only: bool,
+  /// in the status line, see [`HttpResponse::set_version`]
+  #[new(default)]
+  version: Version,
+  /// takes over the connection once this response is sent, see [`HttpResponse::on_upgrade`]
+  #[cfg(feature = "server")]
+  #[new(default)]
+  #[getter(skip)]
+  upgrade: Option<OnUpgrade>,
+}
+
+/// A response body read while it's being sent rather than held in memory
+pub struct BodyStream {
+  #[cfg(feature = "server")]
+  reader: Box<dyn AsyncRead + Send + Unpin>,
+}
+
+impl Debug for BodyStream {
+  fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+    write!(f, "BodyStream")
+  }
 }
 
 impl HttpResponse {
-  pub fn with_body(file_path: &str, file_system: &impl FileSystem) -> Self {
+  pub fn with_body(file_path: &str, file_system: &impl FileSystem, mime_types: &MimeTypes) -> Self {
     let full_path = file_system.get_full_path(file_path);
-    let file_contents = file_system.read_file(&full_path.to_string_lossy());
+    if let Some(in_memory) = file_system.in_memory_file(file_path) {
+      return Self::with_cached_file(&full_path.to_string_lossy(), &in_memory, mime_types);
+    }
+    let file_contents = file_system.read_file(file_path);
+    let content_type = mime_types.content_type(
+      &full_path.to_string_lossy(),
+      file_contents.as_deref().unwrap_or_default(),
+    );
     let response_header =
-      HttpHeader::html_response_header_for_file(full_path, &ReadFileOps).map(Arc::new);
+      HttpHeader::html_response_header_for_file(full_path, &content_type, &ReadFileOps)
+        .map(Arc::new);
 
-    match (file_contents, &response_header) {
-      (Some(contents), Ok(header)) => Self {
-        status_code: StatusCode::Ok,
-        body: Some(contents),
-        http_header: Some(header.clone()),
-      },
-      (Some(_), Err(file_error)) => Self {
-        status_code: StatusCode::InternalError,
-        body: Some(file_error.to_string()),
-        http_header: None,
-      },
-      (None, _) => Self {
-        status_code: StatusCode::NoContent,
-        body: None,
-        http_header: response_header.ok(),
-      },
+    let response = match (file_contents, response_header) {
+      (Some(contents), Ok(header)) => Self::builder().http_header(header).body(contents),
+      (Some(_), Err(file_error)) => Self::builder()
+        .status(StatusCode::InternalError)
+        .body(file_error.to_string()),
+      (None, _) => Self::builder().status(StatusCode::NotFound).empty(),
+    };
+    response.unwrap_or_else(Self::from)
+  }
+
+  /// A file held in memory, with the validators formatted along with it
+  fn with_cached_file(full_path: &str, file: &CachedFile, mime_types: &MimeTypes) -> Self {
+    let content_type = mime_types.content_type(full_path, &file.contents);
+    let header = HttpHeader::cached_file_response_header(&content_type, file);
+    Self::new(
+      StatusCode::Ok,
+      Some(file.contents.to_vec()),
+      Some(Arc::new(header)),
+    )
+  }
+
+  /// Start a response with a chosen status, headers and body, e.g.
+  ///
+  /// ```
+  /// use udemy_server::http::{HttpResponse, StatusCode};
+  ///
+  /// let response = HttpResponse::builder()
+  ///   .status(StatusCode::Created)
+  ///   .header("Location", "/items/42")
+  ///   .body("created")
+  ///   .unwrap();
+  /// assert!(response.head().starts_with("HTTP/1.1 201 Created\r\nLocation: /items/42\r\n"));
+  /// ```
+  pub fn builder() -> ResponseBuilder {
+    ResponseBuilder::default()
+  }
+
+  /// Like [`Self::with_body`] without blocking the runtime: the file is opened on tokio's
+  /// blocking threads and, unless it fits into [`DEFAULT_CHUNK_SIZE`], streamed as it's sent,
+  /// so it isn't held in memory either
+  #[cfg(feature = "server")]
+  pub async fn with_file(
+    file_path: &str,
+    file_system: &impl AsyncFileSystem,
+    mime_types: &MimeTypes,
+  ) -> Self {
+    let full_path = file_system.get_full_path(file_path);
+    if let Some(in_memory) = file_system.in_memory_file(file_path) {
+      return Self::with_cached_file(&full_path.to_string_lossy(), &in_memory, mime_types);
+    }
+    // the start is needed to spot a byte order mark anyway; files that fit into it are sent
+    // from memory, larger ones stream the rest after it
+    let opened = file_system.open_file(file_path, DEFAULT_CHUNK_SIZE as u64);
+    let Some(OpenFile { file, metadata, start, cached }) = opened.await else {
+      return Self::empty_body(StatusCode::NotFound);
+    };
+    if let Some(cached) = cached {
+      return Self::with_cached_file(&full_path.to_string_lossy(), &cached, mime_types);
+    }
+    let content_type = mime_types.content_type(&full_path.to_string_lossy(), &start);
+    let header = metadata
+      .modified()
+      .map_err(FileError::from)
+      .and_then(|modified| {
+        HttpHeader::file_response_header(&content_type, metadata.len(), modified)
+      });
+
+    match header {
+      Ok(header) => {
+        if start.len() as u64 == metadata.len() {
+          return Self::new(StatusCode::Ok, Some(start), Some(Arc::new(header)));
+        }
+        let body = std::io::Cursor::new(start).chain(file);
+        Self::streamed(StatusCode::Ok, body, Some(Arc::new(header)))
+      }
+      Err(file_error) => Self::new(
+        StatusCode::InternalError,
+        Some(file_error.to_string().into_bytes()),
+        None,
+      ),
     }
   }
 
+  /// `307 Temporary Redirect` to `location`, which the client requests with the same method
+  /// and body
+  pub fn redirect(location: &str) -> Self {
+    Self::redirect_with(StatusCode::TemporaryRedirect, location)
+  }
+
+  /// `308 Permanent Redirect` to `location`, e.g. from `/docs` to `/docs/`; clients and
+  /// caches remember it, and the method and body are kept
+  pub fn permanent_redirect(location: &str) -> Self {
+    Self::redirect_with(StatusCode::PermanentRedirect, location)
+  }
+
+  /// `303 See Other`, sending the client to `GET` `location`, e.g. after a form was POSTed
+  pub fn see_other(location: &str) -> Self {
+    Self::redirect_with(StatusCode::SeeOther, location)
+  }
+
+  fn redirect_with(status_code: StatusCode, location: &str) -> Self {
+    Self::builder()
+      .status(status_code)
+      .header(HttpResponseHeaderKey::Location, location)
+      .empty()
+      .unwrap_or_else(Self::from)
+  }
+
   pub fn empty_body(status_code: StatusCode) -> Self {
-    HttpResponse { status_code, body: None, http_header: None }
+    Self::builder()
+      .status(status_code)
+      .empty()
+      .unwrap_or_else(Self::from)
   }
 
-  pub async fn send(&self, stream: &mut TcpStream) -> TokioResult<()> {
-    let body = match &self.body {
-      Some(b) => b,
-      None => "",
-    };
+  /// A response whose body is read from `reader` as it's sent, so large files and generated
+  /// content don't have to fit into memory. Unless `http_header` declares a `Content-Length`,
+  /// it's sent with `Transfer-Encoding: chunked`.
+  #[cfg(feature = "server")]
+  pub fn streamed<R: AsyncRead + Send + Unpin + 'static>(
+    status_code: StatusCode,
+    reader: R,
+    http_header: Option<Arc<HttpHeader>>,
+  ) -> Self {
+    let stream = BodyStream { reader: Box::new(reader) };
+    HttpResponse {
+      stream: Some(stream),
+      ..HttpResponse::new(status_code, None, http_header)
+    }
+  }
+
+  /// A `text/event-stream` of Server-Sent Events, pushed through the returned sender as they
+  /// happen, e.g. from a task the handler spawns. The response lasts until every sender is
+  /// dropped or the client disconnects, which the sender reports; a comment is sent every
+  /// [`DEFAULT_KEEP_ALIVE_INTERVAL`] the stream is idle so that's noticed.
+  #[cfg(feature = "server")]
+  pub fn event_stream() -> (Self, EventSender) {
+    Self::event_stream_with(Some(DEFAULT_KEEP_ALIVE_INTERVAL))
+  }
+
+  /// Like [`Self::event_stream`], with comments every `keep_alive` interval if any
+  #[cfg(feature = "server")]
+  pub fn event_stream_with(keep_alive: Option<std::time::Duration>) -> (Self, EventSender) {
+    let (reader, sender) = EventReader::channel(keep_alive);
+    let mut builder = super::header::HttpResponseHeaderBuilder::new();
+    builder.content_type("text/event-stream");
+    builder.cache_control("no-store");
+    let response = Self::streamed(StatusCode::Ok, reader, Some(Arc::new(builder.build())));
+    (response, sender)
+  }
+
+  /// Hand the connection to `on_upgrade` once this response, a `101 Switching Protocols` such
+  /// as [`super::websocket::handshake`] returns, has been sent. The server stops reading
+  /// requests from it then; `on_upgrade` runs on the connection's task until it's done.
+  #[cfg(feature = "server")]
+  pub fn on_upgrade<F, Fut>(mut self, on_upgrade: F) -> Self
+  where
+    F: FnOnce(Upgraded) -> Fut + Send + 'static,
+    Fut: std::future::Future<Output = ()> + Send + 'static,
+  {
+    self.upgrade = Some(OnUpgrade::new(on_upgrade));
+    self
+  }
+
+  /// What takes over the connection after a `101 Switching Protocols`, if anything does
+  #[cfg(feature = "server")]
+  pub fn take_upgrade(&mut self) -> Option<OnUpgrade> {
+    match self.status_code {
+      StatusCode::SwitchingProtocols => self.upgrade.take(),
+      _ => None,
+    }
+  }
+
+  /// This response, or `304 Not Modified` without a body if it's a successful `GET` or `HEAD`
+  /// whose `ETag` or `Last-Modified` shows the client has it cached already, see
+  /// [`HttpHeader::is_not_modified`]
+  pub fn conditional(self, request: &HttpRequest<'_>) -> Self {
+    let not_modified = self.status_code == StatusCode::Ok
+      && matches!(request.method(), Method::GET | Method::HEAD)
+      && self
+        .http_header
+        .as_deref()
+        .is_some_and(|header| request.header().is_not_modified(header));
+    if !not_modified {
+      return self;
+    }
+    let mut header = self.http_header.as_deref().cloned().unwrap_or_default();
+    header.remove(HttpResponseHeaderKey::ContentLength);
+    HttpResponse::new(StatusCode::NotModified, None, Some(Arc::new(header)))
+  }
+
+  /// Send the head only, framed as if the body followed, the way `HEAD` requests are answered;
+  /// a streamed body is never read
+  pub fn omit_body(&mut self) {
+    self.head_only = true;
+  }
 
+  /// Send `body` of `content_type` in place of the one set, keeping status and headers but
+  /// for the `Content-Length` of the old body
+  pub fn replace_body(&mut self, body: Vec<u8>, content_type: &str) {
+    if let Some(header) = self.http_header.as_mut() {
+      Arc::make_mut(header).remove(HttpResponseHeaderKey::ContentLength);
+    }
+    self.insert_header(HttpResponseHeaderKey::ContentType, content_type);
+    self.stream = None;
+    self.body = Some(body);
+  }
+
+  /// Read a streamed body into [`Self::body`], to work on it as a whole; only for streams
+  /// known to end, unlike event streams
+  #[cfg(feature = "server")]
+  pub async fn collect_body(&mut self) -> TokioResult<()> {
+    if let Some(mut body_stream) = self.stream.take() {
+      let mut body = Vec::new();
+      body_stream.reader.read_to_end(&mut body).await?;
+      self.body = Some(body);
+    }
+    Ok(())
+  }
+
+  /// Whether the body is sent from a [`BodyStream`] rather than [`Self::body`]
+  pub fn is_streamed(&self) -> bool {
+    self.stream.is_some()
+  }
+
+  /// What's known of the body's length up front, `None` for streamed ones
+  fn body_length(&self) -> Option<usize> {
+    match self.stream {
+      Some(_) => None,
+      None => Some(self.body.as_ref().map_or(0, Vec::len)),
+    }
+  }
+
+  /// Add or replace a header after the response has been built, e.g. by the server
+  pub fn insert_header<K: AsRef<str>>(&mut self, key: K, value: &str) {
     let header = self
       .http_header
-      .as_ref()
-      .map(|h| {
-        h.iter()
-          .map(|(k, v)| format!("{}: {}\r\n", k, v))
-          .collect::<String>()
+      .get_or_insert_with(|| Arc::new(HttpHeader::default()));
+    Arc::make_mut(header).insert(key.as_ref().to_string(), value.to_string());
+  }
+
+  /// Add `field` to `Vary`, unless it's listed already, so caches keep the responses to
+  /// requests that differ in it apart
+  pub fn add_vary(&mut self, field: &str) {
+    let vary = self
+      .http_header
+      .as_deref()
+      .and_then(|header| header.get(HttpResponseHeaderKey::Vary))
+      .cloned();
+    match vary {
+      Some(vary)
+        if vary
+          .split(',')
+          .any(|listed| listed.trim().eq_ignore_ascii_case(field)) => {}
+      Some(vary) => {
+        self.insert_header(HttpResponseHeaderKey::Vary, &format!("{}, {}", vary, field))
+      }
+      None => self.insert_header(HttpResponseHeaderKey::Vary, field),
+    }
+  }
+
+  /// Add a `Set-Cookie` field, one per cookie, alongside any set already
+  pub fn set_cookie(&mut self, cookie: &SetCookie) -> Result<(), CookieError> {
+    let value = cookie.header_value()?;
+    let header = self
+      .http_header
+      .get_or_insert_with(|| Arc::new(HttpHeader::default()));
+    Arc::make_mut(header).append(HttpResponseHeaderKey::SetCookie.as_ref().to_string(), value);
+    Ok(())
+  }
+
+  /// Answer in `version`, the request's, framing the body for it
+  pub fn set_version(&mut self, version: Version) {
+    self.version = version;
+  }
+
+  /// Have the connection closed once this response has been sent, telling the client so
+  pub fn close_connection(&mut self) {
+    self.insert_header(HttpResponseHeaderKey::Connection, "close");
+    if let Some(header) = self.http_header.as_mut() {
+      Arc::make_mut(header).remove(HttpResponseHeaderKey::KeepAlive);
+    }
+  }
+
+  /// Whether the connection has to be closed after this response, as it says so or its
+  /// framing doesn't let the client tell where it ends
+  pub fn closes_connection(&self) -> bool {
+    self
+      .framed_header()
+      .get(HttpResponseHeaderKey::Connection)
+      .is_some_and(|connection| {
+        connection
+          .split(',')
+          .any(|option| option.trim().eq_ignore_ascii_case("close"))
       })
-      .unwrap_or_default();
-
-    stream
-      .write_all(
-        format!(
-          "{} {} {}\r\n{}\r\n{}",
-          HTTP1,
-          self.status_code,
-          self.status_code.reason_phrase(),
-          header,
-          body
-        )
-        .as_bytes(),
-      )
-      .await?;
+  }
+
+  /// The header to send. Keep-alive clients can only tell where the response ends from its
+  /// `Content-Length` or chunked encoding, so bodies without either get a `Content-Length`,
+  /// and responses whose framing is still off (e.g. a wrong length) close the connection
+  /// rather than leave the client waiting. Streamed bodies are taken at their word, except by
+  /// HTTP/1.0 clients, which only learn where they end from the connection closing.
+  fn framed_header(&self) -> HttpHeader {
+    let body_length = self.body_length();
+    let mut header = self.http_header.as_deref().cloned().unwrap_or_default();
+    if !self.status_code.allows_body() {
+      return header;
+    }
+
+    let content_length = header.get(HttpResponseHeaderKey::ContentLength);
+    let transfer_encoding = header.get(HttpResponseHeaderKey::TransferEncoding);
+    let framed = match (content_length, transfer_encoding) {
+      // HTTP/1.0 clients know no chunked encoding, so the body ends with the connection
+      (_, Some(_)) if self.version == Version::Http10 && self.stream.is_some() => {
+        header.remove(HttpResponseHeaderKey::TransferEncoding);
+        false
+      }
+      (_, Some(transfer_encoding)) => transfer_encoding
+        .rsplit(',')
+        .next()
+        .is_some_and(|coding| coding.trim().eq_ignore_ascii_case("chunked")),
+      (Some(content_length), None) => match body_length {
+        Some(body_length) => content_length.trim().parse::<usize>() == Ok(body_length),
+        None => content_length.trim().parse::<usize>().is_ok(),
+      },
+      (None, None) => match body_length {
+        Some(body_length) => {
+          header.insert(
+            HttpResponseHeaderKey::ContentLength.as_ref().to_string(),
+            body_length.to_string(),
+          );
+          true
+        }
+        None if self.version == Version::Http10 => false,
+        None => {
+          header.insert(
+            HttpResponseHeaderKey::TransferEncoding.as_ref().to_string(),
+            "chunked".to_string(),
+          );
+          true
+        }
+      },
+    };
+    if !framed {
+      header.insert(
+        HttpResponseHeaderKey::Connection.as_ref().to_string(),
+        "close".to_string(),
+      );
+      header.remove(HttpResponseHeaderKey::KeepAlive);
+    }
+    header
+  }
+
+  /// The status line and header as sent ahead of [`Self::body`], framed for it
+  pub fn head(&self) -> String {
+    let header = self
+      .framed_header()
+      .iter()
+      .map(|(k, v)| format!("{}: {}\r\n", canonical_name(k), v))
+      .collect::<String>();
+
+    format!(
+      "{} {} {}\r\n{}\r\n",
+      self.version,
+      self.status_code,
+      self.status_code.reason_phrase(),
+      header
+    )
+  }
+
+  /// Write the response and return the number of bytes sent
+  #[cfg(feature = "server")]
+  pub async fn send<W: AsyncWrite + Unpin>(&mut self, stream: &mut W) -> TokioResult<usize> {
+    self.send_in_chunks(stream, DEFAULT_CHUNK_SIZE).await
+  }
+
+  /// Write the head, then the body `chunk_size` bytes at a time, each write waiting until the
+  /// connection takes more. Smaller chunks hand the runtime back sooner on slow clients,
+  /// larger ones need fewer writes on fast ones. On connections with vectored writes, the head
+  /// goes out along with the first chunk, and chunked framing along with its data. A streamed
+  /// body is used up by this.
+  #[cfg(feature = "server")]
+  pub async fn send_in_chunks<W: AsyncWrite + Unpin>(
+    &mut self,
+    stream: &mut W,
+    chunk_size: usize,
+  ) -> TokioResult<usize> {
+    let head = self.head();
+    if self.head_only {
+      stream.write_all(head.as_bytes()).await?;
+      stream.flush().await?;
+      return Ok(head.len());
+    }
+    let chunked = self
+      .framed_header()
+      .get(HttpResponseHeaderKey::TransferEncoding)
+      .is_some();
+    let body_length = match self.stream.as_mut() {
+      Some(body_stream) => {
+        // the body may take a while to produce, the client gets the head meanwhile
+        stream.write_all(head.as_bytes()).await?;
+        stream.flush().await?;
+        Self::send_stream(body_stream, stream, chunk_size, chunked).await?
+      }
+      None => {
+        let body = self.body.as_deref().unwrap_or_default();
+        let mut chunks = body.chunks(chunk_size.max(1));
+        let first_chunk = chunks.next().unwrap_or_default();
+        let mut slices = [IoSlice::new(head.as_bytes()), IoSlice::new(first_chunk)];
+        write_all_vectored(stream, &mut slices).await?;
+        for chunk in chunks {
+          stream.write_all(chunk).await?;
+        }
+        body.len()
+      }
+    };
 
     // Ensure all data is sent
     stream.flush().await?;
 
+    Ok(head.len() + body_length)
+  }
+
+  /// Copy `body_stream` to `stream` `chunk_size` bytes at a time, in chunked encoding if
+  /// `chunked`, and return the number of bytes written
+  #[cfg(feature = "server")]
+  async fn send_stream<W: AsyncWrite + Unpin>(
+    body_stream: &mut BodyStream,
+    stream: &mut W,
+    chunk_size: usize,
+    chunked: bool,
+  ) -> TokioResult<usize> {
+    let mut chunk = vec![0; chunk_size.max(1)];
+    let mut sent = 0;
+    loop {
+      let bytes_read = body_stream.reader.read(&mut chunk).await?;
+      if bytes_read == 0 {
+        break;
+      }
+      if chunked {
+        let size_line = format!("{:X}\r\n", bytes_read);
+        let mut slices = [
+          IoSlice::new(size_line.as_bytes()),
+          IoSlice::new(&chunk[..bytes_read]),
+          IoSlice::new(b"\r\n"),
+        ];
+        write_all_vectored(stream, &mut slices).await?;
+        sent += size_line.len() + bytes_read + 2;
+      } else {
+        stream.write_all(&chunk[..bytes_read]).await?;
+        sent += bytes_read;
+      }
+      // what's read may be all there is for a while, as with event streams
+      stream.flush().await?;
+    }
+    if chunked {
+      stream.write_all(b"0\r\n\r\n").await?;
+      sent += 5;
+    }
+    Ok(sent)
+  }
+}
+
+/// `write_all` for several buffers: as few writes as the connection allows, each picking up
+/// where the last left off. Those without vectored writes take the first buffer at a time.
+#[cfg(feature = "server")]
+async fn write_all_vectored<W: AsyncWrite + Unpin>(
+  stream: &mut W,
+  mut slices: &mut [IoSlice<'_>],
+) -> TokioResult<()> {
+  IoSlice::advance_slices(&mut slices, 0);
+  while !slices.is_empty() {
+    let written = stream.write_vectored(slices).await?;
+    if written == 0 {
+      return Err(IoError::from(ErrorKind::WriteZero));
+    }
+    IoSlice::advance_slices(&mut slices, written);
+  }
+  Ok(())
+}
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum ResponseError {
+  #[error("Header name {0:?} isn't a token")]
+  InvalidHeaderName(String),
+  #[error("Value of header {0} has control characters")]
+  InvalidHeaderValue(String),
+  #[error("{0} responses can't have a body")]
+  BodyNotAllowed(StatusCode),
+}
+
+/// A response that couldn't be built is the server's fault
+impl From<ResponseError> for HttpResponse {
+  fn from(error: ResponseError) -> Self {
+    HttpResponse::new(
+      StatusCode::InternalError,
+      Some(format!("{}\n", error).into_bytes()),
+      None,
+    )
+  }
+}
+
+/// Builds an [`HttpResponse`], see [`HttpResponse::builder`]. Headers are checked once the
+/// body is given, so a value taken from a request can't split the response.
+#[derive(Debug, Default)]
+pub struct ResponseBuilder {
+  status_code: Option<StatusCode>,
+  http_header: Option<Arc<HttpHeader>>,
+}
+
+impl ResponseBuilder {
+  /// `200 Ok` unless set
+  pub fn status(mut self, status_code: StatusCode) -> Self {
+    self.status_code = Some(status_code);
+    self
+  }
+
+  /// Add or replace a header, see [`HttpHeader::insert`]
+  pub fn header<K: AsRef<str>>(mut self, key: K, value: &str) -> Self {
+    let header = self.http_header.get_or_insert_with(Default::default);
+    Arc::make_mut(header).insert(key.as_ref().to_string(), value.to_string());
+    self
+  }
+
+  /// Start from the headers of `http_header`, e.g. ones shared between responses
+  pub fn http_header(mut self, http_header: Arc<HttpHeader>) -> Self {
+    self.http_header = Some(http_header);
+    self
+  }
+
+  pub fn body<B: Into<Vec<u8>>>(self, body: B) -> Result<HttpResponse, ResponseError> {
+    self.build(Some(body.into()))
+  }
+
+  /// Without a body, as `204 No Content` or `304 Not Modified` responses have to be
+  pub fn empty(self) -> Result<HttpResponse, ResponseError> {
+    self.build(None)
+  }
+
+  fn build(self, body: Option<Vec<u8>>) -> Result<HttpResponse, ResponseError> {
+    let status_code = self.status_code.unwrap_or(StatusCode::Ok);
+    if body.as_ref().is_some_and(|body| !body.is_empty()) && !status_code.allows_body() {
+      return Err(ResponseError::BodyNotAllowed(status_code));
+    }
+    for (name, value) in self.http_header.iter().flat_map(|header| header.iter()) {
+      if !is_token(name) {
+        return Err(ResponseError::InvalidHeaderName(name.clone()));
+      }
+      // obs-text is let through, as in request headers; CR and LF would end the field
+      if value
+        .bytes()
+        .any(|byte| byte.is_ascii_control() && byte != b'\t')
+      {
+        return Err(ResponseError::InvalidHeaderValue(canonical_name(name)));
+      }
+    }
+    Ok(HttpResponse::new(status_code, body, self.http_header))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::http::header::HttpResponseHeaderBuilder;
+  use expectest::prelude::*;
+  use rstest::*;
+  #[cfg(feature = "server")]
+  use std::{
+    io,
+    pin::Pin,
+    task::{Context, Poll},
+  };
+
+  fn serialize(response: &HttpResponse) -> String {
+    format!(
+      "{}{}",
+      response.head(),
+      String::from_utf8_lossy(response.body().as_deref().unwrap_or_default())
+    )
+  }
+
+  #[rstest]
+  fn test_builder() -> Result<(), ResponseError> {
+    let response = HttpResponse::builder()
+      .status(StatusCode::Accepted)
+      .header("x-request-id", "42")
+      .header("Content-Type", "text/plain")
+      .header("X-Request-Id", "43")
+      .body("queued")?;
+    expect!(serialize(&response)).to(be_equal_to(
+      "HTTP/1.1 202 Accepted\r\nX-Request-Id: 43\r\nContent-Type: text/plain\r\nContent-Length: 6\r\n\r\nqueued",
+    ));
+    let response = HttpResponse::builder().empty()?;
+    expect!(*response.status_code()).to(be_equal_to(StatusCode::Ok));
+    expect!(response.body().as_deref()).to(be_none());
+    Ok(())
+  }
+
+  #[rstest]
+  #[case::name_with_space(
+    HttpResponse::builder().header("X Trail", "1").empty(),
+    ResponseError::InvalidHeaderName("X Trail".to_string())
+  )]
+  #[case::value_with_newline(
+    HttpResponse::builder().header("location", "/\r\nSet-Cookie: a=1").empty(),
+    ResponseError::InvalidHeaderValue("Location".to_string())
+  )]
+  #[case::body_of_no_content(
+    HttpResponse::builder().status(StatusCode::NoContent).body("x"),
+    ResponseError::BodyNotAllowed(StatusCode::NoContent)
+  )]
+  fn test_builder_rejects(
+    #[case] response: Result<HttpResponse, ResponseError>,
+    #[case] expected: ResponseError,
+  ) {
+    expect!(response.map(|response| *response.status_code())).to(be_err().value(expected));
+  }
+
+  #[rstest]
+  #[case::temporary(HttpResponse::redirect("/login"), "307 Temporary Redirect")]
+  #[case::permanent(HttpResponse::permanent_redirect("/login"), "308 Permanent Redirect")]
+  #[case::see_other(HttpResponse::see_other("/login"), "303 See Other")]
+  fn test_redirect(#[case] response: HttpResponse, #[case] status_line: &str) {
+    expect!(serialize(&response)).to(be_equal_to(format!(
+      "HTTP/1.1 {}\r\nLocation: /login\r\nContent-Length: 0\r\n\r\n",
+      status_line
+    )));
+  }
+
+  #[rstest]
+  fn test_redirect_refuses_split_location() {
+    let response = HttpResponse::see_other("/\r\nSet-Cookie: session=stolen");
+    expect!(*response.status_code()).to(be_equal_to(StatusCode::InternalError));
+  }
+
+  /// Takes at most `capacity` bytes per write, remembering the size of each
+  #[cfg(feature = "server")]
+  #[derive(Default)]
+  struct RecordingWriter {
+    capacity: usize,
+    /// takes all buffers of a vectored write rather than only the first
+    vectored: bool,
+    written: Vec<u8>,
+    writes: Vec<usize>,
+  }
+
+  #[cfg(feature = "server")]
+  impl AsyncWrite for RecordingWriter {
+    fn poll_write(
+      mut self: Pin<&mut Self>,
+      _cx: &mut Context<'_>,
+      buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+      let accepted = buf.len().min(self.capacity);
+      self.written.extend_from_slice(&buf[..accepted]);
+      self.writes.push(accepted);
+      Poll::Ready(Ok(accepted))
+    }
+
+    fn poll_write_vectored(
+      mut self: Pin<&mut Self>,
+      _cx: &mut Context<'_>,
+      bufs: &[io::IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+      let mut accepted = 0;
+      for buf in bufs.iter().take(if self.vectored { bufs.len() } else { 1 }) {
+        let taken = buf.len().min(self.capacity - accepted);
+        self.written.extend_from_slice(&buf[..taken]);
+        accepted += taken;
+      }
+      self.writes.push(accepted);
+      Poll::Ready(Ok(accepted))
+    }
+
+    fn is_write_vectored(&self) -> bool {
+      self.vectored
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+      Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+      Poll::Ready(Ok(()))
+    }
+  }
+
+  #[cfg(feature = "server")]
+  #[rstest]
+  #[tokio::test]
+  async fn test_send_body_in_chunks() -> io::Result<()> {
+    let body = "x".repeat(10_000);
+    let mut response = HttpResponse::new(StatusCode::Ok, Some(body.clone().into_bytes()), None);
+    let mut writer = RecordingWriter { capacity: usize::MAX, ..Default::default() };
+
+    let sent = response.send_in_chunks(&mut writer, 4096).await?;
+    let head = "HTTP/1.1 200 Ok\r\nContent-Length: 10000\r\n\r\n";
+    expect!(sent).to(be_equal_to(head.len() + body.len()));
+    expect!(writer.writes).to(be_equal_to(vec![head.len(), 4096, 4096, 1808]));
+    expect!(writer.written).to(be_equal_to(format!("{}{}", head, body).into_bytes()));
+    Ok(())
+  }
+
+  #[cfg(feature = "server")]
+  #[rstest]
+  #[case::whole(usize::MAX, vec![42 + 4096, 4096, 1808])]
+  #[case::partial(100, vec![100, 100, 100])]
+  #[tokio::test]
+  async fn test_send_head_with_first_chunk(
+    #[case] capacity: usize,
+    #[case] expected_writes: Vec<usize>,
+  ) -> io::Result<()> {
+    let body = "x".repeat(10_000);
+    let mut response = HttpResponse::new(StatusCode::Ok, Some(body.clone().into_bytes()), None);
+    let mut writer = RecordingWriter { capacity, vectored: true, ..Default::default() };
+
+    response.send_in_chunks(&mut writer, 4096).await?;
+    let head = "HTTP/1.1 200 Ok\r\nContent-Length: 10000\r\n\r\n";
+    expect!(writer.writes[..3].to_vec()).to(be_equal_to(expected_writes));
+    expect!(writer.written).to(be_equal_to(format!("{}{}", head, body).into_bytes()));
+    Ok(())
+  }
+
+  #[rstest]
+  fn test_send_headers_in_order_with_canonical_names() {
+    let mut builder = HttpResponseHeaderBuilder::new();
+    builder.content_type("text/plain");
+    builder.custom("x-request-id".to_string(), "42");
+    builder.content_length("2");
+    let mut response = HttpResponse::new(
+      StatusCode::Ok,
+      Some(b"ok".to_vec()),
+      Some(Arc::new(builder.build())),
+    );
+    response.insert_header("etag", "\"v1\"");
+
+    expect!(serialize(&response)).to(be_equal_to(
+      "HTTP/1.1 200 Ok\r\nContent-Type: text/plain\r\nX-Request-Id: 42\r\nContent-Length: 2\r\nETag: \"v1\"\r\n\r\nok",
+    ));
+  }
+
+  #[rstest]
+  #[case::missing(
+    StatusCode::NotFound,
+    None,
+    "HTTP/1.1 404 Not Found\r\nContent-Length: 9\r\n\r\nnot found"
+  )]
+  #[case::bodiless_status(StatusCode::NoContent, None, "HTTP/1.1 204 No Content\r\n\r\n")]
+  #[case::transfer_encoding(
+    StatusCode::Ok,
+    Some(("Transfer-Encoding", "chunked")),
+    "HTTP/1.1 200 Ok\r\nTransfer-Encoding: chunked\r\n\r\n"
+  )]
+  #[case::already_set(
+    StatusCode::Ok,
+    Some(("content-length", "0")),
+    "HTTP/1.1 200 Ok\r\nContent-Length: 0\r\n\r\n"
+  )]
+  #[case::wrong_length(
+    StatusCode::NotFound,
+    Some(("Content-Length", "4")),
+    "HTTP/1.1 404 Not Found\r\nContent-Length: 4\r\nConnection: close\r\n\r\nnot found"
+  )]
+  #[case::not_chunked(
+    StatusCode::Ok,
+    Some(("Transfer-Encoding", "gzip")),
+    "HTTP/1.1 200 Ok\r\nTransfer-Encoding: gzip\r\nConnection: close\r\n\r\n"
+  )]
+  fn test_compute_content_length(
+    #[case] status_code: StatusCode,
+    #[case] header: Option<(&str, &str)>,
+    #[case] expected: &str,
+  ) {
+    let body = match status_code {
+      StatusCode::NotFound => Some(b"not found".to_vec()),
+      _ => None,
+    };
+    let mut response = HttpResponse::new(status_code, body, None);
+    if let Some((key, value)) = header {
+      response.insert_header(key, value);
+    }
+
+    expect!(serialize(&response)).to(be_equal_to(expected));
+  }
+
+  #[cfg(feature = "server")]
+  #[rstest]
+  #[tokio::test]
+  async fn test_send_from_another_task() -> Result<(), Box<dyn std::error::Error>> {
+    let produced = tokio::spawn(async {
+      HttpResponse::builder()
+        .header("X-Produced-By", "worker")
+        .body("done")
+    });
+    let mut response = produced.await??;
+    let mut writer = RecordingWriter { capacity: usize::MAX, ..Default::default() };
+    let sent = tokio::spawn(async move {
+      response.send(&mut writer).await?;
+      io::Result::Ok(writer.written)
+    });
+    expect!(String::from_utf8(sent.await??)?).to(be_equal_to(
+      "HTTP/1.1 200 Ok\r\nX-Produced-By: worker\r\nContent-Length: 4\r\n\r\ndone".to_string(),
+    ));
+    Ok(())
+  }
+
+  #[cfg(feature = "server")]
+  #[rstest]
+  #[tokio::test]
+  async fn test_with_cached_file() -> Result<(), Box<dyn std::error::Error>> {
+    use crate::file_cache::FileCache;
+    use crate::filesystem::LocalFileSystem;
+
+    let public_path = tempfile::TempDir::new()?;
+    std::fs::write(public_path.path().join("index.html"), "<h1>Hello</h1>")?;
+    let public_path = public_path
+      .path()
+      .canonicalize()?
+      .to_string_lossy()
+      .to_string();
+    let uncached = LocalFileSystem::new(public_path.clone());
+    let cached = LocalFileSystem::new(public_path).with_cache(FileCache::new(Default::default()));
+    let mime_types = MimeTypes::default();
+
+    let expected = HttpResponse::with_file("index.html", &uncached, &mime_types).await;
+    for _ in 0..2 {
+      let response = HttpResponse::with_file("index.html", &cached, &mime_types).await;
+      expect!(serialize(&response)).to(be_equal_to(serialize(&expected)));
+    }
+    expect!(cached.cache().map(FileCache::len)).to(be_some().value(1));
+    Ok(())
+  }
+
+  #[cfg(feature = "server")]
+  #[rstest]
+  #[tokio::test]
+  async fn test_send_to_slow_reader() -> io::Result<()> {
+    let body = "y".repeat(100_000);
+    let mut response = HttpResponse::new(StatusCode::Ok, Some(body.clone().into_bytes()), None);
+    // the sender has to wait for the reader whenever the pipe's 1KB are full
+    let (mut client, mut server) = tokio::io::duplex(1024);
+
+    let reader = tokio::spawn(async move {
+      let mut received = Vec::new();
+      tokio::io::AsyncReadExt::read_to_end(&mut client, &mut received).await?;
+      io::Result::Ok(received)
+    });
+    response.send_in_chunks(&mut server, 8192).await?;
+    drop(server);
+
+    let received = reader.await??;
+    expect!(received.ends_with(body.as_bytes())).to(be_true());
+    Ok(())
+  }
+
+  #[cfg(feature = "server")]
+  #[rstest]
+  #[case::chunked(None, "HTTP/1.1 200 Ok\r\nTransfer-Encoding: chunked\r\n\r\n4\r\nstre\r\n4\r\named\r\n2\r\n!\n\r\n0\r\n\r\n")]
+  #[case::content_length(Some("10"), "HTTP/1.1 200 Ok\r\nContent-Length: 10\r\n\r\nstreamed!\n")]
+  #[tokio::test]
+  async fn test_send_streamed_body(
+    #[case] content_length: Option<&str>,
+    #[case] expected: &str,
+  ) -> io::Result<()> {
+    let mut builder = HttpResponseHeaderBuilder::new();
+    if let Some(content_length) = content_length {
+      builder.content_length(content_length);
+    }
+    let header = Some(Arc::new(builder.build())).filter(|_| content_length.is_some());
+    let mut response = HttpResponse::streamed(StatusCode::Ok, &b"streamed!\n"[..], header);
+    let mut writer = RecordingWriter { capacity: usize::MAX, vectored: true, ..Default::default() };
+
+    let sent = response.send_in_chunks(&mut writer, 4).await?;
+    // the head, then one write per chunk and its framing
+    expect!(writer.writes.len()).to(be_equal_to(match content_length {
+      Some(_) => 4,
+      None => 5,
+    }));
+    expect!(String::from_utf8(writer.written).unwrap()).to(be_equal_to(expected));
+    expect!(sent).to(be_equal_to(expected.len()));
+    expect!(response.closes_connection()).to(be_false());
+    Ok(())
+  }
+
+  #[cfg(feature = "server")]
+  #[rstest]
+  #[tokio::test]
+  async fn test_send_streamed_body_to_http_1_0() -> io::Result<()> {
+    let mut response = HttpResponse::streamed(StatusCode::Ok, &b"streamed!\n"[..], None);
+    response.set_version(Version::Http10);
+    let mut sent = Vec::new();
+    response.send(&mut sent).await?;
+    // no chunked encoding, the body lasts until the connection closes
+    expect!(String::from_utf8(sent).unwrap()).to(be_equal_to(
+      "HTTP/1.0 200 Ok\r\nConnection: close\r\n\r\nstreamed!\n",
+    ));
+    expect!(response.closes_connection()).to(be_true());
+    Ok(())
+  }
+
+  #[rstest]
+  fn test_set_cookies() -> Result<(), CookieError> {
+    let mut response = HttpResponse::empty_body(StatusCode::NoContent);
+    response.set_cookie(&SetCookie::new("session", "abc").http_only())?;
+    response.set_cookie(&SetCookie::removal("theme"))?;
+    expect!(response.head()).to(be_equal_to(
+      "HTTP/1.1 204 No Content\r\nSet-Cookie: session=abc; HttpOnly\r\nSet-Cookie: theme=; Max-Age=0\r\n\r\n"
+        .to_string(),
+    ));
+    expect!(response.set_cookie(&SetCookie::new("a", "b;c"))).to(be_err());
+    Ok(())
+  }
+
+  #[cfg(feature = "server")]
+  #[rstest]
+  #[case::buffered(false, "HTTP/1.1 200 Ok\r\nContent-Length: 10\r\n\r\n")]
+  #[case::streamed(true, "HTTP/1.1 200 Ok\r\nTransfer-Encoding: chunked\r\n\r\n")]
+  #[tokio::test]
+  async fn test_omit_body(#[case] streamed: bool, #[case] expected: &str) -> io::Result<()> {
+    let mut response = match streamed {
+      true => HttpResponse::streamed(StatusCode::Ok, &b"streamed!\n"[..], None),
+      false => HttpResponse::new(StatusCode::Ok, Some(b"buffered!\n".to_vec()), None),
+    };
+    response.omit_body();
+    let mut writer = RecordingWriter { capacity: usize::MAX, ..Default::default() };
+
+    let sent = response.send(&mut writer).await?;
+    expect!(String::from_utf8(writer.written).unwrap()).to(be_equal_to(expected));
+    expect!(sent).to(be_equal_to(expected.len()));
     Ok(())
   }
 }
@@ -1,28 +1,180 @@
 use std::fmt::{Display, Formatter, Result as FmtResult};
 
-#[derive(Clone, Copy, Debug)]
-pub enum StatusCode {
-  Ok = 200,
-  NoContent = 204,
-  BadRequest = 400,
-  NotFound = 404,
-  InternalError = 500,
+/// Declares the codes of the IANA HTTP Status Code Registry along with their reason phrases,
+/// so the variants, [`StatusCode::from_u16`] and [`StatusCode::reason_phrase`] can't drift
+/// apart
+macro_rules! status_codes {
+  ($($variant:ident = $code:literal, $reason:literal;)*) => {
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+    pub enum StatusCode {
+      $($variant = $code,)*
+    }
+
+    impl StatusCode {
+      pub fn from_u16(code: u16) -> Option<Self> {
+        match code {
+          $($code => Some(Self::$variant),)*
+          _ => None,
+        }
+      }
+
+      pub fn reason_phrase(&self) -> &str {
+        match self {
+          $(Self::$variant => $reason,)*
+        }
+      }
+    }
+  };
+}
+
+status_codes! {
+  Continue = 100, "Continue";
+  SwitchingProtocols = 101, "Switching Protocols";
+  Processing = 102, "Processing";
+  EarlyHints = 103, "Early Hints";
+  Ok = 200, "Ok";
+  Created = 201, "Created";
+  Accepted = 202, "Accepted";
+  NonAuthoritativeInformation = 203, "Non-Authoritative Information";
+  NoContent = 204, "No Content";
+  ResetContent = 205, "Reset Content";
+  PartialContent = 206, "Partial Content";
+  MultiStatus = 207, "Multi-Status";
+  AlreadyReported = 208, "Already Reported";
+  ImUsed = 226, "IM Used";
+  MultipleChoices = 300, "Multiple Choices";
+  MovedPermanently = 301, "Moved Permanently";
+  Found = 302, "Found";
+  SeeOther = 303, "See Other";
+  NotModified = 304, "Not Modified";
+  UseProxy = 305, "Use Proxy";
+  TemporaryRedirect = 307, "Temporary Redirect";
+  PermanentRedirect = 308, "Permanent Redirect";
+  BadRequest = 400, "Bad Request";
+  Unauthorized = 401, "Unauthorized";
+  PaymentRequired = 402, "Payment Required";
+  Forbidden = 403, "Forbidden";
+  NotFound = 404, "Not Found";
+  MethodNotAllowed = 405, "Method Not Allowed";
+  NotAcceptable = 406, "Not Acceptable";
+  ProxyAuthenticationRequired = 407, "Proxy Authentication Required";
+  RequestTimeout = 408, "Request Timeout";
+  Conflict = 409, "Conflict";
+  Gone = 410, "Gone";
+  LengthRequired = 411, "Length Required";
+  PreconditionFailed = 412, "Precondition Failed";
+  PayloadTooLarge = 413, "Payload Too Large";
+  UriTooLong = 414, "URI Too Long";
+  UnsupportedMediaType = 415, "Unsupported Media Type";
+  RangeNotSatisfiable = 416, "Range Not Satisfiable";
+  ExpectationFailed = 417, "Expectation Failed";
+  MisdirectedRequest = 421, "Misdirected Request";
+  UnprocessableContent = 422, "Unprocessable Content";
+  Locked = 423, "Locked";
+  FailedDependency = 424, "Failed Dependency";
+  TooEarly = 425, "Too Early";
+  UpgradeRequired = 426, "Upgrade Required";
+  PreconditionRequired = 428, "Precondition Required";
+  TooManyRequests = 429, "Too Many Requests";
+  RequestHeaderFieldsTooLarge = 431, "Request Header Fields Too Large";
+  UnavailableForLegalReasons = 451, "Unavailable For Legal Reasons";
+  InternalError = 500, "Internal Error";
+  NotImplemented = 501, "Not Implemented";
+  BadGateway = 502, "Bad Gateway";
+  ServiceUnavailable = 503, "Service Unavailable";
+  GatewayTimeout = 504, "Gateway Timeout";
+  HttpVersionNotSupported = 505, "HTTP Version Not Supported";
+  VariantAlsoNegotiates = 506, "Variant Also Negotiates";
+  InsufficientStorage = 507, "Insufficient Storage";
+  LoopDetected = 508, "Loop Detected";
+  NotExtended = 510, "Not Extended";
+  NetworkAuthenticationRequired = 511, "Network Authentication Required";
 }
 
 impl StatusCode {
-  pub fn reason_phrase(&self) -> &str {
-    match self {
-      Self::Ok => "Ok",
-      Self::NoContent => "No Content",
-      Self::BadRequest => "Bad Request",
-      Self::NotFound => "Not Found",
-      Self::InternalError => "Internal Error",
-    }
+  pub fn as_u16(&self) -> u16 {
+    *self as u16
+  }
+
+  /// 1xx, `204 No Content` and `304 Not Modified` responses never carry a body, nor a
+  /// `Content-Length` for one (RFC 9110, section 8.6)
+  pub fn allows_body(&self) -> bool {
+    !matches!(self.as_u16(), 100..=199 | 204 | 304)
+  }
+
+  /// 1xx, sent ahead of the final response
+  pub fn is_informational(&self) -> bool {
+    (100..200).contains(&self.as_u16())
+  }
+
+  /// 2xx
+  pub fn is_success(&self) -> bool {
+    (200..300).contains(&self.as_u16())
+  }
+
+  /// 3xx
+  pub fn is_redirection(&self) -> bool {
+    (300..400).contains(&self.as_u16())
+  }
+
+  /// 4xx
+  pub fn is_client_error(&self) -> bool {
+    (400..500).contains(&self.as_u16())
+  }
+
+  /// 5xx
+  pub fn is_server_error(&self) -> bool {
+    (500..600).contains(&self.as_u16())
   }
 }
 
 impl Display for StatusCode {
   fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
-    write!(f, "{}", *self as u16)
+    write!(f, "{}", self.as_u16())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use expectest::prelude::*;
+  use rstest::*;
+
+  #[rstest]
+  fn test_from_u16_round_trip() {
+    let codes = (100..600)
+      .filter_map(StatusCode::from_u16)
+      .collect::<Vec<_>>();
+    expect!(codes.len()).to(be_equal_to(61));
+    for status_code in codes {
+      expect!(StatusCode::from_u16(status_code.as_u16())).to(be_some().value(status_code));
+    }
+    expect!(StatusCode::from_u16(418)).to(be_none());
+    expect!(StatusCode::from_u16(999)).to(be_none());
+  }
+
+  #[rstest]
+  #[case(StatusCode::Continue, [true, false, false, false, false])]
+  #[case(StatusCode::Created, [false, true, false, false, false])]
+  #[case(StatusCode::PermanentRedirect, [false, false, true, false, false])]
+  #[case(StatusCode::RequestHeaderFieldsTooLarge, [false, false, false, true, false])]
+  #[case(StatusCode::BadGateway, [false, false, false, false, true])]
+  fn test_categories(#[case] status_code: StatusCode, #[case] expected: [bool; 5]) {
+    let categories = [
+      status_code.is_informational(),
+      status_code.is_success(),
+      status_code.is_redirection(),
+      status_code.is_client_error(),
+      status_code.is_server_error(),
+    ];
+    expect!(categories).to(be_equal_to(expected));
+  }
+
+  #[rstest]
+  #[case(StatusCode::MovedPermanently, "301 Moved Permanently")]
+  #[case(StatusCode::HttpVersionNotSupported, "505 HTTP Version Not Supported")]
+  fn test_status_line(#[case] status_code: StatusCode, #[case] expected: &str) {
+    let status_line = format!("{} {}", status_code, status_code.reason_phrase());
+    expect!(status_line.as_str()).to(be_equal_to(expected));
   }
 }
@@ -0,0 +1,211 @@
+use std::{
+  collections::hash_map::RandomState,
+  fmt::{Display, Formatter, Result as FmtResult},
+  hash::BuildHasher,
+  sync::atomic::{AtomicU64, Ordering},
+};
+
+use super::header::HttpHeader;
+
+pub const TRACEPARENT: &str = "traceparent";
+pub const TRACESTATE: &str = "tracestate";
+
+/// Longest `tracestate` passed on, longer ones may be dropped (W3C Trace Context, section 3.3.1.5)
+const MAX_TRACESTATE_LENGTH: usize = 512;
+const SAMPLED: u8 = 0x01;
+
+/// Position of a request within a distributed trace, taken from its
+/// [`traceparent`](https://www.w3.org/TR/trace-context/) header. Requests without a valid
+/// one start a new trace, so every request has a trace ID to log.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceContext {
+  trace_id: u128,
+  parent_id: u64,
+  flags: u8,
+  tracestate: Option<String>,
+}
+
+impl TraceContext {
+  /// The caller's context, or a new trace if it sent none or a malformed one
+  pub fn from_header(header: &HttpHeader) -> Self {
+    let Some(mut context) = header.get(TRACEPARENT).and_then(|value| Self::parse(value)) else {
+      return Self::new_root();
+    };
+    // only meaningful next to the traceparent it was sent with
+    context.tracestate = header
+      .get(TRACESTATE)
+      .map(|value| value.trim().to_string())
+      .filter(|value| !value.is_empty() && value.len() <= MAX_TRACESTATE_LENGTH);
+    context
+  }
+
+  pub fn new_root() -> Self {
+    Self {
+      trace_id: (u128::from(random_id()) << 64) | u128::from(random_id()),
+      parent_id: random_id(),
+      flags: 0,
+      tracestate: None,
+    }
+  }
+
+  /// Parse `version-trace_id-parent_id-flags`, ignoring fields future versions may append
+  pub fn parse(traceparent: &str) -> Option<Self> {
+    let mut fields = traceparent.trim().split('-');
+    let version = fields.next().filter(|version| is_lower_hex(version, 2))?;
+    let trace_id = fields
+      .next()
+      .filter(|trace_id| is_lower_hex(trace_id, 32))?;
+    let parent_id = fields
+      .next()
+      .filter(|parent_id| is_lower_hex(parent_id, 16))?;
+    let flags = fields.next().filter(|flags| is_lower_hex(flags, 2))?;
+    match version {
+      "ff" => return None,
+      "00" if fields.next().is_some() => return None,
+      _ => {}
+    }
+
+    let context = Self {
+      trace_id: u128::from_str_radix(trace_id, 16).ok()?,
+      parent_id: u64::from_str_radix(parent_id, 16).ok()?,
+      flags: u8::from_str_radix(flags, 16).ok()?,
+      tracestate: None,
+    };
+    // all-zero IDs are invalid
+    Some(context).filter(|context| context.trace_id != 0 && context.parent_id != 0)
+  }
+
+  /// The context to hand to upstream services: same trace, this server as the parent
+  pub fn child(&self) -> Self {
+    Self { parent_id: random_id(), ..self.clone() }
+  }
+
+  pub fn trace_id(&self) -> String {
+    format!("{:032x}", self.trace_id)
+  }
+
+  pub fn parent_id(&self) -> String {
+    format!("{:016x}", self.parent_id)
+  }
+
+  pub fn sampled(&self) -> bool {
+    self.flags & SAMPLED != 0
+  }
+
+  pub fn tracestate(&self) -> Option<&str> {
+    self.tracestate.as_deref()
+  }
+
+  /// `traceparent` and, if any, `tracestate` headers carrying this context
+  pub fn headers(&self) -> Vec<(&'static str, String)> {
+    let mut headers = vec![(TRACEPARENT, self.to_string())];
+    if let Some(tracestate) = &self.tracestate {
+      headers.push((TRACESTATE, tracestate.clone()));
+    }
+    headers
+  }
+}
+
+/// Formats as a version 00 `traceparent` value
+impl Display for TraceContext {
+  fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+    write!(
+      f,
+      "00-{:032x}-{:016x}-{:02x}",
+      self.trace_id, self.parent_id, self.flags
+    )
+  }
+}
+
+fn is_lower_hex(field: &str, length: usize) -> bool {
+  field.len() == length
+    && field
+      .bytes()
+      .all(|byte| matches!(byte, b'0'..=b'9' | b'a'..=b'f'))
+}
+
+/// Randomly keyed SipHash over a counter, random enough for IDs without pulling in an RNG
+pub(crate) fn random_id() -> u64 {
+  static COUNTER: AtomicU64 = AtomicU64::new(0);
+  loop {
+    let id = RandomState::new().hash_one(COUNTER.fetch_add(1, Ordering::Relaxed));
+    if id != 0 {
+      return id;
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use expectest::prelude::*;
+  use rstest::*;
+  use std::collections::HashMap;
+
+  const VALID: &str = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+
+  fn header(entries: &[(&str, &str)]) -> HttpHeader {
+    HttpHeader::new(
+      entries
+        .iter()
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .collect::<HashMap<_, _>>(),
+    )
+  }
+
+  #[rstest]
+  fn test_parse_traceparent() {
+    let context = TraceContext::parse(VALID).unwrap();
+    expect!(context.trace_id()).to(be_equal_to("4bf92f3577b34da6a3ce929d0e0e4736"));
+    expect!(context.parent_id()).to(be_equal_to("00f067aa0ba902b7"));
+    expect!(context.sampled()).to(be_true());
+    expect!(context.to_string()).to(be_equal_to(VALID));
+  }
+
+  #[rstest]
+  #[case::uppercase("00-4BF92F3577B34DA6A3CE929D0E0E4736-00f067aa0ba902b7-01")]
+  #[case::zero_trace_id("00-00000000000000000000000000000000-00f067aa0ba902b7-01")]
+  #[case::zero_parent_id("00-4bf92f3577b34da6a3ce929d0e0e4736-0000000000000000-01")]
+  #[case::short_trace_id("00-4bf92f3577b34da6a3ce929d0e0e47-00f067aa0ba902b7-01")]
+  #[case::forbidden_version("ff-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01")]
+  #[case::trailing_field_in_version_00(
+    "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01-extra"
+  )]
+  #[case::missing_flags("00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7")]
+  fn test_reject_invalid_traceparent(#[case] traceparent: &str) {
+    expect!(TraceContext::parse(traceparent)).to(be_none());
+  }
+
+  #[rstest]
+  fn test_accept_future_version_fields() {
+    let traceparent = "cc-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01-what-the-future";
+    expect!(TraceContext::parse(traceparent)).to(be_some());
+  }
+
+  #[rstest]
+  fn test_child_keeps_trace_and_tracestate() {
+    let context = TraceContext::from_header(&header(&[
+      (TRACEPARENT, VALID),
+      (TRACESTATE, "congo=t61rcWkgMzE"),
+    ]));
+    let child = context.child();
+
+    expect!(child.trace_id()).to(be_equal_to(context.trace_id()));
+    expect!(child.parent_id()).not_to(be_equal_to(context.parent_id()));
+    expect!(child.headers()).to(be_equal_to(vec![
+      (TRACEPARENT, child.to_string()),
+      (TRACESTATE, "congo=t61rcWkgMzE".to_string()),
+    ]));
+  }
+
+  #[rstest]
+  fn test_invalid_traceparent_starts_new_trace() {
+    let context = TraceContext::from_header(&header(&[
+      (TRACEPARENT, "garbage"),
+      (TRACESTATE, "congo=t61"),
+    ]));
+    expect!(context.tracestate()).to(be_none());
+    expect!(context.sampled()).to(be_false());
+    expect!(TraceContext::parse(&context.to_string())).to(be_some().value(context));
+  }
+}
@@ -0,0 +1,398 @@
+//! Typed values of well-known header fields, parsed on demand from an [`HttpHeader`]. Each
+//! accessor returns `Ok(None)` for a field the message doesn't have and an error for one that
+//! is there but malformed, so a handler can tell a missing `Content-Length` from a bad one.
+
+use std::{cmp::Ordering, fmt};
+use thiserror::Error;
+use time::{format_description::well_known::Rfc2822, OffsetDateTime};
+
+use super::{
+  codec,
+  header::{HttpHeader, HttpRequestHeaderKey},
+  ParseError,
+};
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum HeaderValueError {
+  #[error("Invalid Content-Length header {0:?}")]
+  InvalidContentLength(String),
+  #[error("Invalid Host header {0:?}")]
+  InvalidHost(String),
+  #[error("Invalid media range {0:?} in Accept header")]
+  InvalidMediaRange(String),
+  #[error("Invalid {0} date {1:?}")]
+  InvalidDate(String, String),
+}
+
+impl From<HeaderValueError> for ParseError {
+  fn from(error: HeaderValueError) -> Self {
+    Self::InvalidRequest(error.to_string())
+  }
+}
+
+/// The `Host` of a request (RFC 9110, section 7.2), borrowed from its header
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Host<'a> {
+  hostname: &'a str,
+  port: Option<u16>,
+}
+
+impl<'a> Host<'a> {
+  /// Parse `host[:port]`, where `host` is a name, an IPv4 address or an IPv6 address in
+  /// brackets
+  ///
+  /// ```
+  /// use udemy_server::http::typed_header::Host;
+  ///
+  /// let host = Host::parse("[::1]:8080").unwrap();
+  /// assert_eq!((host.hostname(), host.port()), ("::1", Some(8080)));
+  /// ```
+  pub fn parse(value: &'a str) -> Result<Self, HeaderValueError> {
+    let invalid = || HeaderValueError::InvalidHost(value.to_string());
+    let trimmed = value.trim();
+    let (hostname, port) = match trimmed.strip_prefix('[') {
+      Some(bracketed) => {
+        let (address, rest) = bracketed.split_once(']').ok_or_else(invalid)?;
+        if address.is_empty()
+          || !address
+            .bytes()
+            .all(|byte| byte.is_ascii_hexdigit() || b":.".contains(&byte))
+        {
+          return Err(invalid());
+        }
+        match rest {
+          "" => (address, None),
+          _ => (address, Some(rest.strip_prefix(':').ok_or_else(invalid)?)),
+        }
+      }
+      None => match trimmed.split_once(':') {
+        Some((hostname, port)) => (hostname, Some(port)),
+        None => (trimmed, None),
+      },
+    };
+    // reg-name of RFC 3986 without percent-encoding, which no DNS name needs
+    if hostname.is_empty()
+      || !hostname
+        .bytes()
+        .all(|byte| byte.is_ascii_alphanumeric() || b"-._~:".contains(&byte))
+    {
+      return Err(invalid());
+    }
+    let port = match port {
+      Some(port) if !port.is_empty() && port.bytes().all(|byte| byte.is_ascii_digit()) => {
+        Some(port.parse::<u16>().map_err(|_| invalid())?)
+      }
+      Some(_) => return Err(invalid()),
+      None => None,
+    };
+    Ok(Self { hostname, port })
+  }
+
+  /// Without brackets for IPv6 addresses, as sent, so compare it case-insensitively
+  pub fn hostname(&self) -> &'a str {
+    self.hostname
+  }
+
+  pub fn port(&self) -> Option<u16> {
+    self.port
+  }
+}
+
+impl fmt::Display for Host<'_> {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self.hostname.contains(':') {
+      true => write!(f, "[{}]", self.hostname)?,
+      false => write!(f, "{}", self.hostname)?,
+    }
+    match self.port {
+      Some(port) => write!(f, ":{}", port),
+      None => Ok(()),
+    }
+  }
+}
+
+/// A media range of an `Accept` header (RFC 9110, section 12.5.1), e.g. `text/*;q=0.8`,
+/// lowercased
+#[derive(Debug, Clone, PartialEq)]
+pub struct MediaType {
+  type_: String,
+  subtype: String,
+  quality: f32,
+  parameters: Vec<(String, String)>,
+}
+
+impl MediaType {
+  /// `*` in `*/*`
+  pub fn type_(&self) -> &str {
+    &self.type_
+  }
+
+  pub fn subtype(&self) -> &str {
+    &self.subtype
+  }
+
+  /// The `q` parameter, 1 if not given; 0 means not acceptable
+  pub fn quality(&self) -> f32 {
+    self.quality
+  }
+
+  /// The parameters other than `q`, e.g. `charset`, with values unquoted
+  pub fn parameters(&self) -> &[(String, String)] {
+    &self.parameters
+  }
+
+  /// Whether `media_type`, e.g. `text/html`, falls within this range
+  pub fn matches(&self, media_type: &str) -> bool {
+    let (type_, subtype) = media_type.split_once('/').unwrap_or((media_type, ""));
+    let matches =
+      |range: &str, value: &str| range == "*" || range.eq_ignore_ascii_case(value.trim());
+    matches(&self.type_, type_) && matches(&self.subtype, subtype)
+  }
+
+  /// How specific the range is: `*/*` is less so than `text/*`, which is less so than
+  /// `text/html`, which is less so than `text/html;level=1`
+  fn precedence(&self) -> usize {
+    match (self.type_.as_str(), self.subtype.as_str()) {
+      ("*", _) => 0,
+      (_, "*") => 1,
+      _ => 2 + self.parameters.len(),
+    }
+  }
+
+  fn parse(range: &str) -> Result<Self, HeaderValueError> {
+    let invalid = || HeaderValueError::InvalidMediaRange(range.trim().to_string());
+    let mut parts = range.split(';');
+    let (type_, subtype) = parts
+      .next()
+      .unwrap_or_default()
+      .trim()
+      .split_once('/')
+      .ok_or_else(invalid)?;
+    if !is_token(type_) || !is_token(subtype) || (type_ == "*" && subtype != "*") {
+      return Err(invalid());
+    }
+    let mut quality = 1.0;
+    let mut parameters = Vec::new();
+    for parameter in parts {
+      let (name, value) = parameter.split_once('=').ok_or_else(invalid)?;
+      let (name, value) = (name.trim().to_ascii_lowercase(), value.trim());
+      if !is_token(&name) {
+        return Err(invalid());
+      }
+      match name.as_str() {
+        "q" => {
+          quality = value
+            .parse::<f32>()
+            .ok()
+            .filter(|quality| (0.0..=1.0).contains(quality))
+            .ok_or_else(invalid)?
+        }
+        _ => {
+          let value = value
+            .strip_prefix('"')
+            .and_then(|value| value.strip_suffix('"'))
+            .unwrap_or(value);
+          parameters.push((name, value.to_string()))
+        }
+      }
+    }
+    Ok(Self {
+      type_: type_.to_ascii_lowercase(),
+      subtype: subtype.to_ascii_lowercase(),
+      quality,
+      parameters,
+    })
+  }
+}
+
+impl fmt::Display for MediaType {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{}/{}", self.type_, self.subtype)?;
+    for (name, value) in &self.parameters {
+      write!(f, ";{}={}", name, value)?;
+    }
+    Ok(())
+  }
+}
+
+impl HttpHeader {
+  /// `Content-Length` as a number of bytes, read by [`codec::parse_content_length`] like the
+  /// server reads the body, so both agree on where it ends
+  pub fn content_length(&self) -> Result<Option<u64>, HeaderValueError> {
+    self
+      .get(HttpRequestHeaderKey::ContentLength)
+      .map(|value| {
+        codec::parse_content_length(value)
+          .ok_or_else(|| HeaderValueError::InvalidContentLength(value.clone()))
+      })
+      .transpose()
+  }
+
+  pub fn host(&self) -> Result<Option<Host<'_>>, HeaderValueError> {
+    self
+      .get(HttpRequestHeaderKey::Host)
+      .map(|host| Host::parse(host))
+      .transpose()
+  }
+
+  /// The media ranges of `Accept`, most preferred first: by quality, then the more specific
+  /// of equals, then in the order sent. Empty without an `Accept` header, which means any
+  /// media type is acceptable.
+  ///
+  /// ```
+  /// use std::str::FromStr;
+  /// use udemy_server::http::header::HttpHeader;
+  ///
+  /// let header = HttpHeader::from_str("Accept: text/*;q=0.5, application/json\r\n\r\n").unwrap();
+  /// let accepted = header.accept().unwrap();
+  /// assert_eq!(accepted[0].to_string(), "application/json");
+  /// assert_eq!(accepted[1].quality(), 0.5);
+  /// ```
+  pub fn accept(&self) -> Result<Vec<MediaType>, HeaderValueError> {
+    let mut accepted = self
+      .get_all(HttpRequestHeaderKey::Accept.as_ref())
+      .flat_map(|value| value.split(','))
+      .filter(|range| !range.trim().is_empty())
+      .map(MediaType::parse)
+      .collect::<Result<Vec<_>, _>>()?;
+    accepted.sort_by(|a, b| {
+      b.quality
+        .partial_cmp(&a.quality)
+        .unwrap_or(Ordering::Equal)
+        .then_with(|| b.precedence().cmp(&a.precedence()))
+    });
+    Ok(accepted)
+  }
+
+  pub fn if_modified_since(&self) -> Result<Option<OffsetDateTime>, HeaderValueError> {
+    self.date(HttpRequestHeaderKey::IfModifiedSince)
+  }
+
+  /// An HTTP-date field like `Last-Modified` or `Date`, in the IMF-fixdate form of RFC 9110,
+  /// section 5.6.7
+  pub fn date<K: AsRef<str>>(&self, name: K) -> Result<Option<OffsetDateTime>, HeaderValueError> {
+    let name = name.as_ref();
+    self
+      .get(name)
+      .map(|date| {
+        OffsetDateTime::parse(date.trim(), &Rfc2822)
+          .map_err(|_| HeaderValueError::InvalidDate(name.to_string(), date.clone()))
+      })
+      .transpose()
+  }
+}
+
+/// `token` of RFC 9110, section 5.6.2
+pub(crate) fn is_token(value: &str) -> bool {
+  !value.is_empty()
+    && value
+      .bytes()
+      .all(|byte| byte.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&byte))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use expectest::prelude::*;
+  use rstest::*;
+  use std::str::FromStr;
+
+  fn header(fields: &str) -> HttpHeader {
+    HttpHeader::from_str(&format!("{}\r\n\r\n", fields)).unwrap()
+  }
+
+  #[rstest]
+  #[case::absent("Host: localhost", Ok(None))]
+  #[case::zero("Content-Length: 0", Ok(Some(0)))]
+  #[case::padded("Content-Length:  42 ", Ok(Some(42)))]
+  #[case::signed("Content-Length: +42", Err(HeaderValueError::InvalidContentLength("+42".to_string())))]
+  #[case::list("Content-Length: 42, 42", Err(HeaderValueError::InvalidContentLength("42, 42".to_string())))]
+  #[case::overflow(
+    "Content-Length: 99999999999999999999",
+    Err(HeaderValueError::InvalidContentLength("99999999999999999999".to_string()))
+  )]
+  fn test_content_length(
+    #[case] fields: &str,
+    #[case] expected: Result<Option<u64>, HeaderValueError>,
+  ) {
+    expect!(header(fields).content_length()).to(be_equal_to(expected));
+  }
+
+  #[rstest]
+  #[case::name("example.com", "example.com", None)]
+  #[case::name_and_port("example.com:8080", "example.com", Some(8080))]
+  #[case::ipv4("127.0.0.1:80", "127.0.0.1", Some(80))]
+  #[case::ipv6("[::1]", "::1", None)]
+  #[case::ipv6_and_port("[2001:db8::1]:443", "2001:db8::1", Some(443))]
+  fn test_host(#[case] value: &str, #[case] hostname: &str, #[case] port: Option<u16>) {
+    let header = header(&format!("Host: {}", value));
+    let host = header.host().unwrap().unwrap();
+    expect!(host.hostname()).to(be_equal_to(hostname));
+    expect!(host.port()).to(be_equal_to(port));
+    expect!(host.to_string()).to(be_equal_to(value));
+  }
+
+  #[rstest]
+  #[case::empty_port("example.com:")]
+  #[case::port_out_of_range("example.com:65536")]
+  #[case::path("example.com/admin")]
+  #[case::unclosed_bracket("[::1")]
+  #[case::after_bracket("[::1]x")]
+  #[case::userinfo("user@example.com")]
+  fn test_host_invalid(#[case] value: &str) {
+    let header = header(&format!("Host: {}", value));
+    expect!(header.host()).to(be_err().value(HeaderValueError::InvalidHost(value.to_string())));
+  }
+
+  #[rstest]
+  #[case::absent("Host: localhost", vec![])]
+  #[case::by_quality("Accept: text/plain;q=0.5, text/html", vec!["text/html", "text/plain"])]
+  #[case::by_specificity(
+    "Accept: */*, text/*, text/html;level=1, text/html",
+    vec!["text/html;level=1", "text/html", "text/*", "*/*"]
+  )]
+  #[case::in_order_sent("Accept: image/png, image/webp", vec!["image/png", "image/webp"])]
+  #[case::lowercased("Accept: Text/HTML; Charset=\"UTF-8\"", vec!["text/html;charset=UTF-8"])]
+  fn test_accept(#[case] fields: &str, #[case] expected: Vec<&str>) {
+    let accepted = header(fields)
+      .accept()
+      .unwrap()
+      .iter()
+      .map(MediaType::to_string)
+      .collect::<Vec<_>>();
+    expect!(accepted).to(be_equal_to(expected));
+  }
+
+  #[rstest]
+  fn test_accept_quality_and_matches() {
+    let accepted = header("Accept: application/json, text/*;q=0.2")
+      .accept()
+      .unwrap();
+    expect!(accepted[1].quality()).to(be_equal_to(0.2));
+    expect!(accepted[1].matches("text/css")).to(be_true());
+    expect!(accepted[1].matches("image/png")).to(be_false());
+    expect!(accepted[0].matches("Application/JSON")).to(be_true());
+  }
+
+  #[rstest]
+  #[case::no_subtype("text")]
+  #[case::wildcard_type("*/html")]
+  #[case::quality_above_one("text/html;q=2")]
+  #[case::parameter_without_value("text/html;level")]
+  fn test_accept_invalid(#[case] range: &str) {
+    let header = header(&format!("Accept: text/plain, {}", range));
+    expect!(header.accept())
+      .to(be_err().value(HeaderValueError::InvalidMediaRange(range.to_string())));
+  }
+
+  #[rstest]
+  fn test_if_modified_since() {
+    let date = header("If-Modified-Since: Tue, 14 Nov 2023 22:13:20 GMT").if_modified_since();
+    expect!(date.map(|date| date.map(OffsetDateTime::unix_timestamp)))
+      .to(be_ok().value(Some(1_700_000_000)));
+    expect!(header("If-Modified-Since: yesterday").if_modified_since()).to(be_err().value(
+      HeaderValueError::InvalidDate("If-Modified-Since".to_string(), "yesterday".to_string()),
+    ));
+    expect!(header("Host: localhost").if_modified_since()).to(be_ok().value(None));
+  }
+}
@@ -0,0 +1,90 @@
+//! Connections handed over once a `101 Switching Protocols` response has been sent, see
+//! [`super::HttpResponse::on_upgrade`]
+
+use std::{
+  fmt::{Debug, Formatter, Result as FmtResult},
+  future::Future,
+  io,
+  pin::Pin,
+  task::{Context, Poll},
+};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+/// A connection as upgrades take it over, whatever transport (TCP, TLS, ...) it runs on
+pub trait Connection: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Connection for T {}
+
+/// The connection of an upgraded request. Reading starts with whatever the client sent right
+/// after the request, which the server may have read along with it.
+pub struct Upgraded {
+  connection: Box<dyn Connection>,
+  buffered: Vec<u8>,
+  position: usize,
+}
+
+impl Upgraded {
+  pub fn new(connection: impl Connection + 'static, buffered: Vec<u8>) -> Self {
+    Self { connection: Box::new(connection), buffered, position: 0 }
+  }
+}
+
+impl AsyncRead for Upgraded {
+  fn poll_read(
+    mut self: Pin<&mut Self>,
+    cx: &mut Context<'_>,
+    buf: &mut ReadBuf<'_>,
+  ) -> Poll<io::Result<()>> {
+    let buffered = &self.buffered[self.position..];
+    if buffered.is_empty() {
+      return Pin::new(&mut self.connection).poll_read(cx, buf);
+    }
+    let copied = buffered.len().min(buf.remaining());
+    buf.put_slice(&buffered[..copied]);
+    self.position += copied;
+    Poll::Ready(Ok(()))
+  }
+}
+
+impl AsyncWrite for Upgraded {
+  fn poll_write(
+    mut self: Pin<&mut Self>,
+    cx: &mut Context<'_>,
+    buf: &[u8],
+  ) -> Poll<io::Result<usize>> {
+    Pin::new(&mut self.connection).poll_write(cx, buf)
+  }
+
+  fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+    Pin::new(&mut self.connection).poll_flush(cx)
+  }
+
+  fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+    Pin::new(&mut self.connection).poll_shutdown(cx)
+  }
+}
+
+type UpgradeFuture = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+/// Takes over a connection, running on it until the connection is done
+pub struct OnUpgrade(Box<dyn FnOnce(Upgraded) -> UpgradeFuture + Send>);
+
+impl OnUpgrade {
+  pub fn new<F, Fut>(on_upgrade: F) -> Self
+  where
+    F: FnOnce(Upgraded) -> Fut + Send + 'static,
+    Fut: Future<Output = ()> + Send + 'static,
+  {
+    Self(Box::new(move |upgraded| Box::pin(on_upgrade(upgraded))))
+  }
+
+  pub async fn run(self, upgraded: Upgraded) {
+    (self.0)(upgraded).await
+  }
+}
+
+impl Debug for OnUpgrade {
+  fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+    write!(f, "OnUpgrade")
+  }
+}
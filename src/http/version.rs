@@ -0,0 +1,67 @@
+use std::fmt::{Display, Formatter, Result as FmtResult};
+use std::str::FromStr;
+use thiserror::Error;
+
+/// The HTTP versions requests are accepted in, and responses answered in
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum Version {
+  /// Closes the connection after every response unless the client asks to keep it alive, and
+  /// knows no chunked encoding
+  Http10,
+  #[default]
+  Http11,
+}
+
+impl Version {
+  /// As it's spelled in request and status lines
+  pub fn as_str(&self) -> &'static str {
+    match self {
+      Self::Http10 => "HTTP/1.0",
+      Self::Http11 => "HTTP/1.1",
+    }
+  }
+}
+
+impl FromStr for Version {
+  type Err = VersionError;
+
+  fn from_str(string: &str) -> Result<Self, Self::Err> {
+    match string {
+      "HTTP/1.0" => Ok(Self::Http10),
+      "HTTP/1.1" => Ok(Self::Http11),
+      _ => Err(VersionError),
+    }
+  }
+}
+
+impl Display for Version {
+  fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+    write!(f, "{}", self.as_str())
+  }
+}
+
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
+#[error("Unsupported HTTP version")]
+pub struct VersionError;
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use expectest::prelude::*;
+  use rstest::*;
+
+  #[rstest]
+  #[case::http_1_0(Version::Http10)]
+  #[case::http_1_1(Version::Http11)]
+  fn test_round_trip(#[case] version: Version) {
+    expect!(version.to_string().parse::<Version>().ok()).to(be_some().value(version));
+  }
+
+  #[rstest]
+  #[case::http_2("HTTP/2.0")]
+  #[case::lowercase("http/1.1")]
+  #[case::other_protocol("RTSP/1.0")]
+  fn test_reject_other_versions(#[case] version: &str) {
+    expect!(version.parse::<Version>().is_err()).to(be_true());
+  }
+}
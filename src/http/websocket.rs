@@ -0,0 +1,413 @@
+//! WebSocket handshakes and frames (RFC 6455). Like [`super::multipart`], this only looks at
+//! bytes; [`crate::websocket::WebSocket`] exchanges messages over an upgraded connection.
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use sha1::{Digest, Sha1};
+use std::{io, sync::Arc};
+use thiserror::Error;
+
+use super::{
+  header::{HttpRequestHeaderKey, HttpResponseHeaderBuilder},
+  HttpRequest, HttpResponse, Method, StatusCode,
+};
+
+/// Appended to the client's key before it's hashed into `Sec-WebSocket-Accept`
+const WEBSOCKET_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+/// The only version of the protocol there is, as `Sec-WebSocket-Version` names it
+pub const WEBSOCKET_VERSION: &str = "13";
+/// Control frames carry at most this many bytes (RFC 6455, section 5.5)
+const MAX_CONTROL_PAYLOAD: usize = 125;
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum WebSocketError {
+  #[error("Not a WebSocket upgrade request")]
+  NotUpgrade,
+  #[error("Unsupported WebSocket version {0:?}")]
+  UnsupportedVersion(Option<String>),
+  #[error("Missing or invalid Sec-WebSocket-Key")]
+  InvalidKey,
+  #[error("Reserved bits set without an extension negotiated")]
+  ReservedBits,
+  #[error("Unknown opcode {0:#x}")]
+  UnknownOpcode(u8),
+  #[error("Frame from the client is not masked")]
+  Unmasked,
+  #[error("Control frame fragmented or longer than 125 bytes")]
+  InvalidControlFrame,
+  #[error("Message larger than {0} bytes")]
+  TooLarge(u64),
+  #[error("Continuation frame out of sequence")]
+  InvalidFragmentation,
+  #[error("Text message is not valid UTF-8")]
+  InvalidUtf8,
+  #[error("Invalid close frame")]
+  InvalidClose,
+}
+
+impl WebSocketError {
+  /// The status a handshake is refused with
+  pub fn status_code(&self) -> StatusCode {
+    match self {
+      Self::NotUpgrade | Self::UnsupportedVersion(_) => StatusCode::UpgradeRequired,
+      _ => StatusCode::BadRequest,
+    }
+  }
+
+  /// The code the connection is closed with when a frame breaks the protocol (RFC 6455,
+  /// section 7.4.1)
+  pub fn close_code(&self) -> u16 {
+    match self {
+      Self::TooLarge(_) => 1009,
+      Self::InvalidUtf8 => 1007,
+      _ => 1002,
+    }
+  }
+}
+
+impl From<WebSocketError> for io::Error {
+  fn from(error: WebSocketError) -> Self {
+    io::Error::new(io::ErrorKind::InvalidData, error)
+  }
+}
+
+/// Whether `request` asks to switch to WebSocket: `Upgrade: websocket` along with an `upgrade`
+/// option in `Connection`
+pub fn is_upgrade(request: &HttpRequest<'_>) -> bool {
+  let header = request.header();
+  let has_token = |key: HttpRequestHeaderKey, token: &str| {
+    header.get(key).is_some_and(|value| {
+      value
+        .split(',')
+        .any(|option| option.trim().eq_ignore_ascii_case(token))
+    })
+  };
+  has_token(HttpRequestHeaderKey::Upgrade, "websocket")
+    && has_token(HttpRequestHeaderKey::Connection, "upgrade")
+}
+
+/// The `Sec-WebSocket-Accept` value proving the server read the client's `Sec-WebSocket-Key`
+pub fn accept_key(key: &str) -> String {
+  STANDARD.encode(Sha1::digest(format!("{}{}", key, WEBSOCKET_GUID)))
+}
+
+/// The `101 Switching Protocols` answer to a WebSocket upgrade request, which still needs
+/// someone to take over the connection, see [`HttpResponse::on_upgrade`]
+pub fn handshake(request: &HttpRequest<'_>) -> Result<HttpResponse, WebSocketError> {
+  if *request.method() != Method::GET || !is_upgrade(request) {
+    return Err(WebSocketError::NotUpgrade);
+  }
+  let version = request.header().get("Sec-WebSocket-Version");
+  if version.map(|version| version.trim()) != Some(WEBSOCKET_VERSION) {
+    return Err(WebSocketError::UnsupportedVersion(version.cloned()));
+  }
+  // a base64-encoded random 16-byte nonce
+  let key = request
+    .header()
+    .get("Sec-WebSocket-Key")
+    .map(|key| key.trim())
+    .filter(|key| STANDARD.decode(key).is_ok_and(|nonce| nonce.len() == 16))
+    .ok_or(WebSocketError::InvalidKey)?;
+
+  let mut builder = HttpResponseHeaderBuilder::new();
+  builder.upgrade("websocket");
+  builder.connection("Upgrade");
+  builder.custom("Sec-WebSocket-Accept".to_string(), &accept_key(key));
+  Ok(HttpResponse::new(
+    StatusCode::SwitchingProtocols,
+    None,
+    Some(Arc::new(builder.build())),
+  ))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Opcode {
+  Continuation = 0x0,
+  Text = 0x1,
+  Binary = 0x2,
+  Close = 0x8,
+  Ping = 0x9,
+  Pong = 0xa,
+}
+
+impl Opcode {
+  fn from_u8(opcode: u8) -> Result<Self, WebSocketError> {
+    match opcode {
+      0x0 => Ok(Self::Continuation),
+      0x1 => Ok(Self::Text),
+      0x2 => Ok(Self::Binary),
+      0x8 => Ok(Self::Close),
+      0x9 => Ok(Self::Ping),
+      0xa => Ok(Self::Pong),
+      _ => Err(WebSocketError::UnknownOpcode(opcode)),
+    }
+  }
+
+  /// Close, ping and pong, which may come between the fragments of a message
+  pub fn is_control(&self) -> bool {
+    (*self as u8) & 0x8 != 0
+  }
+}
+
+/// A single frame, possibly one fragment of a [`Message`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+  /// the last fragment of its message
+  pub fin: bool,
+  pub opcode: Opcode,
+  /// unmasked
+  pub payload: Vec<u8>,
+}
+
+impl Frame {
+  /// A final frame, a message of its own
+  pub fn new(opcode: Opcode, payload: Vec<u8>) -> Self {
+    Self { fin: true, opcode, payload }
+  }
+
+  /// As servers send it, unmasked
+  pub fn encode(&self) -> Vec<u8> {
+    self.encode_with(None)
+  }
+
+  /// As clients send it, masked with `mask`
+  pub fn encode_masked(&self, mask: [u8; 4]) -> Vec<u8> {
+    self.encode_with(Some(mask))
+  }
+
+  fn encode_with(&self, mask: Option<[u8; 4]>) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(self.payload.len() + 14);
+    frame.push(((self.fin as u8) << 7) | self.opcode as u8);
+    let mask_bit = (mask.is_some() as u8) << 7;
+    match self.payload.len() {
+      length @ 0..=125 => frame.push(mask_bit | length as u8),
+      length @ 126..=0xffff => {
+        frame.push(mask_bit | 126);
+        frame.extend_from_slice(&(length as u16).to_be_bytes());
+      }
+      length => {
+        frame.push(mask_bit | 127);
+        frame.extend_from_slice(&(length as u64).to_be_bytes());
+      }
+    }
+    match mask {
+      Some(mask) => {
+        frame.extend_from_slice(&mask);
+        let start = frame.len();
+        frame.extend_from_slice(&self.payload);
+        apply_mask(&mut frame[start..], mask);
+      }
+      None => frame.extend_from_slice(&self.payload),
+    }
+    frame
+  }
+
+  /// The frame a client sent at the start of `buf` along with the bytes it took up, `None`
+  /// until all of it has arrived. Payloads beyond `max_payload_size` are refused from their
+  /// length alone, before they're buffered.
+  pub fn decode(
+    buf: &[u8],
+    max_payload_size: u64,
+  ) -> Result<Option<(Self, usize)>, WebSocketError> {
+    let [first, second, ..] = *buf else {
+      return Ok(None);
+    };
+    if first & 0x70 != 0 {
+      return Err(WebSocketError::ReservedBits);
+    }
+    let fin = first & 0x80 != 0;
+    let opcode = Opcode::from_u8(first & 0x0f)?;
+    if second & 0x80 == 0 {
+      return Err(WebSocketError::Unmasked);
+    }
+    let (length, mut position) = match second & 0x7f {
+      126 => match buf.get(2..4) {
+        Some(length) => (u16::from_be_bytes([length[0], length[1]]) as u64, 4),
+        None => return Ok(None),
+      },
+      127 => match buf.get(2..10).map(|length| length.try_into()) {
+        Some(Ok(length)) => (u64::from_be_bytes(length), 10),
+        _ => return Ok(None),
+      },
+      length => (length as u64, 2),
+    };
+    if opcode.is_control() && (!fin || length > MAX_CONTROL_PAYLOAD as u64) {
+      return Err(WebSocketError::InvalidControlFrame);
+    }
+    if length > max_payload_size {
+      return Err(WebSocketError::TooLarge(max_payload_size));
+    }
+    let Some(mask) = buf.get(position..position + 4) else {
+      return Ok(None);
+    };
+    let mask = [mask[0], mask[1], mask[2], mask[3]];
+    position += 4;
+    // fits, as it's no larger than max_payload_size
+    let end = position + length as usize;
+    let Some(payload) = buf.get(position..end) else {
+      return Ok(None);
+    };
+    let mut payload = payload.to_vec();
+    apply_mask(&mut payload, mask);
+    Ok(Some((Self { fin, opcode, payload }, end)))
+  }
+}
+
+fn apply_mask(payload: &mut [u8], mask: [u8; 4]) {
+  for (index, byte) in payload.iter_mut().enumerate() {
+    *byte ^= mask[index % 4];
+  }
+}
+
+/// Why a connection is closed, as carried by a close frame
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CloseFrame {
+  pub code: u16,
+  pub reason: String,
+}
+
+impl CloseFrame {
+  /// `None` for a close frame without a payload
+  pub fn parse(payload: &[u8]) -> Result<Option<Self>, WebSocketError> {
+    let (code, reason) = match payload {
+      [] => return Ok(None),
+      [first, second, reason @ ..] => (u16::from_be_bytes([*first, *second]), reason),
+      _ => return Err(WebSocketError::InvalidClose),
+    };
+    // codes an endpoint may send, RFC 6455 section 7.4
+    if !matches!(code, 1000..=1003 | 1007..=1011 | 3000..=4999) {
+      return Err(WebSocketError::InvalidClose);
+    }
+    let reason = String::from_utf8(reason.to_vec()).map_err(|_| WebSocketError::InvalidUtf8)?;
+    Ok(Some(Self { code, reason }))
+  }
+
+  fn payload(&self) -> Vec<u8> {
+    let mut payload = self.code.to_be_bytes().to_vec();
+    payload.extend_from_slice(self.reason.as_bytes());
+    payload
+  }
+}
+
+/// What endpoints exchange, each sent as a single frame
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Message {
+  Text(String),
+  Binary(Vec<u8>),
+  Ping(Vec<u8>),
+  Pong(Vec<u8>),
+  Close(Option<CloseFrame>),
+}
+
+impl From<Message> for Frame {
+  fn from(message: Message) -> Self {
+    match message {
+      Message::Text(text) => Frame::new(Opcode::Text, text.into_bytes()),
+      Message::Binary(data) => Frame::new(Opcode::Binary, data),
+      Message::Ping(data) => Frame::new(Opcode::Ping, data),
+      Message::Pong(data) => Frame::new(Opcode::Pong, data),
+      Message::Close(close) => Frame::new(
+        Opcode::Close,
+        close.as_ref().map(CloseFrame::payload).unwrap_or_default(),
+      ),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::http::header::HttpResponseHeaderKey;
+  use expectest::prelude::*;
+  use rstest::*;
+
+  #[rstest]
+  fn test_accept_key() {
+    // the example of RFC 6455, section 1.3
+    expect!(accept_key("dGhlIHNhbXBsZSBub25jZQ==").as_str())
+      .to(be_equal_to("s3pPLMBiTxaQ9kYGzzhZRbK+xOo="));
+  }
+
+  #[rstest]
+  #[case::upgrade("Upgrade: websocket\r\nConnection: keep-alive, Upgrade\r\nSec-WebSocket-Version: 13\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==", Ok(()))]
+  #[case::plain_request("Connection: keep-alive", Err(WebSocketError::NotUpgrade))]
+  #[case::other_protocol("Upgrade: h2c\r\nConnection: Upgrade", Err(WebSocketError::NotUpgrade))]
+  #[case::old_version(
+    "Upgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Version: 8\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==",
+    Err(WebSocketError::UnsupportedVersion(Some("8".to_string())))
+  )]
+  #[case::short_key(
+    "Upgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Version: 13\r\nSec-WebSocket-Key: c2hvcnQ=",
+    Err(WebSocketError::InvalidKey)
+  )]
+  fn test_handshake(
+    #[case] headers: &str,
+    #[case] expected: Result<(), WebSocketError>,
+  ) -> Result<(), crate::http::ParseError> {
+    let raw_request = format!(
+      "GET /chat HTTP/1.1\r\nHost: localhost\r\n{}\r\n\r\n",
+      headers
+    );
+    let request = HttpRequest::try_from(raw_request.as_bytes())?;
+
+    let response = handshake(&request);
+    expect!(response.as_ref().map(|_| ()).map_err(Clone::clone)).to(be_equal_to(expected));
+    if let Ok(response) = response {
+      expect!(*response.status_code()).to(be_equal_to(StatusCode::SwitchingProtocols));
+      let header = response.http_header().as_ref().unwrap();
+      expect!(header
+        .get(HttpResponseHeaderKey::Upgrade)
+        .map(String::as_str))
+      .to(be_some().value("websocket"));
+      expect!(header.get("Sec-WebSocket-Accept").map(String::as_str))
+        .to(be_some().value("s3pPLMBiTxaQ9kYGzzhZRbK+xOo="));
+      expect!(response.head().contains("Content-Length")).to(be_false());
+    }
+    Ok(())
+  }
+
+  #[rstest]
+  #[case::empty(Opcode::Text, 0)]
+  #[case::short(Opcode::Binary, 125)]
+  #[case::medium(Opcode::Binary, 126)]
+  #[case::long(Opcode::Binary, 70_000)]
+  #[case::ping(Opcode::Ping, 4)]
+  fn test_frame_round_trip(#[case] opcode: Opcode, #[case] length: usize) {
+    let frame = Frame::new(opcode, (0..length).map(|index| index as u8).collect());
+    let encoded = frame.encode_masked([1, 2, 3, 4]);
+
+    expect!(Frame::decode(&encoded, 1 << 20))
+      .to(be_ok().value(Some((frame.clone(), encoded.len()))));
+    // nothing until the last byte arrived
+    expect!(Frame::decode(&encoded[..encoded.len() - 1], 1 << 20)).to(be_ok().value(None));
+    // servers' frames aren't masked, which clients may not do
+    let unmasked = frame.encode();
+    expect!(unmasked.len() + 4).to(be_equal_to(encoded.len()));
+    expect!(Frame::decode(&unmasked, 1 << 20)).to(be_err().value(WebSocketError::Unmasked));
+  }
+
+  #[rstest]
+  #[case::reserved_bits(&[0xc1, 0x80], WebSocketError::ReservedBits)]
+  #[case::unknown_opcode(&[0x83, 0x80], WebSocketError::UnknownOpcode(3))]
+  #[case::fragmented_ping(&[0x09, 0x80], WebSocketError::InvalidControlFrame)]
+  #[case::long_ping(&[0x89, 0xfe, 0x00, 0x7e], WebSocketError::InvalidControlFrame)]
+  #[case::too_large(&[0x82, 0xff, 0, 0, 0, 1, 0, 0, 0, 0], WebSocketError::TooLarge(1024))]
+  fn test_invalid_frames(#[case] frame: &[u8], #[case] expected: WebSocketError) {
+    expect!(Frame::decode(frame, 1024)).to(be_err().value(expected));
+  }
+
+  #[rstest]
+  #[case::empty(&[], Ok(None))]
+  #[case::normal(
+    &[0x03, 0xe8, b'b', b'y', b'e'],
+    Ok(Some(CloseFrame { code: 1000, reason: "bye".to_string() }))
+  )]
+  #[case::truncated_code(&[0x03], Err(WebSocketError::InvalidClose))]
+  #[case::reserved_code(&[0x03, 0xed], Err(WebSocketError::InvalidClose))]
+  #[case::invalid_reason(&[0x03, 0xe8, 0xff], Err(WebSocketError::InvalidUtf8))]
+  fn test_parse_close_frame(
+    #[case] payload: &[u8],
+    #[case] expected: Result<Option<CloseFrame>, WebSocketError>,
+  ) {
+    expect!(CloseFrame::parse(payload)).to(be_equal_to(expected));
+  }
+}
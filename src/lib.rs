@@ -1,19 +1,245 @@
-use filesystem::LocalFileSystem;
-use server::Server;
-use std::{env, sync::Arc};
-use website_handler::WebsiteHandler;
+pub mod access_log;
+#[cfg(feature = "acme")]
+pub mod acme;
+#[cfg(feature = "server")]
+pub mod admin_handler;
+#[cfg(feature = "server")]
+pub mod basic_auth;
+#[cfg(feature = "server")]
+pub mod cgi_handler;
+#[cfg(feature = "compression")]
+pub mod compression;
+#[cfg(feature = "server")]
+pub mod config;
+#[cfg(feature = "server")]
+pub mod cors;
+#[cfg(feature = "server")]
+pub mod deadline;
+#[cfg(feature = "server")]
+pub mod echo_handler;
+pub mod embedded_assets;
+pub mod error;
+pub mod error_log;
+pub mod file_cache;
+pub mod filesystem;
+pub mod htpasswd;
+pub mod http;
+#[cfg(feature = "jwt")]
+pub mod jwt;
+pub mod limits;
+pub mod metrics;
+#[cfg(feature = "server")]
+pub mod middleware;
+#[cfg(feature = "server")]
+pub mod proxy_protocol;
+#[cfg(feature = "server")]
+pub mod router;
+#[cfg(feature = "server")]
+pub mod server;
+#[cfg(feature = "server")]
+pub mod session;
+#[cfg(feature = "server")]
+pub mod tenant_handler;
+#[cfg(feature = "server")]
+pub mod throttle;
+#[cfg(feature = "tls")]
+pub mod tls;
+#[cfg(feature = "tower")]
+pub mod tower_handler;
+#[cfg(feature = "server")]
+pub mod transport;
+#[cfg(feature = "server")]
+pub mod upload_handler;
+#[cfg(feature = "server")]
+pub mod virtual_host_handler;
+#[cfg(feature = "server")]
+pub mod website_handler;
+#[cfg(feature = "server")]
+pub mod websocket;
 
-mod filesystem;
-mod http;
-mod server;
-mod website_handler;
+/// Serve with the [`config::Config`] loaded from `CONFIG_PATH` and the environment, logging
+/// to stderr at the levels `RUST_LOG` sets, `info` by default
+#[cfg(feature = "server")]
+pub async fn start() -> Result<(), error::ServerError> {
+  use tracing_subscriber::EnvFilter;
 
-pub async fn start() -> Result<(), Box<dyn std::error::Error>> {
-  // default_path works only for cargo commands (test, run, etc.)
-  let default_path = format!("{}/public", env!("CARGO_MANIFEST_DIR"));
-  let public_path = env::var("PUBLIC_PATH").unwrap_or(default_path);
-  let server = Server::new("127.0.0.1:8080".to_string());
-  let file_system = Arc::new(LocalFileSystem::new(public_path));
-  let website_handler = Arc::new(WebsiteHandler::new(file_system));
-  server.run(website_handler).await
+  // a subscriber the embedding application installed first stays in charge
+  let _ = tracing_subscriber::fmt()
+    .with_env_filter(EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info")))
+    .with_writer(std::io::stderr)
+    .try_init();
+  start_with_config(config::Config::load()?).await
+}
+
+#[cfg(feature = "server")]
+pub async fn start_with_config(config: config::Config) -> Result<(), error::ServerError> {
+  use cgi_handler::CgiHandler;
+  use echo_handler::EchoHandler;
+  use file_cache::FileCache;
+  use filesystem::{FileSystem, LocalFileSystem};
+  use server::{Handler, Server};
+  use std::sync::Arc;
+  use tenant_handler::TenantHandler;
+  use upload_handler::UploadHandler;
+  use virtual_host_handler::VirtualHostHandler;
+  use website_handler::WebsiteHandler;
+
+  let public_path = config.public_path.clone();
+  let mut server = Server::new(config.address())
+    .parser_limits(config.parser_limits)
+    .query_limits(config.query_limits)
+    .limits(config.limits)
+    .access_log(config.access_log)
+    .access_log_sample_rate(config.access_log_sample_rate);
+  if let Some(max_head_size) = config.max_head_size {
+    server = server.max_head_size(max_head_size);
+  }
+  if let Some(max_buffered_body_size) = config.max_buffered_body_size {
+    server = server.max_buffered_body_size(max_buffered_body_size);
+  }
+  if let Some(timeout) = config.keep_alive_timeout_secs {
+    server = server.keep_alive_timeout(std::time::Duration::from_secs(timeout));
+  }
+  if let Some(timeout) = config.header_read_timeout_secs {
+    server = server.header_read_timeout(std::time::Duration::from_secs(timeout));
+  }
+  if let Some(timeout) = config.body_read_timeout_secs {
+    server = server.body_read_timeout(std::time::Duration::from_secs(timeout));
+  }
+  if let Some(timeout) = config.request_timeout_secs {
+    server = server.request_timeout(std::time::Duration::from_secs(timeout));
+  }
+  if let Some(chunk_size) = config.response_chunk_size {
+    server = server.response_chunk_size(chunk_size);
+  }
+  if let Some(bandwidth_limit) = config.bandwidth_limit {
+    server = server.bandwidth_limit(bandwidth_limit);
+  }
+  if let Some(max_connections) = config.max_connections {
+    server = server.max_connections(max_connections, config.connection_overflow);
+  }
+  if let Some(rate_limit) = config.error_log_rate_limit {
+    server = server.error_log_rate_limit(rate_limit);
+  }
+  for route in &config.route_limits {
+    server = server.route_limits(&route.prefix, route.limits);
+  }
+  if let Some(admin_address) = &config.admin_address {
+    server = server.admin_address(admin_address.clone());
+  }
+  if let Some(hsts) = &config.hsts {
+    server = server.hsts(hsts.clone());
+  }
+  if config.proxy_protocol {
+    server = server.proxy_protocol();
+  }
+  for listener in &config.listeners {
+    server = server.listen(listener.clone());
+  }
+  if config.acceptors > 1 {
+    server = server.reuse_port(config.acceptors);
+  }
+  if !config.trusted_proxies.is_empty() {
+    server = server.trusted_proxies(config.trusted_proxies.clone());
+  }
+  if let Some(cors) = &config.cors {
+    server = server.layer(Arc::new(cors.clone()));
+  }
+  if let Some(basic_auth) = &config.basic_auth {
+    server = server.layer(Arc::new(basic_auth::BasicAuth::with_settings(basic_auth)?));
+  }
+  #[cfg(feature = "jwt")]
+  if let Some(jwt) = &config.jwt {
+    server = server.layer(Arc::new(jwt::JwtAuth::with_settings(jwt)?));
+  }
+  if let Some(sessions) = &config.sessions {
+    server = server.layer(Arc::new(session::Sessions::with_settings(sessions)?));
+  }
+  #[cfg(feature = "tls")]
+  if let Some(tls) = &config.tls {
+    server = server.tls(tls.clone())?;
+  }
+  #[cfg(feature = "acme")]
+  if let Some(acme) = &config.acme {
+    server = server.acme(acme.clone())?;
+  }
+  #[cfg(feature = "embed")]
+  let embedded = config.embedded_assets;
+  #[cfg(not(feature = "embed"))]
+  let embedded = false;
+  let mut handler: Arc<dyn Handler> = match embedded {
+    // CGI scripts can't run from memory
+    #[cfg(feature = "embed")]
+    true => Arc::new(WebsiteHandler::with_settings(
+      Arc::new(embedded_assets::EmbeddedFileSystem::new(
+        embedded_assets::PUBLIC,
+      )),
+      config.mime_types(),
+      config.website.clone(),
+    )),
+    _ => {
+      let mut file_system = LocalFileSystem::new(public_path.clone());
+      if let Some(file_cache) = config.file_cache {
+        file_system = file_system.with_cache(FileCache::new(file_cache));
+      }
+      let file_system = Arc::new(file_system);
+      // a wrong PUBLIC_PATH would otherwise answer every request with 404 Not Found
+      file_system.check_ready()?;
+      let readiness_file_system = Arc::clone(&file_system);
+      server = server.readiness_check(move || {
+        readiness_file_system
+          .check_ready()
+          .map_err(|e| e.to_string())
+      });
+      let cgi_handler = Arc::new(CgiHandler::new(public_path));
+      let website_handler = config.cgi_extensions.iter().fold(
+        WebsiteHandler::with_settings(file_system, config.mime_types(), config.website.clone()),
+        |website_handler, extension| {
+          website_handler.register_extension(extension, cgi_handler.clone())
+        },
+      );
+      Arc::new(website_handler)
+    }
+  };
+  if !config.virtual_hosts.is_empty() {
+    // like tenants, virtual hosts get static files only
+    let mut virtual_hosts = VirtualHostHandler::new(handler);
+    for virtual_host in &config.virtual_hosts {
+      let file_system = Arc::new(LocalFileSystem::new(virtual_host.public_path.clone()));
+      file_system.check_ready()?;
+      let website_handler: Arc<dyn Handler> = Arc::new(WebsiteHandler::with_settings(
+        file_system,
+        config.mime_types(),
+        config.website.clone(),
+      ));
+      virtual_hosts = virtual_host
+        .hosts
+        .iter()
+        .fold(virtual_hosts, |virtual_hosts, host| {
+          virtual_hosts.host_pattern(host.clone(), Arc::clone(&website_handler))
+        });
+    }
+    handler = Arc::new(virtual_hosts);
+  }
+  if let Some(tenants) = &config.tenants {
+    // tenants get static files only, their CGI scripts would run with the server's privileges
+    handler = Arc::new(TenantHandler::new(tenants, config.mime_types(), handler));
+  }
+  if let Some(uploads) = &config.uploads {
+    // created up front, so the directory resolves to the same canonical path for every upload
+    std::fs::create_dir_all(&uploads.directory)?;
+    let file_system = LocalFileSystem::new(uploads.directory.to_string_lossy().to_string());
+    handler = Arc::new(UploadHandler::new(uploads, Arc::new(file_system), handler));
+  }
+  if config.debug_echo {
+    handler = Arc::new(EchoHandler::new(handler));
+  }
+  #[cfg(feature = "compression")]
+  if let Some(compression) = &config.compression {
+    handler = Arc::new(compression::CompressionHandler::new(
+      compression.clone(),
+      handler,
+    ));
+  }
+  server.run(handler).await
 }
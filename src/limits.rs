@@ -0,0 +1,318 @@
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::{
+  collections::HashMap,
+  net::IpAddr,
+  sync::Mutex,
+  time::{Duration, Instant},
+};
+use thiserror::Error;
+
+/// Buckets tracked before idle, fully refilled ones are dropped
+const MAX_TRACKED_CLIENTS: usize = 10_000;
+
+/// Per-request limits, set server-wide and overridden below path prefixes, e.g.
+///
+/// ```toml
+/// [limits]
+/// handler_timeout_ms = 5000
+/// max_body_size = 65536
+/// rate_limit = { requests = 100, per_secs = 60 }
+///
+/// [[route_limits]]
+/// prefix = "/upload"
+/// limits = { max_body_size = 104857600, handler_timeout_ms = 60000 }
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Limits {
+  /// answered with `503 Service Unavailable` once exceeded
+  #[serde(rename = "handler_timeout_ms")]
+  #[serde(deserialize_with = "deserialize_millis", serialize_with = "serialize_millis")]
+  pub handler_timeout: Option<Duration>,
+  /// checked against `Content-Length` before the body is read and against chunked bodies as
+  /// they're decoded, answered with `413 Payload Too Large`
+  pub max_body_size: Option<u64>,
+  /// per client address, answered with `429 Too Many Requests`
+  pub rate_limit: Option<RateLimit>,
+}
+
+fn deserialize_millis<'de, D: Deserializer<'de>>(
+  deserializer: D,
+) -> Result<Option<Duration>, D::Error> {
+  Ok(Option::<u64>::deserialize(deserializer)?.map(Duration::from_millis))
+}
+
+fn serialize_millis<S: Serializer>(
+  duration: &Option<Duration>,
+  serializer: S,
+) -> Result<S::Ok, S::Error> {
+  duration
+    .map(|duration| duration.as_millis())
+    .serialize(serializer)
+}
+
+impl Limits {
+  /// `self` with every limit `overrides` sets replaced
+  fn merge(self, overrides: &Limits) -> Self {
+    Self {
+      handler_timeout: overrides.handler_timeout.or(self.handler_timeout),
+      max_body_size: overrides.max_body_size.or(self.max_body_size),
+      rate_limit: overrides.rate_limit.or(self.rate_limit),
+    }
+  }
+}
+
+/// A token bucket: bursts of up to `requests`, refilled evenly over `per_secs`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(try_from = "RateLimitSettings")]
+pub struct RateLimit {
+  requests: u32,
+  per_secs: u64,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RateLimitSettings {
+  requests: u32,
+  per_secs: u64,
+}
+
+#[derive(Error, Debug, PartialEq)]
+pub enum RateLimitError {
+  #[error("rate_limit requests must be at least 1")]
+  NoRequests,
+  #[error("rate_limit per_secs must be at least 1")]
+  NoPeriod,
+}
+
+impl RateLimit {
+  pub fn new(requests: u32, per_secs: u64) -> Result<Self, RateLimitError> {
+    match (requests, per_secs) {
+      (0, _) => Err(RateLimitError::NoRequests),
+      (_, 0) => Err(RateLimitError::NoPeriod),
+      _ => Ok(Self { requests, per_secs }),
+    }
+  }
+
+  pub fn requests(&self) -> u32 {
+    self.requests
+  }
+
+  pub fn per_secs(&self) -> u64 {
+    self.per_secs
+  }
+
+  fn tokens_per_sec(&self) -> f64 {
+    f64::from(self.requests) / self.per_secs as f64
+  }
+}
+
+impl TryFrom<RateLimitSettings> for RateLimit {
+  type Error = RateLimitError;
+
+  fn try_from(settings: RateLimitSettings) -> Result<Self, Self::Error> {
+    Self::new(settings.requests, settings.per_secs)
+  }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct RouteLimits {
+  /// matches the path itself and everything below it, `/upload` covers `/upload/a` but not
+  /// `/uploads`
+  pub prefix: String,
+  pub limits: Limits,
+}
+
+impl RouteLimits {
+  fn matches(&self, path: &str) -> bool {
+    match path.strip_prefix(self.prefix.as_str()) {
+      Some(rest) => rest.is_empty() || rest.starts_with('/') || self.prefix.ends_with('/'),
+      None => false,
+    }
+  }
+}
+
+/// The limits of one request, along with the prefix whose rate limit applies
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ResolvedLimits<'a> {
+  pub limits: Limits,
+  /// requests below different prefixes draw from separate buckets
+  pub rate_limit_scope: &'a str,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct LimitsTable {
+  global: Limits,
+  routes: Vec<RouteLimits>,
+}
+
+impl LimitsTable {
+  pub fn new(global: Limits, routes: Vec<RouteLimits>) -> Self {
+    routes
+      .into_iter()
+      .fold(Self { global, routes: Vec::new() }, |mut table, route| {
+        table.add_route(&route.prefix, route.limits);
+        table
+      })
+  }
+
+  pub fn set_global(&mut self, global: Limits) {
+    self.global = global;
+  }
+
+  /// Override `limits` for `prefix`, replacing earlier overrides of the same prefix
+  pub fn add_route(&mut self, prefix: &str, limits: Limits) {
+    self.routes.retain(|route| route.prefix != prefix);
+    // resolved from the least to the most specific prefix, so nested ones win
+    let position = self
+      .routes
+      .partition_point(|route| route.prefix.len() <= prefix.len());
+    self
+      .routes
+      .insert(position, RouteLimits { prefix: prefix.to_string(), limits });
+  }
+
+  pub fn resolve(&self, path: &str) -> ResolvedLimits<'_> {
+    self.routes.iter().filter(|route| route.matches(path)).fold(
+      ResolvedLimits { limits: self.global, rate_limit_scope: "" },
+      |resolved, route| ResolvedLimits {
+        limits: resolved.limits.merge(&route.limits),
+        rate_limit_scope: match route.limits.rate_limit {
+          Some(_) => route.prefix.as_str(),
+          None => resolved.rate_limit_scope,
+        },
+      },
+    )
+  }
+}
+
+struct Bucket {
+  tokens: f64,
+  updated_at: Instant,
+}
+
+/// Token buckets per rate limit scope and client address
+#[derive(Default)]
+pub struct RateLimiter {
+  buckets: Mutex<HashMap<(String, IpAddr), Bucket>>,
+}
+
+impl RateLimiter {
+  /// Take a token for `client`, or tell how long until one is available
+  pub fn check(&self, scope: &str, client: IpAddr, limit: &RateLimit) -> Result<(), Duration> {
+    self.check_at(scope, client, limit, Instant::now())
+  }
+
+  fn check_at(
+    &self,
+    scope: &str,
+    client: IpAddr,
+    limit: &RateLimit,
+    now: Instant,
+  ) -> Result<(), Duration> {
+    let capacity = f64::from(limit.requests);
+    let mut buckets = self.buckets.lock().unwrap_or_else(|e| e.into_inner());
+    if buckets.len() >= MAX_TRACKED_CLIENTS {
+      buckets.retain(|_, bucket| {
+        bucket.tokens + now.duration_since(bucket.updated_at).as_secs_f64() * limit.tokens_per_sec()
+          < capacity
+      });
+    }
+
+    let bucket = buckets
+      .entry((scope.to_string(), client))
+      .or_insert(Bucket { tokens: capacity, updated_at: now });
+    let refilled = now.duration_since(bucket.updated_at).as_secs_f64() * limit.tokens_per_sec();
+    bucket.tokens = (bucket.tokens + refilled).min(capacity);
+    bucket.updated_at = now;
+
+    if bucket.tokens >= 1.0 {
+      bucket.tokens -= 1.0;
+      Ok(())
+    } else {
+      Err(Duration::from_secs_f64(
+        (1.0 - bucket.tokens) / limit.tokens_per_sec(),
+      ))
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use expectest::prelude::*;
+  use rstest::*;
+  use std::net::Ipv4Addr;
+
+  #[fixture]
+  fn table() -> LimitsTable {
+    let global = Limits {
+      handler_timeout: Some(Duration::from_secs(5)),
+      max_body_size: Some(1024),
+      rate_limit: Some(RateLimit::new(100, 60).unwrap()),
+    };
+    let upload = RouteLimits {
+      prefix: "/upload".to_string(),
+      limits: Limits { max_body_size: Some(1 << 20), ..Limits::default() },
+    };
+    let api = RouteLimits {
+      prefix: "/api/".to_string(),
+      limits: Limits {
+        rate_limit: Some(RateLimit::new(10, 1).unwrap()),
+        ..Limits::default()
+      },
+    };
+    LimitsTable::new(global, vec![upload, api])
+  }
+
+  #[rstest]
+  #[case::global("/index.html", 1024, "")]
+  #[case::route("/upload", 1 << 20, "")]
+  #[case::below_route("/upload/avatar.png", 1 << 20, "")]
+  #[case::sibling_of_route("/uploads", 1024, "")]
+  #[case::rate_limited_route("/api/users", 1024, "/api/")]
+  fn test_resolve_route_limits(
+    table: LimitsTable,
+    #[case] path: &str,
+    #[case] max_body_size: u64,
+    #[case] scope: &str,
+  ) {
+    let resolved = table.resolve(path);
+    expect!(resolved.limits.max_body_size).to(be_some().value(max_body_size));
+    expect!(resolved.limits.handler_timeout).to(be_some().value(Duration::from_secs(5)));
+    expect!(resolved.rate_limit_scope).to(be_equal_to(scope));
+  }
+
+  #[rstest]
+  fn test_parse_limits() {
+    let route: RouteLimits = toml::from_str(
+      r#"
+      prefix = "/upload"
+      limits = { handler_timeout_ms = 1500, rate_limit = { requests = 5, per_secs = 10 } }
+      "#,
+    )
+    .unwrap();
+    expect!(route.limits.handler_timeout).to(be_some().value(Duration::from_millis(1500)));
+    expect!(route.limits.rate_limit).to(be_some().value(RateLimit::new(5, 10).unwrap()));
+
+    expect!(toml::from_str::<Limits>("rate_limit = { requests = 0, per_secs = 10 }").is_err())
+      .to(be_true());
+  }
+
+  #[rstest]
+  fn test_rate_limiter_refills_over_time() {
+    let limiter = RateLimiter::default();
+    let limit = RateLimit::new(2, 10).unwrap();
+    let client = IpAddr::V4(Ipv4Addr::LOCALHOST);
+    let other_client = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1));
+    let start = Instant::now();
+
+    expect!(limiter.check_at("", client, &limit, start)).to(be_ok());
+    expect!(limiter.check_at("", client, &limit, start)).to(be_ok());
+    expect!(limiter.check_at("", client, &limit, start)).to(be_err().value(Duration::from_secs(5)));
+    expect!(limiter.check_at("", other_client, &limit, start)).to(be_ok());
+    expect!(limiter.check_at("/api/", client, &limit, start)).to(be_ok());
+    expect!(limiter.check_at("", client, &limit, start + Duration::from_secs(5))).to(be_ok());
+  }
+}
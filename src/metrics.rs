@@ -0,0 +1,114 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Live gauges and counters of a running [`crate::server::Server`], rendered in the Prometheus
+/// text exposition format by [`crate::admin_handler::AdminHandler`]
+#[derive(Debug, Default)]
+pub struct ServerMetrics {
+  open_connections: AtomicU64,
+  idle_connections: AtomicU64,
+  in_flight_requests: AtomicU64,
+  rejected_connections: AtomicU64,
+}
+
+/// Increments a gauge for as long as it's alive, so early returns and panics can't leak counts
+pub struct GaugeGuard<'a>(&'a AtomicU64);
+
+impl<'a> GaugeGuard<'a> {
+  fn new(gauge: &'a AtomicU64) -> Self {
+    gauge.fetch_add(1, Ordering::Relaxed);
+    Self(gauge)
+  }
+}
+
+impl Drop for GaugeGuard<'_> {
+  fn drop(&mut self) {
+    self.0.fetch_sub(1, Ordering::Relaxed);
+  }
+}
+
+impl ServerMetrics {
+  /// Accepted connections that haven't been closed yet
+  pub fn open_connections(&self) -> u64 {
+    self.open_connections.load(Ordering::Relaxed)
+  }
+
+  /// Keep-alive connections waiting for their next request
+  pub fn idle_connections(&self) -> u64 {
+    self.idle_connections.load(Ordering::Relaxed)
+  }
+
+  /// Handler invocations currently executing
+  pub fn in_flight_requests(&self) -> u64 {
+    self.in_flight_requests.load(Ordering::Relaxed)
+  }
+
+  /// Connections turned away because [`crate::server::Server::max_connections`] were open
+  pub fn rejected_connections(&self) -> u64 {
+    self.rejected_connections.load(Ordering::Relaxed)
+  }
+
+  pub fn count_rejected_connection(&self) {
+    self.rejected_connections.fetch_add(1, Ordering::Relaxed);
+  }
+
+  pub fn track_connection(&self) -> GaugeGuard<'_> {
+    GaugeGuard::new(&self.open_connections)
+  }
+
+  pub fn track_idle_connection(&self) -> GaugeGuard<'_> {
+    GaugeGuard::new(&self.idle_connections)
+  }
+
+  pub fn track_request(&self) -> GaugeGuard<'_> {
+    GaugeGuard::new(&self.in_flight_requests)
+  }
+
+  pub fn render(&self) -> String {
+    [
+      ("open_connections", "Open client connections", "gauge", self.open_connections()),
+      ("idle_connections", "Keep-alive connections waiting for a request", "gauge", self.idle_connections()),
+      ("in_flight_requests", "Requests currently being handled", "gauge", self.in_flight_requests()),
+      ("rejected_connections_total", "Connections refused over the connection limit", "counter", self.rejected_connections()),
+    ]
+    .iter()
+    .map(|(name, help, kind, value)| {
+      format!(
+        "# HELP udemy_server_{name} {help}\n# TYPE udemy_server_{name} {kind}\nudemy_server_{name} {value}\n"
+      )
+    })
+    .collect()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use expectest::prelude::*;
+  use rstest::*;
+
+  #[rstest]
+  fn test_gauge_guards() {
+    let metrics = ServerMetrics::default();
+    {
+      let _connection = metrics.track_connection();
+      let _request = metrics.track_request();
+      expect!(metrics.open_connections()).to(be_equal_to(1));
+      expect!(metrics.in_flight_requests()).to(be_equal_to(1));
+    }
+    expect!(metrics.open_connections()).to(be_equal_to(0));
+    expect!(metrics.in_flight_requests()).to(be_equal_to(0));
+  }
+
+  #[rstest]
+  fn test_render() {
+    let metrics = ServerMetrics::default();
+    let _connection = metrics.track_connection();
+
+    let rendered = metrics.render();
+    expect!(rendered.contains("# TYPE udemy_server_open_connections gauge\n")).to(be_true());
+    expect!(rendered.contains("udemy_server_open_connections 1\n")).to(be_true());
+    expect!(rendered.contains("udemy_server_in_flight_requests 0\n")).to(be_true());
+    expect!(rendered.contains("# TYPE udemy_server_rejected_connections_total counter\n"))
+      .to(be_true());
+  }
+}
@@ -0,0 +1,280 @@
+use serde_json::{json, Value};
+use std::sync::Arc;
+
+use crate::filesystem::normalize;
+use crate::http::{HttpRequest, Method, RequestBody};
+use crate::server::{Handler, HandlerFuture, HandlerResult};
+
+/// Request and response processing shared by handlers, such as logging, authentication or
+/// CORS headers, layered around them with [`Layered`], [`crate::server::Server::layer`] or
+/// [`crate::router::Router::layer`] instead of being built into each one.
+pub trait Middleware: Send + Sync + 'static {
+  /// Respond to `request`, usually by running `next`, the rest of the chain and the handler,
+  /// and adjusting what it returns; answering without running it short-circuits the chain.
+  /// Errors are usually passed on with `?` for the server to answer.
+  fn handle(&self, request: &HttpRequest, next: Next<'_>) -> HandlerResult;
+
+  /// Like [`Self::handle`], for requests the handler answers through [`Handler::handle_async`]:
+  /// awaiting [`Next::run_async`] rather than calling [`Next::run`] keeps the handler's file
+  /// I/O and the like off the server's threads. The default calls `handle`, which runs the
+  /// handler's blocking `handle_request` on the connection's task instead.
+  fn handle_async<'a>(&'a self, request: &'a HttpRequest<'a>, next: Next<'a>) -> HandlerFuture<'a> {
+    Box::pin(async move { self.handle(request, next) })
+  }
+
+  /// What the admin listener's `/config` reports about this middleware
+  fn describe(&self) -> Value {
+    json!({ "middleware": std::any::type_name::<Self>() })
+  }
+}
+
+/// Who sent a request, attached to its [`HttpRequest::extensions`] once authentication
+/// middleware such as [`crate::basic_auth::BasicAuth`] verified their credentials
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RemoteUser(pub String);
+
+/// Whether `path` is one of `prefixes` (without trailing slashes) or below one, once `.` and
+/// `..` segments are applied as files are looked up, so `/public/../admin` is below `/admin`
+pub(crate) fn covered(prefixes: &[String], path: &str) -> bool {
+  // paths climbing above the root aren't served, but who knows what a handler does
+  let Some(normalized) = normalize(path) else {
+    return true;
+  };
+  let normalized = format!("/{}", normalized.to_string_lossy());
+  prefixes
+    .iter()
+    .any(|prefix| match normalized.strip_prefix(prefix.as_str()) {
+      Some(rest) => rest.is_empty() || rest.starts_with('/') || prefix.is_empty(),
+      None => false,
+    })
+}
+
+/// The rest of a middleware chain, ending in the handler
+pub struct Next<'a> {
+  middleware: &'a [Arc<dyn Middleware>],
+  handler: &'a dyn Handler,
+  body: Option<RequestBody>,
+}
+
+impl<'a> Next<'a> {
+  pub(crate) fn new(
+    middleware: &'a [Arc<dyn Middleware>],
+    handler: &'a dyn Handler,
+    body: Option<RequestBody>,
+  ) -> Self {
+    Self { middleware, handler, body }
+  }
+
+  /// Pass `request` on, possibly a modified copy, and return the response
+  pub fn run(self, request: &HttpRequest) -> HandlerResult {
+    match self.middleware.split_first() {
+      Some((middleware, rest)) => middleware.handle(request, Next { middleware: rest, ..self }),
+      None => match self.body {
+        Some(body) => self.handler.handle_with_body(request, body),
+        None => self.handler.handle_request(request),
+      },
+    }
+  }
+
+  /// Like [`Self::run`], awaiting the handler's [`Handler::handle_async`] where it has one for
+  /// `request`. Requests with a body never take this path.
+  pub fn run_async(self, request: &'a HttpRequest<'a>) -> HandlerFuture<'a> {
+    match self.middleware.split_first() {
+      Some((middleware, rest)) => {
+        middleware.handle_async(request, Next { middleware: rest, ..self })
+      }
+      None => Box::pin(async move {
+        match self.handler.handle_async(request) {
+          Some(handled) => handled.await,
+          None => self.handler.handle_request(request),
+        }
+      }),
+    }
+  }
+}
+
+/// A handler behind middleware, which runs in the order it was added: the first added sees the
+/// request first and the response last.
+///
+/// Requests the handler answers through [`Handler::handle_async`] pass through
+/// [`Middleware::handle_async`] on their way there.
+pub struct Layered {
+  middleware: Vec<Arc<dyn Middleware>>,
+  handler: Arc<dyn Handler>,
+}
+
+impl Layered {
+  pub fn new(handler: Arc<dyn Handler>) -> Self {
+    Self { middleware: Vec::new(), handler }
+  }
+
+  pub fn layer(mut self, middleware: Arc<dyn Middleware>) -> Self {
+    self.middleware.push(middleware);
+    self
+  }
+}
+
+impl Handler for Layered {
+  fn handle_request(&self, request: &HttpRequest<'_>) -> HandlerResult {
+    Next::new(&self.middleware, &*self.handler, None).run(request)
+  }
+
+  fn handle_async<'a>(&'a self, request: &'a HttpRequest<'a>) -> Option<HandlerFuture<'a>> {
+    if self.middleware.is_empty() {
+      return self.handler.handle_async(request);
+    }
+    // futures don't do anything until polled, so asking the handler twice is cheap: once to
+    // learn whether it responds asynchronously, then at the end of the chain
+    drop(self.handler.handle_async(request)?);
+    Some(Next::new(&self.middleware, &*self.handler, None).run_async(request))
+  }
+
+  fn handle_with_body(&self, request: &HttpRequest<'_>, body: RequestBody) -> HandlerResult {
+    Next::new(&self.middleware, &*self.handler, Some(body)).run(request)
+  }
+
+  fn allowed_methods(&self) -> Vec<Method> {
+    self.handler.allowed_methods()
+  }
+
+  fn describe(&self) -> Value {
+    json!({
+      "handler": "Layered",
+      "middleware": self.middleware.iter().map(|middleware| middleware.describe()).collect::<Vec<_>>(),
+      "inner": self.handler.describe(),
+    })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::http::{header::HttpRequestHeaderKey, HandlerError, HttpResponse, StatusCode};
+  use expectest::prelude::*;
+  use rstest::*;
+  use std::io::Read;
+
+  /// Echoes the request body
+  struct BodyHandler;
+
+  impl Handler for BodyHandler {
+    fn handle_request(&self, _request: &HttpRequest<'_>) -> HandlerResult {
+      Ok(HttpResponse::new(StatusCode::Ok, Some(Vec::new()), None))
+    }
+
+    fn handle_with_body(&self, _request: &HttpRequest<'_>, mut body: RequestBody) -> HandlerResult {
+      let mut received = Vec::new();
+      body.read_to_end(&mut received)?;
+      Ok(HttpResponse::new(StatusCode::Ok, Some(received), None))
+    }
+  }
+
+  /// Appends its name to the `X-Trail` response header
+  struct Trail(&'static str);
+
+  impl Middleware for Trail {
+    fn handle(&self, request: &HttpRequest, next: Next<'_>) -> HandlerResult {
+      let mut response = next.run(request)?;
+      let trail = response
+        .http_header()
+        .as_ref()
+        .and_then(|header| header.get("X-Trail"))
+        .map_or(self.0.to_string(), |trail| format!("{} {}", trail, self.0));
+      response.insert_header("X-Trail", &trail);
+      Ok(response)
+    }
+  }
+
+  /// Turns away requests without an `Authorization` header
+  struct RequireAuthorization;
+
+  impl Middleware for RequireAuthorization {
+    fn handle(&self, request: &HttpRequest, next: Next<'_>) -> HandlerResult {
+      match request.header().get(HttpRequestHeaderKey::Authorization) {
+        Some(_) => next.run(request),
+        None => Ok(HttpResponse::empty_body(StatusCode::NotFound)),
+      }
+    }
+  }
+
+  #[fixture]
+  fn layered() -> Layered {
+    Layered::new(Arc::new(BodyHandler))
+      .layer(Arc::new(Trail("outer")))
+      .layer(Arc::new(RequireAuthorization))
+      .layer(Arc::new(Trail("inner")))
+  }
+
+  #[rstest]
+  fn test_run_in_order(layered: Layered) -> Result<(), HandlerError> {
+    let request = HttpRequest::try_from(
+      &b"POST / HTTP/1.1\r\nAuthorization: Bearer x\r\nContent-Length: 5\r\n\r\n"[..],
+    )?;
+    let (body, _) = RequestBody::channel(b"hello", 5);
+    let response = layered.handle_with_body(&request, body)?;
+    expect!(response.body().as_deref()).to(be_some().value(&b"hello"[..]));
+    let trail = response
+      .http_header()
+      .as_ref()
+      .and_then(|header| header.get("X-Trail"))
+      .cloned();
+    expect!(trail).to(be_some().value("inner outer"));
+    expect!(layered.handle_async(&request).is_none()).to(be_true());
+    Ok(())
+  }
+
+  #[rstest]
+  fn test_short_circuit(layered: Layered) -> Result<(), HandlerError> {
+    let request = HttpRequest::try_from(&b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n"[..])?;
+    let response = layered.handle_request(&request)?;
+    expect!(*response.status_code()).to(be_equal_to(StatusCode::NotFound));
+    let trail = response
+      .http_header()
+      .as_ref()
+      .and_then(|header| header.get("X-Trail"))
+      .cloned();
+    expect!(trail).to(be_some().value("outer"));
+    Ok(())
+  }
+
+  struct Tenant(String);
+
+  /// Tells handlers which tenant a request is for, by its `Host`
+  struct ResolveTenant;
+
+  impl Middleware for ResolveTenant {
+    fn handle(&self, request: &HttpRequest, next: Next<'_>) -> HandlerResult {
+      let tenant = Tenant(request.hostname().unwrap_or("default").to_string());
+      next.run(&request.clone().with_extension(tenant))
+    }
+  }
+
+  /// Answers with the tenant middleware resolved
+  struct TenantHandler;
+
+  impl Handler for TenantHandler {
+    fn handle_request(&self, request: &HttpRequest<'_>) -> HandlerResult {
+      let tenant = request
+        .extensions()
+        .get::<Tenant>()
+        .ok_or_else(|| HandlerError::internal("No tenant resolved"))?;
+      Ok(HttpResponse::new(
+        StatusCode::Ok,
+        Some(tenant.0.clone().into_bytes()),
+        None,
+      ))
+    }
+  }
+
+  #[rstest]
+  fn test_pass_extensions_on() -> Result<(), HandlerError> {
+    let request = HttpRequest::try_from(&b"GET / HTTP/1.1\r\nHost: acme.example\r\n\r\n"[..])?;
+    let handler = Arc::new(TenantHandler);
+    expect!(handler.handle_request(&request).is_err()).to(be_true());
+
+    let layered = Layered::new(handler).layer(Arc::new(ResolveTenant));
+    let response = layered.handle_request(&request)?;
+    expect!(response.body().as_deref()).to(be_some().value(&b"acme.example"[..]));
+    Ok(())
+  }
+}
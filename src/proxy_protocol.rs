@@ -0,0 +1,199 @@
+use std::{
+  io,
+  net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+  str,
+};
+use thiserror::Error;
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+/// Starts every version 2 header, chosen to never start an HTTP request
+const V2_SIGNATURE: [u8; 12] = *b"\r\n\r\n\0\r\nQUIT\n";
+/// Including the `PROXY ` prefix and the closing CRLF
+const V1_MAX_LENGTH: usize = 107;
+
+#[derive(Error, Debug)]
+pub enum ProxyProtocolError {
+  #[error("Failed to read PROXY protocol header: {0}")]
+  Io(#[from] io::Error),
+  #[error("Invalid PROXY protocol header: {0}")]
+  Invalid(&'static str),
+}
+
+/// Both ends of the client's connection to the proxy, as the proxy reported them
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProxiedAddresses {
+  pub source: SocketAddr,
+  pub destination: SocketAddr,
+}
+
+/// Read the PROXY protocol header, version 1 or 2, that load balancers such as HAProxy or AWS
+/// NLBs send ahead of the client's bytes, and nothing beyond it. `None` for connections the
+/// proxy opened itself (health checks) or from clients it can't name an address for, such as
+/// Unix sockets.
+pub async fn read_header<S: AsyncRead + Unpin>(
+  stream: &mut S,
+) -> Result<Option<ProxiedAddresses>, ProxyProtocolError> {
+  let mut start = [0; 6];
+  stream.read_exact(&mut start).await?;
+  match &start {
+    b"PROXY " => read_v1(stream).await,
+    start if *start == V2_SIGNATURE[..6] => read_v2(stream).await,
+    _ => Err(ProxyProtocolError::Invalid("no PROXY signature")),
+  }
+}
+
+/// `PROXY TCP4 192.0.2.1 198.51.100.1 56324 443\r\n`, after the `PROXY `
+async fn read_v1<S: AsyncRead + Unpin>(
+  stream: &mut S,
+) -> Result<Option<ProxiedAddresses>, ProxyProtocolError> {
+  // byte by byte, what follows the line belongs to the client
+  let mut line = Vec::new();
+  while !line.ends_with(b"\r\n") {
+    if line.len() + "PROXY ".len() >= V1_MAX_LENGTH {
+      return Err(ProxyProtocolError::Invalid("line too long"));
+    }
+    line.push(stream.read_u8().await?);
+  }
+  let invalid = || ProxyProtocolError::Invalid("malformed version 1 line");
+  let line = str::from_utf8(&line[..line.len() - 2]).map_err(|_| invalid())?;
+  let fields: Vec<&str> = line.split(' ').collect();
+  match fields[..] {
+    ["UNKNOWN", ..] => Ok(None),
+    [protocol @ ("TCP4" | "TCP6"), source, destination, source_port, destination_port] => {
+      let ip = |ip: &str| -> Result<IpAddr, ProxyProtocolError> {
+        match protocol {
+          "TCP4" => ip.parse::<Ipv4Addr>().map(IpAddr::V4),
+          _ => ip.parse::<Ipv6Addr>().map(IpAddr::V6),
+        }
+        .map_err(|_| invalid())
+      };
+      let port = |port: &str| port.parse::<u16>().map_err(|_| invalid());
+      Ok(Some(ProxiedAddresses {
+        source: SocketAddr::new(ip(source)?, port(source_port)?),
+        destination: SocketAddr::new(ip(destination)?, port(destination_port)?),
+      }))
+    }
+    _ => Err(invalid()),
+  }
+}
+
+/// The binary header, after the first 6 bytes of its signature
+async fn read_v2<S: AsyncRead + Unpin>(
+  stream: &mut S,
+) -> Result<Option<ProxiedAddresses>, ProxyProtocolError> {
+  let mut rest = [0; 10];
+  stream.read_exact(&mut rest).await?;
+  if rest[..6] != V2_SIGNATURE[6..] {
+    return Err(ProxyProtocolError::Invalid("no PROXY signature"));
+  }
+  let (version_command, family) = (rest[6], rest[7]);
+  let length = u16::from_be_bytes([rest[8], rest[9]]);
+  // TLVs after the addresses are read along, and skipped
+  let mut addresses = vec![0; usize::from(length)];
+  stream.read_exact(&mut addresses).await?;
+
+  if version_command >> 4 != 2 {
+    return Err(ProxyProtocolError::Invalid("unsupported version"));
+  }
+  match version_command & 0x0f {
+    // LOCAL, the proxy's own connection
+    0 => return Ok(None),
+    1 => {}
+    _ => return Err(ProxyProtocolError::Invalid("unsupported command")),
+  }
+  let truncated = ProxyProtocolError::Invalid("addresses truncated");
+  let port = |bytes: &[u8]| u16::from_be_bytes([bytes[0], bytes[1]]);
+  match family >> 4 {
+    // TCP or UDP over IPv4
+    1 => {
+      let bytes = addresses.get(..12).ok_or(truncated)?;
+      let ip = |bytes: &[u8]| IpAddr::from(<[u8; 4]>::try_from(bytes).unwrap_or_default());
+      Ok(Some(ProxiedAddresses {
+        source: SocketAddr::new(ip(&bytes[..4]), port(&bytes[8..])),
+        destination: SocketAddr::new(ip(&bytes[4..8]), port(&bytes[10..])),
+      }))
+    }
+    // over IPv6
+    2 => {
+      let bytes = addresses.get(..36).ok_or(truncated)?;
+      let ip = |bytes: &[u8]| IpAddr::from(<[u8; 16]>::try_from(bytes).unwrap_or_default());
+      Ok(Some(ProxiedAddresses {
+        source: SocketAddr::new(ip(&bytes[..16]), port(&bytes[32..])),
+        destination: SocketAddr::new(ip(&bytes[16..32]), port(&bytes[34..])),
+      }))
+    }
+    // unspecified or Unix sockets, no address to report
+    _ => Ok(None),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use expectest::prelude::*;
+  use rstest::*;
+
+  const REQUEST: &[u8] = b"GET / HTTP/1.1\r\n";
+
+  fn addresses(source: &str, destination: &str) -> Option<ProxiedAddresses> {
+    Some(ProxiedAddresses {
+      source: source.parse().unwrap(),
+      destination: destination.parse().unwrap(),
+    })
+  }
+
+  fn v2(command: u8, family: u8, addresses: &[u8]) -> Vec<u8> {
+    let mut header = V2_SIGNATURE.to_vec();
+    header.extend_from_slice(&[0x20 | command, family]);
+    header.extend_from_slice(&(addresses.len() as u16).to_be_bytes());
+    header.extend_from_slice(addresses);
+    header
+  }
+
+  #[rstest]
+  #[case::v1_tcp4(
+    b"PROXY TCP4 192.0.2.1 198.51.100.1 56324 443\r\n".to_vec(),
+    addresses("192.0.2.1:56324", "198.51.100.1:443")
+  )]
+  #[case::v1_tcp6(
+    b"PROXY TCP6 2001:db8::1 2001:db8::2 56324 443\r\n".to_vec(),
+    addresses("[2001:db8::1]:56324", "[2001:db8::2]:443")
+  )]
+  #[case::v1_unknown(b"PROXY UNKNOWN\r\n".to_vec(), None)]
+  #[case::v2_ipv4(
+    v2(1, 0x11, &[192, 0, 2, 1, 198, 51, 100, 1, 0xdc, 0x04, 0x01, 0xbb, 0x03, 0x00, 0x00]),
+    addresses("192.0.2.1:56324", "198.51.100.1:443")
+  )]
+  #[case::v2_ipv6(
+    v2(1, 0x21, &[[0x20, 0x01, 0x0d, 0xb8].as_slice(), &[0; 11], &[1, 0x20, 0x01, 0x0d, 0xb8], &[0; 11], &[2, 0xdc, 0x04, 0x01, 0xbb]].concat()),
+    addresses("[2001:db8::1]:56324", "[2001:db8::2]:443")
+  )]
+  #[case::v2_local(v2(0, 0x00, &[]), None)]
+  #[case::v2_unix(v2(1, 0x31, &[0; 216]), None)]
+  #[tokio::test]
+  async fn test_read_header(
+    #[case] header: Vec<u8>,
+    #[case] expected: Option<ProxiedAddresses>,
+  ) -> Result<(), ProxyProtocolError> {
+    let connection = [header, REQUEST.to_vec()].concat();
+    let mut stream = &connection[..];
+    expect!(read_header(&mut stream).await?).to(be_equal_to(expected));
+    // the client's bytes are left unread
+    expect!(stream).to(be_equal_to(REQUEST));
+    Ok(())
+  }
+
+  #[rstest]
+  #[case::plain_http(REQUEST.to_vec())]
+  #[case::v1_bad_address(b"PROXY TCP4 192.0.2.1 2001:db8::2 56324 443\r\n".to_vec())]
+  #[case::v1_missing_port(b"PROXY TCP4 192.0.2.1 198.51.100.1 56324\r\n".to_vec())]
+  #[case::v1_too_long([b"PROXY UNKNOWN ".as_slice(), &[b'x'; 100], b"\r\n"].concat())]
+  #[case::v2_version([V2_SIGNATURE.as_slice(), &[0x11, 0x11, 0, 0]].concat())]
+  #[case::v2_truncated(v2(1, 0x11, &[192, 0, 2, 1]))]
+  #[tokio::test]
+  async fn test_invalid_header(#[case] header: Vec<u8>) {
+    let connection = [header, REQUEST.to_vec()].concat();
+    let result = read_header(&mut &connection[..]).await;
+    expect!(matches!(result, Err(ProxyProtocolError::Invalid(_)))).to(be_true());
+  }
+}
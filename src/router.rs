@@ -0,0 +1,367 @@
+use serde_json::{json, Value};
+use std::sync::Arc;
+
+use crate::http::{
+  header::HttpResponseHeaderBuilder,
+  path_pattern::{PathParams, PathPattern},
+  HttpRequest, HttpResponse, Method, RequestBody, StatusCode,
+};
+use crate::middleware::{Middleware, Next};
+use crate::server::{Handler, HandlerFuture, HandlerResult};
+
+/// `methods` as an `Allow` header value, with `HEAD` wherever `GET` is allowed, the server
+/// answering it through the `GET` path
+pub(crate) fn allow(methods: &[Method]) -> String {
+  let mut methods = methods.to_vec();
+  if let Some(get) = methods.iter().position(|method| *method == Method::GET) {
+    if !methods.contains(&Method::HEAD) {
+      methods.insert(get + 1, Method::HEAD);
+    }
+  }
+  methods
+    .iter()
+    .map(Method::to_string)
+    .collect::<Vec<_>>()
+    .join(", ")
+}
+
+/// `405 Method Not Allowed` for a resource that only takes `allowed`
+pub(crate) fn method_not_allowed(allowed: &[Method]) -> HttpResponse {
+  allowing(StatusCode::MethodNotAllowed, allowed)
+}
+
+/// The answer to `OPTIONS` for a resource that takes `allowed`, and `OPTIONS` itself
+pub(crate) fn options(allowed: &[Method]) -> HttpResponse {
+  let mut allowed = allowed.to_vec();
+  if !allowed.contains(&Method::OPTIONS) {
+    allowed.push(Method::OPTIONS);
+  }
+  allowing(StatusCode::Ok, &allowed)
+}
+
+fn allowing(status_code: StatusCode, allowed: &[Method]) -> HttpResponse {
+  let mut builder = HttpResponseHeaderBuilder::new();
+  builder.allow(&allow(allowed));
+  builder.content_length("0");
+  HttpResponse::new(status_code, None, Some(Arc::new(builder.build())))
+}
+
+struct Route {
+  method: Method,
+  pattern: PathPattern,
+  handler: Arc<dyn Handler>,
+}
+
+/// Where [`Router`] sends a request
+enum Routed<'r, 'req> {
+  Route(&'r Route, PathParams<'req>),
+  /// The path matches routes, but none for the request's method
+  MethodNotAllowed(Vec<Method>),
+  NotFound,
+}
+
+/// Dispatches requests to handlers registered by method and [`PathPattern`], such as
+/// `/users/{id}` or `/static/*`. Routes are tried in the order they were registered; the
+/// matched handler finds what the pattern captured in [`HttpRequest::path_params`].
+/// Paths no route matches go to the fallback handler, `404 Not Found` without one, and
+/// paths only matched for other methods get `405 Method Not Allowed` with an `Allow` header,
+/// or that header in a `200` for `OPTIONS`.
+#[derive(Default)]
+pub struct Router {
+  routes: Vec<Route>,
+  fallback: Option<Arc<dyn Handler>>,
+  /// around the routes' handlers and the fallback, not the router's own `404`s and `405`s
+  middleware: Vec<Arc<dyn Middleware>>,
+}
+
+impl Router {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Send `method` requests for paths matching `pattern` to `handler`
+  ///
+  /// # Panics
+  ///
+  /// If `pattern` isn't a valid [`PathPattern`]; routes are meant to be written out in code
+  pub fn route(mut self, method: Method, pattern: &str, handler: Arc<dyn Handler>) -> Self {
+    let pattern = pattern
+      .parse()
+      .unwrap_or_else(|error| panic!("Invalid route: {}", error));
+    self.routes.push(Route { method, pattern, handler });
+    self
+  }
+
+  /// Handle requests no route matches with `handler` rather than a `404`
+  pub fn fallback(mut self, handler: Arc<dyn Handler>) -> Self {
+    self.fallback = Some(handler);
+    self
+  }
+
+  /// Run requests through `middleware` before the handler they're routed to, in the order
+  /// added, wherever the routes were registered
+  pub fn layer(mut self, middleware: Arc<dyn Middleware>) -> Self {
+    self.middleware.push(middleware);
+    self
+  }
+
+  fn routed<'r>(&self, request: &'r HttpRequest<'_>) -> Routed<'_, 'r> {
+    let mut allowed = Vec::new();
+    for route in &self.routes {
+      let Some(params) = route.pattern.matches(request.path()) else {
+        continue;
+      };
+      if route.method == *request.method() {
+        return Routed::Route(route, params);
+      }
+      if !allowed.contains(&route.method) {
+        allowed.push(route.method);
+      }
+    }
+    match allowed.is_empty() {
+      true => Routed::NotFound,
+      false => Routed::MethodNotAllowed(allowed),
+    }
+  }
+
+  /// The response to a request whose path is only routed for `allowed` methods
+  fn not_allowed(request: &HttpRequest<'_>, allowed: &[Method]) -> HttpResponse {
+    match request.method() {
+      Method::OPTIONS => options(allowed),
+      _ => method_not_allowed(allowed),
+    }
+  }
+
+  /// Have `handler` respond, behind this router's middleware
+  fn dispatch(
+    &self,
+    handler: &dyn Handler,
+    request: &HttpRequest<'_>,
+    body: Option<RequestBody>,
+  ) -> HandlerResult {
+    Next::new(&self.middleware, handler, body).run(request)
+  }
+
+  fn not_found(&self, request: &HttpRequest<'_>, body: Option<RequestBody>) -> HandlerResult {
+    match &self.fallback {
+      Some(fallback) => self.dispatch(&**fallback, request, body),
+      None => Ok(HttpResponse::empty_body(StatusCode::NotFound)),
+    }
+  }
+}
+
+impl Handler for Router {
+  fn handle_request(&self, request: &HttpRequest<'_>) -> HandlerResult {
+    match self.routed(request) {
+      Routed::Route(route, params) => self.dispatch(
+        &*route.handler,
+        &request.clone().with_path_params(params),
+        None,
+      ),
+      Routed::MethodNotAllowed(allowed) => Ok(Self::not_allowed(request, &allowed)),
+      Routed::NotFound => self.not_found(request, None),
+    }
+  }
+
+  /// Through this router's middleware, for handlers that respond asynchronously
+  fn handle_async<'a>(&'a self, request: &'a HttpRequest<'a>) -> Option<HandlerFuture<'a>> {
+    match self.routed(request) {
+      Routed::Route(route, params) => {
+        let routed = request.clone().with_path_params(params);
+        // futures don't do anything until polled, so asking the handler twice is cheap: once
+        // to learn whether it responds asynchronously, then for a future owning its request
+        drop(route.handler.handle_async(&routed)?);
+        Some(Box::pin(async move {
+          let next = Next::new(&self.middleware, &*route.handler, None);
+          next.run_async(&routed).await
+        }))
+      }
+      Routed::MethodNotAllowed(_) => None,
+      Routed::NotFound => {
+        let fallback = self.fallback.as_ref()?;
+        drop(fallback.handle_async(request)?);
+        Some(Next::new(&self.middleware, &**fallback, None).run_async(request))
+      }
+    }
+  }
+
+  fn handle_with_body(&self, request: &HttpRequest<'_>, body: RequestBody) -> HandlerResult {
+    match self.routed(request) {
+      Routed::Route(route, params) => self.dispatch(
+        &*route.handler,
+        &request.clone().with_path_params(params),
+        Some(body),
+      ),
+      Routed::MethodNotAllowed(allowed) => Ok(Self::not_allowed(request, &allowed)),
+      Routed::NotFound => self.not_found(request, Some(body)),
+    }
+  }
+
+  fn allowed_methods(&self) -> Vec<Method> {
+    let fallback_methods = self
+      .fallback
+      .iter()
+      .flat_map(|fallback| fallback.allowed_methods());
+    self
+      .routes
+      .iter()
+      .map(|route| route.method)
+      .chain(fallback_methods)
+      .fold(Vec::new(), |mut methods, method| {
+        if !methods.contains(&method) {
+          methods.push(method);
+        }
+        methods
+      })
+  }
+
+  fn describe(&self) -> Value {
+    let routes = self
+      .routes
+      .iter()
+      .map(|route| {
+        json!({
+          "method": route.method.to_string(),
+          "pattern": route.pattern.as_str(),
+          "handler": route.handler.describe(),
+        })
+      })
+      .collect::<Vec<_>>();
+    json!({
+      "handler": "Router",
+      "routes": routes,
+      "fallback": self.fallback.as_ref().map(|fallback| fallback.describe()),
+      "middleware": self.middleware.iter().map(|middleware| middleware.describe()).collect::<Vec<_>>(),
+    })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::http::{header::HttpResponseHeaderKey, HandlerError};
+  use expectest::prelude::*;
+  use rstest::*;
+
+  /// Answers with its name and the captured parameters, e.g. `user id=42`
+  struct NamedHandler(&'static str);
+
+  impl Handler for NamedHandler {
+    fn handle_request(&self, request: &HttpRequest<'_>) -> HandlerResult {
+      let params = request
+        .path_params()
+        .iter()
+        .map(|(name, value)| format!(" {}={}", name, value))
+        .collect::<String>();
+      let body = format!("{}{}", self.0, params);
+      Ok(HttpResponse::new(
+        StatusCode::Ok,
+        Some(body.into_bytes()),
+        None,
+      ))
+    }
+  }
+
+  #[fixture]
+  fn router() -> Router {
+    Router::new()
+      .route(Method::GET, "/users/{id}", Arc::new(NamedHandler("user")))
+      .route(
+        Method::DELETE,
+        "/users/{id}",
+        Arc::new(NamedHandler("delete")),
+      )
+      .route(
+        Method::GET,
+        "/users/me",
+        Arc::new(NamedHandler("unreachable")),
+      )
+      .route(Method::GET, "/static/*", Arc::new(NamedHandler("static")))
+  }
+
+  #[rstest]
+  #[case::param("GET /users/42 HTTP/1.1", "user id=42")]
+  #[case::method("DELETE /users/42 HTTP/1.1", "delete id=42")]
+  #[case::registration_order("GET /users/me HTTP/1.1", "user id=me")]
+  #[case::wildcard("GET /static/css/site.css?v=2 HTTP/1.1", "static *=css/site.css")]
+  fn test_route(
+    router: Router,
+    #[case] request_line: &str,
+    #[case] expected: &str,
+  ) -> Result<(), HandlerError> {
+    let raw_request = format!("{}\r\nHost: localhost\r\n\r\n", request_line);
+    let response = router.handle_request(&HttpRequest::try_from(raw_request.as_bytes())?)?;
+    expect!(*response.status_code()).to(be_equal_to(StatusCode::Ok));
+    expect!(response.body().as_deref()).to(be_some().value(expected.as_bytes()));
+    Ok(())
+  }
+
+  #[rstest]
+  #[case::method_not_allowed("PUT /users/42", StatusCode::MethodNotAllowed, "GET, HEAD, DELETE")]
+  #[case::options("OPTIONS /users/42", StatusCode::Ok, "GET, HEAD, DELETE, OPTIONS")]
+  #[case::without_get("PUT /upload", StatusCode::MethodNotAllowed, "POST")]
+  fn test_allow(
+    router: Router,
+    #[case] request_line: &str,
+    #[case] expected_status: StatusCode,
+    #[case] expected_allow: &str,
+  ) -> Result<(), HandlerError> {
+    let router = router.route(Method::POST, "/upload", Arc::new(NamedHandler("upload")));
+    let raw_request = format!("{} HTTP/1.1\r\nHost: localhost\r\n\r\n", request_line);
+    let response = router.handle_request(&HttpRequest::try_from(raw_request.as_bytes())?)?;
+    expect!(*response.status_code()).to(be_equal_to(expected_status));
+    let allow = response
+      .http_header()
+      .as_ref()
+      .and_then(|header| header.get(HttpResponseHeaderKey::Allow).cloned());
+    expect!(allow).to(be_some().value(expected_allow));
+    Ok(())
+  }
+
+  #[rstest]
+  fn test_fallback(router: Router) -> Result<(), HandlerError> {
+    let request = HttpRequest::try_from(&b"GET /missing HTTP/1.1\r\nHost: localhost\r\n\r\n"[..])?;
+    let response = router.handle_request(&request)?;
+    expect!(*response.status_code()).to(be_equal_to(StatusCode::NotFound));
+
+    let router = router.fallback(Arc::new(NamedHandler("fallback")));
+    let response = router.handle_request(&request)?;
+    expect!(response.body().as_deref()).to(be_some().value(&b"fallback"[..]));
+    Ok(())
+  }
+
+  /// Marks the responses passing through it
+  struct Tagged;
+
+  impl Middleware for Tagged {
+    fn handle(&self, request: &HttpRequest, next: Next<'_>) -> HandlerResult {
+      let mut response = next.run(request)?;
+      response.insert_header("X-Tagged", "yes");
+      Ok(response)
+    }
+  }
+
+  #[rstest]
+  #[case::routed("GET /users/42 HTTP/1.1", true)]
+  #[case::fallback("GET /missing HTTP/1.1", true)]
+  #[case::method_not_allowed("PUT /users/42 HTTP/1.1", false)]
+  fn test_layer(
+    router: Router,
+    #[case] request_line: &str,
+    #[case] expected_tagged: bool,
+  ) -> Result<(), HandlerError> {
+    let router = router
+      .layer(Arc::new(Tagged))
+      .fallback(Arc::new(NamedHandler("fallback")));
+    let raw_request = format!("{}\r\nHost: localhost\r\n\r\n", request_line);
+    let request = HttpRequest::try_from(raw_request.as_bytes())?;
+    let response = router.handle_request(&request)?;
+    let tagged = response
+      .http_header()
+      .as_ref()
+      .is_some_and(|header| header.get("X-Tagged").is_some());
+    expect!(tagged).to(be_equal_to(expected_tagged));
+    expect!(router.handle_async(&request).is_none()).to(be_true());
+    Ok(())
+  }
+}
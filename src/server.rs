@@ -1,60 +1,2755 @@
 #![allow(dead_code)]
 
-use tokio::io::AsyncReadExt;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::task::{JoinError, JoinSet};
+use tracing::{field, Instrument, Span};
 
-use crate::http::{HttpRequest, HttpResponse, StatusCode};
-use std::{convert::TryFrom, sync::Arc};
+use crate::access_log::{AccessLogEntry, AccessLogFormat};
+use crate::admin_handler::AdminHandler;
+use crate::deadline::DeadlineReader;
+use crate::error::ServerError;
+use crate::error_log::ErrorLog;
+use crate::http::{
+  body::RequestBody,
+  chunked::{self, ChunkedDecoder, ChunkedError},
+  codec::{self, Expectation},
+  connection_info::ConnectionInfo,
+  forwarded::{IpRange, TrustedProxies},
+  header::{HttpRequestHeaderKey, HttpResponseHeaderKey},
+  hsts::Hsts,
+  response::DEFAULT_CHUNK_SIZE,
+  upgrade::Upgraded,
+  HandlerError, HttpRequest, HttpResponse, Method, ParseError, ParserLimits, QueryLimits,
+  StatusCode, TargetForm, Version,
+};
+use crate::limits::{Limits, LimitsTable, RateLimit, RateLimiter, ResolvedLimits};
+use crate::metrics::ServerMetrics;
+use crate::middleware::{Layered, Middleware};
+use crate::proxy_protocol;
+use crate::router;
+use crate::throttle::ThrottledWriter;
+use crate::transport::Listener;
+use std::{
+  any::Any,
+  future::Future,
+  io,
+  net::SocketAddr,
+  panic::{self, AssertUnwindSafe},
+  pin::Pin,
+  sync::Arc,
+  task::Poll,
+  time::Duration,
+};
 use tokio::net::TcpListener;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio::time::{timeout, timeout_at, Instant};
+
+#[cfg(feature = "acme")]
+use crate::acme::{AcmeChallenge, AcmeChallengeHandler, AcmeError, AcmeManager, AcmeSettings};
+#[cfg(feature = "tls")]
+use crate::tls::{ReloadableTlsConfig, TlsError, TlsSettings};
+
+/// A handler's response, or the error the server answers for it with its status and logs
+pub type HandlerResult = Result<HttpResponse, HandlerError>;
+
+/// A response being produced by [`Handler::handle_async`], boxed to keep handlers object safe
+pub type HandlerFuture<'a> = Pin<Box<dyn Future<Output = HandlerResult> + Send + 'a>>;
 
 pub trait Handler: Send + Sync + 'static {
-  fn handle_request(&self, request: &HttpRequest) -> HttpResponse;
+  fn handle_request(&self, request: &HttpRequest) -> HandlerResult;
+
+  /// Like [`Self::handle_request`], for requests with a body. The body is streamed from the
+  /// connection while the handler reads it rather than buffered, and as handlers run on a
+  /// blocking thread then, it can be read through [`std::io::Read`]. Handlers that don't
+  /// override this never see the body.
+  fn handle_with_body(&self, request: &HttpRequest, _body: RequestBody) -> HandlerResult {
+    self.handle_request(request)
+  }
+
+  /// An async alternative to [`Self::handle_request`] for handlers waiting on I/O, such as
+  /// files: the server awaits it on the connection's task instead, so it must not block.
+  /// `None`, the default, leaves the request to `handle_request`. Requests with a body
+  /// always go to [`Self::handle_with_body`].
+  fn handle_async<'a>(&'a self, _request: &'a HttpRequest<'a>) -> Option<HandlerFuture<'a>> {
+    None
+  }
+
+  /// Methods advertised in the `Allow` header of server-wide `OPTIONS *` requests
+  fn allowed_methods(&self) -> Vec<Method> {
+    vec![Method::GET]
+  }
+
+  /// What the admin listener's `/config` reports about this handler
+  fn describe(&self) -> Value {
+    json!({ "handler": std::any::type_name::<Self>() })
+  }
 }
 
+/// Waits for `future` from a handler's blocking methods, on the runtime the server calls them
+/// from, so async handlers can share their implementation with those
+pub(crate) fn block_on<F: Future>(future: F) -> Result<F::Output, HandlerError> {
+  let runtime = tokio::runtime::Handle::try_current().map_err(|error| {
+    HandlerError::new(
+      StatusCode::ServiceUnavailable,
+      "Handler called outside of a runtime",
+    )
+    .with_source(error)
+  })?;
+  match runtime.runtime_flavor() {
+    // hands the worker's other tasks off if called from one
+    tokio::runtime::RuntimeFlavor::MultiThread => {
+      Ok(tokio::task::block_in_place(|| runtime.block_on(future)))
+    }
+    _ => Ok(runtime.block_on(future)),
+  }
+}
+
+/// Upper bound of a request's line and headers unless configured otherwise, see
+/// [`Server::max_head_size`]
+pub const DEFAULT_MAX_HEAD_SIZE: usize = codec::DEFAULT_MAX_HEAD_SIZE;
+/// Largest request body read before the request is handled unless configured otherwise, see
+/// [`Server::max_buffered_body_size`]
+pub const DEFAULT_MAX_BUFFERED_BODY_SIZE: usize = 64 * 1024;
+/// How long a connection may sit idle between requests unless configured otherwise, see
+/// [`Server::keep_alive_timeout`]
+pub const DEFAULT_KEEP_ALIVE_TIMEOUT: Duration = Duration::from_secs(5);
+/// How long a client may take to send a request's line and headers unless configured
+/// otherwise, see [`Server::header_read_timeout`]
+pub const DEFAULT_HEADER_READ_TIMEOUT: Duration = Duration::from_secs(10);
+/// How long a client may take to send a request's body unless configured otherwise, see
+/// [`Server::body_read_timeout`]
+pub const DEFAULT_BODY_READ_TIMEOUT: Duration = Duration::from_secs(30);
+/// Requests served per connection before it's closed, as advertised in `Keep-Alive`
+const MAX_REQUESTS_PER_CONNECTION: u64 = 1000;
+/// Bytes requested from the connection per read while waiting for the request head
+const HEAD_READ_SIZE: usize = 4 * 1024;
+/// The interim response telling a client waiting on `Expect: 100-continue` to send its body
+const CONTINUE: &[u8] = b"HTTP/1.1 100 Continue\r\n\r\n";
+
+/// How [`Server::read_head`] stopped
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum HeadRead {
+  Complete,
+  /// the client stopped sending before finishing the head
+  Closed,
+  TooLarge,
+  /// the request line alone exceeds [`ParserLimits::max_request_line_length`]
+  RequestLineTooLong,
+  /// the chunked body couldn't be decoded
+  InvalidBody(ChunkedError),
+  /// the client took longer than the server's read timeouts allow
+  TimedOut,
+  /// the client waits on an `Expect` the server won't meet, see [`Server::meet_expectation`]
+  ExpectationFailed,
+}
+
+async fn acquire_slot(slots: &Arc<Semaphore>) -> OwnedSemaphorePermit {
+  Arc::clone(slots)
+    .acquire_owned()
+    .await
+    .expect("connection slots are never closed")
+}
+
+/// What happened on a connection, reported to [`Server::on_connection_close`]
+#[derive(Debug, Clone, Default)]
+pub struct ConnectionStats {
+  pub requests: u64,
+  pub bytes_read: u64,
+  pub bytes_written: u64,
+  pub duration: Duration,
+}
+
+/// What happens to connections accepted beyond [`Server::max_connections`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ConnectionOverflow {
+  /// Stop accepting until a connection closes, leaving new ones in the listener's backlog
+  #[default]
+  Wait,
+  /// Keep accepting, answering the connections over the limit with
+  /// `503 Service Unavailable` and closing them
+  Reject,
+}
+
+/// Another address [`Server::run`] accepts connections on, see [`Server::listen`], e.g.
+///
+/// ```toml
+/// [[listeners]]
+/// address = "[::]:8443"
+/// tls = true
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct ListenerSettings {
+  pub address: String,
+  /// terminate TLS with the server's certificate, which it needs to have then
+  #[serde(default)]
+  pub tls: bool,
+  /// expect PROXY protocol headers, see [`Server::proxy_protocol`]
+  #[serde(default)]
+  pub proxy_protocol: bool,
+}
+
+pub type ConnectionOpenHook = Arc<dyn Fn(SocketAddr) + Send + Sync>;
+pub type ConnectionCloseHook = Arc<dyn Fn(SocketAddr, &ConnectionStats) + Send + Sync>;
+/// Reports why the server can't serve requests right now, see [`Server::readiness_check`]
+pub type ReadinessCheck = Arc<dyn Fn() -> Result<(), String> + Send + Sync>;
+
 pub struct Server {
   address: String,
+  admin_address: Option<String>,
+  metrics: Arc<ServerMetrics>,
+  access_log: AccessLogFormat,
+  access_log_sample_rate: f64,
+  error_log: ErrorLog,
+  parser_limits: ParserLimits,
+  query_limits: QueryLimits,
+  limits: LimitsTable,
+  rate_limiter: RateLimiter,
+  max_head_size: usize,
+  max_buffered_body_size: usize,
+  keep_alive_timeout: Duration,
+  header_read_timeout: Duration,
+  body_read_timeout: Duration,
+  request_timeout: Option<Duration>,
+  response_chunk_size: usize,
+  bandwidth_limit: Option<u64>,
+  max_connections: Option<usize>,
+  connection_overflow: ConnectionOverflow,
+  hsts: Option<Hsts>,
+  trusted_proxies: TrustedProxies,
+  proxy_protocol: bool,
+  listeners: Vec<ListenerSettings>,
+  acceptors: usize,
+  #[cfg(feature = "tls")]
+  tls: Option<Arc<ReloadableTlsConfig>>,
+  #[cfg(feature = "acme")]
+  acme: Option<Arc<AcmeManager>>,
+  on_connection_open: Option<ConnectionOpenHook>,
+  on_connection_close: Option<ConnectionCloseHook>,
+  readiness_checks: Vec<ReadinessCheck>,
+  middleware: Vec<Arc<dyn Middleware>>,
 }
 
 impl Server {
   // associated function, no instance required
   // Self is a special type within any struct
   pub fn new(address: String) -> Self {
-    Self { address }
+    Self {
+      address,
+      admin_address: None,
+      metrics: Arc::new(ServerMetrics::default()),
+      access_log: AccessLogFormat::default(),
+      access_log_sample_rate: 1.0,
+      error_log: ErrorLog::default(),
+      parser_limits: ParserLimits::default(),
+      query_limits: QueryLimits::default(),
+      limits: LimitsTable::default(),
+      rate_limiter: RateLimiter::default(),
+      max_head_size: DEFAULT_MAX_HEAD_SIZE,
+      max_buffered_body_size: DEFAULT_MAX_BUFFERED_BODY_SIZE,
+      keep_alive_timeout: DEFAULT_KEEP_ALIVE_TIMEOUT,
+      header_read_timeout: DEFAULT_HEADER_READ_TIMEOUT,
+      body_read_timeout: DEFAULT_BODY_READ_TIMEOUT,
+      request_timeout: None,
+      response_chunk_size: DEFAULT_CHUNK_SIZE,
+      bandwidth_limit: None,
+      max_connections: None,
+      connection_overflow: ConnectionOverflow::default(),
+      hsts: None,
+      trusted_proxies: TrustedProxies::default(),
+      proxy_protocol: false,
+      listeners: Vec::new(),
+      acceptors: 1,
+      #[cfg(feature = "tls")]
+      tls: None,
+      #[cfg(feature = "acme")]
+      acme: None,
+      on_connection_open: None,
+      on_connection_close: None,
+      readiness_checks: Vec::new(),
+      middleware: Vec::new(),
+    }
+  }
+
+  /// Serve [`AdminHandler`] (e.g. `/metrics`) on a separate, typically private, address
+  pub fn admin_address(mut self, admin_address: String) -> Self {
+    self.admin_address = Some(admin_address);
+    self
+  }
+
+  pub fn access_log(mut self, access_log: AccessLogFormat) -> Self {
+    self.access_log = access_log;
+    self
+  }
+
+  /// Only write this share (0 to 1) of the requests to the access log, see
+  /// [`AccessLogEntry::sampled`]
+  pub fn access_log_sample_rate(mut self, sample_rate: f64) -> Self {
+    self.access_log_sample_rate = sample_rate.clamp(0.0, 1.0);
+    self
+  }
+
+  /// How often each distinct connection error gets written, see [`ErrorLog`]
+  pub fn error_log_rate_limit(mut self, rate_limit: RateLimit) -> Self {
+    self.error_log = ErrorLog::new(rate_limit);
+    self
+  }
+
+  /// Caps on the request line, headers and body, checked while the head is read and parsed
+  pub fn parser_limits(mut self, parser_limits: ParserLimits) -> Self {
+    self.parser_limits = parser_limits;
+    self
+  }
+
+  pub fn query_limits(mut self, query_limits: QueryLimits) -> Self {
+    self.query_limits = query_limits;
+    self
+  }
+
+  /// Handler timeout, body size and rate limit of every request not covered by
+  /// [`Self::route_limits`]
+  pub fn limits(mut self, limits: Limits) -> Self {
+    self.limits.set_global(limits);
+    self
+  }
+
+  /// Override some of the global [`Limits`] for `prefix` and the paths below it
+  pub fn route_limits(mut self, prefix: &str, limits: Limits) -> Self {
+    self.limits.add_route(prefix, limits);
+    self
+  }
+
+  /// Requests whose line and headers don't fit into `max_head_size` bytes get
+  /// `431 Request Header Fields Too Large`; bodies are bounded by [`Limits::max_body_size`]
+  /// instead
+  pub fn max_head_size(mut self, max_head_size: usize) -> Self {
+    self.max_head_size = max_head_size.max(1);
+    self
+  }
+
+  /// Bodies of up to `max_buffered_body_size` bytes are read along with the head and are
+  /// complete in [`HttpRequest::body`]; larger ones are only streamed to
+  /// [`Handler::handle_with_body`] as they arrive
+  pub fn max_buffered_body_size(mut self, max_buffered_body_size: usize) -> Self {
+    self.max_buffered_body_size = max_buffered_body_size;
+    self
+  }
+
+  /// Close connections that don't start another request within `timeout`
+  pub fn keep_alive_timeout(mut self, timeout: Duration) -> Self {
+    self.keep_alive_timeout = timeout;
+    self
+  }
+
+  /// Requests whose line and headers haven't arrived `timeout` after they started (for the
+  /// first one on a connection, after it was opened) get `408 Request Timeout`, and their
+  /// connection is closed, so clients trickling in bytes can't hold on to it
+  pub fn header_read_timeout(mut self, timeout: Duration) -> Self {
+    self.header_read_timeout = timeout;
+    self
+  }
+
+  /// Like [`Self::header_read_timeout`] for the body, from the end of the head on
+  pub fn body_read_timeout(mut self, timeout: Duration) -> Self {
+    self.body_read_timeout = timeout;
+    self
+  }
+
+  /// Bound the time to receive a whole request, head and body, on top of
+  /// [`Self::header_read_timeout`] and [`Self::body_read_timeout`]; handlers are bounded by
+  /// [`Limits::handler_timeout`] instead
+  pub fn request_timeout(mut self, timeout: Duration) -> Self {
+    self.request_timeout = Some(timeout);
+    self
+  }
+
+  /// Serve at most `max_connections` connections at once, so a flood of sockets can't exhaust
+  /// memory; `overflow` decides what happens to the ones beyond, those turned away are
+  /// counted in [`ServerMetrics::rejected_connections`]
+  pub fn max_connections(mut self, max_connections: usize, overflow: ConnectionOverflow) -> Self {
+    self.max_connections = Some(max_connections.max(1));
+    self.connection_overflow = overflow;
+    self
+  }
+
+  /// Bytes of response body written at a time, see [`HttpResponse::send_in_chunks`]
+  pub fn response_chunk_size(mut self, chunk_size: usize) -> Self {
+    self.response_chunk_size = chunk_size.max(1);
+    self
+  }
+
+  /// Cap the response bytes per second of each connection, so a single large download can't
+  /// take up all of the uplink
+  pub fn bandwidth_limit(mut self, bytes_per_sec: u64) -> Self {
+    self.bandwidth_limit = Some(bytes_per_sec);
+    self
+  }
+
+  /// Send `Strict-Transport-Security` on responses of TLS connections; never on plain
+  /// HTTP, where browsers ignore it anyway
+  pub fn hsts(mut self, hsts: Hsts) -> Self {
+    self.hsts = Some(hsts);
+    self
+  }
+
+  /// Believe what proxies connecting from `ranges` forward about clients, see
+  /// [`HttpRequest::client_ip`] and [`HttpRequest::scheme`]; access logs and rate limits go by
+  /// the forwarded client too
+  pub fn trusted_proxies(mut self, ranges: Vec<IpRange>) -> Self {
+    self.trusted_proxies = TrustedProxies::new(ranges);
+    self
+  }
+
+  /// Expect every connection to start with a PROXY protocol header, as TCP load balancers
+  /// send them, and report the client it names as the peer. Connections without one are
+  /// closed, so only enable it when nothing else can reach the listener.
+  pub fn proxy_protocol(mut self) -> Self {
+    self.proxy_protocol = true;
+    self
+  }
+
+  /// Accept connections on another address too, e.g. IPv6 besides IPv4 or plain HTTP
+  /// besides HTTPS, for the same handler and with the same settings otherwise
+  pub fn listen(mut self, settings: ListenerSettings) -> Self {
+    self.listeners.push(settings);
+    self
+  }
+
+  /// Bind every listener `acceptors` times with `SO_REUSEPORT` and run an accept loop on each
+  /// socket, so the kernel spreads new connections across them rather than one task accepting
+  /// them all. Only on Unix; 1, the default, binds a single plain socket.
+  pub fn reuse_port(mut self, acceptors: usize) -> Self {
+    self.acceptors = acceptors.max(1);
+    self
+  }
+
+  /// A server for HTTPS only, with the certificate chain and private key from PEM files and
+  /// the default [`TlsSettings`], such as ALPN offering `http/1.1`
+  #[cfg(feature = "tls")]
+  pub fn bind_tls<P: Into<std::path::PathBuf>>(
+    address: String,
+    cert_path: P,
+    key_path: P,
+  ) -> Result<Self, TlsError> {
+    Self::new(address).tls(TlsSettings::new(cert_path, key_path))
+  }
+
+  /// Terminate TLS on this server's address. The certificate is loaded right away, so
+  /// misconfigurations surface before the server starts, and reloaded whenever its files
+  /// change or the process receives `SIGHUP`.
+  #[cfg(feature = "tls")]
+  pub fn tls(mut self, settings: TlsSettings) -> Result<Self, TlsError> {
+    self.tls = Some(Arc::new(ReloadableTlsConfig::load(settings)?));
+    Ok(self)
+  }
+
+  /// Terminate TLS with certificates obtained and renewed through ACME. With HTTP-01
+  /// challenges, [`AcmeSettings::http_address`] additionally serves the handler over plain HTTP.
+  #[cfg(feature = "acme")]
+  pub fn acme(mut self, settings: AcmeSettings) -> Result<Self, AcmeError> {
+    let manager = AcmeManager::new(settings)?;
+    self.tls = Some(manager.tls_config());
+    self.acme = Some(Arc::new(manager));
+    Ok(self)
+  }
+
+  /// Gauges of this server, for embedders that export them through their own means
+  pub fn metrics(&self) -> Arc<ServerMetrics> {
+    Arc::clone(&self.metrics)
+  }
+
+  /// Called with the peer address as soon as a connection is accepted
+  pub fn on_connection_open<F>(mut self, hook: F) -> Self
+  where
+    F: Fn(SocketAddr) + Send + Sync + 'static,
+  {
+    self.on_connection_open = Some(Arc::new(hook));
+    self
+  }
+
+  /// Called once the connection has been served, whether successfully or not
+  pub fn on_connection_close<F>(mut self, hook: F) -> Self
+  where
+    F: Fn(SocketAddr, &ConnectionStats) + Send + Sync + 'static,
+  {
+    self.on_connection_close = Some(Arc::new(hook));
+    self
+  }
+
+  /// Consulted by the admin listener's `/readyz`, which only reports ready while every
+  /// check passes
+  pub fn readiness_check<F>(mut self, check: F) -> Self
+  where
+    F: Fn() -> Result<(), String> + Send + Sync + 'static,
+  {
+    self.readiness_checks.push(Arc::new(check));
+    self
+  }
+
+  /// Run every request through `middleware` before the handler, in the order added, see
+  /// [`Layered`]
+  pub fn layer(mut self, middleware: Arc<dyn Middleware>) -> Self {
+    self.middleware.push(middleware);
+    self
+  }
+
+  /// `handler` behind this server's middleware
+  fn layered(&self, handler: Arc<dyn Handler>) -> Arc<dyn Handler> {
+    match self.middleware.is_empty() {
+      true => handler,
+      false => Arc::new(
+        self
+          .middleware
+          .iter()
+          .cloned()
+          .fold(Layered::new(handler), Layered::layer),
+      ),
+    }
   }
 
   // method, requires an instance
-  pub async fn run(self, handler: Arc<dyn Handler>) -> Result<(), Box<dyn std::error::Error>> {
-    println!("Listening on {}", self.address);
+  pub async fn run(self, handler: Arc<dyn Handler>) -> Result<(), ServerError> {
+    let mut listeners = Vec::new();
+    for settings in std::iter::once(self.primary_listener()).chain(self.listeners.clone()) {
+      if settings.tls && !self.has_tls() {
+        return Err(ServerError::MissingCertificate(settings.address));
+      }
+      tracing::info!(address = %settings.address, tls = settings.tls, "Listening");
+      for listener in self.bind(&settings.address).await? {
+        listeners.push((listener, settings.clone()));
+      }
+    }
+
+    if let Some(admin_address) = &self.admin_address {
+      tracing::info!(address = %admin_address, "Admin endpoint listening");
+
+      let admin_listener = TcpListener::bind(admin_address).await?;
+      let admin_server = Server::new(admin_address.clone());
+      let admin_handler = Arc::new(AdminHandler::new(
+        self.metrics(),
+        self.readiness_checks.clone(),
+        self.describe(&*handler),
+      ));
+      tokio::spawn(async move {
+        if let Err(e) = admin_server.serve(admin_listener, admin_handler).await {
+          tracing::error!(error = %e, "Admin server error");
+        }
+      });
+    }
+
+    #[cfg(feature = "tls")]
+    if let Some(tls) = &self.tls {
+      Arc::clone(tls).watch();
+    }
+
+    #[cfg(feature = "acme")]
+    if let Some(acme) = &self.acme {
+      if acme.settings().challenge == AcmeChallenge::Http01 {
+        let http_address = acme.settings().http_address.clone();
+        tracing::info!(address = %http_address, "Answering ACME HTTP-01 challenges");
+
+        let http_listener = TcpListener::bind(&http_address).await?;
+        let http_server = Server::new(http_address)
+          .parser_limits(self.parser_limits)
+          .query_limits(self.query_limits);
+        let http_handler = Arc::new(AcmeChallengeHandler::new(
+          acme.challenges(),
+          self.layered(Arc::clone(&handler)),
+        ));
+        tokio::spawn(async move {
+          if let Err(e) = http_server.serve(http_listener, http_handler).await {
+            tracing::error!(error = %e, "ACME HTTP-01 server error");
+          }
+        });
+      }
+      Arc::clone(acme).watch();
+    }
+
+    let handler = self.layered(handler);
+    let connection_slots = self.connection_slots();
+    let server = Arc::new(self);
+    let mut accept_loops = JoinSet::new();
+    for (listener, settings) in listeners {
+      accept_loops.spawn(Arc::clone(&server).accept_loop(
+        listener,
+        settings,
+        Arc::clone(&handler),
+        connection_slots.clone(),
+      ));
+    }
+    // the first listener to fail stops the others with it
+    match accept_loops.join_next().await {
+      Some(result) => Ok(result??),
+      None => Ok(()),
+    }
+  }
+
+  /// The sockets to accept on at `address`, one per acceptor, see [`Self::reuse_port`]
+  async fn bind(&self, address: &str) -> io::Result<Vec<TcpListener>> {
+    if self.acceptors == 1 {
+      return Ok(vec![TcpListener::bind(address).await?]);
+    }
+    let resolved = tokio::net::lookup_host(address)
+      .await?
+      .next()
+      .ok_or_else(|| {
+        io::Error::new(
+          io::ErrorKind::InvalidInput,
+          format!("{} resolves to no address", address),
+        )
+      })?;
+    let first = reuse_port_listener(resolved)?;
+    // the others share the port the first was given, should it have asked for port 0
+    let address = first.local_addr()?;
+    let mut listeners = vec![first];
+    for _ in 1..self.acceptors {
+      listeners.push(reuse_port_listener(address)?);
+    }
+    Ok(listeners)
+  }
 
-    let listener = TcpListener::bind(&self.address).await?;
+  /// Turn away a connection over [`Self::max_connections`] without blocking the accept loop
+  fn reject_connection<S: AsyncRead + AsyncWrite + Unpin + Send + 'static>(
+    self: &Arc<Self>,
+    mut stream: S,
+    peer: SocketAddr,
+    tls: bool,
+  ) {
+    self.metrics.count_rejected_connection();
+    self.error_log.log(
+      "Rejected connection over the connection limit",
+      format!(
+        "Rejected connection from {} over the connection limit",
+        peer
+      ),
+    );
+    if tls {
+      // a plain text response would only confuse the client's TLS handshake
+      return;
+    }
+    let write_timeout = self.header_read_timeout;
+    tokio::spawn(async move {
+      let mut response = HttpResponse::empty_body(StatusCode::ServiceUnavailable);
+      response.insert_header(HttpResponseHeaderKey::RetryAfter, "1");
+      response.close_connection();
+      let _ = timeout(write_timeout, response.send(&mut stream)).await;
+    });
+  }
 
+  /// The settings this server runs with, as served by the admin listener's `/config`
+  pub fn describe(&self, handler: &dyn Handler) -> Value {
+    #[allow(unused_mut)]
+    let mut description = json!({
+      "address": self.address,
+      "admin_address": self.admin_address,
+      "access_log": self.access_log,
+      "access_log_sample_rate": self.access_log_sample_rate,
+      "parser_limits": self.parser_limits,
+      "query_limits": self.query_limits,
+      "limits": self.limits,
+      "max_head_size": self.max_head_size,
+      "max_buffered_body_size": self.max_buffered_body_size,
+      "keep_alive_timeout_secs": self.keep_alive_timeout.as_secs(),
+      "header_read_timeout_secs": self.header_read_timeout.as_secs(),
+      "body_read_timeout_secs": self.body_read_timeout.as_secs(),
+      "request_timeout_secs": self.request_timeout.map(|timeout| timeout.as_secs()),
+      "response_chunk_size": self.response_chunk_size,
+      "bandwidth_limit": self.bandwidth_limit,
+      "max_connections": self.max_connections,
+      "connection_overflow": self.connection_overflow,
+      "hsts": self.hsts.as_ref().map(Hsts::header_value),
+      "proxy_protocol": self.proxy_protocol,
+      "listeners": self.listeners,
+      "acceptors": self.acceptors,
+      "trusted_proxies": self.trusted_proxies.ranges().iter().map(IpRange::to_string).collect::<Vec<_>>(),
+      "allowed_methods": handler.allowed_methods().iter().map(Method::to_string).collect::<Vec<_>>(),
+      "middleware": self.middleware.iter().map(|middleware| middleware.describe()).collect::<Vec<_>>(),
+      "handler": handler.describe(),
+    });
+    #[cfg(feature = "tls")]
+    {
+      description["tls"] = json!(self.tls.as_ref().map(|tls| tls.settings()));
+    }
+    #[cfg(feature = "acme")]
+    {
+      description["acme"] = json!(self.acme.as_ref().map(|acme| acme.settings()));
+    }
+    description
+  }
+
+  /// Serve connections from `listener` until accepting one fails, see [`Listener`] for
+  /// transports other than the TCP [`Self::run`] binds. Unlike [`Self::run`], this doesn't
+  /// start the admin, ACME or certificate reloading tasks, nor accept on further listeners.
+  pub async fn serve<L: Listener>(self, listener: L, handler: Arc<dyn Handler>) -> io::Result<()> {
+    let settings = self.primary_listener();
+    let handler = self.layered(handler);
+    let connection_slots = self.connection_slots();
+    Arc::new(self)
+      .accept_loop(listener, settings, handler, connection_slots)
+      .await
+  }
+
+  /// The listener on [`Self::new`]'s address, terminating TLS if the server has a certificate
+  fn primary_listener(&self) -> ListenerSettings {
+    ListenerSettings {
+      address: self.address.clone(),
+      tls: self.has_tls(),
+      proxy_protocol: self.proxy_protocol,
+    }
+  }
+
+  #[cfg(feature = "tls")]
+  fn has_tls(&self) -> bool {
+    self.tls.is_some()
+  }
+
+  #[cfg(not(feature = "tls"))]
+  fn has_tls(&self) -> bool {
+    false
+  }
+
+  /// Shared by all listeners, [`Self::max_connections`] counts the server's connections
+  fn connection_slots(&self) -> Option<Arc<Semaphore>> {
+    self
+      .max_connections
+      .map(|max_connections| Arc::new(Semaphore::new(max_connections)))
+  }
+
+  async fn accept_loop<L: Listener>(
+    self: Arc<Self>,
+    listener: L,
+    settings: ListenerSettings,
+    handler: Arc<dyn Handler>,
+    connection_slots: Option<Arc<Semaphore>>,
+  ) -> io::Result<()> {
+    // shared by all connection tasks, settings can't change once the server runs
+    let server = self;
     loop {
-      let (mut stream, _) = listener.accept().await?;
+      // waiting for a free slot before accepting leaves clients queued in the backlog
+      let waited = match (&connection_slots, server.connection_overflow) {
+        (Some(slots), ConnectionOverflow::Wait) => Some(acquire_slot(slots).await),
+        _ => None,
+      };
+      let (stream, peer) = listener.accept().await?;
+      let connection = ConnectionInfo {
+        local_addr: listener.local_addr(&stream),
+        ..ConnectionInfo::new(peer)
+      };
+      let permit = match (waited, &connection_slots) {
+        (Some(permit), _) => Some(permit),
+        (None, Some(slots)) => match Arc::clone(slots).try_acquire_owned() {
+          Ok(permit) => Some(permit),
+          Err(_) => {
+            server.reject_connection(stream, peer, settings.tls);
+            continue;
+          }
+        },
+        (None, None) => None,
+      };
 
+      let server = Arc::clone(&server);
       let handler = Arc::clone(&handler);
+      let settings = settings.clone();
 
       tokio::spawn(async move {
-        // 1KB here is just for demonstration's sake
-        let mut buffer = [0; 1024];
-
-        match stream.read(&mut buffer).await {
-          Ok(_) => {
-            println!("Received a request: {}", String::from_utf8_lossy(&buffer));
-
-            let response = match HttpRequest::try_from(&buffer[..]) {
-              Ok(request) => handler.handle_request(&request),
-              Err(error) => {
-                eprintln!("Failed to parse request: {}", error);
-                HttpResponse::empty_body(StatusCode::BadRequest)
-              }
-            };
-
-            if let Err(e) = response.send(&mut stream).await {
-              eprintln!("Failed to send response: {}", e);
+        // frees the connection's slot once it's closed
+        let _permit = permit;
+        let mut stream = stream;
+        let connection = match settings.proxy_protocol {
+          true => match server.read_proxy_header(&mut stream, connection).await {
+            Some(connection) => connection,
+            None => return,
+          },
+          false => connection,
+        };
+        let peer = connection.peer_addr;
+        if let Some(hook) = &server.on_connection_open {
+          hook(peer);
+        }
+
+        #[cfg(feature = "tls")]
+        let stats = match server.tls.as_ref().filter(|_| settings.tls) {
+          Some(tls) => match tls.acceptor().accept(stream).await {
+            Ok(tls_stream) => {
+              let connection = ConnectionInfo { secure: true, ..connection };
+              server
+                .handle_connection(tls_stream, connection, handler)
+                .await
             }
-          }
-          Err(error) => eprintln!("Failed to read from connection: {}", error),
+            Err(error) => {
+              server.error_log.log(
+                &format!("TLS handshake failed: {}", error),
+                format!("TLS handshake with {} failed: {}", peer, error),
+              );
+              ConnectionStats::default()
+            }
+          },
+          None => server.handle_connection(stream, connection, handler).await,
+        };
+        #[cfg(not(feature = "tls"))]
+        let stats = server.handle_connection(stream, connection, handler).await;
+
+        if let Some(hook) = &server.on_connection_close {
+          hook(peer, &stats);
         }
       });
     }
   }
+
+  /// `connection` with the addresses the PROXY protocol header at the start of `stream`
+  /// names, `None` if there's no valid one in time
+  async fn read_proxy_header<S: AsyncRead + Unpin>(
+    &self,
+    stream: &mut S,
+    connection: ConnectionInfo,
+  ) -> Option<ConnectionInfo> {
+    let error = match timeout(
+      self.header_read_timeout,
+      proxy_protocol::read_header(stream),
+    )
+    .await
+    {
+      Ok(Ok(Some(addresses))) => {
+        return Some(ConnectionInfo {
+          peer_addr: addresses.source,
+          local_addr: Some(addresses.destination),
+          ..connection
+        })
+      }
+      Ok(Ok(None)) => return Some(connection),
+      Ok(Err(error)) => error.to_string(),
+      Err(_) => "Timed out reading PROXY protocol header".to_string(),
+    };
+    self
+      .error_log
+      .log(&error, format!("{} from {}", error, connection.peer_addr));
+    None
+  }
+
+  /// `request` as it came on `connection`, with what trusted proxies forwarded about it
+  fn attach_connection<'r>(
+    &self,
+    request: HttpRequest<'r>,
+    connection: ConnectionInfo,
+  ) -> HttpRequest<'r> {
+    let forwarded = self
+      .trusted_proxies
+      .resolve(connection.peer_addr.ip(), request.header());
+    let request = request.with_connection(connection);
+    match forwarded {
+      Some(forwarded) => request.with_forwarded(forwarded),
+      None => request,
+    }
+  }
+
+  /// `HEAD` requests run the `GET` path, so every handler answers them; the body it returns
+  /// is dropped before sending, see [`HttpResponse::omit_body`]
+  fn served_as_get(request: &HttpRequest<'_>) -> bool {
+    *request.method() == Method::HEAD
+  }
+
+  fn respond(handler: &dyn Handler, request: &HttpRequest<'_>) -> HandlerResult {
+    Self::respond_with_body(handler, request, RequestBody::empty())
+  }
+
+  fn respond_with_body(
+    handler: &dyn Handler,
+    request: &HttpRequest<'_>,
+    body: RequestBody,
+  ) -> HandlerResult {
+    match request.target_form() {
+      TargetForm::Asterisk => Ok(Self::server_options(handler)),
+      TargetForm::Origin if body.is_empty() => handler.handle_request(request),
+      TargetForm::Origin => handler.handle_with_body(request, body),
+    }
+  }
+
+  /// `OPTIONS *` asks about the server rather than a resource, so it never reaches the handler
+  fn server_options(handler: &dyn Handler) -> HttpResponse {
+    router::options(&handler.allowed_methods())
+  }
+
+  /// Enforce the request's [`Limits`] around the handler. `buffer` holds what has been read of
+  /// the request so far, the rest of its body is streamed from `body_source`.
+  async fn respond_within_limits<R: AsyncRead + Unpin>(
+    &self,
+    connection: ConnectionInfo,
+    buffer: &[u8],
+    request: &HttpRequest<'_>,
+    handler: &Arc<dyn Handler>,
+    body_source: &mut R,
+  ) -> HttpResponse {
+    let ResolvedLimits { limits, rate_limit_scope } = self.limits.resolve(request.path());
+    let get_request;
+    let request = match Self::served_as_get(request) {
+      true => {
+        get_request = request.clone().with_method(Method::GET);
+        &get_request
+      }
+      false => request,
+    };
+
+    let content_length = request.header().content_length().ok().flatten();
+    let head_length = codec::head_length(buffer).unwrap_or(buffer.len());
+    let (head, received) = buffer.split_at(head_length);
+    // chunked bodies too large to buffer are still chunked here, and decoded as they stream
+    let chunked = codec::is_chunked(head);
+    // a body left unread on the connection would be taken for the next request
+    let unread_body = |mut response: HttpResponse| {
+      if chunked
+        || content_length.is_some_and(|content_length| content_length > received.len() as u64)
+      {
+        response.close_connection();
+      }
+      response
+    };
+    if let (Some(max_body_size), Some(content_length)) = (limits.max_body_size, content_length) {
+      if content_length > max_body_size {
+        return unread_body(HttpResponse::empty_body(StatusCode::PayloadTooLarge));
+      }
+    }
+
+    if let Some(rate_limit) = &limits.rate_limit {
+      let client_ip = request.client_ip().unwrap_or(connection.peer_addr.ip());
+      if let Err(retry_after) = self
+        .rate_limiter
+        .check(rate_limit_scope, client_ip, rate_limit)
+      {
+        let mut response = HttpResponse::empty_body(StatusCode::TooManyRequests);
+        let retry_after = retry_after.as_secs_f64().ceil() as u64;
+        response.insert_header(HttpResponseHeaderKey::RetryAfter, &retry_after.to_string());
+        return unread_body(response);
+      }
+    }
+
+    let body_length = content_length.filter(|&content_length| content_length > 0);
+    let has_body = body_length.is_some() || chunked;
+    if !has_body && *request.target_form() == TargetForm::Origin {
+      let handled = match panic::catch_unwind(AssertUnwindSafe(|| handler.handle_async(request))) {
+        Ok(handled) => handled,
+        Err(payload) => return self.handler_panicked(request, payload),
+      };
+      if let Some(handled) = handled {
+        let handled = catch_unwind(handled);
+        let handled = match limits.handler_timeout {
+          Some(handler_timeout) => match tokio::time::timeout(handler_timeout, handled).await {
+            Ok(handled) => handled,
+            Err(_) => return self.handler_timed_out(request, handler_timeout),
+          },
+          None => handled.await,
+        };
+        return match handled {
+          Ok(handled) => self.answer(request, handled),
+          Err(payload) => self.handler_panicked(request, payload),
+        };
+      }
+    }
+    if !has_body && limits.handler_timeout.is_none() {
+      return match panic::catch_unwind(AssertUnwindSafe(|| Self::respond(&**handler, request))) {
+        Ok(handled) => self.answer(request, handled),
+        Err(payload) => self.handler_panicked(request, payload),
+      };
+    }
+    let (body, body_sender) = match body_length {
+      Some(body_length) => {
+        let (body, body_sender) = RequestBody::channel(received, body_length);
+        (body, Some(body_sender))
+      }
+      None if chunked => {
+        let max_size = self.chunked_body_max_size(head);
+        let (body, body_sender) = RequestBody::chunked(received, max_size);
+        (body, Some(body_sender))
+      }
+      None => (RequestBody::empty(), None),
+    };
+
+    // the request borrows this connection's buffer, the blocking task parses its own copy
+    let buffer = buffer.to_vec();
+    let (parser_limits, query_limits) = (self.parser_limits, self.query_limits);
+    let handler = Arc::clone(handler);
+    let forwarded = request.forwarded().clone();
+    let request_id = request.request_id().to_string();
+    let task = tokio::task::spawn_blocking(move || {
+      match HttpRequest::parse(&buffer, &parser_limits, &query_limits) {
+        Ok(request) => {
+          let request = match Self::served_as_get(&request) {
+            true => request.with_method(Method::GET),
+            false => request,
+          };
+          let request = request
+            .with_connection(connection)
+            .with_request_id(request_id);
+          let request = match forwarded {
+            Some(forwarded) => request.with_forwarded(forwarded),
+            None => request,
+          };
+          Self::respond_with_body(&*handler, &request, body)
+        }
+        Err(error) => Err(HandlerError::from(error)),
+      }
+    });
+    let handled = async {
+      match limits.handler_timeout {
+        Some(handler_timeout) => tokio::time::timeout(handler_timeout, task)
+          .await
+          .map_err(|_| handler_timeout),
+        None => Ok(task.await),
+      }
+    };
+    let (mut body_complete, mut invalid_body) = (true, None);
+    let handled: Result<Result<HandlerResult, JoinError>, Duration> = match body_sender {
+      // keep reading the body only while the handler runs
+      Some(mut body_sender) => {
+        tokio::pin!(handled);
+        let handled = tokio::select! {
+          handled = &mut handled => handled,
+          _ = body_sender.pump(body_source) => handled.await,
+        };
+        body_complete = body_sender.is_complete();
+        invalid_body = body_sender.invalid_body();
+        handled
+      }
+      None => handled.await,
+    };
+
+    let mut response = match handled {
+      Ok(Ok(handled)) => self.answer(request, handled),
+      Ok(Err(error)) if error.is_panic() => self.handler_panicked(request, error.into_panic()),
+      Ok(Err(error)) => {
+        let message = format!("Handler for {} failed: {}", request.path(), error);
+        self.error_log.log(&message, &message);
+        HttpResponse::empty_body(StatusCode::InternalError)
+      }
+      Err(handler_timeout) => self.handler_timed_out(request, handler_timeout),
+    };
+    // whatever the handler made of its failed read, the client gets the body's status
+    if let Some(error) = invalid_body {
+      tracing::debug!(path = request.path(), %error, "Invalid chunked body");
+      response = HttpResponse::empty_body(error.status_code());
+    }
+    if !body_complete {
+      response.close_connection();
+    }
+    response
+  }
+
+  /// The handler's response, or the one for its error: the error's status, logged along with
+  /// the error's source for failures on the server's side
+  fn answer(&self, request: &HttpRequest<'_>, handled: HandlerResult) -> HttpResponse {
+    let error = match handled {
+      Ok(response) => return response,
+      Err(error) => error,
+    };
+    match error.status_code().is_server_error() {
+      true => self.error_log.log(
+        &format!("Handler for {} failed: {}", request.path(), error.message()),
+        format!(
+          "Handler for {} {} failed: {}",
+          request.method(),
+          request.path(),
+          error
+        ),
+      ),
+      false => tracing::debug!(path = request.path(), error = %error, "Handler refused request"),
+    }
+    HttpResponse::from(error)
+  }
+
+  /// A 500 for a handler that panicked, logged with the panic's message so that a broken
+  /// route shows up in the logs rather than as connection resets
+  fn handler_panicked(
+    &self,
+    request: &HttpRequest<'_>,
+    payload: Box<dyn Any + Send>,
+  ) -> HttpResponse {
+    let panic_message = payload
+      .downcast_ref::<&str>()
+      .copied()
+      .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+      .unwrap_or("non-string panic payload");
+    let client = request.client_ip().map_or_else(
+      || "unknown client".to_string(),
+      |client_ip| client_ip.to_string(),
+    );
+    self.error_log.log(
+      &format!("Handler for {} panicked", request.path()),
+      format!(
+        "Handler for {} {} from {} panicked: {}",
+        request.method(),
+        request.path(),
+        client,
+        panic_message
+      ),
+    );
+    HttpResponse::empty_body(StatusCode::InternalError)
+  }
+
+  fn handler_timed_out(
+    &self,
+    request: &HttpRequest<'_>,
+    handler_timeout: Duration,
+  ) -> HttpResponse {
+    let message = format!(
+      "Handler for {} timed out after {:?}",
+      request.path(),
+      handler_timeout
+    );
+    self.error_log.log(&message, &message);
+    HttpResponse::empty_body(StatusCode::ServiceUnavailable)
+  }
+
+  /// Read from `stream` until `buffer` holds a complete request head, possibly followed by
+  /// the start of its body, which [`Self::respond_within_limits`] streams from there. With an
+  /// `idle_timeout`, the request only starts once its first bytes arrive, and the connection
+  /// counts as closed if none do in time. Returns when the request started along with how
+  /// reading it ended.
+  async fn read_head<S: AsyncRead + AsyncWrite + Unpin>(
+    &self,
+    stream: &mut S,
+    buffer: &mut Vec<u8>,
+    idle_timeout: Option<Duration>,
+  ) -> io::Result<(HeadRead, Instant)> {
+    let mut started_at = (idle_timeout.is_none() || !buffer.is_empty()).then(Instant::now);
+    loop {
+      if codec::request_line_length(buffer) > self.parser_limits.max_request_line_length {
+        let started_at = started_at.unwrap_or_else(Instant::now);
+        return Ok((HeadRead::RequestLineTooLong, started_at));
+      }
+      if let Some(head_length) = codec::head_length(buffer) {
+        let started_at = started_at.unwrap_or_else(Instant::now);
+        // with no telling where its body ends, parsing turns the request down unread
+        if codec::check_framing(&buffer[..head_length]).is_err() {
+          return Ok((HeadRead::Complete, started_at));
+        }
+        if !self.meet_expectation(stream, buffer, head_length).await? {
+          return Ok((HeadRead::ExpectationFailed, started_at));
+        }
+        let body_deadline = self.body_deadline(started_at);
+        let read = if codec::is_chunked(&buffer[..head_length]) {
+          timeout_at(
+            body_deadline,
+            self.read_chunked_body(stream, buffer, head_length),
+          )
+          .await
+        } else {
+          timeout_at(
+            body_deadline,
+            self.read_buffered_body(stream, buffer, head_length),
+          )
+          .await
+          .map(|read| read.map(|()| HeadRead::Complete))
+        };
+        return Ok((read.unwrap_or(Ok(HeadRead::TimedOut))?, started_at));
+      }
+      if buffer.len() >= self.max_head_size {
+        return Ok((HeadRead::TooLarge, started_at.unwrap_or_else(Instant::now)));
+      }
+      buffer.reserve(HEAD_READ_SIZE.min(self.max_head_size - buffer.len()));
+      let read = stream.read_buf(buffer);
+      let bytes_read = match (started_at, idle_timeout) {
+        (Some(started_at), _) => timeout_at(self.head_deadline(started_at), read).await,
+        (None, Some(idle_timeout)) => match timeout(idle_timeout, read).await {
+          Ok(read) => Ok(read),
+          // the client didn't reuse the connection in time
+          Err(_) => return Ok((HeadRead::Closed, Instant::now())),
+        },
+        (None, None) => Ok(read.await),
+      };
+      let Ok(bytes_read) = bytes_read else {
+        return Ok((HeadRead::TimedOut, started_at.unwrap_or_else(Instant::now)));
+      };
+      if bytes_read? == 0 {
+        return Ok((HeadRead::Closed, started_at.unwrap_or_else(Instant::now)));
+      }
+      started_at.get_or_insert_with(Instant::now);
+    }
+  }
+
+  /// Whether the request whose head ends at `head_length` can go on. Clients sending
+  /// `Expect: 100-continue` are told to go ahead with their body by a `100 Continue`, unless
+  /// they started sending it already; a body over [`Limits::max_body_size`], or any other
+  /// expectation, is turned down before the client sends it.
+  async fn meet_expectation<S: AsyncWrite + Unpin>(
+    &self,
+    stream: &mut S,
+    buffer: &[u8],
+    head_length: usize,
+  ) -> io::Result<bool> {
+    let head = &buffer[..head_length];
+    match codec::expectation(head) {
+      None => Ok(true),
+      Some(Expectation::Unsupported) => Ok(false),
+      Some(Expectation::Continue) => {
+        let content_length = codec::content_length(head);
+        if content_length
+          .zip(self.max_body_size(head))
+          .is_some_and(|(content_length, max_body_size)| content_length > max_body_size)
+        {
+          return Ok(false);
+        }
+        let has_body = codec::is_chunked(head) || content_length.is_some_and(|length| length > 0);
+        if has_body && buffer.len() == head_length {
+          stream.write_all(CONTINUE).await?;
+          stream.flush().await?;
+        }
+        Ok(true)
+      }
+    }
+  }
+
+  /// When the head of a request started at `started_at` has to be complete
+  fn head_deadline(&self, started_at: Instant) -> Instant {
+    self.request_deadline(started_at, started_at + self.header_read_timeout)
+  }
+
+  /// When the body of a request started at `started_at`, whose head is complete, has to be
+  fn body_deadline(&self, started_at: Instant) -> Instant {
+    self.request_deadline(started_at, Instant::now() + self.body_read_timeout)
+  }
+
+  /// `deadline`, or the end of the [`Self::request_timeout`] if that comes first
+  fn request_deadline(&self, started_at: Instant, deadline: Instant) -> Instant {
+    match self.request_timeout {
+      Some(request_timeout) => deadline.min(started_at + request_timeout),
+      None => deadline,
+    }
+  }
+
+  /// The [`Limits::max_body_size`] of the request whose head is `head`, or the server-wide
+  /// [`ParserLimits::max_body_size`] if that's lower
+  fn max_body_size(&self, head: &[u8]) -> Option<u64> {
+    let route_max_body_size =
+      codec::request_path(head).and_then(|path| self.limits.resolve(path).limits.max_body_size);
+    match (route_max_body_size, self.parser_limits.max_body_size) {
+      (Some(route), Some(server)) => Some(route.min(server)),
+      (route, server) => route.or(server),
+    }
+  }
+
+  /// [`Self::max_body_size`] for [`ChunkedDecoder`]s
+  fn chunked_body_max_size(&self, head: &[u8]) -> usize {
+    self
+      .max_body_size(head)
+      .and_then(|max_body_size| usize::try_from(max_body_size).ok())
+      .unwrap_or(usize::MAX)
+  }
+
+  /// Read the rest of a `Content-Length` body of up to `max_buffered_body_size` bytes into
+  /// `buffer`, so that [`Handler::handle_request`] sees all of it in [`HttpRequest::body`].
+  /// Bodies beyond [`Limits::max_body_size`] are left unread for the `413` they'll get.
+  async fn read_buffered_body<S: AsyncRead + Unpin>(
+    &self,
+    stream: &mut S,
+    buffer: &mut Vec<u8>,
+    head_length: usize,
+  ) -> io::Result<()> {
+    let head = &buffer[..head_length];
+    let max_body_size = self.max_body_size(head);
+    let Some(content_length) = codec::content_length(head)
+      .filter(|&content_length| max_body_size.is_none_or(|max| content_length <= max))
+      .and_then(|content_length| usize::try_from(content_length).ok())
+      .filter(|&content_length| content_length <= self.max_buffered_body_size)
+    else {
+      return Ok(());
+    };
+    let request_length = head_length + content_length;
+    while buffer.len() < request_length {
+      buffer.reserve(request_length - buffer.len());
+      if stream.read_buf(buffer).await? == 0 {
+        break;
+      }
+    }
+    Ok(())
+  }
+
+  /// Read and decode a chunked body of up to `max_buffered_body_size` bytes, and put the
+  /// request back into `buffer` as if it had been sent with a `Content-Length`. Larger bodies
+  /// are left for [`Self::respond_within_limits`] to stream like those with a
+  /// `Content-Length`, those beyond [`Limits::max_body_size`] are turned down.
+  async fn read_chunked_body<S: AsyncRead + Unpin>(
+    &self,
+    stream: &mut S,
+    buffer: &mut Vec<u8>,
+    head_length: usize,
+  ) -> io::Result<HeadRead> {
+    let mut decoder = ChunkedDecoder::new(self.chunked_body_max_size(&buffer[..head_length]));
+    let mut body = Vec::new();
+    let mut decoded = head_length;
+    loop {
+      match decoder.decode(&buffer[decoded..], &mut body) {
+        Ok(consumed) => decoded += consumed,
+        Err(error) => return Ok(HeadRead::InvalidBody(error)),
+      }
+      if decoder.is_done() {
+        break;
+      }
+      if body.len() > self.max_buffered_body_size {
+        return Ok(HeadRead::Complete);
+      }
+      buffer.reserve(HEAD_READ_SIZE);
+      if stream.read_buf(buffer).await? == 0 {
+        return Ok(HeadRead::InvalidBody(ChunkedError::Truncated));
+      }
+    }
+
+    // any pipelined requests follow the decoded body
+    let following = buffer.split_off(decoded);
+    *buffer = chunked::unchunked_head(&buffer[..head_length], body.len());
+    buffer.extend_from_slice(&body);
+    buffer.extend_from_slice(&following);
+    Ok(HeadRead::Complete)
+  }
+
+  /// Serve requests from `stream` one after another until the client or a response asks to
+  /// close it, it fails, or no further request starts within the keep-alive timeout
+  #[tracing::instrument(
+    name = "connection",
+    skip_all,
+    fields(peer = %connection.peer_addr, secure = connection.secure)
+  )]
+  async fn handle_connection<S: AsyncRead + AsyncWrite + Unpin + Send + 'static>(
+    &self,
+    mut stream: S,
+    connection: ConnectionInfo,
+    handler: Arc<dyn Handler>,
+  ) -> ConnectionStats {
+    let peer = connection.peer_addr;
+    let _connection = self.metrics.track_connection();
+    let opened_at = Instant::now();
+    let mut stats = ConnectionStats::default();
+
+    let mut buffer = Vec::new();
+    loop {
+      // pipelined requests read along with the previous one were counted with it
+      let carried = buffer.len();
+      let head = if stats.requests == 0 {
+        self.read_head(&mut stream, &mut buffer, None).await
+      } else {
+        let _idle = self.metrics.track_idle_connection();
+        self
+          .read_head(&mut stream, &mut buffer, Some(self.keep_alive_timeout))
+          .await
+      };
+      let (head, started_at) = match head {
+        Ok((HeadRead::Closed, _)) if buffer.is_empty() => break,
+        Ok(head) => head,
+        Err(error) => {
+          let message = format!("Failed to read from connection: {}", error);
+          self.error_log.log(&message, &message);
+          break;
+        }
+      };
+
+      stats.bytes_read += buffer.len().saturating_sub(carried) as u64;
+      tracing::debug!(
+        bytes = buffer.len(),
+        pipelined = carried > 0,
+        "Received request"
+      );
+
+      let received_at = Instant::now();
+      let request = match head {
+        HeadRead::TooLarge => Err(ParseError::HeaderFieldsTooLarge(format!(
+          "Request head exceeds {} bytes",
+          self.max_head_size
+        ))),
+        HeadRead::RequestLineTooLong => Err(ParseError::UriTooLong),
+        HeadRead::InvalidBody(error) => Err(ParseError::from(error)),
+        HeadRead::TimedOut => Err(ParseError::Timeout),
+        HeadRead::ExpectationFailed => Err(ParseError::ExpectationFailed),
+        // whatever arrived before the client stopped sending still gets an answer
+        HeadRead::Complete | HeadRead::Closed => {
+          HttpRequest::parse(&buffer, &self.parser_limits, &self.query_limits)
+        }
+      }
+      .map(|request| self.attach_connection(request, connection));
+      let span = Self::request_span(&request);
+      let mut response = match &request {
+        Ok(request) => {
+          stats.requests += 1;
+          let _in_flight = self.metrics.track_request();
+          let mut body_source = DeadlineReader::new(&mut stream, self.body_deadline(started_at));
+          let response = self
+            .respond_within_limits(connection, &buffer, request, &handler, &mut body_source)
+            .instrument(span.clone())
+            .await;
+          match body_source.timed_out() {
+            true => {
+              let mut response = HttpResponse::empty_body(StatusCode::RequestTimeout);
+              response.close_connection();
+              response
+            }
+            false => response,
+          }
+        }
+        Err(error) => {
+          let message = format!("Failed to parse request: {}", error);
+          self.error_log.log(&message, &message);
+          HttpResponse::empty_body(error.status_code())
+        }
+      };
+
+      if request.as_ref().is_ok_and(Self::served_as_get) {
+        response.omit_body();
+      }
+      if let Ok(request) = &request {
+        response.set_version(*request.version());
+      }
+
+      // parse errors leave no telling where the next request would start
+      let keep_alive = match &request {
+        Ok(request) => head == HeadRead::Complete && !Self::client_closes(request),
+        Err(_) => false,
+      };
+      // upgraded connections outlive the response, just not for HTTP
+      let upgrade = response.take_upgrade().filter(|_| keep_alive);
+      if upgrade.is_none() && (!keep_alive || stats.requests >= MAX_REQUESTS_PER_CONNECTION) {
+        response.close_connection();
+      } else if response
+        .http_header()
+        .as_ref()
+        .is_some_and(|header| header.get(HttpResponseHeaderKey::KeepAlive).is_some())
+      {
+        response.insert_header(
+          HttpResponseHeaderKey::KeepAlive,
+          &format!(
+            "timeout={}, max={}",
+            self.keep_alive_timeout.as_secs(),
+            MAX_REQUESTS_PER_CONNECTION - stats.requests
+          ),
+        );
+      }
+      // HTTP/1.0 clients take the connection for closed unless told otherwise
+      let http_1_0 = request
+        .as_ref()
+        .is_ok_and(|request| *request.version() == Version::Http10);
+      if http_1_0 && !response.closes_connection() {
+        response.insert_header(HttpResponseHeaderKey::Connection, "keep-alive");
+      }
+
+      // for clients to quote when reporting a problem
+      if let Ok(request) = &request {
+        response.insert_header(HttpResponseHeaderKey::XRequestId, request.request_id());
+      }
+      if let Some(hsts) = self.hsts.as_ref().filter(|_| connection.secure) {
+        response.insert_header(
+          HttpResponseHeaderKey::StrictTransportSecurity,
+          &hsts.header_value(),
+        );
+      }
+
+      let chunk_size = self.response_chunk_size;
+      let sent = match self.bandwidth_limit {
+        Some(bytes_per_sec) => {
+          let mut stream = ThrottledWriter::new(&mut stream, bytes_per_sec);
+          response.send_in_chunks(&mut stream, chunk_size).await
+        }
+        None => response.send_in_chunks(&mut stream, chunk_size).await,
+      };
+      let sent = match sent {
+        Ok(bytes_written) => {
+          stats.bytes_written += bytes_written as u64;
+          bytes_written as u64
+        }
+        Err(e) => {
+          let message = format!("Failed to send response: {}", e);
+          self.error_log.log(&message, &message);
+          0
+        }
+      };
+
+      span.record("status", response.status_code().as_u16());
+      span.record("duration_ms", received_at.elapsed().as_millis() as u64);
+      tracing::debug!(parent: &span, bytes = sent, "Sent response");
+
+      // the trace ID ties the access log to the caller's and upstream services' logs
+      if let Ok(request) = &request {
+        let entry = AccessLogEntry::new(
+          request.client_ip().unwrap_or(peer.ip()),
+          request,
+          *response.status_code(),
+          sent,
+          received_at.elapsed(),
+        );
+        if entry.sampled(request, self.access_log_sample_rate) {
+          // stdout in the configured format as log processors expect it, not a tracing event
+          println!("{}", entry.format(self.access_log));
+        }
+      }
+
+      let Ok(request) = &request else {
+        break;
+      };
+      if sent == 0 || response.closes_connection() {
+        break;
+      }
+      // the next request starts after this one's body, which may have been read along
+      let consumed = Self::request_length(&buffer, request);
+      buffer.drain(..consumed);
+      if let Some(upgrade) = upgrade {
+        upgrade.run(Upgraded::new(stream, buffer)).await;
+        break;
+      }
+    }
+
+    stats.duration = opened_at.elapsed();
+    stats
+  }
+
+  /// The span a request is handled in, identified like its access log entry; the status and duration
+  /// are recorded once the response has been sent
+  fn request_span(request: &Result<HttpRequest<'_>, ParseError>) -> Span {
+    match request {
+      Ok(request) => tracing::info_span!(
+        "request",
+        request_id = request.request_id(),
+        method = %request.method(),
+        path = request.path(),
+        status = field::Empty,
+        duration_ms = field::Empty,
+      ),
+      Err(_) => tracing::info_span!("request", status = field::Empty, duration_ms = field::Empty),
+    }
+  }
+
+  /// Whether the client asked for the connection to be closed after this request, as HTTP/1.0
+  /// clients do unless they ask for it to be kept alive
+  fn client_closes(request: &HttpRequest<'_>) -> bool {
+    let asks_for = |option: &str| {
+      request
+        .header()
+        .get(HttpRequestHeaderKey::Connection)
+        .is_some_and(|connection| {
+          connection
+            .split(',')
+            .any(|listed| listed.trim().eq_ignore_ascii_case(option))
+        })
+    };
+    match request.version() {
+      Version::Http10 => !asks_for("keep-alive"),
+      Version::Http11 => asks_for("close"),
+    }
+  }
+
+  /// Bytes of `buffer` taken up by `request`, its head and whatever of its body was read
+  /// along with it
+  fn request_length(buffer: &[u8], request: &HttpRequest<'_>) -> usize {
+    let head_length = codec::head_length(buffer).unwrap_or(buffer.len());
+    if codec::is_chunked(&buffer[..head_length]) {
+      // streamed, read no further than its end, which may be in what came with the head
+      let mut decoder = ChunkedDecoder::new(usize::MAX);
+      let body_length = decoder
+        .decode(&buffer[head_length..], &mut Vec::new())
+        .ok()
+        .filter(|_| decoder.is_done());
+      return body_length.map_or(buffer.len(), |body_length| head_length + body_length);
+    }
+    let content_length = request
+      .header()
+      .content_length()
+      .ok()
+      .flatten()
+      .unwrap_or(0);
+    buffer
+      .len()
+      .min(head_length.saturating_add(usize::try_from(content_length).unwrap_or(usize::MAX)))
+  }
+}
+
+/// Resolve to the payload of a panic in `future`, rather than unwinding through the connection
+async fn catch_unwind<F: Future>(future: F) -> Result<F::Output, Box<dyn Any + Send>> {
+  let mut future = std::pin::pin!(future);
+  std::future::poll_fn(|cx| {
+    match panic::catch_unwind(AssertUnwindSafe(|| future.as_mut().poll(cx))) {
+      Ok(poll) => poll.map(Ok),
+      Err(payload) => Poll::Ready(Err(payload)),
+    }
+  })
+  .await
+}
+
+/// A listener on `address` that other sockets can bind too, with the kernel balancing
+/// connections between them
+#[cfg(all(unix, not(target_os = "solaris"), not(target_os = "illumos")))]
+fn reuse_port_listener(address: SocketAddr) -> io::Result<TcpListener> {
+  let socket = match address {
+    SocketAddr::V4(_) => tokio::net::TcpSocket::new_v4()?,
+    SocketAddr::V6(_) => tokio::net::TcpSocket::new_v6()?,
+  };
+  socket.set_reuseaddr(true)?;
+  socket.set_reuseport(true)?;
+  socket.bind(address)?;
+  socket.listen(1024)
+}
+
+#[cfg(not(all(unix, not(target_os = "solaris"), not(target_os = "illumos"))))]
+fn reuse_port_listener(_address: SocketAddr) -> io::Result<TcpListener> {
+  Err(io::Error::new(
+    io::ErrorKind::Unsupported,
+    "SO_REUSEPORT isn't supported on this platform",
+  ))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::http::header::HttpResponseHeaderKey;
+  use expectest::prelude::*;
+  use rstest::*;
+
+  struct PostHandler;
+
+  impl Handler for PostHandler {
+    fn handle_request(&self, _request: &HttpRequest) -> HandlerResult {
+      Ok(HttpResponse::empty_body(StatusCode::NotFound))
+    }
+
+    fn allowed_methods(&self) -> Vec<Method> {
+      vec![Method::GET, Method::POST]
+    }
+  }
+
+  struct SlowHandler;
+
+  impl Handler for SlowHandler {
+    fn handle_request(&self, request: &HttpRequest) -> HandlerResult {
+      if request.path() == "/slow" {
+        std::thread::sleep(Duration::from_millis(200));
+      }
+      Ok(HttpResponse::empty_body(StatusCode::NoContent))
+    }
+  }
+
+  #[rstest]
+  #[case::within_limits(b"GET /fast HTTP/1.1\r\nHost: localhost\r\n\r\n", StatusCode::NoContent)]
+  #[case::body_too_large(
+    b"POST /fast HTTP/1.1\r\nHost: localhost\r\nContent-Length: 2048\r\n\r\n",
+    StatusCode::PayloadTooLarge
+  )]
+  #[case::body_allowed_by_route(
+    b"POST /upload HTTP/1.1\r\nHost: localhost\r\nContent-Length: 2048\r\n\r\n",
+    StatusCode::NoContent
+  )]
+  #[case::handler_timeout(
+    b"GET /slow HTTP/1.1\r\nHost: localhost\r\n\r\n",
+    StatusCode::ServiceUnavailable
+  )]
+  #[tokio::test]
+  async fn test_respond_within_limits(
+    #[case] raw_request: &[u8],
+    #[case] expected: StatusCode,
+  ) -> Result<(), HandlerError> {
+    let limits = Limits {
+      handler_timeout: Some(Duration::from_millis(50)),
+      max_body_size: Some(1024),
+      rate_limit: None,
+    };
+    let upload_limits = Limits { max_body_size: Some(1 << 20), ..Limits::default() };
+    let server = Server::new("127.0.0.1:0".to_string())
+      .limits(limits)
+      .route_limits("/upload", upload_limits);
+    let handler: Arc<dyn Handler> = Arc::new(SlowHandler);
+    let peer = SocketAddr::from(([127, 0, 0, 1], 4000));
+
+    let request = HttpRequest::try_from(raw_request)?;
+    let response = server
+      .respond_within_limits(
+        ConnectionInfo::new(peer),
+        raw_request,
+        &request,
+        &handler,
+        &mut tokio::io::empty(),
+      )
+      .await;
+    expect!(*response.status_code()).to(be_equal_to(expected));
+    Ok(())
+  }
+
+  struct PanickingHandler;
+
+  impl Handler for PanickingHandler {
+    fn handle_request(&self, request: &HttpRequest) -> HandlerResult {
+      panic!("no route for {}", request.path())
+    }
+
+    fn handle_async<'a>(&'a self, request: &'a HttpRequest<'a>) -> Option<HandlerFuture<'a>> {
+      (request.path() == "/async").then(|| -> HandlerFuture<'a> {
+        Box::pin(async {
+          tokio::task::yield_now().await;
+          panic!("async handler failed")
+        })
+      })
+    }
+  }
+
+  #[rstest]
+  #[case::sync(None, b"GET /sync HTTP/1.1\r\nHost: localhost\r\n\r\n")]
+  #[case::blocking(Some(Duration::from_secs(5)), b"GET /sync HTTP/1.1\r\nHost: localhost\r\n\r\n")]
+  #[case::async_handler(None, b"GET /async HTTP/1.1\r\nHost: localhost\r\n\r\n")]
+  #[tokio::test]
+  async fn test_handler_panics(
+    #[case] handler_timeout: Option<Duration>,
+    #[case] raw_request: &[u8],
+  ) -> Result<(), HandlerError> {
+    let server = Server::new("127.0.0.1:0".to_string())
+      .limits(Limits { handler_timeout, ..Limits::default() });
+    let handler: Arc<dyn Handler> = Arc::new(PanickingHandler);
+    let peer = SocketAddr::from(([127, 0, 0, 1], 4000));
+
+    let request = HttpRequest::try_from(raw_request)?;
+    let response = server
+      .respond_within_limits(
+        ConnectionInfo::new(peer),
+        raw_request,
+        &request,
+        &handler,
+        &mut tokio::io::empty(),
+      )
+      .await;
+    expect!(*response.status_code()).to(be_equal_to(StatusCode::InternalError));
+    Ok(())
+  }
+
+  /// Fails with the status in its path
+  struct FailingHandler;
+
+  impl Handler for FailingHandler {
+    fn handle_request(&self, request: &HttpRequest) -> HandlerResult {
+      let status_code = match request.path() {
+        "/missing" => StatusCode::NotFound,
+        _ => StatusCode::InternalError,
+      };
+      let source = io::Error::other("disk on fire");
+      Err(HandlerError::new(status_code, "Lookup failed").with_source(source))
+    }
+  }
+
+  #[rstest]
+  #[case::client_error(b"GET /missing HTTP/1.1\r\nHost: localhost\r\n\r\n", StatusCode::NotFound)]
+  #[case::server_error(
+    b"GET /broken HTTP/1.1\r\nHost: localhost\r\n\r\n",
+    StatusCode::InternalError
+  )]
+  #[tokio::test]
+  async fn test_handler_errors(
+    #[case] raw_request: &[u8],
+    #[case] expected: StatusCode,
+  ) -> Result<(), HandlerError> {
+    let server = Server::new("127.0.0.1:0".to_string());
+    let handler: Arc<dyn Handler> = Arc::new(FailingHandler);
+    let peer = SocketAddr::from(([127, 0, 0, 1], 4000));
+
+    let request = HttpRequest::try_from(raw_request)?;
+    let response = server
+      .respond_within_limits(
+        ConnectionInfo::new(peer),
+        raw_request,
+        &request,
+        &handler,
+        &mut tokio::io::empty(),
+      )
+      .await;
+    expect!(*response.status_code()).to(be_equal_to(expected));
+    // the message and its source stay in the logs
+    expect!(response.body().is_none()).to(be_true());
+    Ok(())
+  }
+
+  #[rstest]
+  #[tokio::test]
+  async fn test_rate_limited_requests() -> Result<(), HandlerError> {
+    let rate_limit = crate::limits::RateLimit::new(1, 60).unwrap();
+    let server = Server::new("127.0.0.1:0".to_string())
+      .limits(Limits { rate_limit: Some(rate_limit), ..Limits::default() });
+    let handler: Arc<dyn Handler> = Arc::new(SlowHandler);
+    let peer = SocketAddr::from(([127, 0, 0, 1], 4000));
+    let raw_request = &b"GET /fast HTTP/1.1\r\nHost: localhost\r\n\r\n"[..];
+    let request = HttpRequest::try_from(raw_request)?;
+
+    let response = server
+      .respond_within_limits(
+        ConnectionInfo::new(peer),
+        raw_request,
+        &request,
+        &handler,
+        &mut tokio::io::empty(),
+      )
+      .await;
+    expect!(*response.status_code()).to(be_equal_to(StatusCode::NoContent));
+
+    let response = server
+      .respond_within_limits(
+        ConnectionInfo::new(peer),
+        raw_request,
+        &request,
+        &handler,
+        &mut tokio::io::empty(),
+      )
+      .await;
+    expect!(*response.status_code()).to(be_equal_to(StatusCode::TooManyRequests));
+    let header = response.http_header().as_ref().unwrap();
+    expect!(header.get(HttpResponseHeaderKey::RetryAfter)).to(be_some().value("60"));
+    Ok(())
+  }
+
+  #[rstest]
+  fn test_server_wide_options() -> Result<(), HandlerError> {
+    let request = HttpRequest::try_from(&b"OPTIONS * HTTP/1.1\r\nHost: localhost\r\n\r\n"[..])?;
+    let response = Server::respond(&PostHandler, &request)?;
+
+    expect!(*response.status_code()).to(be_equal_to(StatusCode::Ok));
+    let header = response.http_header().as_ref().unwrap();
+    expect!(header.get(HttpResponseHeaderKey::Allow))
+      .to(be_some().value("GET, HEAD, POST, OPTIONS"));
+    Ok(())
+  }
+
+  struct PassThrough;
+
+  impl Middleware for PassThrough {
+    fn handle(&self, request: &HttpRequest, next: crate::middleware::Next<'_>) -> HandlerResult {
+      next.run(request)
+    }
+  }
+
+  #[rstest]
+  fn test_describe_routes_and_limits() {
+    let upload_limits = Limits { max_body_size: Some(1 << 20), ..Limits::default() };
+    let server = Server::new("127.0.0.1:0".to_string())
+      .route_limits("/upload", upload_limits)
+      .layer(Arc::new(PassThrough));
+
+    let description = server.describe(&PostHandler);
+    expect!(description["address"].as_str()).to(be_some().value("127.0.0.1:0"));
+    expect!(description["allowed_methods"].clone()).to(be_equal_to(json!(["GET", "POST"])));
+    expect!(description["limits"]["routes"][0]["prefix"].as_str()).to(be_some().value("/upload"));
+    expect!(description["limits"]["routes"][0]["limits"]["max_body_size"].as_u64())
+      .to(be_some().value(1 << 20));
+    expect!(description["handler"]["handler"].as_str()).to(be_some());
+    expect!(description["middleware"][0]["middleware"].as_str()).to(be_some());
+
+    let layered = server.layered(Arc::new(PostHandler));
+    expect!(layered.describe()["handler"].as_str()).to(be_some().value("Layered"));
+  }
+
+  struct UploadHandler;
+
+  impl Handler for UploadHandler {
+    fn handle_request(&self, _request: &HttpRequest) -> HandlerResult {
+      Ok(HttpResponse::empty_body(StatusCode::NoContent))
+    }
+
+    fn handle_with_body(&self, _request: &HttpRequest, mut body: RequestBody) -> HandlerResult {
+      let mut received = Vec::new();
+      std::io::Read::read_to_end(&mut body, &mut received)
+        .map_err(|error| HandlerError::bad_request("Incomplete body").with_source(error))?;
+      Ok(HttpResponse::new(
+        StatusCode::Ok,
+        Some(received.len().to_string().into_bytes()),
+        None,
+      ))
+    }
+  }
+
+  #[rstest]
+  #[case::streamed(100_000, 100_000, StatusCode::Ok)]
+  #[case::client_disconnected(100_000, 50_000, StatusCode::BadRequest)]
+  #[tokio::test]
+  async fn test_stream_request_body(
+    #[case] content_length: usize,
+    #[case] sent: usize,
+    #[case] expected: StatusCode,
+  ) -> Result<(), HandlerError> {
+    let server = Server::new("127.0.0.1:0".to_string());
+    let handler: Arc<dyn Handler> = Arc::new(UploadHandler);
+    let peer = SocketAddr::from(([127, 0, 0, 1], 4000));
+    let head = format!(
+      "POST /upload HTTP/1.1\r\nHost: localhost\r\nContent-Length: {}\r\n\r\n",
+      content_length
+    );
+    let buffer = [head.as_bytes(), &[b'x'; 100][..]].concat();
+    let request = HttpRequest::try_from(&buffer[..])?;
+    let rest = vec![b'x'; sent - 100];
+
+    let response = server
+      .respond_within_limits(
+        ConnectionInfo::new(peer),
+        &buffer,
+        &request,
+        &handler,
+        &mut &rest[..],
+      )
+      .await;
+    expect!(*response.status_code()).to(be_equal_to(expected));
+    if expected == StatusCode::Ok {
+      let expected_body = content_length.to_string().into_bytes();
+      expect!(response.body().as_deref()).to(be_some().value(expected_body.as_slice()));
+    }
+    Ok(())
+  }
+
+  #[rstest]
+  #[case::streamed("5\r\nhello\r\n7;ext=1\r\n, world\r\n0\r\n\r\n", None, StatusCode::Ok)]
+  #[case::too_large(
+    "5\r\nhello\r\n7\r\n, world\r\n0\r\n\r\n",
+    Some(8),
+    StatusCode::PayloadTooLarge
+  )]
+  #[case::malformed("5\r\nhello\r\nzz\r\n", None, StatusCode::BadRequest)]
+  #[case::client_disconnected("5\r\nhello\r\n7\r\n, wo", None, StatusCode::BadRequest)]
+  #[tokio::test]
+  async fn test_stream_chunked_request_body(
+    #[case] body: &str,
+    #[case] max_body_size: Option<u64>,
+    #[case] expected: StatusCode,
+  ) -> Result<(), HandlerError> {
+    let upload_limits = Limits { max_body_size, ..Limits::default() };
+    let server = Server::new("127.0.0.1:0".to_string()).route_limits("/upload", upload_limits);
+    let handler: Arc<dyn Handler> = Arc::new(UploadHandler);
+    let peer = SocketAddr::from(([127, 0, 0, 1], 4000));
+    let head = "POST /upload HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked\r\n\r\n";
+    // the start of the body came along with the head, the rest follows on the connection
+    let buffer = [head.as_bytes(), &body.as_bytes()[..4]].concat();
+    let request = HttpRequest::try_from(&buffer[..])?;
+    let next_request = b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n";
+    let rest = [&body.as_bytes()[4..], &next_request[..]].concat();
+    let mut rest = match expected {
+      StatusCode::Ok => &rest[..],
+      _ => &body.as_bytes()[4..],
+    };
+
+    let response = server
+      .respond_within_limits(
+        ConnectionInfo::new(peer),
+        &buffer,
+        &request,
+        &handler,
+        &mut rest,
+      )
+      .await;
+    expect!(*response.status_code()).to(be_equal_to(expected));
+    if expected == StatusCode::Ok {
+      expect!(response.body().as_deref()).to(be_some().value(&b"12"[..]));
+      // read no further than the body
+      expect!(rest).to(be_equal_to(&next_request[..]));
+    }
+    Ok(())
+  }
+
+  #[rstest]
+  #[tokio::test]
+  async fn test_pipelined_request_after_streamed_chunked_body() -> io::Result<()> {
+    use tokio::io::AsyncWriteExt;
+
+    let server = Server::new("127.0.0.1:0".to_string()).max_buffered_body_size(4);
+    let peer = SocketAddr::from(([127, 0, 0, 1], 4000));
+    let (client, connection) = tokio::io::duplex(16);
+    let connection = tokio::spawn(async move {
+      server
+        .handle_connection(
+          connection,
+          ConnectionInfo::new(peer),
+          Arc::new(UploadHandler),
+        )
+        .await
+    });
+
+    let (mut reader, mut writer) = tokio::io::split(client);
+    let requests = "POST /upload HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked\r\n\r\n\
+      5\r\nhello\r\n7\r\n, world\r\n0\r\n\r\n\
+      POST /upload HTTP/1.1\r\nHost: localhost\r\nContent-Length: 3\r\nConnection: close\r\n\r\nabc";
+    let writing = tokio::spawn(async move { writer.write_all(requests.as_bytes()).await });
+    let mut responses = String::new();
+    reader.read_to_string(&mut responses).await?;
+    writing.await??;
+
+    connection.await?;
+    let statuses = responses.matches("HTTP/1.1 200 Ok\r\n").count();
+    expect!(statuses).to(be_equal_to(2));
+    expect!(responses.contains("\r\n\r\n12HTTP/1.1")).to(be_true());
+    expect!(responses.ends_with("\r\n\r\n3")).to(be_true());
+    Ok(())
+  }
+
+  #[rstest]
+  #[case::split_across_reads(3_000, DEFAULT_MAX_HEAD_SIZE, "HTTP/1.1 200 Ok\r\n")]
+  #[case::too_large(3_000, 2_048, "HTTP/1.1 431 Request Header Fields Too Large\r\n")]
+  #[tokio::test]
+  async fn test_read_request_head(
+    #[case] header_length: usize,
+    #[case] max_head_size: usize,
+    #[case] expected: &str,
+  ) -> io::Result<()> {
+    use tokio::io::AsyncWriteExt;
+
+    let server = Server::new("127.0.0.1:0".to_string()).max_head_size(max_head_size);
+    let peer = SocketAddr::from(([127, 0, 0, 1], 4000));
+    let (client, connection) = tokio::io::duplex(512);
+    let connection = tokio::spawn(async move {
+      server
+        .handle_connection(
+          connection,
+          ConnectionInfo::new(peer),
+          Arc::new(UploadHandler),
+        )
+        .await
+    });
+
+    // header values are capped, spread the padding over several
+    let padding = (0..header_length / 100)
+      .map(|i| format!("X-Padding-{}: {}\r\n", i, "p".repeat(100)))
+      .collect::<String>();
+    let head = format!(
+      "POST /upload HTTP/1.1\r\n{}Content-Length: 5\r\nConnection: close\r\n\r\n",
+      padding
+    );
+    // the 512 byte pipe hands the head over in several reads
+    let (mut reader, mut writer) = tokio::io::split(client);
+    tokio::spawn(async move {
+      // fails once a rejected request's connection is closed
+      let _ = writer.write_all(head.as_bytes()).await;
+      let _ = writer.write_all(b"hello").await;
+    });
+    let mut response = String::new();
+    reader.read_to_string(&mut response).await?;
+
+    let stats = connection.await?;
+    expect!(response.starts_with(expected)).to(be_true());
+    if max_head_size > header_length {
+      expect!(response.ends_with("\r\n\r\n5")).to(be_true());
+      expect!(stats.bytes_read > header_length as u64).to(be_true());
+    } else {
+      expect!(stats.requests).to(be_equal_to(0));
+    }
+    Ok(())
+  }
+
+  #[rstest]
+  #[tokio::test]
+  async fn test_request_line_too_long() -> io::Result<()> {
+    use tokio::io::AsyncWriteExt;
+
+    let server = Server::new("127.0.0.1:0".to_string())
+      .parser_limits(ParserLimits { max_request_line_length: 1_024, ..ParserLimits::default() });
+    let peer = SocketAddr::from(([127, 0, 0, 1], 4000));
+    let (client, connection) = tokio::io::duplex(512);
+    let connection = tokio::spawn(async move {
+      server
+        .handle_connection(connection, ConnectionInfo::new(peer), Arc::new(GetHandler))
+        .await
+    });
+
+    // answered before the line, let alone the head, is complete
+    let (mut reader, mut writer) = tokio::io::split(client);
+    tokio::spawn(async move {
+      let target = format!("GET /{}", "a".repeat(4_096));
+      let _ = writer.write_all(target.as_bytes()).await;
+    });
+    let mut response = String::new();
+    reader.read_to_string(&mut response).await?;
+
+    let stats = connection.await?;
+    expect!(stats.requests).to(be_equal_to(0));
+    expect!(response.starts_with("HTTP/1.1 414 URI Too Long\r\n")).to(be_true());
+    Ok(())
+  }
+
+  #[rstest]
+  #[case::nothing_sent("", 100, 100, None)]
+  #[case::slow_head("POST /upload HTTP/1.1\r\nHost: localhost\r\n", 100, 100, None)]
+  #[case::slow_body(
+    "POST /upload HTTP/1.1\r\nHost: localhost\r\nContent-Length: 10\r\n\r\nhello",
+    100,
+    100,
+    None
+  )]
+  #[case::slow_streamed_body(
+    "POST /upload HTTP/1.1\r\nHost: localhost\r\nContent-Length: 100000\r\n\r\nhello",
+    100,
+    100,
+    None
+  )]
+  #[case::slow_request(
+    "POST /upload HTTP/1.1\r\nHost: localhost\r\nContent-Length: 10\r\n\r\nhello",
+    10_000,
+    10_000,
+    Some(100)
+  )]
+  // real time, paused time doesn't advance while a blocking handler waits for the body
+  #[tokio::test]
+  async fn test_read_timeouts(
+    #[case] sent: &'static str,
+    #[case] header_read_millis: u64,
+    #[case] body_read_millis: u64,
+    #[case] request_millis: Option<u64>,
+  ) -> io::Result<()> {
+    use tokio::io::AsyncWriteExt;
+
+    let mut server = Server::new("127.0.0.1:0".to_string())
+      .header_read_timeout(Duration::from_millis(header_read_millis))
+      .body_read_timeout(Duration::from_millis(body_read_millis));
+    if let Some(request_millis) = request_millis {
+      server = server.request_timeout(Duration::from_millis(request_millis));
+    }
+    let peer = SocketAddr::from(([127, 0, 0, 1], 4000));
+    let (client, connection) = tokio::io::duplex(512);
+    let connection = tokio::spawn(async move {
+      server
+        .handle_connection(
+          connection,
+          ConnectionInfo::new(peer),
+          Arc::new(UploadHandler),
+        )
+        .await
+    });
+
+    // the client never sends the rest, but keeps the connection open
+    let (mut reader, mut writer) = tokio::io::split(client);
+    writer.write_all(sent.as_bytes()).await?;
+    let mut response = String::new();
+    reader.read_to_string(&mut response).await?;
+
+    connection.await?;
+    expect!(response.starts_with("HTTP/1.1 408 Request Timeout\r\n")).to(be_true());
+    expect!(response.contains("Connection: close\r\n")).to(be_true());
+    drop(writer);
+    Ok(())
+  }
+
+  /// Answers with the body it found in the request, without reading any from the connection
+  struct BodyHandler;
+
+  impl Handler for BodyHandler {
+    fn handle_request(&self, request: &HttpRequest) -> HandlerResult {
+      Ok(HttpResponse::new(
+        StatusCode::Ok,
+        Some(request.body().to_vec()),
+        None,
+      ))
+    }
+  }
+
+  #[rstest]
+  #[tokio::test]
+  async fn test_buffer_request_body() -> io::Result<()> {
+    use tokio::io::AsyncWriteExt;
+
+    let server = Server::new("127.0.0.1:0".to_string());
+    let peer = SocketAddr::from(([127, 0, 0, 1], 4000));
+    let (client, connection) = tokio::io::duplex(64);
+    let connection = tokio::spawn(async move {
+      server
+        .handle_connection(connection, ConnectionInfo::new(peer), Arc::new(BodyHandler))
+        .await
+    });
+
+    let body = "b".repeat(500);
+    let (mut reader, mut writer) = tokio::io::split(client);
+    let request = format!(
+      "POST /form HTTP/1.1\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+      body.len(),
+      body
+    );
+    // the 64 byte pipe makes the body arrive well after the head
+    tokio::spawn(async move { writer.write_all(request.as_bytes()).await });
+    let mut response = String::new();
+    reader.read_to_string(&mut response).await?;
+
+    connection.await?;
+    expect!(response.starts_with("HTTP/1.1 200 Ok\r\n")).to(be_true());
+    expect!(response.ends_with(&format!("\r\n\r\n{}", body))).to(be_true());
+    Ok(())
+  }
+
+  #[rstest]
+  #[tokio::test]
+  async fn test_pipelined_requests() -> io::Result<()> {
+    use tokio::io::AsyncWriteExt;
+
+    let server = Server::new("127.0.0.1:0".to_string());
+    let peer = SocketAddr::from(([127, 0, 0, 1], 4000));
+    let (mut client, connection) = tokio::io::duplex(4096);
+    let connection = tokio::spawn(async move {
+      server
+        .handle_connection(connection, ConnectionInfo::new(peer), Arc::new(BodyHandler))
+        .await
+    });
+
+    // all in one read, each answered in the order sent
+    let requests = "POST /a HTTP/1.1\r\nHost: localhost\r\nContent-Length: 5\r\n\r\nfirst\
+      POST /b HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked\r\n\r\n6\r\nsecond\r\n0\r\n\r\n\
+      POST /c HTTP/1.1\r\nHost: localhost\r\nContent-Length: 5\r\nConnection: close\r\n\r\nthird";
+    client.write_all(requests.as_bytes()).await?;
+    let mut response = String::new();
+    client.read_to_string(&mut response).await?;
+
+    let stats = connection.await?;
+    expect!(stats.requests).to(be_equal_to(3));
+    expect!(stats.bytes_read).to(be_equal_to(requests.len() as u64));
+    let bodies: Vec<&str> = response
+      .split("HTTP/1.1 200 Ok\r\n")
+      .skip(1)
+      .filter_map(|response| response.split_once("\r\n\r\n"))
+      .map(|(_, body)| body)
+      .collect();
+    expect!(bodies).to(be_equal_to(vec!["first", "second", "third"]));
+    Ok(())
+  }
+
+  /// Answers `GET` only
+  struct GetHandler;
+
+  impl Handler for GetHandler {
+    fn handle_request(&self, request: &HttpRequest) -> HandlerResult {
+      match request.method() {
+        Method::GET => Ok(HttpResponse::new(
+          StatusCode::Ok,
+          Some(b"hello".to_vec()),
+          None,
+        )),
+        _ => Ok(HttpResponse::empty_body(StatusCode::MethodNotAllowed)),
+      }
+    }
+  }
+
+  #[rstest]
+  #[tokio::test]
+  async fn test_head_runs_get() -> io::Result<()> {
+    use tokio::io::AsyncWriteExt;
+
+    let server = Server::new("127.0.0.1:0".to_string());
+    let peer = SocketAddr::from(([127, 0, 0, 1], 4000));
+    let (client, connection) = tokio::io::duplex(512);
+    let connection = tokio::spawn(async move {
+      server
+        .handle_connection(connection, ConnectionInfo::new(peer), Arc::new(GetHandler))
+        .await
+    });
+
+    let (mut reader, mut writer) = tokio::io::split(client);
+    // the body left out of the first response would be taken for the second one's start
+    let requests = "HEAD / HTTP/1.1\r\nHost: localhost\r\n\r\n\
+      GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n";
+    tokio::spawn(async move { writer.write_all(requests.as_bytes()).await });
+    let mut response = String::new();
+    reader.read_to_string(&mut response).await?;
+
+    connection.await?;
+    let (head_response, get_response) = response.split_once("\r\n\r\n").unwrap();
+    expect!(head_response.starts_with("HTTP/1.1 200 Ok\r\n")).to(be_true());
+    expect!(head_response.contains("Content-Length: 5")).to(be_true());
+    expect!(get_response.starts_with("HTTP/1.1 200 Ok\r\n")).to(be_true());
+    expect!(get_response.ends_with("\r\n\r\nhello")).to(be_true());
+    Ok(())
+  }
+
+  #[rstest]
+  #[tokio::test]
+  async fn test_http_1_0_keep_alive() -> io::Result<()> {
+    use tokio::io::AsyncWriteExt;
+
+    let server = Server::new("127.0.0.1:0".to_string());
+    let peer = SocketAddr::from(([127, 0, 0, 1], 4000));
+    let (mut client, connection) = tokio::io::duplex(4096);
+    let connection = tokio::spawn(async move {
+      server
+        .handle_connection(connection, ConnectionInfo::new(peer), Arc::new(GetHandler))
+        .await
+    });
+
+    // the second request closes the connection by leaving out Connection: keep-alive
+    let requests = "GET / HTTP/1.0\r\nConnection: keep-alive\r\n\r\nGET / HTTP/1.0\r\n\r\n";
+    client.write_all(requests.as_bytes()).await?;
+    let mut response = String::new();
+    client.read_to_string(&mut response).await?;
+
+    let stats = connection.await?;
+    expect!(stats.requests).to(be_equal_to(2));
+    let (first, second) = response.split_once("hello").unwrap();
+    expect!(first.starts_with("HTTP/1.0 200 Ok\r\n")).to(be_true());
+    expect!(first.contains("Connection: keep-alive\r\n")).to(be_true());
+    expect!(second.starts_with("HTTP/1.0 200 Ok\r\n")).to(be_true());
+    expect!(second.contains("Connection: close\r\n")).to(be_true());
+    Ok(())
+  }
+
+  #[rstest]
+  #[tokio::test]
+  async fn test_echo_request_id() -> io::Result<()> {
+    use tokio::io::AsyncWriteExt;
+
+    let server = Server::new("127.0.0.1:0".to_string());
+    let peer = SocketAddr::from(([127, 0, 0, 1], 4000));
+    let (mut client, connection) = tokio::io::duplex(512);
+    let connection = tokio::spawn(async move {
+      server
+        .handle_connection(connection, ConnectionInfo::new(peer), Arc::new(GetHandler))
+        .await
+    });
+
+    let requests = "GET / HTTP/1.1\r\nHost: localhost\r\nX-Request-Id: checkout-42\r\n\r\n\
+      GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n";
+    client.write_all(requests.as_bytes()).await?;
+    let mut response = String::new();
+    client.read_to_string(&mut response).await?;
+
+    connection.await?;
+    let request_ids: Vec<&str> = response
+      .lines()
+      .filter_map(|line| line.strip_prefix("X-Request-Id: "))
+      .collect();
+    expect!(request_ids.len()).to(be_equal_to(2));
+    expect!(request_ids[0]).to(be_equal_to("checkout-42"));
+    // generated for the request that came without one
+    expect!(request_ids[1].len()).to(be_equal_to(32));
+    Ok(())
+  }
+
+  /// Answers with the ID the request reached it under
+  struct RequestIdHandler;
+
+  impl Handler for RequestIdHandler {
+    fn handle_request(&self, request: &HttpRequest) -> HandlerResult {
+      Ok(HttpResponse::new(
+        StatusCode::Ok,
+        Some(request.request_id().as_bytes().to_vec()),
+        None,
+      ))
+    }
+  }
+
+  #[rstest]
+  #[tokio::test]
+  async fn test_request_id_reaches_body_handler() -> io::Result<()> {
+    use tokio::io::AsyncWriteExt;
+
+    let server = Server::new("127.0.0.1:0".to_string());
+    let peer = SocketAddr::from(([127, 0, 0, 1], 4000));
+    let (mut client, connection) = tokio::io::duplex(4096);
+    let connection = tokio::spawn(async move {
+      server
+        .handle_connection(
+          connection,
+          ConnectionInfo::new(peer),
+          Arc::new(RequestIdHandler),
+        )
+        .await
+    });
+
+    // a generated ID, as the body sends the request through a blocking task of its own
+    let request = "POST /form HTTP/1.1\r\nHost: localhost\r\nContent-Length: 5\r\nConnection: close\r\n\r\nhello";
+    client.write_all(request.as_bytes()).await?;
+    let mut response = String::new();
+    client.read_to_string(&mut response).await?;
+
+    connection.await?;
+    let echoed = response
+      .lines()
+      .find_map(|line| line.strip_prefix("X-Request-Id: "))
+      .unwrap();
+    let (_, body) = response.split_once("\r\n\r\n").unwrap();
+    expect!(echoed.len()).to(be_equal_to(32));
+    expect!(body).to(be_equal_to(echoed));
+    Ok(())
+  }
+
+  #[rstest]
+  #[case::decoded(
+    64,
+    "5\r\nhello\r\n7;ext=1\r\n, world\r\n0\r\n\r\n",
+    "HTTP/1.1 200 Ok\r\n",
+    "\r\n\r\nhello, world"
+  )]
+  #[case::malformed(64, "5\r\nhello\r\nzz\r\n", "HTTP/1.1 400 Bad Request\r\n", "\r\n\r\n")]
+  #[tokio::test]
+  async fn test_decode_chunked_body(
+    #[case] max_buffered_body_size: usize,
+    #[case] body: &str,
+    #[case] status_line: &str,
+    #[case] ending: &str,
+  ) -> io::Result<()> {
+    use tokio::io::AsyncWriteExt;
+
+    let server =
+      Server::new("127.0.0.1:0".to_string()).max_buffered_body_size(max_buffered_body_size);
+    let peer = SocketAddr::from(([127, 0, 0, 1], 4000));
+    let (mut client, connection) = tokio::io::duplex(4096);
+    let connection = tokio::spawn(async move {
+      server
+        .handle_connection(connection, ConnectionInfo::new(peer), Arc::new(BodyHandler))
+        .await
+    });
+
+    client
+      .write_all(
+        format!(
+          "POST /form HTTP/1.1\r\nTransfer-Encoding: chunked\r\nConnection: close\r\n\r\n{}",
+          body
+        )
+        .as_bytes(),
+      )
+      .await?;
+    let mut response = String::new();
+    client.read_to_string(&mut response).await?;
+
+    connection.await?;
+    expect!(response.starts_with(status_line)).to(be_true());
+    expect!(response.ends_with(ending)).to(be_true());
+    Ok(())
+  }
+
+  #[rstest]
+  #[case::chunked_within_limit(
+    "Transfer-Encoding: chunked\r\n\r\n5\r\nhello\r\n0\r\n\r\n",
+    "HTTP/1.1 200 Ok\r\n"
+  )]
+  #[case::chunked_too_large(
+    "Transfer-Encoding: chunked\r\n\r\n5\r\nhello\r\n7\r\n, world\r\n0\r\n\r\n",
+    "HTTP/1.1 413 Payload Too Large\r\n"
+  )]
+  // rejected without waiting for a body that would only be thrown away
+  #[case::content_length_too_large(
+    "Content-Length: 100\r\n\r\n",
+    "HTTP/1.1 413 Payload Too Large\r\n"
+  )]
+  #[tokio::test]
+  async fn test_max_body_size_while_reading(
+    #[case] rest: &str,
+    #[case] status_line: &str,
+  ) -> io::Result<()> {
+    use tokio::io::AsyncWriteExt;
+
+    let upload_limits = Limits { max_body_size: Some(8), ..Limits::default() };
+    let server = Server::new("127.0.0.1:0".to_string()).route_limits("/upload", upload_limits);
+    let peer = SocketAddr::from(([127, 0, 0, 1], 4000));
+    let (mut client, connection) = tokio::io::duplex(4096);
+    let connection = tokio::spawn(async move {
+      server
+        .handle_connection(connection, ConnectionInfo::new(peer), Arc::new(BodyHandler))
+        .await
+    });
+
+    let head = "POST /upload HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n";
+    client
+      .write_all(format!("{}{}", head, rest).as_bytes())
+      .await?;
+    let mut response = String::new();
+    client.read_to_string(&mut response).await?;
+
+    connection.await?;
+    expect!(response.starts_with(status_line)).to(be_true());
+    Ok(())
+  }
+
+  #[rstest]
+  #[tokio::test]
+  async fn test_expect_continue() -> io::Result<()> {
+    use tokio::io::AsyncWriteExt;
+
+    let server = Server::new("127.0.0.1:0".to_string());
+    let peer = SocketAddr::from(([127, 0, 0, 1], 4000));
+    let (mut client, connection) = tokio::io::duplex(4096);
+    let connection = tokio::spawn(async move {
+      server
+        .handle_connection(connection, ConnectionInfo::new(peer), Arc::new(BodyHandler))
+        .await
+    });
+
+    client
+      .write_all(b"POST /form HTTP/1.1\r\nContent-Length: 5\r\nExpect: 100-continue\r\nConnection: close\r\n\r\n")
+      .await?;
+    // the body only follows once the server asked for it
+    let mut interim = vec![0; CONTINUE.len()];
+    client.read_exact(&mut interim).await?;
+    expect!(interim.as_slice()).to(be_equal_to(CONTINUE));
+    client.write_all(b"hello").await?;
+    let mut response = String::new();
+    client.read_to_string(&mut response).await?;
+
+    connection.await?;
+    expect!(response.starts_with("HTTP/1.1 200 Ok\r\n")).to(be_true());
+    expect!(response.ends_with("\r\n\r\nhello")).to(be_true());
+    Ok(())
+  }
+
+  #[rstest]
+  #[case::body_too_large("Content-Length: 100\r\nExpect: 100-continue\r\n")]
+  #[case::unsupported("Content-Length: 5\r\nExpect: 200-ok\r\n")]
+  #[tokio::test]
+  async fn test_expectation_failed(#[case] headers: &str) -> io::Result<()> {
+    use tokio::io::AsyncWriteExt;
+
+    let upload_limits = Limits { max_body_size: Some(8), ..Limits::default() };
+    let server = Server::new("127.0.0.1:0".to_string()).route_limits("/upload", upload_limits);
+    let peer = SocketAddr::from(([127, 0, 0, 1], 4000));
+    let (mut client, connection) = tokio::io::duplex(4096);
+    let connection = tokio::spawn(async move {
+      server
+        .handle_connection(connection, ConnectionInfo::new(peer), Arc::new(BodyHandler))
+        .await
+    });
+
+    client
+      .write_all(format!("POST /upload HTTP/1.1\r\n{}\r\n", headers).as_bytes())
+      .await?;
+    let mut response = String::new();
+    client.read_to_string(&mut response).await?;
+
+    connection.await?;
+    expect!(response.starts_with("HTTP/1.1 417 Expectation Failed\r\n")).to(be_true());
+    expect!(response.contains("100 Continue")).to(be_false());
+    Ok(())
+  }
+
+  #[rstest]
+  #[tokio::test(start_paused = true)]
+  async fn test_keep_connection_alive() -> io::Result<()> {
+    use tokio::io::AsyncWriteExt;
+
+    let server = Arc::new(Server::new("127.0.0.1:0".to_string()));
+    let peer = SocketAddr::from(([127, 0, 0, 1], 4000));
+    let (mut client, connection) = tokio::io::duplex(4096);
+    let connection = tokio::spawn({
+      let server = Arc::clone(&server);
+      async move {
+        server
+          .handle_connection(
+            connection,
+            ConnectionInfo::new(peer),
+            Arc::new(UploadHandler),
+          )
+          .await
+      }
+    });
+
+    // the second request follows right after the first one's body
+    client
+      .write_all(b"POST /upload HTTP/1.1\r\nContent-Length: 3\r\n\r\nabcPOST /upload HTTP/1.1\r\nContent-Length: 2\r\n\r\nde")
+      .await?;
+    let mut response = vec![0; 4096];
+    let mut received = 0;
+    while !String::from_utf8_lossy(&response[..received]).ends_with("\r\n\r\n2") {
+      received += client.read(&mut response[received..]).await?;
+    }
+    let responses = String::from_utf8_lossy(&response[..received]).to_string();
+    expect!(responses.matches("HTTP/1.1 200 Ok").count()).to(be_equal_to(2));
+    expect!(responses.contains("Connection: close")).to(be_false());
+
+    // idle until the keep-alive timeout closes it
+    tokio::time::sleep(DEFAULT_KEEP_ALIVE_TIMEOUT / 2).await;
+    expect!(server.metrics().idle_connections()).to(be_equal_to(1));
+    let stats = connection.await?;
+    expect!(stats.requests).to(be_equal_to(2));
+    expect!(server.metrics().idle_connections()).to(be_equal_to(0));
+    expect!(client.read(&mut response).await?).to(be_equal_to(0));
+    Ok(())
+  }
+
+  #[rstest]
+  #[tokio::test]
+  async fn test_upgrade_connection() -> io::Result<()> {
+    use crate::http::websocket::{Frame, Opcode};
+    use crate::websocket::WebSocketHandler;
+    use tokio::io::AsyncWriteExt;
+
+    let server = Server::new("127.0.0.1:0".to_string());
+    let peer = SocketAddr::from(([127, 0, 0, 1], 4000));
+    let (mut client, connection) = tokio::io::duplex(4096);
+    let echo = WebSocketHandler::new(|mut socket| async move {
+      while let Ok(Some(message)) = socket.recv().await {
+        let _ = socket.send(message).await;
+      }
+    });
+    let connection = tokio::spawn(async move {
+      server
+        .handle_connection(connection, ConnectionInfo::new(peer), Arc::new(echo))
+        .await
+    });
+
+    // the first message follows right after the handshake, before it's answered
+    let mut request = b"GET /echo HTTP/1.1\r\nHost: localhost\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Version: 13\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\r\n".to_vec();
+    request.extend(Frame::new(Opcode::Text, b"hi".to_vec()).encode_masked([1, 2, 3, 4]));
+    client.write_all(&request).await?;
+    let mut response = vec![0; 4096];
+    let mut received = 0;
+    while !response[..received].ends_with(b"\x81\x02hi") {
+      received += client.read(&mut response[received..]).await?;
+    }
+    let head = String::from_utf8_lossy(&response[..received]).to_string();
+    expect!(head.starts_with("HTTP/1.1 101 Switching Protocols\r\n")).to(be_true());
+    expect!(head.contains("Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n")).to(be_true());
+    expect!(head.contains("Connection: close")).to(be_false());
+
+    let close = Frame::new(Opcode::Close, vec![0x03, 0xe8]);
+    client.write_all(&close.encode_masked([1, 2, 3, 4])).await?;
+    let stats = connection.await?;
+    expect!(stats.requests).to(be_equal_to(1));
+    let mut closed = Vec::new();
+    client.read_to_end(&mut closed).await?;
+    expect!(closed).to(be_equal_to(close.encode()));
+    Ok(())
+  }
+
+  #[rstest]
+  #[case::client_closes("GET / HTTP/1.1\r\nConnection: close\r\n\r\n")]
+  #[case::http_1_0("GET / HTTP/1.0\r\n\r\n")]
+  #[case::parse_error("GET / HTTP/2.0\r\nHost: localhost\r\n\r\n")]
+  #[case::unread_body("POST / HTTP/1.1\r\nContent-Length: 100\r\n\r\nabc")]
+  #[tokio::test]
+  async fn test_close_connection(#[case] request: &str) -> io::Result<()> {
+    use tokio::io::AsyncWriteExt;
+
+    // bodies aren't buffered, an unread one is left on the connection
+    let server = Server::new("127.0.0.1:0".to_string()).max_buffered_body_size(0);
+    let peer = SocketAddr::from(([127, 0, 0, 1], 4000));
+    let (mut client, connection) = tokio::io::duplex(4096);
+    let connection = tokio::spawn(async move {
+      // PostHandler never reads bodies
+      server
+        .handle_connection(connection, ConnectionInfo::new(peer), Arc::new(PostHandler))
+        .await
+    });
+
+    client.write_all(request.as_bytes()).await?;
+    let mut response = String::new();
+    client.read_to_string(&mut response).await?;
+    expect!(response.contains("Connection: close\r\n")).to(be_true());
+    connection.await?;
+    Ok(())
+  }
+
+  #[rstest]
+  #[case::both("Content-Length: 5\r\nTransfer-Encoding: chunked\r\n")]
+  #[case::conflicting_content_length("Content-Length: 5\r\nContent-Length: 0\r\n")]
+  #[case::obs_fold("Content-Length: 5\r\nX-Folded: a\r\n Transfer-Encoding: chunked\r\n")]
+  #[case::not_ending_in_chunked("Transfer-Encoding: xchunked\r\n")]
+  #[case::repeated_transfer_encoding(
+    "Transfer-Encoding: chunked\r\nTransfer-Encoding: identity\r\n"
+  )]
+  #[case::invalid_content_length("Content-Length: abc\r\n")]
+  // read as 5 by one parser and as none by another, the body would pass for the next request
+  #[case::signed_content_length("Content-Length: +5\r\n")]
+  #[tokio::test]
+  async fn test_reject_ambiguous_framing(#[case] headers: &str) -> io::Result<()> {
+    use tokio::io::AsyncWriteExt;
+
+    let server = Server::new("127.0.0.1:0".to_string());
+    let peer = SocketAddr::from(([127, 0, 0, 1], 4000));
+    let (mut client, connection) = tokio::io::duplex(4096);
+    let connection = tokio::spawn(async move {
+      server
+        .handle_connection(connection, ConnectionInfo::new(peer), Arc::new(BodyHandler))
+        .await
+    });
+
+    // read as a body by one parser, the smuggled request would be served by the next
+    let request = format!(
+      "POST /form HTTP/1.1\r\nHost: localhost\r\n{}\r\n0\r\n\r\nGET /admin HTTP/1.1\r\nHost: localhost\r\n\r\n",
+      headers
+    );
+    client.write_all(request.as_bytes()).await?;
+    let mut response = String::new();
+    client.read_to_string(&mut response).await?;
+
+    let stats = connection.await?;
+    expect!(stats.requests).to(be_equal_to(0));
+    expect!(response.starts_with("HTTP/1.1 400 Bad Request\r\n")).to(be_true());
+    expect!(response.matches("HTTP/1.1 ").count()).to(be_equal_to(1));
+    expect!(response.contains("Connection: close\r\n")).to(be_true());
+    Ok(())
+  }
+
+  #[rstest]
+  #[case::wait(ConnectionOverflow::Wait)]
+  #[case::reject(ConnectionOverflow::Reject)]
+  #[tokio::test]
+  async fn test_max_connections(#[case] overflow: ConnectionOverflow) -> io::Result<()> {
+    use tokio::io::AsyncWriteExt;
+    use tokio::net::TcpStream;
+
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let address = listener.local_addr()?;
+    let server = Server::new(address.to_string()).max_connections(1, overflow);
+    let metrics = server.metrics();
+    tokio::spawn(server.serve(listener, Arc::new(UploadHandler)));
+
+    // holds the only slot while it's kept alive
+    let mut first = TcpStream::connect(address).await?;
+    first
+      .write_all(b"POST /upload HTTP/1.1\r\nHost: localhost\r\nContent-Length: 1\r\n\r\na")
+      .await?;
+    let mut response = vec![0; 1024];
+    expect!(first.read(&mut response).await?).to(be_greater_than(0));
+
+    let mut second = TcpStream::connect(address).await?;
+    let mut response = String::new();
+    match overflow {
+      ConnectionOverflow::Wait => {
+        second
+          .write_all(b"POST /upload HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\nContent-Length: 1\r\n\r\nb")
+          .await?;
+        let waiting = timeout(
+          Duration::from_millis(200),
+          second.read_to_string(&mut response),
+        );
+        expect!(waiting.await.is_err()).to(be_true());
+        drop(first);
+        second.read_to_string(&mut response).await?;
+        expect!(response.starts_with("HTTP/1.1 200 Ok\r\n")).to(be_true());
+        expect!(metrics.rejected_connections()).to(be_equal_to(0));
+      }
+      ConnectionOverflow::Reject => {
+        second.read_to_string(&mut response).await?;
+        expect!(response.starts_with("HTTP/1.1 503 Service Unavailable\r\n")).to(be_true());
+        expect!(response.contains("Retry-After: 1\r\n")).to(be_true());
+        expect!(metrics.rejected_connections()).to(be_equal_to(1));
+      }
+    }
+    Ok(())
+  }
+
+  #[cfg(all(unix, not(target_os = "solaris"), not(target_os = "illumos")))]
+  #[tokio::test]
+  async fn test_reuse_port() -> io::Result<()> {
+    use tokio::io::AsyncWriteExt;
+    use tokio::net::TcpStream;
+
+    let server = Server::new("127.0.0.1:0".to_string()).reuse_port(3);
+    let listeners = server.bind("127.0.0.1:0").await?;
+    expect!(listeners.len()).to(be_equal_to(3));
+    let address = listeners[0].local_addr()?;
+    for listener in &listeners {
+      expect!(listener.local_addr()?).to(be_equal_to(address));
+    }
+    // sockets without the option still can't take the port
+    expect!(TcpListener::bind(address).await.is_err()).to(be_true());
+
+    let server = Arc::new(server);
+    for listener in listeners {
+      let settings = ListenerSettings {
+        address: address.to_string(),
+        tls: false,
+        proxy_protocol: false,
+      };
+      tokio::spawn(Arc::clone(&server).accept_loop(
+        listener,
+        settings,
+        Arc::new(UploadHandler),
+        None,
+      ));
+    }
+    for _ in 0..6 {
+      let mut stream = TcpStream::connect(address).await?;
+      stream
+        .write_all(b"POST /upload HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\nContent-Length: 1\r\n\r\na")
+        .await?;
+      let mut response = String::new();
+      stream.read_to_string(&mut response).await?;
+      expect!(response.starts_with("HTTP/1.1 200 Ok\r\n")).to(be_true());
+    }
+    Ok(())
+  }
 }
@@ -0,0 +1,496 @@
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use ring::{
+  hmac,
+  rand::{SecureRandom, SystemRandom},
+};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::{
+  collections::HashMap,
+  io,
+  sync::{Arc, Mutex},
+  time::{Duration, Instant},
+};
+
+use crate::http::{
+  cookie::{SameSite, SetCookie},
+  header::HttpResponseHeaderKey,
+  HttpRequest, HttpResponse,
+};
+use crate::middleware::{Middleware, Next};
+use crate::server::{HandlerFuture, HandlerResult};
+
+/// What a session holds, set by handlers through [`Session::insert`]
+pub type SessionData = HashMap<String, String>;
+
+/// How long sessions last without being used, unless configured otherwise
+pub const DEFAULT_SESSION_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+/// Shorter secrets are refused, HMAC-SHA256 keys had better be as long as its output
+const MIN_SECRET_LENGTH: usize = 32;
+
+/// Where sessions are kept between requests, keyed by their ID. Sessions are dropped once
+/// they've gone unused for the `ttl` they were last saved or loaded with.
+pub trait SessionStore: Send + Sync + 'static {
+  /// The data of session `id`, unless it's unknown or expired, extending its lifetime to `ttl`
+  fn load(&self, id: &str, ttl: Duration) -> Option<SessionData>;
+
+  fn save(&self, id: &str, data: &SessionData, ttl: Duration);
+
+  fn remove(&self, id: &str);
+}
+
+#[derive(Default)]
+struct StoredSessions {
+  sessions: HashMap<String, (SessionData, Instant)>,
+  /// the earliest a session may expire, when expired ones are dropped next
+  next_purge: Option<Instant>,
+}
+
+/// Keeps sessions in the server's memory, so they're gone once it restarts and aren't shared
+/// between instances
+#[derive(Default)]
+pub struct MemoryStore {
+  stored: Mutex<StoredSessions>,
+}
+
+impl MemoryStore {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  pub fn len(&self) -> usize {
+    self
+      .stored
+      .lock()
+      .unwrap_or_else(|e| e.into_inner())
+      .sessions
+      .len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.len() == 0
+  }
+}
+
+impl SessionStore for MemoryStore {
+  fn load(&self, id: &str, ttl: Duration) -> Option<SessionData> {
+    let mut stored = self.stored.lock().unwrap_or_else(|e| e.into_inner());
+    let now = Instant::now();
+    let (data, expires) = stored.sessions.get_mut(id)?;
+    if *expires <= now {
+      stored.sessions.remove(id);
+      return None;
+    }
+    *expires = now + ttl;
+    Some(data.clone())
+  }
+
+  fn save(&self, id: &str, data: &SessionData, ttl: Duration) {
+    let mut stored = self.stored.lock().unwrap_or_else(|e| e.into_inner());
+    let now = Instant::now();
+    // abandoned sessions would pile up otherwise, as nothing loads them again
+    if stored
+      .next_purge
+      .is_some_and(|next_purge| next_purge <= now)
+    {
+      stored.sessions.retain(|_, (_, expires)| *expires > now);
+      stored.next_purge = stored.sessions.values().map(|(_, expires)| *expires).min();
+    }
+    let expires = now + ttl;
+    stored.next_purge = Some(stored.next_purge.map_or(expires, |next| next.min(expires)));
+    stored
+      .sessions
+      .insert(id.to_string(), (data.clone(), expires));
+  }
+
+  fn remove(&self, id: &str) {
+    let mut stored = self.stored.lock().unwrap_or_else(|e| e.into_inner());
+    stored.sessions.remove(id);
+  }
+}
+
+#[derive(Debug, Default)]
+struct SessionState {
+  data: SessionData,
+  changed: bool,
+  /// a new ID is due, see [`Session::regenerate`]
+  regenerate: bool,
+  destroyed: bool,
+}
+
+/// The session of a request, found in its [`HttpRequest::extensions`] behind [`Sessions`]. Clones
+/// share the data; what handlers change is saved once they've responded. Visitors only get a
+/// session, and a cookie, once something is stored in it.
+#[derive(Debug, Clone, Default)]
+pub struct Session {
+  state: Arc<Mutex<SessionState>>,
+}
+
+impl Session {
+  fn new(data: SessionData) -> Self {
+    let state = SessionState { data, ..SessionState::default() };
+    Self { state: Arc::new(Mutex::new(state)) }
+  }
+
+  fn state(&self) -> std::sync::MutexGuard<'_, SessionState> {
+    self.state.lock().unwrap_or_else(|e| e.into_inner())
+  }
+
+  pub fn get(&self, key: &str) -> Option<String> {
+    self.state().data.get(key).cloned()
+  }
+
+  pub fn insert(&self, key: &str, value: &str) {
+    let mut state = self.state();
+    state.data.insert(key.to_string(), value.to_string());
+    state.changed = true;
+  }
+
+  pub fn remove(&self, key: &str) -> Option<String> {
+    let mut state = self.state();
+    let removed = state.data.remove(key);
+    state.changed |= removed.is_some();
+    removed
+  }
+
+  /// Move the data to a new session ID, as is due whenever the user's privileges change (e.g.
+  /// logging in), so an ID planted by someone else before is worthless
+  pub fn regenerate(&self) {
+    let mut state = self.state();
+    state.regenerate = true;
+    state.changed = true;
+  }
+
+  /// Drop the session and its cookie, e.g. logging out
+  pub fn destroy(&self) {
+    let mut state = self.state();
+    state.data.clear();
+    state.destroyed = true;
+  }
+}
+
+/// Sessions kept in a signed cookie, e.g.
+///
+/// ```toml
+/// [sessions]
+/// secret = "at least 32 bytes of random characters"
+/// cookie_name = "session"
+/// ttl_secs = 3600
+/// secure = true
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SessionSettings {
+  /// signs the cookies, at least 32 bytes; keep the config file private
+  pub secret: String,
+  #[serde(default = "default_cookie_name")]
+  pub cookie_name: String,
+  /// defaults to [`DEFAULT_SESSION_TTL`]
+  pub ttl_secs: Option<u64>,
+  /// only send the cookie over HTTPS
+  #[serde(default)]
+  pub secure: bool,
+}
+
+fn default_cookie_name() -> String {
+  "session".to_string()
+}
+
+/// Gives every request a [`Session`], identified by a random ID in a cookie signed with
+/// HMAC-SHA256: cookies that weren't issued with the secret are ignored. The data stays in the
+/// [`SessionStore`], only the ID travels. Cookies are `HttpOnly` and `SameSite=Lax`, and last
+/// as long as the browser runs; the store expires sessions after `ttl` without use.
+pub struct Sessions {
+  store: Arc<dyn SessionStore>,
+  key: hmac::Key,
+  cookie_name: String,
+  ttl: Duration,
+  secure: bool,
+}
+
+impl Sessions {
+  /// Sessions signed with `secret`
+  ///
+  /// # Panics
+  ///
+  /// If `secret` is shorter than 32 bytes
+  pub fn new(secret: &[u8], store: Arc<dyn SessionStore>) -> Self {
+    assert!(
+      secret.len() >= MIN_SECRET_LENGTH,
+      "Session secrets must be at least {} bytes long",
+      MIN_SECRET_LENGTH
+    );
+    Self {
+      store,
+      key: hmac::Key::new(hmac::HMAC_SHA256, secret),
+      cookie_name: default_cookie_name(),
+      ttl: DEFAULT_SESSION_TTL,
+      secure: false,
+    }
+  }
+
+  /// Kept in a [`MemoryStore`]
+  pub fn with_settings(settings: &SessionSettings) -> io::Result<Self> {
+    if settings.secret.len() < MIN_SECRET_LENGTH {
+      return Err(io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!(
+          "The session secret must be at least {} bytes long",
+          MIN_SECRET_LENGTH
+        ),
+      ));
+    }
+    let mut sessions = Self::new(settings.secret.as_bytes(), Arc::new(MemoryStore::new()))
+      .cookie_name(&settings.cookie_name);
+    if let Some(ttl) = settings.ttl_secs {
+      sessions = sessions.ttl(Duration::from_secs(ttl));
+    }
+    if settings.secure {
+      sessions = sessions.secure();
+    }
+    Ok(sessions)
+  }
+
+  pub fn cookie_name(mut self, cookie_name: &str) -> Self {
+    self.cookie_name = cookie_name.to_string();
+    self
+  }
+
+  pub fn ttl(mut self, ttl: Duration) -> Self {
+    self.ttl = ttl;
+    self
+  }
+
+  /// Only send the cookie over HTTPS
+  pub fn secure(mut self) -> Self {
+    self.secure = true;
+    self
+  }
+
+  /// `id` and its signature, as the cookie holds them
+  fn sign(&self, id: &str) -> String {
+    let signature = hmac::sign(&self.key, id.as_bytes());
+    format!("{}.{}", id, URL_SAFE_NO_PAD.encode(signature))
+  }
+
+  /// The session ID in the cookie of `request`, if it's signed with the secret
+  fn session_id(&self, request: &HttpRequest<'_>) -> Option<String> {
+    let cookies = request.cookies();
+    let (id, signature) = cookies.get(&self.cookie_name)?.value().split_once('.')?;
+    let signature = URL_SAFE_NO_PAD.decode(signature).ok()?;
+    hmac::verify(&self.key, id.as_bytes(), &signature).ok()?;
+    Some(id.to_string())
+  }
+
+  /// The session `request` continues, with the ID it was stored under, or a new one
+  fn load(&self, request: &HttpRequest<'_>) -> (Option<String>, Session) {
+    let loaded = self
+      .session_id(request)
+      .and_then(|id| Some(id.clone()).zip(self.store.load(&id, self.ttl)));
+    match loaded {
+      Some((id, data)) => (Some(id), Session::new(data)),
+      None => (None, Session::default()),
+    }
+  }
+
+  fn cookie(&self, value: &str) -> SetCookie {
+    let cookie = SetCookie::new(&self.cookie_name, value)
+      .path("/")
+      .http_only()
+      .same_site(SameSite::Lax);
+    match self.secure {
+      true => cookie.secure(),
+      false => cookie,
+    }
+  }
+
+  /// Store what the handler left in `session`, setting the cookie on `response` where needed
+  fn save(&self, loaded_id: Option<String>, session: &Session, response: &mut HttpResponse) {
+    let state = session.state();
+    let cookie = match (&loaded_id, state.destroyed, state.changed) {
+      (Some(loaded_id), true, _) => {
+        self.store.remove(loaded_id);
+        Some(self.cookie("").max_age(Duration::ZERO))
+      }
+      (Some(loaded_id), false, true) if !state.regenerate => {
+        self.store.save(loaded_id, &state.data, self.ttl);
+        None
+      }
+      (_, false, true) => {
+        if let Some(loaded_id) = &loaded_id {
+          self.store.remove(loaded_id);
+        }
+        let id = new_session_id();
+        self.store.save(&id, &state.data, self.ttl);
+        Some(self.cookie(&self.sign(&id)))
+      }
+      _ => None,
+    };
+    if let Some(cookie) = cookie {
+      // a session cookie on a shared response would hand the session to everyone
+      response.insert_header(HttpResponseHeaderKey::CacheControl, "private");
+      if let Err(error) = response.set_cookie(&cookie) {
+        tracing::warn!(%error, "Failed to set the session cookie");
+      }
+    }
+  }
+}
+
+/// 256 random bits, so IDs can't be guessed
+fn new_session_id() -> String {
+  let mut id = [0u8; 32];
+  SystemRandom::new()
+    .fill(&mut id)
+    .expect("The system's random number generator failed");
+  URL_SAFE_NO_PAD.encode(id)
+}
+
+impl Middleware for Sessions {
+  fn handle(&self, request: &HttpRequest, next: Next<'_>) -> HandlerResult {
+    let (loaded_id, session) = self.load(request);
+    let mut response = next.run(&request.clone().with_extension(session.clone()))?;
+    self.save(loaded_id, &session, &mut response);
+    Ok(response)
+  }
+
+  fn handle_async<'a>(&'a self, request: &'a HttpRequest<'a>, next: Next<'a>) -> HandlerFuture<'a> {
+    Box::pin(async move {
+      let (loaded_id, session) = self.load(request);
+      let request = request.clone().with_extension(session.clone());
+      let mut response = next.run_async(&request).await?;
+      self.save(loaded_id, &session, &mut response);
+      Ok(response)
+    })
+  }
+
+  fn describe(&self) -> Value {
+    json!({
+      "middleware": "Sessions",
+      "cookie_name": self.cookie_name,
+      "ttl_secs": self.ttl.as_secs(),
+      "secure": self.secure,
+    })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::http::StatusCode;
+  use crate::middleware::Layered;
+  use crate::server::Handler;
+  use expectest::prelude::*;
+  use rstest::*;
+
+  const SECRET: &[u8] = b"0123456789abcdef0123456789abcdef";
+
+  /// A cart: `/add?item` adds `item`, `/login` regenerates, `/logout` destroys, anything else
+  /// answers with the cart
+  struct CartHandler;
+
+  impl Handler for CartHandler {
+    fn handle_request(&self, request: &HttpRequest<'_>) -> HandlerResult {
+      let session = request.extensions().get::<Session>().unwrap();
+      match request.path() {
+        "/add" => {
+          let item = request.query_string().as_ref().unwrap().raw().to_string();
+          let cart = session
+            .get("cart")
+            .map_or(item.clone(), |c| format!("{},{}", c, item));
+          session.insert("cart", &cart);
+        }
+        "/login" => session.regenerate(),
+        "/logout" => session.destroy(),
+        _ => {}
+      }
+      let cart = session.get("cart").unwrap_or_default();
+      Ok(HttpResponse::new(
+        StatusCode::Ok,
+        Some(cart.into_bytes()),
+        None,
+      ))
+    }
+  }
+
+  /// The body of the response to `target` and the value of the cookie it set, if any
+  fn get(handler: &Layered, target: &str, cookie: Option<&str>) -> (String, Option<String>) {
+    let cookie = cookie
+      .map(|cookie| format!("Cookie: session={}\r\n", cookie))
+      .unwrap_or_default();
+    let raw_request = format!(
+      "GET {} HTTP/1.1\r\nHost: localhost\r\n{}\r\n",
+      target, cookie
+    );
+    let request = HttpRequest::try_from(raw_request.as_bytes()).unwrap();
+    let response = handler.handle_request(&request).unwrap();
+    let set_cookie = response
+      .http_header()
+      .as_ref()
+      .and_then(|header| header.get(HttpResponseHeaderKey::SetCookie).cloned())
+      .map(|set_cookie| {
+        let (cookie, _) = set_cookie.split_once(';').unwrap();
+        cookie.trim_start_matches("session=").to_string()
+      });
+    let body = String::from_utf8(response.body().clone().unwrap_or_default()).unwrap();
+    (body, set_cookie)
+  }
+
+  #[rstest]
+  fn test_session_lifecycle() {
+    let store = Arc::new(MemoryStore::new());
+    let handler =
+      Layered::new(Arc::new(CartHandler)).layer(Arc::new(Sessions::new(SECRET, store.clone())));
+
+    // no session until something is stored
+    expect!(get(&handler, "/", None)).to(be_equal_to((String::new(), None)));
+    expect!(store.is_empty()).to(be_true());
+
+    let (cart, cookie) = get(&handler, "/add?apple", None);
+    expect!(cart.as_str()).to(be_equal_to("apple"));
+    let cookie = cookie.unwrap();
+    // the same ID keeps the cookie as it is
+    expect!(get(&handler, "/add?pear", Some(&cookie)))
+      .to(be_equal_to(("apple,pear".to_string(), None)));
+
+    let (cart, regenerated) = get(&handler, "/login", Some(&cookie));
+    expect!(cart.as_str()).to(be_equal_to("apple,pear"));
+    let regenerated = regenerated.unwrap();
+    expect!(regenerated.as_str()).not_to(be_equal_to(cookie.as_str()));
+    expect!(get(&handler, "/", Some(&cookie))).to(be_equal_to((String::new(), None)));
+    expect!(store.len()).to(be_equal_to(1));
+
+    expect!(get(&handler, "/logout", Some(&regenerated)))
+      .to(be_equal_to((String::new(), Some(String::new()))));
+    expect!(store.is_empty()).to(be_true());
+  }
+
+  #[rstest]
+  #[case::unsigned("abc")]
+  #[case::forged_signature("abc.c2lnbmF0dXJl")]
+  fn test_ignore_forged_cookies(#[case] cookie: &str) {
+    let store = Arc::new(MemoryStore::new());
+    store.save(
+      "abc",
+      &SessionData::from([("cart".to_string(), "gold".to_string())]),
+      DEFAULT_SESSION_TTL,
+    );
+    let handler = Layered::new(Arc::new(CartHandler)).layer(Arc::new(Sessions::new(SECRET, store)));
+
+    expect!(get(&handler, "/", Some(cookie))).to(be_equal_to((String::new(), None)));
+  }
+
+  #[rstest]
+  fn test_expire_sessions() {
+    let store = MemoryStore::new();
+    let data = SessionData::from([("user".to_string(), "alice".to_string())]);
+    store.save("old", &data, Duration::ZERO);
+    store.save("current", &data, DEFAULT_SESSION_TTL);
+
+    expect!(store.load("old", DEFAULT_SESSION_TTL)).to(be_none());
+    expect!(store.load("current", DEFAULT_SESSION_TTL)).to(be_some().value(data.clone()));
+    // dropped while saving others, even if never loaded again
+    store.save("expired", &data, Duration::ZERO);
+    store.save("next", &data, DEFAULT_SESSION_TTL);
+    expect!(store.len()).to(be_equal_to(2));
+  }
+}
@@ -0,0 +1,219 @@
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::{
+  fs,
+  path::{Path, PathBuf},
+  sync::Arc,
+};
+
+use crate::filesystem::LocalFileSystem;
+use crate::http::{
+  HandlerError, HttpRequest, HttpResponse, Method, MimeTypes, RequestBody, StatusCode,
+};
+use crate::server::{Handler, HandlerFuture, HandlerResult};
+use crate::website_handler::WebsiteHandler;
+
+/// Serves every subdomain of `domain` from its own directory, e.g.
+///
+/// ```toml
+/// [tenants]
+/// domain = "*.sites.example.com"
+/// base_path = "/srv/tenants"
+/// ```
+///
+/// answers `Host: blog.sites.example.com` from `/srv/tenants/blog/public`
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct TenantSettings {
+  /// `*.` followed by the domain whose direct subdomains are tenants
+  pub domain: String,
+  pub base_path: PathBuf,
+}
+
+/// Resolves the tenant from the `Host` header of each request and serves its files, passing
+/// requests for other hosts on to `fallback`. Tenants are confined to
+/// `<base_path>/<tenant>/public`: tenant names are single DNS labels, and roots that resolve
+/// outside of `base_path` (e.g. through symlinks) are refused.
+pub struct TenantHandler {
+  /// `.sites.example.com` for `*.sites.example.com`
+  domain_suffix: String,
+  base_path: PathBuf,
+  mime_types: MimeTypes,
+  fallback: Arc<dyn Handler>,
+}
+
+impl TenantHandler {
+  pub fn new(settings: &TenantSettings, mime_types: MimeTypes, fallback: Arc<dyn Handler>) -> Self {
+    // `sites.example.com` means the same, and must not match `blogsites.example.com`
+    let domain = settings.domain.to_lowercase();
+    let domain = domain.trim_start_matches('*').trim_start_matches('.');
+    Self {
+      domain_suffix: format!(".{}", domain),
+      base_path: settings.base_path.clone(),
+      mime_types,
+      fallback,
+    }
+  }
+
+  /// The tenant named by the `Host` of `request`, if it's a direct subdomain of the
+  /// configured domain
+  fn tenant<'a>(&self, request: &'a HttpRequest<'_>) -> Option<&'a str> {
+    let host = request.hostname()?;
+    let suffix_start = host.len().checked_sub(self.domain_suffix.len())?;
+    if !host.is_char_boundary(suffix_start)
+      || !host[suffix_start..].eq_ignore_ascii_case(&self.domain_suffix)
+    {
+      return None;
+    }
+    Some(&host[..suffix_start]).filter(|tenant| is_dns_label(tenant))
+  }
+
+  /// The canonical public directory of `tenant`, if it exists within `base_path`
+  fn public_path(base_path: &Path, tenant: &str) -> Option<PathBuf> {
+    let base_path = fs::canonicalize(base_path).ok()?;
+    let public_path =
+      fs::canonicalize(base_path.join(tenant.to_lowercase()).join("public")).ok()?;
+    Some(public_path).filter(|path| path.starts_with(&base_path) && path.is_dir())
+  }
+}
+
+/// Letters, digits and inner hyphens, so a tenant can never name `..` or a nested path
+fn is_dns_label(label: &str) -> bool {
+  (1..=63).contains(&label.len())
+    && label
+      .bytes()
+      .all(|byte| byte.is_ascii_alphanumeric() || byte == b'-')
+    && !label.starts_with('-')
+    && !label.ends_with('-')
+}
+
+impl TenantHandler {
+  /// LocalFileSystem rejects files resolving outside of the (canonical) public path
+  fn website_handler(&self, public_path: &Path) -> WebsiteHandler<LocalFileSystem> {
+    let file_system = LocalFileSystem::new(public_path.to_string_lossy().to_string());
+    WebsiteHandler::new(Arc::new(file_system), self.mime_types.clone())
+  }
+}
+
+impl Handler for TenantHandler {
+  fn handle_request(&self, request: &HttpRequest<'_>) -> HandlerResult {
+    let Some(tenant) = self.tenant(request) else {
+      return self.fallback.handle_request(request);
+    };
+
+    match Self::public_path(&self.base_path, tenant) {
+      Some(public_path) => self.website_handler(&public_path).handle_request(request),
+      None => Ok(HttpResponse::empty_body(StatusCode::NotFound)),
+    }
+  }
+
+  fn handle_async<'a>(&'a self, request: &'a HttpRequest<'a>) -> Option<HandlerFuture<'a>> {
+    let Some(tenant) = self.tenant(request) else {
+      return self.fallback.handle_async(request);
+    };
+
+    let (base_path, tenant) = (self.base_path.clone(), tenant.to_string());
+    Some(Box::pin(async move {
+      let public_path =
+        tokio::task::spawn_blocking(move || Self::public_path(&base_path, &tenant)).await;
+      let public_path = match public_path {
+        Ok(Some(public_path)) => public_path,
+        Ok(None) => return Ok(HttpResponse::empty_body(StatusCode::NotFound)),
+        Err(error) => {
+          return Err(HandlerError::internal("Tenant lookup failed").with_source(error))
+        }
+      };
+      let website_handler = self.website_handler(&public_path);
+      let response = match website_handler.handle_async(request) {
+        Some(handled) => handled.await,
+        None => website_handler.handle_request(request),
+      };
+      response
+    }))
+  }
+
+  fn handle_with_body(&self, request: &HttpRequest<'_>, body: RequestBody) -> HandlerResult {
+    match self.tenant(request) {
+      // static files don't take a body
+      Some(_) => self.handle_request(request),
+      None => self.fallback.handle_with_body(request, body),
+    }
+  }
+
+  fn allowed_methods(&self) -> Vec<Method> {
+    self.fallback.allowed_methods()
+  }
+
+  fn describe(&self) -> Value {
+    json!({
+      "handler": "TenantHandler",
+      "domain": format!("*{}", self.domain_suffix),
+      "public_paths": self.base_path.join("<tenant>").join("public"),
+      "fallback": self.fallback.describe(),
+    })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use expectest::prelude::*;
+  use rstest::*;
+  use std::io;
+  use tempfile::TempDir;
+
+  struct FallbackHandler;
+
+  impl Handler for FallbackHandler {
+    fn handle_request(&self, _request: &HttpRequest<'_>) -> HandlerResult {
+      Ok(HttpResponse::empty_body(StatusCode::NoContent))
+    }
+  }
+
+  #[rstest]
+  #[case::tenant("blog.sites.example.com", "/", StatusCode::Ok, Some("blog"))]
+  #[case::port_and_case("Blog.Sites.Example.com:8080", "/", StatusCode::Ok, Some("blog"))]
+  #[case::unknown_tenant("shop.sites.example.com", "/", StatusCode::NotFound, None)]
+  #[case::other_host("www.example.com", "/", StatusCode::NoContent, None)]
+  #[case::apex("sites.example.com", "/", StatusCode::NoContent, None)]
+  #[case::nested_subdomain("a.blog.sites.example.com", "/", StatusCode::NoContent, None)]
+  #[case::traversal(
+    "blog.sites.example.com",
+    "/../../secret.html",
+    StatusCode::NotFound,
+    Some("<h1>404 Not Found</h1>")
+  )]
+  #[case::escaping_symlink("escape.sites.example.com", "/", StatusCode::NotFound, None)]
+  fn test_serve_tenant(
+    #[case] host: &str,
+    #[case] path: &str,
+    #[case] expected_status: StatusCode,
+    #[case] expected_body: Option<&str>,
+  ) -> io::Result<()> {
+    let outside = TempDir::new()?;
+    fs::create_dir(outside.path().join("public"))?;
+    fs::write(outside.path().join("public/index.html"), "outside")?;
+    fs::write(outside.path().join("secret.html"), "secret")?;
+    let base = TempDir::new()?;
+    fs::create_dir_all(base.path().join("blog/public"))?;
+    fs::write(base.path().join("blog/public/index.html"), "blog")?;
+    std::os::unix::fs::symlink(outside.path(), base.path().join("escape"))?;
+
+    let settings = TenantSettings {
+      domain: "*.sites.example.com".to_string(),
+      base_path: base.path().to_path_buf(),
+    };
+    let handler = TenantHandler::new(&settings, MimeTypes::default(), Arc::new(FallbackHandler));
+    let raw_request = format!("GET {} HTTP/1.1\r\nHost: {}\r\n\r\n", path, host);
+    let request = HttpRequest::try_from(raw_request.as_bytes()).unwrap();
+
+    let response = handler.handle_request(&request).unwrap();
+    expect!(*response.status_code()).to(be_equal_to(expected_status));
+    let body = response.body().as_deref().map(String::from_utf8_lossy);
+    match expected_body {
+      Some(expected_body) => expect!(body.unwrap().contains(expected_body)).to(be_true()),
+      None => expect!(body).to(be_none()),
+    };
+    Ok(())
+  }
+}
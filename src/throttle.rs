@@ -0,0 +1,115 @@
+use std::{
+  future::Future,
+  io,
+  pin::Pin,
+  task::{ready, Context, Poll},
+  time::Duration,
+};
+use tokio::{
+  io::AsyncWrite,
+  time::{sleep, Instant, Sleep},
+};
+
+/// Caps the bytes per second written through it with a token bucket holding up to one second's
+/// worth, so short responses go out at full speed while long downloads settle at the rate
+pub struct ThrottledWriter<W> {
+  inner: W,
+  bytes_per_sec: f64,
+  tokens: f64,
+  updated_at: Instant,
+  delay: Option<Pin<Box<Sleep>>>,
+}
+
+impl<W> ThrottledWriter<W> {
+  pub fn new(inner: W, bytes_per_sec: u64) -> Self {
+    let bytes_per_sec = bytes_per_sec.max(1) as f64;
+    Self {
+      inner,
+      bytes_per_sec,
+      tokens: bytes_per_sec,
+      updated_at: Instant::now(),
+      delay: None,
+    }
+  }
+
+  fn refill(&mut self) {
+    let now = Instant::now();
+    let refilled = now.duration_since(self.updated_at).as_secs_f64() * self.bytes_per_sec;
+    self.tokens = (self.tokens + refilled).min(self.bytes_per_sec);
+    self.updated_at = now;
+  }
+}
+
+impl<W: AsyncWrite + Unpin> AsyncWrite for ThrottledWriter<W> {
+  fn poll_write(
+    mut self: Pin<&mut Self>,
+    cx: &mut Context<'_>,
+    buf: &[u8],
+  ) -> Poll<io::Result<usize>> {
+    if buf.is_empty() {
+      return Pin::new(&mut self.inner).poll_write(cx, buf);
+    }
+
+    loop {
+      if let Some(delay) = self.delay.as_mut() {
+        ready!(delay.as_mut().poll(cx));
+        self.delay = None;
+      }
+
+      self.refill();
+      // wait for a full chunk rather than trickling out single bytes
+      let wanted = (buf.len() as f64).min(self.bytes_per_sec);
+      if self.tokens >= wanted {
+        let written = ready!(Pin::new(&mut self.inner).poll_write(cx, &buf[..wanted as usize]))?;
+        self.tokens -= written as f64;
+        return Poll::Ready(Ok(written));
+      }
+
+      let wait = Duration::from_secs_f64((wanted - self.tokens) / self.bytes_per_sec);
+      self.delay = Some(Box::pin(sleep(wait)));
+    }
+  }
+
+  fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+    Pin::new(&mut self.inner).poll_flush(cx)
+  }
+
+  fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+    Pin::new(&mut self.inner).poll_shutdown(cx)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use expectest::prelude::*;
+  use rstest::*;
+  use tokio::io::AsyncWriteExt;
+
+  #[rstest]
+  #[tokio::test(start_paused = true)]
+  async fn test_throttle_writes() -> io::Result<()> {
+    let mut output = Vec::new();
+    let start = Instant::now();
+    {
+      let mut writer = ThrottledWriter::new(&mut output, 1000);
+      // the first second's worth goes out right away, the rest at 1000 bytes per second
+      writer.write_all(&[b'x'; 3500]).await?;
+    }
+    expect!(output.len()).to(be_equal_to(3500));
+    expect!(start.elapsed()).to(be_equal_to(Duration::from_millis(2500)));
+    Ok(())
+  }
+
+  #[rstest]
+  #[tokio::test(start_paused = true)]
+  async fn test_short_writes_are_not_delayed() -> io::Result<()> {
+    let mut output = Vec::new();
+    let start = Instant::now();
+    ThrottledWriter::new(&mut output, 1000)
+      .write_all(b"HTTP/1.1 204 No Content\r\n\r\n")
+      .await?;
+    expect!(start.elapsed()).to(be_equal_to(Duration::ZERO));
+    Ok(())
+  }
+}
@@ -0,0 +1,584 @@
+use std::{
+  collections::HashMap,
+  fs, io,
+  path::PathBuf,
+  sync::{Arc, RwLock},
+  time::{Duration, SystemTime},
+};
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use thiserror::Error;
+use tokio_rustls::{
+  rustls::{
+    crypto::{ring, CryptoProvider},
+    pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer},
+    server::{ClientHello, NoServerSessionStorage, ResolvesServerCert, ServerSessionMemoryCache},
+    sign::CertifiedKey,
+    version, ServerConfig, SupportedProtocolVersion,
+  },
+  TlsAcceptor,
+};
+
+/// How often the certificate files are checked for changes
+pub const DEFAULT_RELOAD_INTERVAL: Duration = Duration::from_secs(60);
+
+/// ALPN protocol of TLS-ALPN-01 validation handshakes (RFC 8737)
+pub const ACME_TLS_ALPN_NAME: &[u8] = b"acme-tls/1";
+
+/// Certificates answering TLS-ALPN-01 challenges, keyed by domain
+pub type AlpnChallenges = Arc<RwLock<HashMap<String, Arc<CertifiedKey>>>>;
+
+/// Certificate chain and private key of a TLS listener, both PEM encoded. Additional
+/// certificates are picked by the server name (SNI) the client asks for, the default one
+/// is served to everybody else.
+///
+/// ```toml
+/// [tls]
+/// cert_path = "/etc/letsencrypt/live/example.com/fullchain.pem"
+/// key_path = "/etc/letsencrypt/live/example.com/privkey.pem"
+/// reload_interval_secs = 60
+///
+/// [[tls.certificates]]
+/// server_name = "*.example.org"
+/// cert_path = "/etc/letsencrypt/live/example.org/fullchain.pem"
+/// key_path = "/etc/letsencrypt/live/example.org/privkey.pem"
+/// ```
+///
+/// The remaining knobs default to what rustls considers safe and only need touching when a
+/// compliance regime says so:
+///
+/// ```toml
+/// min_version = "1.3"
+/// # in order of preference, which then takes precedence over the client's
+/// cipher_suites = ["TLS13_AES_256_GCM_SHA384", "TLS13_AES_128_GCM_SHA256"]
+/// alpn_protocols = ["http/1.1"]
+/// session_tickets = false
+/// # stateful resumption, 0 turns it off
+/// session_cache_size = 0
+/// ```
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct TlsSettings {
+  pub cert_path: PathBuf,
+  pub key_path: PathBuf,
+  #[serde(default)]
+  pub certificates: Vec<SniCertificate>,
+  #[serde(rename = "reload_interval_secs", default = "default_reload_interval")]
+  #[serde(deserialize_with = "deserialize_secs", serialize_with = "serialize_secs")]
+  pub reload_interval: Duration,
+  #[serde(default)]
+  pub min_version: TlsVersion,
+  /// rustls names such as `TLS13_AES_128_GCM_SHA256`, empty for the provider's defaults
+  #[serde(default)]
+  pub cipher_suites: Vec<String>,
+  #[serde(default = "default_alpn_protocols")]
+  pub alpn_protocols: Vec<String>,
+  #[serde(default = "default_true")]
+  pub session_tickets: bool,
+  #[serde(default = "default_session_cache_size")]
+  pub session_cache_size: usize,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub enum TlsVersion {
+  #[default]
+  #[serde(rename = "1.2")]
+  Tls12,
+  #[serde(rename = "1.3")]
+  Tls13,
+}
+
+fn default_alpn_protocols() -> Vec<String> {
+  vec!["http/1.1".to_string()]
+}
+
+fn default_true() -> bool {
+  true
+}
+
+fn default_session_cache_size() -> usize {
+  256
+}
+
+fn default_reload_interval() -> Duration {
+  DEFAULT_RELOAD_INTERVAL
+}
+
+fn deserialize_secs<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+  u64::deserialize(deserializer).map(Duration::from_secs)
+}
+
+fn serialize_secs<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+  duration.as_secs().serialize(serializer)
+}
+
+impl TlsSettings {
+  pub fn new<P: Into<PathBuf>>(cert_path: P, key_path: P) -> Self {
+    Self {
+      cert_path: cert_path.into(),
+      key_path: key_path.into(),
+      certificates: Vec::new(),
+      reload_interval: DEFAULT_RELOAD_INTERVAL,
+      min_version: TlsVersion::default(),
+      cipher_suites: Vec::new(),
+      alpn_protocols: default_alpn_protocols(),
+      session_tickets: true,
+      session_cache_size: default_session_cache_size(),
+    }
+  }
+
+  pub fn min_version(mut self, min_version: TlsVersion) -> Self {
+    self.min_version = min_version;
+    self
+  }
+
+  /// Restrict the handshake to these suites, preferred in the given order
+  pub fn cipher_suites<S: Into<String>>(mut self, cipher_suites: Vec<S>) -> Self {
+    self.cipher_suites = cipher_suites.into_iter().map(Into::into).collect();
+    self
+  }
+
+  /// Protocols offered through ALPN, most preferred first
+  pub fn alpn_protocols<S: Into<String>>(mut self, alpn_protocols: Vec<S>) -> Self {
+    self.alpn_protocols = alpn_protocols.into_iter().map(Into::into).collect();
+    self
+  }
+
+  /// Stateless resumption through encrypted session tickets
+  pub fn session_tickets(mut self, session_tickets: bool) -> Self {
+    self.session_tickets = session_tickets;
+    self
+  }
+
+  /// Sessions kept for stateful resumption, `0` disables it
+  pub fn session_cache_size(mut self, session_cache_size: usize) -> Self {
+    self.session_cache_size = session_cache_size;
+    self
+  }
+
+  /// Serve another certificate to clients asking for `server_name`, which may be a
+  /// wildcard like `*.example.org`
+  pub fn with_certificate<P: Into<PathBuf>>(
+    mut self,
+    server_name: &str,
+    cert_path: P,
+    key_path: P,
+  ) -> Self {
+    self.certificates.push(SniCertificate {
+      server_name: server_name.to_string(),
+      cert_path: cert_path.into(),
+      key_path: key_path.into(),
+    });
+    self
+  }
+
+  fn files(&self) -> impl Iterator<Item = &PathBuf> {
+    [&self.cert_path, &self.key_path].into_iter().chain(
+      self
+        .certificates
+        .iter()
+        .flat_map(|certificate| [&certificate.cert_path, &certificate.key_path]),
+    )
+  }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct SniCertificate {
+  pub server_name: String,
+  pub cert_path: PathBuf,
+  pub key_path: PathBuf,
+}
+
+#[derive(Error, Debug)]
+pub enum TlsError {
+  #[error("Failed to read {path}: {source}")]
+  Io { path: PathBuf, source: io::Error },
+  #[error("Invalid PEM in {path}: {source}")]
+  Pem {
+    path: PathBuf,
+    source: tokio_rustls::rustls::pki_types::pem::Error,
+  },
+  #[error("No certificate found in {0}")]
+  NoCertificate(PathBuf),
+  #[error("Unknown or unsupported cipher suite {0}")]
+  UnknownCipherSuite(String),
+  #[error("Invalid TLS configuration: {0}")]
+  Rustls(#[from] tokio_rustls::rustls::Error),
+}
+
+/// The rustls configuration of a TLS listener, swapped atomically when the certificate
+/// files change or on `SIGHUP`. Handshakes in progress keep the configuration they started
+/// with, so renewals never drop connections.
+pub struct ReloadableTlsConfig {
+  settings: TlsSettings,
+  alpn_challenges: Option<AlpnChallenges>,
+  current: RwLock<Arc<ServerConfig>>,
+  loaded_at: RwLock<Option<SystemTime>>,
+}
+
+impl ReloadableTlsConfig {
+  pub fn load(settings: TlsSettings) -> Result<Self, TlsError> {
+    Self::load_with_alpn_challenges(settings, None)
+  }
+
+  /// Like [`Self::load`], additionally answering `acme-tls/1` handshakes with `alpn_challenges`
+  pub fn load_with_alpn_challenges(
+    settings: TlsSettings,
+    alpn_challenges: Option<AlpnChallenges>,
+  ) -> Result<Self, TlsError> {
+    let config = build_server_config(&settings, alpn_challenges.as_ref())?;
+    let loaded_at = files_modified_at(&settings);
+    Ok(Self {
+      settings,
+      alpn_challenges,
+      current: RwLock::new(Arc::new(config)),
+      loaded_at: RwLock::new(loaded_at),
+    })
+  }
+
+  pub fn settings(&self) -> &TlsSettings {
+    &self.settings
+  }
+
+  pub fn current(&self) -> Arc<ServerConfig> {
+    Arc::clone(&self.current.read().unwrap_or_else(|e| e.into_inner()))
+  }
+
+  pub fn acceptor(&self) -> TlsAcceptor {
+    TlsAcceptor::from(self.current())
+  }
+
+  /// Re-read the certificate and key; on failure the previous configuration stays active
+  pub fn reload(&self) -> Result<(), TlsError> {
+    let config = build_server_config(&self.settings, self.alpn_challenges.as_ref())?;
+    *self.current.write().unwrap_or_else(|e| e.into_inner()) = Arc::new(config);
+    *self.loaded_at.write().unwrap_or_else(|e| e.into_inner()) = files_modified_at(&self.settings);
+    Ok(())
+  }
+
+  fn files_changed(&self) -> bool {
+    let loaded_at = *self.loaded_at.read().unwrap_or_else(|e| e.into_inner());
+    files_modified_at(&self.settings) != loaded_at
+  }
+
+  /// Poll the certificate files and listen for `SIGHUP`, reloading on either
+  pub fn watch(self: Arc<Self>) {
+    tokio::spawn(async move {
+      let mut interval = tokio::time::interval(self.settings.reload_interval);
+      #[cfg(unix)]
+      let mut hangup = match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup())
+      {
+        Ok(hangup) => Some(hangup),
+        Err(error) => {
+          tracing::warn!(%error, "Failed to listen for SIGHUP, relying on file polling");
+          None
+        }
+      };
+
+      loop {
+        #[cfg(unix)]
+        let forced = tokio::select! {
+          _ = interval.tick() => false,
+          Some(_) = async { hangup.as_mut()?.recv().await } => true,
+        };
+        #[cfg(not(unix))]
+        let forced = {
+          interval.tick().await;
+          false
+        };
+
+        if forced || self.files_changed() {
+          match self.reload() {
+            Ok(()) => tracing::info!(
+              cert_path = %self.settings.cert_path.display(),
+              "Reloaded TLS certificate"
+            ),
+            Err(error) => tracing::error!(%error, "Keeping previous TLS certificate"),
+          }
+        }
+      }
+    });
+  }
+}
+
+pub(crate) fn load_certified_key(
+  cert_path: &PathBuf,
+  key_path: &PathBuf,
+  provider: &CryptoProvider,
+) -> Result<CertifiedKey, TlsError> {
+  let cert_pem = read(cert_path)?;
+  let certs = CertificateDer::pem_slice_iter(&cert_pem)
+    .collect::<Result<Vec<_>, _>>()
+    .map_err(|source| TlsError::Pem { path: cert_path.clone(), source })?;
+  if certs.is_empty() {
+    return Err(TlsError::NoCertificate(cert_path.clone()));
+  }
+
+  let key_pem = read(key_path)?;
+  let key = PrivateKeyDer::from_pem_slice(&key_pem)
+    .map_err(|source| TlsError::Pem { path: key_path.clone(), source })?;
+  Ok(CertifiedKey::from_der(certs, key, provider)?)
+}
+
+/// Picks the certificate for the requested server name, except for ACME validators probing
+/// a pending TLS-ALPN-01 challenge
+#[derive(Debug)]
+struct CertResolver {
+  certified_key: Arc<CertifiedKey>,
+  /// lowercase server name or `*.`-wildcard -> certificate
+  by_server_name: HashMap<String, Arc<CertifiedKey>>,
+  alpn_challenges: Option<AlpnChallenges>,
+}
+
+impl CertResolver {
+  fn load(
+    settings: &TlsSettings,
+    alpn_challenges: Option<&AlpnChallenges>,
+    provider: &CryptoProvider,
+  ) -> Result<Self, TlsError> {
+    let certified_key = load_certified_key(&settings.cert_path, &settings.key_path, provider)?;
+    let by_server_name = settings
+      .certificates
+      .iter()
+      .map(|certificate| {
+        let certified_key =
+          load_certified_key(&certificate.cert_path, &certificate.key_path, provider)?;
+        Ok((
+          certificate.server_name.to_ascii_lowercase(),
+          Arc::new(certified_key),
+        ))
+      })
+      .collect::<Result<_, TlsError>>()?;
+    Ok(Self {
+      certified_key: Arc::new(certified_key),
+      by_server_name,
+      alpn_challenges: alpn_challenges.cloned(),
+    })
+  }
+
+  fn select(&self, server_name: Option<&str>) -> Arc<CertifiedKey> {
+    let server_name = server_name.map(str::to_ascii_lowercase);
+    let exact = server_name.as_deref();
+    let wildcard = exact
+      .and_then(|name| name.split_once('.'))
+      .map(|(_, parent)| format!("*.{}", parent));
+    exact
+      .and_then(|name| self.by_server_name.get(name))
+      .or_else(|| wildcard.and_then(|name| self.by_server_name.get(&name)))
+      .unwrap_or(&self.certified_key)
+      .clone()
+  }
+}
+
+impl ResolvesServerCert for CertResolver {
+  fn resolve(&self, client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+    let acme_validation = client_hello
+      .alpn()
+      .is_some_and(|mut protocols| protocols.any(|protocol| protocol == ACME_TLS_ALPN_NAME));
+    if !acme_validation {
+      return Some(self.select(client_hello.server_name()));
+    }
+
+    let challenges = self.alpn_challenges.as_ref()?;
+    let challenges = challenges.read().unwrap_or_else(|e| e.into_inner());
+    challenges.get(client_hello.server_name()?).cloned()
+  }
+}
+
+fn build_server_config(
+  settings: &TlsSettings,
+  alpn_challenges: Option<&AlpnChallenges>,
+) -> Result<ServerConfig, TlsError> {
+  let provider = Arc::new(crypto_provider(settings)?);
+  let resolver = CertResolver::load(settings, alpn_challenges, &provider)?;
+  let versions: &[&SupportedProtocolVersion] = match settings.min_version {
+    TlsVersion::Tls12 => &[&version::TLS13, &version::TLS12],
+    TlsVersion::Tls13 => &[&version::TLS13],
+  };
+  let mut config = ServerConfig::builder_with_provider(provider)
+    .with_protocol_versions(versions)?
+    .with_no_client_auth()
+    .with_cert_resolver(Arc::new(resolver));
+
+  config.ignore_client_order = !settings.cipher_suites.is_empty();
+  config.alpn_protocols = settings
+    .alpn_protocols
+    .iter()
+    .map(|protocol| protocol.as_bytes().to_vec())
+    .collect();
+  if alpn_challenges.is_some() {
+    // validators only offer acme-tls/1, so it has to be negotiable next to the others
+    config.alpn_protocols.push(ACME_TLS_ALPN_NAME.to_vec());
+  }
+  if settings.session_tickets {
+    config.ticketer = ring::Ticketer::new()?;
+  }
+  config.session_storage = match settings.session_cache_size {
+    0 => Arc::new(NoServerSessionStorage {}),
+    size => ServerSessionMemoryCache::new(size),
+  };
+  Ok(config)
+}
+
+/// The ring provider, limited to and ordered by the configured cipher suites
+fn crypto_provider(settings: &TlsSettings) -> Result<CryptoProvider, TlsError> {
+  let mut provider = ring::default_provider();
+  if settings.cipher_suites.is_empty() {
+    return Ok(provider);
+  }
+
+  provider.cipher_suites = settings
+    .cipher_suites
+    .iter()
+    .map(|name| {
+      provider
+        .cipher_suites
+        .iter()
+        .find(|suite| format!("{:?}", suite.suite()).eq_ignore_ascii_case(name))
+        .copied()
+        .ok_or_else(|| TlsError::UnknownCipherSuite(name.clone()))
+    })
+    .collect::<Result<_, _>>()?;
+  Ok(provider)
+}
+
+fn read(path: &PathBuf) -> Result<Vec<u8>, TlsError> {
+  fs::read(path).map_err(|source| TlsError::Io { path: path.clone(), source })
+}
+
+/// The latest modification time of all certificate and key files, `None` if any can't be read
+fn files_modified_at(settings: &TlsSettings) -> Option<SystemTime> {
+  settings
+    .files()
+    .map(|path| {
+      fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+    })
+    .try_fold(SystemTime::UNIX_EPOCH, |latest, modified| {
+      Some(latest.max(modified?))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use expectest::prelude::*;
+  use rstest::*;
+  use tempfile::TempDir;
+
+  fn write_certificate(dir: &TempDir, domain: &str) -> TlsSettings {
+    let (cert_path, key_path) = write_named_certificate(dir, "cert", domain);
+    TlsSettings::new(cert_path, key_path)
+  }
+
+  fn write_named_certificate(dir: &TempDir, name: &str, domain: &str) -> (PathBuf, PathBuf) {
+    let certified_key = rcgen::generate_simple_self_signed(vec![domain.to_string()]).unwrap();
+    let cert_path = dir.path().join(format!("{}.pem", name));
+    let key_path = dir.path().join(format!("{}.key.pem", name));
+    fs::write(&cert_path, certified_key.cert.pem()).unwrap();
+    fs::write(&key_path, certified_key.signing_key.serialize_pem()).unwrap();
+    (cert_path, key_path)
+  }
+
+  #[rstest]
+  #[case::exact(Some("example.org"), "example.org")]
+  #[case::case_insensitive(Some("Example.ORG"), "example.org")]
+  #[case::wildcard(Some("www.example.net"), "*.example.net")]
+  #[case::wildcard_covers_one_label_only(Some("a.b.example.net"), "default")]
+  #[case::unknown(Some("example.com"), "default")]
+  #[case::no_sni(None, "default")]
+  fn test_select_certificate_by_server_name(
+    #[case] server_name: Option<&str>,
+    #[case] expected: &str,
+  ) -> Result<(), TlsError> {
+    let dir = TempDir::new().unwrap();
+    let (org_cert, org_key) = write_named_certificate(&dir, "org", "example.org");
+    let (net_cert, net_key) = write_named_certificate(&dir, "net", "*.example.net");
+    let settings = write_certificate(&dir, "localhost")
+      .with_certificate("example.org", org_cert, org_key)
+      .with_certificate("*.example.net", net_cert, net_key);
+
+    let resolver = CertResolver::load(&settings, None, &ring::default_provider())?;
+    let expected = match expected {
+      "default" => &resolver.certified_key,
+      name => &resolver.by_server_name[name],
+    };
+
+    expect!(Arc::ptr_eq(&resolver.select(server_name), expected)).to(be_true());
+    Ok(())
+  }
+
+  #[rstest]
+  fn test_reload_swaps_configuration() -> Result<(), TlsError> {
+    let dir = TempDir::new().unwrap();
+    let tls_config = ReloadableTlsConfig::load(write_certificate(&dir, "localhost"))?;
+    let before = tls_config.current();
+
+    write_certificate(&dir, "example.com");
+    tls_config.reload()?;
+
+    expect!(Arc::ptr_eq(&before, &tls_config.current())).to(be_false());
+    Ok(())
+  }
+
+  #[rstest]
+  fn test_failed_reload_keeps_previous_configuration() -> Result<(), TlsError> {
+    let dir = TempDir::new().unwrap();
+    let settings = write_certificate(&dir, "localhost");
+    let tls_config = ReloadableTlsConfig::load(settings.clone())?;
+    let before = tls_config.current();
+
+    fs::write(&settings.cert_path, "not a certificate").unwrap();
+
+    expect!(tls_config.reload()).to(be_err());
+    expect!(Arc::ptr_eq(&before, &tls_config.current())).to(be_true());
+    Ok(())
+  }
+
+  #[rstest]
+  fn test_tuned_configuration() -> Result<(), TlsError> {
+    let dir = TempDir::new().unwrap();
+    let settings = write_certificate(&dir, "localhost")
+      .min_version(TlsVersion::Tls13)
+      .cipher_suites(vec![
+        "tls13_chacha20_poly1305_sha256",
+        "TLS13_AES_256_GCM_SHA384",
+      ])
+      .alpn_protocols(vec!["h2", "http/1.1"])
+      .session_tickets(false)
+      .session_cache_size(0);
+    let config = build_server_config(&settings, None)?;
+
+    let suites = config
+      .crypto_provider()
+      .cipher_suites
+      .iter()
+      .map(|suite| format!("{:?}", suite.suite()))
+      .collect::<Vec<_>>();
+    expect!(suites).to(be_equal_to(vec![
+      "TLS13_CHACHA20_POLY1305_SHA256",
+      "TLS13_AES_256_GCM_SHA384",
+    ]));
+    expect!(config.ignore_client_order).to(be_true());
+    expect!(config.alpn_protocols).to(be_equal_to(vec![b"h2".to_vec(), b"http/1.1".to_vec()]));
+    expect!(config.ticketer.enabled()).to(be_false());
+    expect!(config.session_storage.can_cache()).to(be_false());
+    Ok(())
+  }
+
+  #[rstest]
+  fn test_reject_unknown_cipher_suite() {
+    let dir = TempDir::new().unwrap();
+    let settings = write_certificate(&dir, "localhost").cipher_suites(vec!["TLS_RSA_WITH_RC4"]);
+    expect!(build_server_config(&settings, None).is_err()).to(be_true());
+  }
+
+  #[rstest]
+  fn test_missing_files_fail_to_load() {
+    let dir = TempDir::new().unwrap();
+    let settings = TlsSettings::new(dir.path().join("cert.pem"), dir.path().join("key.pem"));
+    expect!(ReloadableTlsConfig::load(settings).is_err()).to(be_true());
+  }
+}
@@ -0,0 +1,251 @@
+use bytes::Bytes;
+use http_body::Body;
+use http_body_util::{BodyExt, Full};
+use serde_json::{json, Value};
+use std::{fmt::Display, future::poll_fn, io::Read, sync::Arc};
+use tower_service::Service;
+
+use crate::http::{
+  header::{HttpResponseHeaderBuilder, HttpResponseHeaderKey},
+  HandlerError, HttpRequest, HttpResponse, Method, RequestBody, StatusCode,
+};
+use crate::server::{self, Handler, HandlerFuture, HandlerResult};
+
+/// What mounted services are called with: the request head, along with the whole body
+pub type TowerRequest = http::Request<Full<Bytes>>;
+
+/// Response headers describing the connection or the body's encoding on the way in, which
+/// no longer apply once the body has been collected
+const HOP_BY_HOP_HEADERS: [HttpResponseHeaderKey; 4] = [
+  HttpResponseHeaderKey::Connection,
+  HttpResponseHeaderKey::ContentLength,
+  HttpResponseHeaderKey::KeepAlive,
+  HttpResponseHeaderKey::TransferEncoding,
+];
+
+/// Mounts a [`tower_service::Service`], e.g. an axum `Router` or a hyper service, as a
+/// [`Handler`], so it's served behind this server's listeners, TLS, limits and logging.
+///
+/// Services run on the server's runtime: bodyless requests await them through
+/// [`Handler::handle_async`], the blocking paths wait for them on the thread they're called on.
+pub struct TowerHandler<S> {
+  service: S,
+}
+
+impl<S> TowerHandler<S> {
+  pub fn new(service: S) -> Self {
+    Self { service }
+  }
+}
+
+impl<S, B> TowerHandler<S>
+where
+  S: Service<TowerRequest, Response = http::Response<B>> + Clone + Send + Sync + 'static,
+  S::Future: Send + 'static,
+  S::Error: Display,
+  B: Body + Send + 'static,
+  B::Data: Send,
+  B::Error: Display,
+{
+  async fn call(&self, request: &HttpRequest<'_>, body: Bytes) -> HandlerResult {
+    let request = to_http_request(request, body)
+      .map_err(|error| HandlerError::bad_request("Failed to convert request").with_source(error))?;
+    let failed =
+      |error: String| HandlerError::internal(format!("Mounted service failed: {}", error));
+
+    let mut service = self.service.clone();
+    poll_fn(|cx| service.poll_ready(cx))
+      .await
+      .map_err(|e| failed(e.to_string()))?;
+    let (parts, body) = service
+      .call(request)
+      .await
+      .map_err(|e| failed(e.to_string()))?
+      .into_parts();
+    let body = body
+      .collect()
+      .await
+      .map_err(|e| failed(e.to_string()))?
+      .to_bytes();
+    Ok(from_http_response(parts, body))
+  }
+}
+
+fn to_http_request(request: &HttpRequest<'_>, body: Bytes) -> Result<TowerRequest, http::Error> {
+  let uri = match request.query_string() {
+    Some(query_string) => format!("{}?{}", request.raw_path(), query_string.raw()),
+    None => request.raw_path().to_string(),
+  };
+  let builder = http::Request::builder()
+    .method(request.method().to_string().as_str())
+    .uri(uri);
+  let builder = request
+    .header()
+    .iter()
+    .fold(builder, |builder, (key, value)| {
+      builder.header(key.as_str(), value.as_str())
+    });
+  builder.body(Full::new(body))
+}
+
+fn from_http_response(parts: http::response::Parts, body: Bytes) -> HttpResponse {
+  let status_code = StatusCode::from_u16(parts.status.as_u16()).unwrap_or_else(|| {
+    tracing::error!(status = %parts.status, "Mounted service answered with unsupported status");
+    StatusCode::InternalError
+  });
+
+  let mut builder = HttpResponseHeaderBuilder::new();
+  for name in parts.headers.keys() {
+    if HOP_BY_HOP_HEADERS
+      .iter()
+      .any(|hop_by_hop| name.as_str().eq_ignore_ascii_case(hop_by_hop.as_ref()))
+    {
+      continue;
+    }
+    // one value per name here, repeated ones are combined as RFC 9110 (section 5.3) allows
+    let value = parts
+      .headers
+      .get_all(name)
+      .iter()
+      .map(|value| String::from_utf8_lossy(value.as_bytes()).into_owned())
+      .collect::<Vec<_>>()
+      .join(", ");
+    builder.custom(name.as_str().to_string(), &value);
+  }
+
+  let body = Some(body.to_vec()).filter(|body| !body.is_empty());
+  HttpResponse::new(status_code, body, Some(Arc::new(builder.build())))
+}
+
+impl<S, B> Handler for TowerHandler<S>
+where
+  S: Service<TowerRequest, Response = http::Response<B>> + Clone + Send + Sync + 'static,
+  S::Future: Send + 'static,
+  S::Error: Display,
+  B: Body + Send + 'static,
+  B::Data: Send,
+  B::Error: Display,
+{
+  fn handle_request(&self, request: &HttpRequest<'_>) -> HandlerResult {
+    server::block_on(self.call(request, Bytes::new()))?
+  }
+
+  fn handle_async<'a>(&'a self, request: &'a HttpRequest<'a>) -> Option<HandlerFuture<'a>> {
+    Some(Box::pin(self.call(request, Bytes::new())))
+  }
+
+  /// The body is read entirely before the service is called
+  fn handle_with_body(&self, request: &HttpRequest<'_>, mut body: RequestBody) -> HandlerResult {
+    let mut buffered = Vec::new();
+    body.read_to_end(&mut buffered).map_err(|error| {
+      HandlerError::bad_request("Failed to read request body").with_source(error)
+    })?;
+    server::block_on(self.call(request, Bytes::from(buffered)))?
+  }
+
+  fn allowed_methods(&self) -> Vec<Method> {
+    vec![Method::GET, Method::POST, Method::PUT, Method::DELETE, Method::PATCH]
+  }
+
+  fn describe(&self) -> Value {
+    json!({
+      "handler": "TowerHandler",
+      "service": std::any::type_name::<S>(),
+    })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use expectest::prelude::*;
+  use rstest::*;
+  use std::{
+    future::{ready, Ready},
+    task::{Context, Poll},
+  };
+
+  /// Answers with the request's method and target in headers, and its body
+  #[derive(Clone)]
+  struct EchoService;
+
+  impl Service<TowerRequest> for EchoService {
+    type Response = http::Response<Full<Bytes>>;
+    type Error = http::Error;
+    type Future = Ready<Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+      Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: TowerRequest) -> Self::Future {
+      let response = http::Response::builder()
+        .status(404)
+        .header("x-method", request.method().as_str())
+        .header("x-target", request.uri().to_string())
+        .header("content-length", "999")
+        .header("vary", "accept")
+        .header("vary", "user-agent")
+        .body(request.into_body());
+      ready(response)
+    }
+  }
+
+  fn respond(request: &[u8], body: Option<&[u8]>) -> HandlerResult {
+    let handler = TowerHandler::new(EchoService);
+    let request = HttpRequest::try_from(request).unwrap();
+    match body {
+      Some(body) => {
+        let (body, _sender) = RequestBody::channel(body, body.len() as u64);
+        handler.handle_with_body(&request, body)
+      }
+      None => handler.handle_request(&request),
+    }
+  }
+
+  #[rstest]
+  #[tokio::test(flavor = "multi_thread")]
+  async fn test_convert_request_and_response() {
+    let response = respond(
+      b"POST /api/items?page=2 HTTP/1.1\r\nHost: localhost\r\n\r\n",
+      Some(b"{\"name\":\"x\"}"),
+    )
+    .unwrap();
+
+    expect!(*response.status_code()).to(be_equal_to(StatusCode::NotFound));
+    expect!(response.body().as_deref()).to(be_some().value(&b"{\"name\":\"x\"}"[..]));
+    let header = response.http_header().as_ref().unwrap();
+    expect!(header.get("x-method")).to(be_some().value("POST"));
+    expect!(header.get("x-target")).to(be_some().value("/api/items?page=2"));
+    expect!(header.get("vary")).to(be_some().value("accept, user-agent"));
+    // recomputed from the converted body when sending
+    expect!(header.get(HttpResponseHeaderKey::ContentLength)).to(be_none());
+  }
+
+  #[rstest]
+  #[tokio::test]
+  async fn test_call_asynchronously() {
+    let handler = TowerHandler::new(EchoService);
+    let request = HttpRequest::try_from(&b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n"[..]).unwrap();
+    let response = handler.handle_async(&request).unwrap().await.unwrap();
+    expect!(*response.status_code()).to(be_equal_to(StatusCode::NotFound));
+    expect!(response.body().as_deref()).to(be_none());
+  }
+
+  #[rstest]
+  #[tokio::test]
+  async fn test_call_from_blocking_thread() {
+    let response = tokio::task::spawn_blocking(|| {
+      respond(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n", None).unwrap()
+    })
+    .await
+    .unwrap();
+    expect!(*response.status_code()).to(be_equal_to(StatusCode::NotFound));
+  }
+
+  #[rstest]
+  fn test_call_without_runtime() {
+    let error = respond(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n", None).unwrap_err();
+    expect!(error.status_code()).to(be_equal_to(StatusCode::ServiceUnavailable));
+  }
+}
@@ -0,0 +1,178 @@
+use std::{future::Future, io, net::SocketAddr};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::{TcpListener, TcpStream};
+
+/// Where [`crate::server::Server`] takes connections from. TCP through tokio is what
+/// [`crate::server::Server::run`] binds; other transports, such as sockets handed over by a
+/// wasm32-wasi host or an in-memory pipe, implement this to be served with
+/// [`crate::server::Server::serve`] by the same parsing, limits and handlers.
+pub trait Listener: Send + Sync + 'static {
+  type Stream: AsyncRead + AsyncWrite + Unpin + Send + 'static;
+
+  /// The next connection, with the peer address access logs, rate limits and hooks key on.
+  /// Transports without one report an unspecified address.
+  fn accept(&self) -> impl Future<Output = io::Result<(Self::Stream, SocketAddr)>> + Send;
+
+  /// The server's end of an accepted connection, reported to handlers in
+  /// [`crate::http::ConnectionInfo::local_addr`]
+  fn local_addr(&self, _stream: &Self::Stream) -> Option<SocketAddr> {
+    None
+  }
+}
+
+impl Listener for TcpListener {
+  type Stream = TcpStream;
+
+  async fn accept(&self) -> io::Result<(TcpStream, SocketAddr)> {
+    TcpListener::accept(self).await
+  }
+
+  fn local_addr(&self, stream: &TcpStream) -> Option<SocketAddr> {
+    stream.local_addr().ok()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::http::{HttpRequest, HttpResponse, StatusCode};
+  use crate::server::{Handler, HandlerResult, Server};
+  use expectest::prelude::*;
+  use rstest::*;
+  use std::{
+    net::{Ipv4Addr, SocketAddrV4},
+    sync::Arc,
+  };
+  use tokio::{
+    io::{duplex, AsyncReadExt, AsyncWriteExt, DuplexStream},
+    sync::{mpsc, Mutex},
+  };
+
+  /// Connections made through in-memory pipes
+  struct MemoryListener {
+    connections: Mutex<mpsc::Receiver<DuplexStream>>,
+  }
+
+  impl Listener for MemoryListener {
+    type Stream = DuplexStream;
+
+    async fn accept(&self) -> io::Result<(DuplexStream, SocketAddr)> {
+      let stream = self.connections.lock().await.recv().await;
+      let peer = SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, 0));
+      stream
+        .map(|stream| (stream, peer))
+        .ok_or_else(|| io::Error::from(io::ErrorKind::ConnectionAborted))
+    }
+  }
+
+  struct NoContentHandler;
+
+  impl Handler for NoContentHandler {
+    fn handle_request(&self, _request: &HttpRequest) -> HandlerResult {
+      Ok(HttpResponse::empty_body(StatusCode::NoContent))
+    }
+  }
+
+  #[rstest]
+  #[tokio::test]
+  async fn test_serve_other_transport() -> io::Result<()> {
+    let (connect, connections) = mpsc::channel(1);
+    let listener = MemoryListener { connections: Mutex::new(connections) };
+    let server =
+      tokio::spawn(Server::new("memory".to_string()).serve(listener, Arc::new(NoContentHandler)));
+
+    let (mut client, stream) = duplex(1024);
+    connect.send(stream).await.unwrap();
+    client
+      .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+      .await?;
+    let mut response = String::new();
+    client.read_to_string(&mut response).await?;
+    expect!(response.starts_with("HTTP/1.1 204 No Content\r\n")).to(be_true());
+
+    // no more connections
+    drop(connect);
+    expect!(server.await.unwrap().is_err()).to(be_true());
+    Ok(())
+  }
+
+  /// Answers with the addresses of the request's connection
+  struct AddressHandler;
+
+  impl Handler for AddressHandler {
+    fn handle_request(&self, request: &HttpRequest) -> HandlerResult {
+      let addresses = request.connection().map(|connection| {
+        let local_addr = connection
+          .local_addr
+          .map(|local_addr| local_addr.to_string());
+        format!(
+          "{} {}",
+          connection.peer_addr,
+          local_addr.unwrap_or_default()
+        )
+      });
+      Ok(HttpResponse::new(
+        StatusCode::Ok,
+        addresses.map(String::into_bytes),
+        None,
+      ))
+    }
+  }
+
+  #[rstest]
+  #[tokio::test]
+  async fn test_connection_info() -> io::Result<()> {
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let address = listener.local_addr()?;
+    tokio::spawn(Server::new("tcp".to_string()).serve(listener, Arc::new(AddressHandler)));
+
+    let mut client = TcpStream::connect(address).await?;
+    client
+      .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+      .await?;
+    let mut response = String::new();
+    client.read_to_string(&mut response).await?;
+    let expected_body = format!("{} {}", client.local_addr()?, address);
+    expect!(response.ends_with(&format!("\r\n\r\n{}", expected_body))).to(be_true());
+    Ok(())
+  }
+
+  #[rstest]
+  #[case::proxied(
+    "PROXY TCP4 192.0.2.1 198.51.100.1 56324 443\r\n",
+    Some("192.0.2.1:56324 198.51.100.1:443")
+  )]
+  #[case::health_check("PROXY UNKNOWN\r\n", Some("0.0.0.0:0 "))]
+  #[case::missing("", None)]
+  #[tokio::test]
+  async fn test_proxy_protocol(
+    #[case] proxy_header: &str,
+    #[case] expected_body: Option<&str>,
+  ) -> io::Result<()> {
+    let (connect, connections) = mpsc::channel(1);
+    let listener = MemoryListener { connections: Mutex::new(connections) };
+    let server = Server::new("memory".to_string()).proxy_protocol();
+    tokio::spawn(server.serve(listener, Arc::new(AddressHandler)));
+
+    let (mut client, stream) = duplex(1024);
+    connect.send(stream).await.unwrap();
+    let request = format!(
+      "{}GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+      proxy_header
+    );
+    client.write_all(request.as_bytes()).await?;
+    let mut response = String::new();
+    client.read_to_string(&mut response).await?;
+    match expected_body {
+      Some(expected_body) => {
+        expect!(response.starts_with("HTTP/1.1 200 Ok\r\n")).to(be_true());
+        expect!(response.ends_with(&format!("\r\n\r\n{}", expected_body))).to(be_true());
+      }
+      // closed without an answer
+      None => {
+        expect!(response.as_str()).to(be_equal_to(""));
+      }
+    }
+    Ok(())
+  }
+}
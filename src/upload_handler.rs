@@ -0,0 +1,447 @@
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::{
+  io::{self, Read},
+  path::PathBuf,
+  sync::Arc,
+};
+
+use crate::filesystem::{FileSystem, Written};
+use crate::http::{
+  header::{HttpResponseHeaderBuilder, HttpResponseHeaderKey},
+  multipart::{self, MultipartError, MultipartLimits, MultipartReader},
+  percent_encoding::encode_segment,
+  HandlerError, HttpRequest, HttpResponse, Method, RequestBody, StatusCode,
+};
+use crate::server::{Handler, HandlerFuture, HandlerResult};
+
+/// Accept uploads below a path prefix, e.g.
+///
+/// ```toml
+/// [uploads]
+/// path = "/uploads"
+/// directory = "/srv/uploads"
+/// max_file_size = 104857600
+/// overwrite = true
+/// ```
+///
+/// stores `PUT /uploads/reports/q1.pdf` at `/srv/uploads/reports/q1.pdf`
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct UploadSettings {
+  #[serde(default = "default_path")]
+  pub path: String,
+  /// where uploaded files are stored; within the public path, they're served as well
+  pub directory: PathBuf,
+  /// bytes per file, 10 MiB by default; bodies are bounded by [`crate::limits::Limits`] too
+  #[serde(default = "default_max_file_size")]
+  pub max_file_size: u64,
+  /// replace existing files rather than answering `409 Conflict`
+  #[serde(default)]
+  pub overwrite: bool,
+}
+
+fn default_path() -> String {
+  "/uploads".to_string()
+}
+
+fn default_max_file_size() -> u64 {
+  10 * 1024 * 1024
+}
+
+/// Stores files sent below [`UploadSettings::path`] in `file_system`, turning the server into
+/// a minimal drop target:
+///
+/// - `PUT <path>/<file>` stores the body as `<file>`, answering `201 Created` with its
+///   `Location`, or `204 No Content` if it replaced one
+/// - `POST <path>/<directory>/` with a `multipart/form-data` body stores every part that has a
+///   filename in that directory, answering `201 Created` with their paths, one per line
+///
+/// Hidden names are refused with `403 Forbidden`, as they aren't served either. Other
+/// requests, including `GET` for uploaded files, go to `fallback`.
+pub struct UploadHandler {
+  /// `path` without a trailing slash
+  prefix: String,
+  max_file_size: u64,
+  overwrite: bool,
+  file_system: Arc<dyn FileSystem + Send + Sync>,
+  fallback: Arc<dyn Handler>,
+}
+
+impl UploadHandler {
+  pub fn new(
+    settings: &UploadSettings,
+    file_system: Arc<dyn FileSystem + Send + Sync>,
+    fallback: Arc<dyn Handler>,
+  ) -> Self {
+    Self {
+      prefix: settings.path.trim_end_matches('/').to_string(),
+      max_file_size: settings.max_file_size,
+      overwrite: settings.overwrite,
+      file_system,
+      fallback,
+    }
+  }
+
+  /// The path `request` uploads to, relative to the upload directory; `None` if it isn't an
+  /// upload
+  fn upload_path<'a>(&self, request: &'a HttpRequest<'_>) -> Option<&'a str> {
+    if !matches!(*request.method(), Method::PUT | Method::POST) {
+      return None;
+    }
+    let rest = request.path().strip_prefix(&self.prefix)?;
+    match rest {
+      "" => Some(rest),
+      _ => rest.strip_prefix('/'),
+    }
+  }
+
+  fn upload(&self, request: &HttpRequest<'_>, file_path: &str, body: RequestBody) -> HandlerResult {
+    if file_path.split('/').any(|segment| segment.starts_with('.')) {
+      return Err(HandlerError::new(
+        StatusCode::Forbidden,
+        "Upload to a hidden path",
+      ));
+    }
+    match *request.method() {
+      Method::PUT => self.put(file_path, body),
+      _ => self.post(request, file_path, body),
+    }
+  }
+
+  fn put(&self, file_path: &str, body: RequestBody) -> HandlerResult {
+    if file_path.is_empty() || file_path.ends_with('/') {
+      return Err(HandlerError::new(
+        StatusCode::Conflict,
+        "PUT to a directory",
+      ));
+    }
+    // refused before reading, chunked bodies are counted as they arrive
+    if body.len() > self.max_file_size {
+      return Err(HandlerError::new(
+        StatusCode::PayloadTooLarge,
+        "Upload over the maximum file size",
+      ));
+    }
+    let mut contents = SizeLimited { reader: body, remaining: self.max_file_size };
+    match self
+      .file_system
+      .write_file(file_path, &mut contents, self.overwrite)
+    {
+      Ok(Written::Created) => Ok(
+        HttpResponse::builder()
+          .status(StatusCode::Created)
+          .header(HttpResponseHeaderKey::Location, &self.location(file_path))
+          .empty()
+          .unwrap_or_else(HttpResponse::from),
+      ),
+      Ok(Written::Replaced) => Ok(HttpResponse::empty_body(StatusCode::NoContent)),
+      Err(error) => Err(Self::failed(file_path, error)),
+    }
+  }
+
+  /// Parts stored before one fails stay stored
+  fn post(&self, request: &HttpRequest<'_>, dir_path: &str, body: RequestBody) -> HandlerResult {
+    let boundary = multipart::boundary(request.header()).map_err(Self::invalid_multipart)?;
+    let limits = MultipartLimits {
+      max_part_size: self.max_file_size,
+      ..MultipartLimits::default()
+    };
+    let mut reader = MultipartReader::new(body, &boundary, limits);
+    let mut locations = Vec::new();
+    loop {
+      let Some(part) = reader.next_part().map_err(Self::invalid_multipart)? else {
+        break;
+      };
+      // form fields other than files are skipped
+      let Some(file_name) = part.filename().map(base_name) else {
+        continue;
+      };
+      if file_name.is_empty() || file_name.starts_with('.') {
+        return Err(HandlerError::new(
+          StatusCode::Forbidden,
+          "Upload of a hidden file",
+        ));
+      }
+      let file_path = format!("{}{}", dir_path_prefix(dir_path), file_name);
+      self
+        .file_system
+        .write_file(&file_path, &mut reader, self.overwrite)
+        .map_err(|error| Self::failed(&file_path, error))?;
+      locations.push(self.location(&file_path));
+    }
+    let Some(location) = locations.first() else {
+      return Err(HandlerError::bad_request("Multipart body without files"));
+    };
+
+    let body = locations.iter().map(|location| format!("{}\n", location));
+    let body = body.collect::<String>();
+    let mut builder = HttpResponseHeaderBuilder::new();
+    builder.content_type("text/plain; charset=utf-8");
+    builder.content_length(&body.len().to_string());
+    Ok(
+      HttpResponse::builder()
+        .status(StatusCode::Created)
+        .http_header(Arc::new(builder.build()))
+        .header(HttpResponseHeaderKey::Location, location)
+        .body(body)
+        .unwrap_or_else(HttpResponse::from),
+    )
+  }
+
+  fn invalid_multipart(error: MultipartError) -> HandlerError {
+    HandlerError::new(error.status_code(), "Invalid multipart body").with_source(error)
+  }
+
+  /// The URL path `file_path` is uploaded to
+  fn location(&self, file_path: &str) -> String {
+    let segments = file_path.split('/').map(encode_segment);
+    let segments = segments.collect::<Vec<_>>();
+    format!("{}/{}", self.prefix, segments.join("/"))
+  }
+
+  fn failed(file_path: &str, error: io::Error) -> HandlerError {
+    let multipart_error = error
+      .get_ref()
+      .and_then(|error| error.downcast_ref::<MultipartError>());
+    let status_code = match (multipart_error, error.kind()) {
+      (Some(multipart_error), _) => multipart_error.status_code(),
+      (_, io::ErrorKind::FileTooLarge) => StatusCode::PayloadTooLarge,
+      (_, io::ErrorKind::AlreadyExists | io::ErrorKind::IsADirectory) => StatusCode::Conflict,
+      (_, io::ErrorKind::PermissionDenied) => StatusCode::Forbidden,
+      (_, io::ErrorKind::Unsupported) => StatusCode::MethodNotAllowed,
+      _ => StatusCode::InternalError,
+    };
+    HandlerError::new(status_code, format!("Failed to store upload {}", file_path))
+      .with_source(error)
+  }
+}
+
+/// The last segment of a client's file name, which browsers may send as a full Windows path
+fn base_name(file_name: &str) -> &str {
+  file_name.rsplit(['/', '\\']).next().unwrap_or_default()
+}
+
+/// `dir_path` with a trailing slash, empty for the upload directory
+fn dir_path_prefix(dir_path: &str) -> String {
+  match dir_path.trim_matches('/') {
+    "" => String::new(),
+    dir_path => format!("{}/", dir_path),
+  }
+}
+
+/// Fails with [`io::ErrorKind::FileTooLarge`] once more than `remaining` bytes are read
+struct SizeLimited<R> {
+  reader: R,
+  remaining: u64,
+}
+
+impl<R: Read> Read for SizeLimited<R> {
+  fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+    let read = self.reader.read(buf)?;
+    self.remaining = self
+      .remaining
+      .checked_sub(read as u64)
+      .ok_or(io::ErrorKind::FileTooLarge)?;
+    Ok(read)
+  }
+}
+
+impl Handler for UploadHandler {
+  /// Uploads without a body store empty files
+  fn handle_request(&self, request: &HttpRequest<'_>) -> HandlerResult {
+    match self.upload_path(request) {
+      Some(file_path) => self.upload(request, file_path, RequestBody::empty()),
+      None => self.fallback.handle_request(request),
+    }
+  }
+
+  fn handle_async<'a>(&'a self, request: &'a HttpRequest<'a>) -> Option<HandlerFuture<'a>> {
+    match self.upload_path(request) {
+      Some(_) => None,
+      None => self.fallback.handle_async(request),
+    }
+  }
+
+  /// The body is streamed to the file system, only a few chunks of it are held in memory
+  fn handle_with_body(&self, request: &HttpRequest<'_>, body: RequestBody) -> HandlerResult {
+    match self.upload_path(request) {
+      Some(file_path) => self.upload(request, file_path, body),
+      None => self.fallback.handle_with_body(request, body),
+    }
+  }
+
+  fn allowed_methods(&self) -> Vec<Method> {
+    let mut methods = self.fallback.allowed_methods();
+    for method in [Method::PUT, Method::POST] {
+      if !methods.contains(&method) {
+        methods.push(method);
+      }
+    }
+    methods
+  }
+
+  fn describe(&self) -> Value {
+    json!({
+      "handler": "UploadHandler",
+      "path": self.prefix,
+      "max_file_size": self.max_file_size,
+      "overwrite": self.overwrite,
+      "fallback": self.fallback.describe(),
+    })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::filesystem::LocalFileSystem;
+  use expectest::prelude::*;
+  use rstest::*;
+  use std::fs;
+  use tempfile::TempDir;
+
+  struct FallbackHandler;
+
+  impl Handler for FallbackHandler {
+    fn handle_request(&self, _request: &HttpRequest<'_>) -> HandlerResult {
+      Ok(HttpResponse::empty_body(StatusCode::Accepted))
+    }
+  }
+
+  fn upload_handler(dir: &TempDir, overwrite: bool) -> UploadHandler {
+    let settings = UploadSettings {
+      path: "/uploads/".to_string(),
+      directory: dir.path().to_path_buf(),
+      max_file_size: 8,
+      overwrite,
+    };
+    let file_system = LocalFileSystem::new(dir.path().to_string_lossy().to_string());
+    UploadHandler::new(&settings, Arc::new(file_system), Arc::new(FallbackHandler))
+  }
+
+  fn send(handler: &UploadHandler, head: &str, body: &[u8]) -> HttpResponse {
+    let raw_request = format!(
+      "{}\r\nHost: localhost\r\nContent-Length: {}\r\n\r\n",
+      head,
+      body.len()
+    );
+    let request = HttpRequest::try_from(raw_request.as_bytes()).unwrap();
+    let (body, _sender) = RequestBody::channel(body, body.len() as u64);
+    handler
+      .handle_with_body(&request, body)
+      .unwrap_or_else(HttpResponse::from)
+  }
+
+  #[rstest]
+  #[case::created("/uploads/docs/new%20file.txt", false, StatusCode::Created, Some("new"))]
+  #[case::replaced("/uploads/existing.txt", true, StatusCode::NoContent, Some("new"))]
+  #[case::kept("/uploads/existing.txt", false, StatusCode::Conflict, Some("old"))]
+  #[case::hidden("/uploads/.env", true, StatusCode::Forbidden, None)]
+  #[case::directory("/uploads/docs/", true, StatusCode::Conflict, None)]
+  fn test_put(
+    #[case] path: &str,
+    #[case] overwrite: bool,
+    #[case] expected_status: StatusCode,
+    #[case] expected_contents: Option<&str>,
+  ) -> io::Result<()> {
+    let dir = TempDir::new()?;
+    fs::write(dir.path().join("existing.txt"), "old")?;
+    let handler = upload_handler(&dir, overwrite);
+
+    let response = send(&handler, &format!("PUT {} HTTP/1.1", path), b"new");
+    expect!(*response.status_code()).to(be_equal_to(expected_status));
+    let file_path = dir
+      .path()
+      .join(path.trim_start_matches("/uploads/").replace("%20", " "));
+    let contents = fs::read_to_string(file_path).ok();
+    expect!(contents.as_deref()).to(be_equal_to(expected_contents));
+    if expected_status == StatusCode::Created {
+      let header = response.http_header().as_ref().unwrap();
+      let location = header.get(HttpResponseHeaderKey::Location);
+      expect!(location.map(String::as_str)).to(be_some().value(path));
+    }
+    Ok(())
+  }
+
+  #[rstest]
+  fn test_put_too_large() -> io::Result<()> {
+    let dir = TempDir::new()?;
+    let handler = upload_handler(&dir, true);
+
+    let response = send(&handler, "PUT /uploads/large.bin HTTP/1.1", b"0123456789");
+    expect!(*response.status_code()).to(be_equal_to(StatusCode::PayloadTooLarge));
+    // a body whose length isn't declared up front is cut off as it's read
+    let mut contents = SizeLimited { reader: &b"0123456789"[..], remaining: 8 };
+    let written = handler
+      .file_system
+      .write_file("large.bin", &mut contents, true);
+    expect!(written.map_err(|e| e.kind())).to(be_err().value(io::ErrorKind::FileTooLarge));
+    expect!(fs::read_dir(dir.path())?.count()).to(be_equal_to(0));
+    Ok(())
+  }
+
+  #[rstest]
+  fn test_post_multipart() -> io::Result<()> {
+    let dir = TempDir::new()?;
+    let handler = upload_handler(&dir, false);
+    let body = concat!(
+      "--x\r\n",
+      "Content-Disposition: form-data; name=\"comment\"\r\n\r\n",
+      "skipped\r\n",
+      "--x\r\n",
+      "Content-Disposition: form-data; name=\"file\"; filename=\"C:\\\\Users\\\\a.txt\"\r\n\r\n",
+      "first\r\n",
+      "--x\r\n",
+      "Content-Disposition: form-data; name=\"file\"; filename=\"b.txt\"\r\n\r\n",
+      "second\r\n",
+      "--x--\r\n",
+    );
+
+    let response = send(
+      &handler,
+      "POST /uploads/inbox/ HTTP/1.1\r\nContent-Type: multipart/form-data; boundary=x",
+      body.as_bytes(),
+    );
+    expect!(*response.status_code()).to(be_equal_to(StatusCode::Created));
+    let locations = String::from_utf8_lossy(response.body().as_deref().unwrap_or_default());
+    expect!(locations.as_ref()).to(be_equal_to("/uploads/inbox/a.txt\n/uploads/inbox/b.txt\n"));
+    expect!(fs::read_to_string(dir.path().join("inbox/a.txt"))?).to(be_equal_to("first"));
+    expect!(fs::read_to_string(dir.path().join("inbox/b.txt"))?).to(be_equal_to("second"));
+    Ok(())
+  }
+
+  #[rstest]
+  #[case::not_multipart("Content-Type: text/plain", StatusCode::UnsupportedMediaType)]
+  #[case::without_files("Content-Type: multipart/form-data; boundary=x", StatusCode::BadRequest)]
+  fn test_post_without_files(
+    #[case] content_type: &str,
+    #[case] expected_status: StatusCode,
+  ) -> io::Result<()> {
+    let dir = TempDir::new()?;
+    let handler = upload_handler(&dir, false);
+    let body = "--x\r\nContent-Disposition: form-data; name=\"comment\"\r\n\r\nhi\r\n--x--\r\n";
+
+    let head = format!("POST /uploads HTTP/1.1\r\n{}", content_type);
+    let response = send(&handler, &head, body.as_bytes());
+    expect!(*response.status_code()).to(be_equal_to(expected_status));
+    Ok(())
+  }
+
+  #[rstest]
+  #[case::download("GET /uploads/a.txt HTTP/1.1")]
+  #[case::other_prefix("PUT /uploads-old/a.txt HTTP/1.1")]
+  fn test_pass_other_requests_on(#[case] head: &str) -> io::Result<()> {
+    let dir = TempDir::new()?;
+    let handler = upload_handler(&dir, false);
+
+    let response = send(&handler, head, b"");
+    expect!(*response.status_code()).to(be_equal_to(StatusCode::Accepted));
+    expect!(handler.allowed_methods()).to(be_equal_to(vec![
+      Method::GET,
+      Method::PUT,
+      Method::POST,
+    ]));
+    Ok(())
+  }
+}
@@ -0,0 +1,253 @@
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::{fmt, str::FromStr, sync::Arc};
+use thiserror::Error;
+
+use crate::http::{HttpRequest, Method, RequestBody};
+use crate::server::{Handler, HandlerFuture, HandlerResult};
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+#[error("Invalid host pattern {0:?}, expected a name like example.com or *.example.com")]
+pub struct InvalidHostPattern(String);
+
+/// The hosts a virtual host answers for: a name such as `example.com`, or `*.example.com` for
+/// all of its subdomains, at any depth but without the domain itself. Lowercase, without a
+/// trailing dot.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub enum HostPattern {
+  Exact(String),
+  /// `.example.com` for `*.example.com`
+  Wildcard(String),
+}
+
+impl HostPattern {
+  fn matches(&self, hostname: &str) -> bool {
+    match self {
+      Self::Exact(name) => hostname.eq_ignore_ascii_case(name),
+      Self::Wildcard(suffix) => {
+        let Some(suffix_start) = hostname.len().checked_sub(suffix.len()) else {
+          return false;
+        };
+        suffix_start > 0
+          && hostname.is_char_boundary(suffix_start)
+          && hostname[suffix_start..].eq_ignore_ascii_case(suffix)
+      }
+    }
+  }
+}
+
+impl FromStr for HostPattern {
+  type Err = InvalidHostPattern;
+
+  fn from_str(pattern: &str) -> Result<Self, Self::Err> {
+    let invalid = || InvalidHostPattern(pattern.to_string());
+    let lowercase = pattern.to_lowercase();
+    let lowercase = lowercase.strip_suffix('.').unwrap_or(&lowercase);
+    let (name, wildcard) = match lowercase.strip_prefix("*.") {
+      Some(domain) => (domain, true),
+      None => (lowercase, false),
+    };
+    let is_name = name.split('.').all(|label| {
+      !label.is_empty()
+        && label
+          .bytes()
+          .all(|b| b.is_ascii_alphanumeric() || b == b'-')
+    });
+    match (is_name, wildcard) {
+      (false, _) => Err(invalid()),
+      (true, true) => Ok(Self::Wildcard(format!(".{}", name))),
+      (true, false) => Ok(Self::Exact(name.to_string())),
+    }
+  }
+}
+
+impl TryFrom<String> for HostPattern {
+  type Error = InvalidHostPattern;
+
+  fn try_from(pattern: String) -> Result<Self, Self::Error> {
+    pattern.parse()
+  }
+}
+
+impl fmt::Display for HostPattern {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Self::Exact(name) => write!(f, "{}", name),
+      Self::Wildcard(suffix) => write!(f, "*{}", suffix),
+    }
+  }
+}
+
+/// A site of its own, served from `public_path` for requests to any of `hosts`, e.g.
+///
+/// ```toml
+/// [[virtual_hosts]]
+/// hosts = ["example.com", "*.example.com"]
+/// public_path = "/srv/example"
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct VirtualHostSettings {
+  pub hosts: Vec<HostPattern>,
+  pub public_path: String,
+}
+
+struct VirtualHost {
+  pattern: HostPattern,
+  handler: Arc<dyn Handler>,
+}
+
+/// Dispatches requests to handlers by the host they're for, as the `Host` header names it.
+/// Names registered exactly win over wildcards, and longer wildcards over shorter ones, as
+/// in `www.example.com`, `*.www.example.com`, `*.example.com`; requests for other hosts, or
+/// without a valid `Host`, go to the default handler.
+pub struct VirtualHostHandler {
+  hosts: Vec<VirtualHost>,
+  default: Arc<dyn Handler>,
+}
+
+impl VirtualHostHandler {
+  pub fn new(default: Arc<dyn Handler>) -> Self {
+    Self { hosts: Vec::new(), default }
+  }
+
+  /// Send requests for hosts matching `pattern` to `handler`
+  ///
+  /// # Panics
+  ///
+  /// If `pattern` isn't a valid [`HostPattern`]; see [`Self::host_pattern`] for patterns that
+  /// come from elsewhere than the code
+  pub fn host(self, pattern: &str, handler: Arc<dyn Handler>) -> Self {
+    let pattern = pattern.parse().unwrap_or_else(|error| panic!("{}", error));
+    self.host_pattern(pattern, handler)
+  }
+
+  pub fn host_pattern(mut self, pattern: HostPattern, handler: Arc<dyn Handler>) -> Self {
+    self.hosts.push(VirtualHost { pattern, handler });
+    self
+  }
+
+  /// The handler for the host `request` is for
+  fn handler(&self, request: &HttpRequest<'_>) -> &dyn Handler {
+    let Some(hostname) = request.hostname() else {
+      return self.default.as_ref();
+    };
+    let precedence = |host: &&VirtualHost| match &host.pattern {
+      HostPattern::Exact(_) => usize::MAX,
+      HostPattern::Wildcard(suffix) => suffix.len(),
+    };
+    self
+      .hosts
+      .iter()
+      .filter(|host| host.pattern.matches(hostname))
+      // the first registered among equals
+      .rev()
+      .max_by_key(precedence)
+      .map_or(self.default.as_ref(), |host| host.handler.as_ref())
+  }
+}
+
+impl Handler for VirtualHostHandler {
+  fn handle_request(&self, request: &HttpRequest<'_>) -> HandlerResult {
+    self.handler(request).handle_request(request)
+  }
+
+  fn handle_async<'a>(&'a self, request: &'a HttpRequest<'a>) -> Option<HandlerFuture<'a>> {
+    self.handler(request).handle_async(request)
+  }
+
+  fn handle_with_body(&self, request: &HttpRequest<'_>, body: RequestBody) -> HandlerResult {
+    self.handler(request).handle_with_body(request, body)
+  }
+
+  /// `OPTIONS *` doesn't know which host it's for beyond its `Host`, so any host's methods
+  fn allowed_methods(&self) -> Vec<Method> {
+    let mut methods = self.default.allowed_methods();
+    for method in self
+      .hosts
+      .iter()
+      .flat_map(|host| host.handler.allowed_methods())
+    {
+      if !methods.contains(&method) {
+        methods.push(method);
+      }
+    }
+    methods
+  }
+
+  fn describe(&self) -> Value {
+    let hosts: Vec<Value> = self
+      .hosts
+      .iter()
+      .map(|host| json!({ "host": host.pattern.to_string(), "handler": host.handler.describe() }))
+      .collect();
+    json!({
+      "handler": "VirtualHostHandler",
+      "hosts": hosts,
+      "default": self.default.describe(),
+    })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::http::{HttpResponse, StatusCode};
+  use expectest::prelude::*;
+  use rstest::*;
+
+  /// Answers with its status, telling handlers apart
+  struct StatusHandler(StatusCode);
+
+  impl Handler for StatusHandler {
+    fn handle_request(&self, _request: &HttpRequest<'_>) -> HandlerResult {
+      Ok(HttpResponse::empty_body(self.0))
+    }
+  }
+
+  #[rstest]
+  #[case::exact("Example.com", Ok(HostPattern::Exact("example.com".to_string())))]
+  #[case::wildcard("*.example.com.", Ok(HostPattern::Wildcard(".example.com".to_string())))]
+  #[case::inner_wildcard("www.*.com", Err(InvalidHostPattern("www.*.com".to_string())))]
+  #[case::bare_wildcard("*", Err(InvalidHostPattern("*".to_string())))]
+  #[case::port("example.com:8080", Err(InvalidHostPattern("example.com:8080".to_string())))]
+  #[case::empty_label("a..com", Err(InvalidHostPattern("a..com".to_string())))]
+  fn test_parse_host_pattern(
+    #[case] pattern: &str,
+    #[case] expected: Result<HostPattern, InvalidHostPattern>,
+  ) {
+    expect!(pattern.parse::<HostPattern>()).to(be_equal_to(expected));
+  }
+
+  #[rstest]
+  #[case::exact("www.example.com", StatusCode::Ok)]
+  #[case::port_and_case("WWW.Example.com:8080", StatusCode::Ok)]
+  #[case::longer_wildcard("a.www.example.com", StatusCode::Accepted)]
+  #[case::wildcard("blog.example.com", StatusCode::Created)]
+  #[case::nested("a.blog.example.com", StatusCode::Created)]
+  #[case::apex("example.com", StatusCode::NoContent)]
+  #[case::lookalike("badexample.com", StatusCode::NoContent)]
+  #[case::other("example.org", StatusCode::NoContent)]
+  fn test_dispatch_by_host(#[case] host: &str, #[case] expected_status: StatusCode) {
+    let handler = VirtualHostHandler::new(Arc::new(StatusHandler(StatusCode::NoContent)))
+      .host(
+        "*.example.com",
+        Arc::new(StatusHandler(StatusCode::Created)),
+      )
+      .host(
+        "*.www.example.com",
+        Arc::new(StatusHandler(StatusCode::Accepted)),
+      )
+      .host("www.example.com", Arc::new(StatusHandler(StatusCode::Ok)))
+      .host(
+        "*.example.com",
+        Arc::new(StatusHandler(StatusCode::NotFound)),
+      );
+    let raw_request = format!("GET / HTTP/1.1\r\nHost: {}\r\n\r\n", host);
+    let request = HttpRequest::try_from(raw_request.as_bytes()).unwrap();
+
+    let response = handler.handle_request(&request).unwrap();
+    expect!(*response.status_code()).to(be_equal_to(expected_status));
+  }
+}
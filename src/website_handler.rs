@@ -1,30 +1,1110 @@
-use std::sync::Arc;
+use std::{
+  collections::{BTreeMap, HashMap},
+  ffi::OsStr,
+  path::Path,
+  sync::Arc,
+};
 
-use derive_new::new;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
 
-use super::filesystem::FileSystem;
-use crate::http::Method;
+use super::filesystem::{AsyncFileSystem, DirEntry, FileSystem};
+use crate::http::{
+  content_coding::ContentCoding,
+  header::{HttpHeader, HttpRequestHeaderKey, HttpResponseHeaderKey},
+  percent_encoding::encode_segment,
+  Method, MimeTypes, RequestBody, StatusCode,
+};
+use crate::router::Router;
 
-use super::http::{HttpRequest, HttpResponse, StatusCode};
-use super::server::Handler;
+use super::http::{HttpRequest, HttpResponse};
+use super::server::{Handler, HandlerFuture, HandlerResult};
+
+/// Paths served by a file of another name, any other path is served by the file it names
+const NAMED_FILES: [(&str, &str); 1] = [("/hello", "hello.html")];
+
+/// How the public path is served, e.g.
+///
+/// ```toml
+/// [website]
+/// index_documents = ["index.html", "index.htm"]
+/// spa_fallback = true
+/// directory_listing = false
+/// precompressed = true
+/// allowed_dotfiles = [".well-known"]
+///
+/// [website.error_pages]
+/// 404 = "errors/404.html"
+/// 500 = "errors/500.html"
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WebsiteSettings {
+  /// Files serving the directory they're in, the first one found of them, `index.html` by
+  /// default
+  pub index_documents: Vec<String>,
+  /// Answer `GET` requests for paths without a file with the public path's first index
+  /// document, so single-page applications routing on the client can be entered at any of
+  /// their paths
+  pub spa_fallback: bool,
+  /// List the files of directories without an index file. Off by default: it shows
+  /// visitors every file below the public path, linked or not.
+  pub directory_listing: bool,
+  /// status code -> file below the public path sent as the body of error responses that
+  /// come without one; other statuses and missing files get a built-in page
+  pub error_pages: BTreeMap<u16, String>,
+  /// Serve `app.js.br`, `app.js.zst` or `app.js.gz` for `app.js` to clients that accept the
+  /// coding, rather than compressing on the fly. Off by default, as it looks for the
+  /// compressed files with every request.
+  pub precompressed: bool,
+  /// Serve files and directories whose names start with a dot, like `.env` or `.git/config`.
+  /// Off by default, they're rarely meant to be public; paths with `.` or `..` segments are
+  /// refused either way.
+  pub serve_dotfiles: bool,
+  /// Names of dotfiles and dot-directories served all the same, `.well-known` by default
+  pub allowed_dotfiles: Vec<String>,
+}
+
+impl Default for WebsiteSettings {
+  fn default() -> Self {
+    Self {
+      index_documents: vec!["index.html".to_string()],
+      spa_fallback: false,
+      directory_listing: false,
+      error_pages: BTreeMap::new(),
+      precompressed: false,
+      serve_dotfiles: false,
+      allowed_dotfiles: vec![".well-known".to_string()],
+    }
+  }
+}
 
-#[derive(new)]
 pub struct WebsiteHandler<F: FileSystem> {
   file_system: Arc<F>,
+  mime_types: MimeTypes,
+  settings: Arc<WebsiteSettings>,
+  /// lowercase extension (without the dot) -> handler that serves matching files
+  extension_handlers: HashMap<String, Arc<dyn Handler>>,
+  router: Router,
+}
+
+impl<F: AsyncFileSystem + 'static> WebsiteHandler<F> {
+  pub fn new(file_system: Arc<F>, mime_types: MimeTypes) -> Self {
+    Self::with_settings(file_system, mime_types, WebsiteSettings::default())
+  }
+
+  pub fn with_settings(
+    file_system: Arc<F>,
+    mime_types: MimeTypes,
+    settings: WebsiteSettings,
+  ) -> Self {
+    let settings = Arc::new(settings);
+    let file_handler = |file_path| {
+      Arc::new(FileHandler {
+        file_system: Arc::clone(&file_system),
+        mime_types: mime_types.clone(),
+        settings: Arc::clone(&settings),
+        file_path,
+      })
+    };
+    let router = NAMED_FILES
+      .iter()
+      .fold(Router::new(), |router, (path, file_path)| {
+        router.route(Method::GET, path, file_handler(Some(*file_path)))
+      })
+      .route(Method::GET, "/*", file_handler(None));
+    Self {
+      file_system,
+      mime_types,
+      settings,
+      extension_handlers: HashMap::new(),
+      router,
+    }
+  }
+}
+
+impl<F: FileSystem> WebsiteHandler<F> {
+  /// Delegate requests for files with `extension` (e.g. `.md` or `cgi`) to `handler`
+  /// instead of serving them raw
+  pub fn register_extension(mut self, extension: &str, handler: Arc<dyn Handler>) -> Self {
+    self
+      .extension_handlers
+      .insert(extension.trim_start_matches('.').to_lowercase(), handler);
+    self
+  }
+
+  fn file_path(request_path: &str) -> &str {
+    NAMED_FILES
+      .iter()
+      .find(|(path, _)| *path == request_path)
+      .map_or(request_path.trim_start_matches('/'), |(_, file_path)| {
+        file_path
+      })
+  }
+
+  fn extension_handler(&self, file_path: &str) -> Option<&Arc<dyn Handler>> {
+    Path::new(file_path)
+      .extension()
+      .and_then(OsStr::to_str)
+      .and_then(|extension| self.extension_handlers.get(&extension.to_lowercase()))
+  }
+
+  /// `response` with an error page if it's an error without a body
+  fn with_error_page(&self, mut response: HttpResponse) -> HttpResponse {
+    if needs_error_page(&response) {
+      let status_code = *response.status_code();
+      let (page, content_type) = error_page(
+        &*self.file_system,
+        &self.settings,
+        &self.mime_types,
+        status_code,
+      );
+      response.replace_body(page, &content_type);
+    }
+    response
+  }
+}
+
+/// Error statuses whose handler didn't send anything to show
+fn needs_error_page(response: &HttpResponse) -> bool {
+  let status_code = response.status_code();
+  (status_code.is_client_error() || status_code.is_server_error())
+    && !response.is_streamed()
+    && response.body().as_ref().is_none_or(Vec::is_empty)
+}
+
+/// The page configured for `status_code` and its content type, or the built-in one
+fn error_page<F: FileSystem>(
+  file_system: &F,
+  settings: &WebsiteSettings,
+  mime_types: &MimeTypes,
+  status_code: StatusCode,
+) -> (Vec<u8>, String) {
+  let configured = settings
+    .error_pages
+    .get(&status_code.as_u16())
+    .and_then(|file_path| {
+      let page = file_system.read_file(file_path)?;
+      let content_type = mime_types.content_type(file_path, &page);
+      Some((page, content_type))
+    });
+  configured.unwrap_or_else(|| built_in_error_page(status_code))
+}
+
+fn built_in_error_page(status_code: StatusCode) -> (Vec<u8>, String) {
+  let title = format!("{} {}", status_code, status_code.reason_phrase());
+  let page = format!(
+    "<!DOCTYPE html>\n<html>\n<head><meta charset=\"utf-8\"><title>{title}</title></head>\n\
+     <body>\n<h1>{title}</h1>\n</body>\n</html>\n"
+  );
+  (page.into_bytes(), "text/html; charset=utf-8".to_string())
 }
 
 impl<F> Handler for WebsiteHandler<F>
 where
-  F: FileSystem + std::marker::Sync + std::marker::Send + 'static,
+  F: AsyncFileSystem + 'static,
 {
-  fn handle_request(&self, request: &HttpRequest<'_>) -> HttpResponse {
-    match request.method() {
-      Method::GET => match request.path() {
-        "/" => HttpResponse::with_body("index.html", &*self.file_system),
-        "/hello" => HttpResponse::with_body("hello.html", &*self.file_system),
-        path => HttpResponse::with_body(path.trim_start_matches('/'), &*self.file_system),
-      },
-      _ => HttpResponse::empty_body(StatusCode::NotFound),
+  /// Errors pass through for the server to answer, error pages only dress up error responses
+  fn handle_request(&self, request: &HttpRequest<'_>) -> HandlerResult {
+    // delegated handlers decide for themselves which methods they support
+    let response = match self.extension_handler(Self::file_path(request.path())) {
+      Some(handler) => handler.handle_request(request),
+      None => self.router.handle_request(request),
+    }?;
+    Ok(self.with_error_page(response))
+  }
+
+  /// Files are read through tokio, off the runtime's threads
+  fn handle_async<'a>(&'a self, request: &'a HttpRequest<'a>) -> Option<HandlerFuture<'a>> {
+    let handled = match self.extension_handler(Self::file_path(request.path())) {
+      Some(handler) => handler.handle_async(request),
+      None => self.router.handle_async(request),
+    }?;
+    Some(Box::pin(async move {
+      let mut response = handled.await?;
+      if needs_error_page(&response) {
+        let status_code = *response.status_code();
+        let (file_system, settings) = (Arc::clone(&self.file_system), Arc::clone(&self.settings));
+        let mime_types = self.mime_types.clone();
+        let page = tokio::task::spawn_blocking(move || {
+          error_page(&*file_system, &settings, &mime_types, status_code)
+        });
+        let (page, content_type) = page
+          .await
+          .unwrap_or_else(|_| built_in_error_page(status_code));
+        response.replace_body(page, &content_type);
+      }
+      Ok(response)
+    }))
+  }
+
+  fn handle_with_body(&self, request: &HttpRequest<'_>, body: RequestBody) -> HandlerResult {
+    let response = match self.extension_handler(Self::file_path(request.path())) {
+      Some(handler) => handler.handle_with_body(request, body),
+      None => self.router.handle_request(request),
+    }?;
+    Ok(self.with_error_page(response))
+  }
+
+  fn describe(&self) -> Value {
+    let extension_handlers = self
+      .extension_handlers
+      .iter()
+      .map(|(extension, handler)| (extension.clone(), handler.describe()))
+      .collect::<serde_json::Map<_, _>>();
+    let routes = NAMED_FILES
+      .iter()
+      .map(|(path, file_path)| json!({ "path": path, "file": file_path }))
+      .collect::<Vec<_>>();
+    json!({
+      "handler": "WebsiteHandler",
+      "public_path": self.file_system.get_full_path(""),
+      "routes": routes,
+      "settings": *self.settings,
+      "extension_handlers": extension_handlers,
+      "mime_types": self.mime_types,
+    })
+  }
+
+  fn allowed_methods(&self) -> Vec<Method> {
+    self
+      .extension_handlers
+      .values()
+      .flat_map(|handler| handler.allowed_methods())
+      .fold(self.router.allowed_methods(), |mut methods, method| {
+        if !methods.contains(&method) {
+          methods.push(method);
+        }
+        methods
+      })
+  }
+}
+
+/// Serves `file_path`, or the file the route's wildcard names without one. Paths naming a
+/// directory are served its first index document, or as `settings` say else.
+struct FileHandler<F> {
+  file_system: Arc<F>,
+  mime_types: MimeTypes,
+  settings: Arc<WebsiteSettings>,
+  file_path: Option<&'static str>,
+}
+
+impl<F> FileHandler<F> {
+  fn file_path<'a>(&'a self, request: &'a HttpRequest<'_>) -> &'a str {
+    self.file_path.unwrap_or_else(|| {
+      let file_path = request.path_params().wildcard().unwrap_or_default();
+      file_path.trim_start_matches('/')
+    })
+  }
+
+  /// Whether the path segment or file name `name` is withheld by the dotfile policy
+  fn is_hidden(&self, name: &str) -> bool {
+    match name {
+      "." | ".." => true,
+      _ => {
+        name.starts_with('.')
+          && !self.settings.serve_dotfiles
+          && !self
+            .settings
+            .allowed_dotfiles
+            .iter()
+            .any(|allowed| allowed == name)
+      }
+    }
+  }
+
+  /// Whether `request`'s path has a segment withheld by the dotfile policy, answered as if
+  /// there was no such file
+  fn is_hidden_path(&self, request: &HttpRequest<'_>) -> bool {
+    request
+      .path()
+      .split('/')
+      .any(|segment| self.is_hidden(segment))
+  }
+
+  /// The files compressed ahead of time that may stand in for `file_path`, by the coding
+  /// they're compressed with, in the order `request` accepts them
+  fn precompressed_variants(
+    &self,
+    request: &HttpRequest<'_>,
+    file_path: &str,
+  ) -> Vec<(ContentCoding, String)> {
+    if !self.settings.precompressed || file_path.is_empty() || file_path.ends_with('/') {
+      return Vec::new();
+    }
+    let accept_encoding = request.header().get(HttpRequestHeaderKey::AcceptEncoding);
+    let accepted = accept_encoding.map(|accept_encoding| ContentCoding::accepted(accept_encoding));
+    accepted
+      .unwrap_or_default()
+      .into_iter()
+      .filter_map(|coding| {
+        let extension = coding.file_extension()?;
+        Some((coding, format!("{}.{}", file_path, extension)))
+      })
+      .collect()
+  }
+
+  /// `response`, serving the variant of `file_path` compressed with `coding`, labeled with the
+  /// type of `file_path` itself
+  fn precompressed(
+    &self,
+    file_path: &str,
+    coding: ContentCoding,
+    mut response: HttpResponse,
+  ) -> HttpResponse
+  where
+    F: FileSystem,
+  {
+    let full_path = self.file_system.get_full_path(file_path);
+    let content_type = self
+      .mime_types
+      .content_type(&full_path.to_string_lossy(), &[]);
+    response.insert_header(HttpResponseHeaderKey::ContentType, &content_type);
+    response.insert_header(HttpResponseHeaderKey::ContentEncoding, coding.as_str());
+    response.add_vary("Accept-Encoding");
+    response
+  }
+
+  /// `response`, serving `file_path` as it is; clients accepting other codings may have been
+  /// sent a compressed variant of it
+  fn uncompressed(&self, mut response: HttpResponse) -> HttpResponse {
+    if self.settings.precompressed && !is_missing(&response) {
+      response.add_vary("Accept-Encoding");
     }
+    response
+  }
+
+  /// The public path's own index document, served for single-page applications
+  fn spa_fallback(&self) -> Option<&str> {
+    match self.settings.spa_fallback {
+      true => self.settings.index_documents.first().map(String::as_str),
+      false => None,
+    }
+  }
+}
+
+/// The directory `request` names, relative to the public path: empty for `/`, ending in a
+/// slash below it
+fn directory<'a>(request: &'a HttpRequest<'_>) -> &'a str {
+  request.path().trim_start_matches('/')
+}
+
+/// Directories are served with a trailing slash, so relative links resolve below them
+fn slash_redirect(request: &HttpRequest<'_>) -> Option<HttpResponse> {
+  match request.path().ends_with('/') {
+    true => None,
+    false => Some(HttpResponse::permanent_redirect(&format!(
+      "{}/",
+      request.raw_path()
+    ))),
+  }
+}
+
+/// A listing of `entries`, directories first
+fn directory_listing(request: &HttpRequest<'_>, mut entries: Vec<DirEntry>) -> HttpResponse {
+  entries.sort_by(|a, b| b.is_dir.cmp(&a.is_dir).then_with(|| a.name.cmp(&b.name)));
+  let title = format!("Index of {}", escape_html(request.path()));
+  let mut rows = match request.path() {
+    "/" => String::new(),
+    _ => "<tr><td><a href=\"../\">../</a></td><td></td><td></td></tr>\n".to_string(),
+  };
+  for entry in entries {
+    let suffix = if entry.is_dir { "/" } else { "" };
+    let size = match entry.is_dir {
+      true => "-".to_string(),
+      false => entry.size.to_string(),
+    };
+    let modified = entry
+      .modified
+      .and_then(|modified| HttpHeader::last_modified(modified).ok())
+      .unwrap_or_default();
+    rows.push_str(&format!(
+      "<tr><td><a href=\"{}{}\">{}{}</a></td><td>{}</td><td>{}</td></tr>\n",
+      encode_segment(&entry.name),
+      suffix,
+      escape_html(&entry.name),
+      suffix,
+      size,
+      modified
+    ));
+  }
+  let body = format!(
+    "<!DOCTYPE html>\n<html>\n<head><meta charset=\"utf-8\"><title>{title}</title></head>\n\
+     <body>\n<h1>{title}</h1>\n<table>\n\
+     <tr><th>Name</th><th>Size</th><th>Last modified</th></tr>\n{rows}</table>\n</body>\n</html>\n"
+  );
+  HttpResponse::builder()
+    .header(
+      HttpResponseHeaderKey::ContentType,
+      "text/html; charset=utf-8",
+    )
+    .body(body)
+    .unwrap_or_else(HttpResponse::from)
+}
+
+fn escape_html(text: &str) -> String {
+  text
+    .replace('&', "&amp;")
+    .replace('<', "&lt;")
+    .replace('>', "&gt;")
+    .replace('"', "&quot;")
+}
+
+/// `HttpResponse::with_body` and `with_file` answer files that don't exist with an empty
+/// `404 Not Found`
+fn is_missing(response: &HttpResponse) -> bool {
+  *response.status_code() == StatusCode::NotFound
+}
+
+impl<F: AsyncFileSystem + 'static> Handler for FileHandler<F> {
+  fn handle_request(&self, request: &HttpRequest<'_>) -> HandlerResult {
+    let serve = |file_path: &str| {
+      for (coding, variant) in self.precompressed_variants(request, file_path) {
+        let response = HttpResponse::with_body(&variant, &*self.file_system, &self.mime_types);
+        if !is_missing(&response) {
+          return self.precompressed(file_path, coding, response);
+        }
+      }
+      self.uncompressed(HttpResponse::with_body(
+        file_path,
+        &*self.file_system,
+        &self.mime_types,
+      ))
+    };
+    if self.is_hidden_path(request) {
+      return Ok(HttpResponse::empty_body(StatusCode::NotFound));
+    }
+    let response = serve(self.file_path(request));
+    if !is_missing(&response) {
+      return Ok(response.conditional(request));
+    }
+    if self.file_system.is_dir(directory(request)) {
+      if let Some(redirect) = slash_redirect(request) {
+        return Ok(redirect);
+      }
+      for index_document in &self.settings.index_documents {
+        let response = serve(&format!("{}{}", directory(request), index_document));
+        if !is_missing(&response) {
+          return Ok(response.conditional(request));
+        }
+      }
+      let listing = match self.settings.directory_listing {
+        true => self.file_system.list_dir(directory(request)),
+        false => None,
+      };
+      if let Some(mut entries) = listing {
+        entries.retain(|entry| !self.is_hidden(&entry.name));
+        return Ok(directory_listing(request, entries));
+      }
+    }
+    Ok(match self.spa_fallback() {
+      Some(fallback) => serve(fallback).conditional(request),
+      None => response,
+    })
+  }
+
+  fn handle_async<'a>(&'a self, request: &'a HttpRequest<'a>) -> Option<HandlerFuture<'a>> {
+    let serve = move |file_path: String| async move {
+      for (coding, variant) in self.precompressed_variants(request, &file_path) {
+        let response =
+          HttpResponse::with_file(&variant, &*self.file_system, &self.mime_types).await;
+        if !is_missing(&response) {
+          return self.precompressed(&file_path, coding, response);
+        }
+      }
+      let response = HttpResponse::with_file(&file_path, &*self.file_system, &self.mime_types);
+      self.uncompressed(response.await)
+    };
+    Some(Box::pin(async move {
+      if self.is_hidden_path(request) {
+        return Ok(HttpResponse::empty_body(StatusCode::NotFound));
+      }
+      let response = serve(self.file_path(request).to_string()).await;
+      if !is_missing(&response) {
+        return Ok(response.conditional(request));
+      }
+      let (file_system, dir_path) = (
+        Arc::clone(&self.file_system),
+        directory(request).to_string(),
+      );
+      let listing_enabled = self.settings.directory_listing;
+      // `None` for paths that aren't directories, no entries unless they are to be listed
+      let directory = tokio::task::spawn_blocking(move || {
+        file_system
+          .is_dir(&dir_path)
+          .then(|| match listing_enabled {
+            true => file_system.list_dir(&dir_path),
+            false => None,
+          })
+      });
+      if let Ok(Some(listing)) = directory.await {
+        if let Some(redirect) = slash_redirect(request) {
+          return Ok(redirect);
+        }
+        for index_document in &self.settings.index_documents {
+          let response = serve(format!("{}{}", self::directory(request), index_document)).await;
+          if !is_missing(&response) {
+            return Ok(response.conditional(request));
+          }
+        }
+        if let Some(mut entries) = listing {
+          entries.retain(|entry| !self.is_hidden(&entry.name));
+          return Ok(directory_listing(request, entries));
+        }
+      }
+      Ok(match self.spa_fallback() {
+        Some(fallback) => serve(fallback.to_string()).await.conditional(request),
+        None => response,
+      })
+    }))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::cors::{CorsPolicy, CorsSettings};
+  use crate::filesystem::LocalFileSystem;
+  use crate::http::{header::HttpResponseHeaderKey, HandlerError, StatusCode};
+  use crate::middleware::{Layered, Middleware, Next};
+  use expectest::prelude::*;
+  use rstest::*;
+
+  struct TeapotHandler;
+
+  impl Handler for TeapotHandler {
+    fn handle_request(&self, _request: &HttpRequest<'_>) -> HandlerResult {
+      Ok(HttpResponse::empty_body(StatusCode::BadRequest))
+    }
+  }
+
+  #[fixture]
+  fn website_handler() -> WebsiteHandler<LocalFileSystem> {
+    let file_system = Arc::new(LocalFileSystem::new("/nonexistent".to_string()));
+    WebsiteHandler::new(file_system, MimeTypes::default())
+      .register_extension(".MD", Arc::new(TeapotHandler))
+  }
+
+  #[rstest]
+  #[case::get("GET /docs/readme.md HTTP/1.1\r\nHost: localhost\r\n\r\n", StatusCode::BadRequest)]
+  #[case::other_method(
+    "POST /README.md HTTP/1.1\r\nHost: localhost\r\n\r\n",
+    StatusCode::BadRequest
+  )]
+  #[case::unregistered(
+    "PUT /index.html HTTP/1.1\r\nHost: localhost\r\n\r\n",
+    StatusCode::MethodNotAllowed
+  )]
+  fn test_extension_handler_delegation(
+    website_handler: WebsiteHandler<LocalFileSystem>,
+    #[case] raw_request: &str,
+    #[case] expected_status: StatusCode,
+  ) -> Result<(), HandlerError> {
+    let request = HttpRequest::try_from(raw_request.as_bytes())?;
+    let response = website_handler.handle_request(&request)?;
+    expect!(*response.status_code()).to(be_equal_to(expected_status));
+    Ok(())
+  }
+
+  #[rstest]
+  #[case::index("/", "index")]
+  #[case::hello("/hello", "hello")]
+  #[case::nested("/css/site.css", "css")]
+  #[case::named_file("/hello.html", "hello")]
+  #[case::encoded("/hello%20world.html", "hello world")]
+  fn test_route_to_file(
+    #[case] path: &str,
+    #[case] expected: &str,
+  ) -> Result<(), Box<dyn std::error::Error>> {
+    let public_path = tempfile::TempDir::new()?;
+    std::fs::write(public_path.path().join("index.html"), "index")?;
+    std::fs::write(public_path.path().join("hello.html"), "hello")?;
+    std::fs::write(public_path.path().join("hello world.html"), "hello world")?;
+    std::fs::create_dir(public_path.path().join("css"))?;
+    std::fs::write(public_path.path().join("css").join("site.css"), "css")?;
+    let file_system = LocalFileSystem::new(public_path.path().to_string_lossy().to_string());
+    let website_handler = WebsiteHandler::new(Arc::new(file_system), MimeTypes::default());
+
+    let raw_request = format!("GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path);
+    let response =
+      website_handler.handle_request(&HttpRequest::try_from(raw_request.as_bytes())?)?;
+    expect!(response.body().as_deref()).to(be_some().value(expected.as_bytes()));
+    Ok(())
+  }
+
+  #[rstest]
+  #[case::fallback(true, "/dashboard/settings", StatusCode::Ok, "app")]
+  #[case::existing_file(true, "/app.js", StatusCode::Ok, "script")]
+  #[case::disabled(false, "/dashboard/settings", StatusCode::NotFound, "<h1>404 Not Found</h1>")]
+  #[tokio::test]
+  async fn test_spa_fallback(
+    #[case] spa_fallback: bool,
+    #[case] path: &str,
+    #[case] expected_status: StatusCode,
+    #[case] expected_body: &str,
+  ) -> Result<(), Box<dyn std::error::Error>> {
+    let public_path = tempfile::TempDir::new()?;
+    std::fs::write(public_path.path().join("index.html"), "app")?;
+    std::fs::write(public_path.path().join("app.js"), "script")?;
+    let file_system = LocalFileSystem::new(public_path.path().to_string_lossy().to_string());
+    let settings = WebsiteSettings { spa_fallback, ..Default::default() };
+    let website_handler =
+      WebsiteHandler::with_settings(Arc::new(file_system), MimeTypes::default(), settings);
+
+    let raw_request = format!("GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path);
+    let request = HttpRequest::try_from(raw_request.as_bytes())?;
+    for response in [
+      website_handler.handle_request(&request)?,
+      website_handler
+        .handle_async(&request)
+        .unwrap()
+        .await
+        .unwrap(),
+    ] {
+      expect!(*response.status_code()).to(be_equal_to(expected_status));
+      let body = response.body().as_deref().unwrap_or_default();
+      expect!(String::from_utf8_lossy(body).contains(expected_body)).to(be_true());
+    }
+    Ok(())
+  }
+
+  #[rstest]
+  #[case::root("/", StatusCode::Ok, "root")]
+  #[case::second_choice("/blog/", StatusCode::Ok, "blog")]
+  #[case::first_choice_wins("/docs/", StatusCode::Ok, "docs html")]
+  #[case::without_slash("/docs", StatusCode::PermanentRedirect, "")]
+  #[case::no_index("/empty/", StatusCode::NotFound, "<h1>404 Not Found</h1>")]
+  #[tokio::test]
+  async fn test_index_documents(
+    #[case] path: &str,
+    #[case] expected_status: StatusCode,
+    #[case] expected_body: &str,
+  ) -> Result<(), Box<dyn std::error::Error>> {
+    let public_path = tempfile::TempDir::new()?;
+    let root = public_path.path();
+    for directory in ["blog", "docs", "empty"] {
+      std::fs::create_dir(root.join(directory))?;
+    }
+    std::fs::write(root.join("index.htm"), "root")?;
+    std::fs::write(root.join("blog").join("index.htm"), "blog")?;
+    std::fs::write(root.join("docs").join("index.html"), "docs html")?;
+    std::fs::write(root.join("docs").join("index.htm"), "docs htm")?;
+    let file_system = LocalFileSystem::new(root.to_string_lossy().to_string());
+    let settings = WebsiteSettings {
+      index_documents: vec!["index.html".to_string(), "index.htm".to_string()],
+      ..Default::default()
+    };
+    let website_handler =
+      WebsiteHandler::with_settings(Arc::new(file_system), MimeTypes::default(), settings);
+
+    let raw_request = format!("GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path);
+    let request = HttpRequest::try_from(raw_request.as_bytes())?;
+    for response in [
+      website_handler.handle_request(&request)?,
+      website_handler
+        .handle_async(&request)
+        .unwrap()
+        .await
+        .unwrap(),
+    ] {
+      expect!(*response.status_code()).to(be_equal_to(expected_status));
+      let body = response.body().as_deref().unwrap_or_default();
+      expect!(String::from_utf8_lossy(body).contains(expected_body)).to(be_true());
+    }
+    Ok(())
+  }
+
+  #[rstest]
+  #[case::root("/", true, StatusCode::Ok)]
+  #[case::nested("/docs/", true, StatusCode::Ok)]
+  #[case::without_slash("/docs", true, StatusCode::PermanentRedirect)]
+  #[case::disabled("/docs/", false, StatusCode::NotFound)]
+  #[tokio::test]
+  async fn test_directory_listing(
+    #[case] path: &str,
+    #[case] directory_listing: bool,
+    #[case] expected_status: StatusCode,
+  ) -> Result<(), Box<dyn std::error::Error>> {
+    let public_path = tempfile::TempDir::new()?;
+    std::fs::create_dir_all(public_path.path().join("docs").join("drafts"))?;
+    std::fs::write(public_path.path().join("docs").join("a <b>.txt"), "12345")?;
+    std::fs::write(public_path.path().join("readme.txt"), "hi")?;
+    let file_system = LocalFileSystem::new(public_path.path().to_string_lossy().to_string());
+    let settings = WebsiteSettings { directory_listing, ..Default::default() };
+    let website_handler =
+      WebsiteHandler::with_settings(Arc::new(file_system), MimeTypes::default(), settings);
+
+    let raw_request = format!("GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path);
+    let request = HttpRequest::try_from(raw_request.as_bytes())?;
+    for response in [
+      website_handler.handle_request(&request)?,
+      website_handler
+        .handle_async(&request)
+        .unwrap()
+        .await
+        .unwrap(),
+    ] {
+      expect!(*response.status_code()).to(be_equal_to(expected_status));
+      let body = String::from_utf8_lossy(response.body().as_deref().unwrap_or_default());
+      match (expected_status, path) {
+        (StatusCode::Ok, "/") => {
+          expect!(body.contains("<a href=\"docs/\">docs/</a>")).to(be_true());
+          expect!(body.contains("<td>2</td>")).to(be_true());
+          expect!(body.contains("../")).to(be_false());
+        }
+        (StatusCode::Ok, _) => {
+          expect!(body.contains("<title>Index of /docs/</title>")).to(be_true());
+          expect!(body.contains("<a href=\"../\">../</a>")).to(be_true());
+          // directories first, names escaped in the text and encoded in the link
+          let drafts = body.find("drafts/").unwrap();
+          let file = body
+            .find("<a href=\"a%20%3Cb%3E.txt\">a &lt;b&gt;.txt</a>")
+            .unwrap();
+          expect!(drafts < file).to(be_true());
+        }
+        (StatusCode::PermanentRedirect, _) => {
+          let location = response
+            .http_header()
+            .as_ref()
+            .unwrap()
+            .get("Location")
+            .cloned();
+          expect!(location).to(be_some().value("/docs/"));
+        }
+        _ => {}
+      }
+    }
+    Ok(())
+  }
+
+  #[rstest]
+  #[case::configured(
+    "GET /missing HTTP/1.1",
+    "errors/404.html",
+    "<p>Lost</p>",
+    "text/html; charset=utf-8"
+  )]
+  #[case::configured_file_missing(
+    "GET /missing HTTP/1.1",
+    "errors/gone.html",
+    "<h1>404 Not Found</h1>",
+    "text/html; charset=utf-8"
+  )]
+  #[case::built_in(
+    "PUT /index.html HTTP/1.1",
+    "errors/404.html",
+    "<h1>405 Method Not Allowed</h1>",
+    "text/html; charset=utf-8"
+  )]
+  #[tokio::test]
+  async fn test_error_pages(
+    #[case] request_line: &str,
+    #[case] not_found_page: &str,
+    #[case] expected_body: &str,
+    #[case] expected_content_type: &str,
+  ) -> Result<(), Box<dyn std::error::Error>> {
+    use crate::http::header::HttpResponseHeaderKey;
+
+    let public_path = tempfile::TempDir::new()?;
+    std::fs::create_dir(public_path.path().join("errors"))?;
+    std::fs::write(
+      public_path.path().join("errors").join("404.html"),
+      "<p>Lost</p>",
+    )?;
+    let file_system = LocalFileSystem::new(public_path.path().to_string_lossy().to_string());
+    let settings = WebsiteSettings {
+      error_pages: BTreeMap::from([(404, not_found_page.to_string())]),
+      ..Default::default()
+    };
+    let website_handler =
+      WebsiteHandler::with_settings(Arc::new(file_system), MimeTypes::default(), settings);
+
+    let raw_request = format!("{}\r\nHost: localhost\r\n\r\n", request_line);
+    let request = HttpRequest::try_from(raw_request.as_bytes())?;
+    let mut responses = vec![website_handler.handle_request(&request)?];
+    // the router answers methods without a route synchronously
+    if let Some(response) = website_handler.handle_async(&request) {
+      responses.push(response.await?);
+    }
+    for mut response in responses {
+      let body =
+        String::from_utf8_lossy(response.body().as_deref().unwrap_or_default()).into_owned();
+      expect!(body.contains(expected_body)).to(be_true());
+      let header = response.http_header().as_ref().unwrap();
+      let content_type = header.get(HttpResponseHeaderKey::ContentType);
+      expect!(content_type.map(String::as_str)).to(be_some().value(expected_content_type));
+      if request_line.starts_with("PUT") {
+        expect!(header.get(HttpResponseHeaderKey::Allow).is_some()).to(be_true());
+      }
+      let mut sent = Vec::new();
+      response.send(&mut sent).await?;
+      let content_length = format!("Content-Length: {}\r\n", body.len());
+      expect!(String::from_utf8(sent)?.contains(&content_length)).to(be_true());
+    }
+    Ok(())
+  }
+
+  #[rstest]
+  #[case::brotli("/app.js", "gzip, br", true, "app br", Some("br"))]
+  #[case::by_quality("/app.js", "br;q=0.5, gzip", true, "app gz", Some("gzip"))]
+  #[case::next_accepted_variant("/style.css", "br, gzip", true, "style gz", Some("gzip"))]
+  #[case::not_accepted("/app.js", "identity", true, "app", None)]
+  #[case::disabled("/app.js", "br", false, "app", None)]
+  #[tokio::test]
+  async fn test_precompressed(
+    #[case] path: &str,
+    #[case] accept_encoding: &str,
+    #[case] precompressed: bool,
+    #[case] expected_body: &str,
+    #[case] expected_encoding: Option<&str>,
+  ) -> Result<(), Box<dyn std::error::Error>> {
+    use crate::http::header::HttpResponseHeaderKey;
+
+    let public_path = tempfile::TempDir::new()?;
+    for (name, contents) in [
+      ("app.js", "app"),
+      ("app.js.br", "app br"),
+      ("app.js.gz", "app gz"),
+      ("style.css", "style"),
+      ("style.css.gz", "style gz"),
+    ] {
+      std::fs::write(public_path.path().join(name), contents)?;
+    }
+    let file_system = LocalFileSystem::new(public_path.path().to_string_lossy().to_string());
+    let settings = WebsiteSettings { precompressed, ..Default::default() };
+    let website_handler =
+      WebsiteHandler::with_settings(Arc::new(file_system), MimeTypes::default(), settings);
+
+    let raw_request = format!(
+      "GET {} HTTP/1.1\r\nHost: localhost\r\nAccept-Encoding: {}\r\n\r\n",
+      path, accept_encoding
+    );
+    let request = HttpRequest::try_from(raw_request.as_bytes())?;
+    for response in [
+      website_handler.handle_request(&request)?,
+      website_handler
+        .handle_async(&request)
+        .unwrap()
+        .await
+        .unwrap(),
+    ] {
+      expect!(*response.status_code()).to(be_equal_to(StatusCode::Ok));
+      expect!(response.body().as_deref()).to(be_some().value(expected_body.as_bytes()));
+      let header = response.http_header().as_ref().unwrap();
+      let content_encoding = header.get(HttpResponseHeaderKey::ContentEncoding);
+      expect!(content_encoding.map(String::as_str)).to(be_equal_to(expected_encoding));
+      let content_type = header.get(HttpResponseHeaderKey::ContentType).unwrap();
+      expect!(content_type.starts_with("application/octet-stream")).to(be_false());
+      let vary = header.get(HttpResponseHeaderKey::Vary).map(String::as_str);
+      expect!(vary).to(be_equal_to(precompressed.then_some("Accept-Encoding")));
+    }
+    Ok(())
+  }
+
+  #[rstest]
+  #[case::dotfile("/.env", false, StatusCode::NotFound)]
+  #[case::dot_directory("/.git/config", false, StatusCode::NotFound)]
+  #[case::allowed("/.well-known/security.txt", false, StatusCode::Ok)]
+  #[case::served("/.env", true, StatusCode::Ok)]
+  #[case::dot_segment("/.well-known/../index.html", true, StatusCode::NotFound)]
+  #[case::listing_hides_dotfiles("/", false, StatusCode::Ok)]
+  #[tokio::test]
+  async fn test_dotfiles(
+    #[case] path: &str,
+    #[case] serve_dotfiles: bool,
+    #[case] expected_status: StatusCode,
+  ) -> Result<(), Box<dyn std::error::Error>> {
+    let public_path = tempfile::TempDir::new()?;
+    let root = public_path.path();
+    std::fs::create_dir(root.join(".git"))?;
+    std::fs::create_dir(root.join(".well-known"))?;
+    std::fs::write(root.join(".env"), "SECRET=1")?;
+    std::fs::write(root.join(".git").join("config"), "[core]")?;
+    std::fs::write(
+      root.join(".well-known").join("security.txt"),
+      "Contact: security@",
+    )?;
+    std::fs::write(root.join("index.html"), "home")?;
+    let file_system = LocalFileSystem::new(root.to_string_lossy().to_string());
+    let settings = WebsiteSettings {
+      index_documents: Vec::new(),
+      directory_listing: true,
+      serve_dotfiles,
+      ..Default::default()
+    };
+    let website_handler =
+      WebsiteHandler::with_settings(Arc::new(file_system), MimeTypes::default(), settings);
+
+    let raw_request = format!("GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path);
+    let request = HttpRequest::try_from(raw_request.as_bytes())?;
+    for response in [
+      website_handler.handle_request(&request)?,
+      website_handler
+        .handle_async(&request)
+        .unwrap()
+        .await
+        .unwrap(),
+    ] {
+      expect!(*response.status_code()).to(be_equal_to(expected_status));
+      let body = String::from_utf8_lossy(response.body().as_deref().unwrap_or_default());
+      let leaked = body.contains("SECRET") || body.contains("[core]");
+      expect!(leaked).to(be_equal_to(
+        expected_status == StatusCode::Ok && path == "/.env",
+      ));
+      if path == "/" {
+        expect!(body.contains("index.html")).to(be_true());
+        expect!(body.contains(".git") || body.contains(".env")).to(be_false());
+        expect!(body.contains(".well-known/")).to(be_true());
+      }
+    }
+    Ok(())
+  }
+
+  #[rstest]
+  fn test_serve_binary_file() -> Result<(), Box<dyn std::error::Error>> {
+    let public_path = tempfile::TempDir::new()?;
+    // a PNG signature and bytes that aren't valid UTF-8
+    let image = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n', 0xff, 0xfe, 0x00];
+    std::fs::write(public_path.path().join("logo.png"), image)?;
+    let file_system = LocalFileSystem::new(public_path.path().to_string_lossy().to_string());
+    let website_handler = WebsiteHandler::new(Arc::new(file_system), MimeTypes::default());
+
+    let request = HttpRequest::try_from(&b"GET /logo.png HTTP/1.1\r\nHost: localhost\r\n\r\n"[..])?;
+    let response = website_handler.handle_request(&request)?;
+    expect!(*response.status_code()).to(be_equal_to(StatusCode::Ok));
+    expect!(response.body().as_deref()).to(be_some().value(&image[..]));
+    let header = response.http_header().as_ref().unwrap();
+    let content_type = header.get(crate::http::header::HttpResponseHeaderKey::ContentType);
+    expect!(content_type.map(String::as_str)).to(be_some().value("image/png"));
+    Ok(())
+  }
+
+  #[rstest]
+  #[tokio::test]
+  async fn test_conditional_get() -> Result<(), Box<dyn std::error::Error>> {
+    use crate::http::header::HttpResponseHeaderKey;
+
+    let public_path = tempfile::TempDir::new()?;
+    std::fs::write(public_path.path().join("index.html"), "<h1>Hello</h1>")?;
+    let file_system = LocalFileSystem::new(public_path.path().to_string_lossy().to_string());
+    let website_handler = WebsiteHandler::new(Arc::new(file_system), MimeTypes::default());
+
+    let request = HttpRequest::try_from(&b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n"[..])?;
+    let response = website_handler.handle_request(&request)?;
+    let header = response.http_header().as_ref().unwrap();
+    let etag = header.get(HttpResponseHeaderKey::Etag).unwrap();
+    let last_modified = header.get(HttpResponseHeaderKey::LastModified).unwrap();
+
+    for condition in
+      [format!("If-None-Match: {}", etag), format!("If-Modified-Since: {}", last_modified)]
+    {
+      let raw_request = format!("GET / HTTP/1.1\r\nHost: localhost\r\n{}\r\n\r\n", condition);
+      let request = HttpRequest::try_from(raw_request.as_bytes())?;
+      let response = website_handler.handle_request(&request)?;
+      expect!(*response.status_code()).to(be_equal_to(StatusCode::NotModified));
+      expect!(response.body().as_deref()).to(be_none());
+      let mut response = website_handler
+        .handle_async(&request)
+        .unwrap()
+        .await
+        .unwrap();
+      expect!(*response.status_code()).to(be_equal_to(StatusCode::NotModified));
+      let mut sent = Vec::new();
+      response.send(&mut sent).await?;
+      let sent = String::from_utf8(sent)?;
+      expect!(sent.starts_with("HTTP/1.1 304 Not Modified\r\n")).to(be_true());
+      expect!(sent.contains("Content-Length")).to(be_false());
+      expect!(sent.ends_with("\r\n\r\n")).to(be_true());
+    }
+
+    let request = HttpRequest::try_from(
+      &b"GET / HTTP/1.1\r\nHost: localhost\r\nIf-None-Match: \"stale\"\r\n\r\n"[..],
+    )?;
+    let response = website_handler.handle_request(&request)?;
+    expect!(*response.status_code()).to(be_equal_to(StatusCode::Ok));
+    Ok(())
+  }
+
+  #[rstest]
+  #[case::small(1, false)]
+  #[case::larger_than_a_chunk(crate::http::response::DEFAULT_CHUNK_SIZE, true)]
+  #[tokio::test]
+  async fn test_serve_file_asynchronously(
+    #[case] repeat: usize,
+    #[case] expected_streamed: bool,
+  ) -> Result<(), Box<dyn std::error::Error>> {
+    let public_path = tempfile::TempDir::new()?;
+    let contents = "<h1>Hello</h1>".repeat(repeat);
+    std::fs::write(public_path.path().join("index.html"), &contents)?;
+    let file_system = LocalFileSystem::new(public_path.path().to_string_lossy().to_string());
+    let website_handler = WebsiteHandler::new(Arc::new(file_system), MimeTypes::default())
+      .register_extension("md", Arc::new(TeapotHandler));
+
+    let request = HttpRequest::try_from(&b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n"[..])?;
+    let mut response = website_handler
+      .handle_async(&request)
+      .unwrap()
+      .await
+      .unwrap();
+    expect!(response.is_streamed()).to(be_equal_to(expected_streamed));
+    let mut sent = Vec::new();
+    response.send(&mut sent).await?;
+    let sent = String::from_utf8(sent)?;
+    expect!(sent.starts_with("HTTP/1.1 200 Ok\r\n")).to(be_true());
+    let content_length = format!("Content-Length: {}\r\n", contents.len());
+    expect!(sent.contains(&content_length)).to(be_true());
+    expect!(sent.ends_with(&format!("\r\n\r\n{}", contents))).to(be_true());
+
+    // delegated handlers without an async path are left to `handle_request`
+    let request = HttpRequest::try_from(&b"GET /notes.md HTTP/1.1\r\nHost: localhost\r\n\r\n"[..])?;
+    expect!(website_handler.handle_async(&request).is_none()).to(be_true());
+    Ok(())
+  }
+
+  /// Tags responses, which only ever come through the async path
+  struct AsyncOnly;
+
+  impl Middleware for AsyncOnly {
+    fn handle(&self, _request: &HttpRequest, _next: Next<'_>) -> HandlerResult {
+      unreachable!("files are served asynchronously")
+    }
+
+    fn handle_async<'a>(
+      &'a self,
+      request: &'a HttpRequest<'a>,
+      next: Next<'a>,
+    ) -> HandlerFuture<'a> {
+      Box::pin(async move {
+        let mut response = next.run_async(request).await?;
+        response.insert_header("X-Layered", "yes");
+        Ok(response)
+      })
+    }
+  }
+
+  #[rstest]
+  #[tokio::test]
+  async fn test_serve_file_asynchronously_behind_middleware(
+  ) -> Result<(), Box<dyn std::error::Error>> {
+    let public_path = tempfile::TempDir::new()?;
+    let contents = "<h1>Hello</h1>".repeat(crate::http::response::DEFAULT_CHUNK_SIZE);
+    std::fs::write(public_path.path().join("index.html"), &contents)?;
+    let file_system = LocalFileSystem::new(public_path.path().to_string_lossy().to_string());
+    let website_handler = WebsiteHandler::new(Arc::new(file_system), MimeTypes::default());
+    let cors = CorsPolicy::try_from(CorsSettings::default())?;
+    let layered = Layered::new(Arc::new(website_handler))
+      .layer(Arc::new(cors))
+      .layer(Arc::new(AsyncOnly));
+
+    let request = HttpRequest::try_from(
+      &b"GET / HTTP/1.1\r\nHost: localhost\r\nOrigin: https://example.com\r\n\r\n"[..],
+    )?;
+    let response = layered.handle_async(&request).unwrap().await?;
+    // only files read through tokio are streamed
+    expect!(response.is_streamed()).to(be_true());
+    let header = response.http_header().as_ref().unwrap();
+    expect!(header.get("X-Layered")).to(be_some().value("yes"));
+    expect!(header.get(HttpResponseHeaderKey::AccessControlAllowOrigin)).to(be_some().value("*"));
+    Ok(())
   }
 }
@@ -0,0 +1,365 @@
+//! WebSocket endpoints: [`WebSocketHandler`] answers the handshake and hands the upgraded
+//! connection to an endpoint, which exchanges [`Message`]s through a [`WebSocket`]
+
+use serde_json::{json, Value};
+use std::{future::Future, io, pin::Pin, sync::Arc};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+use crate::http::{
+  header::HttpResponseHeaderBuilder,
+  upgrade::Upgraded,
+  websocket::{self, CloseFrame, Frame, Opcode, WebSocketError, WEBSOCKET_VERSION},
+  HttpRequest, HttpResponse, OwnedHttpRequest,
+};
+use crate::server::{Handler, HandlerResult};
+
+pub use crate::http::websocket::Message;
+
+/// Largest message an endpoint receives unless configured otherwise, see
+/// [`WebSocketHandler::max_message_size`]
+pub const DEFAULT_MAX_MESSAGE_SIZE: u64 = 1024 * 1024;
+/// Bytes requested from the connection per read
+const READ_SIZE: usize = 8 * 1024;
+
+/// A WebSocket connection as seen by the server. Pings are answered as they arrive and
+/// fragmented messages put back together, so [`Self::recv`] only returns whole messages.
+pub struct WebSocket {
+  connection: Upgraded,
+  request: OwnedHttpRequest,
+  /// read from the connection, not decoded yet
+  buffer: Vec<u8>,
+  max_message_size: u64,
+  /// a close frame was sent, nothing may follow it
+  closed: bool,
+}
+
+impl WebSocket {
+  pub fn new(connection: Upgraded, request: OwnedHttpRequest, max_message_size: u64) -> Self {
+    Self {
+      connection,
+      request,
+      buffer: Vec::new(),
+      max_message_size,
+      closed: false,
+    }
+  }
+
+  /// The upgrade request, for its path, query or cookies
+  pub fn request(&self) -> &OwnedHttpRequest {
+    &self.request
+  }
+
+  /// The next text or binary message, `None` once the client closed the connection; its close
+  /// frame is answered. Frames breaking the protocol close the connection with the matching
+  /// code and fail with [`io::ErrorKind::InvalidData`].
+  pub async fn recv(&mut self) -> io::Result<Option<Message>> {
+    let mut fragmented: Option<(Opcode, Vec<u8>)> = None;
+    loop {
+      let Some(frame) = self.read_frame().await? else {
+        return Ok(None);
+      };
+      let (opcode, payload) = match (frame.opcode, fragmented.as_mut()) {
+        (Opcode::Ping, _) => {
+          self
+            .write_frame(Frame::new(Opcode::Pong, frame.payload))
+            .await?;
+          continue;
+        }
+        (Opcode::Pong, _) => continue,
+        (Opcode::Close, _) => {
+          let close = match CloseFrame::parse(&frame.payload) {
+            Ok(close) => close,
+            Err(error) => return Err(self.fail(error).await),
+          };
+          // echoing the code, as RFC 6455 section 5.5.1 suggests
+          let code = close.map_or(1000, |close| close.code);
+          self.close(code, "").await?;
+          return Ok(None);
+        }
+        (Opcode::Continuation, Some((opcode, payload))) => {
+          if (payload.len() + frame.payload.len()) as u64 > self.max_message_size {
+            return Err(
+              self
+                .fail(WebSocketError::TooLarge(self.max_message_size))
+                .await,
+            );
+          }
+          payload.extend_from_slice(&frame.payload);
+          match frame.fin {
+            true => (*opcode, std::mem::take(payload)),
+            false => continue,
+          }
+        }
+        (Opcode::Text | Opcode::Binary, None) if !frame.fin => {
+          fragmented = Some((frame.opcode, frame.payload));
+          continue;
+        }
+        (Opcode::Text | Opcode::Binary, None) => (frame.opcode, frame.payload),
+        _ => return Err(self.fail(WebSocketError::InvalidFragmentation).await),
+      };
+      return match opcode {
+        Opcode::Text => match String::from_utf8(payload) {
+          Ok(text) => Ok(Some(Message::Text(text))),
+          Err(_) => Err(self.fail(WebSocketError::InvalidUtf8).await),
+        },
+        _ => Ok(Some(Message::Binary(payload))),
+      };
+    }
+  }
+
+  /// Send `message` as a single frame
+  pub async fn send(&mut self, message: Message) -> io::Result<()> {
+    if let Message::Close(close) = message {
+      let close = close.unwrap_or(CloseFrame { code: 1000, reason: String::new() });
+      return self.close(close.code, &close.reason).await;
+    }
+    self.write_frame(Frame::from(message)).await
+  }
+
+  /// Send a close frame with `code` and `reason`, after which nothing else is sent
+  pub async fn close(&mut self, code: u16, reason: &str) -> io::Result<()> {
+    if self.closed {
+      return Ok(());
+    }
+    let close = CloseFrame { code, reason: reason.to_string() };
+    self
+      .write_frame(Frame::from(Message::Close(Some(close))))
+      .await?;
+    self.closed = true;
+    self.connection.shutdown().await
+  }
+
+  /// Close the connection with the code `error` calls for, returning it to fail with
+  async fn fail(&mut self, error: WebSocketError) -> io::Error {
+    // the client broke the protocol, whether it hears why is up to it
+    let _ = self.close(error.close_code(), "").await;
+    io::Error::from(error)
+  }
+
+  async fn write_frame(&mut self, frame: Frame) -> io::Result<()> {
+    if self.closed {
+      return Err(io::ErrorKind::NotConnected.into());
+    }
+    self.connection.write_all(&frame.encode()).await?;
+    self.connection.flush().await
+  }
+
+  /// `None` once the connection is closed, or was by this side already
+  async fn read_frame(&mut self) -> io::Result<Option<Frame>> {
+    loop {
+      if self.closed {
+        return Ok(None);
+      }
+      match Frame::decode(&self.buffer, self.max_message_size) {
+        Ok(Some((frame, length))) => {
+          self.buffer.drain(..length);
+          return Ok(Some(frame));
+        }
+        Ok(None) => {}
+        Err(error) => return Err(self.fail(error).await),
+      }
+      let start = self.buffer.len();
+      self.buffer.resize(start + READ_SIZE, 0);
+      let read = self.connection.read(&mut self.buffer[start..]).await?;
+      self.buffer.truncate(start + read);
+      if read == 0 {
+        return Ok(None);
+      }
+    }
+  }
+}
+
+type Endpoint = dyn Fn(WebSocket) -> Pin<Box<dyn Future<Output = ()> + Send>> + Send + Sync;
+
+/// Answers WebSocket upgrade requests and runs `endpoint` on each connection, e.g. mounted on
+/// a [`crate::router::Router`] route:
+///
+/// ```no_run
+/// use std::sync::Arc;
+/// use udemy_server::{http::Method, router::Router, websocket::{Message, WebSocketHandler}};
+///
+/// let echo = WebSocketHandler::new(|mut socket| async move {
+///   while let Ok(Some(message)) = socket.recv().await {
+///     if socket.send(message).await.is_err() {
+///       break;
+///     }
+///   }
+/// });
+/// let router = Router::new().route(Method::GET, "/echo", Arc::new(echo));
+/// ```
+///
+/// Other requests get `426 Upgrade Required`, malformed handshakes `400 Bad Request`.
+pub struct WebSocketHandler {
+  endpoint: Arc<Endpoint>,
+  max_message_size: u64,
+}
+
+impl WebSocketHandler {
+  pub fn new<F, Fut>(endpoint: F) -> Self
+  where
+    F: Fn(WebSocket) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = ()> + Send + 'static,
+  {
+    Self {
+      endpoint: Arc::new(move |socket| Box::pin(endpoint(socket))),
+      max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+    }
+  }
+
+  /// Close connections whose messages grow beyond `max_message_size` bytes, see
+  /// [`DEFAULT_MAX_MESSAGE_SIZE`]
+  pub fn max_message_size(mut self, max_message_size: u64) -> Self {
+    self.max_message_size = max_message_size;
+    self
+  }
+}
+
+impl Handler for WebSocketHandler {
+  fn handle_request(&self, request: &HttpRequest<'_>) -> HandlerResult {
+    let response = match websocket::handshake(request) {
+      Ok(response) => response,
+      Err(error) => {
+        let mut builder = HttpResponseHeaderBuilder::new();
+        builder.upgrade("websocket");
+        builder.custom("Sec-WebSocket-Version".to_string(), WEBSOCKET_VERSION);
+        builder.content_length("0");
+        return Ok(HttpResponse::new(
+          error.status_code(),
+          None,
+          Some(Arc::new(builder.build())),
+        ));
+      }
+    };
+    let endpoint = Arc::clone(&self.endpoint);
+    let request = request.clone().into_owned();
+    let max_message_size = self.max_message_size;
+    Ok(response.on_upgrade(move |connection| {
+      endpoint(WebSocket::new(connection, request, max_message_size))
+    }))
+  }
+
+  fn describe(&self) -> Value {
+    json!({
+      "handler": "WebSocketHandler",
+      "max_message_size": self.max_message_size,
+    })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::http::StatusCode;
+  use expectest::prelude::*;
+  use rstest::*;
+  use tokio::io::{duplex, DuplexStream};
+
+  const MASK: [u8; 4] = [0x37, 0xfa, 0x21, 0x3d];
+
+  /// A socket on one end of an in-memory connection, the client on the other
+  fn connect(buffered: &[u8], max_message_size: u64) -> (WebSocket, DuplexStream) {
+    let (server, client) = duplex(64 * 1024);
+    let request =
+      HttpRequest::try_from(&b"GET /chat?room=1 HTTP/1.1\r\nHost: localhost\r\n\r\n"[..]).unwrap();
+    let connection = Upgraded::new(server, buffered.to_vec());
+    (
+      WebSocket::new(connection, request.into_owned(), max_message_size),
+      client,
+    )
+  }
+
+  fn client_frame(fin: bool, opcode: Opcode, payload: &[u8]) -> Vec<u8> {
+    Frame { fin, opcode, payload: payload.to_vec() }.encode_masked(MASK)
+  }
+
+  async fn read_frames(client: &mut DuplexStream) -> io::Result<Vec<(Opcode, Vec<u8>)>> {
+    let mut sent = Vec::new();
+    client.read_to_end(&mut sent).await?;
+    let mut frames = Vec::new();
+    let mut rest = &sent[..];
+    // what the server sends isn't masked, unlike what Frame::decode expects
+    while let [first, length, payload @ ..] = rest {
+      let length = (*length & 0x7f) as usize;
+      let opcode = match first & 0x0f {
+        0x1 => Opcode::Text,
+        0x2 => Opcode::Binary,
+        0x8 => Opcode::Close,
+        _ => Opcode::Pong,
+      };
+      frames.push((opcode, payload[..length].to_vec()));
+      rest = &payload[length..];
+    }
+    Ok(frames)
+  }
+
+  #[rstest]
+  #[tokio::test]
+  async fn test_exchange_messages() -> io::Result<()> {
+    // the first frame was read along with the upgrade request
+    let (mut socket, mut client) = connect(&client_frame(true, Opcode::Text, b"hello"), 1024);
+    expect!(socket.request().path()).to(be_equal_to("/chat"));
+
+    let mut frames = client_frame(false, Opcode::Binary, b"frag");
+    frames.extend(client_frame(true, Opcode::Ping, b"ping"));
+    frames.extend(client_frame(true, Opcode::Continuation, b"mented"));
+    frames.extend(client_frame(true, Opcode::Close, &[0x03, 0xe8]));
+    client.write_all(&frames).await?;
+
+    expect!(socket.recv().await?).to(be_some().value(Message::Text("hello".to_string())));
+    socket.send(Message::Text("hi".to_string())).await?;
+    expect!(socket.recv().await?).to(be_some().value(Message::Binary(b"fragmented".to_vec())));
+    expect!(socket.recv().await?).to(be_none());
+    expect!(socket.send(Message::Text("late".to_string())).await).to(be_err());
+
+    expect!(read_frames(&mut client).await?).to(be_equal_to(vec![
+      (Opcode::Text, b"hi".to_vec()),
+      (Opcode::Pong, b"ping".to_vec()),
+      (Opcode::Close, vec![0x03, 0xe8]),
+    ]));
+    Ok(())
+  }
+
+  #[rstest]
+  #[case::unmasked(Frame::new(Opcode::Text, b"hi".to_vec()).encode(), 1002)]
+  #[case::too_large(client_frame(true, Opcode::Binary, b"0123456789"), 1009)]
+  #[case::invalid_utf8(client_frame(true, Opcode::Text, &[0xff, 0xfe]), 1007)]
+  #[case::stray_continuation(client_frame(true, Opcode::Continuation, b"more"), 1002)]
+  #[tokio::test]
+  async fn test_close_on_protocol_errors(
+    #[case] frame: Vec<u8>,
+    #[case] expected_code: u16,
+  ) -> io::Result<()> {
+    let (mut socket, mut client) = connect(&frame, 8);
+
+    let error = socket.recv().await.unwrap_err();
+    expect!(error.kind()).to(be_equal_to(io::ErrorKind::InvalidData));
+    let frames = read_frames(&mut client).await?;
+    expect!(frames).to(be_equal_to(vec![(
+      Opcode::Close,
+      expected_code.to_be_bytes().to_vec(),
+    )]));
+    Ok(())
+  }
+
+  #[rstest]
+  #[case::plain_get("", StatusCode::UpgradeRequired)]
+  #[case::missing_key(
+    "Upgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Version: 13\r\n",
+    StatusCode::BadRequest
+  )]
+  #[case::upgrade(
+    "Upgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Version: 13\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n",
+    StatusCode::SwitchingProtocols
+  )]
+  fn test_handshake_responses(#[case] headers: &str, #[case] expected_status: StatusCode) {
+    let handler = WebSocketHandler::new(|_socket| async {});
+    let raw_request = format!("GET /chat HTTP/1.1\r\nHost: localhost\r\n{}\r\n", headers);
+    let request = HttpRequest::try_from(raw_request.as_bytes()).unwrap();
+
+    let mut response = handler.handle_request(&request).unwrap();
+    expect!(*response.status_code()).to(be_equal_to(expected_status));
+    expect!(response.take_upgrade().is_some()).to(be_equal_to(
+      expected_status == StatusCode::SwitchingProtocols,
+    ));
+  }
+}
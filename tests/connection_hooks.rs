@@ -0,0 +1,59 @@
+use std::{
+  error::Error,
+  sync::{Arc, Mutex},
+  time::Duration,
+};
+
+use reqwest::Client;
+use udemy_server::{
+  filesystem::LocalFileSystem,
+  http::MimeTypes,
+  server::{ConnectionStats, Server},
+  website_handler::WebsiteHandler,
+};
+
+#[tokio::test]
+async fn test_connection_lifecycle_hooks() -> Result<(), Box<dyn Error>> {
+  let opened = Arc::new(Mutex::new(Vec::new()));
+  let closed: Arc<Mutex<Vec<ConnectionStats>>> = Arc::new(Mutex::new(Vec::new()));
+
+  let server = Server::new("127.0.0.1:8081".to_string())
+    .on_connection_open({
+      let opened = Arc::clone(&opened);
+      move |peer| opened.lock().unwrap().push(peer)
+    })
+    .on_connection_close({
+      let closed = Arc::clone(&closed);
+      move |_, stats| closed.lock().unwrap().push(stats.clone())
+    });
+  let file_system = Arc::new(LocalFileSystem::new(format!(
+    "{}/public",
+    env!("CARGO_MANIFEST_DIR")
+  )));
+  let handler = Arc::new(WebsiteHandler::new(file_system, MimeTypes::default()));
+
+  tokio::spawn(async move {
+    if let Err(e) = server.run(handler).await {
+      eprintln!("Server error: {:?}", e);
+    }
+  });
+  tokio::time::sleep(Duration::from_millis(500)).await;
+
+  let body = Client::new()
+    .get("http://127.0.0.1:8081/hello")
+    // otherwise the connection stays open for the next request
+    .header("Connection", "close")
+    .send()
+    .await?
+    .text()
+    .await?;
+  tokio::time::sleep(Duration::from_millis(100)).await;
+
+  assert_eq!(opened.lock().unwrap().len(), 1);
+  let closed = closed.lock().unwrap();
+  assert_eq!(closed.len(), 1);
+  assert_eq!(closed[0].requests, 1);
+  assert!(closed[0].bytes_read > 0);
+  assert!(closed[0].bytes_written as usize > body.len());
+  Ok(())
+}
@@ -0,0 +1,56 @@
+use std::{error::Error, sync::Arc, time::Duration};
+
+use reqwest::Client;
+use udemy_server::{
+  filesystem::LocalFileSystem,
+  http::MimeTypes,
+  server::{ListenerSettings, Server},
+  website_handler::WebsiteHandler,
+};
+
+fn website_handler() -> Arc<WebsiteHandler<LocalFileSystem>> {
+  let file_system = Arc::new(LocalFileSystem::new(format!(
+    "{}/public",
+    env!("CARGO_MANIFEST_DIR")
+  )));
+  Arc::new(WebsiteHandler::new(file_system, MimeTypes::default()))
+}
+
+#[tokio::test]
+async fn test_serve_on_every_listener() -> Result<(), Box<dyn Error>> {
+  let server = Server::new("127.0.0.1:8082".to_string()).listen(ListenerSettings {
+    address: "127.0.0.1:8083".to_string(),
+    tls: false,
+    proxy_protocol: false,
+  });
+  tokio::spawn(async move {
+    if let Err(e) = server.run(website_handler()).await {
+      eprintln!("Server error: {:?}", e);
+    }
+  });
+  tokio::time::sleep(Duration::from_millis(500)).await;
+
+  let client = Client::new();
+  for address in ["127.0.0.1:8082", "127.0.0.1:8083"] {
+    let response = client
+      .get(format!("http://{}/hello", address))
+      .send()
+      .await?;
+    assert_eq!(response.status(), 200);
+  }
+  Ok(())
+}
+
+#[tokio::test]
+async fn test_tls_listener_needs_certificate() {
+  let server = Server::new("127.0.0.1:8084".to_string()).listen(ListenerSettings {
+    address: "127.0.0.1:8085".to_string(),
+    tls: true,
+    proxy_protocol: false,
+  });
+  let error = server.run(website_handler()).await.unwrap_err();
+  assert_eq!(
+    error.to_string(),
+    "Listener on 127.0.0.1:8085 needs TLS, but the server has no certificate"
+  );
+}
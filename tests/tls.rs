@@ -0,0 +1,60 @@
+use std::{error::Error, fs, sync::Arc, time::Duration};
+
+use tempfile::TempDir;
+use tokio::{
+  io::{AsyncReadExt, AsyncWriteExt},
+  net::TcpStream,
+};
+use tokio_rustls::{
+  rustls::{crypto::ring, pki_types::ServerName, ClientConfig, RootCertStore},
+  TlsConnector,
+};
+use udemy_server::{
+  filesystem::LocalFileSystem, http::MimeTypes, server::Server, website_handler::WebsiteHandler,
+};
+
+#[tokio::test]
+async fn test_serve_over_tls() -> Result<(), Box<dyn Error>> {
+  let dir = TempDir::new()?;
+  let certified_key = rcgen::generate_simple_self_signed(vec!["localhost".to_string()])?;
+  let (cert_path, key_path) = (dir.path().join("cert.pem"), dir.path().join("key.pem"));
+  fs::write(&cert_path, certified_key.cert.pem())?;
+  fs::write(&key_path, certified_key.signing_key.serialize_pem())?;
+
+  let server = Server::bind_tls("127.0.0.1:8443".to_string(), cert_path, key_path)?;
+  let file_system = Arc::new(LocalFileSystem::new(format!(
+    "{}/public",
+    env!("CARGO_MANIFEST_DIR")
+  )));
+  let handler = Arc::new(WebsiteHandler::new(file_system, MimeTypes::default()));
+  tokio::spawn(async move {
+    if let Err(e) = server.run(handler).await {
+      eprintln!("Server error: {:?}", e);
+    }
+  });
+  tokio::time::sleep(Duration::from_millis(500)).await;
+
+  let mut roots = RootCertStore::empty();
+  roots.add(certified_key.cert.der().clone())?;
+  let mut config = ClientConfig::builder_with_provider(Arc::new(ring::default_provider()))
+    .with_safe_default_protocol_versions()?
+    .with_root_certificates(roots)
+    .with_no_client_auth();
+  config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+  let stream = TcpStream::connect("127.0.0.1:8443").await?;
+  let mut stream = TlsConnector::from(Arc::new(config))
+    .connect(ServerName::try_from("localhost")?, stream)
+    .await?;
+  // the server only speaks HTTP/1.1
+  assert_eq!(stream.get_ref().1.alpn_protocol(), Some(&b"http/1.1"[..]));
+
+  stream
+    .write_all(b"GET /hello HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+    .await?;
+  let mut response = Vec::new();
+  // rustls reports the missing close_notify once the server hangs up
+  let _ = stream.read_to_end(&mut response).await;
+  let response = String::from_utf8_lossy(&response);
+  assert!(response.starts_with("HTTP/1.1 200 Ok\r\n"), "{}", response);
+  Ok(())
+}